@@ -0,0 +1,2 @@
+# Create app directory
+mkdir -p "$REMOTE_DIR"
@@ -0,0 +1,13 @@
+# Setup firewall: firewalld on RHEL and Fedora, ufw elsewhere
+if ! command -v ufw &>/dev/null && command -v firewall-cmd &>/dev/null; then
+    systemctl enable --now firewalld
+    firewall-cmd --permanent --add-service=ssh
+    firewall-cmd --permanent --add-service=http
+    firewall-cmd --permanent --add-service=https
+    firewall-cmd --reload
+else
+    ufw allow OpenSSH
+    ufw allow 80/tcp
+    ufw allow 443/tcp
+    ufw --force enable
+fi
@@ -0,0 +1,29 @@
+# Install Docker
+if ! command -v docker &>/dev/null; then
+    echo "Installing Docker..."
+    apt_get update
+    apt_get install -y ca-certificates curl
+    install -m 0755 -d /etc/apt/keyrings
+    curl -fsSL https://download.docker.com/linux/ubuntu/gpg \
+        -o /etc/apt/keyrings/docker.asc
+    chmod a+r /etc/apt/keyrings/docker.asc
+    # shellcheck source=/dev/null
+    . /etc/os-release
+    echo \
+        "deb [arch=$(dpkg --print-architecture) \
+        signed-by=/etc/apt/keyrings/docker.asc] \
+        https://download.docker.com/linux/ubuntu \
+        $VERSION_CODENAME \
+        stable" > /etc/apt/sources.list.d/docker.list
+    apt_get update
+    apt_get install -y \
+        docker-ce \
+        docker-ce-cli \
+        containerd.io \
+        docker-compose-plugin
+    systemctl enable docker
+    systemctl start docker
+else
+    echo "Docker already installed"
+    docker --version
+fi
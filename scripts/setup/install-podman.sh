@@ -0,0 +1,30 @@
+# Install Podman and podman-compose
+if ! command -v podman &>/dev/null || ! command -v podman-compose &>/dev/null; then
+    echo "Installing Podman..."
+    if command -v dnf &>/dev/null; then
+        dnf install -y podman
+        # podman-compose comes from EPEL on RHEL and its rebuilds
+        if ! dnf install -y podman-compose; then
+            dnf install -y epel-release
+            dnf install -y podman-compose
+        fi
+    else
+        apt_get update
+        apt_get install -y podman podman-compose
+    fi
+else
+    echo "Podman already installed"
+    podman --version
+fi
+
+# Pull unqualified images (caddy:2-alpine) from Docker Hub: with
+# several search registries, Podman asks which one to use and
+# fails without a terminal
+mkdir -p /etc/containers/registries.conf.d
+cat > /etc/containers/registries.conf.d/catapulta.conf << 'CONF'
+unqualified-search-registries = ["docker.io"]
+CONF
+
+# Start containers with restart: unless-stopped again after a
+# reboot, which Docker's daemon does on its own
+systemctl enable podman-restart.service
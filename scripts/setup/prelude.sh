@@ -0,0 +1,28 @@
+#!/usr/bin/env bash
+# Shared start of the server setup script, followed by the
+# setup steps. Steps can use $DOMAIN, $REMOTE_DIR and apt_get.
+#
+# Usage: <script> <domain> <remote_dir> [cloud_init_timeout]
+set -euo pipefail
+
+DOMAIN="${1:?Usage: setup <domain> <remote_dir>}"
+REMOTE_DIR="${2:?Usage: setup <domain> <remote_dir>}"
+CLOUD_INIT_TIMEOUT="${3:-600}"
+
+APT_OPTS="-o DPkg::Lock::Timeout=120"
+
+# apt-get, retried when it fails (e.g. a lock held past the
+# timeout above, or a mirror hiccup)
+apt_get() {
+    local attempt=1
+    # shellcheck disable=SC2086
+    until apt-get $APT_OPTS "$@"; do
+        if [ "$attempt" -ge 5 ]; then
+            echo "apt-get $1 failed after $attempt attempts" >&2
+            return 1
+        fi
+        echo "  apt-get $1 failed (attempt $attempt/5), retrying in 10s..."
+        attempt=$((attempt + 1))
+        sleep 10
+    done
+}
@@ -0,0 +1,38 @@
+# Write placeholder Caddyfile
+cat > "$REMOTE_DIR/Caddyfile" << CADDY
+$DOMAIN {
+    respond "Service is being deployed..." 503
+}
+CADDY
+
+# Write minimal docker-compose for Caddy only
+cat > "$REMOTE_DIR/docker-compose.yml" << 'COMPOSE'
+services:
+  caddy:
+    image: caddy:2-alpine
+    container_name: app-caddy
+    restart: unless-stopped
+    ports:
+      - "80:80"
+      - "443:443"
+    volumes:
+      - ./Caddyfile:/etc/caddy/Caddyfile:ro
+      - caddy-data:/data
+      - caddy-config:/config
+
+volumes:
+  caddy-data:
+    driver: local
+  caddy-config:
+    driver: local
+COMPOSE
+
+# Start Caddy, with podman-compose on servers without Docker
+cd "$REMOTE_DIR"
+if command -v docker &>/dev/null; then
+    docker compose pull
+    docker compose up -d
+else
+    podman-compose pull
+    podman-compose up -d
+fi
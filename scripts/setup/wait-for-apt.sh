@@ -0,0 +1,34 @@
+# Let cloud-init finish the first boot: it runs apt itself and
+# holds the dpkg lock meanwhile
+if command -v cloud-init &>/dev/null; then
+    echo "Waiting for cloud-init (up to ${CLOUD_INIT_TIMEOUT}s)..."
+    rc=0
+    timeout "$CLOUD_INIT_TIMEOUT" cloud-init status --wait >/dev/null || rc=$?
+    case "$rc" in
+        0) echo "cloud-init done" ;;
+        124) echo "cloud-init still running after ${CLOUD_INIT_TIMEOUT}s, continuing" ;;
+        *) echo "cloud-init finished with errors (status $rc), continuing" ;;
+    esac
+fi
+
+# Kill unattended-upgrades permanently
+echo "Stopping unattended-upgrades..."
+systemctl stop unattended-upgrades 2>/dev/null || true
+systemctl disable unattended-upgrades 2>/dev/null || true
+systemctl mask unattended-upgrades 2>/dev/null || true
+pkill -9 unattended-upgr 2>/dev/null || true
+pkill -9 apt-get 2>/dev/null || true
+pkill -9 dpkg 2>/dev/null || true
+sleep 5
+
+# Wait for apt locks
+echo "Waiting for apt locks..."
+while fuser /var/lib/dpkg/lock-frontend \
+    /var/lib/dpkg/lock \
+    /var/lib/apt/lists/lock \
+    /var/cache/apt/archives/lock \
+    >/dev/null 2>&1; do
+    echo "  Locks still held, waiting..."
+    sleep 3
+done
+echo "apt locks released"
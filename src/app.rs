@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::audit;
+
 /// A resolved upstream address: container name + port.
 ///
 /// Produced by [`App::upstream`] and [`App::upstream_port`] so
@@ -16,10 +18,36 @@ use std::fmt;
 ///
 /// assert_eq!(upstream.to_string(), "api:8000");
 /// ```
+///
+/// An app can be routed on several of its ports, e.g. an HTTP
+/// API and a gRPC service:
+///
+/// ```
+/// use catapulta::{App, Caddy};
+///
+/// let app = App::new("api").expose(8000).expose(9000);
+/// let caddy = Caddy::new()
+///     .route("/grpc.*", app.upstream_port(9000).h2c())
+///     .route("", app.upstream_port(8000));
+///
+/// assert!(caddy.routes[0].1.h2c);
+/// ```
 #[derive(Debug, Clone)]
 pub struct Upstream {
     pub name: String,
     pub port: u16,
+    /// Proxy over cleartext HTTP/2, as gRPC servers expect.
+    pub h2c: bool,
+}
+
+impl Upstream {
+    /// Proxy to this upstream over cleartext HTTP/2 (h2c), for
+    /// gRPC services. WebSocket upgrades need no option.
+    #[must_use]
+    pub const fn h2c(mut self) -> Self {
+        self.h2c = true;
+        self
+    }
 }
 
 impl fmt::Display for Upstream {
@@ -28,6 +56,136 @@ impl fmt::Display for Upstream {
     }
 }
 
+/// An environment variable of an app or job.
+///
+/// Values marked `secret` (see [`App::env_secret`]) are redacted
+/// from dry-run output and logs, and the compose file holding
+/// them is written readable by its owner only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+    pub secret: bool,
+}
+
+impl EnvVar {
+    #[must_use]
+    pub fn new(key: &str, value: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.to_string(),
+            secret: false,
+        }
+    }
+
+    /// A variable whose value is redacted wherever catapulta
+    /// prints it.
+    #[must_use]
+    pub fn secret(key: &str, value: &str) -> Self {
+        audit::register_secret(value);
+        Self {
+            secret: true,
+            ..Self::new(key, value)
+        }
+    }
+}
+
+impl PartialEq<(String, String)> for EnvVar {
+    /// Compare with a `(key, value)` pair, the type `env` had
+    /// before secrets could be marked.
+    fn eq(&self, (key, value): &(String, String)) -> bool {
+        self.key == *key && self.value == *value
+    }
+}
+
+impl fmt::Display for EnvVar {
+    /// `KEY=value`, as in a compose `environment` list.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// A generated file written next to `docker-compose.yml` and
+/// mounted read-only into an app container.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    /// File name in the deployment directory.
+    pub name: String,
+    pub content: String,
+    /// Path inside the container.
+    pub mount: String,
+}
+
+/// A short-lived container run to completion before its app
+/// starts, e.g. schema migrations or seed data.
+///
+/// Rendered as a compose service named `<app>-init` (or
+/// `<app>-init-<n>` when an app has several) that the app
+/// depends on with `condition: service_completed_successfully`.
+/// Jobs inherit the app's `env_file` and environment, with the
+/// job's own variables added after them.
+///
+/// # Example
+///
+/// ```
+/// use catapulta::{App, Job};
+///
+/// let app = App::new("api")
+///     .env_file("deploy/.env.api")
+///     .init(Job::from_image("api:latest").command("./api migrate"));
+///
+/// assert_eq!(app.init.len(), 1);
+/// assert_eq!(app.init[0].command.as_deref(), Some("./api migrate"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub image: String,
+    pub command: Option<String>,
+    pub env: Vec<EnvVar>,
+    pub volumes: Vec<(String, String)>,
+}
+
+impl Job {
+    /// A job running `image` with its default command.
+    #[must_use]
+    pub fn from_image(image: &str) -> Self {
+        Self {
+            image: image.to_string(),
+            command: None,
+            env: Vec::new(),
+            volumes: Vec::new(),
+        }
+    }
+
+    /// Override the image's command. Run through `sh -c`, so
+    /// it may chain several steps.
+    #[must_use]
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = Some(command.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push(EnvVar::new(key, value));
+        self
+    }
+
+    /// Like [`Self::env`], with the value marked secret; see
+    /// [`EnvVar`].
+    #[must_use]
+    pub fn env_secret(mut self, key: &str, value: &str) -> Self {
+        self.env.push(EnvVar::secret(key, value));
+        self
+    }
+
+    #[must_use]
+    pub fn volume(mut self, name: &str, mount: &str) -> Self {
+        self.volumes.push((name.to_string(), mount.to_string()));
+        self
+    }
+}
+
 /// Defines the application container: image, environment,
 /// volumes, health checks, and exposed ports.
 ///
@@ -54,16 +212,35 @@ pub struct App {
     pub name: String,
     pub dockerfile: String,
     pub platform: String,
+    pub platforms: Vec<String>,
     pub build_args: Vec<(String, String)>,
-    pub env: Vec<(String, String)>,
+    pub env: Vec<EnvVar>,
     pub env_file: Option<String>,
     pub volumes: Vec<(String, String)>,
+    /// Bind mounts added by `cargo xtask dev` only, e.g. the
+    /// source directory.
+    pub dev_volumes: Vec<(String, String)>,
     pub expose: Vec<u16>,
     pub ports: Vec<(u16, u16)>,
     pub healthcheck: Option<String>,
     pub context: Option<String>,
     pub source: Option<(String, String)>,
     pub cache_source: bool,
+    pub domain: Option<String>,
+    pub init: Vec<Job>,
+    pub hostname: Option<String>,
+    pub working_dir: Option<String>,
+    pub shm_size: Option<String>,
+    pub aliases: Vec<String>,
+    pub image: Option<String>,
+    pub auto_update: bool,
+    pub config_files: Vec<ConfigFile>,
+    pub replicas: u32,
+    pub pre_build: Vec<String>,
+    pub profiles: Vec<String>,
+    /// Subdirectory of the stack's directory holding the app's
+    /// env and config files.
+    pub remote_dir: Option<String>,
 }
 
 impl App {
@@ -73,16 +250,31 @@ impl App {
             name: name.to_string(),
             dockerfile: "Dockerfile".to_string(),
             platform: "linux/amd64".to_string(),
+            platforms: Vec::new(),
             build_args: Vec::new(),
             env: Vec::new(),
             env_file: None,
             volumes: Vec::new(),
+            dev_volumes: Vec::new(),
             expose: Vec::new(),
             ports: Vec::new(),
             healthcheck: None,
             context: None,
             source: None,
             cache_source: false,
+            domain: None,
+            init: Vec::new(),
+            hostname: None,
+            working_dir: None,
+            shm_size: None,
+            aliases: Vec::new(),
+            image: None,
+            auto_update: false,
+            config_files: Vec::new(),
+            replicas: 1,
+            pre_build: Vec::new(),
+            profiles: Vec::new(),
+            remote_dir: None,
         }
     }
 
@@ -92,12 +284,64 @@ impl App {
         self
     }
 
+    /// Platform the image is built for (default `linux/amd64`).
+    ///
+    /// Deploys check it against the server's architecture before
+    /// building, and fail on a mismatch such as an ARM server.
     #[must_use]
     pub fn platform(mut self, platform: &str) -> Self {
         self.platform = platform.to_string();
         self
     }
 
+    /// Build the image for several platforms with `docker buildx`.
+    ///
+    /// Each platform is built into its own local tag (see
+    /// [`App::platform_tag`]). At deploy time the image matching
+    /// the server's architecture is transferred and tagged
+    /// `name:latest` there, so one pipeline can target x86 and
+    /// ARM servers alike. Takes precedence over [`App::platform`].
+    #[must_use]
+    pub fn platforms(mut self, platforms: &[&str]) -> Self {
+        self.platforms = platforms.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Local tag of the image built for `platform`, e.g.
+    /// `api:latest-arm64` for `linux/arm64` or
+    /// `api:latest-arm-v7` for `linux/arm/v7`.
+    #[must_use]
+    pub fn platform_tag(&self, platform: &str) -> String {
+        let arch = platform.strip_prefix("linux/").unwrap_or(platform);
+        format!("{}:latest-{}", self.name, arch.replace('/', "-"))
+    }
+
+    /// The configured platform that runs on a host whose
+    /// `uname -m` is `machine`, if any.
+    #[must_use]
+    pub fn platform_for_machine(&self, machine: &str) -> Option<&str> {
+        let platform = machine_platform(machine)?;
+        self.platforms
+            .iter()
+            .map(String::as_str)
+            .find(|p| *p == platform)
+    }
+
+    /// Whether the image built for this app runs on a host whose
+    /// `uname -m` is `machine`. Unknown architectures are assumed
+    /// to match.
+    #[must_use]
+    pub fn runs_on(&self, machine: &str) -> bool {
+        let Some(platform) = machine_platform(machine) else {
+            return true;
+        };
+        if self.platforms.is_empty() {
+            self.platform == platform
+        } else {
+            self.platforms.iter().any(|p| p == platform)
+        }
+    }
+
     #[must_use]
     pub fn build_arg(mut self, key: &str, value: &str) -> Self {
         self.build_args.push((key.to_string(), value.to_string()));
@@ -106,22 +350,99 @@ impl App {
 
     #[must_use]
     pub fn env(mut self, key: &str, value: &str) -> Self {
-        self.env.push((key.to_string(), value.to_string()));
+        self.env.push(EnvVar::new(key, value));
+        self
+    }
+
+    /// Like [`Self::env`], with the value marked secret: it is
+    /// redacted from dry-run output and logs, and the compose file
+    /// is written with mode `0600`.
+    ///
+    /// ```
+    /// use catapulta::App;
+    ///
+    /// let app = App::new("api").env_secret("API_KEY", "s3cr3t");
+    ///
+    /// assert!(app.has_secrets());
+    /// ```
+    #[must_use]
+    pub fn env_secret(mut self, key: &str, value: &str) -> Self {
+        self.env.push(EnvVar::secret(key, value));
         self
     }
 
+    /// Whether the app or one of its init jobs has a secret
+    /// environment variable.
+    #[must_use]
+    pub fn has_secrets(&self) -> bool {
+        self.env
+            .iter()
+            .chain(self.init.iter().flat_map(|j| &j.env))
+            .any(|v| v.secret)
+    }
+
+    /// Register the values of the secret environment variables
+    /// of the app and its init jobs with the audit log, including
+    /// those built as struct literals rather than with
+    /// [`EnvVar::secret`].
+    pub fn register_secrets(&self) {
+        self.env
+            .iter()
+            .chain(self.init.iter().flat_map(|j| &j.env))
+            .filter(|v| v.secret)
+            .for_each(|v| audit::register_secret(&v.value));
+    }
+
     #[must_use]
     pub fn env_file(mut self, path: &str) -> Self {
         self.env_file = Some(path.to_string());
         self
     }
 
+    /// Keep the app's env file (as `.env`) and
+    /// [config files](Self::config_file) in `dir`, relative to
+    /// the stack's directory on the server, instead of next to
+    /// `docker-compose.yml`. See also
+    /// [`Pipeline::app_dirs`](crate::Pipeline::app_dirs).
+    ///
+    /// ```
+    /// use catapulta::App;
+    ///
+    /// let app = App::new("api").remote_dir("api");
+    ///
+    /// assert_eq!(app.remote_path("nginx.conf"), "api/nginx.conf");
+    /// ```
+    #[must_use]
+    pub fn remote_dir(mut self, dir: &str) -> Self {
+        self.remote_dir = Some(dir.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Path of the app's file `name` relative to the stack's
+    /// directory: in [`Self::remote_dir`] when set.
+    #[must_use]
+    pub fn remote_path(&self, name: &str) -> String {
+        self.remote_dir
+            .as_ref()
+            .map_or_else(|| name.to_string(), |dir| format!("{dir}/{name}"))
+    }
+
     #[must_use]
     pub fn volume(mut self, name: &str, mount: &str) -> Self {
         self.volumes.push((name.to_string(), mount.to_string()));
         self
     }
 
+    /// Bind mount the local `path` at `mount` when the stack runs
+    /// with `cargo xtask dev`, e.g. `./src` over the sources
+    /// baked into the image so changes show without a rebuild.
+    /// Deploys leave it out.
+    #[must_use]
+    pub fn dev_volume(mut self, path: &str, mount: &str) -> Self {
+        self.dev_volumes.push((path.to_string(), mount.to_string()));
+        self
+    }
+
     #[must_use]
     pub fn expose(mut self, port: u16) -> Self {
         self.expose.push(port);
@@ -132,7 +453,8 @@ impl App {
     ///
     /// This renders as `"host:container"` under the `ports` key in
     /// docker-compose, making the port accessible from outside the
-    /// Docker network.
+    /// Docker network. Deploys allow `host` in the server's ufw
+    /// firewall when it is active (see [`crate::firewall`]).
     #[must_use]
     pub fn port(mut self, host: u16, container: u16) -> Self {
         self.ports.push((host, container));
@@ -145,6 +467,19 @@ impl App {
         self
     }
 
+    /// Run the shell command `command` locally before the image
+    /// is built, e.g. `npm run build` for frontend assets compiled
+    /// outside the Dockerfile.
+    ///
+    /// Commands run in order with `sh -c`, from the current
+    /// directory or from the cloned repository when
+    /// [`App::source`] is set. A failing command aborts the build.
+    #[must_use]
+    pub fn pre_build(mut self, command: &str) -> Self {
+        self.pre_build.push(command.to_string());
+        self
+    }
+
     /// Clone a remote Git repository as the Docker build source.
     ///
     /// The `ssh_url` must be an SSH URL
@@ -176,6 +511,123 @@ impl App {
         self
     }
 
+    /// Serve this app on its own domain.
+    ///
+    /// The Caddyfile gets a dedicated site block proxying the
+    /// domain to the app's first exposed port, and the pipeline
+    /// creates an A record for it alongside the main domain.
+    /// The app must expose at least one port.
+    #[must_use]
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Run `job` to completion before this app starts. Jobs
+    /// run in parallel; the app waits for all of them.
+    #[must_use]
+    pub fn init(mut self, job: Job) -> Self {
+        self.init.push(job);
+        self
+    }
+
+    /// Compose service names of the init jobs, in order.
+    #[must_use]
+    pub fn init_names(&self) -> Vec<String> {
+        match self.init.len() {
+            0 => Vec::new(),
+            1 => vec![format!("{}-init", self.name)],
+            n => (1..=n).map(|i| format!("{}-init-{i}", self.name)).collect(),
+        }
+    }
+
+    /// Run a prebuilt image from a registry (e.g.
+    /// `"postgres:16"`) instead of building one.
+    ///
+    /// The pipeline skips the build and transfer steps for this
+    /// app; the server pulls the image when the stack starts.
+    #[must_use]
+    pub fn image(mut self, image: &str) -> Self {
+        self.image = Some(image.to_string());
+        self
+    }
+
+    /// Let the auto-update sidecar pull new versions of this
+    /// app's [`App::image`]. Ignored for locally built apps,
+    /// which have no registry to pull from.
+    #[must_use]
+    pub const fn auto_update(mut self) -> Self {
+        self.auto_update = true;
+        self
+    }
+
+    /// Write `content` to `name` in the deployment directory on
+    /// every deploy and mount it read-only at `mount`.
+    #[must_use]
+    pub fn config_file(mut self, name: &str, content: &str, mount: &str) -> Self {
+        self.config_files.push(ConfigFile {
+            name: name.to_string(),
+            content: content.to_string(),
+            mount: mount.to_string(),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.hostname = Some(hostname.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn working_dir(mut self, dir: &str) -> Self {
+        self.working_dir = Some(dir.to_string());
+        self
+    }
+
+    /// Size of `/dev/shm`, in compose notation (e.g. `"1g"`).
+    ///
+    /// Docker defaults to 64 MB, which is too small for
+    /// `PostgreSQL` parallel queries and headless browsers.
+    #[must_use]
+    pub fn shm_size(mut self, size: &str) -> Self {
+        self.shm_size = Some(size.to_string());
+        self
+    }
+
+    /// Add an extra name the container is reachable by on the
+    /// stack's network, besides the app name.
+    #[must_use]
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    /// Run `n` containers of this app.
+    ///
+    /// With more than one replica the service gets no fixed
+    /// container name, and Caddy balances requests over the
+    /// replicas' addresses. Host [`App::port`] mappings cannot be
+    /// shared between replicas. `cargo xtask scale` changes the
+    /// count on a running server without a redeploy.
+    #[must_use]
+    pub const fn replicas(mut self, n: u32) -> Self {
+        self.replicas = n;
+        self
+    }
+
+    /// Put this app in the compose profile `name`, so it only
+    /// starts when deployed with `--profile name` (e.g. debug
+    /// tools or an admin UI). Repeat to list it in several
+    /// profiles.
+    ///
+    /// Caddy does not wait for profiled apps to become healthy.
+    #[must_use]
+    pub fn profile(mut self, name: &str) -> Self {
+        self.profiles.push(name.to_string());
+        self
+    }
+
     #[must_use]
     pub fn healthcheck(mut self, cmd: &str) -> Self {
         self.healthcheck = Some(cmd.to_string());
@@ -196,6 +648,7 @@ impl App {
         Upstream {
             name: self.name.clone(),
             port: *port,
+            h2c: false,
         }
     }
 
@@ -214,6 +667,25 @@ impl App {
         Upstream {
             name: self.name.clone(),
             port,
+            h2c: false,
         }
     }
 }
+
+/// Docker platform of a host whose `uname -m` is `machine`
+/// (e.g. `linux/arm64` for `aarch64`).
+#[must_use]
+pub fn machine_platform(machine: &str) -> Option<&'static str> {
+    let platform = match machine.trim() {
+        "x86_64" | "amd64" => "linux/amd64",
+        "aarch64" | "arm64" => "linux/arm64",
+        "armv7l" => "linux/arm/v7",
+        "armv6l" => "linux/arm/v6",
+        "i686" | "i386" => "linux/386",
+        "ppc64le" => "linux/ppc64le",
+        "s390x" => "linux/s390x",
+        "riscv64" => "linux/riscv64",
+        _ => return None,
+    };
+    Some(platform)
+}
@@ -0,0 +1,166 @@
+//! Audit log of the external and remote commands a run executes.
+//!
+//! [`start`] opens the log (by default `.catapulta/last-run.log`,
+//! truncated on every run). Each command run through [`crate::cmd`]
+//! or the native SSH client is then appended as one line with its
+//! start offset, duration, outcome, and redacted command line.
+//! With tracing enabled, commands are also echoed to stderr as
+//! they start.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::error::{DeployError, DeployResult};
+
+struct AuditLog {
+    file: File,
+    started: Instant,
+}
+
+static LOG: Mutex<Option<AuditLog>> = Mutex::new(None);
+static TRACE: AtomicBool = AtomicBool::new(false);
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Start recording commands to `path`, replacing any previous
+/// log. With `trace`, commands are also echoed to stderr.
+pub fn start(path: &Path, trace: bool) -> DeployResult<()> {
+    TRACE.store(trace, Ordering::Relaxed);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    let invocation: Vec<String> = std::env::args().map(|a| redact(&a)).collect();
+    writeln!(file, "# {}", invocation.join(" "))?;
+    if let Ok(mut log) = LOG.lock() {
+        *log = Some(AuditLog {
+            file,
+            started: Instant::now(),
+        });
+    }
+    Ok(())
+}
+
+/// Run `f`, recording `program` and `args` with the outcome and
+/// duration of the call.
+pub fn record<T>(
+    program: &str,
+    args: &[&str],
+    f: impl FnOnce() -> DeployResult<T>,
+) -> DeployResult<T> {
+    let line = command_line(program, args);
+    if TRACE.load(Ordering::Relaxed) {
+        eprintln!("+ {line}");
+    }
+    let start = Instant::now();
+    let result = f();
+    write_entry(start, start.elapsed(), &outcome(&result), &line);
+    result
+}
+
+/// Mask `secret` wherever it appears in later command lines,
+/// including inside shell snippets that [`redact`] cannot parse.
+pub fn register_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    if let Ok(mut secrets) = SECRETS.lock() {
+        if !secrets.iter().any(|s| s == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+/// Format a command for the log, redacting secrets in each
+/// argument.
+#[must_use]
+pub fn command_line(program: &str, args: &[&str]) -> String {
+    let mut parts = vec![program.to_string()];
+    parts.extend(args.iter().map(|a| redact(a)));
+    mask(&parts.join(" "))
+}
+
+/// Replace the secrets registered with [`register_secret`] in
+/// `text` by `[REDACTED]`.
+#[must_use]
+pub fn mask(text: &str) -> String {
+    let mut text = text.to_string();
+    if let Ok(secrets) = SECRETS.lock() {
+        for secret in secrets.iter() {
+            text = text.replace(secret.as_str(), "[REDACTED]");
+        }
+    }
+    text
+}
+
+/// Hide secret values in a single argument.
+///
+/// Covers credential headers (`Authorization`, `X-Ovh-*`,
+/// `X-Auth-*`) and `KEY=value` pairs whose key names a secret
+/// (`TOKEN`, `SECRET`, `PASSWORD`, `PASSWD`, `API_KEY`,
+/// `PRIVATE_KEY`, `CREDENTIAL`).
+#[must_use]
+pub fn redact(arg: &str) -> String {
+    const SECRET_HEADERS: &[&str] = &["authorization", "x-ovh-", "x-auth-"];
+    const SECRET_KEYS: &[&str] = &[
+        "TOKEN",
+        "SECRET",
+        "PASSWORD",
+        "PASSWD",
+        "API_KEY",
+        "PRIVATE_KEY",
+        "CREDENTIAL",
+    ];
+
+    if let Some((name, _)) = arg.split_once(':') {
+        let lower = name.trim().to_ascii_lowercase();
+        if SECRET_HEADERS.iter().any(|h| lower.starts_with(h)) {
+            return format!("{name}: [REDACTED]");
+        }
+    }
+    if let Some((key, _)) = arg.split_once('=') {
+        let upper = key.to_ascii_uppercase();
+        let is_name = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if is_name && SECRET_KEYS.iter().any(|k| upper.contains(k)) {
+            return format!("{key}=[REDACTED]");
+        }
+    }
+    arg.to_string()
+}
+
+fn outcome<T>(result: &DeployResult<T>) -> String {
+    match result {
+        Ok(_) => "ok".to_string(),
+        Err(DeployError::CommandFailed { status, .. }) => status
+            .code()
+            .map_or_else(|| "killed".to_string(), |code| format!("exit {code}")),
+        Err(DeployError::RemoteCommandFailed { exit_status, .. }) => {
+            format!("exit {exit_status}")
+        }
+        Err(DeployError::Timeout { .. }) => "timeout".to_string(),
+        Err(DeployError::CommandNotFound(_)) => "not found".to_string(),
+        Err(_) => "error".to_string(),
+    }
+}
+
+fn write_entry(start: Instant, duration: Duration, outcome: &str, line: &str) {
+    let Ok(mut guard) = LOG.lock() else {
+        return;
+    };
+    let Some(log) = guard.as_mut() else {
+        return;
+    };
+    let offset = start.saturating_duration_since(log.started);
+    let _ = writeln!(
+        log.file,
+        "+{:.3}s {:.3}s {outcome} {line}",
+        offset.as_secs_f64(),
+        duration.as_secs_f64()
+    );
+}
@@ -1,4 +1,37 @@
-use crate::app::Upstream;
+use serde_json::Value;
+
+use crate::app::{EnvVar, Upstream};
+
+/// Image of the Caddy container when none is set.
+pub const DEFAULT_IMAGE: &str = "caddy:2-alpine";
+
+/// A TCP or UDP port forwarded to an app, see [`Caddy::stream`].
+#[derive(Debug, Clone)]
+pub struct Stream {
+    /// Port listened on by the server.
+    pub port: u16,
+    pub upstream: Upstream,
+    /// UDP instead of TCP.
+    pub udp: bool,
+}
+
+impl Stream {
+    /// `tcp` or `udp`.
+    #[must_use]
+    pub const fn protocol(&self) -> &'static str {
+        if self.udp { "udp" } else { "tcp" }
+    }
+}
+
+/// ACME DNS-01 challenge settings, see [`Caddy::dns_challenge`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsChallenge {
+    /// Caddy DNS provider module, e.g. `cloudflare`.
+    pub provider: String,
+    /// The provider's API token, passed to the Caddy container
+    /// as a secret environment variable.
+    pub token: EnvVar,
+}
 
 /// Configuration for the Caddy reverse proxy container.
 ///
@@ -20,6 +53,7 @@ use crate::app::Upstream;
 /// assert_eq!(caddy.volumes.len(), 1);
 /// ```
 #[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Caddy {
     pub basic_auth: Option<(String, String)>,
     pub reverse_proxy: Option<Upstream>,
@@ -40,6 +74,26 @@ pub struct Caddy {
     /// during deployment). The file content is embedded into the
     /// Caddyfile as a `handle_errors` block with `respond`.
     pub maintenance_page: Option<String>,
+    /// Let the auto-update sidecar pull new Caddy releases.
+    pub auto_update: bool,
+    /// Configure Caddy with its native JSON config instead of a
+    /// Caddyfile; see [`crate::caddyjson`].
+    pub json: bool,
+    /// Extra apps of the JSON config, by name (e.g. `layer4`).
+    pub json_apps: Vec<(String, Value)>,
+    /// Custom Caddy image (default [`DEFAULT_IMAGE`]).
+    pub image: Option<String>,
+    /// The image includes the layer4 plugin, so streams go
+    /// through Caddy.
+    pub layer4: bool,
+    /// TCP/UDP ports forwarded to apps.
+    pub streams: Vec<Stream>,
+    /// Proxy through the server's shared Caddy instead of one in
+    /// the stack; see [`crate::shared_caddy`].
+    pub shared: bool,
+    /// Get certificates with the DNS-01 challenge instead of
+    /// HTTP-01.
+    pub dns_challenge: Option<DnsChallenge>,
 }
 
 impl Caddy {
@@ -60,6 +114,22 @@ impl Caddy {
         self
     }
 
+    /// Proxy to a gRPC backend, over cleartext HTTP/2 (see
+    /// [`Upstream::h2c`]).
+    ///
+    /// ```
+    /// use catapulta::{App, Caddy};
+    ///
+    /// let app = App::new("api").expose(50051);
+    /// let caddy = Caddy::new().reverse_proxy_grpc(app.upstream());
+    ///
+    /// assert!(caddy.reverse_proxy.unwrap().h2c);
+    /// ```
+    #[must_use]
+    pub fn reverse_proxy_grpc(self, upstream: Upstream) -> Self {
+        self.reverse_proxy(upstream.h2c())
+    }
+
     #[must_use]
     pub const fn gzip(mut self) -> Self {
         self.gzip = true;
@@ -80,6 +150,34 @@ impl Caddy {
         self
     }
 
+    /// Get certificates with the ACME DNS-01 challenge, through
+    /// the Caddy DNS `provider` module (e.g. `cloudflare`) with
+    /// the API `token` set in the container as `token_env`.
+    ///
+    /// Required for wildcard domains such as `*.example.com`,
+    /// e.g. an [`App::domain`](crate::App::domain) routing every
+    /// tenant's subdomain to one container. The provider module
+    /// isn't part of the default image: build one with `xcaddy`
+    /// and set it with [`Self::image`].
+    ///
+    /// ```
+    /// use catapulta::Caddy;
+    ///
+    /// let caddy = Caddy::new()
+    ///     .image("ghcr.io/example/caddy-cloudflare:2")
+    ///     .dns_challenge("cloudflare", "CF_API_TOKEN", "token");
+    ///
+    /// assert_eq!(caddy.dns_challenge.unwrap().provider, "cloudflare");
+    /// ```
+    #[must_use]
+    pub fn dns_challenge(mut self, provider: &str, token_env: &str, token: &str) -> Self {
+        self.dns_challenge = Some(DnsChallenge {
+            provider: provider.to_string(),
+            token: EnvVar::secret(token_env, token),
+        });
+        self
+    }
+
     /// Add a path-based route rendered as a Caddy `handle` block.
     ///
     /// Use `/*` suffix for prefix matching. The last route
@@ -90,6 +188,14 @@ impl Caddy {
         self
     }
 
+    /// Add a path-based route to a gRPC backend, over cleartext
+    /// HTTP/2. gRPC paths are `/<package>.<Service>/<Method>`,
+    /// so match a package with e.g. `/helloworld.*`.
+    #[must_use]
+    pub fn route_grpc(self, path: &str, upstream: Upstream) -> Self {
+        self.route(path, upstream.h2c())
+    }
+
     /// Returns true when Caddy should be included in the
     /// compose stack (has a `reverse_proxy` or routes).
     #[must_use]
@@ -113,6 +219,168 @@ impl Caddy {
         self
     }
 
+    /// Let the auto-update sidecar pull new releases of the
+    /// Caddy image.
+    #[must_use]
+    pub const fn auto_update(mut self) -> Self {
+        self.auto_update = true;
+        self
+    }
+
+    /// Render Caddy's native JSON config (`caddy.json`) instead
+    /// of a Caddyfile, for features only it can express.
+    #[must_use]
+    pub const fn json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// Add an app to the JSON config, next to the generated
+    /// `http` app (which it replaces when named `http`). Implies
+    /// [`Self::json`].
+    ///
+    /// ```
+    /// use catapulta::Caddy;
+    /// use serde_json::json;
+    ///
+    /// let caddy = Caddy::new().json_app(
+    ///     "layer4",
+    ///     json!({ "servers": { "ssh": {
+    ///         "listen": [":2222"],
+    ///         "routes": [{ "handle": [{
+    ///             "handler": "proxy",
+    ///             "upstreams": [{ "dial": ["git:22"] }]
+    ///         }] }]
+    ///     } } }),
+    /// );
+    ///
+    /// assert!(caddy.json);
+    /// ```
+    #[must_use]
+    pub fn json_app(mut self, name: &str, config: Value) -> Self {
+        self.json = true;
+        self.json_apps.push((name.to_string(), config));
+        self
+    }
+
+    /// Run a custom Caddy image, e.g. one built with `xcaddy`
+    /// to add plugins.
+    #[must_use]
+    pub fn image(mut self, image: &str) -> Self {
+        self.image = Some(image.to_string());
+        self
+    }
+
+    /// The Caddy image includes the [layer4] plugin: streams are
+    /// proxied by Caddy instead of published by their app.
+    ///
+    /// [layer4]: https://github.com/mholt/caddy-l4
+    #[must_use]
+    pub const fn layer4(mut self) -> Self {
+        self.layer4 = true;
+        self
+    }
+
+    /// Forward TCP port `listen_port` of the server to
+    /// `upstream`, for services that don't speak HTTP (MQTT, SMTP,
+    /// game servers, ...).
+    ///
+    /// With [`Self::layer4`], Caddy listens on the port and proxies
+    /// the connections (switching to the JSON config). Otherwise
+    /// the upstream's container publishes the port itself. Either
+    /// way, deploys open it in the server's firewall.
+    ///
+    /// ```
+    /// use catapulta::{App, Caddy};
+    ///
+    /// let mqtt = App::new("mosquitto").image("eclipse-mosquitto:2").expose(1883);
+    /// let caddy = Caddy::new().stream(8883, mqtt.upstream());
+    ///
+    /// assert_eq!(caddy.streams[0].protocol(), "tcp");
+    /// ```
+    #[must_use]
+    pub fn stream(mut self, listen_port: u16, upstream: Upstream) -> Self {
+        self.streams.push(Stream {
+            port: listen_port,
+            upstream,
+            udp: false,
+        });
+        self
+    }
+
+    /// Forward UDP port `listen_port` to `upstream`; see
+    /// [`Self::stream`].
+    #[must_use]
+    pub fn stream_udp(mut self, listen_port: u16, upstream: Upstream) -> Self {
+        self.streams.push(Stream {
+            port: listen_port,
+            upstream,
+            udp: true,
+        });
+        self
+    }
+
+    /// Serve the stack's sites from a Caddy shared by every
+    /// stack on the server, so several pipelines can deploy to
+    /// one server without fighting over ports 80 and 443.
+    ///
+    /// The shared Caddy is started on the first deploy using it
+    /// and imports one Caddyfile per stack; see
+    /// [`crate::shared_caddy`]. It is configured with a
+    /// Caddyfile, so [`Self::json`] isn't supported, and streams
+    /// are published by their app. Stacks sharing a server are
+    /// told apart by [`crate::Pipeline::project`] and should give
+    /// their apps their own [`App::domain`](crate::App::domain).
+    ///
+    /// ```
+    /// use catapulta::{App, Caddy};
+    ///
+    /// let app = App::new("blog").expose(2368);
+    /// let caddy = Caddy::new().reverse_proxy(app.upstream()).shared();
+    ///
+    /// assert!(caddy.shared);
+    /// ```
+    #[must_use]
+    pub const fn shared(mut self) -> Self {
+        self.shared = true;
+        self
+    }
+
+    /// Whether Caddy proxies the streams, through layer4.
+    #[must_use]
+    pub fn proxies_streams(&self) -> bool {
+        self.layer4 && !self.shared && !self.streams.is_empty()
+    }
+
+    /// Whether Caddy is configured with the JSON config: when
+    /// asked to, or to proxy streams.
+    #[must_use]
+    pub fn uses_json(&self) -> bool {
+        self.json || self.proxies_streams()
+    }
+
+    /// Name of the generated config file: `caddy.json` with the
+    /// JSON config, `Caddyfile` otherwise.
+    #[must_use]
+    pub fn config_file(&self) -> &'static str {
+        if self.uses_json() {
+            "caddy.json"
+        } else {
+            "Caddyfile"
+        }
+    }
+
+    /// Command run in the Caddy container to load the generated
+    /// config through Caddy's admin API.
+    #[must_use]
+    pub fn reload_command(&self) -> String {
+        if self.uses_json() {
+            "caddy reload --config /etc/caddy/caddy.json".to_string()
+        } else {
+            "caddy reload --config /etc/caddy/Caddyfile --adapter caddyfile".to_string()
+        }
+    }
+
     /// Set a maintenance page served on 502/503/504 errors.
     ///
     /// The given path should point to a local HTML file. Its
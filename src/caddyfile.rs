@@ -1,25 +1,180 @@
 use caddyfile_rs::{Caddyfile, Directive, Matcher, SiteBlock, format};
 
+use crate::app::{App, Upstream};
 use crate::caddy::Caddy;
+use crate::caddyjson;
+use crate::dns;
+use crate::error::{DeployError, DeployResult};
 
 /// Render a complete Caddyfile from the Caddy config.
 #[must_use]
 pub fn render(caddy: &Caddy, domain: &str) -> String {
-    let mut site = SiteBlock::new(domain);
+    render_with_apps(caddy, domain, &[])
+}
 
-    if let Some((user, hash)) = &caddy.basic_auth {
-        site = site.basic_auth(user, hash);
+/// Render the config file named by [`Caddy::config_file`]: the
+/// JSON config when [`Caddy::uses_json`], the Caddyfile
+/// otherwise.
+///
+/// Fails for a wildcard site when Caddy can't get a certificate
+/// for it, i.e. without [`Caddy::dns_challenge`] or
+/// [`Caddy::tls_internal`].
+pub fn render_config(caddy: &Caddy, domain: &str, apps: &[App]) -> DeployResult<String> {
+    let wildcard = std::iter::once(domain)
+        .chain(apps.iter().filter_map(|a| a.domain.as_deref()))
+        .find(|d| dns::is_wildcard(d));
+    if let Some(wildcard) =
+        wildcard.filter(|_| caddy.dns_challenge.is_none() && !caddy.tls_internal)
+    {
+        return Err(DeployError::InvalidConfig(format!(
+            "{wildcard} needs a certificate from the DNS-01 challenge; \
+             set it up with `Caddy::dns_challenge`"
+        )));
+    }
+    if caddy.uses_json() && caddy.dns_challenge.is_some() {
+        return Err(DeployError::InvalidConfig(
+            "Caddy::dns_challenge is only rendered in the Caddyfile, not the JSON config".into(),
+        ));
     }
+    if caddy.uses_json() {
+        caddyjson::render_with_apps(caddy, domain, apps)
+    } else {
+        Ok(render_with_apps(caddy, domain, apps))
+    }
+}
+
+/// Render a Caddyfile with a site block for `domain` plus one
+/// per app that has its own [`App::domain`].
+///
+/// App sites proxy to the app's first exposed port and share the
+/// auth, TLS, compression, header, and maintenance settings of
+/// the main site. The main site is left out when it has nothing
+/// to serve and every app has its own domain.
+#[must_use]
+pub fn render_with_apps(caddy: &Caddy, domain: &str, apps: &[App]) -> String {
+    let app_sites: Vec<&App> = apps.iter().filter(|a| a.domain.is_some()).collect();
+    let mut caddyfile = Caddyfile::new();
+
+    let main_is_empty = !caddy.has_upstreams() && caddy.extra_directives.is_empty();
+    if app_sites.is_empty() || !main_is_empty {
+        let mut site = SiteBlock::new(domain);
+        if let Some((user, hash)) = &caddy.basic_auth {
+            site = site.basic_auth(user, hash);
+        }
+
+        // Routes take precedence over single reverse_proxy
+        if !caddy.routes.is_empty() {
+            site = add_route_handles(site, &caddy.routes, apps);
+        } else if let Some(upstream) = &caddy.reverse_proxy {
+            site = site.directive(reverse_proxy(upstream, apps));
+        }
 
-    // Routes take precedence over single reverse_proxy
-    if !caddy.routes.is_empty() {
-        site = add_route_handles(site, &caddy.routes);
-    } else if let Some(upstream) = &caddy.reverse_proxy {
-        site = site.reverse_proxy(&upstream.to_string());
+        site = add_shared_directives(site, caddy);
+        for d in &caddy.extra_directives {
+            site = site.directive(Directive::new(d));
+        }
+        if let Some(ref path) = caddy.maintenance_page {
+            site = add_maintenance_page(site, path);
+        }
+        caddyfile = caddyfile.site(site);
+    }
+
+    for app in app_sites {
+        let Some(app_domain) = &app.domain else {
+            continue;
+        };
+        let mut site = SiteBlock::new(app_domain);
+        if let Some((user, hash)) = &caddy.basic_auth {
+            site = site.basic_auth(user, hash);
+        }
+        site = site.directive(reverse_proxy(&app.upstream(), apps));
+        site = add_shared_directives(site, caddy);
+        if let Some(ref path) = caddy.maintenance_page {
+            site = add_maintenance_page(site, path);
+        }
+        caddyfile = caddyfile.site(site);
     }
 
+    format(&caddyfile)
+}
+
+/// Page served by `maintenance --on` when no
+/// [`Caddy::maintenance_page`] is set.
+pub const DEFAULT_MAINTENANCE_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>Down for maintenance</title></head>
+<body>
+<h1>Down for maintenance</h1>
+<p>We'll be back shortly.</p>
+</body>
+</html>";
+
+/// Render a Caddyfile answering every site of the stack with
+/// `html` and a `503`, advertising `Retry-After: retry_after`
+/// seconds.
+///
+/// Used by `maintenance --on`. Sites keep their addresses and
+/// TLS settings so certificates carry over, but proxy nothing.
+#[must_use]
+pub fn render_maintenance(
+    caddy: &Caddy,
+    domain: &str,
+    apps: &[App],
+    html: &str,
+    retry_after: u32,
+) -> String {
+    let app_domains = apps.iter().filter_map(|a| a.domain.as_deref());
+    let main_is_empty = !caddy.has_upstreams() && caddy.extra_directives.is_empty();
+    let has_app_sites = apps.iter().any(|a| a.domain.is_some());
+    let main = (!has_app_sites || !main_is_empty).then_some(domain);
+
+    let mut caddyfile = Caddyfile::new();
+    for address in main.into_iter().chain(app_domains) {
+        let mut site = SiteBlock::new(address);
+        if let Some(tls) = tls_directive(caddy) {
+            site = site.directive(tls);
+        }
+        site = site
+            .directive(
+                Directive::new("header")
+                    .arg("Content-Type")
+                    .quoted_arg("text/html; charset=utf-8"),
+            )
+            .directive(
+                Directive::new("header")
+                    .arg("Retry-After")
+                    .arg(&retry_after.to_string()),
+            );
+        // Heredoc content and closing marker are unindented, as
+        // in the maintenance page `handle_errors` block
+        site = site.directive(Directive::new(&format!("respond <<HTML\n{html}\nHTML 503")));
+        caddyfile = caddyfile.site(site);
+    }
+
+    format(&caddyfile)
+}
+
+/// The `tls` directive of every site: `tls internal`, or the
+/// DNS-01 challenge with the provider's token read from the
+/// container's environment.
+fn tls_directive(caddy: &Caddy) -> Option<Directive> {
     if caddy.tls_internal {
-        site = site.directive(Directive::new("tls internal"));
+        return Some(Directive::new("tls internal"));
+    }
+    caddy.dns_challenge.as_ref().map(|challenge| {
+        Directive::new("tls").block(vec![
+            Directive::new("dns")
+                .arg(&challenge.provider)
+                .arg(&format!("{{env.{}}}", challenge.token.key)),
+        ])
+    })
+}
+
+/// TLS, compression, and security header settings applied to
+/// every site block.
+fn add_shared_directives(mut site: SiteBlock, caddy: &Caddy) -> SiteBlock {
+    if let Some(tls) = tls_directive(caddy) {
+        site = site.directive(tls);
     }
 
     if caddy.gzip {
@@ -30,16 +185,7 @@ pub fn render(caddy: &Caddy, domain: &str) -> String {
         site = site.security_headers();
     }
 
-    for d in &caddy.extra_directives {
-        site = site.directive(Directive::new(d));
-    }
-
-    if let Some(ref path) = caddy.maintenance_page {
-        site = add_maintenance_page(site, path);
-    }
-
-    let caddyfile = Caddyfile::new().site(site);
-    format(&caddyfile)
+    site
 }
 
 /// Add `handle_errors` block that serves a user-provided
@@ -72,14 +218,56 @@ fn add_maintenance_page(site: SiteBlock, path: &str) -> SiteBlock {
     site.directive(Directive::new(&raw))
 }
 
+/// A `reverse_proxy` directive for `upstream`.
+///
+/// An app with several replicas is proxied through a `dynamic a`
+/// upstream, which resolves every replica's address from the
+/// Docker network and balances requests over them. h2c upstreams
+/// get an HTTP transport limited to cleartext HTTP/2.
+fn reverse_proxy(upstream: &Upstream, apps: &[App]) -> Directive {
+    let scaled = apps
+        .iter()
+        .any(|a| a.name == upstream.name && a.replicas > 1);
+    let mut options = Vec::new();
+    if upstream.h2c {
+        options.push(
+            Directive::new("transport")
+                .arg("http")
+                .block(vec![Directive::new("versions").arg("h2c").arg("2")]),
+        );
+    }
+    if !scaled {
+        let directive = Directive::new("reverse_proxy").arg(&upstream.to_string());
+        return if options.is_empty() {
+            directive
+        } else {
+            directive.block(options)
+        };
+    }
+    let mut block = vec![
+        Directive::new("dynamic")
+            .arg("a")
+            .arg(&upstream.name)
+            .arg(&upstream.port.to_string())
+            .block(vec![Directive::new("refresh").arg("5s")]),
+        Directive::new("lb_policy").arg("round_robin"),
+    ];
+    block.append(&mut options);
+    Directive::new("reverse_proxy").block(block)
+}
+
 /// Build `handle` directives for path-based routing.
 ///
 /// Routes with a path pattern get `handle <path> { ... }`.
 /// A route with an empty path becomes a bare `handle { ... }`
 /// (catch-all).
-fn add_route_handles(mut site: SiteBlock, routes: &[(String, crate::app::Upstream)]) -> SiteBlock {
+fn add_route_handles(
+    mut site: SiteBlock,
+    routes: &[(String, Upstream)],
+    apps: &[App],
+) -> SiteBlock {
     for (path, upstream) in routes {
-        let inner = vec![Directive::new("reverse_proxy").arg(&upstream.to_string())];
+        let inner = vec![reverse_proxy(upstream, apps)];
         let mut handle = Directive::new("handle");
         if !path.is_empty() {
             handle = handle.matcher(Matcher::Path(path.clone()));
@@ -0,0 +1,208 @@
+//! Caddy's native JSON config.
+//!
+//! With [`Caddy::json`], the stack is configured with a
+//! `caddy.json` instead of a Caddyfile, loaded through Caddy's
+//! admin API. It expresses the same sites as
+//! [`crate::caddyfile::render_with_apps`], and takes extra apps
+//! the Caddyfile has no syntax for (e.g. `layer4`) through
+//! [`Caddy::json_app`]. Streams are rendered as a `layer4` app
+//! when [`Caddy::proxies_streams`]. Raw [`Caddy::directive`]s
+//! are Caddyfile text and can't be combined with it.
+
+use serde_json::{Map, Value, json};
+
+use crate::app::{App, Upstream};
+use crate::caddy::Caddy;
+use crate::error::{DeployError, DeployResult};
+
+/// Render the JSON config serving `domain` plus one site per app
+/// with its own [`App::domain`], pretty-printed.
+pub fn render_with_apps(caddy: &Caddy, domain: &str, apps: &[App]) -> DeployResult<String> {
+    Ok(serde_json::to_string_pretty(&config(caddy, domain, apps)?)? + "\n")
+}
+
+/// The JSON config as a value; see [`render_with_apps`].
+pub fn config(caddy: &Caddy, domain: &str, apps: &[App]) -> DeployResult<Value> {
+    if !caddy.extra_directives.is_empty() {
+        return Err(DeployError::InvalidConfig(
+            "raw Caddyfile directives can't be used with the JSON config; \
+             add the equivalent with Caddy::json_app"
+                .into(),
+        ));
+    }
+
+    let app_sites: Vec<&App> = apps.iter().filter(|a| a.domain.is_some()).collect();
+    let mut hosts = Vec::new();
+    let mut routes = Vec::new();
+
+    if app_sites.is_empty() || caddy.has_upstreams() {
+        let mut site = shared_routes(caddy);
+        if caddy.routes.is_empty() {
+            if let Some(upstream) = &caddy.reverse_proxy {
+                site.push(json!({ "handle": [reverse_proxy(upstream, apps)] }));
+            }
+        } else {
+            for (path, upstream) in &caddy.routes {
+                let mut route = json!({
+                    "handle": [reverse_proxy(upstream, apps)],
+                    "terminal": true,
+                });
+                if !path.is_empty() {
+                    route["match"] = json!([{ "path": [path] }]);
+                }
+                site.push(route);
+            }
+        }
+        routes.push(site_route(domain, &site));
+        hosts.push(domain.to_string());
+    }
+
+    for app in app_sites {
+        let Some(app_domain) = &app.domain else {
+            continue;
+        };
+        let mut site = shared_routes(caddy);
+        site.push(json!({ "handle": [reverse_proxy(&app.upstream(), apps)] }));
+        routes.push(site_route(app_domain, &site));
+        hosts.push(app_domain.clone());
+    }
+
+    let mut server = json!({ "listen": [":443"], "routes": routes });
+    if let Some(path) = &caddy.maintenance_page {
+        let html = std::fs::read_to_string(path)
+            .map_err(|_| DeployError::FileNotFound(format!("maintenance page: {path}")))?;
+        server["errors"] = json!({ "routes": [maintenance_route(&html)] });
+    }
+
+    let mut caddy_apps = Map::new();
+    caddy_apps.insert("http".to_string(), json!({ "servers": { "srv0": server } }));
+    if caddy.tls_internal {
+        caddy_apps.insert(
+            "tls".to_string(),
+            json!({
+                "automation": {
+                    "policies": [{ "subjects": hosts, "issuers": [{ "module": "internal" }] }]
+                }
+            }),
+        );
+    }
+    if caddy.proxies_streams() {
+        caddy_apps.insert("layer4".to_string(), layer4(caddy));
+    }
+    for (name, value) in &caddy.json_apps {
+        caddy_apps.insert(name.clone(), value.clone());
+    }
+
+    Ok(json!({ "apps": caddy_apps }))
+}
+
+/// A route matching `host` and handing requests to `routes`.
+fn site_route(host: &str, routes: &[Value]) -> Value {
+    json!({
+        "match": [{ "host": [host] }],
+        "handle": [{ "handler": "subroute", "routes": routes }],
+        "terminal": true,
+    })
+}
+
+/// Header, compression and auth routes every site starts with,
+/// in the order the Caddyfile adapter sorts them.
+fn shared_routes(caddy: &Caddy) -> Vec<Value> {
+    let mut routes = Vec::new();
+    if caddy.security_headers {
+        routes.push(json!({
+            "handle": [{
+                "handler": "headers",
+                "response": {
+                    "set": {
+                        "X-Content-Type-Options": ["nosniff"],
+                        "X-Frame-Options": ["DENY"],
+                        "X-Xss-Protection": ["1; mode=block"],
+                        "Referrer-Policy": ["strict-origin-when-cross-origin"],
+                    }
+                }
+            }]
+        }));
+    }
+    if caddy.gzip {
+        routes.push(json!({
+            "handle": [{ "handler": "encode", "encodings": { "gzip": {} }, "prefer": ["gzip"] }]
+        }));
+    }
+    if let Some((user, hash)) = &caddy.basic_auth {
+        // ACME HTTP challenges must stay reachable
+        routes.push(json!({
+            "match": [{ "not": [{ "path": ["/.well-known/acme-challenge/*"] }] }],
+            "handle": [{
+                "handler": "authentication",
+                "providers": {
+                    "http_basic": {
+                        "accounts": [{ "username": user, "password": hash }],
+                        "hash": { "algorithm": "bcrypt" },
+                    }
+                }
+            }]
+        }));
+    }
+    routes
+}
+
+/// A `reverse_proxy` handler for `upstream`; scaled apps and
+/// h2c upstreams are handled as in the Caddyfile.
+fn reverse_proxy(upstream: &Upstream, apps: &[App]) -> Value {
+    let scaled = apps
+        .iter()
+        .any(|a| a.name == upstream.name && a.replicas > 1);
+    let mut handler = json!({ "handler": "reverse_proxy" });
+    if scaled {
+        handler["dynamic_upstreams"] = json!({
+            "source": "a",
+            "name": upstream.name,
+            "port": upstream.port.to_string(),
+            "refresh": "5s",
+        });
+        handler["load_balancing"] = json!({ "selection_policy": { "policy": "round_robin" } });
+    } else {
+        handler["upstreams"] = json!([{ "dial": upstream.to_string() }]);
+    }
+    if upstream.h2c {
+        handler["transport"] = json!({ "protocol": "http", "versions": ["h2c", "2"] });
+    }
+    handler
+}
+
+/// The `layer4` app: one server per stream, proxying to its
+/// upstream.
+fn layer4(caddy: &Caddy) -> Value {
+    let mut servers = Map::new();
+    for stream in &caddy.streams {
+        let protocol = stream.protocol();
+        servers.insert(
+            format!("{protocol}-{}", stream.port),
+            json!({
+                "listen": [format!("{protocol}/:{}", stream.port)],
+                "routes": [{
+                    "handle": [{
+                        "handler": "proxy",
+                        "upstreams": [{ "dial": [format!("{protocol}/{}", stream.upstream)] }]
+                    }]
+                }]
+            }),
+        );
+    }
+    json!({ "servers": servers })
+}
+
+/// Error route serving `html` on 502, 503 and 504.
+fn maintenance_route(html: &str) -> Value {
+    json!({
+        "match": [{ "expression": "{http.error.status_code} in [502, 503, 504]" }],
+        "handle": [
+            {
+                "handler": "headers",
+                "response": { "set": { "Content-Type": ["text/html; charset=utf-8"] } }
+            },
+            { "handler": "static_response", "body": html, "status_code": 200 }
+        ]
+    })
+}
@@ -0,0 +1,188 @@
+//! CI workflows running the deploys, printed by
+//! `cargo xtask ci generate github`.
+//!
+//! The GitHub Actions workflow deploys on every push to a branch
+//! (and on demand): it restores the Cargo cache, loads the SSH
+//! key from the [`SSH_KEY_SECRET`] secret into an agent, writes
+//! each app's env file from a secret, and runs
+//! `cargo xtask deploy` on the hosts in the [`HOSTS_VARIABLE`]
+//! repository variable, with the registry tokens and the
+//! confirmation of a protected environment in its environment.
+//! Runs never overlap, so two pushes can't deploy at once.
+//!
+//! With the local state (see [`crate::state`]), each run starts
+//! without the servers recorded by `provision`: deploy to
+//! domains or IPs, or share the state with
+//! [`crate::Pipeline::state_store`].
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::pipeline::CONFIRM_ENV;
+use crate::ssh::shell_quote;
+use crate::state::USER_ENV;
+
+/// Secret holding the private SSH key the deploys connect with.
+pub const SSH_KEY_SECRET: &str = "SSH_PRIVATE_KEY";
+
+/// Repository variable holding the hosts to deploy to,
+/// separated by spaces.
+pub const HOSTS_VARIABLE: &str = "DEPLOY_HOSTS";
+
+/// A CI workflow deploying the pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workflow {
+    /// Branch whose pushes deploy.
+    pub branch: String,
+    /// Env files written before the deploy, with the secret
+    /// holding the content of each.
+    pub env_files: Vec<(String, String)>,
+    /// Environment variables of the deploy, each set from the
+    /// secret of the same name.
+    pub secrets: Vec<String>,
+    /// Protected environment the deploy confirms, also the
+    /// GitHub environment of the job, so its protection rules
+    /// (required reviewers, ...) apply.
+    pub environment: Option<String>,
+}
+
+impl Workflow {
+    #[must_use]
+    pub fn new(branch: &str) -> Self {
+        Self {
+            branch: branch.to_string(),
+            env_files: Vec::new(),
+            secrets: Vec::new(),
+            environment: None,
+        }
+    }
+
+    /// Write `path` from the `secret` secret before deploying.
+    #[must_use]
+    pub fn env_file(mut self, path: &str, secret: &str) -> Self {
+        self.env_files.push((path.to_string(), secret.to_string()));
+        self
+    }
+
+    /// Pass the `name` secret to the deploy as the `name`
+    /// environment variable.
+    #[must_use]
+    pub fn secret(mut self, name: &str) -> Self {
+        if !self.secrets.iter().any(|s| s == name) {
+            self.secrets.push(name.to_string());
+        }
+        self
+    }
+
+    /// Run in the GitHub environment `name`, confirming the
+    /// deploys of the protected environment of that name.
+    #[must_use]
+    pub fn environment(mut self, name: &str) -> Self {
+        self.environment = Some(name.to_string());
+        self
+    }
+
+    /// The secrets to create in the repository, in the order the
+    /// workflow uses them.
+    #[must_use]
+    pub fn required_secrets(&self) -> Vec<&str> {
+        std::iter::once(SSH_KEY_SECRET)
+            .chain(self.env_files.iter().map(|(_, secret)| secret.as_str()))
+            .chain(self.secrets.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// The GitHub Actions workflow, e.g. for
+    /// `.github/workflows/deploy.yml`.
+    #[must_use]
+    pub fn github(&self) -> String {
+        let mut yaml = String::from("# Generated by `cargo xtask ci generate github`.\n#\n");
+        let secrets = self.required_secrets().join(", ");
+        let _ = writeln!(yaml, "# Repository secrets: {secrets}");
+        let _ = writeln!(
+            yaml,
+            "# Repository variable: {HOSTS_VARIABLE}, the hosts to deploy to"
+        );
+        yaml += "\nname: Deploy\n\non:\n  push:\n";
+        let _ = writeln!(yaml, "    branches: [{}]", self.branch);
+        yaml += "  workflow_dispatch:\n\n";
+        yaml += "concurrency:\n  group: deploy\n  cancel-in-progress: false\n\n";
+        yaml += "jobs:\n  deploy:\n    runs-on: ubuntu-latest\n";
+        if let Some(environment) = &self.environment {
+            let _ = writeln!(yaml, "    environment: {environment}");
+        }
+        yaml += "    steps:\n";
+        yaml += "      - uses: actions/checkout@v4\n";
+        yaml += "      - uses: dtolnay/rust-toolchain@stable\n";
+        yaml += "      - uses: Swatinem/rust-cache@v2\n";
+        yaml += "      - uses: webfactory/ssh-agent@v0.9.0\n        with:\n";
+        let _ = writeln!(
+            yaml,
+            "          ssh-private-key: ${{{{ secrets.{SSH_KEY_SECRET} }}}}"
+        );
+        if !self.env_files.is_empty() {
+            yaml += &self.env_files_step();
+        }
+        yaml += &self.deploy_step();
+        yaml
+    }
+
+    fn env_files_step(&self) -> String {
+        let mut step = String::from("      - name: Write env files\n        env:\n");
+        for (_, secret) in &self.env_files {
+            let _ = writeln!(step, "          {secret}: ${{{{ secrets.{secret} }}}}");
+        }
+        step += "        run: |\n";
+        for (path, secret) in &self.env_files {
+            let dir = Path::new(path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty());
+            if let Some(dir) = dir {
+                let _ = writeln!(
+                    step,
+                    "          mkdir -p {}",
+                    shell_quote(&dir.to_string_lossy())
+                );
+            }
+            let _ = writeln!(
+                step,
+                "          printf '%s\\n' \"${secret}\" > {}",
+                shell_quote(path)
+            );
+        }
+        step
+    }
+
+    fn deploy_step(&self) -> String {
+        let mut step = String::from("      - name: Deploy\n        env:\n");
+        let _ = writeln!(step, "          {USER_ENV}: ${{{{ github.actor }}}}");
+        if let Some(environment) = &self.environment {
+            let _ = writeln!(step, "          {CONFIRM_ENV}: {environment}");
+        }
+        for secret in &self.secrets {
+            let _ = writeln!(step, "          {secret}: ${{{{ secrets.{secret} }}}}");
+        }
+        let _ = writeln!(
+            step,
+            "        run: cargo xtask deploy ${{{{ vars.{HOSTS_VARIABLE} }}}}"
+        );
+        step
+    }
+}
+
+/// The secret holding the env file of the app `name`, e.g.
+/// `ENV_FILE_API` for `api`.
+#[must_use]
+pub fn env_file_secret(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("ENV_FILE_{name}")
+}
@@ -1,86 +1,272 @@
-use std::process::{Command, Output, Stdio};
+use std::fmt;
+use std::io::Read;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::audit;
 use crate::error::{DeployError, DeployResult};
 
+/// Default timeout for provider API calls (`curl`, `doctl`).
+pub const API_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default timeout for non-interactive remote commands.
+pub const REMOTE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default number of attempts for [`retry`].
+pub const RETRY_ATTEMPTS: u32 = 3;
+
+/// Default initial delay for [`retry`], doubled after each
+/// failed attempt.
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Run `f` up to `attempts` times, retrying only transient
+/// failures (see [`DeployError::is_transient`]).
+///
+/// Waits `backoff` after the first failure and doubles the delay
+/// after each subsequent one. Permanent errors and the error of
+/// the last attempt are returned as-is.
+pub fn retry<T>(
+    attempts: u32,
+    backoff: Duration,
+    mut f: impl FnMut() -> DeployResult<T>,
+) -> DeployResult<T> {
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if attempt < attempts && e.is_transient() => {
+                eprintln!(
+                    "Transient failure: {e}; retrying in {}s \
+                     (attempt {}/{attempts})",
+                    delay.as_secs(),
+                    attempt + 1
+                );
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
 /// Run a command and capture its output. Fails if the command
 /// returns a non-zero exit code.
 pub fn run(program: &str, args: &[&str]) -> DeployResult<String> {
-    let output = spawn(program, args)?;
-
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-        let command = format_command(program, args);
-        eprintln!("stderr: {stderr}");
-        Err(DeployError::CommandFailed {
-            command,
-            status: output.status,
-        })
-    }
+    audit::record(program, args, || {
+        let output = spawn(program, args)?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            let command = format_command(program, args);
+            eprintln!("stderr: {stderr}");
+            Err(DeployError::CommandFailed {
+                command,
+                status: output.status,
+            })
+        }
+    })
+}
+
+/// Run a command and capture its output, killing it if it does
+/// not finish within `timeout`.
+///
+/// Behaves like [`run`] otherwise. Fails with
+/// [`DeployError::Timeout`] when the deadline passes.
+pub fn run_with_timeout(program: &str, args: &[&str], timeout: Duration) -> DeployResult<String> {
+    run_classified(program, args, timeout, |_| None)
+}
+
+/// Run a command like [`run_with_timeout`], turning a failure
+/// into the error `classify` derives from its stderr, if any,
+/// instead of [`DeployError::CommandFailed`].
+pub fn run_classified(
+    program: &str,
+    args: &[&str],
+    timeout: Duration,
+    classify: impl FnOnce(&str) -> Option<DeployError>,
+) -> DeployResult<String> {
+    audit::record(program, args, || {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    DeployError::CommandNotFound(program.to_string())
+                } else {
+                    DeployError::Io(e)
+                }
+            })?;
+
+        // Drain both pipes on their own threads so a chatty child
+        // cannot block on a full pipe while we wait for it.
+        let stdout = child.stdout.take().map(drain);
+        let stderr = child.stderr.take().map(drain);
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(DeployError::Timeout {
+                    command: format_command(program, args),
+                    timeout,
+                });
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let stdout = stdout.and_then(|h| h.join().ok()).unwrap_or_default();
+        let stderr = stderr.and_then(|h| h.join().ok()).unwrap_or_default();
+
+        if status.success() {
+            Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+        } else {
+            let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+            eprintln!("stderr: {stderr}");
+            Err(
+                classify(&stderr).unwrap_or_else(|| DeployError::CommandFailed {
+                    command: format_command(program, args),
+                    status,
+                }),
+            )
+        }
+    })
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
 }
 
 /// Run a command with stdin/stdout/stderr inherited (interactive).
 pub fn run_interactive(program: &str, args: &[&str]) -> DeployResult<()> {
-    let status = Command::new(program)
-        .args(args)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                DeployError::CommandNotFound(program.to_string())
-            } else {
-                DeployError::Io(e)
-            }
-        })?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        Err(DeployError::CommandFailed {
-            command: format_command(program, args),
-            status,
-        })
-    }
+    audit::record(program, args, || {
+        let status = Command::new(program)
+            .args(args)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    DeployError::CommandNotFound(program.to_string())
+                } else {
+                    DeployError::Io(e)
+                }
+            })?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(DeployError::CommandFailed {
+                command: format_command(program, args),
+                status,
+            })
+        }
+    })
 }
 
 /// Run a command that pipes its stdin from a byte slice.
 pub fn run_with_stdin(program: &str, args: &[&str], stdin_data: &[u8]) -> DeployResult<String> {
-    use std::io::Write;
+    audit::record(program, args, || {
+        use std::io::Write;
 
-    let mut child = Command::new(program)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                DeployError::CommandNotFound(program.to_string())
-            } else {
-                DeployError::Io(e)
-            }
-        })?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    DeployError::CommandNotFound(program.to_string())
+                } else {
+                    DeployError::Io(e)
+                }
+            })?;
+
+        if let Some(stdin) = &mut child.stdin {
+            stdin.write_all(stdin_data)?;
+        }
+        drop(child.stdin.take());
+
+        let output = child.wait_with_output()?;
 
-    if let Some(stdin) = &mut child.stdin {
-        stdin.write_all(stdin_data)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            eprintln!("stderr: {stderr}");
+            Err(DeployError::CommandFailed {
+                command: format_command(program, args),
+                status: output.status,
+            })
+        }
+    })
+}
+
+/// Make an HTTP request with curl, passing the options on stdin
+/// so credentials in `headers` and `body` stay out of the process
+/// list. `options` are extra lines of curl's config file (e.g.
+/// `cacert = "ca.pem"`). Returns the status and the response
+/// body.
+pub(crate) fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[String],
+    body: Option<&str>,
+    options: &[String],
+) -> DeployResult<(u16, String)> {
+    let mut config = vec![
+        format!("url = {}", curl_quote(url)),
+        format!("request = {}", curl_quote(method)),
+        format!("max-time = {}", API_TIMEOUT.as_secs()),
+        "silent".to_string(),
+        format!("write-out = {}", curl_quote("\n%{http_code}")),
+        format!("header = {}", curl_quote("Content-Type: application/json")),
+    ];
+    for header in headers {
+        config.push(format!("header = {}", curl_quote(header)));
     }
-    drop(child.stdin.take());
-
-    let output = child.wait_with_output()?;
-
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-        eprintln!("stderr: {stderr}");
-        Err(DeployError::CommandFailed {
-            command: format_command(program, args),
-            status: output.status,
-        })
+    if let Some(body) = body {
+        config.push(format!("data = {}", curl_quote(body)));
     }
+    config.extend_from_slice(options);
+
+    let output = run_with_stdin("curl", &["-K", "-"], (config.join("\n") + "\n").as_bytes())?;
+    let (response, status) = output.rsplit_once('\n').unwrap_or(("", output.as_str()));
+    let status = status.parse().map_err(|_| {
+        DeployError::Other(format!("{method} {url}: unexpected curl output: {output}"))
+    })?;
+    Ok((status, response.to_string()))
+}
+
+/// `value` as a double-quoted string of a curl config file.
+pub(crate) fn curl_quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
 
 /// Run a shell pipeline (via `sh -c`).
@@ -88,6 +274,120 @@ pub fn run_pipeline(shell_cmd: &str) -> DeployResult<()> {
     run_interactive("sh", &["-c", shell_cmd])
 }
 
+/// Commands connected like a shell pipeline, the stdout of each
+/// feeding the stdin of the next, without a shell in between.
+///
+/// Data flows straight from one process to the next, so nothing
+/// is staged on disk, and arguments need no quoting.
+///
+/// ```
+/// use catapulta::cmd::Pipe;
+///
+/// Pipe::new("echo", &["hello"]).then("tr", &["a-z", "A-Z"]).run()?;
+/// # Ok::<(), catapulta::error::DeployError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipe {
+    stages: Vec<(String, Vec<String>)>,
+}
+
+impl Pipe {
+    #[must_use]
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self { stages: Vec::new() }.then(program, args)
+    }
+
+    /// Feed the output of the pipe into `program`.
+    #[must_use]
+    pub fn then(mut self, program: &str, args: &[&str]) -> Self {
+        self.stages.push((
+            program.to_string(),
+            args.iter().map(ToString::to_string).collect(),
+        ));
+        self
+    }
+
+    /// Run the commands, the last one writing to stdout. Fails
+    /// when any of them fails, with the last failure, like
+    /// `set -o pipefail`.
+    pub fn run(&self) -> DeployResult<()> {
+        let line = self.to_string();
+        audit::record(&line, &[], || {
+            let children = self.spawn(Stdio::inherit())?;
+            self.wait(children)
+        })
+    }
+
+    /// Start the commands, the last one writing to `stdout`.
+    pub(crate) fn spawn(&self, stdout: Stdio) -> DeployResult<Vec<Child>> {
+        let mut children: Vec<Child> = Vec::new();
+        let mut stdout = Some(stdout);
+        for (i, (program, args)) in self.stages.iter().enumerate() {
+            let stdin = children
+                .last_mut()
+                .and_then(|c| c.stdout.take())
+                .map_or_else(Stdio::null, Stdio::from);
+            let out = if i + 1 == self.stages.len() {
+                stdout.take().unwrap_or_else(Stdio::piped)
+            } else {
+                Stdio::piped()
+            };
+            let spawned = Command::new(program)
+                .args(args)
+                .stdin(stdin)
+                .stdout(out)
+                .stderr(Stdio::inherit())
+                .spawn();
+            match spawned {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for child in &mut children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(if e.kind() == std::io::ErrorKind::NotFound {
+                        DeployError::CommandNotFound(program.clone())
+                    } else {
+                        DeployError::Io(e)
+                    });
+                }
+            }
+        }
+        Ok(children)
+    }
+
+    /// Wait for the `children` started by [`Pipe::spawn`],
+    /// failing with the last one that failed.
+    pub(crate) fn wait(&self, children: Vec<Child>) -> DeployResult<()> {
+        let mut result = Ok(());
+        for ((program, args), mut child) in self.stages.iter().zip(children) {
+            let status = child.wait()?;
+            if !status.success() {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                result = Err(DeployError::CommandFailed {
+                    command: format_command(program, &args),
+                    status,
+                });
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Display for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<String> = self
+            .stages
+            .iter()
+            .map(|(program, args)| {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                format_command(program, &args)
+            })
+            .collect();
+        f.write_str(&stages.join(" | "))
+    }
+}
+
 /// Check if a command exists on PATH.
 #[must_use]
 pub fn command_exists(program: &str) -> bool {
@@ -115,7 +415,5 @@ fn spawn(program: &str, args: &[&str]) -> DeployResult<Output> {
 }
 
 fn format_command(program: &str, args: &[&str]) -> String {
-    let mut parts = vec![program.to_string()];
-    parts.extend(args.iter().map(|a| (*a).to_string()));
-    parts.join(" ")
+    audit::command_line(program, args)
 }
@@ -1,25 +1,78 @@
+use std::cell::RefCell;
 use std::path::Path;
 
 use docker_compose_types::{
-    Compose, ComposeNetworks, ComposeVolume, DependsCondition, DependsOnOptions, Environment,
-    Healthcheck, HealthcheckTest, Labels, MapOrEmpty, NetworkSettings, Networks, Ports, Service,
-    Services, TopLevelVolumes, Volumes,
+    AdvancedNetworkSettings, AdvancedNetworks, Command, Compose, ComposeNetwork, ComposeNetworks,
+    ComposeVolume, DependsCondition, DependsOnOptions, Deploy, Environment, Healthcheck,
+    HealthcheckTest, Labels, MapOrEmpty, NetworkSettings, Networks, Ports, Service, Services,
+    TopLevelVolumes, Volumes,
 };
 use indexmap::IndexMap;
 
-use crate::app::App;
-use crate::caddy::Caddy;
+use crate::app::{App, EnvVar, Job};
+use crate::caddy::{Caddy, DEFAULT_IMAGE};
+use crate::shared_caddy::{self, SHARED_NETWORK};
+use crate::ssh::FileAttrs;
+
+/// Image of the auto-update sidecar.
+const WATCHTOWER_IMAGE: &str = "containrrr/watchtower:latest";
+
+/// Seconds between auto-update checks.
+const WATCHTOWER_INTERVAL: u32 = 24 * 60 * 60;
+
+thread_local! {
+    static PROJECT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous project when a scope ends, including
+/// by panic.
+struct ProjectScope(Option<String>);
+
+impl Drop for ProjectScope {
+    fn drop(&mut self) {
+        PROJECT.set(self.0.take());
+    }
+}
+
+/// Run `f` with compose files rendered for the compose
+/// `project` on this thread (see [`crate::Pipeline::project`]).
+pub fn with_project<T>(project: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let _scope = ProjectScope(PROJECT.replace(project.map(str::to_string)));
+    f()
+}
+
+/// The compose project set with [`with_project`], if any.
+#[must_use]
+pub fn project() -> Option<String> {
+    PROJECT.with_borrow(Clone::clone)
+}
+
+/// Prefix of the stack's network and Caddy container: the
+/// project, or the first app's name without one.
+#[must_use]
+pub fn stack_name(apps: &[App]) -> String {
+    project().unwrap_or_else(|| apps[0].name.clone())
+}
+
+/// Container name of the app `name`, prefixed with the project
+/// when there is one so stacks sharing a server don't collide.
+#[must_use]
+pub fn container_name(name: &str) -> String {
+    project().map_or_else(|| name.to_string(), |p| format!("{p}-{name}"))
+}
 
 /// Render a complete `docker-compose.yml` from one or more Apps
 /// and Caddy configuration.
 #[must_use]
 pub fn render(apps: &[App], caddy: &Caddy) -> String {
     assert!(!apps.is_empty(), "at least one app is required");
+    apps.iter().for_each(App::register_secrets);
 
-    let network_name = format!("{}-network", apps[0].name);
+    let stack = stack_name(apps);
+    let network_name = format!("{stack}-network");
     let mut services = IndexMap::new();
 
-    if caddy.has_upstreams() {
+    if needs_caddy(apps, caddy) {
         services.insert(
             "caddy".to_string(),
             Some(caddy_service(apps, caddy, &network_name)),
@@ -27,19 +80,133 @@ pub fn render(apps: &[App], caddy: &Caddy) -> String {
     }
 
     for app in apps {
-        services.insert(app.name.clone(), Some(app_service(app, &network_name)));
+        for (name, job) in app.init_names().into_iter().zip(&app.init) {
+            services.insert(name, Some(job_service(app, job, &network_name)));
+        }
+        services.insert(
+            app.name.clone(),
+            Some(app_service(app, caddy, &stack, &network_name)),
+        );
+    }
+
+    if auto_updates(apps, caddy) {
+        services.insert(
+            "watchtower".to_string(),
+            Some(watchtower_service(apps, &network_name)),
+        );
     }
 
     let compose = Compose {
+        name: project(),
         services: Services(services),
         volumes: top_level_volumes(apps, caddy),
-        networks: network(&network_name),
+        networks: network(&network_name, proxies(apps, caddy) && caddy.shared),
         ..Default::default()
     };
 
     serde_yaml::to_string(&compose).expect("failed to serialize compose")
 }
 
+/// Whether `app` starts when deploying with `profiles`: it is
+/// in no profile, or in one of them.
+#[must_use]
+pub fn is_active(app: &App, profiles: &[String]) -> bool {
+    app.profiles.is_empty() || app.profiles.iter().any(|p| profiles.contains(p))
+}
+
+/// `apps` for a deploy with `profiles`: apps in one of them are
+/// taken out of their profiles so a plain `docker compose up`
+/// starts them, the others keep theirs and stay stopped.
+#[must_use]
+pub fn activate_profiles(apps: &[App], profiles: &[String]) -> Vec<App> {
+    apps.iter()
+        .map(|app| {
+            let mut app = app.clone();
+            if is_active(&app, profiles) {
+                app.profiles.clear();
+            }
+            app
+        })
+        .collect()
+}
+
+/// Whether any service in the stack opted into auto-updates.
+fn auto_updates(apps: &[App], caddy: &Caddy) -> bool {
+    (caddy.auto_update && needs_caddy(apps, caddy))
+        || apps.iter().any(|a| a.auto_update && a.image.is_some())
+}
+
+/// Labels that let the stack's Watchtower, and only it, update
+/// a container.
+fn watchtower_labels(network_name: &str) -> Labels {
+    let mut labels = IndexMap::new();
+    labels.insert(
+        "com.centurylinklabs.watchtower.enable".to_string(),
+        "true".to_string(),
+    );
+    labels.insert(
+        "com.centurylinklabs.watchtower.scope".to_string(),
+        network_name.to_string(),
+    );
+    Labels::Map(labels)
+}
+
+/// Watchtower restricted to labelled containers in this stack's
+/// scope, removing replaced images.
+fn watchtower_service(apps: &[App], network_name: &str) -> Service {
+    Service {
+        image: Some(WATCHTOWER_IMAGE.to_string()),
+        container_name: Some(format!("{}-watchtower", stack_name(apps))),
+        restart: Some("unless-stopped".to_string()),
+        command: Some(Command::Args(vec![
+            "--label-enable".to_string(),
+            "--scope".to_string(),
+            network_name.to_string(),
+            "--cleanup".to_string(),
+            "--interval".to_string(),
+            WATCHTOWER_INTERVAL.to_string(),
+        ])),
+        volumes: vec![Volumes::Simple(
+            "/var/run/docker.sock:/var/run/docker.sock".to_string(),
+        )],
+        labels: watchtower_labels(network_name),
+        networks: Networks::Simple(vec![network_name.to_string()]),
+        ..Default::default()
+    }
+}
+
+/// Whether Caddy proxies anything: the main site's upstreams,
+/// an app with its own domain, or streams.
+#[must_use]
+pub fn proxies(apps: &[App], caddy: &Caddy) -> bool {
+    caddy.has_upstreams() || caddy.proxies_streams() || apps.iter().any(|a| a.domain.is_some())
+}
+
+/// Caddy runs in the stack when it [`proxies`] anything and
+/// isn't [shared](Caddy::shared). Without it, no Caddy config
+/// is written to the stack's directory either.
+#[must_use]
+pub fn needs_caddy(apps: &[App], caddy: &Caddy) -> bool {
+    !caddy.shared && proxies(apps, caddy)
+}
+
+/// Whether Caddy proxies requests to `app`.
+fn is_proxied(app: &App, caddy: &Caddy) -> bool {
+    app.domain.is_some()
+        || caddy.reverse_proxy.iter().any(|up| up.name == app.name)
+        || caddy.routes.iter().any(|(_, up)| up.name == app.name)
+}
+
+/// Compose port mapping of `host` to `container`, with a
+/// `/udp` suffix for UDP.
+fn port_mapping(host: u16, container: u16, udp: bool) -> String {
+    if udp {
+        format!("{host}:{container}/udp")
+    } else {
+        format!("{host}:{container}")
+    }
+}
+
 fn caddy_service(apps: &[App], caddy: &Caddy, network_name: &str) -> Service {
     let mut proxied_names: Vec<&str> = Vec::new();
     if let Some(ref up) = caddy.reverse_proxy {
@@ -50,6 +217,11 @@ fn caddy_service(apps: &[App], caddy: &Caddy, network_name: &str) -> Service {
             proxied_names.push(&up.name);
         }
     }
+    for app in apps.iter().filter(|a| a.domain.is_some()) {
+        if !proxied_names.contains(&app.name.as_str()) {
+            proxied_names.push(&app.name);
+        }
+    }
 
     // When maintenance_page is enabled, Caddy must be able
     // to start and stay running independently of app
@@ -58,14 +230,23 @@ fn caddy_service(apps: &[App], caddy: &Caddy, network_name: &str) -> Service {
     let mut depends = IndexMap::new();
     if caddy.maintenance_page.is_none() {
         for app in apps {
-            if proxied_names.contains(&app.name.as_str()) {
+            // A profiled app may not be started
+            if proxied_names.contains(&app.name.as_str()) && app.profiles.is_empty() {
                 depends.insert(app.name.clone(), DependsCondition::service_healthy());
             }
         }
     }
 
+    let mut ports = vec!["80:80".to_string(), "443:443".to_string()];
+    if caddy.proxies_streams() {
+        for stream in &caddy.streams {
+            ports.push(port_mapping(stream.port, stream.port, stream.udp));
+        }
+    }
+
+    let config_file = caddy.config_file();
     let mut volumes = vec![
-        Volumes::Simple("./Caddyfile:/etc/caddy/Caddyfile:ro".to_string()),
+        Volumes::Simple(format!("./{config_file}:/etc/caddy/{config_file}:ro")),
         Volumes::Simple("caddy-data:/data".to_string()),
         Volumes::Simple("caddy-config:/config".to_string()),
     ];
@@ -74,39 +255,59 @@ fn caddy_service(apps: &[App], caddy: &Caddy, network_name: &str) -> Service {
     }
 
     Service {
-        image: Some("caddy:2-alpine".to_string()),
-        container_name: Some(format!("{}-caddy", apps[0].name)),
+        image: Some(
+            caddy
+                .image
+                .clone()
+                .unwrap_or_else(|| DEFAULT_IMAGE.to_string()),
+        ),
+        container_name: Some(format!("{}-caddy", stack_name(apps))),
         restart: Some("unless-stopped".to_string()),
-        ports: Ports::Short(vec!["80:80".to_string(), "443:443".to_string()]),
+        ports: Ports::Short(ports),
         volumes,
         depends_on: DependsOnOptions::Conditional(depends),
+        labels: if caddy.auto_update {
+            watchtower_labels(network_name)
+        } else {
+            Labels::default()
+        },
         networks: Networks::Simple(vec![network_name.to_string()]),
+        environment: environment(
+            caddy
+                .dns_challenge
+                .as_ref()
+                .map(|c| std::slice::from_ref(&c.token))
+                .unwrap_or_default(),
+        ),
+        command: caddy.uses_json().then(|| {
+            Command::Args(
+                ["caddy", "run", "--config", "/etc/caddy/caddy.json"]
+                    .map(String::from)
+                    .to_vec(),
+            )
+        }),
         ..Default::default()
     }
 }
 
-fn app_service(app: &App, network_name: &str) -> Service {
+fn app_service(app: &App, caddy: &Caddy, stack: &str, network_name: &str) -> Service {
     let expose: Vec<String> = app.expose.iter().map(ToString::to_string).collect();
 
-    let env_file = app.env_file.as_ref().map(|ef| {
-        let name = Path::new(ef)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or(ef);
-        docker_compose_types::StringOrList::Simple(name.to_string())
-    });
-
-    let environment = if app.env.is_empty() {
-        Environment::default()
-    } else {
-        Environment::List(app.env.iter().map(|(k, v)| format!("{k}={v}")).collect())
-    };
+    let env_file = env_file(app);
+    let environment = environment(&app.env);
 
-    let volumes: Vec<Volumes> = app
+    let mut volumes: Vec<Volumes> = app
         .volumes
         .iter()
         .map(|(name, mount)| Volumes::Simple(format!("{name}:{mount}")))
         .collect();
+    for file in &app.config_files {
+        volumes.push(Volumes::Simple(format!(
+            "./{}:{}:ro",
+            app.remote_path(&file.name),
+            file.mount
+        )));
+    }
 
     let healthcheck = app.healthcheck.as_ref().map(|cmd| Healthcheck {
         test: Some(HealthcheckTest::Multiple(vec![
@@ -122,20 +323,54 @@ fn app_service(app: &App, network_name: &str) -> Service {
         ..Default::default()
     });
 
-    let ports = if app.ports.is_empty() {
+    // Streams not proxied by Caddy are published by their app
+    let mut published: Vec<String> = app
+        .ports
+        .iter()
+        .map(|(host, container)| port_mapping(*host, *container, false))
+        .collect();
+    if !caddy.proxies_streams() {
+        for stream in caddy.streams.iter().filter(|s| s.upstream.name == app.name) {
+            published.push(port_mapping(stream.port, stream.upstream.port, stream.udp));
+        }
+    }
+    let ports = if published.is_empty() {
         Ports::default()
     } else {
-        Ports::Short(
-            app.ports
-                .iter()
-                .map(|(host, container)| format!("{host}:{container}"))
-                .collect(),
-        )
+        Ports::Short(published)
+    };
+
+    let mut depends = IndexMap::new();
+    for name in app.init_names() {
+        depends.insert(name, DependsCondition::service_completed_successfully());
+    }
+
+    let image = app
+        .image
+        .clone()
+        .unwrap_or_else(|| format!("{}:latest", app.name));
+    let labels = if app.auto_update && app.image.is_some() {
+        watchtower_labels(network_name)
+    } else {
+        Labels::default()
+    };
+
+    // Replicas can't share a container name
+    let (container_name, deploy) = if app.replicas == 1 {
+        (Some(container_name(&app.name)), None)
+    } else {
+        let deploy = Deploy {
+            replicas: Some(i64::from(app.replicas)),
+            ..Default::default()
+        };
+        (None, Some(deploy))
     };
 
     Service {
-        image: Some(format!("{}:latest", app.name)),
-        container_name: Some(app.name.clone()),
+        image: Some(image),
+        container_name,
+        deploy,
+        labels,
         restart: Some("unless-stopped".to_string()),
         expose,
         ports,
@@ -143,11 +378,111 @@ fn app_service(app: &App, network_name: &str) -> Service {
         environment,
         volumes,
         healthcheck,
+        depends_on: DependsOnOptions::Conditional(depends),
+        networks: app_networks(app, caddy, stack, network_name),
+        hostname: app.hostname.clone(),
+        working_dir: app.working_dir.clone(),
+        shm_size: app.shm_size.clone(),
+        profiles: app.profiles.clone(),
+        ..Default::default()
+    }
+}
+
+/// The stack network, with the app's aliases when it has any,
+/// and the shared Caddy's network when that proxies the app.
+fn app_networks(app: &App, caddy: &Caddy, stack: &str, network_name: &str) -> Networks {
+    let shared = caddy.shared && is_proxied(app, caddy);
+    if app.aliases.is_empty() && !shared {
+        return Networks::Simple(vec![network_name.to_string()]);
+    }
+    let mut nets = IndexMap::new();
+    nets.insert(
+        network_name.to_string(),
+        if app.aliases.is_empty() {
+            MapOrEmpty::Empty
+        } else {
+            MapOrEmpty::Map(AdvancedNetworkSettings {
+                aliases: app.aliases.clone(),
+                ..Default::default()
+            })
+        },
+    );
+    if shared {
+        nets.insert(
+            SHARED_NETWORK.to_string(),
+            MapOrEmpty::Map(AdvancedNetworkSettings {
+                aliases: vec![shared_caddy::upstream_alias(stack, &app.name)],
+                ..Default::default()
+            }),
+        );
+    }
+    Networks::Advanced(AdvancedNetworks(nets))
+}
+
+/// A one-shot service for an init [`Job`], sharing the app's
+/// env file and environment.
+fn job_service(app: &App, job: &Job, network_name: &str) -> Service {
+    let env: Vec<EnvVar> = app.env.iter().chain(&job.env).cloned().collect();
+    let command = job
+        .command
+        .as_ref()
+        .map(|c| Command::Args(vec!["sh".to_string(), "-c".to_string(), c.clone()]));
+    let volumes = job
+        .volumes
+        .iter()
+        .map(|(name, mount)| Volumes::Simple(format!("{name}:{mount}")))
+        .collect();
+
+    Service {
+        image: Some(job.image.clone()),
+        restart: Some("no".to_string()),
+        command,
+        env_file: env_file(app),
+        environment: environment(&env),
+        volumes,
         networks: Networks::Simple(vec![network_name.to_string()]),
+        profiles: app.profiles.clone(),
         ..Default::default()
     }
 }
 
+/// The app's env file, referenced by file name since it is
+/// copied next to the compose file.
+fn env_file(app: &App) -> Option<docker_compose_types::StringOrList> {
+    app.env_file.as_ref().map(|ef| {
+        let name = if app.remote_dir.is_some() {
+            app.remote_path(".env")
+        } else {
+            Path::new(ef)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(ef)
+                .to_string()
+        };
+        docker_compose_types::StringOrList::Simple(name)
+    })
+}
+
+/// Attributes of a written compose file: readable by its owner
+/// only when `apps` have secret environment variables, or Caddy
+/// the token of its [`Caddy::dns_challenge`].
+#[must_use]
+pub fn file_attrs(apps: &[App], caddy: &Caddy) -> FileAttrs {
+    if apps.iter().any(App::has_secrets) || caddy.dns_challenge.is_some() {
+        FileAttrs::new().mode(0o600)
+    } else {
+        FileAttrs::new()
+    }
+}
+
+fn environment(env: &[EnvVar]) -> Environment {
+    if env.is_empty() {
+        Environment::default()
+    } else {
+        Environment::List(env.iter().map(ToString::to_string).collect())
+    }
+}
+
 fn local_volume() -> ComposeVolume {
     ComposeVolume {
         driver: Some("local".to_string()),
@@ -162,19 +497,22 @@ fn top_level_volumes(apps: &[App], caddy: &Caddy) -> TopLevelVolumes {
     let mut vols = IndexMap::new();
 
     for app in apps {
-        for (name, _) in &app.volumes {
-            vols.insert(name.clone(), MapOrEmpty::Map(local_volume()));
+        let job_volumes = app.init.iter().flat_map(|j| &j.volumes);
+        for (name, _) in app.volumes.iter().chain(job_volumes) {
+            if !is_bind_mount(name) {
+                vols.insert(name.clone(), MapOrEmpty::Map(local_volume()));
+            }
         }
     }
 
-    if caddy.has_upstreams() {
+    if needs_caddy(apps, caddy) {
         let local = MapOrEmpty::Map(local_volume());
         vols.insert("caddy-data".to_string(), local.clone());
         vols.insert("caddy-config".to_string(), local);
     }
 
     for (host, _) in &caddy.volumes {
-        if !host.starts_with("./") && !host.starts_with('/') {
+        if !is_bind_mount(host) {
             vols.insert(host.clone(), MapOrEmpty::Map(local_volume()));
         }
     }
@@ -182,7 +520,15 @@ fn top_level_volumes(apps: &[App], caddy: &Caddy) -> TopLevelVolumes {
     TopLevelVolumes(vols)
 }
 
-fn network(network_name: &str) -> ComposeNetworks {
+/// Host paths (`./dir`, `/abs`) are bind mounts, anything else
+/// a named volume.
+fn is_bind_mount(source: &str) -> bool {
+    source.starts_with("./") || source.starts_with('/')
+}
+
+/// The stack's bridge network, plus the shared Caddy's network
+/// when the stack is proxied by it.
+fn network(network_name: &str, shared: bool) -> ComposeNetworks {
     let mut nets = IndexMap::new();
     nets.insert(
         network_name.to_string(),
@@ -191,5 +537,14 @@ fn network(network_name: &str) -> ComposeNetworks {
             ..Default::default()
         }),
     );
+    if shared {
+        nets.insert(
+            SHARED_NETWORK.to_string(),
+            MapOrEmpty::Map(NetworkSettings {
+                external: Some(ComposeNetwork::Bool(true)),
+                ..Default::default()
+            }),
+        );
+    }
     ComposeNetworks(nets)
 }
@@ -0,0 +1,917 @@
+//! Pipelines described in a TOML or YAML file.
+//!
+//! [`crate::Pipeline::from_config`] reads apps, Caddy,
+//! provisioner, DNS, and deploy settings from a file and builds
+//! the same [`App`], [`Caddy`], and provider structs the Rust DSL
+//! does, so simple projects can change a port or an env file
+//! without recompiling the xtask. The format follows the builder
+//! methods; unknown keys are rejected to catch typos.
+//!
+//! ```toml
+//! remote_dir = "/opt/app"
+//!
+//! [[app]]
+//! name = "api"
+//! expose = [8000]
+//! env_file = "deploy/.env.api"
+//! healthcheck = "curl -f http://localhost:8000/health"
+//! env = { RUST_LOG = "info" }
+//! volumes = { api-data = "/data" }
+//!
+//! [caddy]
+//! reverse_proxy = "api"        # first exposed port, or "api:8000"
+//! gzip = true
+//! security_headers = true
+//!
+//! [provisioner]
+//! type = "digitalocean"
+//! size = "s-2vcpu-4gb"
+//!
+//! [[dns]]
+//! type = "ovh"
+//! domain = "api.example.com"
+//!
+//! [deploy]
+//! transfer = "stream-zstd"        # or "rsync", with compression = "zstd"
+//!
+//! [[registry]]
+//! registry = "ghcr.io"
+//! user = "ci-bot"
+//! token_env = "GHCR_TOKEN"
+//!
+//! [state]
+//! type = "s3"
+//! bucket = "acme-ops"
+//! key = "catapulta/servers.json"
+//! ```
+//!
+//! Upstreams name an app, optionally with one of its exposed
+//! ports; `[[caddy.route]]` entries take `grpc = true` (or
+//! `h2c = true`) for gRPC upstreams. Build the pipeline in Rust instead for anything the
+//! file cannot express (init jobs, hooks, log shipping, ...), or
+//! keep extending the one returned by `from_config`.
+
+use std::path::Path;
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::app::{App, Upstream};
+use crate::caddy::Caddy;
+use crate::deploy::Runtime;
+use crate::deploy::docker_save::{Compression, DockerSaveLoad, Transfer};
+use crate::dns::DEFAULT_TTL;
+use crate::dns::adguard::AdGuardHome;
+use crate::dns::cloudflare::Cloudflare;
+use crate::dns::duckdns::DuckDns;
+use crate::dns::ovh::Ovh;
+use crate::dns::pihole::Pihole;
+use crate::error::{DeployError, DeployResult};
+use crate::pipeline::Pipeline;
+use crate::provision::digitalocean::DigitalOcean;
+use crate::provision::existing::ExistingServer;
+use crate::provision::libvirt::{Libvirt, NetworkMode};
+use crate::provision::proxmox::Proxmox;
+use crate::provision::scaleway::Scaleway;
+use crate::state::{LocalState, S3State};
+
+/// A pipeline definition, as read from a config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "app", alias = "apps")]
+    pub apps: Vec<AppConfig>,
+    #[serde(default)]
+    pub caddy: CaddyConfig,
+    pub provisioner: Option<ProvisionerConfig>,
+    #[serde(default)]
+    pub dns: Vec<DnsConfig>,
+    #[serde(default)]
+    pub deploy: DeployConfig,
+    #[serde(default, rename = "registry", alias = "registries")]
+    pub registries: Vec<RegistryConfig>,
+    pub remote_dir: Option<String>,
+    pub ssh_user: Option<String>,
+    pub keep_images: Option<u32>,
+    /// Compose project name (see [`Pipeline::project`]).
+    pub project: Option<String>,
+    /// Run without Caddy (see [`Pipeline::no_proxy`]).
+    #[serde(default)]
+    pub no_proxy: bool,
+    /// One subdirectory per app (see [`Pipeline::app_dirs`]).
+    #[serde(default)]
+    pub app_dirs: bool,
+    /// Where the state is kept (see [`Pipeline::state_store`]).
+    pub state: Option<StateConfig>,
+    /// Protected environment name (see [`Pipeline::protect`]).
+    pub protected: Option<String>,
+}
+
+/// An `[[app]]` entry; see [`App`] for each setting.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppConfig {
+    pub name: String,
+    pub dockerfile: Option<String>,
+    pub platform: Option<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub build_args: IndexMap<String, String>,
+    #[serde(default)]
+    pub env: IndexMap<String, String>,
+    /// Variables set with [`App::env_secret`].
+    #[serde(default)]
+    pub secret_env: IndexMap<String, String>,
+    pub env_file: Option<String>,
+    /// Volume name or host path to mount path.
+    #[serde(default)]
+    pub volumes: IndexMap<String, String>,
+    /// Local path to mount path, for `dev` only (see
+    /// [`App::dev_volume`]).
+    #[serde(default)]
+    pub dev_volumes: IndexMap<String, String>,
+    #[serde(default)]
+    pub expose: Vec<u16>,
+    /// Published ports as `"HOST:CONTAINER"`.
+    #[serde(default)]
+    pub ports: Vec<String>,
+    pub healthcheck: Option<String>,
+    pub context: Option<String>,
+    pub source: Option<SourceConfig>,
+    #[serde(default)]
+    pub cache_source: bool,
+    pub domain: Option<String>,
+    pub hostname: Option<String>,
+    pub working_dir: Option<String>,
+    pub shm_size: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub image: Option<String>,
+    #[serde(default)]
+    pub auto_update: bool,
+    pub replicas: Option<u32>,
+    /// Local commands run before the image is built.
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    /// Subdirectory for the app's files (see
+    /// [`App::remote_dir`]).
+    pub remote_dir: Option<String>,
+}
+
+/// Remote Git build source (see [`App::source`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub url: String,
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+}
+
+/// The `[caddy]` table; see [`Caddy`] for each setting.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CaddyConfig {
+    /// Upstream as `"app"` or `"app:port"`.
+    pub reverse_proxy: Option<String>,
+    #[serde(default, rename = "route", alias = "routes")]
+    pub routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pub gzip: bool,
+    #[serde(default)]
+    pub security_headers: bool,
+    #[serde(default)]
+    pub tls_internal: bool,
+    pub basic_auth: Option<BasicAuthConfig>,
+    #[serde(default)]
+    pub directives: Vec<String>,
+    #[serde(default)]
+    pub volumes: IndexMap<String, String>,
+    pub maintenance_page: Option<String>,
+    #[serde(default)]
+    pub auto_update: bool,
+    /// Render Caddy's JSON config (see [`Caddy::json`]).
+    #[serde(default)]
+    pub json: bool,
+    /// Extra apps of the JSON config, by name.
+    #[serde(default)]
+    pub json_apps: IndexMap<String, serde_json::Value>,
+    pub image: Option<String>,
+    #[serde(default)]
+    pub layer4: bool,
+    #[serde(default, rename = "stream", alias = "streams")]
+    pub streams: Vec<StreamConfig>,
+    /// Proxy through the server's shared Caddy (see
+    /// [`Caddy::shared`]).
+    #[serde(default)]
+    pub shared: bool,
+    pub dns_challenge: Option<DnsChallengeConfig>,
+}
+
+/// A `[[caddy.stream]]` entry (see [`Caddy::stream`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamConfig {
+    pub port: u16,
+    /// Upstream as `"app"` or `"app:port"`.
+    pub upstream: String,
+    #[serde(default)]
+    pub udp: bool,
+}
+
+/// A `[[caddy.route]]` entry (see [`Caddy::route`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    pub path: String,
+    /// Upstream as `"app"` or `"app:port"`.
+    pub upstream: String,
+    /// Proxy over cleartext HTTP/2 (see [`Upstream::h2c`]).
+    #[serde(default, alias = "grpc")]
+    pub h2c: bool,
+}
+
+/// Caddy basic auth (see [`Caddy::basic_auth`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthConfig {
+    pub user: String,
+    pub password_hash: String,
+}
+
+/// Caddy DNS-01 challenge (see [`Caddy::dns_challenge`]), with
+/// the provider token read from the `token_env` variable.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsChallengeConfig {
+    pub provider: String,
+    pub token_env: String,
+}
+
+/// The `[provisioner]` table, selected by `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ProvisionerConfig {
+    /// [`DigitalOcean`]; unset fields keep its defaults.
+    DigitalOcean {
+        size: Option<String>,
+        region: Option<String>,
+        image: Option<String>,
+        /// See [`DigitalOcean::fallback_region`].
+        #[serde(default)]
+        fallback_regions: Vec<String>,
+    },
+    /// [`Scaleway`]; unset fields keep its defaults.
+    Scaleway {
+        commercial_type: Option<String>,
+        zone: Option<String>,
+        image: Option<String>,
+    },
+    /// [`Libvirt`] on the hypervisor `host`; unset fields keep
+    /// its defaults. `bridge` selects bridged networking instead
+    /// of NAT.
+    Libvirt {
+        host: String,
+        vm_ssh_key: String,
+        user: Option<String>,
+        key: Option<String>,
+        port: Option<u16>,
+        vcpus: Option<u32>,
+        memory_mib: Option<u32>,
+        disk_gib: Option<u32>,
+        image_url: Option<String>,
+        image_sha256: Option<String>,
+        bridge: Option<String>,
+        storage_dir: Option<String>,
+        os_variant: Option<String>,
+        /// See [`Libvirt::data_disk`].
+        #[serde(default)]
+        data_disks: Vec<DataDiskConfig>,
+    },
+    Proxmox(ProxmoxConfig),
+    /// [`ExistingServer`] at `host`, reached as `root` with
+    /// `ssh_key`.
+    Existing {
+        host: String,
+        ssh_key: String,
+    },
+}
+
+/// A `[provisioner]` table of type `proxmox`: [`Proxmox`] cloning
+/// the template `template` on `node`; unset fields keep its
+/// defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxmoxConfig {
+    pub api_url: String,
+    pub node: String,
+    pub template: u32,
+    pub vm_ssh_key: String,
+    pub cores: Option<u32>,
+    pub memory_mib: Option<u32>,
+    pub disk_gib: Option<u32>,
+    pub disk: Option<String>,
+    pub storage: Option<String>,
+    pub ca_cert: Option<String>,
+}
+
+/// A `[[provisioner.data_disks]]` entry of a `libvirt`
+/// provisioner.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataDiskConfig {
+    pub size_gib: u32,
+    pub mount_path: String,
+}
+
+/// A `[[dns]]` entry, selected by `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DnsConfig {
+    /// [`Ovh`], with credentials from `~/.ovh.conf`.
+    Ovh { domain: String, ttl: Option<u32> },
+    /// [`Cloudflare`], with the token from `CF_API_TOKEN`.
+    Cloudflare {
+        domain: String,
+        ttl: Option<u32>,
+        #[serde(default)]
+        proxied: bool,
+    },
+    /// [`Pihole`] at `url`, with the password from
+    /// `PIHOLE_PASSWORD`.
+    Pihole { url: String, domain: String },
+    /// [`AdGuardHome`] at `url`, with the credentials from
+    /// `ADGUARD_USER` and `ADGUARD_PASSWORD`.
+    Adguard { url: String, domain: String },
+    /// [`DuckDns`], with the token from `DUCKDNS_TOKEN`.
+    Duckdns { domain: String },
+}
+
+/// The `[state]` table, selected by `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StateConfig {
+    /// `servers.json` in `dir`, the pipeline's local directory
+    /// when unset.
+    Local { dir: Option<String> },
+    /// [`S3State`], with credentials from the AWS configuration.
+    S3 {
+        bucket: String,
+        key: String,
+        endpoint: Option<String>,
+    },
+}
+
+/// A `[[registry]]` entry; see [`Pipeline::registry_auth`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    pub registry: String,
+    pub user: String,
+    /// Environment variable holding the token.
+    pub token_env: String,
+}
+
+/// The `[deploy]` table, configuring [`DockerSaveLoad`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeployConfig {
+    #[serde(default)]
+    pub transfer: TransferConfig,
+    /// Transfer bandwidth limit in KiB/s.
+    pub bwlimit: Option<u32>,
+    pub cipher: Option<String>,
+    pub compression_level: Option<u8>,
+    /// Compression of the tarballs copied with rsync or scp.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Seconds each app gets to become healthy.
+    pub healthy_timeout: Option<u64>,
+    /// Seconds between two health checks.
+    pub healthy_interval: Option<u64>,
+    /// Container engine on the servers.
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+}
+
+/// [`Transfer`] modes by name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferConfig {
+    #[default]
+    Auto,
+    Rsync,
+    Scp,
+    Stream,
+    StreamZstd,
+}
+
+/// [`Compression`] formats by name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionConfig {
+    #[default]
+    Auto,
+    None,
+    Zstd,
+    Gzip,
+}
+
+/// [`Runtime`] engines by name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeConfig {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl ProvisionerConfig {
+    /// Set the provisioner these settings describe on
+    /// `pipeline`.
+    fn provision(self, pipeline: Pipeline) -> Pipeline {
+        match self {
+            Self::DigitalOcean {
+                size,
+                region,
+                image,
+                fallback_regions,
+            } => {
+                let mut provisioner = DigitalOcean::new();
+                if let Some(size) = size {
+                    provisioner = provisioner.size(&size);
+                }
+                if let Some(region) = region {
+                    provisioner = provisioner.region(&region);
+                }
+                if let Some(image) = image {
+                    provisioner = provisioner.image(&image);
+                }
+                for region in &fallback_regions {
+                    provisioner = provisioner.fallback_region(region);
+                }
+                pipeline.provision(provisioner)
+            }
+            Self::Scaleway {
+                commercial_type,
+                zone,
+                image,
+            } => {
+                let mut provisioner = Scaleway::new();
+                if let Some(commercial_type) = commercial_type {
+                    provisioner = provisioner.commercial_type(&commercial_type);
+                }
+                if let Some(zone) = zone {
+                    provisioner = provisioner.zone(&zone);
+                }
+                if let Some(image) = image {
+                    provisioner = provisioner.image(&image);
+                }
+                pipeline.provision(provisioner)
+            }
+            Self::Libvirt {
+                host,
+                vm_ssh_key,
+                user,
+                key,
+                port,
+                vcpus,
+                memory_mib,
+                disk_gib,
+                image_url,
+                image_sha256,
+                bridge,
+                storage_dir,
+                os_variant,
+                data_disks,
+            } => {
+                let mut provisioner = Libvirt::new(&host, &vm_ssh_key);
+                if let Some(user) = user {
+                    provisioner = provisioner.hypervisor_user(&user);
+                }
+                if let Some(key) = key {
+                    provisioner = provisioner.hypervisor_key(&key);
+                }
+                if let Some(port) = port {
+                    provisioner = provisioner.hypervisor_port(port);
+                }
+                if let Some(vcpus) = vcpus {
+                    provisioner = provisioner.vcpus(vcpus);
+                }
+                if let Some(mib) = memory_mib {
+                    provisioner = provisioner.memory_mib(mib);
+                }
+                if let Some(gib) = disk_gib {
+                    provisioner = provisioner.disk_gib(gib);
+                }
+                if let Some(url) = image_url {
+                    provisioner = provisioner.image_url(&url);
+                }
+                if let Some(sha256) = image_sha256 {
+                    provisioner = provisioner.image_sha256(&sha256);
+                }
+                if let Some(bridge) = bridge {
+                    provisioner = provisioner.network(NetworkMode::Bridged(bridge));
+                }
+                if let Some(dir) = storage_dir {
+                    provisioner = provisioner.storage_dir(&dir);
+                }
+                if let Some(variant) = os_variant {
+                    provisioner = provisioner.os_variant(&variant);
+                }
+                for disk in data_disks {
+                    provisioner = provisioner.data_disk(disk.size_gib, &disk.mount_path);
+                }
+                pipeline.provision(provisioner)
+            }
+            Self::Proxmox(config) => pipeline.provision(config.provisioner()),
+            Self::Existing { host, ssh_key } => {
+                pipeline.provision(ExistingServer::new(&host, &ssh_key))
+            }
+        }
+    }
+}
+
+impl ProxmoxConfig {
+    /// The [`Proxmox`] provisioner these settings describe.
+    #[must_use]
+    pub fn provisioner(self) -> Proxmox {
+        let defaults = Proxmox::new(&self.api_url, &self.node, self.template, &self.vm_ssh_key);
+        Proxmox {
+            cores: self.cores.unwrap_or(defaults.cores),
+            memory_mib: self.memory_mib.unwrap_or(defaults.memory_mib),
+            disk_gib: self.disk_gib,
+            disk: self.disk.unwrap_or(defaults.disk),
+            storage: self.storage,
+            ca_cert: self.ca_cert,
+            ..defaults
+        }
+    }
+}
+
+impl DeployConfig {
+    /// The [`DockerSaveLoad`] deployer these settings describe.
+    #[must_use]
+    pub fn deployer(&self) -> DockerSaveLoad {
+        let mut deployer = DockerSaveLoad::new()
+            .transfer(self.transfer.into())
+            .compression(self.compression.into())
+            .runtime(self.runtime.into());
+        if let Some(kbps) = self.bwlimit {
+            deployer = deployer.bwlimit(kbps);
+        }
+        if let Some(cipher) = &self.cipher {
+            deployer = deployer.cipher(cipher);
+        }
+        if let Some(level) = self.compression_level {
+            deployer = deployer.compression_level(level);
+        }
+        if let Some(secs) = self.healthy_interval {
+            deployer = deployer.healthy_interval(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.healthy_timeout {
+            deployer = deployer.healthy_timeout(Duration::from_secs(secs));
+        }
+        deployer
+    }
+}
+
+impl From<TransferConfig> for Transfer {
+    fn from(config: TransferConfig) -> Self {
+        match config {
+            TransferConfig::Auto => Self::Auto,
+            TransferConfig::Rsync => Self::Rsync,
+            TransferConfig::Scp => Self::Scp,
+            TransferConfig::Stream => Self::Stream { zstd: false },
+            TransferConfig::StreamZstd => Self::Stream { zstd: true },
+        }
+    }
+}
+
+impl From<CompressionConfig> for Compression {
+    fn from(config: CompressionConfig) -> Self {
+        match config {
+            CompressionConfig::Auto => Self::Auto,
+            CompressionConfig::None => Self::None,
+            CompressionConfig::Zstd => Self::Zstd,
+            CompressionConfig::Gzip => Self::Gzip,
+        }
+    }
+}
+
+impl From<RuntimeConfig> for Runtime {
+    fn from(config: RuntimeConfig) -> Self {
+        match config {
+            RuntimeConfig::Docker => Self::Docker,
+            RuntimeConfig::Podman => Self::Podman,
+        }
+    }
+}
+
+impl Config {
+    /// Read `path`, as YAML for `.yaml`/`.yml` files and TOML
+    /// otherwise.
+    pub fn load(path: &str) -> DeployResult<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| DeployError::FileNotFound(format!("{path}: {e}")))?;
+        let yaml = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml");
+        let config = if yaml {
+            Self::from_yaml(&content)
+        } else {
+            Self::from_toml(&content)
+        };
+        config.map_err(|e| match e {
+            DeployError::InvalidConfig(msg) => DeployError::InvalidConfig(format!("{path}: {msg}")),
+            e => e,
+        })
+    }
+
+    pub fn from_toml(content: &str) -> DeployResult<Self> {
+        toml::from_str(content).map_err(|e| DeployError::InvalidConfig(e.to_string()))
+    }
+
+    pub fn from_yaml(content: &str) -> DeployResult<Self> {
+        serde_yaml::from_str(content).map_err(|e| DeployError::InvalidConfig(e.to_string()))
+    }
+
+    /// The configured apps.
+    pub fn apps(&self) -> DeployResult<Vec<App>> {
+        if self.apps.is_empty() {
+            return Err(DeployError::InvalidConfig(
+                "at least one [[app]] is required".into(),
+            ));
+        }
+        self.apps.iter().map(AppConfig::to_app).collect()
+    }
+
+    /// The Caddy settings, with upstreams resolved against
+    /// `apps`.
+    pub fn caddy(&self, apps: &[App]) -> DeployResult<Caddy> {
+        let c = &self.caddy;
+        let mut caddy = Caddy::new();
+        if let Some(spec) = &c.reverse_proxy {
+            caddy = caddy.reverse_proxy(upstream(apps, spec)?);
+        }
+        for route in &c.routes {
+            let mut up = upstream(apps, &route.upstream)?;
+            up.h2c = route.h2c;
+            caddy = caddy.route(&route.path, up);
+        }
+        if c.gzip {
+            caddy = caddy.gzip();
+        }
+        if c.security_headers {
+            caddy = caddy.security_headers();
+        }
+        if c.tls_internal {
+            caddy = caddy.tls_internal();
+        }
+        if let Some(auth) = &c.basic_auth {
+            caddy = caddy.basic_auth(&auth.user, &auth.password_hash);
+        }
+        for directive in &c.directives {
+            caddy = caddy.directive(directive);
+        }
+        for (host, container) in &c.volumes {
+            caddy = caddy.volume(host, container);
+        }
+        if let Some(page) = &c.maintenance_page {
+            caddy = caddy.maintenance_page(page);
+        }
+        if c.auto_update {
+            caddy = caddy.auto_update();
+        }
+        if c.json {
+            caddy = caddy.json();
+        }
+        for (name, config) in &c.json_apps {
+            caddy = caddy.json_app(name, config.clone());
+        }
+        if let Some(image) = &c.image {
+            caddy = caddy.image(image);
+        }
+        if c.layer4 {
+            caddy = caddy.layer4();
+        }
+        for stream in &c.streams {
+            let up = upstream(apps, &stream.upstream)?;
+            caddy = if stream.udp {
+                caddy.stream_udp(stream.port, up)
+            } else {
+                caddy.stream(stream.port, up)
+            };
+        }
+        if let Some(challenge) = &c.dns_challenge {
+            let token = std::env::var(&challenge.token_env).map_err(|_| {
+                DeployError::EnvMissing(format!(
+                    "{} not set, needed for the Caddy DNS-01 challenge",
+                    challenge.token_env
+                ))
+            })?;
+            caddy = caddy.dns_challenge(&challenge.provider, &challenge.token_env, &token);
+        }
+        if c.shared {
+            caddy = caddy.shared();
+        }
+        Ok(caddy)
+    }
+
+    /// Build the pipeline, deploying with [`DockerSaveLoad`].
+    pub fn into_pipeline(self) -> DeployResult<Pipeline> {
+        let apps = self.apps()?;
+        let caddy = self.caddy(&apps)?;
+        let mut pipeline = Pipeline::multi(apps, caddy).deploy(self.deploy.deployer());
+        if self.no_proxy {
+            pipeline = pipeline.no_proxy();
+        }
+        if let Some(project) = &self.project {
+            pipeline = pipeline.project(project);
+        }
+        if self.app_dirs {
+            pipeline = pipeline.app_dirs();
+        }
+        if let Some(name) = &self.protected {
+            pipeline = pipeline.protect(name);
+        }
+
+        if let Some(provisioner) = self.provisioner {
+            pipeline = provisioner.provision(pipeline);
+        }
+
+        for dns in &self.dns {
+            pipeline = match dns {
+                DnsConfig::Ovh { domain, ttl } => {
+                    pipeline.dns(Ovh::new(domain).ttl(ttl.unwrap_or(DEFAULT_TTL)))
+                }
+                DnsConfig::Cloudflare {
+                    domain,
+                    ttl,
+                    proxied,
+                } => pipeline.dns(
+                    Cloudflare::new(domain)
+                        .ttl(ttl.unwrap_or(DEFAULT_TTL))
+                        .proxied(*proxied),
+                ),
+                DnsConfig::Pihole { url, domain } => pipeline.dns(Pihole::new(url, domain)),
+                DnsConfig::Adguard { url, domain } => pipeline.dns(AdGuardHome::new(url, domain)),
+                DnsConfig::Duckdns { domain } => pipeline.dns(DuckDns::new(domain)),
+            };
+        }
+        for r in &self.registries {
+            pipeline = pipeline.registry_auth(&r.registry, &r.user, &r.token_env);
+        }
+        if let Some(dir) = &self.remote_dir {
+            pipeline = pipeline.remote_dir(dir);
+        }
+        if let Some(n) = self.keep_images {
+            pipeline = pipeline.keep_images(n);
+        }
+        if let Some(user) = &self.ssh_user {
+            pipeline = pipeline.ssh_user(user);
+        }
+        match &self.state {
+            Some(StateConfig::Local { dir: Some(dir) }) => {
+                pipeline = pipeline.state_store(LocalState::new(dir));
+            }
+            Some(StateConfig::S3 {
+                bucket,
+                key,
+                endpoint,
+            }) => {
+                let s3 = S3State::new(bucket, key);
+                pipeline = pipeline.state_store(match endpoint {
+                    Some(url) => s3.endpoint(url),
+                    None => s3,
+                });
+            }
+            Some(StateConfig::Local { dir: None }) | None => {}
+        }
+        Ok(pipeline)
+    }
+}
+
+impl AppConfig {
+    /// Build the [`App`] through its builder methods.
+    pub fn to_app(&self) -> DeployResult<App> {
+        let mut app = App::new(&self.name);
+        if let Some(dockerfile) = &self.dockerfile {
+            app = app.dockerfile(dockerfile);
+        }
+        if let Some(platform) = &self.platform {
+            app = app.platform(platform);
+        }
+        if !self.platforms.is_empty() {
+            let platforms: Vec<&str> = self.platforms.iter().map(String::as_str).collect();
+            app = app.platforms(&platforms);
+        }
+        for (key, value) in &self.build_args {
+            app = app.build_arg(key, value);
+        }
+        for (key, value) in &self.env {
+            app = app.env(key, value);
+        }
+        for (key, value) in &self.secret_env {
+            app = app.env_secret(key, value);
+        }
+        if let Some(env_file) = &self.env_file {
+            app = app.env_file(env_file);
+        }
+        for (name, mount) in &self.volumes {
+            app = app.volume(name, mount);
+        }
+        for (path, mount) in &self.dev_volumes {
+            app = app.dev_volume(path, mount);
+        }
+        for port in &self.expose {
+            app = app.expose(*port);
+        }
+        for spec in &self.ports {
+            let (host, container) = parse_port(spec).ok_or_else(|| {
+                DeployError::InvalidConfig(format!(
+                    "app '{}': invalid port '{spec}', expected HOST:CONTAINER",
+                    self.name
+                ))
+            })?;
+            app = app.port(host, container);
+        }
+        if let Some(cmd) = &self.healthcheck {
+            app = app.healthcheck(cmd);
+        }
+        if let Some(context) = &self.context {
+            app = app.context(context);
+        }
+        if let Some(source) = &self.source {
+            app = app.source(&source.url, &source.git_ref);
+        }
+        app = app.cache_source(self.cache_source);
+        if let Some(domain) = &self.domain {
+            app = app.domain(domain);
+        }
+        if let Some(hostname) = &self.hostname {
+            app = app.hostname(hostname);
+        }
+        if let Some(dir) = &self.working_dir {
+            app = app.working_dir(dir);
+        }
+        if let Some(size) = &self.shm_size {
+            app = app.shm_size(size);
+        }
+        for alias in &self.aliases {
+            app = app.alias(alias);
+        }
+        if let Some(image) = &self.image {
+            app = app.image(image);
+        }
+        if self.auto_update {
+            app = app.auto_update();
+        }
+        if let Some(n) = self.replicas {
+            app = app.replicas(n);
+        }
+        for command in &self.pre_build {
+            app = app.pre_build(command);
+        }
+        for profile in &self.profiles {
+            app = app.profile(profile);
+        }
+        if let Some(dir) = &self.remote_dir {
+            app = app.remote_dir(dir);
+        }
+        Ok(app)
+    }
+}
+
+fn parse_port(spec: &str) -> Option<(u16, u16)> {
+    let (host, container) = spec.split_once(':')?;
+    Some((host.parse().ok()?, container.parse().ok()?))
+}
+
+/// Resolve `"app"` or `"app:port"` to an upstream of one of
+/// `apps`, checking what [`App::upstream`] and
+/// [`App::upstream_port`] would assert.
+fn upstream(apps: &[App], spec: &str) -> DeployResult<Upstream> {
+    let invalid = |reason: &str| DeployError::InvalidConfig(format!("upstream '{spec}': {reason}"));
+    let (name, port) = match spec.split_once(':') {
+        Some((name, port)) => (
+            name,
+            Some(port.parse::<u16>().map_err(|_| invalid("invalid port"))?),
+        ),
+        None => (spec, None),
+    };
+    let app = apps
+        .iter()
+        .find(|a| a.name == name)
+        .ok_or_else(|| invalid("no app with this name"))?;
+    match port {
+        Some(port) if app.expose.contains(&port) => Ok(app.upstream_port(port)),
+        Some(_) => Err(invalid("port is not exposed by the app")),
+        None if app.expose.is_empty() => Err(invalid("the app exposes no port")),
+        None => Ok(app.upstream()),
+    }
+}
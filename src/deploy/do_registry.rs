@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use crate::app::App;
+use crate::caddy::Caddy;
+use crate::cmd;
+use crate::deploy::docker_save::{DockerSaveLoad, remote_platform_tag, retag_latest};
+use crate::deploy::{Deployer, Runtime};
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::{FileAttrs, SshSession, shell_quote};
+use crate::timing;
+
+/// Host of the `DigitalOcean` Container Registry.
+pub const DOCR_HOST: &str = "registry.digitalocean.com";
+
+/// Lifetime of the read-only credentials handed to the droplet.
+const PULL_CREDENTIALS_EXPIRY: Duration = Duration::from_secs(600);
+
+/// Deploy through the `DigitalOcean` Container Registry (DOCR).
+///
+/// Images are built like with [`DockerSaveLoad`], then pushed to
+/// a DOCR registry created with `doctl` on first use, keeping
+/// images and droplets in one account. Each droplet pulls the
+/// image with short-lived read-only credentials from `doctl
+/// registry docker-config`, which never outlive the deploy.
+///
+/// ```rust,no_run
+/// use catapulta::{App, Caddy, DigitalOcean, DoRegistry, Pipeline};
+///
+/// let app = App::new("api").expose(8000);
+/// let caddy = Caddy::new().reverse_proxy(app.upstream());
+/// Pipeline::new(app, caddy)
+///     .provision(DigitalOcean::new())
+///     .deploy(DoRegistry::new("my-team").region("fra1"));
+/// ```
+pub struct DoRegistry {
+    /// Registry name, unique across DOCR.
+    pub registry: String,
+    /// Subscription tier used when creating the registry.
+    pub tier: String,
+    /// Region used when creating the registry.
+    pub region: Option<String>,
+    /// Builds the images and starts the stack.
+    pub inner: DockerSaveLoad,
+}
+
+impl DoRegistry {
+    #[must_use]
+    pub fn new(registry: &str) -> Self {
+        Self {
+            registry: registry.to_string(),
+            tier: "starter".to_string(),
+            region: None,
+            inner: DockerSaveLoad::new(),
+        }
+    }
+
+    /// Subscription tier of a created registry (`starter`,
+    /// `basic` or `professional`; default `starter`).
+    #[must_use]
+    pub fn subscription_tier(mut self, tier: &str) -> Self {
+        self.tier = tier.to_string();
+        self
+    }
+
+    /// Region of a created registry (default: chosen by
+    /// `DigitalOcean`).
+    #[must_use]
+    pub fn region(mut self, region: &str) -> Self {
+        self.region = Some(region.to_string());
+        self
+    }
+
+    /// Give each app about `timeout` to become healthy; see
+    /// [`DockerSaveLoad::healthy_timeout`].
+    #[must_use]
+    pub fn healthy_timeout(mut self, timeout: Duration) -> Self {
+        self.inner = self.inner.healthy_timeout(timeout);
+        self
+    }
+
+    /// Reference of the local image `tag` in the registry, e.g.
+    /// `registry.digitalocean.com/my-team/api:latest`.
+    #[must_use]
+    pub fn image_ref(&self, tag: &str) -> String {
+        format!("{DOCR_HOST}/{}/{tag}", self.registry)
+    }
+
+    /// Remote command pulling `tag` with the Docker config in
+    /// `config_dir`, which it removes whatever the outcome.
+    #[must_use]
+    pub fn pull_command(&self, tag: &str, config_dir: &str) -> String {
+        let reference = self.image_ref(tag);
+        let config_dir = shell_quote(config_dir);
+        format!(
+            "DOCKER_CONFIG={config_dir} docker pull {reference}; status=$?; \
+             rm -rf {config_dir}; [ $status -eq 0 ] && docker tag {reference} {tag}"
+        )
+    }
+
+    /// `docker` arguments publishing `name:latest` in the registry
+    /// as a multi-platform manifest list of the images pushed for
+    /// each of the app's [`App::platforms`]; none for an app
+    /// built for a single platform.
+    #[must_use]
+    pub fn manifest_commands(&self, app: &App) -> Vec<Vec<String>> {
+        if app.platforms.is_empty() {
+            return Vec::new();
+        }
+        let list = self.image_ref(&format!("{}:latest", app.name));
+        let mut create = vec![
+            "manifest".to_string(),
+            "create".to_string(),
+            "--amend".to_string(),
+            list.clone(),
+        ];
+        create.extend(Self::tags(app).iter().map(|tag| self.image_ref(tag)));
+        let push = vec![
+            "manifest".to_string(),
+            "push".to_string(),
+            "--purge".to_string(),
+            list,
+        ];
+        vec![create, push]
+    }
+
+    /// Create the registry unless the account already has it.
+    /// DOCR allows one registry per account, so another name is
+    /// an error.
+    fn ensure_registry(&self) -> DeployResult<()> {
+        if !cmd::command_exists("doctl") {
+            return Err(DeployError::PrerequisiteMissing(
+                "doctl is not installed. \
+                 Install with: brew install doctl"
+                    .into(),
+            ));
+        }
+        let existing = cmd::run_with_timeout(
+            "doctl",
+            &["registry", "get", "--format", "Name", "--no-header"],
+            cmd::API_TIMEOUT,
+        )
+        .ok();
+        match existing.as_deref().map(str::trim) {
+            Some(name) if name == self.registry => Ok(()),
+            Some(name) if !name.is_empty() => Err(DeployError::InvalidConfig(format!(
+                "the DigitalOcean account already has registry '{name}', \
+                 not '{}'",
+                self.registry
+            ))),
+            _ => {
+                eprintln!("Creating registry {}...", self.registry);
+                let mut args = vec![
+                    "registry",
+                    "create",
+                    &self.registry,
+                    "--subscription-tier",
+                    &self.tier,
+                ];
+                if let Some(region) = &self.region {
+                    args.extend(["--region", region]);
+                }
+                cmd::run_with_timeout("doctl", &args, cmd::API_TIMEOUT)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Local tags of the images built for `app`.
+    fn tags(app: &App) -> Vec<String> {
+        if app.platforms.is_empty() {
+            vec![format!("{}:latest", app.name)]
+        } else {
+            app.platforms.iter().map(|p| app.platform_tag(p)).collect()
+        }
+    }
+}
+
+impl Deployer for DoRegistry {
+    fn build_image(&self, app: &App) -> DeployResult<()> {
+        self.ensure_registry()?;
+        self.inner.build_image(app)?;
+
+        cmd::run_with_timeout("doctl", &["registry", "login"], cmd::API_TIMEOUT)?;
+        for tag in Self::tags(app) {
+            let reference = self.image_ref(&tag);
+            eprintln!("Pushing {reference}...");
+            cmd::run("docker", &["tag", &tag, &reference])?;
+            timing::measure("push", || {
+                cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                    cmd::run_interactive("docker", &["push", &reference])
+                })
+            })?;
+        }
+        for args in self.manifest_commands(app) {
+            let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                cmd::run("docker", &refs)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn transfer_image(&self, app: &App, ssh: &SshSession) -> DeployResult<()> {
+        let tag = if app.platforms.is_empty() {
+            format!("{}:latest", app.name)
+        } else {
+            remote_platform_tag(app, ssh)?
+        };
+
+        let expiry = PULL_CREDENTIALS_EXPIRY.as_secs().to_string();
+        let config = cmd::run_with_timeout(
+            "doctl",
+            &["registry", "docker-config", "--expiry-seconds", &expiry],
+            cmd::API_TIMEOUT,
+        )?;
+        let config_dir = format!("/tmp/catapulta-docr-{}", app.name);
+        ssh.exec(&format!("mkdir -p -m 700 {}", shell_quote(&config_dir)))?;
+        ssh.write_remote_file_with(
+            &config,
+            &format!("{config_dir}/config.json"),
+            &FileAttrs::new().mode(0o600),
+        )?;
+
+        eprintln!("Pulling {} on {}...", self.image_ref(&tag), ssh.host());
+        timing::measure("pull", || {
+            ssh.exec_interactive(&self.pull_command(&tag, &config_dir))
+        })?;
+        retag_latest(app, ssh, &tag, Runtime::Docker)
+    }
+
+    fn deploy(
+        &self,
+        ssh: &SshSession,
+        apps: &[App],
+        caddy: &Caddy,
+        remote_dir: &str,
+        only: &[String],
+    ) -> DeployResult<()> {
+        self.inner.deploy(ssh, apps, caddy, remote_dir, only)
+    }
+}
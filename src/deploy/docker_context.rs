@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use crate::app::App;
+use crate::caddy::Caddy;
+use crate::deploy::Deployer;
+use crate::deploy::docker_save::{Build, DockerSaveLoad, build_app};
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::SshSession;
+use crate::timing;
+
+/// Deploy by building the images with the server's own Docker
+/// daemon, reached over SSH like with `DOCKER_HOST=ssh://...`.
+///
+/// The build context is sent to the server and the image is
+/// built there, so nothing is saved, copied, or loaded. Apps
+/// with [`App::platforms`] are built for the server's platform
+/// only. The stack is then started like with
+/// [`DockerSaveLoad`].
+///
+/// Docker connects with the `ssh` binary and `~/.ssh/config`,
+/// not the pipeline's SSH options: jump hosts, ports other than
+/// the pipeline's and identity files must be set there (servers
+/// created by `provision` get an entry). [`DockerContext::context`]
+/// uses a Docker context instead.
+///
+/// ```rust,no_run
+/// use catapulta::{App, Caddy, DockerContext, Pipeline};
+///
+/// let app = App::new("api").expose(8000);
+/// let caddy = Caddy::new().reverse_proxy(app.upstream());
+/// Pipeline::new(app, caddy).deploy(DockerContext::new());
+/// ```
+pub struct DockerContext {
+    /// Docker context to build with, instead of the server's
+    /// `ssh://` host.
+    pub context: Option<String>,
+    /// Starts the stack.
+    pub inner: DockerSaveLoad,
+}
+
+impl DockerContext {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            context: None,
+            inner: DockerSaveLoad::new(),
+        }
+    }
+
+    /// Build with the Docker context `name` (see `docker context
+    /// ls`), which must point at the server deployed to.
+    #[must_use]
+    pub fn context(mut self, name: &str) -> Self {
+        self.context = Some(name.to_string());
+        self
+    }
+
+    /// Give each app about `timeout` to become healthy; see
+    /// [`DockerSaveLoad::healthy_timeout`].
+    #[must_use]
+    pub fn healthy_timeout(mut self, timeout: Duration) -> Self {
+        self.inner = self.inner.healthy_timeout(timeout);
+        self
+    }
+
+    /// Arguments selecting the daemon of `ssh`'s host, passed to
+    /// `docker` before the command.
+    #[must_use]
+    pub fn docker_args(&self, ssh: &SshSession) -> Vec<String> {
+        self.context.as_ref().map_or_else(
+            || vec!["--host".to_string(), ssh.docker_host()],
+            |context| vec!["--context".to_string(), context.clone()],
+        )
+    }
+}
+
+impl Default for DockerContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deployer for DockerContext {
+    /// Does nothing: images are built on the server when they
+    /// are "transferred".
+    fn build_image(&self, _app: &App) -> DeployResult<()> {
+        Ok(())
+    }
+
+    fn transfer_image(&self, app: &App, ssh: &SshSession) -> DeployResult<()> {
+        let platform = if app.platforms.is_empty() {
+            app.platform.as_str()
+        } else {
+            let machine = ssh.exec("uname -m")?;
+            app.platform_for_machine(&machine).ok_or_else(|| {
+                DeployError::Other(format!(
+                    "{} ({machine}) is not in the platforms of app '{}': {}",
+                    ssh.host(),
+                    app.name,
+                    app.platforms.join(", ")
+                ))
+            })?
+        };
+        eprintln!("Building Docker image for {platform} on {}...", ssh.host());
+
+        let docker_args = self.docker_args(ssh);
+        let docker_args: Vec<&str> = docker_args.iter().map(String::as_str).collect();
+        let build = Build {
+            platform,
+            tag: format!("{}:latest", app.name),
+            buildx: false,
+        };
+        timing::measure("build", || build_app(app, &docker_args, &[build]))
+    }
+
+    fn deploy(
+        &self,
+        ssh: &SshSession,
+        apps: &[App],
+        caddy: &Caddy,
+        remote_dir: &str,
+        only: &[String],
+    ) -> DeployResult<()> {
+        self.inner.deploy(ssh, apps, caddy, remote_dir, only)
+    }
+}
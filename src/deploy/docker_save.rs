@@ -1,148 +1,785 @@
+use std::time::Duration;
+
 use crate::app::App;
 use crate::caddy::Caddy;
 use crate::caddyfile;
 use crate::cmd;
 use crate::compose;
-use crate::deploy::{Deployer, check_env_files, cleanup_source, prepare_source, wait_healthy};
-use crate::error::DeployResult;
-use crate::ssh::SshSession;
+use crate::deploy::envfile::{self, EnvDiff};
+use crate::deploy::layout;
+use crate::deploy::{
+    Deployer, HealthWait, Runtime, caddy_reload_command, check_env_files, cleanup_source, image_id,
+    image_size, preflight, prepare_source, report_image_built, run_pre_build, same_image,
+    wait_healthy_or_report,
+};
+use crate::error::{DeployError, DeployResult};
+use crate::events::{self, DeployEvent};
+use crate::sign::{self, Signer};
+use crate::ssh::{FileAttrs, SshSession, shell_quote};
+use crate::timing;
+
+/// How the saved image reaches the remote host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transfer {
+    /// Stream the image, compressed with zstd when it is
+    /// installed locally and on the remote host.
+    #[default]
+    Auto,
+    /// Save the image to a local tarball and copy it with
+    /// `rsync --partial`, resuming interrupted transfers. Needs
+    /// room for the tarball on both ends.
+    Rsync,
+    /// Save the image to a local tarball and copy it with
+    /// [`SshSession::scp_to`] (SFTP with the native client). No
+    /// resume, but needs nothing beyond SSH.
+    Scp,
+    /// Pipe `docker save` straight into `docker load` over SSH.
+    /// Nothing is written to local or remote disk, but an
+    /// interrupted transfer starts over. With `zstd`, the stream
+    /// is compressed (falls back to uncompressed when the remote
+    /// host lacks `zstd`).
+    Stream { zstd: bool },
+}
 
-/// Deploy via `docker save` + `rsync` + `docker load`.
+/// How a saved image tarball is compressed before
+/// [`Transfer::Rsync`] or [`Transfer::Scp`] copy it, and
+/// decompressed on the remote host as it is loaded.
+///
+/// Streamed transfers compress with zstd instead (see
+/// [`Transfer::Stream`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// zstd when it is installed locally and on the remote
+    /// host, else gzip when both have it, else none.
+    #[default]
+    Auto,
+    /// Copy the tarball as saved, compressed on the wire by
+    /// rsync only.
+    None,
+    /// Compress with zstd (default level 3).
+    Zstd,
+    /// Compress with gzip (default level 6).
+    Gzip,
+}
+
+impl Compression {
+    /// Program compressing and decompressing the tarball.
+    #[must_use]
+    pub const fn program(self) -> Option<&'static str> {
+        match self {
+            Self::Auto | Self::None => None,
+            Self::Zstd => Some("zstd"),
+            Self::Gzip => Some("gzip"),
+        }
+    }
+
+    /// Suffix of a tarball compressed this way, e.g. `.zst`.
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Auto | Self::None => "",
+            Self::Zstd => ".zst",
+            Self::Gzip => ".gz",
+        }
+    }
+
+    /// Remote command loading the tarball at `path` with the
+    /// `runtime` engine, then removing it.
+    #[must_use]
+    pub fn load_command(self, path: &str, runtime: Runtime) -> String {
+        let cli = runtime.cli();
+        self.program().map_or_else(
+            || format!("{cli} load < {path} && rm -f {path}"),
+            |program| format!("{program} -dc {path} | {cli} load && rm -f {path}"),
+        )
+    }
+}
+
+/// Deploy via `docker save` piped over SSH into `docker load`.
 ///
 /// This is the simplest deployment strategy - no registry
-/// needed. The image is built locally for linux/amd64,
-/// rsynced to the remote host, then loaded with docker.
-pub struct DockerSaveLoad;
+/// needed. The image is built locally for linux/amd64 (or, with
+/// [`App::platforms`], for each listed platform via `docker
+/// buildx`, picking the one matching the server at transfer
+/// time), then streamed to the remote host, compressed with
+/// zstd when both ends have it, and loaded with docker as it
+/// arrives: no tarball is written on either end, so the server
+/// only needs room for the image itself. The images of a
+/// multi-app stack go in a single `docker save`, so the layers
+/// they share cross the network once. Tarballs copied with
+/// rsync or scp are available for links where resuming matters
+/// (see [`Transfer`]), compressed with zstd or gzip (see
+/// [`Compression`]). An image the server already has, e.g.
+/// on a deploy with `--skip-build`, is not transferred again:
+/// `docker save` and `docker load` keep the image id, so the id
+/// of `name:latest` on the server tells whether it changed.
+/// With [`DockerSaveLoad::sign`], the tarballs and the files of
+/// the stack are signed, and verified on the server before use.
+/// Servers running Podman instead of Docker load and start the
+/// images with it (see [`DockerSaveLoad::runtime`]).
+pub struct DockerSaveLoad {
+    pub transfer: Transfer,
+    pub bwlimit: Option<u32>,
+    pub cipher: Option<String>,
+    pub compression_level: Option<u8>,
+    pub compression: Compression,
+    pub health_wait: HealthWait,
+    pub runtime: Runtime,
+    pub signer: Option<Signer>,
+}
 
 impl DockerSaveLoad {
     #[must_use]
     pub const fn new() -> Self {
-        Self
+        Self {
+            transfer: Transfer::Auto,
+            bwlimit: None,
+            cipher: None,
+            compression_level: None,
+            compression: Compression::Auto,
+            health_wait: HealthWait::new(),
+            runtime: Runtime::Docker,
+            signer: None,
+        }
     }
-}
 
-impl Default for DockerSaveLoad {
-    fn default() -> Self {
-        Self::new()
+    /// Choose how images are copied to the remote host.
+    #[must_use]
+    pub const fn transfer(mut self, transfer: Transfer) -> Self {
+        self.transfer = transfer;
+        self
     }
-}
 
-impl Deployer for DockerSaveLoad {
-    fn build_image(&self, app: &App) -> DeployResult<()> {
-        eprintln!("Building Docker image for {}...", app.platform);
+    /// Cap the transfer at `kbps` KiB/s, leaving room on a slow
+    /// uplink for everything else.
+    ///
+    /// Passed to rsync as `--bwlimit`. A streamed transfer is
+    /// throttled through `pv` when it is installed locally; scp
+    /// transfers are not limited.
+    #[must_use]
+    pub const fn bwlimit(mut self, kbps: u32) -> Self {
+        self.bwlimit = Some(kbps);
+        self
+    }
 
-        let source_dir = prepare_source(app)?;
+    /// Cipher of the ssh connection rsync runs over (e.g.
+    /// `aes128-gcm@openssh.com`, cheaper on CPUs with AES-NI).
+    #[must_use]
+    pub fn cipher(mut self, cipher: &str) -> Self {
+        self.cipher = Some(cipher.to_string());
+        self
+    }
 
-        let base = source_dir
-            .as_deref()
-            .map(|p| p.to_string_lossy().into_owned());
+    /// Compression level: the zstd level of a compressed stream
+    /// (default 3), the level of the tarball's [`Compression`],
+    /// or rsync's `--compress-level` for an uncompressed tarball
+    /// (0 turns compression off).
+    #[must_use]
+    pub const fn compression_level(mut self, level: u8) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
 
-        let context = match (&base, &app.context) {
-            (Some(b), Some(sub)) => format!("{b}/{sub}"),
-            (Some(b), None) => b.clone(),
-            (None, Some(ctx)) => ctx.clone(),
-            (None, None) => ".".to_string(),
-        };
+    /// Compress the tarballs copied with rsync or scp.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::{Compression, DockerSaveLoad, Transfer};
+    ///
+    /// let deployer = DockerSaveLoad::new()
+    ///     .transfer(Transfer::Rsync)
+    ///     .compression(Compression::Zstd);
+    /// ```
+    #[must_use]
+    pub const fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Give each app about `timeout` to become healthy after the
+    /// deploy, checking at the current interval (default 30
+    /// checks, 5 seconds apart).
+    #[must_use]
+    pub fn healthy_timeout(mut self, timeout: Duration) -> Self {
+        self.health_wait = self.health_wait.timeout(timeout);
+        self
+    }
+
+    /// Number of health checks per app before the deploy fails.
+    #[must_use]
+    pub const fn healthy_attempts(mut self, attempts: u32) -> Self {
+        self.health_wait.attempts = attempts;
+        self
+    }
+
+    /// Pause between two health checks.
+    #[must_use]
+    pub const fn healthy_interval(mut self, interval: Duration) -> Self {
+        self.health_wait.interval = interval;
+        self
+    }
 
-        let dockerfile = if source_dir.is_some() {
-            format!("{context}/{}", app.dockerfile)
+    /// Load and start the images with `runtime` on the server
+    /// (default [`Runtime::Docker`]).
+    ///
+    /// ```rust,no_run
+    /// use catapulta::{App, Caddy, DockerSaveLoad, Pipeline, Runtime};
+    ///
+    /// let app = App::new("api").expose(8000);
+    /// let caddy = Caddy::new().reverse_proxy(app.upstream());
+    /// Pipeline::new(app, caddy).deploy(DockerSaveLoad::new().runtime(Runtime::Podman));
+    /// ```
+    #[must_use]
+    pub const fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Sign the image tarballs and the files of the stack with
+    /// `signer`, and verify them on the server before using them;
+    /// see [`crate::sign`].
+    ///
+    /// ```rust,no_run
+    /// use catapulta::DockerSaveLoad;
+    /// use catapulta::sign::Signer;
+    ///
+    /// let deployer = DockerSaveLoad::new().sign(Signer::minisign(
+    ///     "deploy/minisign.key",
+    ///     "deploy/minisign.pub",
+    /// ));
+    /// ```
+    #[must_use]
+    pub fn sign(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Write `content` to the bind-mounted file `path` in place,
+    /// returning whether it replaced a different previous version.
+    fn write_mounted(&self, ssh: &SshSession, content: &str, path: &str) -> DeployResult<bool> {
+        let previous = ssh.exec(&format!("cat {} 2>/dev/null || true", shell_quote(path)))?;
+        self.write_file(ssh, content, path, FileAttrs::new().in_place())?;
+        Ok(!previous.is_empty() && previous.trim_end() != content.trim_end())
+    }
+
+    /// Arguments of the rsync copying `src` to `dest`.
+    ///
+    /// rsync compresses on the wire only when the tarball isn't
+    /// already `compressed`.
+    fn rsync_args(&self, ssh: &SshSession, src: &str, dest: &str, compressed: bool) -> Vec<String> {
+        let mut ssh_cmd = ssh.ssh_command();
+        if let Some(cipher) = &self.cipher {
+            ssh_cmd.push_str(" -c ");
+            ssh_cmd.push_str(cipher);
+        }
+        let mut args = vec![
+            if compressed { "-v" } else { "-vz" }.to_string(),
+            "--progress".to_string(),
+            "--partial".to_string(),
+        ];
+        if let Some(level) = self.compression_level.filter(|_| !compressed) {
+            args.push(format!("--compress-level={level}"));
+        }
+        if let Some(kbps) = self.bwlimit {
+            args.push(format!("--bwlimit={kbps}"));
+        }
+        args.extend(["-e".to_string(), ssh_cmd, src.to_string(), dest.to_string()]);
+        args
+    }
+
+    /// Resolve [`Transfer::Auto`] into a stream, compressed when
+    /// zstd is installed on both ends, or when signing into a
+    /// tarball copied with rsync, or scp without it.
+    fn resolve_transfer(&self, ssh: &SshSession) -> Transfer {
+        match self.transfer {
+            Transfer::Auto if self.signer.is_some() => {
+                if cmd::command_exists("rsync") && remote_has(ssh, "rsync") {
+                    Transfer::Rsync
+                } else {
+                    Transfer::Scp
+                }
+            }
+            Transfer::Auto => {
+                let zstd = cmd::command_exists("zstd") && remote_has(ssh, "zstd");
+                if !zstd {
+                    eprintln!("  zstd not available on both ends, streaming uncompressed");
+                }
+                Transfer::Stream { zstd }
+            }
+            other => other,
+        }
+    }
+
+    /// Resolve [`Compression::Auto`] into the first of zstd and
+    /// gzip installed on both ends, and fall back to no
+    /// compression when the remote host lacks the chosen one.
+    fn resolve_compression(&self, ssh: &SshSession) -> DeployResult<Compression> {
+        let both = |program| cmd::command_exists(program) && remote_has(ssh, program);
+        match self.compression {
+            Compression::Auto => Ok([Compression::Zstd, Compression::Gzip]
+                .into_iter()
+                .find(|c| c.program().is_some_and(both))
+                .unwrap_or(Compression::None)),
+            Compression::None => Ok(Compression::None),
+            compression => {
+                let program = compression.program().unwrap_or_default();
+                if !cmd::command_exists(program) {
+                    return Err(DeployError::PrerequisiteMissing(format!(
+                        "{program} (required for compressed tarballs)"
+                    )));
+                }
+                if remote_has(ssh, program) {
+                    Ok(compression)
+                } else {
+                    eprintln!(
+                        "  {program} not found on {}, copying uncompressed",
+                        ssh.host()
+                    );
+                    Ok(Compression::None)
+                }
+            }
+        }
+    }
+
+    /// Save the images `tags` to a local tarball named after
+    /// `label`, copy it to the remote host with `transfer` (rsync
+    /// or scp), and load it there, reporting the copy's progress.
+    fn transfer_tarball(
+        &self,
+        label: &str,
+        ssh: &SshSession,
+        tags: &[&str],
+        transfer: Transfer,
+        progress: &dyn Fn(u8),
+    ) -> DeployResult<()> {
+        let host = ssh.host();
+        let user = ssh.user();
+        let compression = self.resolve_compression(ssh)?;
+        let saved_tar = std::env::temp_dir().join(format!("catapulta-{label}.tar"));
+        let saved_tar_str = saved_tar.to_string_lossy().to_string();
+        let local_tar_str = format!("{saved_tar_str}{}", compression.extension());
+        let local_tar = std::path::PathBuf::from(&local_tar_str);
+        let remote_tar = format!("/tmp/catapulta-{label}.tar{}", compression.extension());
+
+        // 1. Save image to local temp file, and compress it
+        eprintln!("  Saving images to {saved_tar_str}...");
+        let save_result = timing::measure("save", || {
+            let mut args = vec!["save", "-o", &saved_tar_str];
+            args.extend(tags);
+            cmd::run_interactive("docker", &args)
+        })
+        .and_then(|()| compress_tarball(&saved_tar_str, compression, self.compression_level));
+        if save_result.is_err() {
+            let _ = std::fs::remove_file(&saved_tar);
+            let _ = std::fs::remove_file(&local_tar);
+            return save_result;
+        }
+        let remote_signature = format!("{remote_tar}{}", sign::SIGNATURE_SUFFIX);
+        if let Some(signer) = &self.signer {
+            let signed = send_signature(signer, ssh, &local_tar_str, &remote_signature);
+            if signed.is_err() {
+                let _ = std::fs::remove_file(&local_tar);
+                return signed;
+            }
+        }
+
+        // 2. Copy to remote (rsync resumes partial transfers)
+        let copy_result = if transfer == Transfer::Scp {
+            if self.bwlimit.is_some() {
+                eprintln!("  Warning: scp transfers ignore the bandwidth limit");
+            }
+            eprintln!("  Copying to {user}@{host}...");
+            let mut last = 0;
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                ssh.scp_to_with_progress(&local_tar_str, &remote_tar, &mut |sent, total| {
+                    // Loading the image is the last step
+                    let pct = u8::try_from(sent * 99 / total.max(1)).unwrap_or(99);
+                    if pct > last {
+                        last = pct;
+                        progress(pct);
+                    }
+                })
+            })
         } else {
-            app.dockerfile.clone()
+            let dest = format!("{}:{remote_tar}", ssh.destination());
+            let compressed = compression != Compression::None;
+            let args = self.rsync_args(ssh, &local_tar_str, &dest, compressed);
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+            // --partial lets a retry resume where the last
+            // attempt stopped
+            eprintln!("  Syncing to {user}@{host}...");
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                cmd::run_interactive("rsync", &args)
+            })
         };
+        let _ = std::fs::remove_file(&local_tar);
+        copy_result?;
 
-        let mut args = vec!["build", "--platform", &app.platform, "-f", &dockerfile];
+        // 3. Load on remote (once verified) and clean up remote tar
+        eprintln!("  Loading image on remote...");
+        let mut load = compression.load_command(&remote_tar, self.runtime);
+        if self.signer.is_some() {
+            load = format!(
+                "{} || {{ rm -f {remote_tar} {remote_signature}; exit 1; }}; \
+                 rm -f {remote_signature}; {load}",
+                sign::verify_command(&remote_tar, &remote_signature)
+            );
+        }
+        timing::measure("load", || ssh.exec_interactive(&load))
+    }
+}
 
-        let build_arg_strings: Vec<String> = app
-            .build_args
-            .iter()
-            .map(|(k, v)| format!("{k}={v}"))
-            .collect();
+/// Compress the tarball at `path` in place with `compression`
+/// at `level`, replacing it with the file of the compression's
+/// extension.
+fn compress_tarball(path: &str, compression: Compression, level: Option<u8>) -> DeployResult<()> {
+    let level = match compression {
+        Compression::Auto | Compression::None => return Ok(()),
+        Compression::Zstd => level.unwrap_or(3).clamp(1, 19),
+        Compression::Gzip => level.unwrap_or(6).clamp(1, 9),
+    };
+    let program = compression.program().unwrap_or_default();
+    eprintln!("  Compressing with {program}...");
+    let level = format!("-{level}");
+    timing::measure("compress", || match compression {
+        Compression::Zstd => {
+            cmd::run_interactive("zstd", &["-q", "-T0", &level, "--rm", "-f", path])
+        }
+        _ => cmd::run_interactive("gzip", &[&level, "-f", path]),
+    })
+}
+
+/// Sign the local tarball `local_tar` with `signer`, and
+/// write the signature to `remote_signature` on the server
+/// along with the public key verifying it.
+fn send_signature(
+    signer: &Signer,
+    ssh: &SshSession,
+    local_tar: &str,
+    remote_signature: &str,
+) -> DeployResult<()> {
+    eprintln!("  Signing {local_tar}...");
+    let signature = signer.sign_file(local_tar)?;
+    let content = std::fs::read_to_string(&signature);
+    let _ = std::fs::remove_file(&signature);
+    signer.install(ssh)?;
+    ssh.write_remote_file(&content?, remote_signature)
+}
 
+/// Whether `program` is on the remote host's `PATH`.
+fn remote_has(ssh: &SshSession, program: &str) -> bool {
+    ssh.exec(&format!(
+        "command -v {program} >/dev/null 2>&1 && echo yes || true"
+    ))
+    .is_ok_and(|out| out == "yes")
+}
+
+/// Pick the per-platform image matching the remote host's
+/// architecture.
+pub(crate) fn remote_platform_tag(app: &App, ssh: &SshSession) -> DeployResult<String> {
+    let machine = ssh.exec("uname -m")?;
+    let platform = app.platform_for_machine(&machine).ok_or_else(|| {
+        DeployError::Other(format!(
+            "no image for {} ({machine}) in the platforms of app '{}': {}",
+            ssh.host(),
+            app.name,
+            app.platforms.join(", ")
+        ))
+    })?;
+    eprintln!("  {} is {machine}, using the {platform} image", ssh.host());
+    Ok(app.platform_tag(platform))
+}
+
+/// Compare the keys of `env_file` (holding `content`) with the
+/// `remote_name` it replaces and its example, warning about
+/// dropped and missing keys.
+fn report_env_changes(ssh: &SshSession, env_file: &str, content: &str, remote_name: &str) {
+    let remote = ssh
+        .exec(&format!(
+            "cat {} 2>/dev/null || true",
+            shell_quote(remote_name)
+        ))
+        .ok()
+        .filter(|r| !r.trim().is_empty());
+    let example = envfile::example_path(env_file).and_then(|p| std::fs::read_to_string(p).ok());
+    let diff = EnvDiff::new(content, remote.as_deref(), example.as_deref());
+    if !diff.added.is_empty() {
+        eprintln!("  {env_file} adds {}", diff.added.join(", "));
+    }
+    for warning in diff.warnings(env_file) {
+        eprintln!("Warning: {warning}");
+    }
+}
+
+/// Tag a loaded per-platform image as `name:latest` on the
+/// remote host, where the compose file expects it.
+pub(crate) fn retag_latest(
+    app: &App,
+    ssh: &SshSession,
+    tag: &str,
+    runtime: Runtime,
+) -> DeployResult<()> {
+    let latest = format!("{}:latest", app.name);
+    if tag == latest {
+        return Ok(());
+    }
+    let cli = runtime.cli();
+    ssh.exec(&format!(
+        "{cli} tag {tag} {latest} && {cli} rmi {tag} >/dev/null"
+    ))?;
+    Ok(())
+}
+
+/// Stream `docker save` of the images `tags` into the `load` of
+/// `runtime` on the remote host, optionally compressed with zstd
+/// at `level` and throttled to `bwlimit` KiB/s.
+fn stream_images(
+    ssh: &SshSession,
+    tags: &[&str],
+    runtime: Runtime,
+    zstd: bool,
+    level: u8,
+    bwlimit: Option<u32>,
+) -> DeployResult<()> {
+    if zstd && !cmd::command_exists("zstd") {
+        return Err(DeployError::PrerequisiteMissing(
+            "zstd (required for compressed streaming)".into(),
+        ));
+    }
+    let compress = zstd && remote_has(ssh, "zstd");
+    if zstd && !compress {
+        eprintln!("  zstd not found on {}, streaming uncompressed", ssh.host());
+    }
+
+    let mut args = vec!["save"];
+    args.extend(tags);
+    let mut local = cmd::Pipe::new("docker", &args);
+    let remote = if compress {
+        local = local.then("zstd", &["-T0", &format!("-{level}"), "-c"]);
+        format!("zstd -dc | {} load", runtime.cli())
+    } else {
+        format!("{} load", runtime.cli())
+    };
+    if let Some(kbps) = bwlimit {
+        if cmd::command_exists("pv") {
+            local = local.then("pv", &["-q", "-L", &format!("{kbps}k")]);
+        } else {
+            eprintln!("  Warning: pv not found, streaming without bandwidth limit");
+        }
+    }
+
+    eprintln!(
+        "  Streaming {}{}...",
+        if tags.len() == 1 { "image" } else { "images" },
+        if compress { " (zstd)" } else { "" }
+    );
+    // A stream cut short loads nothing, so it can start over
+    timing::measure("stream", || {
+        cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+            ssh.pipe_from_local(&local, &remote)
+        })
+    })
+}
+
+impl Default for DockerSaveLoad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One image build of an app.
+pub(crate) struct Build<'a> {
+    pub platform: &'a str,
+    pub tag: String,
+    /// Build with `docker buildx build --load`, for a platform
+    /// the daemon doesn't run on natively.
+    pub buildx: bool,
+}
+
+/// Build the images of `app` with `docker`, passing it
+/// `docker_args` first (e.g. `--host` for a remote daemon).
+pub(crate) fn build_app(app: &App, docker_args: &[&str], builds: &[Build]) -> DeployResult<()> {
+    let source_dir = prepare_source(app)?;
+
+    let base = source_dir
+        .as_deref()
+        .map(|p| p.to_string_lossy().into_owned());
+
+    let context = match (&base, &app.context) {
+        (Some(b), Some(sub)) => format!("{b}/{sub}"),
+        (Some(b), None) => b.clone(),
+        (None, Some(ctx)) => ctx.clone(),
+        (None, None) => ".".to_string(),
+    };
+
+    let dockerfile = if source_dir.is_some() {
+        format!("{context}/{}", app.dockerfile)
+    } else {
+        app.dockerfile.clone()
+    };
+
+    let build_arg_strings: Vec<String> = app
+        .build_args
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect();
+
+    let run_build = |build: &Build| {
+        let mut args = docker_args.to_vec();
+        if build.buildx {
+            args.extend(["buildx", "build", "--platform", build.platform, "--load"]);
+        } else {
+            args.extend(["build", "--platform", build.platform]);
+        }
+        args.extend(["-f", &dockerfile]);
         for arg_str in &build_arg_strings {
             args.push("--build-arg");
             args.push(arg_str);
         }
+        args.extend(["-t", &build.tag, &context]);
+        cmd::run_interactive("docker", &args)?;
+        report_image_built(app, &build.tag);
+        Ok(())
+    };
+
+    let result = run_pre_build(app, source_dir.as_deref()).and_then(|()| {
+        builds.iter().try_for_each(|build| {
+            if builds.len() > 1 {
+                eprintln!("  Building {}...", build.platform);
+            }
+            run_build(build)
+        })
+    });
+
+    if !app.cache_source {
+        if let Some(dir) = &source_dir {
+            cleanup_source(dir);
+        }
+    }
 
-        let tag = format!("{}:latest", app.name);
-        args.push("-t");
-        args.push(&tag);
-        args.push(&context);
+    result
+}
 
-        let result = cmd::run_interactive("docker", &args);
+impl Deployer for DockerSaveLoad {
+    fn build_image(&self, app: &App) -> DeployResult<()> {
+        if app.platforms.is_empty() {
+            eprintln!("Building Docker image for {}...", app.platform);
+            let build = Build {
+                platform: &app.platform,
+                tag: format!("{}:latest", app.name),
+                buildx: false,
+            };
+            build_app(app, &[], &[build])
+        } else {
+            eprintln!("Building Docker images for {}...", app.platforms.join(", "));
+            let builds: Vec<Build> = app
+                .platforms
+                .iter()
+                .map(|platform| Build {
+                    platform,
+                    tag: app.platform_tag(platform),
+                    buildx: true,
+                })
+                .collect();
+            build_app(app, &[], &builds)
+        }
+    }
 
-        if !app.cache_source {
-            if let Some(dir) = &source_dir {
-                cleanup_source(dir);
+    fn image_unchanged(&self, app: &App, ssh: &SshSession) -> bool {
+        let tag = if app.platforms.is_empty() {
+            format!("{}:latest", app.name)
+        } else {
+            match remote_platform_tag(app, ssh) {
+                Ok(tag) => tag,
+                Err(_) => return false,
             }
-        }
+        };
+        let Ok(local) = image_id(&tag) else {
+            return false;
+        };
+        let remote = ssh
+            .exec(&format!(
+                "{} image inspect -f '{{{{.Id}}}}' {}:latest 2>/dev/null || true",
+                self.runtime.cli(),
+                app.name
+            ))
+            .unwrap_or_default();
+        same_image(&local, &remote)
+    }
 
-        result
+    fn transfer_image(&self, app: &App, ssh: &SshSession) -> DeployResult<()> {
+        self.transfer_images(&[app], ssh)
     }
 
-    fn transfer_image(&self, app: &App, host: &str, user: &str) -> DeployResult<()> {
-        let tag = format!("{}:latest", app.name);
+    /// Transfer the images of `apps` together: a single `docker
+    /// save` of all of them, so the layers they share are copied
+    /// once and the server loads them in one go.
+    fn transfer_images(&self, apps: &[&App], ssh: &SshSession) -> DeployResult<()> {
+        let host = ssh.host();
+        let user = ssh.user();
+        let tags = apps
+            .iter()
+            .map(|app| {
+                if app.platforms.is_empty() {
+                    Ok(format!("{}:latest", app.name))
+                } else {
+                    remote_platform_tag(app, ssh)
+                }
+            })
+            .collect::<DeployResult<Vec<String>>>()?;
+        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
 
-        // Query image size for logging
-        let size_bytes = cmd::run(
-            "docker",
-            &["image", "inspect", "--format", "{{.Size}}", &tag],
-        )?;
-        let size_bytes: u64 = size_bytes.parse().unwrap_or(0);
-        let size_mb = size_bytes / (1024 * 1024);
+        let size = tags
+            .iter()
+            .map(|tag| image_size(tag))
+            .sum::<DeployResult<u64>>()?;
+        let size_mb = size / (1024 * 1024);
+
+        // Fail before copying anything if the images won't fit
+        let transfer = self.resolve_transfer(ssh);
+        let streamed = matches!(transfer, Transfer::Stream { .. });
+        if !streamed {
+            preflight::check_local(size)?;
+        }
+        preflight::check_remote(ssh, size, streamed)?;
 
+        let noun = if tags.len() == 1 { "image" } else { "images" };
         eprintln!(
-            "Transferring image {tag} ({size_mb} MB) \
-             to {user}@{host}"
+            "Transferring {noun} {} ({size_mb} MB) \
+             to {user}@{host}",
+            tags.join(", ")
         );
 
-        let local_tar = std::env::temp_dir().join(format!("catapulta-{}.tar", app.name));
-        let local_tar_str = local_tar.to_string_lossy().to_string();
-        let remote_tar = format!("/tmp/catapulta-{}.tar", app.name);
+        let progress = |pct: u8| {
+            for app in apps {
+                events::emit(DeployEvent::TransferProgress {
+                    app: app.name.clone(),
+                    pct,
+                });
+            }
+        };
+        progress(0);
 
-        // 1. Save image to local temp file
-        eprintln!("  Saving image to {local_tar_str}...");
-        let save_result = cmd::run_interactive("docker", &["save", &tag, "-o", &local_tar_str]);
-        if save_result.is_err() {
-            let _ = std::fs::remove_file(&local_tar);
-            return save_result;
+        if let Transfer::Stream { zstd } = transfer {
+            let level = self.compression_level.unwrap_or(3);
+            stream_images(ssh, &tags, self.runtime, zstd, level, self.bwlimit)?;
+        } else {
+            let label: Vec<&str> = apps.iter().map(|app| app.name.as_str()).collect();
+            self.transfer_tarball(&label.join("-"), ssh, &tags, transfer, &progress)?;
         }
-
-        // 2. rsync to remote with resume support
-        let ssh_cmd = "ssh -o StrictHostKeyChecking=accept-new \
-             -o ConnectTimeout=10";
-        let dest = format!("{user}@{host}:{remote_tar}");
-
-        eprintln!("  Syncing to {user}@{host}...");
-        let rsync_result = cmd::run_interactive(
-            "rsync",
-            &[
-                "-vz",
-                "--progress",
-                "--partial",
-                "-e",
-                ssh_cmd,
-                &local_tar_str,
-                &dest,
-            ],
+        eprintln!(
+            "  {} loaded on {host}",
+            if tags.len() == 1 { "Image" } else { "Images" }
         );
-        let _ = std::fs::remove_file(&local_tar);
-        rsync_result?;
-
-        // 3. Load on remote and clean up remote tar
-        eprintln!("  Loading image on remote...");
-        let ssh = SshSession::new(host, user);
-        ssh.exec_interactive(&format!(
-            "docker load < {remote_tar} && \
-             rm -f {remote_tar}"
-        ))?;
-        eprintln!("  Image loaded on {host}");
-        Ok(())
+        progress(100);
+        apps.iter()
+            .zip(&tags)
+            .try_for_each(|(app, tag)| retag_latest(app, ssh, tag, self.runtime))
     }
 
     fn deploy(
         &self,
-        host: &str,
-        user: &str,
+        ssh: &SshSession,
         apps: &[App],
         caddy: &Caddy,
         remote_dir: &str,
@@ -157,66 +794,132 @@ impl Deployer for DockerSaveLoad {
 
         check_env_files(apps)?;
 
-        eprintln!("Deploying to {user}@{host}...");
-
-        let ssh = SshSession::new(host, user);
+        let host = ssh.host();
+        eprintln!("Deploying to {}...", ssh.destination());
 
         // Generate config files (always full stack)
-        let caddyfile_content = caddyfile::render(caddy, host);
+        let has_caddy = compose::needs_caddy(apps, caddy);
         let compose_content = compose::render(apps, caddy);
 
         // Write generated files to remote
         eprintln!("Writing deployment config...");
-        ssh.write_remote_file(
+        if let Some(signer) = &self.signer {
+            signer.install(ssh)?;
+        }
+        layout::create_dirs(ssh, remote_dir, apps)?;
+        self.write_file(
+            ssh,
             &compose_content,
             &format!("{remote_dir}/docker-compose.yml"),
+            compose::file_attrs(apps, caddy),
         )?;
-        ssh.write_remote_file(&caddyfile_content, &format!("{remote_dir}/Caddyfile"))?;
+        let caddy_changed = has_caddy
+            && self.write_mounted(
+                ssh,
+                &caddyfile::render_config(caddy, host, apps)?,
+                &format!("{remote_dir}/{}", caddy.config_file()),
+            )?;
+        let mut config_changed = Vec::new();
+        for app in apps {
+            let mut changed = false;
+            for file in &app.config_files {
+                let path = format!("{remote_dir}/{}", app.remote_path(&file.name));
+                changed |= self.write_mounted(ssh, &file.content, &path)?;
+            }
+            if changed && env_apps.iter().any(|a| a.name == app.name) {
+                config_changed.push(app.name.as_str());
+            }
+        }
 
         // Transfer .env files (only selected apps)
         for app in &env_apps {
             if let Some(env_file) = &app.env_file {
-                let remote_name = if apps.len() > 1 {
-                    format!("{remote_dir}/.env.{}", app.name)
-                } else {
-                    format!("{remote_dir}/.env")
-                };
-                ssh.scp_to(env_file, &remote_name)?;
-                ssh.exec(&format!("chmod 600 {remote_name}"))?;
+                let remote_name = format!("{remote_dir}/{}", layout::env_file(app, apps));
+                let content = std::fs::read_to_string(env_file)?;
+                report_env_changes(ssh, env_file, &content, &remote_name);
+                self.write_file(ssh, &content, &remote_name, FileAttrs::new().mode(0o600))?;
             }
         }
+        layout::prune(ssh, remote_dir, apps, caddy)?;
 
         // Start containers
         eprintln!("Starting containers...");
-        if only.is_empty() {
-            ssh.exec_interactive(&format!("cd {remote_dir} && docker compose up -d"))?;
-        } else {
-            let names = only.join(" ");
-            ssh.exec_interactive(&format!(
-                "cd {remote_dir} && \
-                 docker compose up -d {names}"
+        let compose = self.runtime.compose();
+        timing::measure("restart", || {
+            // Apps only read their config files when they start
+            if !config_changed.is_empty() {
+                ssh.exec_interactive(&format!(
+                    "cd {remote_dir} && {compose} up -d --no-deps --force-recreate {}",
+                    config_changed.join(" ")
+                ))?;
+            }
+            if only.is_empty() {
+                ssh.exec_interactive(&format!(
+                    "cd {remote_dir} && {compose} up -d --remove-orphans"
+                ))
+            } else {
+                let names = only.join(" ");
+                ssh.exec_interactive(&format!(
+                    "cd {remote_dir} && \
+                     {compose} up -d {names}"
+                ))
+            }
+        })?;
+        // `up -d` recreates only the services whose image or
+        // compose config changed and leaves Caddy running, so a
+        // new Caddyfile is applied with a reload
+        if caddy_changed {
+            ssh.exec(&format!(
+                "cd {remote_dir} && {}",
+                caddy_reload_command(compose, &caddy.reload_command())
             ))?;
         }
 
         // Wait for health (only selected apps)
         let health_apps: Vec<App> = env_apps.iter().map(|a| (*a).clone()).collect();
         let rd = remote_dir.to_string();
-        wait_healthy(&health_apps, |name| {
-            ssh.exec(&format!(
-                "cd {rd} && \
-                     docker inspect \
-                     --format='{{{{.State.Health.Status}}}}' \
-                     {name}"
-            ))
-        })?;
+        wait_healthy_or_report(
+            &health_apps,
+            &self.health_wait,
+            |name| {
+                let command = self.runtime.health_status_command(name);
+                ssh.exec(&format!("cd {rd} && {command}"))
+            },
+            |name| {
+                let command = self.runtime.unhealthy_report_command(name);
+                ssh.exec(&format!("cd {rd} && {command}"))
+            },
+        )?;
 
         // Show status
-        ssh.exec_interactive(&format!("cd {remote_dir} && docker compose ps"))?;
+        ssh.exec_interactive(&format!("cd {remote_dir} && {compose} ps"))?;
 
         eprintln!();
         eprintln!("Deployment complete!");
-        eprintln!("Application available at: https://{host}");
+        if has_caddy {
+            eprintln!("Application available at: https://{host}");
+        }
 
         Ok(())
     }
+
+    /// Write `content` to the remote `path` with `attrs`, signed
+    /// when signing is on.
+    fn write_file(
+        &self,
+        ssh: &SshSession,
+        content: &str,
+        path: &str,
+        attrs: FileAttrs,
+    ) -> DeployResult<()> {
+        let attrs = match &self.signer {
+            Some(signer) => signer.signed(content, attrs)?,
+            None => attrs,
+        };
+        ssh.write_remote_file_with(content, path, &attrs)
+    }
+
+    fn runtime(&self) -> Runtime {
+        self.runtime
+    }
 }
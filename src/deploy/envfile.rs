@@ -0,0 +1,106 @@
+//! Key-level comparison of `.env` files.
+//!
+//! Before a deploy overwrites a remote `.env`, its keys are
+//! compared with the local file and with the `.env.example`
+//! next to it, so a variable dropped by mistake or a newly
+//! required one shows up as a warning instead of a container
+//! silently running with a default. Only keys are compared;
+//! values are never printed.
+
+use std::path::Path;
+
+/// Keys set in `.env` `content`, in order, without duplicates.
+///
+/// Blank lines and `#` comments are skipped, and an `export `
+/// prefix is ignored.
+#[must_use]
+pub fn parse_keys(content: &str) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, _)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_string();
+        if !key.is_empty() && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// How a local `.env` differs from the remote one it replaces
+/// and from its example.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvDiff {
+    /// Keys set on the server that the local file drops.
+    pub removed: Vec<String>,
+    /// Keys the local file adds.
+    pub added: Vec<String>,
+    /// Keys of the example that the local file lacks.
+    pub missing: Vec<String>,
+}
+
+impl EnvDiff {
+    /// Compare `local` with the `remote` file it replaces (`None`
+    /// on a first deploy) and the `example` it follows, if any.
+    #[must_use]
+    pub fn new(local: &str, remote: Option<&str>, example: Option<&str>) -> Self {
+        let local = parse_keys(local);
+        let not_local = |keys: Vec<String>| -> Vec<String> {
+            keys.into_iter().filter(|k| !local.contains(k)).collect()
+        };
+        let removed = remote.map(parse_keys).map(not_local).unwrap_or_default();
+        let missing = example.map(parse_keys).map(not_local).unwrap_or_default();
+        let added = remote.map_or_else(Vec::new, |remote| {
+            let remote = parse_keys(remote);
+            local
+                .iter()
+                .filter(|k| !remote.contains(k))
+                .cloned()
+                .collect()
+        });
+        Self {
+            removed,
+            added,
+            missing,
+        }
+    }
+
+    /// Warnings about `env_file`: dropped and missing keys.
+    #[must_use]
+    pub fn warnings(&self, env_file: &str) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.removed.is_empty() {
+            warnings.push(format!(
+                "{env_file} drops {} set on the server",
+                self.removed.join(", ")
+            ));
+        }
+        if !self.missing.is_empty() {
+            warnings.push(format!(
+                "{env_file} lacks {} from its example",
+                self.missing.join(", ")
+            ));
+        }
+        warnings
+    }
+}
+
+/// The example of `env_file`: `<env_file>.example`, or
+/// `.env.example` in the same directory.
+#[must_use]
+pub fn example_path(env_file: &str) -> Option<String> {
+    let own = format!("{env_file}.example");
+    if Path::new(&own).exists() {
+        return Some(own);
+    }
+    let shared = Path::new(env_file).with_file_name(".env.example");
+    shared
+        .exists()
+        .then(|| shared.to_string_lossy().into_owned())
+}
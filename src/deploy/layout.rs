@@ -0,0 +1,126 @@
+//! Where a stack's files go on the server.
+//!
+//! Generated files (`docker-compose.yml`, the Caddy config) sit
+//! at the root of the stack's directory, and each app's env and
+//! config files next to them or in its [`App::remote_dir`]. Every
+//! deploy records the files it wrote in [`MANIFEST`], and deletes
+//! those the previous deploy recorded that it no longer writes:
+//! an app moved to its own directory, a renamed config file or a
+//! removed app leaves nothing behind.
+
+use crate::app::App;
+use crate::caddy::Caddy;
+use crate::compose;
+use crate::error::DeployResult;
+use crate::ssh::{SshSession, shell_quote};
+
+/// File listing the files written by the last deploy, relative
+/// to the stack's directory.
+pub const MANIFEST: &str = ".catapulta-files";
+
+/// Path of `app`'s env file relative to the stack's directory:
+/// `.env` in its [`App::remote_dir`], otherwise `.env.<name>` in
+/// a stack of several `apps` and `.env` in a single-app one.
+#[must_use]
+pub fn env_file(app: &App, apps: &[App]) -> String {
+    if app.remote_dir.is_some() {
+        app.remote_path(".env")
+    } else if apps.len() > 1 {
+        format!(".env.{}", app.name)
+    } else {
+        ".env".to_string()
+    }
+}
+
+/// The distinct [`App::remote_dir`]s of `apps`.
+#[must_use]
+pub fn app_dirs(apps: &[App]) -> Vec<&str> {
+    let mut dirs: Vec<&str> = Vec::new();
+    for dir in apps.iter().filter_map(|a| a.remote_dir.as_deref()) {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Files a deploy of `apps` writes, relative to the stack's
+/// directory.
+#[must_use]
+pub fn stack_files(apps: &[App], caddy: &Caddy) -> Vec<String> {
+    let mut files = vec!["docker-compose.yml".to_string()];
+    if compose::needs_caddy(apps, caddy) {
+        files.push(caddy.config_file().to_string());
+    }
+    for app in apps {
+        for file in &app.config_files {
+            files.push(app.remote_path(&file.name));
+        }
+        if app.env_file.is_some() {
+            files.push(env_file(app, apps));
+        }
+    }
+    files
+}
+
+/// Files listed in the `previous` manifest that are not in
+/// `current`. Entries escaping the stack's directory are
+/// ignored.
+#[must_use]
+pub fn stale_files(previous: &str, current: &[String]) -> Vec<String> {
+    previous
+        .lines()
+        .map(str::trim)
+        .filter(|f| !f.is_empty() && !f.starts_with('/') && !f.split('/').any(|p| p == ".."))
+        .filter(|f| !current.iter().any(|c| c == f))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Create the app directories under `remote_dir`.
+pub fn create_dirs(ssh: &SshSession, remote_dir: &str, apps: &[App]) -> DeployResult<()> {
+    let dirs = app_dirs(apps);
+    if dirs.is_empty() {
+        return Ok(());
+    }
+    let paths: Vec<String> = dirs
+        .iter()
+        .map(|d| shell_quote(&format!("{remote_dir}/{d}")))
+        .collect();
+    ssh.exec(&format!("mkdir -p {}", paths.join(" ")))?;
+    Ok(())
+}
+
+/// Delete the files of the previous deploy that this one no
+/// longer writes, with their directories once empty, and record
+/// the current files.
+pub fn prune(ssh: &SshSession, remote_dir: &str, apps: &[App], caddy: &Caddy) -> DeployResult<()> {
+    let manifest = format!("{remote_dir}/{MANIFEST}");
+    let previous = ssh.exec(&format!(
+        "cat {} 2>/dev/null || true",
+        shell_quote(&manifest)
+    ))?;
+    let current = stack_files(apps, caddy);
+    let stale = stale_files(&previous, &current);
+    if !stale.is_empty() {
+        eprintln!(
+            "Removing files left by a previous deploy: {}",
+            stale.join(", ")
+        );
+        let mut steps = Vec::new();
+        for file in &stale {
+            steps.push(format!(
+                "rm -f {}",
+                shell_quote(&format!("{remote_dir}/{file}"))
+            ));
+            if let Some((dir, _)) = file.rsplit_once('/') {
+                steps.push(format!(
+                    "(rmdir {} 2>/dev/null || true)",
+                    shell_quote(&format!("{remote_dir}/{dir}"))
+                ));
+            }
+        }
+        ssh.exec(&steps.join(" && "))?;
+    }
+    ssh.write_remote_file(&(current.join("\n") + "\n"), &manifest)
+}
@@ -0,0 +1,252 @@
+//! Checks of the generated config files before they are
+//! transferred.
+//!
+//! Docker Compose and Caddy only read the files on the server,
+//! where a broken Caddyfile fails the reload of the live proxy
+//! and a broken compose file fails `docker compose up` after the
+//! containers were stopped. Extra directives, routes and app
+//! names all end up in these files, so [`check`] lints them
+//! locally, on deploys and dry runs alike.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use caddyfile_rs::{Address, Directive, Matcher};
+use serde_yaml::Value;
+
+use crate::error::{DeployError, DeployResult};
+
+/// Name of the generated compose file.
+pub const COMPOSE_FILE: &str = "docker-compose.yml";
+
+/// Problems in the generated file `name`: [`COMPOSE_FILE`], a
+/// Caddy JSON config (`*.json`) or a Caddyfile.
+#[must_use]
+pub fn lint(name: &str, content: &str) -> Vec<String> {
+    if name == COMPOSE_FILE {
+        compose(content)
+    } else if Path::new(name).extension().is_some_and(|ext| ext == "json") {
+        caddy_json(content)
+    } else {
+        caddyfile(content)
+    }
+}
+
+/// Fail with the problems [`lint`] finds in the `(name, content)`
+/// generated `files`.
+pub fn check(files: &[(&str, &str)]) -> DeployResult<()> {
+    let problems: Vec<String> = files
+        .iter()
+        .flat_map(|(name, content)| {
+            lint(name, content)
+                .into_iter()
+                .map(move |problem| format!("{name}: {problem}"))
+        })
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(DeployError::InvalidConfig(format!(
+            "generated files are invalid: {}",
+            problems.join("; ")
+        )))
+    }
+}
+
+/// Problems in a Caddyfile: syntax errors, sites defined twice
+/// and named matchers used without being defined.
+#[must_use]
+pub fn caddyfile(content: &str) -> Vec<String> {
+    let caddyfile = match caddyfile_rs::parse_str(&parseable(content)) {
+        Ok(caddyfile) => caddyfile,
+        Err(e) => return vec![e.to_string()],
+    };
+
+    let mut problems = Vec::new();
+    let mut addresses: Vec<&Address> = Vec::new();
+    for site in &caddyfile.sites {
+        for address in &site.addresses {
+            if addresses.contains(&address) {
+                problems.push(format!("site {address} is defined twice"));
+            }
+            addresses.push(address);
+        }
+
+        let mut defined = BTreeSet::new();
+        let mut used = BTreeSet::new();
+        collect_matchers(&site.directives, &mut defined, &mut used);
+        for name in used.difference(&defined) {
+            let site = site.addresses.first().map(ToString::to_string);
+            problems.push(format!(
+                "matcher @{name} of site {} is not defined",
+                site.unwrap_or_default()
+            ));
+        }
+    }
+    problems
+}
+
+/// `content` in the syntax the parser reads, line for line.
+///
+/// Caddy accepts two things the parser does not: placeholders
+/// such as `{host}`, whose braces the parser takes for blocks,
+/// and arguments after the closing marker of a heredoc
+/// (`HTML 503`). Braces that are not tokens of their own become
+/// parentheses and the arguments are dropped.
+fn parseable(content: &str) -> String {
+    let mut marker: Option<&str> = None;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        match marker {
+            Some(m) if line.split_whitespace().next() == Some(m) => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                lines.push(format!("{indent}{m}"));
+                marker = None;
+            }
+            Some(_) => lines.push(line.to_string()),
+            None => {
+                marker = line
+                    .split_whitespace()
+                    .last()
+                    .and_then(|word| word.strip_prefix("<<"));
+                lines.push(without_placeholder_braces(line));
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+fn without_placeholder_braces(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let standalone = |i: usize| {
+        let before = i.checked_sub(1).map(|j| chars[j]);
+        let after = chars.get(i + 1);
+        before.is_none_or(char::is_whitespace) && after.is_none_or(|c| c.is_whitespace())
+    };
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| match c {
+            '{' if !standalone(i) => '(',
+            '}' if !standalone(i) => ')',
+            _ => c,
+        })
+        .collect()
+}
+
+/// The named matchers `directives` define and use, nested
+/// blocks included.
+fn collect_matchers<'a>(
+    directives: &'a [Directive],
+    defined: &mut BTreeSet<&'a str>,
+    used: &mut BTreeSet<&'a str>,
+) {
+    for directive in directives {
+        if let Some(name) = directive.name.strip_prefix('@') {
+            defined.insert(name);
+        }
+        if let Some(Matcher::Named(name)) = &directive.matcher {
+            used.insert(name);
+        }
+        if let Some(block) = &directive.block {
+            collect_matchers(block, defined, used);
+        }
+    }
+}
+
+/// Problems in a Caddy JSON config.
+#[must_use]
+pub fn caddy_json(content: &str) -> Vec<String> {
+    match serde_json::from_str::<serde_json::Value>(content) {
+        Ok(config) if config.is_object() => Vec::new(),
+        Ok(_) => vec!["not a JSON object".to_string()],
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// Problems in a compose file that `docker compose` would reject
+/// or fail to start: invalid service names, references to
+/// undefined services, networks and volumes, and host ports
+/// published twice.
+#[must_use]
+pub fn compose(content: &str) -> Vec<String> {
+    let compose: Value = match serde_yaml::from_str(content) {
+        Ok(compose) => compose,
+        Err(e) => return vec![e.to_string()],
+    };
+    let Some(services) = compose["services"].as_mapping() else {
+        return vec!["no services".to_string()];
+    };
+
+    let mut problems = Vec::new();
+    let mut published: HashMap<String, &str> = HashMap::new();
+    for (name, service) in services {
+        let name = name.as_str().unwrap_or_default();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        {
+            problems.push(format!("invalid service name '{name}'"));
+        }
+        for dependency in keys(&service["depends_on"]) {
+            if !services.contains_key(dependency) {
+                problems.push(format!(
+                    "service {name} depends on undefined service {dependency}"
+                ));
+            }
+        }
+        for network in keys(&service["networks"]) {
+            if compose["networks"].get(network).is_none() {
+                problems.push(format!("service {name} uses undefined network {network}"));
+            }
+        }
+        for volume in sequence(&service["volumes"]).filter_map(named_volume) {
+            if compose["volumes"].get(volume).is_none() {
+                problems.push(format!("service {name} uses undefined volume {volume}"));
+            }
+        }
+        for port in sequence(&service["ports"]).filter_map(host_port) {
+            if let Some(other) = published.insert(port.clone(), name) {
+                problems.push(format!(
+                    "host port {port} is published by both {other} and {name}"
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// The names in a list or map of names, e.g. `depends_on`.
+fn keys(value: &Value) -> Vec<&str> {
+    match value {
+        Value::Sequence(names) => names.iter().filter_map(Value::as_str).collect(),
+        Value::Mapping(map) => map.keys().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn sequence(value: &Value) -> impl Iterator<Item = &str> {
+    value
+        .as_sequence()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+/// The named volume of a short volume entry, `None` for bind
+/// mounts.
+fn named_volume(volume: &str) -> Option<&str> {
+    let (source, _) = volume.split_once(':')?;
+    let bind = source.starts_with(['/', '.', '~', '$']) || source.contains('/');
+    (!bind).then_some(source)
+}
+
+/// The host port and protocol of a short port entry, `None`
+/// when the port is not published.
+fn host_port(port: &str) -> Option<String> {
+    let (mapping, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+    let parts: Vec<&str> = mapping.split(':').collect();
+    let host = parts.len().checked_sub(2).map(|i| parts[i])?;
+    (!host.is_empty()).then(|| format!("{host}/{protocol}"))
+}
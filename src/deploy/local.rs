@@ -1,31 +1,113 @@
 use std::fs;
+use std::time::Duration;
 
 use crate::app::App;
 use crate::caddy::Caddy;
 use crate::caddyfile;
 use crate::cmd;
 use crate::compose;
-use crate::deploy::{Deployer, check_env_files, cleanup_source, prepare_source, wait_healthy};
+use crate::deploy::layout;
+use crate::deploy::{
+    CADDY_RELOAD_ATTEMPTS, Deployer, HealthWait, STATUS_FORMAT, check_env_files, cleanup_source,
+    prepare_source, report_image_built, run_pre_build, unhealthy_report_command,
+    wait_healthy_or_report,
+};
 use crate::error::DeployResult;
+use crate::ssh::{SshSession, shell_quote};
+use crate::timing;
 
 /// Deploy to the local Docker daemon for testing.
 ///
 /// Images are built for the native platform (no cross-compile
 /// overhead), and the full compose stack runs locally with
-/// `tls internal` for self-signed HTTPS.
+/// `tls internal` for self-signed HTTPS, or plain HTTP with
+/// [`LocalDeploy::plain_http`].
 ///
-/// This is a unit struct like [`super::docker_save::DockerSaveLoad`].
 /// The local directory is passed as the `remote_dir` parameter
-/// to [`Deployer::deploy`].
-pub struct LocalDeploy;
+/// to [`Deployer::deploy`], and the local domain as the host of
+/// the (never connected) [`SshSession`].
+pub struct LocalDeploy {
+    /// Serve the site over plain HTTP instead of `tls internal`,
+    /// as `cargo xtask dev` does.
+    pub plain_http: bool,
+}
 
 impl LocalDeploy {
     #[must_use]
     pub const fn new() -> Self {
-        Self
+        Self { plain_http: false }
+    }
+
+    /// Serve the site over plain HTTP, without certificates.
+    #[must_use]
+    pub const fn plain_http(mut self) -> Self {
+        self.plain_http = true;
+        self
+    }
+
+    /// `caddy` as the local stack runs it: with `tls internal`
+    /// for self-signed HTTPS unless [`Self::plain_http`], and its
+    /// own Caddy even when the servers share one.
+    #[must_use]
+    pub fn caddy(&self, caddy: &Caddy) -> Caddy {
+        let mut local = caddy.clone();
+        local.tls_internal = !self.plain_http;
+        local.shared = false;
+        local
+    }
+
+    /// The address of the site on `host`.
+    #[must_use]
+    pub fn site(&self, host: &str) -> String {
+        if self.plain_http {
+            format!("http://{host}")
+        } else {
+            host.to_string()
+        }
     }
 }
 
+/// `apps` as `cargo xtask dev` runs them.
+///
+/// Every exposed port is published on the same host port, or the
+/// next free one, the [`App::dev_volumes`] are mounted, and apps
+/// lose their own domains, so everything is reached through
+/// localhost.
+///
+/// Relative dev volume paths are made absolute, since compose
+/// would resolve them in the local stack's directory instead of
+/// the one `cargo xtask` runs in.
+#[must_use]
+pub fn dev_apps(apps: &[App]) -> Vec<App> {
+    let mut taken: Vec<u16> = apps
+        .iter()
+        .flat_map(|a| a.ports.iter().map(|(host, _)| *host))
+        .collect();
+    apps.iter()
+        .map(|app| {
+            let mut app = app.clone();
+            for port in app.expose.clone() {
+                if app.ports.iter().any(|(_, container)| *container == port) {
+                    continue;
+                }
+                let mut host = port;
+                while taken.contains(&host) {
+                    host = host.saturating_add(1);
+                }
+                taken.push(host);
+                app.ports.push((host, port));
+            }
+            for (path, mount) in std::mem::take(&mut app.dev_volumes) {
+                let path =
+                    std::path::absolute(&path).map_or(path, |p| p.to_string_lossy().into_owned());
+                app.volumes.push((path, mount));
+            }
+            app.domain = None;
+            app
+        })
+        .collect()
+}
+
 impl Default for LocalDeploy {
     fn default() -> Self {
         Self::new()
@@ -53,6 +135,31 @@ fn run_compose(local_dir: &str, args: &[&str]) -> DeployResult<()> {
     cmd::run_interactive("docker", &refs)
 }
 
+/// Write `content` to the bind-mounted file `path`, in place,
+/// returning whether it replaced a different previous version.
+fn write_mounted(path: &str, content: &str) -> DeployResult<bool> {
+    let previous = fs::read_to_string(path).ok();
+    fs::write(path, content)?;
+    Ok(previous.is_some_and(|previous| previous != content))
+}
+
+/// Reload Caddy with `reload`, retried while its admin API
+/// starts.
+fn reload_caddy(local_dir: &str, reload: &str) -> DeployResult<()> {
+    let mut args = vec!["exec", "-T", "caddy"];
+    args.extend(reload.split_whitespace());
+    let mut attempt = 1;
+    loop {
+        match run_compose(local_dir, &args) {
+            Err(_) if attempt < CADDY_RELOAD_ATTEMPTS => {
+                attempt += 1;
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            result => return result,
+        }
+    }
+}
+
 impl Deployer for LocalDeploy {
     fn build_image(&self, app: &App) -> DeployResult<()> {
         eprintln!("Building Docker image for native platform...");
@@ -95,7 +202,11 @@ impl Deployer for LocalDeploy {
         args.push(&tag);
         args.push(&context);
 
-        let result = cmd::run_interactive("docker", &args);
+        let result = run_pre_build(app, source_dir.as_deref())
+            .and_then(|()| cmd::run_interactive("docker", &args));
+        if result.is_ok() {
+            report_image_built(app, &tag);
+        }
 
         if !app.cache_source {
             if let Some(dir) = &source_dir {
@@ -106,15 +217,14 @@ impl Deployer for LocalDeploy {
         result
     }
 
-    fn transfer_image(&self, _app: &App, _host: &str, _user: &str) -> DeployResult<()> {
+    fn transfer_image(&self, _app: &App, _ssh: &SshSession) -> DeployResult<()> {
         // No-op: images are already in the local daemon
         Ok(())
     }
 
     fn deploy(
         &self,
-        host: &str,
-        _user: &str,
+        ssh: &SshSession,
         apps: &[App],
         caddy: &Caddy,
         local_dir: &str,
@@ -129,60 +239,107 @@ impl Deployer for LocalDeploy {
 
         check_env_files(apps)?;
 
+        let host = ssh.host();
         eprintln!("Deploying locally to {local_dir}/...");
 
         // Create local directory
         fs::create_dir_all(local_dir)?;
 
         // Generate config files with tls internal (always full)
-        let mut local_caddy = caddy.clone();
-        local_caddy.tls_internal = true;
-        let caddyfile_content = caddyfile::render(&local_caddy, host);
-        let compose_content = compose::render(apps, caddy);
+        let local_caddy = self.caddy(caddy);
+        let site = self.site(host);
+        let has_caddy = compose::needs_caddy(apps, &local_caddy);
+        let compose_content = compose::render(apps, &local_caddy);
 
         // Write config files
         eprintln!("Writing deployment config...");
         fs::write(format!("{local_dir}/docker-compose.yml"), &compose_content)?;
-        fs::write(format!("{local_dir}/Caddyfile"), &caddyfile_content)?;
+        let caddy_changed = has_caddy
+            && write_mounted(
+                &format!("{local_dir}/{}", local_caddy.config_file()),
+                &caddyfile::render_config(&local_caddy, &site, apps)?,
+            )?;
+        for dir in layout::app_dirs(apps) {
+            fs::create_dir_all(format!("{local_dir}/{dir}"))?;
+        }
+        let mut config_changed = Vec::new();
+        for app in apps {
+            let mut changed = false;
+            for file in &app.config_files {
+                let path = format!("{local_dir}/{}", app.remote_path(&file.name));
+                changed |= write_mounted(&path, &file.content)?;
+            }
+            if changed && env_apps.iter().any(|a| a.name == app.name) {
+                config_changed.push(app.name.as_str());
+            }
+        }
 
         // Copy .env files (only selected apps)
         for app in &env_apps {
             if let Some(env_file) = &app.env_file {
-                let local_name = if apps.len() > 1 {
-                    format!("{local_dir}/.env.{}", app.name)
-                } else {
-                    format!("{local_dir}/.env")
-                };
+                let local_name = format!("{local_dir}/{}", layout::env_file(app, apps));
                 fs::copy(env_file, &local_name)?;
             }
         }
 
         // Start containers
         eprintln!("Starting containers...");
-        if only.is_empty() {
-            run_compose(local_dir, &["up", "-d"])?;
-        } else {
+        timing::measure("restart", || {
+            // Apps only read their config files when they start
+            if !config_changed.is_empty() {
+                let mut args = vec!["up", "-d", "--no-deps", "--force-recreate"];
+                args.extend(&config_changed);
+                run_compose(local_dir, &args)?;
+            }
             let mut args: Vec<&str> = vec!["up", "-d"];
-            let names: Vec<&str> = only.iter().map(String::as_str).collect();
-            args.extend(&names);
-            run_compose(local_dir, &args)?;
+            if only.is_empty() {
+                args.push("--remove-orphans");
+            }
+            args.extend(only.iter().map(String::as_str));
+            run_compose(local_dir, &args)
+        })?;
+        // `up -d` leaves an unchanged Caddy running, so a new
+        // Caddyfile is applied with a reload
+        if caddy_changed {
+            reload_caddy(local_dir, &local_caddy.reload_command())?;
         }
 
         // Wait for health (only selected apps)
         let health_apps: Vec<App> = env_apps.iter().copied().cloned().collect();
-        wait_healthy(&health_apps, |name| {
-            cmd::run(
-                "docker",
-                &["inspect", "--format={{.State.Health.Status}}", name],
-            )
-        })?;
+        wait_healthy_or_report(
+            &health_apps,
+            &HealthWait::default(),
+            |name| {
+                let ps = compose_cmd(local_dir, &["ps", "-aq", name]);
+                let ps: Vec<&str> = ps.iter().map(String::as_str).collect();
+                let ids = cmd::run("docker", &ps)?;
+                let format = format!("--format={STATUS_FORMAT}");
+                let mut args = vec!["inspect", &format];
+                args.extend(ids.split_whitespace());
+                cmd::run("docker", &args)
+            },
+            |name| {
+                let report = format!(
+                    "cd {} && {}",
+                    shell_quote(local_dir),
+                    unhealthy_report_command(name)
+                );
+                cmd::run("sh", &["-c", &report])
+            },
+        )?;
 
         // Show status
         run_compose(local_dir, &["ps"])?;
 
         eprintln!();
         eprintln!("Local deployment complete!");
-        eprintln!("Application available at: https://{host}");
+        if has_caddy {
+            if self.plain_http {
+                eprintln!("Application available at: {site}");
+            } else {
+                eprintln!("Application available at: https://{host}");
+            }
+        }
 
         Ok(())
     }
@@ -1,6 +1,13 @@
+pub mod do_registry;
+pub mod docker_context;
 pub mod docker_save;
+pub mod envfile;
+pub mod layout;
+pub mod lint;
 pub mod local;
+pub mod preflight;
 
+use std::cell::Cell;
 use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
@@ -9,15 +16,38 @@ use crate::app::App;
 use crate::caddy::Caddy;
 use crate::cmd;
 use crate::error::{DeployError, DeployResult};
+use crate::events::{self, DeployEvent};
+use crate::ssh::{FileAttrs, SshSession, shell_quote};
+use crate::timing;
 
 /// A deployer builds, transfers, and starts containers on
 /// a remote host.
+///
+/// The remote host is reached through the [`SshSession`] built by
+/// the pipeline, so jump hosts and other connection settings
+/// apply to every command a deployer runs.
 pub trait Deployer {
     /// Build the Docker image locally.
     fn build_image(&self, app: &App) -> DeployResult<()>;
 
     /// Transfer the image to the remote host.
-    fn transfer_image(&self, app: &App, host: &str, user: &str) -> DeployResult<()>;
+    fn transfer_image(&self, app: &App, ssh: &SshSession) -> DeployResult<()>;
+
+    /// Transfer the images of `apps` to the remote host, one
+    /// after the other unless the deployer can send them
+    /// together.
+    fn transfer_images(&self, apps: &[&App], ssh: &SshSession) -> DeployResult<()> {
+        apps.iter()
+            .try_for_each(|app| self.transfer_image(app, ssh))
+    }
+
+    /// Whether the remote host already runs the image just built
+    /// for `app`, so the pipeline skips its transfer (and keeps
+    /// the rollback history as is). Deployers that can't tell
+    /// return `false`.
+    fn image_unchanged(&self, _app: &App, _ssh: &SshSession) -> bool {
+        false
+    }
 
     /// Deploy the full stack to the remote host.
     ///
@@ -27,13 +57,135 @@ pub trait Deployer {
     /// written in full.
     fn deploy(
         &self,
-        host: &str,
-        user: &str,
+        ssh: &SshSession,
         apps: &[App],
         caddy: &Caddy,
         remote_dir: &str,
         only: &[String],
     ) -> DeployResult<()>;
+
+    /// Write a stack file such as `docker-compose.yml` to the
+    /// remote `path`, the way [`Self::deploy`] writes it, so
+    /// commands updating the stack outside a deploy (`scale`)
+    /// keep the deployer's guarantees, e.g. signatures.
+    fn write_file(
+        &self,
+        ssh: &SshSession,
+        content: &str,
+        path: &str,
+        attrs: FileAttrs,
+    ) -> DeployResult<()> {
+        ssh.write_remote_file_with(content, path, &attrs)
+    }
+
+    /// Container engine running the stack on the remote host,
+    /// which the pipeline's own commands on the server use too.
+    fn runtime(&self) -> Runtime {
+        Runtime::Docker
+    }
+}
+
+/// Container engine on the remote host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Runtime {
+    /// Docker with the compose plugin.
+    #[default]
+    Docker,
+    /// Podman with `podman-compose`, as shipped by RHEL and
+    /// Fedora (see [`crate::provision::setup::SetupStep::podman_defaults`]).
+    ///
+    /// Images are still built and saved with the local Docker.
+    /// The shared Caddy ([`Caddy::shared`]) and the collision
+    /// and compose checks before a deploy need Docker.
+    Podman,
+}
+
+impl Runtime {
+    /// Name shown to the user.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Docker => "Docker",
+            Self::Podman => "Podman",
+        }
+    }
+
+    /// The engine's CLI (`docker`, `podman`).
+    #[must_use]
+    pub const fn cli(self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Podman => "podman",
+        }
+    }
+
+    /// Command running compose (`docker compose`,
+    /// `podman-compose`).
+    #[must_use]
+    pub const fn compose(self) -> &'static str {
+        match self {
+            Self::Docker => "docker compose",
+            Self::Podman => "podman-compose",
+        }
+    }
+
+    /// Shell command listing the IDs of the containers of
+    /// `service`, stopped ones included with `all`. Run from the
+    /// directory holding `docker-compose.yml`.
+    ///
+    /// `podman-compose ps` can't select a service, so Podman
+    /// containers are found by the labels `podman-compose` sets.
+    #[must_use]
+    pub fn containers(self, service: &str, all: bool) -> String {
+        let flags = if all { "-aq" } else { "-q" };
+        match self {
+            Self::Docker => format!("docker compose ps {flags} {service}"),
+            Self::Podman => format!(
+                "podman ps {flags} \
+                 --filter \"label=com.docker.compose.project.working_dir=$(pwd -P)\" \
+                 --filter label=com.docker.compose.service={service}"
+            ),
+        }
+    }
+
+    /// Shell command printing the [`STATUS_FORMAT`] status of
+    /// each of the service's containers, stopped ones included,
+    /// one per line. Run from the directory holding
+    /// `docker-compose.yml`.
+    #[must_use]
+    pub fn health_status_command(self, service: &str) -> String {
+        format!(
+            "{} inspect --format='{STATUS_FORMAT}' $({})",
+            self.cli(),
+            self.containers(service, true)
+        )
+    }
+
+    /// Shell command printing the last [`UNHEALTHY_LOG_LINES`]
+    /// log lines of the service and the output of its latest
+    /// healthchecks. Run from the directory holding
+    /// `docker-compose.yml`.
+    #[must_use]
+    pub fn unhealthy_report_command(self, service: &str) -> String {
+        let logs = match self {
+            Self::Docker => {
+                format!("docker compose logs --no-color --tail {UNHEALTHY_LOG_LINES} {service}")
+            }
+            Self::Podman => format!(
+                "podman logs --tail {UNHEALTHY_LOG_LINES} $({})",
+                self.containers(service, false)
+            ),
+        };
+        format!(
+            "echo '--- last {UNHEALTHY_LOG_LINES} log lines ---'; \
+             {logs} 2>&1; \
+             echo '--- healthcheck output ---'; \
+             {} inspect --format='{{{{range .State.Health.Log}}}}exit {{{{.ExitCode}}}}: \
+             {{{{.Output}}}}{{{{end}}}}' $({}) 2>&1; true",
+            self.cli(),
+            self.containers(service, false)
+        )
+    }
 }
 
 /// Verify that all referenced `.env` files exist on disk.
@@ -94,6 +246,20 @@ pub fn prepare_source(app: &App) -> DeployResult<Option<PathBuf>> {
     }
 }
 
+/// Run `app`'s [`App::pre_build`] commands, from `source_dir`
+/// when the app is built from a cloned repository.
+pub fn run_pre_build(app: &App, source_dir: Option<&Path>) -> DeployResult<()> {
+    for command in &app.pre_build {
+        eprintln!("Running pre-build for {}: {command}", app.name);
+        let command = source_dir.map_or_else(
+            || command.clone(),
+            |dir| format!("cd {} && {command}", shell_quote(&dir.to_string_lossy())),
+        );
+        cmd::run_pipeline(&command)?;
+    }
+    Ok(())
+}
+
 /// Remove a non-cached source directory.
 pub fn cleanup_source(dir: &Path) {
     if let Err(e) = std::fs::remove_dir_all(dir) {
@@ -101,11 +267,146 @@ pub fn cleanup_source(dir: &Path) {
     }
 }
 
+/// Size in bytes of the local image `tag`.
+pub fn image_size(tag: &str) -> DeployResult<u64> {
+    let size = cmd::run(
+        "docker",
+        &["image", "inspect", "--format", "{{.Size}}", tag],
+    )?;
+    Ok(size.parse().unwrap_or(0))
+}
+
+/// Id of the local image `tag`, i.e. the digest of its config.
+pub fn image_id(tag: &str) -> DeployResult<String> {
+    cmd::run("docker", &["image", "inspect", "--format", "{{.Id}}", tag])
+}
+
+/// Whether the image ids `a` and `b` are the same image. Podman
+/// prints ids without the `sha256:` prefix Docker adds; an empty
+/// id (no such image) matches nothing.
+#[must_use]
+pub fn same_image(a: &str, b: &str) -> bool {
+    let a = a.trim().trim_start_matches("sha256:");
+    let b = b.trim().trim_start_matches("sha256:");
+    !a.is_empty() && a == b
+}
+
+/// Emit [`DeployEvent::ImageBuilt`] for `app`'s image `tag`.
+pub fn report_image_built(app: &App, tag: &str) {
+    events::emit(DeployEvent::ImageBuilt {
+        app: app.name.clone(),
+        tag: tag.to_string(),
+        size: image_size(tag).unwrap_or(0),
+    });
+}
+
+/// `docker inspect` format printing the status of a container.
+///
+/// That is its health status, or for a container without
+/// healthcheck its state: `running`, `completed` once it exited
+/// with status 0, `restarting` once it restarted, `exited` or
+/// `dead`.
+pub const STATUS_FORMAT: &str = "{{if .State.Health}}{{.State.Health.Status}}\
+     {{else if gt .RestartCount 0}}restarting\
+     {{else if and (eq .State.Status \"exited\") (eq .State.ExitCode 0)}}completed\
+     {{else}}{{.State.Status}}{{end}}";
+
+/// [`Runtime::health_status_command`] with Docker.
+#[must_use]
+pub fn health_status_command(service: &str) -> String {
+    Runtime::Docker.health_status_command(service)
+}
+
+/// How long to wait for containers to become healthy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthWait {
+    /// Health checks per app before giving up.
+    pub attempts: u32,
+    /// Pause between two checks.
+    pub interval: Duration,
+}
+
+impl Default for HealthWait {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthWait {
+    /// 30 checks, 5 seconds apart.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            attempts: 30,
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Wait about `timeout` per app, checking every
+    /// [`Self::interval`].
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        let interval = self.interval.as_millis().max(1);
+        let attempts = timeout.as_millis().div_ceil(interval);
+        self.attempts = u32::try_from(attempts).unwrap_or(u32::MAX).max(1);
+        self
+    }
+}
+
+thread_local! {
+    static NO_WAIT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Restores the previous [`without_health_wait`] setting when
+/// a scope ends, including by panic.
+struct NoWaitScope(bool);
+
+impl Drop for NoWaitScope {
+    fn drop(&mut self) {
+        NO_WAIT.set(self.0);
+    }
+}
+
+/// Run `f` without waiting for containers to become healthy on
+/// this thread, for fire-and-forget deploys of slow-starting
+/// services (`deploy --no-wait`).
+pub fn without_health_wait<T>(f: impl FnOnce() -> T) -> T {
+    let _scope = NoWaitScope(NO_WAIT.replace(true));
+    f()
+}
+
+/// Log lines shown for a container that did not become healthy.
+pub const UNHEALTHY_LOG_LINES: u32 = 50;
+
+/// [`Runtime::unhealthy_report_command`] with Docker.
+#[must_use]
+pub fn unhealthy_report_command(service: &str) -> String {
+    Runtime::Docker.unhealthy_report_command(service)
+}
+
+/// Attempts at reloading Caddy right after `up`, while its admin
+/// API may still be starting.
+pub const CADDY_RELOAD_ATTEMPTS: u32 = 5;
+
+/// Command reloading the `caddy` service of `compose` with
+/// `reload` (see [`crate::Caddy::reload_command`]), retried up to
+/// [`CADDY_RELOAD_ATTEMPTS`] times. Fails when every attempt
+/// does.
+#[must_use]
+pub fn caddy_reload_command(compose: &str, reload: &str) -> String {
+    let attempts: Vec<String> = (1..=CADDY_RELOAD_ATTEMPTS).map(|n| n.to_string()).collect();
+    format!(
+        "for attempt in {}; do {compose} exec -T caddy {reload} && exit 0; sleep 2; done; exit 1",
+        attempts.join(" ")
+    )
+}
+
 /// Poll container health status via `docker inspect`.
 ///
 /// When an app has a healthcheck configured, queries the health
 /// status in a loop. Falls back to a brief sleep when no
-/// healthcheck is defined.
+/// healthcheck is defined, then fails for apps without one whose
+/// container already stopped or restarted.
 ///
 /// The `inspect_fn` closure runs the inspect command and returns
 /// the status string. This allows reuse for both SSH-based remote
@@ -114,32 +415,107 @@ pub fn wait_healthy<F>(apps: &[App], inspect_fn: F) -> DeployResult<()>
 where
     F: Fn(&str) -> DeployResult<String>,
 {
-    const MAX_ATTEMPTS: u32 = 30;
-    const INTERVAL: Duration = Duration::from_secs(5);
+    wait_healthy_or_report(apps, &HealthWait::default(), inspect_fn, |_| {
+        Ok(String::new())
+    })
+}
+
+/// Like [`wait_healthy`], waiting as long as `wait` says and
+/// reporting on apps that fail.
+///
+/// For an app that does not become healthy, what `report_fn`
+/// returns (e.g. the output of [`unhealthy_report_command`]) is
+/// printed before failing, so the cause shows without logging
+/// into the server.
+pub fn wait_healthy_or_report<F, R>(
+    apps: &[App],
+    wait: &HealthWait,
+    inspect_fn: F,
+    report_fn: R,
+) -> DeployResult<()>
+where
+    F: Fn(&str) -> DeployResult<String>,
+    R: Fn(&str) -> DeployResult<String>,
+{
+    if NO_WAIT.get() {
+        eprintln!("Not waiting for containers to be healthy");
+        return Ok(());
+    }
+    timing::measure("healthcheck", || {
+        poll_healthy(apps, wait, inspect_fn, report_fn)
+    })
+}
+
+/// Print `report_fn`'s output for the app `name`, which `failed`
+/// (e.g. "did not become healthy").
+fn report_unhealthy<R>(name: &str, failed: &str, report_fn: R)
+where
+    R: Fn(&str) -> DeployResult<String>,
+{
+    match report_fn(name) {
+        Ok(report) if report.trim().is_empty() => {}
+        Ok(report) => {
+            eprintln!("{name} {failed}:");
+            for line in report.lines() {
+                eprintln!("  {line}");
+            }
+        }
+        Err(e) => eprintln!("Warning: could not read the logs of {name}: {e}"),
+    }
+}
+
+fn poll_healthy<F, R>(
+    apps: &[App],
+    wait: &HealthWait,
+    inspect_fn: F,
+    report_fn: R,
+) -> DeployResult<()>
+where
+    F: Fn(&str) -> DeployResult<String>,
+    R: Fn(&str) -> DeployResult<String>,
+{
+    let max_attempts = wait.attempts.max(1);
 
-    let apps_with_hc: Vec<&App> = apps.iter().filter(|a| a.healthcheck.is_some()).collect();
+    // Apps left in a profile are not started
+    let apps_with_hc: Vec<&App> = apps
+        .iter()
+        .filter(|a| a.healthcheck.is_some() && a.profiles.is_empty())
+        .collect();
+
+    let apps_without_hc: Vec<&App> = apps
+        .iter()
+        .filter(|a| a.healthcheck.is_none() && a.profiles.is_empty())
+        .collect();
 
     if apps_with_hc.is_empty() {
         eprintln!("No healthcheck configured, waiting 5s...");
         thread::sleep(Duration::from_secs(5));
-        return Ok(());
+        return check_running(&apps_without_hc, inspect_fn, report_fn);
     }
 
     eprintln!("Waiting for containers to be healthy...");
 
     for app in &apps_with_hc {
-        for attempt in 1..=MAX_ATTEMPTS {
+        for attempt in 1..=max_attempts {
             let output = inspect_fn(&app.name);
+            events::emit(DeployEvent::HealthcheckAttempt {
+                app: app.name.clone(),
+                attempt,
+                max_attempts,
+                status: output.as_ref().ok().map(|s| s.trim().to_string()),
+            });
 
             match output {
                 Ok(status) => {
-                    let status = status.trim();
+                    // One line per replica
+                    let statuses: Vec<&str> = status.split_whitespace().collect();
                     eprint!(
-                        "  {} ({attempt}/{MAX_ATTEMPTS}): \
-                         {status}",
-                        app.name
+                        "  {} ({attempt}/{max_attempts}): \
+                         {}",
+                        app.name,
+                        statuses.join(", ")
                     );
-                    if status == "healthy" {
+                    if !statuses.is_empty() && statuses.iter().all(|s| *s == "healthy") {
                         eprintln!();
                         break;
                     }
@@ -147,23 +523,50 @@ where
                 }
                 Err(_) => {
                     eprintln!(
-                        "  {} ({attempt}/{MAX_ATTEMPTS}): \
+                        "  {} ({attempt}/{max_attempts}): \
                          waiting for container...",
                         app.name
                     );
                 }
             }
 
-            if attempt == MAX_ATTEMPTS {
+            if attempt == max_attempts {
+                report_unhealthy(&app.name, "did not become healthy", &report_fn);
                 return Err(DeployError::HealthcheckTimeout(
                     app.name.clone(),
-                    MAX_ATTEMPTS,
+                    max_attempts,
                 ));
             }
 
-            thread::sleep(INTERVAL);
+            thread::sleep(wait.interval);
         }
     }
 
+    check_running(&apps_without_hc, inspect_fn, report_fn)
+}
+
+/// Fail for the first of `apps` with a container that exited,
+/// died or restarted, printing `report_fn`'s output for it.
+fn check_running<F, R>(apps: &[&App], inspect_fn: F, report_fn: R) -> DeployResult<()>
+where
+    F: Fn(&str) -> DeployResult<String>,
+    R: Fn(&str) -> DeployResult<String>,
+{
+    for app in apps {
+        // A container that can't be inspected shows in `ps` below
+        let Ok(status) = inspect_fn(&app.name) else {
+            continue;
+        };
+        let stopped = status
+            .split_whitespace()
+            .find(|s| matches!(*s, "exited" | "dead" | "restarting"));
+        if let Some(state) = stopped {
+            report_unhealthy(&app.name, "stopped right after starting", &report_fn);
+            return Err(DeployError::ContainerExited {
+                name: app.name.clone(),
+                state: state.to_string(),
+            });
+        }
+    }
     Ok(())
 }
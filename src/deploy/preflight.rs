@@ -0,0 +1,503 @@
+//! Checks run before a deploy changes anything.
+//!
+//! [`check_host`] verifies that the server was set up, so a
+//! fresh or half-configured host fails with what is missing
+//! instead of a raw command error midway through the deploy.
+//! [`check_platforms`] catches images built for another
+//! architecture (an amd64 image on an ARM server), which would
+//! otherwise only fail with an exec format error once the
+//! containers start.
+//! [`check_collisions`] stops a deploy that would take over the
+//! containers, images, compose project or ports of another stack
+//! deployed to a different directory of the same server.
+//! [`check_compose`] has `docker compose` on the server validate
+//! the new compose file while the running stack is still up.
+//! The free disk space checks catch a multi-GB image that does
+//! not fit, which otherwise fails late, as a cryptic rsync or
+//! `docker load` error after most of the copy.
+//!
+//! Checks are skipped when their commands' output can't be read.
+
+use std::collections::HashMap;
+
+use crate::app::{App, machine_platform};
+use crate::caddy::Caddy;
+use crate::cmd;
+use crate::compose;
+use crate::deploy::Runtime;
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::{SshSession, shell_quote};
+
+/// Free space on the filesystem holding one `df` argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskFree {
+    /// Mount point of the filesystem.
+    pub mount: String,
+    /// Size of the filesystem in bytes.
+    pub size: u64,
+    /// Bytes available to unprivileged users.
+    pub available: u64,
+}
+
+/// Parse `df -Pk` output, one entry per argument in order.
+#[must_use]
+pub fn parse_df(output: &str) -> Vec<DiskFree> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let size: u64 = fields.get(1)?.parse().ok()?;
+            let available: u64 = fields.get(3)?.parse().ok()?;
+            let mount = fields.get(5..).filter(|m| !m.is_empty())?.join(" ");
+            Some(DiskFree {
+                mount,
+                size: size * 1024,
+                available: available * 1024,
+            })
+        })
+        .collect()
+}
+
+/// Check that each `(location, bytes)` need fits in the matching
+/// `free` entry. Needs on the same filesystem add up.
+pub fn check(needs: &[(&str, u64)], free: &[DiskFree]) -> DeployResult<()> {
+    let mut by_mount: Vec<(&DiskFree, Vec<&str>, u64)> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for ((location, bytes), disk) in needs.iter().zip(free) {
+        if let Some(&i) = index.get(disk.mount.as_str()) {
+            by_mount[i].1.push(location);
+            by_mount[i].2 += bytes;
+        } else {
+            index.insert(&disk.mount, by_mount.len());
+            by_mount.push((disk, vec![location], *bytes));
+        }
+    }
+
+    for (disk, locations, needed) in by_mount {
+        if needed > disk.available {
+            return Err(DeployError::InsufficientDiskSpace {
+                location: locations.join(" and "),
+                needed,
+                available: disk.available,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check that the local temp directory can hold a `tarball`
+/// bytes image archive.
+pub fn check_local(tarball: u64) -> DeployResult<()> {
+    let temp = std::env::temp_dir();
+    let temp = temp.to_string_lossy();
+    let Ok(output) = cmd::run("df", &["-Pk", &temp]) else {
+        return Ok(());
+    };
+    let location = format!("local {temp}");
+    check(&[(&location, tarball)], &parse_df(&output))
+}
+
+/// Check that the server can load an `image` bytes image, and
+/// hold its archive in `/tmp` first unless it is streamed.
+pub fn check_remote(ssh: &SshSession, image: u64, streamed: bool) -> DeployResult<()> {
+    let data_dir = "\"$(docker info -f '{{.DockerRootDir}}' 2>/dev/null \
+                    || podman info -f '{{.Store.GraphRoot}}' 2>/dev/null \
+                    || echo /var/lib/docker)\"";
+    let (paths, needs) = if streamed {
+        (
+            data_dir.to_string(),
+            vec![("remote Docker data dir", image)],
+        )
+    } else {
+        (
+            format!("/tmp {data_dir}"),
+            vec![("remote /tmp", image), ("remote Docker data dir", image)],
+        )
+    };
+    let Ok(output) = ssh.exec(&format!("df -Pk {paths}")) else {
+        return Ok(());
+    };
+    check(&needs, &parse_df(&output))
+}
+
+/// What [`check_host`] found on the server. `None` when the
+/// probe output didn't say.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostSetup {
+    /// Engine probed for.
+    pub runtime: Runtime,
+    /// Whether the engine's CLI (`docker`, `podman`) is
+    /// installed.
+    pub docker: Option<bool>,
+    /// Whether the Docker daemon, or `podman info`, answers.
+    pub daemon: Option<bool>,
+    /// `docker compose version --short` (`podman-compose`'s),
+    /// empty without compose.
+    pub compose: Option<String>,
+    /// Whether the remote directory exists, or could be
+    /// created, and is writable.
+    pub remote_dir: Option<bool>,
+    /// `uname -m` of the server (e.g. `aarch64`).
+    pub machine: Option<String>,
+}
+
+/// Minimum major version of the Docker compose plugin.
+const COMPOSE_MAJOR: u32 = 2;
+
+/// Remote command probing what [`parse_host_setup`] reads, for
+/// the `runtime` engine.
+#[must_use]
+pub fn host_setup_command(remote_dir: &str, runtime: Runtime) -> String {
+    let dir = shell_quote(remote_dir);
+    let cli = runtime.cli();
+    let mut probes = vec![
+        format!("echo docker=$(command -v {cli} >/dev/null 2>&1 && echo yes || echo no)"),
+        format!("echo daemon=$({cli} info >/dev/null 2>&1 && echo yes || echo no)"),
+        format!(
+            "echo compose=$({} version --short 2>/dev/null)",
+            runtime.compose()
+        ),
+        format!(
+            "echo dir=$(mkdir -p {dir} 2>/dev/null; test -d {dir} && test -w {dir} && echo yes || echo no)"
+        ),
+        "echo machine=$(uname -m)".to_string(),
+    ];
+    if runtime == Runtime::Podman {
+        probes.insert(0, "echo runtime=podman".to_string());
+    }
+    probes.join("; ")
+}
+
+/// Parse the output of [`host_setup_command`].
+#[must_use]
+pub fn parse_host_setup(output: &str) -> HostSetup {
+    let mut setup = HostSetup::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let yes = Some(value == "yes");
+        match key {
+            "runtime" if value == "podman" => setup.runtime = Runtime::Podman,
+            "docker" => setup.docker = yes,
+            "daemon" => setup.daemon = yes,
+            "compose" => setup.compose = Some(value.to_string()),
+            "dir" => setup.remote_dir = yes,
+            "machine" if !value.is_empty() => setup.machine = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    setup
+}
+
+impl HostSetup {
+    /// The first thing missing for a deploy, if any.
+    #[must_use]
+    pub fn problem(&self, remote_dir: &str) -> Option<String> {
+        let podman = self.runtime == Runtime::Podman;
+        if self.docker == Some(false) {
+            return Some(format!("{} is not installed", self.runtime.name()));
+        }
+        if self.daemon == Some(false) {
+            return Some(if podman {
+                "Podman does not answer (`podman info` fails)".to_string()
+            } else {
+                "the Docker daemon is not running".to_string()
+            });
+        }
+        if let Some(version) = &self.compose {
+            if version.is_empty() {
+                return Some(if podman {
+                    "podman-compose is not installed".to_string()
+                } else {
+                    "the Docker compose plugin is not installed".to_string()
+                });
+            }
+            let major = version
+                .trim_start_matches('v')
+                .split('.')
+                .next()
+                .and_then(|m| m.parse::<u32>().ok());
+            // podman-compose has versions of its own
+            if !podman && major.is_some_and(|m| m < COMPOSE_MAJOR) {
+                return Some(format!(
+                    "Docker compose {version} is too old, {COMPOSE_MAJOR}.0 or newer is needed"
+                ));
+            }
+        }
+        if self.remote_dir == Some(false) {
+            return Some(format!("{remote_dir} does not exist or is not writable"));
+        }
+        None
+    }
+}
+
+/// Check that the server behind `ssh` is ready for a deploy into
+/// `remote_dir`: the `runtime` engine installed and running, with
+/// compose, and the remote directory in place.
+///
+/// Returns what was found, for further checks such as
+/// [`check_platforms`].
+pub fn check_host(ssh: &SshSession, remote_dir: &str, runtime: Runtime) -> DeployResult<HostSetup> {
+    let Ok(output) = ssh.exec(&host_setup_command(remote_dir, runtime)) else {
+        return Ok(HostSetup::default());
+    };
+    let setup = parse_host_setup(&output);
+    setup.problem(remote_dir).map_or(Ok(setup), |problem| {
+        Err(DeployError::HostNotPrepared(problem))
+    })
+}
+
+/// Check that the images of `apps` run on a server whose
+/// `uname -m` is `machine`.
+pub fn check_platforms(apps: &[&App], machine: &str) -> DeployResult<()> {
+    let Some(app) = apps.iter().find(|app| !app.runs_on(machine)) else {
+        return Ok(());
+    };
+    let platform = if app.platforms.is_empty() {
+        app.platform.clone()
+    } else {
+        app.platforms.join(", ")
+    };
+    Err(DeployError::PlatformMismatch {
+        app: app.name.clone(),
+        platform,
+        machine: machine_platform(machine).map_or_else(
+            || machine.to_string(),
+            |p| format!("{} ({p})", machine.trim()),
+        ),
+    })
+}
+
+/// Command listing the server's containers for
+/// [`parse_containers`].
+pub const CONTAINERS_COMMAND: &str = "docker ps -a --format \
+     '{{.Names}}|{{.Image}}|{{.Label \"com.docker.compose.project\"}}|\
+     {{.Label \"com.docker.compose.project.working_dir\"}}|{{.Ports}}'";
+
+/// A container found on the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Container {
+    pub name: String,
+    pub image: String,
+    /// Compose project, empty for containers started otherwise.
+    pub project: String,
+    /// Directory of the compose project.
+    pub working_dir: String,
+    /// Published host ports.
+    pub ports: Vec<u16>,
+}
+
+/// Parse the output of [`CONTAINERS_COMMAND`].
+#[must_use]
+pub fn parse_containers(output: &str) -> Vec<Container> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(5, '|').collect();
+            let [name, image, project, working_dir, ports] = fields[..] else {
+                return None;
+            };
+            // e.g. "0.0.0.0:80->80/tcp, [::]:80->80/tcp"
+            let mut host_ports: Vec<u16> = ports
+                .split(", ")
+                .filter_map(|p| p.split_once("->")?.0.rsplit(':').next()?.parse().ok())
+                .collect();
+            host_ports.dedup();
+            Some(Container {
+                name: name.to_string(),
+                image: image.to_string(),
+                project: project.to_string(),
+                working_dir: working_dir.to_string(),
+                ports: host_ports,
+            })
+        })
+        .collect()
+}
+
+/// What a stack takes on the server, to compare with the
+/// containers of other stacks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Footprint {
+    /// Compose project name.
+    pub project: String,
+    pub containers: Vec<String>,
+    /// Images built locally, shared by name on the server.
+    pub images: Vec<String>,
+    /// Published host ports.
+    pub ports: Vec<u16>,
+}
+
+impl Footprint {
+    /// The footprint of `apps` and `caddy` deployed to
+    /// `remote_dir`, for the current [`compose::project`].
+    #[must_use]
+    pub fn new(apps: &[App], caddy: &Caddy, remote_dir: &str) -> Self {
+        let project = compose::project().unwrap_or_else(|| default_project(remote_dir));
+        let mut containers: Vec<String> = apps
+            .iter()
+            .filter(|a| a.replicas == 1)
+            .map(|a| compose::container_name(&a.name))
+            .collect();
+        let mut ports: Vec<u16> = apps
+            .iter()
+            .flat_map(|a| a.ports.iter().map(|(host, _)| *host))
+            .collect();
+        if compose::needs_caddy(apps, caddy) {
+            containers.push(format!("{}-caddy", compose::stack_name(apps)));
+            ports.extend([80, 443]);
+        }
+        ports.extend(caddy.streams.iter().map(|s| s.port));
+        ports.sort_unstable();
+        ports.dedup();
+        let images = apps
+            .iter()
+            .filter(|a| a.image.is_none())
+            .map(|a| format!("{}:latest", a.name))
+            .collect();
+        Self {
+            project,
+            containers,
+            images,
+            ports,
+        }
+    }
+
+    /// How the footprint collides with `containers` not deployed
+    /// from `remote_dir`.
+    #[must_use]
+    pub fn collisions(&self, containers: &[Container], remote_dir: &str) -> Vec<String> {
+        let remote_dir = remote_dir.trim_end_matches('/');
+        let mut found = Vec::new();
+        let mut report = |problem: String| {
+            if !found.contains(&problem) {
+                found.push(problem);
+            }
+        };
+        for c in containers {
+            if c.working_dir.trim_end_matches('/') == remote_dir {
+                continue;
+            }
+            let owner = if c.working_dir.is_empty() {
+                format!("container {}", c.name)
+            } else {
+                format!("the stack in {}", c.working_dir)
+            };
+            if self.containers.contains(&c.name) {
+                report(format!("container name {} is taken by {owner}", c.name));
+            }
+            if !c.project.is_empty() && c.project == self.project {
+                report(format!("compose project {} is used by {owner}", c.project));
+            }
+            if self.images.contains(&c.image) {
+                report(format!("image {} is used by {owner}", c.image));
+            }
+            for port in c.ports.iter().filter(|p| self.ports.contains(p)) {
+                report(format!("port {port} is published by {owner}"));
+            }
+        }
+        found
+    }
+}
+
+/// The compose project of a stack in `remote_dir` without an
+/// explicit one: its directory name, as `docker compose`
+/// normalizes it.
+#[must_use]
+pub fn default_project(remote_dir: &str) -> String {
+    let name = remote_dir
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    name.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+/// Check that deploying `apps` and `caddy` to `remote_dir` takes
+/// nothing used by another stack on the server.
+pub fn check_collisions(
+    ssh: &SshSession,
+    apps: &[App],
+    caddy: &Caddy,
+    remote_dir: &str,
+) -> DeployResult<()> {
+    let Ok(output) = ssh.exec(CONTAINERS_COMMAND) else {
+        return Ok(());
+    };
+    let collisions =
+        Footprint::new(apps, caddy, remote_dir).collisions(&parse_containers(&output), remote_dir);
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(DeployError::StackCollision(collisions.join("; ")))
+    }
+}
+
+/// Name of the copy of the new compose file checked by
+/// [`check_compose`], next to the deployed one so relative paths
+/// resolve the same.
+pub const COMPOSE_CHECK_FILE: &str = ".docker-compose.check.yml";
+
+/// Command running `docker compose config` on the
+/// [`COMPOSE_CHECK_FILE`] in `remote_dir` and removing it,
+/// printing the errors followed by a `status=` line.
+#[must_use]
+pub fn compose_check_command(remote_dir: &str) -> String {
+    format!(
+        "cd {} && out=$(docker compose -f {COMPOSE_CHECK_FILE} config -q 2>&1); \
+         status=$?; rm -f {COMPOSE_CHECK_FILE}; printf '%s\\nstatus=%s\\n' \"$out\" \"$status\"",
+        shell_quote(remote_dir)
+    )
+}
+
+/// The errors `docker compose config` reported in the output of
+/// [`compose_check_command`]; `None` when the file is valid or
+/// the output can't be read.
+#[must_use]
+pub fn parse_compose_check(output: &str) -> Option<String> {
+    let output = output.trim_end();
+    let (errors, last) = output.rsplit_once('\n').unwrap_or(("", output));
+    let status: i32 = last.strip_prefix("status=")?.parse().ok()?;
+    (status != 0).then(|| errors.trim().to_string())
+}
+
+/// Check with `docker compose config` on the server that the
+/// compose file of `apps` is valid, before the running stack is
+/// stopped for it.
+///
+/// Env files are left out of the checked file: the deploy only
+/// uploads them later, so new ones don't exist yet.
+pub fn check_compose(
+    ssh: &SshSession,
+    apps: &[App],
+    caddy: &Caddy,
+    remote_dir: &str,
+) -> DeployResult<()> {
+    let apps: Vec<App> = apps
+        .iter()
+        .cloned()
+        .map(|mut app| {
+            app.env_file = None;
+            app
+        })
+        .collect();
+    ssh.write_remote_file_with(
+        &compose::render(&apps, caddy),
+        &format!("{remote_dir}/{COMPOSE_CHECK_FILE}"),
+        &compose::file_attrs(&apps, caddy),
+    )?;
+    let Ok(output) = ssh.exec(&compose_check_command(remote_dir)) else {
+        return Ok(());
+    };
+    parse_compose_check(&output).map_or(Ok(()), |errors| {
+        Err(DeployError::InvalidConfig(format!(
+            "generated files are invalid: docker compose on {} rejects \
+             docker-compose.yml, the running stack was left alone: {errors}",
+            ssh.host()
+        )))
+    })
+}
@@ -0,0 +1,121 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Value, json};
+
+use crate::audit;
+use crate::dns::{self, DnsProvider};
+use crate::error::{DeployError, DeployResult};
+
+/// `AdGuard` Home local DNS provider, managing DNS rewrites through
+/// its API.
+///
+/// For home-lab deployments resolved by `AdGuard` Home rather than
+/// a public zone. Requires `ADGUARD_USER` and `ADGUARD_PASSWORD`
+/// set with the credentials of the web interface.
+pub struct AdGuardHome {
+    /// Base URL of `AdGuard` Home, e.g. `http://192.168.1.2:3000`.
+    pub url: String,
+    /// The fully-qualified domain name to manage.
+    pub domain: String,
+}
+
+impl AdGuardHome {
+    #[must_use]
+    pub fn new(url: &str, domain: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            domain: domain.to_string(),
+        }
+    }
+
+    /// The `Authorization` header for the credentials.
+    fn auth_header() -> DeployResult<String> {
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| {
+                DeployError::EnvMissing(format!(
+                    "{name} not set. Use the AdGuard Home web interface credentials"
+                ))
+            })
+        };
+        let credentials = STANDARD.encode(format!(
+            "{}:{}",
+            var("ADGUARD_USER")?,
+            var("ADGUARD_PASSWORD")?
+        ));
+        audit::register_secret(&credentials);
+        Ok(format!("Authorization: Basic {credentials}"))
+    }
+
+    fn request(&self, method: &str, path: &str, body: Option<&str>) -> DeployResult<String> {
+        dns::http_request(
+            method,
+            &format!("{}/control/{path}", self.url),
+            &[Self::auth_header()?],
+            body,
+        )
+    }
+
+    /// The answers of the rewrites of this domain.
+    fn answers(&self) -> DeployResult<Vec<String>> {
+        rewrite_answers(&self.request("GET", "rewrite/list", None)?, &self.domain)
+    }
+
+    fn rewrite(&self, action: &str, answer: &str) -> DeployResult<()> {
+        let body = json!({ "domain": self.domain, "answer": answer }).to_string();
+        self.request("POST", &format!("rewrite/{action}"), Some(&body))?;
+        Ok(())
+    }
+}
+
+impl DnsProvider for AdGuardHome {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn for_domain(&self, domain: &str) -> Option<Box<dyn DnsProvider>> {
+        Some(Box::new(Self::new(&self.url, domain)))
+    }
+
+    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+        eprintln!("AdGuard Home DNS: {} -> {ip}", self.domain);
+        let answers = self.answers()?;
+        for answer in answers.iter().filter(|a| *a != ip) {
+            eprintln!("  Removing rewrite to {answer}...");
+            self.rewrite("delete", answer)?;
+        }
+        if !answers.iter().any(|a| a == ip) {
+            eprintln!("  Adding rewrite...");
+            self.rewrite("add", ip)?;
+        }
+        eprintln!("DNS record set: {} -> {ip}", self.domain);
+        Ok(())
+    }
+
+    fn delete_a_record(&self) -> DeployResult<()> {
+        for answer in self.answers()? {
+            eprintln!("  Removing rewrite to {answer}...");
+            self.rewrite("delete", &answer)?;
+        }
+        eprintln!("DNS record deleted: {}", self.domain);
+        Ok(())
+    }
+
+    fn check_access(&self) -> DeployResult<()> {
+        self.answers().map(drop)
+    }
+}
+
+/// The answers of the rewrites of `domain` in the response of
+/// `GET /control/rewrite/list`.
+pub fn rewrite_answers(response: &str, domain: &str) -> DeployResult<Vec<String>> {
+    let rewrites: Value = serde_json::from_str(response)?;
+    let rewrites = rewrites.as_array().ok_or_else(|| {
+        DeployError::DnsError(format!("unexpected AdGuard Home response: {response}"))
+    })?;
+    Ok(rewrites
+        .iter()
+        .filter(|r| r["domain"] == domain)
+        .filter_map(|r| r["answer"].as_str())
+        .map(str::to_string)
+        .collect())
+}
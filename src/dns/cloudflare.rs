@@ -1,4 +1,7 @@
-use std::net::Ipv4Addr;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
 
 use cloudflare::endpoints::dns::dns::{
     CreateDnsRecord, CreateDnsRecordParams, DeleteDnsRecord, DnsContent, ListDnsRecords,
@@ -9,16 +12,154 @@ use cloudflare::framework::Environment;
 use cloudflare::framework::auth::Credentials;
 use cloudflare::framework::client::ClientConfig;
 use cloudflare::framework::client::async_api::Client;
+use cloudflare::framework::endpoint::spec::EndpointSpec;
+use cloudflare::framework::endpoint::{Method, RequestBody};
+use cloudflare::framework::response::{ApiResult, ApiSuccess};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
 
 use crate::dns::{self, DnsProvider};
 use crate::error::{DeployError, DeployResult};
 
+/// Environment variable holding the Cloudflare API token.
+pub const TOKEN_ENV: &str = "CF_API_TOKEN";
+
 /// Cloudflare DNS provider using the official cloudflare crate.
 ///
 /// Requires `CF_API_TOKEN` environment variable set with a token
 /// that has `Zone > DNS > Edit` permissions.
+///
+/// The API client is created on first use and shared with the
+/// providers derived with [`DnsProvider::for_domain`], along with
+/// the zone ids looked up so far. The `*_async` methods are the
+/// same operations for callers already running on a runtime; the
+/// [`DnsProvider`] methods run them on a runtime of their own.
 pub struct Cloudflare {
     domain: String,
+    /// TTL of the records, in seconds; `1` is Cloudflare's
+    /// automatic TTL.
+    pub ttl: u32,
+    /// Whether traffic to the records goes through Cloudflare's
+    /// proxy.
+    pub proxied: bool,
+    session: Arc<Mutex<Option<Arc<Session>>>>,
+}
+
+/// API client shared by a provider and those derived from it.
+struct Session {
+    client: Client,
+    /// Runtime for the blocking [`DnsProvider`] methods, only
+    /// created when one is called.
+    runtime: OnceLock<Runtime>,
+    /// Zone ids by zone name.
+    zones: Mutex<HashMap<String, String>>,
+}
+
+impl Session {
+    fn block_on<F: Future>(&self, f: F) -> DeployResult<F::Output> {
+        let runtime = if let Some(runtime) = self.runtime.get() {
+            runtime
+        } else {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| DeployError::DnsError(e.to_string()))?;
+            self.runtime.get_or_init(|| runtime)
+        };
+        Ok(runtime.block_on(f))
+    }
+
+    async fn zone_id(&self, zone: &str) -> DeployResult<String> {
+        if let Some(id) = lock(&self.zones).get(zone) {
+            return Ok(id.clone());
+        }
+        let response = self
+            .client
+            .request(&ListZones {
+                params: ListZonesParams {
+                    name: Some(zone.to_string()),
+                    ..ListZonesParams::default()
+                },
+            })
+            .await
+            .map_err(|e| DeployError::DnsError(e.to_string()))?;
+
+        let id = response
+            .result
+            .first()
+            .map(|z| z.id.clone())
+            .ok_or_else(|| DeployError::DnsError(format!("zone '{zone}' not found")))?;
+        lock(&self.zones).insert(zone.to_string(), id.clone());
+        Ok(id)
+    }
+
+    /// The id of a record of `domain` with the type of
+    /// `record_type`.
+    async fn find_existing_record(
+        &self,
+        zone_id: &str,
+        domain: &str,
+        record_type: DnsContent,
+    ) -> DeployResult<Option<String>> {
+        let response = self
+            .client
+            .request(&ListDnsRecords {
+                zone_identifier: zone_id,
+                params: ListDnsRecordsParams {
+                    name: Some(domain.to_string()),
+                    record_type: Some(record_type),
+                    ..ListDnsRecordsParams::default()
+                },
+            })
+            .await
+            .map_err(|e| DeployError::DnsError(e.to_string()))?;
+
+        Ok(response.result.first().map(|r| r.id.clone()))
+    }
+}
+
+/// Purge cached content of a zone, which the cloudflare crate
+/// has no endpoint for.
+/// <https://developers.cloudflare.com/api/resources/cache/methods/purge/>
+struct PurgeCache<'a> {
+    zone_identifier: &'a str,
+    params: PurgeCacheParams,
+}
+
+#[derive(Serialize)]
+struct PurgeCacheParams {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    purge_everything: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeCacheResponse {}
+
+impl ApiResult for PurgeCacheResponse {}
+
+impl EndpointSpec for PurgeCache<'_> {
+    type JsonResponse = PurgeCacheResponse;
+    type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!("zones/{}/purge_cache", self.zone_identifier)
+    }
+
+    fn body(&self) -> Option<RequestBody<'_>> {
+        serde_json::to_string(&self.params)
+            .ok()
+            .map(RequestBody::Json)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 impl Cloudflare {
@@ -26,11 +167,32 @@ impl Cloudflare {
     pub fn new(domain: &str) -> Self {
         Self {
             domain: domain.to_string(),
+            ttl: dns::DEFAULT_TTL,
+            proxied: false,
+            session: Arc::default(),
         }
     }
 
+    /// Set the TTL of the records, in seconds (default 300); `1`
+    /// lets Cloudflare pick it. Proxied records always use the
+    /// automatic TTL.
+    #[must_use]
+    pub const fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Route traffic to the records through Cloudflare's proxy,
+    /// which hides the server IP and serves the domain from
+    /// Cloudflare's edge.
+    #[must_use]
+    pub const fn proxied(mut self, proxied: bool) -> Self {
+        self.proxied = proxied;
+        self
+    }
+
     fn token() -> DeployResult<String> {
-        std::env::var("CF_API_TOKEN").map_err(|_| {
+        std::env::var(TOKEN_ENV).map_err(|_| {
             DeployError::EnvMissing(
                 "CF_API_TOKEN not set. Create a token at: \
                  https://dash.cloudflare.com/profile/api-tokens"
@@ -50,59 +212,66 @@ impl Cloudflare {
         .map_err(|e| DeployError::DnsError(e.to_string()))
     }
 
-    fn block_on<F: std::future::Future>(f: F) -> DeployResult<F::Output> {
-        tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .map_err(|e| DeployError::DnsError(e.to_string()))
-            .map(|rt| rt.block_on(f))
+    /// The shared session, created with the token on first use.
+    fn session(&self) -> DeployResult<Arc<Session>> {
+        let mut session = lock(&self.session);
+        if let Some(session) = session.as_ref() {
+            return Ok(Arc::clone(session));
+        }
+        let created = Arc::new(Session {
+            client: Self::client(&Self::token()?)?,
+            runtime: OnceLock::new(),
+            zones: Mutex::default(),
+        });
+        *session = Some(Arc::clone(&created));
+        drop(session);
+        Ok(created)
     }
 
-    fn get_zone_id(client: &Client, zone: &str) -> DeployResult<String> {
-        let response = Self::block_on(client.request(&ListZones {
-            params: ListZonesParams {
-                name: Some(zone.to_string()),
-                ..ListZonesParams::default()
-            },
-        }))?
-        .map_err(|e| DeployError::DnsError(e.to_string()))?;
+    /// [`DnsProvider::upsert_a_record`], without blocking.
+    pub async fn upsert_a_record_async(&self, ip: &str) -> DeployResult<()> {
+        let ip_addr: Ipv4Addr = ip
+            .parse()
+            .map_err(|e| DeployError::DnsError(format!("invalid IP: {e}")))?;
+        self.upsert_record("A", ip, DnsContent::A { content: ip_addr })
+            .await
+    }
 
-        response
-            .result
-            .first()
-            .map(|z| z.id.clone())
-            .ok_or_else(|| DeployError::DnsError(format!("zone '{zone}' not found")))
+    /// [`DnsProvider::upsert_aaaa_record`], without blocking.
+    pub async fn upsert_aaaa_record_async(&self, ip: &str) -> DeployResult<()> {
+        let ip_addr: Ipv6Addr = ip
+            .parse()
+            .map_err(|e| DeployError::DnsError(format!("invalid IPv6 address: {e}")))?;
+        self.upsert_record("AAAA", ip, DnsContent::AAAA { content: ip_addr })
+            .await
     }
 
-    fn find_existing_record(
-        client: &Client,
-        zone_id: &str,
-        domain: &str,
-    ) -> DeployResult<Option<String>> {
-        let response = Self::block_on(client.request(&ListDnsRecords {
-            zone_identifier: zone_id,
-            params: ListDnsRecordsParams {
-                name: Some(domain.to_string()),
-                record_type: Some(DnsContent::A {
-                    content: Ipv4Addr::UNSPECIFIED,
-                }),
-                ..ListDnsRecordsParams::default()
+    /// [`DnsProvider::delete_a_record`], without blocking.
+    pub async fn delete_a_record_async(&self) -> DeployResult<()> {
+        self.delete_record(
+            "A",
+            DnsContent::A {
+                content: Ipv4Addr::UNSPECIFIED,
             },
-        }))?
-        .map_err(|e| DeployError::DnsError(e.to_string()))?;
-
-        Ok(response.result.first().map(|r| r.id.clone()))
+        )
+        .await
     }
-}
 
-impl DnsProvider for Cloudflare {
-    fn domain(&self) -> &str {
-        &self.domain
+    /// [`DnsProvider::delete_aaaa_record`], without blocking.
+    pub async fn delete_aaaa_record_async(&self) -> DeployResult<()> {
+        self.delete_record(
+            "AAAA",
+            DnsContent::AAAA {
+                content: Ipv6Addr::UNSPECIFIED,
+            },
+        )
+        .await
     }
 
-    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
-        let token = Self::token()?;
-        let client = Self::client(&token)?;
+    /// Create or update the `kind` record of the domain, set to
+    /// `content`.
+    async fn upsert_record(&self, kind: &str, ip: &str, content: DnsContent) -> DeployResult<()> {
+        let session = self.session()?;
         let (zone, subdomain) = dns::split_domain(&self.domain);
 
         eprintln!("Cloudflare DNS: {} -> {ip}", self.domain);
@@ -116,65 +285,199 @@ impl DnsProvider for Cloudflare {
             }
         );
 
-        let ip_addr: Ipv4Addr = ip
-            .parse()
-            .map_err(|e| DeployError::DnsError(format!("invalid IP: {e}")))?;
-
-        let zone_id = Self::get_zone_id(&client, &zone)?;
-        let existing = Self::find_existing_record(&client, &zone_id, &self.domain)?;
+        let zone_id = session.zone_id(&zone).await?;
+        let existing = session
+            .find_existing_record(&zone_id, &self.domain, content.clone())
+            .await?;
 
         if let Some(record_id) = existing {
-            eprintln!("  Updating existing A record...");
-            Self::block_on(client.request(&UpdateDnsRecord {
-                zone_identifier: &zone_id,
-                identifier: &record_id,
-                params: UpdateDnsRecordParams {
-                    ttl: Some(300),
-                    proxied: Some(false),
-                    name: &self.domain,
-                    content: DnsContent::A { content: ip_addr },
-                },
-            }))?
-            .map_err(|e| DeployError::DnsError(e.to_string()))?;
+            eprintln!("  Updating existing {kind} record...");
+            session
+                .client
+                .request(&UpdateDnsRecord {
+                    zone_identifier: &zone_id,
+                    identifier: &record_id,
+                    params: UpdateDnsRecordParams {
+                        ttl: Some(self.ttl),
+                        proxied: Some(self.proxied),
+                        name: &self.domain,
+                        content,
+                    },
+                })
+                .await
+                .map_err(|e| DeployError::DnsError(e.to_string()))?;
         } else {
-            eprintln!("  Creating new A record...");
-            Self::block_on(client.request(&CreateDnsRecord {
-                zone_identifier: &zone_id,
-                params: CreateDnsRecordParams {
-                    ttl: Some(300),
-                    priority: None,
-                    proxied: Some(false),
-                    name: &self.domain,
-                    content: DnsContent::A { content: ip_addr },
-                },
-            }))?
-            .map_err(|e| DeployError::DnsError(e.to_string()))?;
+            eprintln!("  Creating new {kind} record...");
+            session
+                .client
+                .request(&CreateDnsRecord {
+                    zone_identifier: &zone_id,
+                    params: CreateDnsRecordParams {
+                        ttl: Some(self.ttl),
+                        priority: None,
+                        proxied: Some(self.proxied),
+                        name: &self.domain,
+                        content,
+                    },
+                })
+                .await
+                .map_err(|e| DeployError::DnsError(e.to_string()))?;
         }
 
         eprintln!("DNS record set: {} -> {ip}", self.domain);
         Ok(())
     }
 
-    fn delete_a_record(&self) -> DeployResult<()> {
-        let token = Self::token()?;
-        let client = Self::client(&token)?;
+    /// Delete the `kind` record of the domain, of the type of
+    /// `record_type`.
+    async fn delete_record(&self, kind: &str, record_type: DnsContent) -> DeployResult<()> {
+        let session = self.session()?;
         let (zone, _) = dns::split_domain(&self.domain);
 
-        let zone_id = Self::get_zone_id(&client, &zone)?;
-        let existing = Self::find_existing_record(&client, &zone_id, &self.domain)?;
+        let zone_id = session.zone_id(&zone).await?;
+        let existing = session
+            .find_existing_record(&zone_id, &self.domain, record_type)
+            .await?;
 
         if let Some(record_id) = existing {
-            eprintln!("  Deleting A record...");
-            Self::block_on(client.request(&DeleteDnsRecord {
-                zone_identifier: &zone_id,
-                identifier: &record_id,
-            }))?
-            .map_err(|e| DeployError::DnsError(e.to_string()))?;
+            eprintln!("  Deleting {kind} record...");
+            session
+                .client
+                .request(&DeleteDnsRecord {
+                    zone_identifier: &zone_id,
+                    identifier: &record_id,
+                })
+                .await
+                .map_err(|e| DeployError::DnsError(e.to_string()))?;
             eprintln!("DNS record deleted: {}", self.domain);
         } else {
-            eprintln!("No A record found for {}", self.domain);
+            eprintln!("No {kind} record found for {}", self.domain);
         }
 
         Ok(())
     }
+
+    /// [`DnsProvider::check_access`], without blocking.
+    pub async fn check_access_async(&self) -> DeployResult<()> {
+        let session = self.session()?;
+        let (zone, _) = dns::split_domain(&self.domain);
+        let probe = format!(
+            "{}.{}",
+            dns::PROBE_RECORD,
+            self.domain.trim_start_matches("*.")
+        );
+
+        let zone_id = session.zone_id(&zone).await?;
+        session
+            .find_existing_record(
+                &zone_id,
+                &self.domain,
+                DnsContent::A {
+                    content: Ipv4Addr::UNSPECIFIED,
+                },
+            )
+            .await?;
+        let record = session
+            .client
+            .request(&CreateDnsRecord {
+                zone_identifier: &zone_id,
+                params: CreateDnsRecordParams {
+                    ttl: Some(60),
+                    priority: None,
+                    proxied: None,
+                    name: &probe,
+                    content: DnsContent::TXT {
+                        content: dns::PROBE_CONTENT.to_string(),
+                    },
+                },
+            })
+            .await
+            .map_err(|e| {
+                DeployError::DnsError(format!(
+                    "CF_API_TOKEN can't edit records of {zone} ({e}); \
+                     it needs Zone > DNS > Edit"
+                ))
+            })?;
+        session
+            .client
+            .request(&DeleteDnsRecord {
+                zone_identifier: &zone_id,
+                identifier: &record.result.id,
+            })
+            .await
+            .map_err(|e| DeployError::DnsError(format!("deleting {probe}: {e}")))?;
+        Ok(())
+    }
+
+    /// [`DnsProvider::purge_cache`], without blocking. Paths are
+    /// purged as `https://<domain><path>` URLs.
+    pub async fn purge_cache_async(&self, paths: &[String]) -> DeployResult<()> {
+        let session = self.session()?;
+        let (zone, _) = dns::split_domain(&self.domain);
+
+        let zone_id = session.zone_id(&zone).await?;
+        session
+            .client
+            .request(&PurgeCache {
+                zone_identifier: &zone_id,
+                params: PurgeCacheParams {
+                    files: paths
+                        .iter()
+                        .map(|p| format!("https://{}{p}", self.domain))
+                        .collect(),
+                    purge_everything: paths.is_empty(),
+                },
+            })
+            .await
+            .map_err(|e| DeployError::DnsError(format!("purging the cache of {zone}: {e}")))?;
+        Ok(())
+    }
+}
+
+impl DnsProvider for Cloudflare {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn for_domain(&self, domain: &str) -> Option<Box<dyn DnsProvider>> {
+        Some(Box::new(Self {
+            domain: domain.to_string(),
+            ttl: self.ttl,
+            proxied: self.proxied,
+            session: Arc::clone(&self.session),
+        }))
+    }
+
+    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+        self.session()?.block_on(self.upsert_a_record_async(ip))?
+    }
+
+    fn delete_a_record(&self) -> DeployResult<()> {
+        self.session()?.block_on(self.delete_a_record_async())?
+    }
+
+    fn upsert_aaaa_record(&self, ip: &str) -> DeployResult<()> {
+        self.session()?
+            .block_on(self.upsert_aaaa_record_async(ip))?
+    }
+
+    fn delete_aaaa_record(&self) -> DeployResult<()> {
+        self.session()?.block_on(self.delete_aaaa_record_async())?
+    }
+
+    fn manages_aaaa_records(&self) -> bool {
+        true
+    }
+
+    fn check_access(&self) -> DeployResult<()> {
+        self.session()?.block_on(self.check_access_async())?
+    }
+
+    fn purge_cache(&self, paths: &[String]) -> DeployResult<()> {
+        self.session()?.block_on(self.purge_cache_async(paths))?
+    }
+
+    fn caddy_module(&self) -> Option<(&str, &str)> {
+        Some(("cloudflare", TOKEN_ENV))
+    }
 }
@@ -0,0 +1,105 @@
+use crate::audit;
+use crate::dns::{self, DnsProvider};
+use crate::error::{DeployError, DeployResult};
+
+/// Environment variable holding the `DuckDNS` token.
+pub const TOKEN_ENV: &str = "DUCKDNS_TOKEN";
+
+/// `DuckDNS` dynamic DNS provider, pointing a `duckdns.org` name
+/// at a server through the update URL.
+///
+/// For home-lab deployments behind a residential IP. Requires
+/// `DUCKDNS_TOKEN` set with the token of the account. Subdomains
+/// of a `DuckDNS` name resolve to the same IP, so `api.home.duckdns.org`
+/// updates `home`.
+pub struct DuckDns {
+    /// The fully-qualified domain name to manage, under
+    /// `duckdns.org`.
+    pub domain: String,
+}
+
+impl DuckDns {
+    #[must_use]
+    pub fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+        }
+    }
+
+    fn token() -> DeployResult<String> {
+        let token = std::env::var(TOKEN_ENV).map_err(|_| {
+            DeployError::EnvMissing(
+                "DUCKDNS_TOKEN not set. Use the token shown on the duckdns.org account page".into(),
+            )
+        })?;
+        audit::register_secret(&token);
+        Ok(token)
+    }
+
+    fn name(&self) -> DeployResult<&str> {
+        update_name(&self.domain).ok_or_else(|| {
+            DeployError::InvalidConfig(format!("{} is not a duckdns.org name", self.domain))
+        })
+    }
+}
+
+impl DnsProvider for DuckDns {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn for_domain(&self, domain: &str) -> Option<Box<dyn DnsProvider>> {
+        update_name(domain).map(|_| Box::new(Self::new(domain)) as Box<dyn DnsProvider>)
+    }
+
+    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+        eprintln!("DuckDNS: {} -> {ip}", self.domain);
+        let url = update_url(self.name()?, &Self::token()?, ip);
+        let response = dns::http_request("GET", &url, &[], None)?;
+        if response.trim() != "OK" {
+            return Err(DeployError::DnsError(format!(
+                "DuckDNS refused the update of {}: {}",
+                self.domain,
+                response.trim()
+            )));
+        }
+        eprintln!("DNS record set: {} -> {ip}", self.domain);
+        Ok(())
+    }
+
+    /// A no-op: a `DuckDNS` name always points somewhere and is
+    /// released from the account page, not through the API.
+    fn delete_a_record(&self) -> DeployResult<()> {
+        eprintln!(
+            "DuckDNS: {} keeps its last IP, remove it on duckdns.org if unused",
+            self.domain
+        );
+        Ok(())
+    }
+
+    /// Checks the token is set and the domain is under
+    /// `duckdns.org`: the API has no call that doesn't change the
+    /// record.
+    fn check_access(&self) -> DeployResult<()> {
+        self.name()?;
+        Self::token().map(drop)
+    }
+
+    fn caddy_module(&self) -> Option<(&str, &str)> {
+        Some(("duckdns", TOKEN_ENV))
+    }
+}
+
+/// The `DuckDNS` name updated for `domain`, i.e. the label right
+/// before `duckdns.org`, or `None` for other domains.
+#[must_use]
+pub fn update_name(domain: &str) -> Option<&str> {
+    let rest = domain.trim_end_matches('.').strip_suffix(".duckdns.org")?;
+    rest.rsplit('.').next().filter(|name| !name.is_empty())
+}
+
+/// The update URL setting `name` to `ip`.
+#[must_use]
+pub fn update_url(name: &str, token: &str, ip: &str) -> String {
+    format!("https://www.duckdns.org/update?domains={name}&token={token}&ip={ip}")
+}
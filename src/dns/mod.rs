@@ -1,7 +1,44 @@
+pub mod adguard;
 pub mod cloudflare;
+pub mod duckdns;
 pub mod ovh;
+pub mod pihole;
 
-use crate::error::DeployResult;
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+
+/// Label of the TXT record [`DnsProvider::check_access`] creates
+/// and deletes under the managed domain.
+pub const PROBE_RECORD: &str = "_catapulta-check";
+
+/// TTL in seconds of the records set by providers that take
+/// one, unless configured otherwise.
+pub const DEFAULT_TTL: u32 = 300;
+
+/// Content of the probe TXT record.
+pub const PROBE_CONTENT: &str = "catapulta access check";
+
+/// Certificate authorities the CAA records of
+/// [`DnsPurpose::Caa`] providers allow: Let's Encrypt, and
+/// `ZeroSSL` (issuing through Sectigo), the two Caddy gets its
+/// certificates from.
+pub const CAA_ISSUERS: &[&str] = &["letsencrypt.org", "sectigo.com"];
+
+/// What a provider attached with [`crate::Pipeline::dns_for`]
+/// manages, for zones split across services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsPurpose {
+    /// The A and AAAA records pointing the domain at the server,
+    /// as with [`crate::Pipeline::dns`].
+    Records,
+    /// The TXT records of the ACME DNS-01 challenge, set by Caddy
+    /// itself through the provider's
+    /// [`caddy_module`](DnsProvider::caddy_module).
+    Challenge,
+    /// CAA records allowing only [`CAA_ISSUERS`] to issue
+    /// certificates for the domain, set by `provision`.
+    Caa,
+}
 
 /// A DNS provider that can create, update, and delete A records.
 pub trait DnsProvider {
@@ -13,6 +50,109 @@ pub trait DnsProvider {
 
     /// Delete the A record for this domain.
     fn delete_a_record(&self) -> DeployResult<()>;
+
+    /// Create or update an AAAA record pointing to `ip`, for
+    /// servers with an IPv6 address. Returns an error when the
+    /// provider doesn't manage AAAA records; see
+    /// [`manages_aaaa_records`](Self::manages_aaaa_records).
+    fn upsert_aaaa_record(&self, _ip: &str) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} doesn't manage AAAA records",
+            self.domain()
+        )))
+    }
+
+    /// Delete the AAAA record for this domain.
+    fn delete_aaaa_record(&self) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} doesn't manage AAAA records",
+            self.domain()
+        )))
+    }
+
+    /// Whether [`upsert_aaaa_record`](Self::upsert_aaaa_record)
+    /// is implemented, so an IPv6 address is only published
+    /// through providers that can.
+    fn manages_aaaa_records(&self) -> bool {
+        false
+    }
+
+    /// Create or update a CNAME record pointing to `target`,
+    /// for domains served by a host under its own name. Returns
+    /// an error when the provider doesn't manage CNAME records.
+    fn upsert_cname_record(&self, _target: &str) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} doesn't manage CNAME records",
+            self.domain()
+        )))
+    }
+
+    /// Delete the CNAME record for this domain.
+    fn delete_cname_record(&self) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} doesn't manage CNAME records",
+            self.domain()
+        )))
+    }
+
+    /// Check that the credentials can read and edit the zone of
+    /// [`domain`](Self::domain), by creating and deleting a TXT
+    /// record named [`PROBE_RECORD`] under it. Run before a
+    /// server is created, so missing permissions fail early.
+    fn check_access(&self) -> DeployResult<()> {
+        Ok(())
+    }
+
+    /// Purge the CDN cache of `paths` (e.g. `/assets/app.js`)
+    /// under [`domain`](Self::domain), or of the whole zone when
+    /// `paths` is empty. Providers that don't serve traffic
+    /// return an error.
+    fn purge_cache(&self, _paths: &[String]) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} has no cache to purge",
+            self.domain()
+        )))
+    }
+
+    /// Replace the CAA records of this domain with an `issue`
+    /// record for each CA of `issuers`. Returns an error when
+    /// the provider doesn't manage CAA records.
+    fn upsert_caa_records(&self, _issuers: &[&str]) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} doesn't manage CAA records",
+            self.domain()
+        )))
+    }
+
+    /// Delete the CAA records of this domain.
+    fn delete_caa_records(&self) -> DeployResult<()> {
+        Err(DeployError::InvalidConfig(format!(
+            "the DNS provider of {} doesn't manage CAA records",
+            self.domain()
+        )))
+    }
+
+    /// The Caddy DNS module solving the ACME DNS-01 challenge
+    /// with this provider, e.g. `cloudflare`, and the environment
+    /// variable holding its API token. `None` for providers
+    /// without one.
+    fn caddy_module(&self) -> Option<(&str, &str)> {
+        None
+    }
+
+    /// A provider with the same credentials managing `domain`
+    /// instead, used for apps with their own domain. Returns
+    /// `None` when the provider cannot manage other names.
+    fn for_domain(&self, _domain: &str) -> Option<Box<dyn DnsProvider>> {
+        None
+    }
+}
+
+/// Whether `fqdn` is a wildcard name such as `*.example.com`,
+/// covering every subdomain of `example.com`.
+#[must_use]
+pub fn is_wildcard(fqdn: &str) -> bool {
+    fqdn.starts_with("*.")
 }
 
 /// Split an FQDN into (zone, subdomain).
@@ -31,3 +171,20 @@ pub fn split_domain(fqdn: &str) -> (String, String) {
     let subdomain = parts[..parts.len() - 2].join(".");
     (zone, subdomain)
 }
+
+/// Make an HTTP request to a DNS server's API with
+/// [`cmd::http_request`]. Returns the response body when the
+/// status is below 400.
+pub(crate) fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[String],
+    body: Option<&str>,
+) -> DeployResult<String> {
+    match cmd::http_request(method, url, headers, body, &[])? {
+        (status, response) if status < 400 => Ok(response),
+        (status, response) => Err(DeployError::DnsError(format!(
+            "{method} {url} returned HTTP {status}: {response}"
+        ))),
+    }
+}
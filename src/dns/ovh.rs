@@ -1,6 +1,7 @@
 use std::fs;
 use std::path::PathBuf;
 
+use crate::audit;
 use crate::cmd;
 use crate::dns::{self, DnsProvider};
 use crate::error::{DeployError, DeployResult};
@@ -12,6 +13,8 @@ use crate::error::{DeployError, DeployResult};
 pub struct Ovh {
     /// The fully-qualified domain name to manage.
     pub domain: String,
+    /// TTL of the records, in seconds.
+    pub ttl: u32,
 }
 
 /// Credentials read from `~/.ovh.conf`.
@@ -31,9 +34,18 @@ impl Ovh {
     pub fn new(domain: &str) -> Self {
         Self {
             domain: domain.to_string(),
+            ttl: dns::DEFAULT_TTL,
         }
     }
 
+    /// Set the TTL of the records, in seconds (default 300). `0`
+    /// uses the zone's default TTL.
+    #[must_use]
+    pub const fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
     fn read_credentials() -> DeployResult<OvhCredentials> {
         let home = std::env::var("HOME").map_err(|_| DeployError::EnvMissing("HOME".into()))?;
         let conf_path = PathBuf::from(home).join(".ovh.conf");
@@ -59,6 +71,9 @@ impl Ovh {
         let ck = parse_ini_value(&content, &endpoint, "consumer_key")
             .ok_or_else(|| DeployError::Other("missing consumer_key in ~/.ovh.conf".into()))?;
 
+        audit::register_secret(&app_secret);
+        audit::register_secret(&ck);
+
         Ok(OvhCredentials {
             endpoint,
             application_key: ak,
@@ -79,6 +94,31 @@ impl Ovh {
         }
     }
 
+    /// Compute the `X-Ovh-Signature` header value:
+    /// `$1$` followed by the hex SHA-1 of
+    /// `AS+CK+METHOD+URL+BODY+TIMESTAMP`.
+    ///
+    /// The signed data is piped to `shasum` on stdin, so quotes or
+    /// shell syntax in the body never reach a shell.
+    pub fn sign(
+        creds: &OvhCredentials,
+        method: &str,
+        url: &str,
+        body: &str,
+        timestamp: &str,
+    ) -> DeployResult<String> {
+        let sig_data = format!(
+            "{}+{}+{method}+{url}+{body}+{timestamp}",
+            creds.application_secret, creds.consumer_key,
+        );
+        let output = cmd::run_with_stdin("shasum", &["-a", "1"], sig_data.as_bytes())?;
+        let sha1 = output
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| DeployError::Other("shasum produced no output".into()))?;
+        Ok(format!("$1${sha1}"))
+    }
+
     /// Make a signed OVH API request via curl.
     fn api_request(
         creds: &OvhCredentials,
@@ -90,24 +130,13 @@ impl Ovh {
         let url = format!("{base}{path}");
 
         // Get server timestamp
-        let ts = cmd::run("curl", &["-s", &format!("{base}/auth/time")])?;
-
-        // Build signature:
-        // $1$SHA1(AS+CK+METHOD+URL+BODY+TS)
-        let body_str = body.unwrap_or("");
-        let sig_data = format!(
-            "{}+{}+{method}+{url}+{body_str}+{ts}",
-            creds.application_secret, creds.consumer_key,
-        );
-
-        let sha1 = cmd::run(
-            "sh",
-            &[
-                "-c",
-                &format!("printf '%s' '{sig_data}' | shasum -a 1 | cut -d' ' -f1"),
-            ],
+        let ts = cmd::run_with_timeout(
+            "curl",
+            &["-s", &format!("{base}/auth/time")],
+            cmd::API_TIMEOUT,
         )?;
-        let signature = format!("$1${sha1}");
+
+        let signature = Self::sign(creds, method, &url, body.unwrap_or(""), &ts)?;
 
         let mut args = vec![
             "-s".to_string(),
@@ -130,23 +159,29 @@ impl Ovh {
             args.push(b.to_string());
         }
 
+        // Append the HTTP status on its own line
+        args.push("-w".to_string());
+        args.push("\n%{http_code}".to_string());
         args.push(url);
 
         let args_ref: Vec<&str> = args.iter().map(String::as_str).collect();
-        cmd::run("curl", &args_ref)
-    }
-}
-
-impl DnsProvider for Ovh {
-    fn domain(&self) -> &str {
-        &self.domain
+        let output = cmd::run_with_timeout("curl", &args_ref, cmd::API_TIMEOUT)?;
+        let (response, status) = output.rsplit_once('\n').unwrap_or(("", output.as_str()));
+        match status.parse::<u16>() {
+            Ok(code) if code < 400 => Ok(response.to_string()),
+            _ => Err(DeployError::DnsError(format!(
+                "OVH API {method} {path} returned HTTP {status}: {response}"
+            ))),
+        }
     }
 
-    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+    /// Create or update the `field_type` record of the domain
+    /// pointing to `target`, then refresh the zone.
+    fn upsert_record(&self, field_type: &str, target: &str) -> DeployResult<()> {
         let creds = Self::read_credentials()?;
         let (zone, subdomain) = dns::split_domain(&self.domain);
 
-        eprintln!("OVH DNS: {} -> {ip}", self.domain);
+        eprintln!("OVH DNS: {} -> {target}", self.domain);
         eprintln!("  Zone: {zone}");
         eprintln!(
             "  SubDomain: {}",
@@ -157,25 +192,26 @@ impl DnsProvider for Ovh {
             }
         );
 
-        // Find existing A record
+        // Find existing record
         let path = format!(
             "/domain/zone/{zone}/record\
-             ?fieldType=A&subDomain={subdomain}"
+             ?fieldType={field_type}&subDomain={subdomain}"
         );
         let response = Self::api_request(&creds, "GET", &path, None)?;
 
         let ids: Vec<u64> = serde_json::from_str(&response).unwrap_or_default();
 
         if let Some(record_id) = ids.first() {
-            eprintln!("  Updating existing A record (id: {record_id})...");
+            eprintln!("  Updating existing {field_type} record (id: {record_id})...");
             let path = format!("/domain/zone/{zone}/record/{record_id}");
-            let body = format!(r#"{{"target":"{ip}","ttl":300}}"#);
+            let body = format!(r#"{{"target":"{target}","ttl":{}}}"#, self.ttl);
             Self::api_request(&creds, "PUT", &path, Some(&body))?;
         } else {
-            eprintln!("  Creating new A record...");
+            eprintln!("  Creating new {field_type} record...");
             let path = format!("/domain/zone/{zone}/record");
             let body = format!(
-                r#"{{"fieldType":"A","subDomain":"{subdomain}","target":"{ip}","ttl":300}}"#
+                r#"{{"fieldType":"{field_type}","subDomain":"{subdomain}","target":"{target}","ttl":{}}}"#,
+                self.ttl
             );
             Self::api_request(&creds, "POST", &path, Some(&body))?;
         }
@@ -189,24 +225,26 @@ impl DnsProvider for Ovh {
             None,
         )?;
 
-        eprintln!("DNS record set: {} -> {ip}", self.domain);
+        eprintln!("DNS record set: {} -> {target}", self.domain);
         Ok(())
     }
 
-    fn delete_a_record(&self) -> DeployResult<()> {
+    /// Delete the `field_type` records of the domain, then
+    /// refresh the zone.
+    fn delete_records(&self, field_type: &str) -> DeployResult<()> {
         let creds = Self::read_credentials()?;
         let (zone, subdomain) = dns::split_domain(&self.domain);
 
         let path = format!(
             "/domain/zone/{zone}/record\
-             ?fieldType=A&subDomain={subdomain}"
+             ?fieldType={field_type}&subDomain={subdomain}"
         );
         let response = Self::api_request(&creds, "GET", &path, None)?;
 
         let ids: Vec<u64> = serde_json::from_str(&response).unwrap_or_default();
 
         for record_id in &ids {
-            eprintln!("  Deleting A record (id: {record_id})...");
+            eprintln!("  Deleting {field_type} record (id: {record_id})...");
             let path = format!("/domain/zone/{zone}/record/{record_id}");
             Self::api_request(&creds, "DELETE", &path, None)?;
         }
@@ -224,6 +262,139 @@ impl DnsProvider for Ovh {
     }
 }
 
+impl DnsProvider for Ovh {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn for_domain(&self, domain: &str) -> Option<Box<dyn DnsProvider>> {
+        Some(Box::new(Self::new(domain).ttl(self.ttl)))
+    }
+
+    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+        self.upsert_record("A", ip)
+    }
+
+    fn delete_a_record(&self) -> DeployResult<()> {
+        self.delete_records("A")
+    }
+
+    fn upsert_aaaa_record(&self, ip: &str) -> DeployResult<()> {
+        self.upsert_record("AAAA", ip)
+    }
+
+    fn delete_aaaa_record(&self) -> DeployResult<()> {
+        self.delete_records("AAAA")
+    }
+
+    fn manages_aaaa_records(&self) -> bool {
+        true
+    }
+
+    fn upsert_cname_record(&self, target: &str) -> DeployResult<()> {
+        let (_, subdomain) = dns::split_domain(&self.domain);
+        if subdomain.is_empty() {
+            return Err(DeployError::InvalidConfig(format!(
+                "{} is a zone apex, which can't have a CNAME record",
+                self.domain
+            )));
+        }
+        self.upsert_record("CNAME", &cname_target(target))
+    }
+
+    fn delete_cname_record(&self) -> DeployResult<()> {
+        self.delete_records("CNAME")
+    }
+
+    fn upsert_caa_records(&self, issuers: &[&str]) -> DeployResult<()> {
+        self.delete_records("CAA")?;
+        let creds = Self::read_credentials()?;
+        let (zone, subdomain) = dns::split_domain(&self.domain);
+        for issuer in issuers {
+            eprintln!("  Creating CAA record for {issuer}...");
+            let body = serde_json::json!({
+                "fieldType": "CAA",
+                "subDomain": subdomain,
+                "target": caa_target(issuer),
+                "ttl": self.ttl,
+            });
+            let path = format!("/domain/zone/{zone}/record");
+            Self::api_request(&creds, "POST", &path, Some(&body.to_string()))?;
+        }
+        Self::api_request(
+            &creds,
+            "POST",
+            &format!("/domain/zone/{zone}/refresh"),
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn delete_caa_records(&self) -> DeployResult<()> {
+        self.delete_records("CAA")
+    }
+
+    fn check_access(&self) -> DeployResult<()> {
+        let creds = Self::read_credentials()?;
+        let (zone, subdomain) = dns::split_domain(&self.domain);
+        // A wildcard's probe goes under the name it covers
+        let base = subdomain.trim_start_matches('*').trim_start_matches('.');
+        let probe = if base.is_empty() {
+            dns::PROBE_RECORD.to_string()
+        } else {
+            format!("{}.{base}", dns::PROBE_RECORD)
+        };
+
+        Self::api_request(
+            &creds,
+            "GET",
+            &format!("/domain/zone/{zone}/record?fieldType=A&subDomain={subdomain}"),
+            None,
+        )?;
+        let body = format!(
+            r#"{{"fieldType":"TXT","subDomain":"{probe}","target":"{}","ttl":60}}"#,
+            dns::PROBE_CONTENT
+        );
+        let response = Self::api_request(
+            &creds,
+            "POST",
+            &format!("/domain/zone/{zone}/record"),
+            Some(&body),
+        )?;
+        let record: serde_json::Value = serde_json::from_str(&response)?;
+        let record_id = record["id"].as_u64().ok_or_else(|| {
+            DeployError::DnsError(format!(
+                "OVH returned no id for the probe record: {response}"
+            ))
+        })?;
+        Self::api_request(
+            &creds,
+            "DELETE",
+            &format!("/domain/zone/{zone}/record/{record_id}"),
+            None,
+        )?;
+        Ok(())
+    }
+}
+
+/// `target` as an absolute name, ending with a dot: OVH reads a
+/// CNAME target without one as relative to the zone.
+#[must_use]
+pub fn cname_target(target: &str) -> String {
+    if target.ends_with('.') {
+        target.to_string()
+    } else {
+        format!("{target}.")
+    }
+}
+
+/// Target of the CAA record allowing `issuer` to issue
+/// certificates, e.g. `0 issue "letsencrypt.org"`.
+#[must_use]
+pub fn caa_target(issuer: &str) -> String {
+    format!("0 issue \"{issuer}\"")
+}
+
 /// Parse a value from an INI-style config file.
 ///
 /// Looks for `[section]`, then finds `key = value` within that
@@ -0,0 +1,152 @@
+use serde_json::Value;
+
+use crate::audit;
+use crate::dns::{self, DnsProvider};
+use crate::error::{DeployError, DeployResult};
+
+/// Pi-hole local DNS provider, managing the "Local DNS records"
+/// of a Pi-hole v6 through its API.
+///
+/// For home-lab deployments resolved by the Pi-hole rather than
+/// a public zone. Requires `PIHOLE_PASSWORD` set with the web
+/// interface or an app password.
+pub struct Pihole {
+    /// Base URL of the Pi-hole, e.g. `http://pi.hole`.
+    pub url: String,
+    /// The fully-qualified domain name to manage.
+    pub domain: String,
+}
+
+impl Pihole {
+    #[must_use]
+    pub fn new(url: &str, domain: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            domain: domain.to_string(),
+        }
+    }
+
+    fn password() -> DeployResult<String> {
+        let password = std::env::var("PIHOLE_PASSWORD").map_err(|_| {
+            DeployError::EnvMissing(
+                "PIHOLE_PASSWORD not set. Use the web interface password \
+                 or an app password from Settings > Web interface / API"
+                    .into(),
+            )
+        })?;
+        audit::register_secret(&password);
+        Ok(password)
+    }
+
+    /// Open an API session, returning its `X-FTL-SID` header.
+    fn login(&self) -> DeployResult<String> {
+        let body = serde_json::json!({ "password": Self::password()? }).to_string();
+        let response = dns::http_request("POST", &self.api("auth"), &[], Some(&body))?;
+        let auth: Value = serde_json::from_str(&response)?;
+        let sid = auth["session"]["sid"]
+            .as_str()
+            .filter(|_| auth["session"]["valid"] == true)
+            .ok_or_else(|| {
+                DeployError::DnsError(format!("Pi-hole at {} refused the password", self.url))
+            })?;
+        audit::register_secret(sid);
+        Ok(format!("X-FTL-SID: {sid}"))
+    }
+
+    /// Run `f` in an API session, closed afterwards.
+    fn with_session<T>(&self, f: impl FnOnce(&[String]) -> DeployResult<T>) -> DeployResult<T> {
+        let headers = [self.login()?];
+        let result = f(&headers);
+        // Sessions are limited; a failed logout only lets it expire
+        let _ = dns::http_request("DELETE", &self.api("auth"), &headers, None);
+        result
+    }
+
+    fn api(&self, path: &str) -> String {
+        format!("{}/api/{path}", self.url)
+    }
+
+    fn host_url(&self, entry: &str) -> String {
+        self.api(&format!("config/dns/hosts/{}", entry.replace(' ', "%20")))
+    }
+
+    /// Local DNS records name single hosts.
+    fn check_not_wildcard(&self) -> DeployResult<()> {
+        if dns::is_wildcard(&self.domain) {
+            return Err(DeployError::InvalidConfig(format!(
+                "Pi-hole local DNS records can't be wildcards like {}; \
+                 use AdGuard Home, whose rewrites can",
+                self.domain
+            )));
+        }
+        Ok(())
+    }
+
+    /// The local DNS records for this domain.
+    fn entries(&self, headers: &[String]) -> DeployResult<Vec<String>> {
+        let response = dns::http_request("GET", &self.api("config/dns/hosts"), headers, None)?;
+        host_entries(&response, &self.domain)
+    }
+}
+
+impl DnsProvider for Pihole {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn for_domain(&self, domain: &str) -> Option<Box<dyn DnsProvider>> {
+        Some(Box::new(Self::new(&self.url, domain)))
+    }
+
+    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+        self.check_not_wildcard()?;
+        eprintln!("Pi-hole DNS: {} -> {ip}", self.domain);
+        let wanted = format!("{ip} {}", self.domain);
+        self.with_session(|headers| {
+            let entries = self.entries(headers)?;
+            for entry in entries.iter().filter(|e| **e != wanted) {
+                eprintln!("  Removing {entry}...");
+                dns::http_request("DELETE", &self.host_url(entry), headers, None)?;
+            }
+            if !entries.contains(&wanted) {
+                eprintln!("  Adding {wanted}...");
+                dns::http_request("PUT", &self.host_url(&wanted), headers, None)?;
+            }
+            Ok(())
+        })?;
+        eprintln!("DNS record set: {} -> {ip}", self.domain);
+        Ok(())
+    }
+
+    fn delete_a_record(&self) -> DeployResult<()> {
+        self.with_session(|headers| {
+            for entry in self.entries(headers)? {
+                eprintln!("  Removing {entry}...");
+                dns::http_request("DELETE", &self.host_url(&entry), headers, None)?;
+            }
+            Ok(())
+        })?;
+        eprintln!("DNS record deleted: {}", self.domain);
+        Ok(())
+    }
+
+    fn check_access(&self) -> DeployResult<()> {
+        self.check_not_wildcard()?;
+        self.with_session(|headers| self.entries(headers).map(drop))
+    }
+}
+
+/// The `"<ip> <domain>"` entries of `domain` in the response of
+/// `GET /api/config/dns/hosts`.
+pub fn host_entries(response: &str, domain: &str) -> DeployResult<Vec<String>> {
+    let config: Value = serde_json::from_str(response)?;
+    let hosts = config["config"]["dns"]["hosts"]
+        .as_array()
+        .ok_or_else(|| DeployError::DnsError(format!("unexpected Pi-hole response: {response}")))?;
+    Ok(hosts
+        .iter()
+        .filter_map(Value::as_str)
+        .filter(|entry| entry.split_whitespace().skip(1).any(|name| name == domain))
+        .map(str::to_string)
+        .collect())
+}
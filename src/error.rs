@@ -1,4 +1,8 @@
+use std::fmt::Write;
 use std::process::ExitStatus;
+use std::time::Duration;
+
+use crate::scan::Severity;
 
 pub type DeployResult<T> = Result<T, DeployError>;
 
@@ -7,9 +11,33 @@ pub enum DeployError {
     #[error("command failed: {command}")]
     CommandFailed { command: String, status: ExitStatus },
 
+    #[error("command timed out after {}s: {command}", timeout.as_secs())]
+    Timeout { command: String, timeout: Duration },
+
     #[error("command not found: {0}")]
     CommandNotFound(String),
 
+    /// `stderr` holds the last lines the command wrote to its
+    /// standard error, when it was captured.
+    #[error("remote command exited with status {exit_status}: {command}")]
+    RemoteCommandFailed {
+        command: String,
+        exit_status: u32,
+        stderr: String,
+    },
+
+    #[error(
+        "host key for {host} does not match the pinned key; \
+         rerun with --trust-new-hostkey if the server was rebuilt"
+    )]
+    HostKeyMismatch { host: String },
+
+    #[error(
+        "no host key pinned for {host}; add the server with `provision` or \
+         `import`, or rerun with --trust-new-hostkey"
+    )]
+    HostKeyUnknown { host: String },
+
     #[error("SSH connection failed: {0}")]
     SshFailed(String),
 
@@ -19,6 +47,22 @@ pub enum DeployError {
     #[error("server not found: {0}")]
     ServerNotFound(String),
 
+    #[error("server quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("no capacity for {size} servers in {region}")]
+    RegionUnavailable { region: String, size: String },
+
+    #[error("invalid server size '{size}' in {region}")]
+    InvalidSize { size: String, region: String },
+
+    #[error("checksum of {url} is {actual}, expected {expected}")]
+    ImageChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
     #[error("DNS error: {0}")]
     DnsError(String),
 
@@ -28,9 +72,52 @@ pub enum DeployError {
     #[error("file not found: {0}")]
     FileNotFound(String),
 
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error(
+        "{environment} is a protected environment: confirm with \
+         --confirm {environment}, or CATAPULTA_CONFIRM={environment} in CI"
+    )]
+    NotConfirmed { environment: String },
+
     #[error("container '{0}' did not become healthy after {1} attempts")]
     HealthcheckTimeout(String, u32),
 
+    #[error("container '{name}' stopped right after starting ({state})")]
+    ContainerExited { name: String, state: String },
+
+    #[error(
+        "not enough disk space in {location}: {} MB needed, {} MB available",
+        needed / (1024 * 1024),
+        available / (1024 * 1024)
+    )]
+    InsufficientDiskSpace {
+        location: String,
+        needed: u64,
+        available: u64,
+    },
+
+    #[error("host not prepared for deploy: {0}")]
+    HostNotPrepared(String),
+
+    #[error("app '{app}' is built for {platform}, but the server is {machine}")]
+    PlatformMismatch {
+        app: String,
+        platform: String,
+        machine: String,
+    },
+
+    #[error("stack collides with another on the server: {0}")]
+    StackCollision(String),
+
+    #[error("image {image} has vulnerabilities of {threshold} severity or above: {found}")]
+    Vulnerable {
+        image: String,
+        threshold: Severity,
+        found: String,
+    },
+
     #[error("{0}")]
     Other(String),
 
@@ -39,4 +126,306 @@ pub enum DeployError {
 
     #[error(transparent)]
     Json(#[from] serde_json::Error),
+
+    /// An error annotated with the pipeline phase, and host when
+    /// there is one, it happened in.
+    #[error("{phase}{}: {source}", host.as_ref().map(|h| format!(" on {h}")).unwrap_or_default())]
+    Context {
+        phase: String,
+        host: Option<String>,
+        source: Box<Self>,
+    },
+}
+
+/// Attach the pipeline phase and host to an error.
+pub trait ResultExt<T> {
+    /// Wrap an error with the `phase` it happened in and the
+    /// `host` being worked on, if any.
+    fn context(self, phase: &str, host: Option<&str>) -> DeployResult<T>;
+}
+
+impl<T> ResultExt<T> for DeployResult<T> {
+    fn context(self, phase: &str, host: Option<&str>) -> Self {
+        self.map_err(|e| e.context(phase, host))
+    }
+}
+
+impl DeployError {
+    /// Wrap the error with the `phase` it happened in and the
+    /// `host` being worked on, if any.
+    #[must_use]
+    pub fn context(self, phase: &str, host: Option<&str>) -> Self {
+        Self::Context {
+            phase: phase.to_string(),
+            host: host.map(ToString::to_string),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error without any [`DeployError::Context`] wrappers.
+    #[must_use]
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// Stable error code, for searching docs and issues.
+    ///
+    /// Codes are grouped by area: `E1xx` local and remote
+    /// commands, `E2xx` SSH, `E3xx` provisioning, `E4xx` DNS,
+    /// `E5xx` configuration, `E6xx` deployment, `E9xx` other.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self.root() {
+            Self::CommandFailed { .. } => "E101",
+            Self::Timeout { .. } => "E102",
+            Self::CommandNotFound(_) => "E103",
+            Self::RemoteCommandFailed { .. } => "E104",
+            Self::SshFailed(_) => "E201",
+            Self::HostKeyMismatch { .. } => "E202",
+            Self::HostKeyUnknown { .. } => "E203",
+            Self::PrerequisiteMissing(_) => "E301",
+            Self::ServerNotFound(_) => "E302",
+            Self::QuotaExceeded(_) => "E303",
+            Self::RegionUnavailable { .. } => "E304",
+            Self::InvalidSize { .. } => "E305",
+            Self::ImageChecksumMismatch { .. } => "E306",
+            Self::DnsError(_) => "E401",
+            Self::EnvMissing(_) => "E501",
+            Self::FileNotFound(_) => "E502",
+            Self::InvalidConfig(_) => "E503",
+            Self::NotConfirmed { .. } => "E504",
+            Self::HealthcheckTimeout(..) => "E601",
+            Self::InsufficientDiskSpace { .. } => "E602",
+            Self::HostNotPrepared(_) => "E603",
+            Self::PlatformMismatch { .. } => "E604",
+            Self::StackCollision(_) => "E605",
+            Self::ContainerExited { .. } => "E606",
+            Self::Vulnerable { .. } => "E607",
+            Self::Io(_) => "E902",
+            Self::Json(_) => "E903",
+            Self::Other(_) | Self::Context { .. } => "E901",
+        }
+    }
+
+    /// The command that failed, for command errors.
+    #[must_use]
+    pub fn command(&self) -> Option<&str> {
+        match self.root() {
+            Self::CommandFailed { command, .. }
+            | Self::Timeout { command, .. }
+            | Self::RemoteCommandFailed { command, .. } => Some(command),
+            _ => None,
+        }
+    }
+
+    /// A suggested fix, when there is a likely one.
+    #[must_use]
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self.root() {
+            Self::CommandFailed { command, .. } => command_hint(command),
+            Self::Timeout { .. } => {
+                "check the server load, or raise the limit with \
+                 `SshOptions::command_timeout`"
+            }
+            Self::CommandNotFound(program) => {
+                return Some(format!(
+                    "install `{program}` and make sure it is on your PATH"
+                ));
+            }
+            Self::RemoteCommandFailed { .. } => {
+                "check the output above; `cargo xtask status <host>` shows the \
+                 state of the containers"
+            }
+            error @ (Self::SshFailed(_)
+            | Self::HostKeyMismatch { .. }
+            | Self::HostKeyUnknown { .. }) => ssh_hint(error),
+            Self::PrerequisiteMissing(_) => "install the missing tool and rerun",
+            Self::ServerNotFound(name) => {
+                return Some(format!(
+                    "create it first with `cargo xtask provision {name}`"
+                ));
+            }
+            Self::QuotaExceeded(_) => {
+                "destroy servers you no longer need, or ask the provider to raise \
+                 the account's limit"
+            }
+            Self::RegionUnavailable { .. } => {
+                "provision in another region with --region, or list fallbacks with \
+                 `DigitalOcean::fallback_region`"
+            }
+            Self::InvalidSize { region, .. } => {
+                return Some(format!(
+                    "pick a size offered in {region} (`doctl compute size list` or \
+                     `scw instance server-type list`)"
+                ));
+            }
+            Self::ImageChecksumMismatch { .. } => {
+                "retry in case the download was cut short; if the image was replaced \
+                 upstream, pin the new checksum with `Libvirt::image_sha256`"
+            }
+            Self::DnsError(msg) if msg.contains("HTTP 401") || msg.contains("HTTP 403") => {
+                "check the DNS provider credentials (`~/.ovh.conf` or `CF_API_TOKEN`)"
+            }
+            Self::DnsError(_) => "check that the zone exists in the DNS provider account",
+            Self::EnvMissing(_) => "export the variable before running cargo xtask",
+            Self::FileNotFound(_) => "paths are relative to the directory cargo xtask runs in",
+            Self::InvalidConfig(msg) if msg.starts_with("generated files") => {
+                "check the extra directives, routes and names the files are \
+                 rendered from, e.g. with `deploy --dry-run`"
+            }
+            Self::InvalidConfig(_) => {
+                "see the `catapulta::config` documentation for the file format"
+            }
+            Self::NotConfirmed { .. } => "the pipeline is guarded with `Pipeline::protect`",
+            Self::HealthcheckTimeout(name, _) => {
+                return Some(format!(
+                    "read the container's logs with `docker compose logs {name}` \
+                     on the server"
+                ));
+            }
+            Self::InsufficientDiskSpace { location, .. } if location.starts_with("local") => {
+                "free up space in the temp directory, or stream the image with \
+                 `Transfer::Stream`"
+            }
+            Self::InsufficientDiskSpace { .. } => {
+                "free up space on the server (e.g. `docker image prune -a`), or stream \
+                 the image with `Transfer::Stream` to skip the copy in /tmp"
+            }
+            Self::HostNotPrepared(_) => {
+                "set the server up with `cargo xtask provision` first, or install \
+                 Docker with the compose plugin and create the remote directory"
+            }
+            Self::PlatformMismatch { .. } => {
+                "build for the server's architecture with `App::platform` (e.g. \
+                 `linux/arm64`), or for several with `App::platforms`"
+            }
+            Self::StackCollision(_) => {
+                "give each stack on the server its own `Pipeline::project`, and distinct \
+                 app names and published ports"
+            }
+            Self::ContainerExited { name, .. } => {
+                return Some(format!(
+                    "the logs above show why it stopped; run it in the foreground with \
+                     `docker compose up {name}` to watch it start"
+                ));
+            }
+            Self::Vulnerable { .. } => "patch and rebuild, or raise `ImageScan::fail_on`",
+            Self::Other(_) | Self::Io(_) | Self::Json(_) | Self::Context { .. } => return None,
+        };
+        Some(hint.to_string())
+    }
+
+    /// Multi-line report with the error code, phase, host,
+    /// failing command, its standard error, and suggested fix.
+    ///
+    /// ```text
+    /// error[E104]: remote command exited with status 1: docker compose up -d
+    ///   phase:   deploy
+    ///   host:    example.com
+    ///   command: docker compose up -d
+    ///   hint:    check the output above; ...
+    /// ```
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = format!("error[{}]: {}", self.code(), self.root());
+        let mut error = self;
+        while let Self::Context {
+            phase,
+            host,
+            source,
+        } = error
+        {
+            let _ = write!(out, "\n  phase:   {phase}");
+            if let Some(host) = host {
+                let _ = write!(out, "\n  host:    {host}");
+            }
+            error = source;
+        }
+        if let Some(command) = self.command() {
+            let _ = write!(out, "\n  command: {command}");
+        }
+        if let Self::RemoteCommandFailed { stderr, .. } = self.root()
+            && !stderr.is_empty()
+        {
+            let _ = write!(
+                out,
+                "\n  stderr:  {}",
+                stderr.replace('\n', "\n           ")
+            );
+        }
+        if let Some(hint) = self.hint() {
+            let _ = write!(out, "\n  hint:    {hint}");
+        }
+        out
+    }
+
+    /// Whether the failure is likely to go away on its own, so
+    /// the operation is worth retrying (see [`crate::cmd::retry`]).
+    ///
+    /// Covers dropped or refused SSH connections, connect and
+    /// handshake timeouts, rate limiting and server errors from
+    /// DNS APIs, and rsync network or partial-transfer errors. A
+    /// command that outran its own timeout ([`Self::Timeout`]) is
+    /// not retried: it would most likely time out again.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self.root() {
+            Self::SshFailed(msg) => !msg.starts_with("authentication failed"),
+            Self::DnsError(msg) => {
+                msg.contains("HTTP 429")
+                    || msg.contains("HTTP 5")
+                    || msg.contains("error sending request")
+            }
+            Self::CommandFailed { command, status } => {
+                let program = command.split_whitespace().next().unwrap_or("");
+                // ssh/scp 255: could not connect or lost the
+                // connection. rsync: socket I/O, protocol stream,
+                // partial transfer, data and connection timeouts.
+                matches!(
+                    (program, status.code()),
+                    ("ssh" | "scp", Some(255)) | ("rsync", Some(10 | 12 | 23 | 30 | 35 | 255))
+                )
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The hint for an SSH connection `error`.
+fn ssh_hint(error: &DeployError) -> &'static str {
+    match error {
+        DeployError::SshFailed(msg) if msg.starts_with("authentication failed") => {
+            "load your key with `ssh-add`, or pass it with `SshSession::with_key`"
+        }
+        DeployError::HostKeyMismatch { .. } => {
+            "if the server was rebuilt, rerun with --trust-new-hostkey; \
+             otherwise find out why its key changed before connecting"
+        }
+        DeployError::HostKeyUnknown { .. } => {
+            "check the fingerprint printed above against the server's console \
+             before trusting it"
+        }
+        _ => {
+            "check that the server is up and its SSH port is reachable; raise \
+             `SshOptions::retries` if it is still booting"
+        }
+    }
+}
+
+/// The hint for a failed local `command`, by program.
+fn command_hint(command: &str) -> &'static str {
+    match command.split_whitespace().next().unwrap_or("") {
+        "docker" => "check that the Docker daemon is running (`docker info`)",
+        "ssh" | "scp" | "rsync" => {
+            "check that the server is reachable over SSH, then rerun to \
+             resume the transfer"
+        }
+        _ => {
+            "rerun with --trace-commands, or see .catapulta/last-run.log, \
+             for every command that ran"
+        }
+    }
 }
@@ -0,0 +1,89 @@
+//! Typed progress events.
+//!
+//! Listeners registered with [`crate::Pipeline::on_event`] receive
+//! a [`DeployEvent`] as each phase starts, images are built and
+//! transferred, and healthchecks are polled, so GUIs, bots, and CI
+//! wrappers can render progress without scraping stderr.
+//!
+//! Events are delivered on the thread running the pipeline.
+//! Deployers and provisioners report through [`emit`], which
+//! reaches the listeners of the pipeline currently running on
+//! that thread and does nothing outside of one.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A callback receiving [`DeployEvent`]s.
+pub type Listener = Rc<dyn Fn(DeployEvent)>;
+
+/// Progress reported while a pipeline command runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeployEvent {
+    /// A phase (`"build"`, `"transfer image"`, `"deploy"`, ...)
+    /// started, on `host` when it runs remotely. Phase names
+    /// match those in [`crate::error::DeployError::Context`].
+    PhaseStarted { phase: String, host: Option<String> },
+    /// An image was built; `size` is in bytes (0 when unknown).
+    ImageBuilt { app: String, tag: String, size: u64 },
+    /// Share of an image's transfer completed, from 0 to 100.
+    TransferProgress { app: String, pct: u8 },
+    /// A container's health was polled; `status` is `None`
+    /// while the container is not up yet.
+    HealthcheckAttempt {
+        app: String,
+        attempt: u32,
+        max_attempts: u32,
+        status: Option<String>,
+    },
+    /// The command finished.
+    Done { success: bool },
+}
+
+thread_local! {
+    static LISTENERS: RefCell<Vec<Listener>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Restores the previous listeners when a scope ends, including
+/// by panic.
+struct Scope(Vec<Listener>);
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        LISTENERS.set(std::mem::take(&mut self.0));
+    }
+}
+
+/// Run `f` with `listeners` receiving the events emitted on this
+/// thread.
+pub fn with_listeners<T>(listeners: &[Listener], f: impl FnOnce() -> T) -> T {
+    let _scope = Scope(LISTENERS.replace(listeners.to_vec()));
+    f()
+}
+
+/// Run `f` with `listener` receiving the events emitted on this
+/// thread, on top of the current listeners.
+pub fn with_listener<T>(listener: Listener, f: impl FnOnce() -> T) -> T {
+    let mut listeners = LISTENERS.with_borrow(Clone::clone);
+    listeners.push(listener);
+    with_listeners(&listeners, f)
+}
+
+/// Send `event` to the current listeners.
+pub fn emit(event: DeployEvent) {
+    // Cloned out so a listener may emit in turn.
+    let listeners = LISTENERS.with_borrow(Clone::clone);
+    if let Some((last, rest)) = listeners.split_last() {
+        for listener in rest {
+            listener(event.clone());
+        }
+        last(event);
+    }
+}
+
+/// Emit [`DeployEvent::PhaseStarted`].
+pub fn phase_started(phase: &str, host: Option<&str>) {
+    emit(DeployEvent::PhaseStarted {
+        phase: phase.to_string(),
+        host: host.map(ToString::to_string),
+    });
+}
@@ -0,0 +1,113 @@
+//! Firewall rules for published ports.
+//!
+//! Provisioned servers run ufw with only SSH, HTTP and HTTPS
+//! open. A host port published with [`App::port`] or forwarded
+//! with [`Caddy::stream`] would be blocked, so deploys open the
+//! missing ones. Servers without ufw, or with ufw inactive, are
+//! left alone.
+
+use crate::app::App;
+use crate::caddy::Caddy;
+use crate::error::DeployResult;
+use crate::ssh::SshSession;
+
+/// Rules of an ufw firewall, from `ufw status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UfwStatus {
+    /// Whether ufw filters traffic.
+    pub active: bool,
+    /// Targets of the `ALLOW` rules (e.g. `443/tcp`, `OpenSSH`).
+    pub allowed: Vec<String>,
+}
+
+impl UfwStatus {
+    /// Whether TCP traffic to `port` is allowed.
+    #[must_use]
+    pub fn allows(&self, port: u16) -> bool {
+        self.allows_protocol(port, "tcp")
+    }
+
+    /// Whether `protocol` (`tcp` or `udp`) traffic to `port` is
+    /// allowed.
+    #[must_use]
+    pub fn allows_protocol(&self, port: u16, protocol: &str) -> bool {
+        let exact = format!("{port}/{protocol}");
+        let any = port.to_string();
+        self.allowed
+            .iter()
+            .any(|rule| *rule == exact || *rule == any)
+    }
+}
+
+/// Parse `ufw status` output. Anything else (no ufw, not root)
+/// reads as inactive.
+#[must_use]
+pub fn parse_status(output: &str) -> UfwStatus {
+    let active = output.lines().any(|line| line.trim() == "Status: active");
+    let allowed = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let target = fields.next()?;
+            fields.any(|f| f == "ALLOW").then(|| target.to_string())
+        })
+        .collect();
+    UfwStatus { active, allowed }
+}
+
+/// Host ports published by `apps`, sorted and without
+/// duplicates.
+#[must_use]
+pub fn published_ports(apps: &[App]) -> Vec<u16> {
+    let mut ports: Vec<u16> = apps
+        .iter()
+        .flat_map(|a| a.ports.iter().map(|(host, _)| *host))
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// Ports of the streams forwarded by `caddy`, with their
+/// protocol, without duplicates.
+#[must_use]
+pub fn stream_ports(caddy: &Caddy) -> Vec<(u16, &'static str)> {
+    let mut ports: Vec<(u16, &'static str)> = Vec::new();
+    for stream in &caddy.streams {
+        let port = (stream.port, stream.protocol());
+        if !ports.contains(&port) {
+            ports.push(port);
+        }
+    }
+    ports
+}
+
+/// Allow the host ports published by `apps` and the streams of
+/// `caddy` in the server's ufw firewall, when it is active and
+/// they aren't allowed yet.
+pub fn open_published_ports(ssh: &SshSession, apps: &[App], caddy: &Caddy) -> DeployResult<()> {
+    let mut ports: Vec<(u16, &str)> = published_ports(apps)
+        .into_iter()
+        .map(|port| (port, "tcp"))
+        .collect();
+    for port in stream_ports(caddy) {
+        if !ports.contains(&port) {
+            ports.push(port);
+        }
+    }
+    if ports.is_empty() {
+        return Ok(());
+    }
+    let status = parse_status(&ssh.exec("ufw status 2>/dev/null || true")?);
+    if !status.active {
+        return Ok(());
+    }
+    for (port, protocol) in ports
+        .into_iter()
+        .filter(|(port, protocol)| !status.allows_protocol(*port, protocol))
+    {
+        eprintln!("Opening port {port}/{protocol} in the firewall...");
+        ssh.exec(&format!("ufw allow {port}/{protocol}"))?;
+    }
+    Ok(())
+}
@@ -19,21 +19,27 @@
 //!   volumes, healthcheck)
 //! - A [`Caddy`] reverse proxy config (TLS, basic auth, headers)
 //! - A [`Provisioner`](provision::Provisioner) for cloud servers
-//!   (e.g. [`DigitalOcean`])
+//!   (e.g. [`DigitalOcean`], [`Scaleway`], [`Libvirt`] and
+//!   [`Proxmox`] for home labs, or [`ExistingServer`] for servers
+//!   you already have)
 //! - A [`DnsProvider`](dns::DnsProvider) for DNS records (e.g.
-//!   [`Ovh`], [`Cloudflare`])
+//!   [`Ovh`], [`Cloudflare`], [`Pihole`] and [`AdGuardHome`]
+//!   for local zones, or [`DuckDns`] behind a residential IP)
 //! - A [`Deployer`](deploy::Deployer) strategy (e.g.
-//!   [`DockerSaveLoad`])
+//!   [`DockerSaveLoad`], [`DoRegistry`], or [`DockerContext`]
+//!   building on the server)
 //!
 //! # Architecture
 //!
 //! The pipeline follows a three-phase model:
 //!
 //! 1. **Provision** - create a VPS, install Docker, configure
-//!    firewall, set up SSH
+//!    firewall, set up SSH (see [`provision::setup`] to add or
+//!    skip setup steps)
 //! 2. **DNS** - create or update A records pointing to the server
 //! 3. **Deploy** - build the image, transfer it, generate
-//!    `docker-compose.yml` and `Caddyfile`, start containers
+//!    `docker-compose.yml` and `Caddyfile` (or Caddy's JSON config,
+//!    see [`caddyjson`]), start containers
 //!
 //! Each phase is pluggable via traits ([`Provisioner`],
 //! [`DnsProvider`], [`Deployer`]).
@@ -79,12 +85,79 @@
 //! # Provision a new server
 //! cargo xtask provision my-service --domain my-service.example.com
 //!
+//! # Override the configured size and image for this server
+//! cargo xtask provision my-service --size s-2vcpu-4gb --image ubuntu-22-04-x64
+//!
 //! # Deploy the application
 //! cargo xtask deploy my-service.example.com
 //!
+//! # Or name the server as at provision time (see catapulta::state)
+//! cargo xtask deploy my-service
+//!
+//! # Adopt a server set up by hand, checking SSH access and Docker
+//! cargo xtask import legacy --ip 203.0.113.10 --domain legacy.example.com
+//!
 //! # Preview generated files without deploying
 //! cargo xtask deploy my-service.example.com --dry-run
 //!
+//! # Also start the apps of an optional profile (see App::profile)
+//! cargo xtask deploy my-service.example.com --profile debug
+//!
+//! # Roll out to a fleet one host at a time, stopping (and rolling
+//! # the failed host back) when one doesn't come up healthy
+//! cargo xtask deploy web1.example.com web2.example.com --rollback-on-failure
+//!
+//! # Confirm a deploy to a protected environment (see
+//! # Pipeline::protect), or set CATAPULTA_CONFIRM=production
+//! cargo xtask deploy my-service.example.com --confirm production
+//!
+//! # Re-pin the host key after rebuilding the server
+//! cargo xtask deploy my-service.example.com --trust-new-hostkey
+//!
+//! # Serve a maintenance page (503) while working on the server
+//! cargo xtask maintenance my-service.example.com --on
+//! cargo xtask maintenance my-service.example.com --off
+//!
+//! # Go back to the previously deployed image, without rebuilding
+//! cargo xtask rollback my-service.example.com
+//!
+//! # Run three replicas of the api service
+//! cargo xtask scale my-service.example.com api=3
+//!
+//! # Run the stack on this machine over plain HTTP, with the
+//! # exposed ports published and the dev volumes mounted
+//! cargo xtask dev
+//!
+//! # CPU and memory use of each container on the server
+//! cargo xtask top my-service.example.com
+//!
+//! # Any other docker compose command, in the remote directory
+//! cargo xtask compose my-service.example.com -- logs --tail 100 api
+//!
+//! # Reach a service on the server from localhost
+//! cargo xtask tunnel my-service.example.com 15432:localhost:5432
+//!
+//! # Print every command as it runs (always logged to
+//! # .catapulta/last-run.log, with secrets redacted)
+//! cargo xtask deploy my-service.example.com --trace-commands
+//!
+//! # Attach to the serial console of a VM that never got an IP
+//! # (libvirt)
+//! cargo xtask console my-service
+//!
+//! # Who provisioned, deployed, rolled back or destroyed what,
+//! # and when
+//! cargo xtask audit --last 20
+//!
+//! # GitHub Actions workflow deploying every push to main
+//! cargo xtask ci generate github > .github/workflows/deploy.yml
+//!
+//! # Remove the cloud images cached on the hypervisor (libvirt)
+//! cargo xtask cache clean
+//!
+//! # List what destroy would remove, without removing it
+//! cargo xtask destroy my-service --dry-run
+//!
 //! # Tear everything down
 //! cargo xtask destroy my-service
 //! ```
@@ -133,6 +206,80 @@
 //! }
 //! ```
 //!
+//! ## Per-app domains
+//!
+//! Give an app its own domain with [`App::domain`] instead of a
+//! path route. Each such app gets a dedicated Caddy site block,
+//! and its A record is created by the DNS provider managing the
+//! same zone:
+//!
+//! ```rust,no_run
+//! use catapulta::{App, Caddy, DigitalOcean, DockerSaveLoad, Ovh, Pipeline};
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     let api = App::new("api").expose(8000).domain("api.example.com");
+//!     let docs = App::new("docs").expose(80).domain("docs.example.com");
+//!     let web = App::new("web").expose(3000);
+//!
+//!     // example.com -> web; api.* and docs.* get their own sites
+//!     let caddy = Caddy::new().reverse_proxy(web.upstream()).gzip();
+//!
+//!     Pipeline::multi(vec![api, docs, web], caddy)
+//!         .provision(DigitalOcean::new())
+//!         .dns(Ovh::new("example.com"))
+//!         .deploy(DockerSaveLoad::new())
+//!         .run()?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! A wildcard domain such as `*.example.com` routes every
+//! subdomain to one app, e.g. for a multi-tenant service. Its
+//! certificate can only come from the ACME DNS-01 challenge:
+//! [`Caddy::dns_challenge`] names the Caddy DNS module, which
+//! the default image lacks, and the token it uses. Pi-hole can't
+//! hold wildcard records; the other DNS providers can.
+//!
+//! ```rust,no_run
+//! use catapulta::{App, Caddy, Cloudflare, DockerSaveLoad, Pipeline};
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     let saas = App::new("saas").expose(3000).domain("*.example.com");
+//!     let token = std::env::var("CF_API_TOKEN")?;
+//!     let caddy = Caddy::new()
+//!         .image("ghcr.io/caddybuilds/caddy-cloudflare:2")
+//!         .dns_challenge("cloudflare", "CF_API_TOKEN", &token);
+//!
+//!     Pipeline::multi(vec![saas], caddy)
+//!         .dns(Cloudflare::new("example.com"))
+//!         .deploy(DockerSaveLoad::new())
+//!         .run()?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## Log shipping
+//!
+//! [`Pipeline::ship_logs`] adds a Vector container that forwards
+//! the logs of every container in the stack to Loki,
+//! Elasticsearch, or a Loki deployed alongside it:
+//!
+//! ```rust,no_run
+//! use catapulta::logs::LogShipping;
+//! use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     let app = App::new("my-service").expose(3000);
+//!     let caddy = Caddy::new().reverse_proxy(app.upstream());
+//!
+//!     Pipeline::new(app, caddy)
+//!         .ship_logs(&LogShipping::bundled_loki())
+//!         .deploy(DockerSaveLoad::new())
+//!         .run()?;
+//!     Ok(())
+//! }
+//! ```
+//!
 //! ## Cloudflare DNS
 //!
 //! Use Cloudflare instead of OVH for DNS management.
@@ -410,6 +557,13 @@
 //! Catapulta reads the `.pub` sibling and injects it into the
 //! VM via cloud-init.
 //!
+//! ### Data disks
+//!
+//! `.data_disk(size_gib, mount_path)` attaches an extra disk,
+//! formatted and mounted by cloud-init. Bind app volumes under
+//! its mount path: `destroy` keeps the disk and a VM provisioned
+//! again under the same name gets it back with its data.
+//!
 //! ### Complete example
 //!
 //! ```rust,no_run
@@ -436,7 +590,8 @@
 //!                 .network(NetworkMode::Bridged("br0".into()))
 //!                 .vcpus(2)
 //!                 .memory_mib(2048)
-//!                 .disk_gib(20),
+//!                 .disk_gib(20)
+//!                 .data_disk(50, "/srv/data"),
 //!         )
 //!         .deploy(DockerSaveLoad::new());
 //!
@@ -456,9 +611,21 @@
 //!
 //! **SSH connection timeout:**
 //! The VM may still be booting. Catapulta retries automatically
-//! (30 attempts, 10 seconds apart). If it still fails, SSH to
-//! the hypervisor and check the VM console:
-//! `virsh console <vm-name>`.
+//! (30 attempts, 10 seconds apart by default; raise it with
+//! `.ssh_options(SshOptions::new().retries(..))` on the
+//! [`Pipeline`]). If it still fails, SSH to the hypervisor and
+//! check the VM console: `virsh console <vm-name>`.
+//!
+//! **NAT VM unreachable from your machine:**
+//! NAT VMs are only routable from the hypervisor. Tunnel through
+//! it with `.jump_host("hypervisor.local", "root", None)` on the
+//! [`Pipeline`].
+//!
+//! **Checksum mismatch (E306):**
+//! The image downloaded on the hypervisor doesn't have the
+//! SHA256 published in `SHA256SUMS` (or pinned with
+//! `.image_sha256(..)`). Retry; if the image was replaced
+//! upstream, pin its new checksum.
 //!
 //! **"virsh: command not found":**
 //! libvirt is not installed on the hypervisor. See the setup
@@ -481,28 +648,60 @@
 )]
 
 pub mod app;
+pub mod audit;
 pub mod caddy;
 pub mod caddyfile;
+pub mod caddyjson;
+pub mod ci;
 pub mod cmd;
 pub mod compose;
+pub mod config;
 pub mod deploy;
 pub mod dns;
 pub mod error;
+pub mod events;
+pub mod firewall;
+pub mod logs;
+pub mod otel;
 pub mod pipeline;
 pub mod provision;
+pub mod registry;
+pub mod report;
+pub mod rollback;
+pub mod scale;
+pub mod scan;
+pub mod shared_caddy;
+pub mod sign;
 pub mod ssh;
+pub mod state;
+pub mod stats;
+pub mod status;
+pub mod testing;
+pub mod timing;
 
 pub use app::App;
+pub use app::EnvVar;
+pub use app::Job;
 pub use app::Upstream;
 pub use caddy::Caddy;
+pub use deploy::Runtime;
+pub use deploy::do_registry::DoRegistry;
+pub use deploy::docker_context::DockerContext;
 pub use deploy::docker_save::DockerSaveLoad;
+pub use deploy::docker_save::{Compression, Transfer};
 pub use deploy::local::LocalDeploy;
+pub use dns::adguard::AdGuardHome;
 pub use dns::cloudflare::Cloudflare;
+pub use dns::duckdns::DuckDns;
 pub use dns::ovh::Ovh;
 pub use dns::ovh::OvhCredentials;
 pub use dns::ovh::parse_ini_value;
+pub use dns::pihole::Pihole;
 pub use pipeline::Pipeline;
 pub use provision::digitalocean::DigitalOcean;
+pub use provision::existing::ExistingServer;
 pub use provision::libvirt::Libvirt;
 pub use provision::libvirt::NetworkMode;
+pub use provision::proxmox::Proxmox;
 pub use provision::remove_ssh_host_entry;
+pub use provision::scaleway::Scaleway;
@@ -0,0 +1,182 @@
+//! Log shipping addon.
+//!
+//! [`LogShipping`] describes where container logs go. Passed to
+//! [`crate::Pipeline::ship_logs`], it adds a Vector container
+//! that tails the stack's containers (Caddy included) through the
+//! Docker socket and forwards every line to Loki or
+//! Elasticsearch, so logs outlive container restarts and can be
+//! searched.
+
+use serde_json::{Value, json};
+
+use crate::app::App;
+
+/// Image of the log shipper.
+const VECTOR_IMAGE: &str = "timberio/vector:latest-alpine";
+
+/// Image of the bundled Loki.
+const LOKI_IMAGE: &str = "grafana/loki:latest";
+
+/// Where shipped logs are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogSink {
+    /// A Loki push endpoint (base URL, e.g.
+    /// `https://logs.example.com`).
+    Loki(String),
+    /// An Elasticsearch endpoint, one index per day.
+    Elasticsearch(String),
+    /// A Loki container deployed alongside the stack, storing
+    /// logs in the `loki-data` volume.
+    BundledLoki,
+}
+
+/// Log shipping configuration.
+///
+/// # Example
+///
+/// ```
+/// use catapulta::logs::LogShipping;
+///
+/// let logs = LogShipping::loki("https://logs.example.com")
+///     .basic_auth("stack", "LOKI_PASSWORD")
+///     .env_file("deploy/.env.vector");
+///
+/// let apps = logs.apps("my-service", &["my-service", "my-service-caddy"]);
+///
+/// assert_eq!(apps[0].name, "vector");
+/// assert!(apps[0].config_files[0].content.contains("logs.example.com"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LogShipping {
+    pub sink: LogSink,
+    /// Basic auth user and the environment variable holding the
+    /// password.
+    pub basic_auth: Option<(String, String)>,
+    pub env_file: Option<String>,
+}
+
+impl LogShipping {
+    #[must_use]
+    pub const fn new(sink: LogSink) -> Self {
+        Self {
+            sink,
+            basic_auth: None,
+            env_file: None,
+        }
+    }
+
+    /// Ship to an existing Loki at `url`.
+    #[must_use]
+    pub fn loki(url: &str) -> Self {
+        Self::new(LogSink::Loki(url.to_string()))
+    }
+
+    /// Ship to an existing Elasticsearch at `url`.
+    #[must_use]
+    pub fn elasticsearch(url: &str) -> Self {
+        Self::new(LogSink::Elasticsearch(url.to_string()))
+    }
+
+    /// Deploy a Loki container next to the stack and ship to it.
+    /// Reach it with `cargo xtask tunnel <host> 3100:loki:3100`.
+    #[must_use]
+    pub const fn bundled_loki() -> Self {
+        Self::new(LogSink::BundledLoki)
+    }
+
+    /// Authenticate to the sink as `user`, with the password
+    /// read from `password_env` in the shipper's environment.
+    ///
+    /// Provide the variable through [`LogShipping::env_file`] so
+    /// the password never lands in the compose file.
+    #[must_use]
+    pub fn basic_auth(mut self, user: &str, password_env: &str) -> Self {
+        self.basic_auth = Some((user.to_string(), password_env.to_string()));
+        self
+    }
+
+    /// Env file for the shipper container (e.g. holding the
+    /// [`LogShipping::basic_auth`] password).
+    #[must_use]
+    pub fn env_file(mut self, path: &str) -> Self {
+        self.env_file = Some(path.to_string());
+        self
+    }
+
+    /// Render the Vector configuration shipping the logs of
+    /// `containers`, labelled with `stack`.
+    #[must_use]
+    pub fn vector_config(&self, stack: &str, containers: &[&str]) -> String {
+        let mut sink = match &self.sink {
+            LogSink::Loki(url) => loki_sink(url, stack),
+            LogSink::BundledLoki => loki_sink("http://loki:3100", stack),
+            LogSink::Elasticsearch(url) => json!({
+                "type": "elasticsearch",
+                "inputs": ["containers"],
+                "endpoints": [url],
+                "bulk": { "index": format!("{stack}-%Y.%m.%d") },
+            }),
+        };
+        if let Some((user, password_env)) = &self.basic_auth {
+            sink["auth"] = json!({
+                "strategy": "basic",
+                "user": user,
+                "password": format!("${{{password_env}}}"),
+            });
+        }
+
+        let config = json!({
+            "data_dir": "/var/lib/vector",
+            "sources": {
+                "containers": {
+                    "type": "docker_logs",
+                    "include_containers": containers,
+                },
+            },
+            "sinks": { "logs": sink },
+        });
+        serde_yaml::to_string(&config).expect("failed to serialize vector config")
+    }
+
+    /// The containers to add to a stack: Vector, plus Loki for
+    /// [`LogSink::BundledLoki`].
+    #[must_use]
+    pub fn apps(&self, stack: &str, containers: &[&str]) -> Vec<App> {
+        let mut vector = App::new("vector")
+            .image(VECTOR_IMAGE)
+            .volume("/var/run/docker.sock", "/var/run/docker.sock:ro")
+            .volume("vector-data", "/var/lib/vector")
+            .config_file(
+                "vector.yaml",
+                &self.vector_config(stack, containers),
+                "/etc/vector/vector.yaml",
+            );
+        if let Some(env_file) = &self.env_file {
+            vector = vector.env_file(env_file);
+        }
+
+        let mut apps = vec![vector];
+        if self.sink == LogSink::BundledLoki {
+            apps.push(
+                App::new("loki")
+                    .image(LOKI_IMAGE)
+                    .volume("loki-data", "/loki")
+                    .expose(3100),
+            );
+        }
+        apps
+    }
+}
+
+fn loki_sink(url: &str, stack: &str) -> Value {
+    json!({
+        "type": "loki",
+        "inputs": ["containers"],
+        "endpoint": url,
+        "encoding": { "codec": "json" },
+        "labels": {
+            "stack": stack,
+            "container": "{{ container_name }}",
+        },
+    })
+}
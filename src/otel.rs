@@ -0,0 +1,253 @@
+//! OpenTelemetry traces of the pipeline commands.
+//!
+//! When [`ENDPOINT_ENV`] (or [`TRACES_ENDPOINT_ENV`]) is set,
+//! each command is exported as a trace over OTLP/HTTP with the
+//! JSON encoding: a root span named after the command, a child
+//! span per phase reported with [`DeployEvent::PhaseStarted`]
+//! (`provision`, `DNS update`, `build`, `transfer image`,
+//! `deploy`, ...), and a `healthcheck` span within the phase
+//! polling the containers' health. The phase a command failed
+//! in, and the command, end with an error status.
+//!
+//! The service is named by [`SERVICE_NAME_ENV`] (default
+//! `catapulta`), and [`HEADERS_ENV`] adds headers to the export
+//! (e.g. `authorization=Bearer ...`). A failed export only
+//! prints a warning.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+use crate::audit;
+use crate::cmd;
+use crate::events::DeployEvent;
+
+/// Base URL of the OTLP/HTTP collector, e.g.
+/// `http://localhost:4318`; traces go to `/v1/traces` under it.
+pub const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Full URL traces are sent to, taking precedence over
+/// [`ENDPOINT_ENV`].
+pub const TRACES_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+
+/// Name of the service in the exported resource.
+pub const SERVICE_NAME_ENV: &str = "OTEL_SERVICE_NAME";
+
+/// Extra headers of the export, as `key=value` pairs separated
+/// by commas.
+pub const HEADERS_ENV: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+
+/// A span of a [`Trace`]. Times are Unix times in nanoseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub name: String,
+    pub span_id: String,
+    /// `None` for the root span of the command.
+    pub parent_span_id: Option<String>,
+    pub start: u64,
+    /// `None` while the span is open.
+    pub end: Option<u64>,
+    /// The host the span ran on.
+    pub host: Option<String>,
+    /// Whether the span ended with an error.
+    pub failed: bool,
+}
+
+/// The spans of one pipeline command, built from its events.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub trace_id: String,
+    pub spans: Vec<Span>,
+    /// Index of the open phase span.
+    phase: Option<usize>,
+    /// Index of the open healthcheck span.
+    healthcheck: Option<usize>,
+}
+
+impl Trace {
+    /// A trace of `command` starting now.
+    #[must_use]
+    pub fn new(command: &str) -> Self {
+        Self::starting_at(command, now())
+    }
+
+    /// A trace of `command` starting at `start`.
+    #[must_use]
+    pub fn starting_at(command: &str, start: u64) -> Self {
+        let trace_id = format!("{:016x}{:016x}", random_id(), random_id());
+        Self {
+            trace_id,
+            spans: vec![Span {
+                name: command.to_string(),
+                span_id: format!("{:016x}", random_id()),
+                parent_span_id: None,
+                start,
+                end: None,
+                host: None,
+                failed: false,
+            }],
+            phase: None,
+            healthcheck: None,
+        }
+    }
+
+    /// Record `event`, happening now.
+    pub fn record(&mut self, event: &DeployEvent) {
+        self.record_at(event, now());
+    }
+
+    /// Record `event`, happening at `at`.
+    pub fn record_at(&mut self, event: &DeployEvent, at: u64) {
+        match event {
+            DeployEvent::PhaseStarted { phase, host } => {
+                self.close_phase(at, false);
+                let parent = self.spans[0].span_id.clone();
+                self.phase = Some(self.open(phase, parent, at, host.clone()));
+            }
+            DeployEvent::HealthcheckAttempt { .. } => {
+                let i = self.healthcheck.unwrap_or_else(|| {
+                    let parent = self.phase.unwrap_or(0);
+                    let host = self.spans[parent].host.clone();
+                    let parent = self.spans[parent].span_id.clone();
+                    self.open("healthcheck", parent, at, host)
+                });
+                // Ends at the last attempt, not when the phase
+                // moves on
+                self.spans[i].end = Some(at);
+                self.healthcheck = Some(i);
+            }
+            DeployEvent::Done { success } => {
+                self.close_phase(at, !success);
+                let root = &mut self.spans[0];
+                root.end = Some(at);
+                root.failed = !success;
+            }
+            DeployEvent::ImageBuilt { .. } | DeployEvent::TransferProgress { .. } => {}
+        }
+    }
+
+    fn open(&mut self, name: &str, parent: String, at: u64, host: Option<String>) -> usize {
+        self.spans.push(Span {
+            name: name.to_string(),
+            span_id: format!("{:016x}", random_id()),
+            parent_span_id: Some(parent),
+            start: at,
+            end: None,
+            host,
+            failed: false,
+        });
+        self.spans.len() - 1
+    }
+
+    /// End the open phase (and its healthcheck) at `at`.
+    fn close_phase(&mut self, at: u64, failed: bool) {
+        self.healthcheck = None;
+        if let Some(i) = self.phase.take() {
+            self.spans[i].end = Some(at);
+            self.spans[i].failed = failed;
+        }
+    }
+
+    /// The trace as an OTLP `ExportTraceServiceRequest` in JSON,
+    /// from the service `service`. Spans still open end at the
+    /// end of the root span.
+    #[must_use]
+    pub fn to_otlp(&self, service: &str) -> Value {
+        let last = self.spans[0].end.unwrap_or_else(now);
+        let spans: Vec<Value> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let mut value = json!({
+                    "traceId": self.trace_id,
+                    "spanId": span.span_id,
+                    "name": span.name,
+                    "kind": 1,
+                    "startTimeUnixNano": span.start.to_string(),
+                    "endTimeUnixNano": span.end.unwrap_or(last).to_string(),
+                    "status": {"code": if span.failed { 2 } else { 1 }},
+                });
+                if let Some(parent) = &span.parent_span_id {
+                    value["parentSpanId"] = json!(parent);
+                }
+                if let Some(host) = &span.host {
+                    value["attributes"] = json!([attribute("host.name", host)]);
+                }
+                value
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": {"attributes": [attribute("service.name", service)]},
+                "scopeSpans": [{
+                    "scope": {"name": "catapulta", "version": env!("CARGO_PKG_VERSION")},
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+}
+
+/// An OTLP string attribute.
+fn attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// The URL traces are exported to, if exporting is on.
+#[must_use]
+pub fn endpoint() -> Option<String> {
+    let non_empty = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+    non_empty(TRACES_ENDPOINT_ENV).or_else(|| {
+        non_empty(ENDPOINT_ENV).map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+    })
+}
+
+/// The headers of [`HEADERS_ENV`] (`key=value,...`), as
+/// `key: value` lines.
+#[must_use]
+pub fn parse_headers(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| format!("{}: {}", key.trim(), value.trim()))
+        .collect()
+}
+
+/// Send `trace` to the collector, warning when that fails.
+pub fn export(trace: &Trace) {
+    let Some(url) = endpoint() else {
+        return;
+    };
+    let service = std::env::var(SERVICE_NAME_ENV).unwrap_or_else(|_| "catapulta".to_string());
+    let headers = std::env::var(HEADERS_ENV)
+        .map(|value| parse_headers(&value))
+        .unwrap_or_default();
+    for header in &headers {
+        if let Some((_, value)) = header.split_once(": ") {
+            audit::register_secret(value);
+        }
+    }
+    let body = trace.to_otlp(&service).to_string();
+    match cmd::http_request("POST", &url, &headers, Some(&body), &[]) {
+        Ok((status, _)) if (200..300).contains(&status) => {}
+        Ok((status, response)) => {
+            eprintln!("Warning: exporting the trace to {url} failed: HTTP {status} {response}");
+        }
+        Err(e) => eprintln!("Warning: exporting the trace to {url} failed: {e}"),
+    }
+}
+
+/// Current Unix time in nanoseconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// A random non-zero id: the standard library seeds each
+/// [`RandomState`] randomly.
+fn random_id() -> u64 {
+    RandomState::new().hash_one(now()).max(1)
+}
@@ -1,16 +1,47 @@
-use clap::{Parser, Subcommand};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 
 use crate::app::App;
+use crate::audit;
 use crate::caddy::Caddy;
 use crate::caddyfile;
+use crate::ci::{self, Workflow};
 use crate::cmd;
 use crate::compose;
-use crate::deploy::Deployer;
-use crate::deploy::local::LocalDeploy;
-use crate::dns::DnsProvider;
-use crate::error::{DeployError, DeployResult};
-use crate::provision::Provisioner;
-use crate::ssh::SshSession;
+use crate::config::Config;
+use crate::deploy::local::{self, LocalDeploy};
+use crate::deploy::{
+    self, Deployer, HealthWait, Runtime, lint, preflight, wait_healthy_or_report,
+    without_health_wait,
+};
+use crate::dns::{self, DnsProvider, DnsPurpose};
+use crate::error::{DeployError, DeployResult, ResultExt};
+use crate::events::{self, DeployEvent, Listener};
+use crate::firewall;
+use crate::logs::LogShipping;
+use crate::otel::{self, Trace};
+use crate::provision::{ProvisionOverrides, Provisioner, ServerInfo};
+use crate::registry::RegistryAuth;
+use crate::report::{self, HostReport, Recorder, ReportFormat};
+use crate::rollback;
+use crate::scale::{self, Replicas};
+use crate::scan::{ImageScan, ScanReport};
+use crate::shared_caddy;
+use crate::ssh::tunnel::PortForward;
+use crate::ssh::{FileAttrs, SshOptions, SshSession, shell_quote};
+use crate::state::{
+    self, DeploymentRecord, DnsRecord, LocalState, ServerRecord, State, StateStore, TrailEntry,
+};
+use crate::stats;
+use crate::status;
+use crate::timing::{self, Timings};
 
 /// Action to run on the remote host after deployment.
 enum PostDeployHook {
@@ -24,8 +55,18 @@ enum PostDeployHook {
     },
     /// Execute a shell command on the remote host.
     Exec(String),
+    /// Purge the CDN cache of the DNS providers' domains.
+    PurgeCache(Vec<String>),
 }
 
+/// Directory of the stack on the server when neither
+/// [`Pipeline::remote_dir`] nor [`Pipeline::project`] is set.
+pub const DEFAULT_REMOTE_DIR: &str = "/opt/app";
+
+/// Environment variable confirming a deploy or destroy of a
+/// [`Pipeline::protect`]ed environment, like `--confirm`.
+pub const CONFIRM_ENV: &str = "CATAPULTA_CONFIRM";
+
 /// Deployment pipeline orchestrating provisioning, DNS, and
 /// deployment.
 pub struct Pipeline {
@@ -33,11 +74,22 @@ pub struct Pipeline {
     caddy: Caddy,
     provisioner: Option<Box<dyn Provisioner>>,
     dns: Vec<Box<dyn DnsProvider>>,
+    caa_dns: Vec<Box<dyn DnsProvider>>,
     deployer: Option<Box<dyn Deployer>>,
     remote_dir: String,
     ssh_user: String,
+    ssh: SshOptions,
     post_deploy: Vec<PostDeployHook>,
     local_dir: String,
+    listeners: Vec<Listener>,
+    metrics_file: Option<String>,
+    report_file: Option<String>,
+    keep_images: u32,
+    registries: Vec<RegistryAuth>,
+    project: Option<String>,
+    state_store: Option<Box<dyn StateStore>>,
+    protected: Option<String>,
+    image_scan: Option<ImageScan>,
 }
 
 impl Pipeline {
@@ -49,11 +101,22 @@ impl Pipeline {
             caddy,
             provisioner: None,
             dns: Vec::new(),
+            caa_dns: Vec::new(),
             deployer: None,
-            remote_dir: "/opt/app".to_string(),
+            remote_dir: DEFAULT_REMOTE_DIR.to_string(),
             ssh_user: "root".to_string(),
+            ssh: SshOptions::default(),
             post_deploy: Vec::new(),
             local_dir: ".catapulta".to_string(),
+            listeners: Vec::new(),
+            metrics_file: None,
+            report_file: None,
+            keep_images: rollback::DEFAULT_KEEP,
+            registries: Vec::new(),
+            project: None,
+            state_store: None,
+            protected: None,
+            image_scan: None,
         }
     }
 
@@ -66,14 +129,43 @@ impl Pipeline {
             caddy,
             provisioner: None,
             dns: Vec::new(),
+            caa_dns: Vec::new(),
             deployer: None,
-            remote_dir: "/opt/app".to_string(),
+            remote_dir: DEFAULT_REMOTE_DIR.to_string(),
             ssh_user: "root".to_string(),
+            ssh: SshOptions::default(),
             post_deploy: Vec::new(),
             local_dir: ".catapulta".to_string(),
+            listeners: Vec::new(),
+            metrics_file: None,
+            report_file: None,
+            keep_images: rollback::DEFAULT_KEEP,
+            registries: Vec::new(),
+            project: None,
+            state_store: None,
+            protected: None,
+            image_scan: None,
         }
     }
 
+    /// Create a pipeline from a TOML (or, for `.yaml`/`.yml`,
+    /// YAML) config file; see [`crate::config`] for the format.
+    ///
+    /// The pipeline deploys with [`crate::DockerSaveLoad`] and
+    /// can be extended with the builder methods like any other.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::Pipeline;
+    ///
+    /// Pipeline::from_config("catapulta.toml")?
+    ///     .after_deploy("docker image prune -f")
+    ///     .run()?;
+    /// # Ok::<(), catapulta::error::DeployError>(())
+    /// ```
+    pub fn from_config(path: &str) -> DeployResult<Self> {
+        Config::load(path)?.into_pipeline()
+    }
+
     #[must_use]
     pub fn provision(mut self, provisioner: impl Provisioner + 'static) -> Self {
         self.provisioner = Some(Box::new(provisioner));
@@ -86,12 +178,157 @@ impl Pipeline {
         self
     }
 
+    /// Attach `provider` for one `purpose` only, for zones split
+    /// across services: e.g. the A records at the registrar, and
+    /// the ACME DNS-01 challenge through the Cloudflare zone of a
+    /// subdomain. [`DnsPurpose::Records`] is the same as
+    /// [`Self::dns`].
+    ///
+    /// [`DnsPurpose::Challenge`] sets [`Caddy::dns_challenge`]
+    /// from the provider's Caddy module, with the token read from
+    /// its environment variable now; the Caddy image still needs
+    /// the module (see [`Caddy::image`]).
+    ///
+    /// ```rust,no_run
+    /// use catapulta::dns::DnsPurpose;
+    /// use catapulta::{App, Caddy, Cloudflare, Ovh, Pipeline};
+    ///
+    /// let app = App::new("api").expose(8000);
+    /// let caddy = Caddy::new()
+    ///     .reverse_proxy(app.upstream())
+    ///     .image("ghcr.io/example/caddy-cloudflare:2");
+    /// Pipeline::new(app, caddy)
+    ///     .dns(Ovh::new("api.example.com"))
+    ///     .dns_for(DnsPurpose::Caa, Ovh::new("api.example.com"))
+    ///     .dns_for(DnsPurpose::Challenge, Cloudflare::new("api.example.com"));
+    /// ```
+    #[must_use]
+    pub fn dns_for(mut self, purpose: DnsPurpose, provider: impl DnsProvider + 'static) -> Self {
+        match purpose {
+            DnsPurpose::Records => self.dns.push(Box::new(provider)),
+            DnsPurpose::Caa => self.caa_dns.push(Box::new(provider)),
+            DnsPurpose::Challenge => match provider.caddy_module() {
+                Some((module, token_env)) => {
+                    let token = std::env::var(token_env).unwrap_or_else(|_| {
+                        eprintln!(
+                            "Warning: {token_env} not set, needed for the Caddy DNS-01 challenge"
+                        );
+                        String::new()
+                    });
+                    self.caddy = self.caddy.dns_challenge(module, token_env, &token);
+                }
+                None => eprintln!(
+                    "Warning: the DNS provider of {} has no Caddy module \
+                     for the DNS-01 challenge",
+                    provider.domain()
+                ),
+            },
+        }
+        self
+    }
+
     #[must_use]
     pub fn deploy(mut self, deployer: impl Deployer + 'static) -> Self {
         self.deployer = Some(Box::new(deployer));
         self
     }
 
+    /// Name the compose project of the stack (default: the name
+    /// of the remote directory), so several stacks can share a
+    /// server from different remote directories. Container names
+    /// and the stack's network are prefixed with it, and deploys
+    /// refuse to take over the containers, images, project or
+    /// ports of another stack.
+    ///
+    /// Unless set with [`Self::remote_dir`], the stack lives in
+    /// `/opt/<name>` instead of [`DEFAULT_REMOTE_DIR`], created by
+    /// the first deploy.
+    ///
+    /// Setting it on a deployed stack recreates its containers
+    /// and volumes under the new project; move the data first.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::{App, Caddy, Pipeline};
+    ///
+    /// let db = App::new("postgres").image("postgres:17");
+    /// Pipeline::new(db, Caddy::new()).project("billing");
+    /// ```
+    #[must_use]
+    pub fn project(mut self, name: &str) -> Self {
+        self.project = Some(name.to_string());
+        if self.remote_dir == DEFAULT_REMOTE_DIR {
+            self.remote_dir = format!("/opt/{name}");
+        }
+        self
+    }
+
+    /// Keep each app's env and config files in a subdirectory
+    /// named after it, unless it has its own
+    /// [`App::remote_dir`]. Files of the previous layout are
+    /// removed by the next deploy.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::{App, Caddy, Pipeline};
+    ///
+    /// let api = App::new("api").env_file("deploy/.env.api");
+    /// let worker = App::new("worker").env_file("deploy/.env.worker");
+    /// // /opt/app/api/.env and /opt/app/worker/.env
+    /// Pipeline::multi(vec![api, worker], Caddy::new()).app_dirs();
+    /// ```
+    #[must_use]
+    pub fn app_dirs(mut self) -> Self {
+        for app in &mut self.apps {
+            if app.remote_dir.is_none() {
+                app.remote_dir = Some(app.name.clone());
+            }
+        }
+        self
+    }
+
+    /// Run without Caddy: every app publishes its exposed ports
+    /// on the server (unless it sets its own with [`App::port`]),
+    /// and deploys open them in the firewall. For internal
+    /// services (NATS, databases) where a reverse proxy is pure
+    /// overhead. Routes and app domains are dropped, so call it
+    /// after adding the apps.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::{App, Caddy, Pipeline};
+    ///
+    /// let nats = App::new("nats").image("nats:2").expose(4222);
+    /// Pipeline::new(nats, Caddy::new()).no_proxy();
+    /// ```
+    #[must_use]
+    pub fn no_proxy(mut self) -> Self {
+        self.caddy = Caddy::new();
+        for app in &mut self.apps {
+            app.domain = None;
+            if app.ports.is_empty() {
+                app.ports = app.expose.iter().map(|port| (*port, *port)).collect();
+            }
+        }
+        self
+    }
+
+    /// Keep the `n` images replaced by the last deploys on the
+    /// server (default 1), for `rollback`. 0 keeps none.
+    #[must_use]
+    pub const fn keep_images(mut self, n: u32) -> Self {
+        self.keep_images = n;
+        self
+    }
+
+    /// Log Docker in to a private `registry` as `user`, with the
+    /// token read from the `token_env` environment variable: on
+    /// the local machine before building, and on the server when
+    /// a prebuilt image is pulled from it. See [`crate::registry`].
+    #[must_use]
+    pub fn registry_auth(mut self, registry: &str, user: &str, token_env: &str) -> Self {
+        self.registries
+            .push(RegistryAuth::new(registry, user, token_env));
+        self
+    }
+
     #[must_use]
     pub fn remote_dir(mut self, dir: &str) -> Self {
         self.remote_dir = dir.to_string();
@@ -104,6 +341,83 @@ impl Pipeline {
         self
     }
 
+    /// Replace the SSH connection settings (port, timeouts,
+    /// retries, keepalive, jump host) used for every connection
+    /// the pipeline opens, including server setup.
+    #[must_use]
+    pub fn ssh_options(mut self, options: SshOptions) -> Self {
+        self.ssh = options;
+        self
+    }
+
+    /// Connect to the server on a non-default SSH port.
+    #[must_use]
+    pub fn ssh_port(mut self, port: u16) -> Self {
+        self.ssh = self.ssh.port(port);
+        self
+    }
+
+    /// Reach the server through a bastion host.
+    ///
+    /// Applies to every SSH connection the pipeline opens,
+    /// including those made by the deployer and during server
+    /// setup. `key` selects a private key for the bastion; when
+    /// `None`, the agent and `~/.ssh/config` are used.
+    #[must_use]
+    pub fn jump_host(mut self, host: &str, user: &str, key: Option<&str>) -> Self {
+        self.ssh = self.ssh.jump_host(host, user, key);
+        self
+    }
+
+    /// Keep prebuilt images up to date between deploys.
+    ///
+    /// Adds a Watchtower sidecar to the stack that checks daily
+    /// for new versions of Caddy and of every app running an
+    /// [`App::image`], and restarts them on the new image.
+    /// Locally built apps and containers outside the stack are
+    /// never touched.
+    #[must_use]
+    pub fn auto_update(mut self) -> Self {
+        for app in &mut self.apps {
+            if app.image.is_some() {
+                app.auto_update = true;
+            }
+        }
+        self.caddy.auto_update = true;
+        self
+    }
+
+    /// Ship the logs of every container in the stack to the sink
+    /// described by `shipping`, and turn on Caddy's access log
+    /// for the main site.
+    ///
+    /// Call after the apps, Caddy and [`Self::project`] are
+    /// configured: only the containers known at this point are
+    /// shipped.
+    #[must_use]
+    pub fn ship_logs(mut self, shipping: &LogShipping) -> Self {
+        let (stack, containers) = compose::with_project(self.project.as_deref(), || {
+            let stack = compose::stack_name(&self.apps);
+            let mut containers: Vec<String> = self
+                .apps
+                .iter()
+                .map(|a| compose::container_name(&a.name))
+                .collect();
+            if compose::needs_caddy(&self.apps, &self.caddy) {
+                containers.push(format!("{stack}-caddy"));
+            }
+            (stack, containers)
+        });
+        let containers: Vec<&str> = containers.iter().map(String::as_str).collect();
+        let addon = shipping.apps(&stack, &containers);
+        self.apps.extend(addon);
+
+        if self.caddy.has_upstreams() && !self.caddy.extra_directives.iter().any(|d| d == "log") {
+            self.caddy = self.caddy.directive("log");
+        }
+        self
+    }
+
     /// Upload a local file to the remote host after deployment.
     ///
     /// The remote path can be absolute or relative to the remote
@@ -145,12 +459,166 @@ impl Pipeline {
         self
     }
 
+    /// Purge the CDN cache of the DNS providers' domains after
+    /// deployment, so new frontend assets are served right away.
+    ///
+    /// `paths` (e.g. `/assets/app.js`) are purged on every domain;
+    /// with none, the whole zone is. Only [`crate::Cloudflare`]
+    /// supports it, for proxied records. Skipped during
+    /// `--dry-run`.
+    #[must_use]
+    pub fn purge_cache(mut self, paths: &[&str]) -> Self {
+        self.post_deploy.push(PostDeployHook::PurgeCache(
+            paths.iter().map(|p| (*p).to_string()).collect(),
+        ));
+        self
+    }
+
     #[must_use]
     pub fn local_dir(mut self, dir: &str) -> Self {
         self.local_dir = dir.to_string();
         self
     }
 
+    /// Keep the servers, DNS records and deployments (see
+    /// [`crate::state`]) in `store` instead of `servers.json`
+    /// in [`Pipeline::local_dir`], e.g. to share them with a
+    /// team.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::state::S3State;
+    /// use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+    ///
+    /// let app = App::new("my-service").expose(3000);
+    /// let caddy = Caddy::new().reverse_proxy(app.upstream());
+    /// Pipeline::new(app, caddy)
+    ///     .deploy(DockerSaveLoad::new())
+    ///     .state_store(S3State::new("acme-ops", "catapulta/servers.json"));
+    /// ```
+    #[must_use]
+    pub fn state_store(mut self, store: impl StateStore + 'static) -> Self {
+        self.state_store = Some(Box::new(store));
+        self
+    }
+
+    /// Mark the pipeline as the protected environment `name`
+    /// (e.g. `production`).
+    ///
+    /// `deploy` and `destroy` then stop unless the name is typed
+    /// again with `--confirm <name>`, or [`CONFIRM_ENV`] holds it
+    /// (e.g. set by an approved CI job), so a deploy run from
+    /// muscle memory doesn't reach production. Dry runs need no
+    /// confirmation.
+    ///
+    /// ```rust,no_run
+    /// use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+    ///
+    /// let app = App::new("my-service").expose(3000);
+    /// let caddy = Caddy::new().reverse_proxy(app.upstream());
+    /// Pipeline::new(app, caddy)
+    ///     .deploy(DockerSaveLoad::new())
+    ///     .protect("production");
+    /// ```
+    #[must_use]
+    pub fn protect(mut self, name: &str) -> Self {
+        self.protected = Some(name.to_string());
+        self
+    }
+
+    /// Scan the images built for a deploy for vulnerabilities
+    /// before transferring them, failing the deploy above the
+    /// threshold of `scan`; see [`crate::scan`].
+    #[must_use]
+    pub const fn scan_images(mut self, scan: ImageScan) -> Self {
+        self.image_scan = Some(scan);
+        self
+    }
+
+    /// Append a JSON line with the phase durations of every
+    /// deploy to `path` (e.g. `.catapulta/metrics.jsonl`), to
+    /// track deploy times across runs.
+    #[must_use]
+    pub fn metrics_file(mut self, path: &str) -> Self {
+        self.metrics_file = Some(path.to_string());
+        self
+    }
+
+    /// Write a report of every deploy to `path` (e.g.
+    /// `deploy-report.md`, or `.html`), to attach to a pull
+    /// request or a release page; see [`crate::report`].
+    #[must_use]
+    pub fn report_file(mut self, path: &str) -> Self {
+        self.report_file = Some(path.to_string());
+        self
+    }
+
+    /// Write the report of the deploys to `hosts` to the report
+    /// file, warning when that fails.
+    fn write_report(&self, hosts: &[HostReport]) {
+        let Some(path) = &self.report_file else {
+            return;
+        };
+        let report = report::render(hosts, ReportFormat::for_path(path));
+        match std::fs::write(path, report) {
+            Ok(()) => eprintln!("Deploy report written to {path}"),
+            Err(e) => eprintln!("Warning: cannot write the deploy report to {path}: {e}"),
+        }
+    }
+
+    /// The last successful deploy to `host` in the state.
+    fn last_deployment(&self, host: &str) -> Option<DeploymentRecord> {
+        let state = self.with_state_store(|store| store.load()).ok()?;
+        state
+            .deployments
+            .into_iter()
+            .rev()
+            .find(|d| d.host == host && d.success)
+    }
+
+    /// Print the timing summary of a deploy and append it to
+    /// the metrics file, if any.
+    fn report_timings(&self, timings: &Timings, command: &str, host: &str, success: bool) {
+        eprintln!();
+        eprint!("{}", timings.summary());
+        let Some(path) = &self.metrics_file else {
+            return;
+        };
+        let line = timings.to_json_line(command, host, success);
+        let appended = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut f| writeln!(f, "{line}"));
+        if let Err(e) = appended {
+            eprintln!("Warning: cannot write metrics to {path}: {e}");
+        }
+    }
+
+    /// Call `listener` with a [`DeployEvent`] as the pipeline
+    /// progresses (phases, image builds, transfers, healthchecks).
+    ///
+    /// ```rust,no_run
+    /// use catapulta::events::DeployEvent;
+    /// use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+    ///
+    /// let app = App::new("my-service").expose(3000);
+    /// let caddy = Caddy::new().reverse_proxy(app.upstream());
+    /// Pipeline::new(app, caddy)
+    ///     .deploy(DockerSaveLoad::new())
+    ///     .on_event(|e| {
+    ///         if let DeployEvent::TransferProgress { app, pct } = e {
+    ///             println!("{app}: {pct}%");
+    ///         }
+    ///     })
+    ///     .run()?;
+    /// # Ok::<(), catapulta::error::DeployError>(())
+    /// ```
+    #[must_use]
+    pub fn on_event(mut self, listener: impl Fn(DeployEvent) + 'static) -> Self {
+        self.listeners.push(Rc::new(listener));
+        self
+    }
+
     /// Validate that all `--only` names match configured apps.
     fn validate_only(&self, only: &[String]) -> DeployResult<()> {
         for name in only {
@@ -167,6 +635,31 @@ impl Pipeline {
         Ok(())
     }
 
+    /// Validate that every `--profile` names a profile of at
+    /// least one configured app.
+    fn validate_profiles(&self, profiles: &[String]) -> DeployResult<()> {
+        let mut known: Vec<&str> = self
+            .apps
+            .iter()
+            .flat_map(|a| a.profiles.iter().map(String::as_str))
+            .collect();
+        known.sort_unstable();
+        known.dedup();
+        for name in profiles {
+            if !known.contains(&name.as_str()) {
+                return Err(DeployError::Other(format!(
+                    "unknown profile '{name}'. Known profiles: {}",
+                    if known.is_empty() {
+                        "none".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                )));
+            }
+        }
+        Ok(())
+    }
+
     /// Return apps filtered by `--only`, or all apps when empty.
     fn selected_apps(&self, only: &[String]) -> Vec<&App> {
         if only.is_empty() {
@@ -179,6 +672,253 @@ impl Pipeline {
         }
     }
 
+    /// DNS providers for apps with their own domain, each derived
+    /// from the configured provider managing the same zone.
+    fn app_dns(&self) -> Vec<Box<dyn DnsProvider>> {
+        let mut providers = Vec::new();
+        for domain in self.apps.iter().filter_map(|a| a.domain.as_deref()) {
+            if self.dns.iter().any(|p| p.domain() == domain) {
+                continue;
+            }
+            let (zone, _) = dns::split_domain(domain);
+            let provider = self
+                .dns
+                .iter()
+                .find(|p| dns::split_domain(p.domain()).0 == zone)
+                .and_then(|p| p.for_domain(domain));
+            match provider {
+                Some(provider) => providers.push(provider),
+                None => eprintln!(
+                    "Warning: no DNS provider manages {zone}; \
+                     create the A record for {domain} manually"
+                ),
+            }
+        }
+        providers
+    }
+
+    /// Open an SSH session to `host` with the pipeline's user and
+    /// connection settings.
+    ///
+    /// With `trust_new_hostkey`, the pinned host key is dropped
+    /// first so the server's current key gets pinned instead.
+    /// Otherwise a host without a pinned key is rejected.
+    fn session(&self, host: &str, trust_new_hostkey: bool) -> SshSession {
+        if !trust_new_hostkey {
+            return SshSession::new(host, &self.ssh_user).with_options(&self.ssh);
+        }
+        eprintln!("Trusting new host key for {host}");
+        let ssh = SshSession::new(host, &self.ssh_user)
+            .with_options(&self.ssh.clone().trust_new_host_key());
+        ssh.forget_host_key();
+        ssh
+    }
+
+    /// Container engine on the servers: the deployer's, Docker
+    /// without one.
+    fn runtime(&self) -> Runtime {
+        self.deployer
+            .as_ref()
+            .map_or(Runtime::Docker, |deployer| deployer.runtime())
+    }
+
+    /// The host to connect to for the `host` argument of a
+    /// command.
+    ///
+    /// A server name recorded by `provision` (see
+    /// [`crate::state`]) becomes its domain or IP. Other names
+    /// without dots are looked up with the provisioner, if any.
+    /// Anything else is used as is.
+    fn resolve_host(&self, host: &str) -> String {
+        let state = self
+            .with_state_store(|store| store.load())
+            .unwrap_or_else(|e| {
+                eprintln!("Warning: cannot read the server state: {e}");
+                State::default()
+            });
+        if let Some(resolved) = state.host(host) {
+            if resolved != host {
+                eprintln!("Server '{host}' is {resolved}");
+            }
+            return resolved.to_string();
+        }
+        let lookup = !host.contains(['.', ':']);
+        if let Some(provisioner) = self.provisioner.as_ref().filter(|_| lookup) {
+            if let Ok(Some(server)) = provisioner.get_server(host) {
+                eprintln!("Server '{host}' is {}", server.ip);
+                return server.ip;
+            }
+        }
+        host.to_string()
+    }
+
+    /// Record the server `name` in the state, warning when the
+    /// state can't be saved (see [`Pipeline::save_server`]).
+    fn record_server(&self, name: &str, ip: &str, domain: Option<&str>) -> ServerRecord {
+        self.save_server(name, ip, domain).unwrap_or_else(|e| {
+            eprintln!("Warning: cannot record server '{name}': {e}");
+            ServerRecord {
+                ip: ip.to_string(),
+                domain: domain.map(ToString::to_string),
+            }
+        })
+    }
+
+    /// Pin the host key of `server` under its IP and its domain,
+    /// which is what `deploy <name>` connects to.
+    fn pin_host_key(&self, server: &ServerRecord) -> DeployResult<()> {
+        let aliases: Vec<&str> = server.domain.iter().map(String::as_str).collect();
+        SshSession::new(&server.ip, &self.ssh_user)
+            .with_options(&self.ssh.clone().trust_new_host_key())
+            .pin_host_key(&aliases)
+    }
+
+    /// Remove the host keys known for the server `name` when it
+    /// is now at `ip` rather than the IP recorded for it, since
+    /// they belong to the server it replaced. Runs before the new
+    /// server's key is pinned, so that it is kept.
+    fn clear_replaced_host_keys(&self, name: &str, ip: &str, domain: Option<&str>) {
+        let stale = self.update_state(|state| {
+            let previous = state.servers.get(name).filter(|p| p.ip != ip)?;
+            let domain = domain
+                .map(ToString::to_string)
+                .or_else(|| previous.domain.clone());
+            Some(
+                [previous.ip.clone(), ip.to_string()]
+                    .into_iter()
+                    .chain(domain),
+            )
+        });
+        match stale {
+            Ok(Some(hosts)) => self.clear_known_hosts(hosts),
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: cannot read the state of server '{name}': {e}"),
+        }
+    }
+
+    /// Record the server `name` in the state. A missing
+    /// `domain` keeps the one recorded before.
+    fn save_server(
+        &self,
+        name: &str,
+        ip: &str,
+        domain: Option<&str>,
+    ) -> DeployResult<ServerRecord> {
+        self.update_state(|state| {
+            let domain = domain
+                .map(ToString::to_string)
+                .or_else(|| state.servers.get(name).and_then(|s| s.domain.clone()));
+            let record = ServerRecord {
+                ip: ip.to_string(),
+                domain,
+            };
+            state.servers.insert(name.to_string(), record.clone());
+            record
+        })
+    }
+
+    /// Remove the server `name` and the DNS records pointing at
+    /// it from the state, returning its record.
+    fn forget_server(&self, name: &str) -> Option<ServerRecord> {
+        self.update_state(|state| {
+            state.dns_records.retain(|_, r| r.server != name);
+            state.servers.remove(name)
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("Warning: cannot forget server '{name}': {e}");
+            None
+        })
+    }
+
+    /// Record the DNS records of `domains` as pointing at
+    /// `server`, provisioned as `name`.
+    fn record_dns(&self, name: &str, server: &ServerInfo, domains: &[&dyn DnsProvider]) {
+        let result = self.update_state(|state| {
+            for dns in domains {
+                state.dns_records.insert(
+                    dns.domain().to_string(),
+                    DnsRecord {
+                        server: name.to_string(),
+                        ip: server.ip.clone(),
+                        ipv6: server.ipv6.clone().filter(|_| dns.manages_aaaa_records()),
+                    },
+                );
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: cannot record the DNS records of '{name}': {e}");
+        }
+    }
+
+    /// Record a deploy of the `only` apps (all when empty) to
+    /// `host`, with the `scans` of its images.
+    fn record_deployment(
+        &self,
+        host: &str,
+        only: &[String],
+        profiles: &[String],
+        success: bool,
+        scans: &BTreeMap<String, ScanReport>,
+    ) -> DeploymentRecord {
+        let images = self
+            .selected_apps(only)
+            .into_iter()
+            .filter(|a| compose::is_active(a, profiles))
+            .map(|a| {
+                let image = a
+                    .image
+                    .clone()
+                    .unwrap_or_else(|| format!("{}:latest", a.name));
+                (a.name.clone(), image)
+            })
+            .collect();
+        let mut deployment = DeploymentRecord::now(host, success, images);
+        deployment.scans.clone_from(scans);
+        if let Err(e) = self.update_state(|state| state.record_deployment(deployment.clone())) {
+            eprintln!("Warning: cannot record the deployment to {host}: {e}");
+        }
+        deployment
+    }
+
+    /// Run `f` with the state store: the configured one, or the
+    /// state file in the local directory.
+    fn with_state_store<T>(&self, f: impl FnOnce(&dyn StateStore) -> T) -> T {
+        match &self.state_store {
+            Some(store) => f(store.as_ref()),
+            None => f(&LocalState::new(&self.local_dir)),
+        }
+    }
+
+    /// Load the state, apply `change`, and save it back if it
+    /// changed.
+    fn update_state<T>(&self, change: impl FnOnce(&mut State) -> T) -> DeployResult<T> {
+        self.with_state_store(|store| {
+            let mut state = store.load()?;
+            let before = state.clone();
+            let result = change(&mut state);
+            if state != before {
+                store.save(&state)?;
+            }
+            Ok(result)
+        })
+    }
+
+    /// Remove the `known_hosts` entries and pinned keys of
+    /// `hosts`, so a server rebuilt under the same name, domain
+    /// or IP isn't refused for its new host key.
+    fn clear_known_hosts(&self, hosts: impl IntoIterator<Item = String>) {
+        let mut cleared: Vec<String> = Vec::new();
+        for host in hosts {
+            if !host.is_empty() && !cleared.contains(&host) {
+                self.ssh.clear_known_host(&host);
+                cleared.push(host);
+            }
+        }
+        if !cleared.is_empty() {
+            eprintln!("Known host keys removed: {}", cleared.join(", "));
+        }
+    }
+
     /// Parse CLI arguments and dispatch the appropriate
     /// command.
     ///
@@ -186,30 +926,286 @@ impl Pipeline {
     ///
     /// Returns an error if the dispatched command fails.
     pub fn run(&self) -> DeployResult<()> {
-        let cli = Cli::parse();
+        let matches = Cli::command().get_matches();
+        let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        self.dispatch(&cli, matches.subcommand_name().unwrap_or_default())
+    }
+
+    /// Like [`Pipeline::run`], parsing `args` (program name
+    /// first) instead of the process arguments.
+    ///
+    /// Combined with the mocks in [`crate::testing`], this lets
+    /// tests drive a pipeline without a cloud account or server.
+    pub fn run_from<I, T>(&self, args: I) -> DeployResult<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Cli::command()
+            .try_get_matches_from(args)
+            .map_err(|e| DeployError::Other(e.to_string()))?;
+        let cli = Cli::from_arg_matches(&matches).map_err(|e| DeployError::Other(e.to_string()))?;
+        self.dispatch(&cli, matches.subcommand_name().unwrap_or_default())
+    }
+
+    /// Run the command of `cli`, named `name`, traced with
+    /// OpenTelemetry when an endpoint is configured (see
+    /// [`crate::otel`]).
+    fn dispatch(&self, cli: &Cli, name: &str) -> DeployResult<()> {
+        let log_path = Path::new(&self.local_dir).join("last-run.log");
+        if let Err(e) = audit::start(&log_path, cli.trace_commands) {
+            eprintln!("Warning: cannot write {}: {e}", log_path.display());
+        }
+
+        let trace = otel::endpoint().map(|_| Rc::new(RefCell::new(Trace::new(name))));
+        let mut listeners = self.listeners.clone();
+        if let Some(trace) = &trace {
+            let trace = Rc::clone(trace);
+            listeners.push(Rc::new(move |event| trace.borrow_mut().record(&event)));
+        }
+        let result = events::with_listeners(&listeners, || {
+            let result = self
+                .check_confirmed(&cli.command, cli.confirm.as_deref())
+                .and_then(|()| {
+                    compose::with_project(self.project.as_deref(), || {
+                        match self.trail_action(&cli.command) {
+                            Some((action, target, detail)) => {
+                                self.audited(action, &target, detail, || {
+                                    self.run_command(&cli.command)
+                                })
+                            }
+                            None => self.run_command(&cli.command),
+                        }
+                    })
+                });
+            events::emit(DeployEvent::Done {
+                success: result.is_ok(),
+            });
+            result
+        });
+        if let Some(trace) = trace {
+            otel::export(&trace.borrow());
+        }
+        result
+    }
+
+    /// Refuse to run a deploy or destroy of a protected
+    /// environment that wasn't confirmed with its name, through
+    /// `confirm` (`--confirm`) or [`CONFIRM_ENV`].
+    fn check_confirmed(&self, command: &Command, confirm: Option<&str>) -> DeployResult<()> {
+        let Some(environment) = &self.protected else {
+            return Ok(());
+        };
+        let guarded = match command {
+            Command::Deploy { dry_run, .. } | Command::Destroy { dry_run, .. } => !dry_run,
+            _ => false,
+        };
+        if !guarded {
+            return Ok(());
+        }
+        let from_env = std::env::var(CONFIRM_ENV).ok();
+        if confirm.or(from_env.as_deref()) != Some(environment.as_str()) {
+            return Err(DeployError::NotConfirmed {
+                environment: environment.clone(),
+            });
+        }
+        eprintln!("Protected environment '{environment}' confirmed");
+        Ok(())
+    }
+
+    /// The action, target and detail `command` is recorded
+    /// under in the audit trail, if it changes servers. `destroy`
+    /// is recorded by [`Pipeline::cmd_destroy`] once confirmed.
+    fn trail_action(&self, command: &Command) -> Option<(&'static str, String, Option<String>)> {
+        match command {
+            Command::Provision { name, domain, .. } => Some((
+                "provision",
+                name.clone(),
+                domain.as_ref().map(|d| format!("domain {d}")),
+            )),
+            Command::Import { name, ip, .. } => {
+                Some(("import", name.clone(), Some(format!("ip {ip}"))))
+            }
+            Command::Deploy {
+                hosts,
+                dry_run: false,
+                only,
+                ..
+            } => {
+                let env_files: Vec<&str> = self
+                    .selected_apps(only)
+                    .into_iter()
+                    .filter_map(|a| a.env_file.as_deref())
+                    .collect();
+                let detail =
+                    (!env_files.is_empty()).then(|| format!("env files {}", env_files.join(" ")));
+                Some(("deploy", hosts.join(" "), detail))
+            }
+            Command::Rollback {
+                host,
+                only,
+                to,
+                list: false,
+            } => {
+                let mut detail = Vec::new();
+                if let Some(to) = to {
+                    detail.push(format!("to {to}"));
+                }
+                if !only.is_empty() {
+                    detail.push(format!("only {}", only.join(" ")));
+                }
+                Some((
+                    "rollback",
+                    host.clone(),
+                    (!detail.is_empty()).then(|| detail.join(", ")),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Run `f`, then add `action` on `target` to the audit trail
+    /// with its outcome, warning when the trail can't be written.
+    fn audited(
+        &self,
+        action: &str,
+        target: &str,
+        detail: Option<String>,
+        f: impl FnOnce() -> DeployResult<()>,
+    ) -> DeployResult<()> {
+        let result = f();
+        let mut entry = TrailEntry::now(action, target, result.is_ok());
+        entry.detail = detail;
+        if let Err(e) = self.with_state_store(|store| store.append_trail(&entry)) {
+            eprintln!("Warning: cannot add the {action} to the audit trail: {e}");
+        }
+        result
+    }
 
-        match &cli.command {
+    fn run_command(&self, command: &Command) -> DeployResult<()> {
+        match command {
             Command::Provision {
                 name,
                 domain,
                 region,
-            } => self.cmd_provision(name, domain.as_deref(), region.as_deref()),
+                size,
+                image,
+                ssh_key,
+            } => {
+                let overrides = ProvisionOverrides {
+                    size: size.clone(),
+                    image: image.clone(),
+                    ssh_keys: ssh_key.clone(),
+                };
+                self.cmd_provision(name, domain.as_deref(), region.as_deref(), &overrides)
+            }
             Command::Deploy {
-                host,
+                hosts,
                 skip_build,
                 dry_run,
                 only,
-            } => self.cmd_deploy(host, *skip_build, *dry_run, only),
+                profile,
+                trust_new_hostkey,
+                no_wait,
+                rollback_on_failure,
+            } => {
+                let flags = DeployFlags {
+                    skip_build: *skip_build,
+                    only,
+                    profiles: profile,
+                    trust_new_hostkey: *trust_new_hostkey,
+                    rollback_on_failure: *rollback_on_failure,
+                };
+                let hosts: Vec<String> = hosts.iter().map(|h| self.resolve_host(h)).collect();
+                let deploy = || self.cmd_deploy(&hosts, *dry_run, &flags);
+                if *no_wait {
+                    without_health_wait(deploy)
+                } else {
+                    deploy()
+                }
+            }
             Command::DeployLocal {
                 domain,
                 skip_build,
                 dry_run,
                 only,
             } => self.cmd_deploy_local(domain, *skip_build, *dry_run, only),
+            Command::Dev { skip_build } => self.cmd_dev(*skip_build),
             Command::LocalDown => self.cmd_local_down(),
             Command::LocalStatus => self.cmd_local_status(),
-            Command::Status { host } => self.cmd_status(host),
-            Command::Destroy { name, force } => self.cmd_destroy(name, *force),
+            Command::Status {
+                host,
+                trust_new_hostkey,
+            } => self.cmd_status(&self.resolve_host(host), *trust_new_hostkey),
+            Command::Top { host } => self.cmd_top(&self.resolve_host(host)),
+            Command::Compose { host, args } => self.cmd_compose(&self.resolve_host(host), args),
+            Command::Scale { host, services } => self.cmd_scale(&self.resolve_host(host), services),
+            Command::Rollback {
+                host,
+                only,
+                to,
+                list,
+            } => self.cmd_rollback(&self.resolve_host(host), only, to.as_deref(), *list),
+            Command::Maintenance {
+                host,
+                on,
+                off: _,
+                retry_after,
+            } => self.cmd_maintenance(&self.resolve_host(host), *on, *retry_after),
+            Command::Tunnel { host, forward } => self.cmd_tunnel(&self.resolve_host(host), forward),
+            Command::Console { name } => self.cmd_console(name),
+            Command::Import { name, ip, domain } => self.cmd_import(name, ip, domain.as_deref()),
+            Command::Audit { last } => self.cmd_audit(*last),
+            Command::Ci {
+                action:
+                    CiCommand::Generate {
+                        platform: CiPlatform::Github { branch },
+                    },
+            } => {
+                print!("{}", self.ci_workflow(branch).github());
+                Ok(())
+            }
+            Command::Cache {
+                action: CacheCommand::Clean,
+            } => self.cmd_cache_clean(),
+            Command::Destroy {
+                name,
+                force,
+                dry_run,
+            } => {
+                if *dry_run {
+                    self.cmd_destroy_dry_run(name)
+                } else {
+                    self.cmd_destroy(name, *force)
+                }
+            }
+        }
+    }
+
+    /// Like [`Pipeline::run`], for returning straight from
+    /// `main`: failures are printed as a multi-line report with
+    /// an error code, the phase and host involved, and a
+    /// suggested fix (see [`DeployError::render`]).
+    ///
+    /// ```rust,no_run
+    /// use std::process::ExitCode;
+    ///
+    /// use catapulta::{App, Caddy, Pipeline};
+    ///
+    /// fn main() -> ExitCode {
+    ///     let app = App::new("my-service").expose(3000);
+    ///     let caddy = Caddy::new().reverse_proxy(app.upstream());
+    ///     Pipeline::new(app, caddy).main()
+    /// }
+    /// ```
+    #[must_use]
+    pub fn main(&self) -> ExitCode {
+        match self.run() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e.render());
+                ExitCode::FAILURE
+            }
         }
     }
 
@@ -218,73 +1214,262 @@ impl Pipeline {
         name: &str,
         domain: Option<&str>,
         region: Option<&str>,
+        overrides: &ProvisionOverrides,
     ) -> DeployResult<()> {
         let provisioner = self
             .provisioner
             .as_ref()
             .ok_or_else(|| DeployError::Other("no provisioner configured".into()))?;
 
-        provisioner.check_prerequisites()?;
+        events::phase_started("check prerequisites", None);
+        provisioner
+            .check_prerequisites()
+            .context("check prerequisites", None)?;
+
+        // DNS is set after the server is created, so check the
+        // credentials first rather than leave a server without it
+        let app_dns = if domain.is_some() {
+            self.app_dns()
+        } else {
+            Vec::new()
+        };
+        let dns_providers: Vec<&dyn DnsProvider> = if domain.is_some() {
+            self.dns.iter().chain(&app_dns).map(AsRef::as_ref).collect()
+        } else {
+            Vec::new()
+        };
+        let caa_providers: &[Box<dyn DnsProvider>] =
+            if domain.is_some() { &self.caa_dns } else { &[] };
+        for dns in dns_providers
+            .iter()
+            .copied()
+            .chain(caa_providers.iter().map(AsRef::as_ref))
+        {
+            eprintln!("Checking DNS access for {}...", dns.domain());
+            dns.check_access()
+                .context("check prerequisites", Some(dns.domain()))?;
+        }
 
         // Check if already exists
         if let Some(existing) = provisioner.get_server(name)? {
             eprintln!(
-                "Droplet '{name}' already exists \
+                "Server '{name}' already exists \
                  (IP: {})",
                 existing.ip
             );
+            if *overrides != ProvisionOverrides::default() {
+                eprintln!(
+                    "Warning: --size, --image and --ssh-key only apply \
+                     to new servers"
+                );
+            }
 
             // Update DNS to point at the current IP
-            if domain.is_some() {
-                for dns in &self.dns {
-                    let d = dns.domain();
-                    eprintln!("Updating DNS for {d}...");
-                    dns.upsert_a_record(&existing.ip)?;
-                    eprintln!("DNS record set: {d} -> {}", existing.ip);
-                }
+            Self::publish_dns(&dns_providers, &existing, "Updating")?;
+            Self::publish_caa(caa_providers)?;
+            self.record_dns(name, &existing, &dns_providers);
+
+            self.clear_replaced_host_keys(name, &existing.ip, domain);
+            let record = self.record_server(name, &existing.ip, domain);
+            if let Err(e) = self.pin_host_key(&record) {
+                eprintln!("Warning: cannot pin the host key of '{name}': {e}");
             }
-
-            let host = domain.unwrap_or(&existing.ip);
             eprintln!("Deploy with:");
-            eprintln!("  cargo xtask deploy {host}");
+            eprintln!("  cargo xtask deploy {name}");
             return Ok(());
         }
 
         // Detect SSH keys
-        let keys = provisioner.detect_ssh_keys()?;
+        events::phase_started("provision", None);
+        let keys = provisioner.detect_ssh_keys().context("provision", None)?;
         let key_ids: Vec<String> = keys.iter().map(|(id, _)| id.clone()).collect();
 
-        let region = region.unwrap_or("fra1");
+        let region = region.unwrap_or_else(|| provisioner.default_region());
 
         // Setup DNS before server setup so the domain resolves
         // by the time Caddy requests a TLS certificate
-        let server = provisioner.create_server(name, region, &key_ids)?;
+        let server = provisioner
+            .create_server_with(name, region, &key_ids, overrides)
+            .context("provision", None)?;
+
+        Self::publish_dns(&dns_providers, &server, "Setting up")?;
+        Self::publish_caa(caa_providers)?;
+        self.record_dns(name, &server, &dns_providers);
+
+        events::phase_started("server setup", Some(&server.ip));
+        self.clear_replaced_host_keys(name, &server.ip, domain);
+        provisioner
+            .setup_server(&server, domain, &self.ssh.clone().trust_new_host_key())
+            .context("server setup", Some(&server.ip))?;
+        let record = self.record_server(name, &server.ip, domain);
+        self.pin_host_key(&record)
+            .context("server setup", Some(&server.ip))
+    }
+
+    /// Allow only [`dns::CAA_ISSUERS`] to issue certificates for
+    /// the domains of `caa_providers`.
+    fn publish_caa(caa_providers: &[Box<dyn DnsProvider>]) -> DeployResult<()> {
+        for dns in caa_providers {
+            let d = dns.domain();
+            events::phase_started("DNS update", Some(d));
+            eprintln!("Setting CAA records for {d}...");
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                dns.upsert_caa_records(dns::CAA_ISSUERS)
+            })
+            .context("DNS update", Some(d))?;
+            eprintln!("CAA records set: {d} -> {}", dns::CAA_ISSUERS.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Point the domains of `dns_providers` at `server`: an A
+    /// record to its IP, and an AAAA record to its IPv6 address
+    /// when it has one and the provider manages them.
+    fn publish_dns(
+        dns_providers: &[&dyn DnsProvider],
+        server: &ServerInfo,
+        action: &str,
+    ) -> DeployResult<()> {
+        for dns in dns_providers {
+            let d = dns.domain();
+            events::phase_started("DNS update", Some(d));
+            eprintln!("{action} DNS for {d}...");
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                dns.upsert_a_record(&server.ip)
+            })
+            .context("DNS update", Some(d))?;
+            eprintln!("DNS record set: {d} -> {}", server.ip);
+
+            match &server.ipv6 {
+                Some(ipv6) if dns.manages_aaaa_records() => {
+                    cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                        dns.upsert_aaaa_record(ipv6)
+                    })
+                    .context("DNS update", Some(d))?;
+                    eprintln!("DNS record set: {d} -> {ipv6}");
+                }
+                Some(_) => eprintln!(
+                    "Note: the DNS provider of {d} doesn't manage AAAA records, \
+                     so {d} is only reachable over IPv4"
+                ),
+                None => {}
+            }
+        }
+        Ok(())
+    }
 
-        if domain.is_some() {
-            for dns in &self.dns {
-                let d = dns.domain();
-                eprintln!("Setting up DNS for {d}...");
-                dns.upsert_a_record(&server.ip)?;
-                eprintln!("DNS record set: {d} -> {}", server.ip);
+    /// Deploy to `hosts` one at a time, stopping at the first
+    /// that fails, so a bad release takes down at most one host of
+    /// a round-robin fleet.
+    fn cmd_deploy(&self, hosts: &[String], dry_run: bool, flags: &DeployFlags) -> DeployResult<()> {
+        self.validate_profiles(flags.profiles)?;
+        if dry_run {
+            for host in hosts {
+                self.cmd_deploy_dry_run(host, flags.only, flags.profiles)?;
             }
+            return Ok(());
         }
 
-        provisioner.setup_server(&server, domain)?;
+        // Images are built and scanned for the first host only
+        let mut scans = BTreeMap::new();
+        let recorder = self
+            .report_file
+            .as_ref()
+            .map(|_| Rc::new(RefCell::new(Recorder::new())));
+        let mut reports = Vec::new();
+        for (i, host) in hosts.iter().enumerate() {
+            if hosts.len() > 1 {
+                eprintln!("=== Deploying to {host} ({}/{}) ===", i + 1, hosts.len());
+            }
+            let skip_build = flags.skip_build || i > 0;
+            let previous = recorder.as_ref().and_then(|_| self.last_deployment(host));
+            let (result, timings) = timing::collect(|| {
+                let mut run = || {
+                    self.deploy_remote(
+                        host,
+                        skip_build,
+                        flags.only,
+                        flags.profiles,
+                        flags.trust_new_hostkey,
+                        &mut scans,
+                    )
+                };
+                match &recorder {
+                    Some(recorder) => {
+                        let recorder = Rc::clone(recorder);
+                        events::with_listener(
+                            Rc::new(move |event| recorder.borrow_mut().record(&event)),
+                            run,
+                        )
+                    }
+                    None => run(),
+                }
+            });
+            self.report_timings(&timings, "deploy", host, result.is_ok());
+            let deployment =
+                self.record_deployment(host, flags.only, flags.profiles, result.is_ok(), &scans);
+            if let Some(recorder) = &recorder {
+                let mut recorder = recorder.borrow_mut();
+                for image in recorder.built.values_mut() {
+                    if image.digest.is_none() {
+                        image.digest = deploy::image_id(&image.tag).ok();
+                    }
+                }
+                let error = result.as_ref().err().map(|e| audit::mask(&e.to_string()));
+                let mut report = recorder.finish_host(deployment, previous, timings, error);
+                report.hooks = self.smoke_tests();
+                reports.push(report);
+                self.write_report(&reports);
+            }
 
+            if let Err(e) = result {
+                if flags.rollback_on_failure && restarted_services(&e) {
+                    self.rollback_failed_host(host, flags.only);
+                }
+                if hosts.len() > 1 {
+                    eprintln!();
+                    if i > 0 {
+                        eprintln!("Deployed to: {}", hosts[..i].join(", "));
+                    }
+                    eprintln!("Stopped at: {host}");
+                    if i + 1 < hosts.len() {
+                        eprintln!("Not deployed to: {}", hosts[i + 1..].join(", "));
+                    }
+                }
+                return Err(e);
+            }
+        }
         Ok(())
     }
 
-    fn cmd_deploy(
+    /// Put the previous images back on `host` after a failed
+    /// deploy. Failing to is only reported, so the deploy error
+    /// stays the one returned.
+    fn rollback_failed_host(&self, host: &str, only: &[String]) {
+        let selected = self.selected_apps(only);
+        let built = built_apps(&selected);
+        if built.is_empty() || self.keep_images == 0 {
+            eprintln!("Warning: no previous images to roll {host} back to");
+            return;
+        }
+        eprintln!();
+        eprintln!("Rolling back {host}...");
+        events::phase_started("rollback", Some(host));
+        let ssh = self.session(host, false);
+        if let Err(e) = self.rollback(&ssh, &built, None) {
+            eprintln!("Warning: rollback of {host} failed: {e}");
+        }
+    }
+
+    fn deploy_remote(
         &self,
         host: &str,
         skip_build: bool,
-        dry_run: bool,
         only: &[String],
+        profiles: &[String],
+        trust_new_hostkey: bool,
+        scans: &mut BTreeMap<String, ScanReport>,
     ) -> DeployResult<()> {
-        if dry_run {
-            return self.cmd_deploy_dry_run(host, only);
-        }
-
         let deployer = self
             .deployer
             .as_ref()
@@ -293,78 +1478,304 @@ impl Pipeline {
         // Validate --only names against configured apps
         self.validate_only(only)?;
 
-        // Select which apps to build/transfer
+        // Select which apps to build/transfer; prebuilt images
+        // are pulled on the server instead, apps in a profile not
+        // requested are not started
         let selected = self.selected_apps(only);
+        let built = active_apps(built_apps(&selected), profiles);
+
+        // Fail before touching the server when a generated file
+        // is broken
+        Self::check_generated(
+            &self.caddy,
+            host,
+            &compose::activate_profiles(&self.apps, profiles),
+        )?;
+
+        // Fail before a long build when the server isn't set up,
+        // or runs on another architecture than the images, or another
+        // stack on it would collide with this one
+        let ssh = self.session(host, trust_new_hostkey);
+        let runtime = self.runtime();
+        let setup = preflight::check_host(&ssh, &self.remote_dir, runtime)
+            .context("check host", Some(host))?;
+        if let Some(machine) = &setup.machine {
+            preflight::check_platforms(&built, machine).context("check host", Some(host))?;
+        }
+        // Both read the output of `docker` commands
+        if runtime == Runtime::Docker {
+            preflight::check_collisions(&ssh, &self.apps, &self.caddy, &self.remote_dir)
+                .context("check host", Some(host))?;
+            preflight::check_compose(
+                &ssh,
+                &compose::activate_profiles(&self.apps, profiles),
+                &self.caddy,
+                &self.remote_dir,
+            )
+            .context("check host", Some(host))?;
+        }
+
+        self.registry_login(&ssh, &selected, profiles, !skip_build && !built.is_empty())
+            .context("registry login", Some(host))?;
 
         if !skip_build {
-            for app in &selected {
-                deployer.build_image(app)?;
+            for app in &built {
+                events::phase_started("build", None);
+                timing::measure("build", || deployer.build_image(app)).context("build", None)?;
             }
+            timing::measure("scan", || self.scan_built(&built, scans))
+                .context("scan images", None)?;
         }
 
-        // Stop containers before loading to free memory on
-        // constrained VPS instances.
-        // When a maintenance page is configured, keep Caddy
-        // running so it can serve the maintenance page while
-        // app containers are down.
-        eprintln!("Stopping containers...");
-        let ssh = SshSession::new(host, &self.ssh_user);
         if self.caddy.maintenance_page.is_some() {
-            // First, deploy updated Caddyfile with handle_errors
-            // so Caddy can serve the maintenance page.
-            let caddyfile_content = caddyfile::render(&self.caddy, host);
-            ssh.write_remote_file(
-                &caddyfile_content,
-                &format!("{}/Caddyfile", self.remote_dir),
-            )?;
-            // Reload Caddy config if it's running
-            ssh.exec(&format!(
-                "cd {} && docker compose exec -T caddy \
-                 caddy reload --config /etc/caddy/Caddyfile \
-                 2>/dev/null || true",
-                self.remote_dir,
-            ))?;
-            // Only stop selected app containers, keep Caddy
-            let stop_names: Vec<&str> = selected.iter().map(|a| a.name.as_str()).collect();
-            let names = stop_names.join(" ");
-            ssh.exec(&format!(
-                "cd {} && docker compose rm -sf {} \
-                 2>/dev/null || true",
-                self.remote_dir, names,
-            ))?;
-        } else if only.is_empty() {
-            ssh.exec(&format!(
-                "cd {} && docker compose down \
-                 2>/dev/null || true",
-                self.remote_dir
-            ))?;
-        } else {
-            // Only stop selected services
-            let stop_names: Vec<&str> = selected.iter().map(|a| a.name.as_str()).collect();
-            let names = stop_names.join(" ");
-            ssh.exec(&format!(
-                "cd {} && docker compose rm -sf {} \
-                 2>/dev/null || true",
-                self.remote_dir, names,
-            ))?;
+            eprintln!("Stopping containers...");
+            events::phase_started("stop containers", Some(host));
+            timing::measure("stop", || self.stop_containers(&ssh, host, &selected))
+                .context("stop containers", Some(host))?;
         }
 
-        for app in &selected {
-            deployer.transfer_image(app, host, &self.ssh_user)?;
+        self.transfer_images(deployer.as_ref(), &ssh, &built)
+            .context("transfer image", Some(host))?;
+
+        let active: Vec<App> = self
+            .apps
+            .iter()
+            .filter(|a| compose::is_active(a, profiles))
+            .cloned()
+            .collect();
+        events::phase_started("deploy", Some(host));
+        firewall::open_published_ports(&ssh, &active, &self.caddy).context("deploy", Some(host))?;
+        timing::measure("configure", || {
+            // Keep replica counts set with `scale`
+            let apps = scale::apply(&self.apps, &self.recorded_replicas(&ssh)?);
+            let apps = compose::activate_profiles(&apps, profiles);
+            let shared = self.shares_caddy(&apps);
+            if shared {
+                shared_caddy::start(&ssh)?;
+            }
+            deployer.deploy(&ssh, &apps, &self.caddy, &self.remote_dir, only)?;
+            if shared {
+                let site = shared_caddy::render_site(&self.caddy, host, &apps)?;
+                shared_caddy::publish(&ssh, &compose::stack_name(&apps), &site)?;
+            }
+            Ok(())
+        })
+        .context("deploy", Some(host))?;
+
+        events::phase_started("post-deploy hooks", Some(host));
+        timing::measure("hooks", || self.run_post_deploy(&ssh))
+            .context("post-deploy hooks", Some(host))?;
+
+        Ok(())
+    }
+
+    /// Scan the local images of the `built` apps when
+    /// [`Pipeline::scan_images`] is set, adding their reports to
+    /// `scans`.
+    fn scan_built(
+        &self,
+        built: &[&App],
+        scans: &mut BTreeMap<String, ScanReport>,
+    ) -> DeployResult<()> {
+        let Some(scan) = &self.image_scan else {
+            return Ok(());
+        };
+        events::phase_started("scan images", None);
+        for app in built {
+            let tags: Vec<String> = if app.platforms.is_empty() {
+                vec![format!("{}:latest", app.name)]
+            } else {
+                app.platforms.iter().map(|p| app.platform_tag(p)).collect()
+            };
+            for tag in tags {
+                if deploy::image_id(&tag).is_err() {
+                    eprintln!("  {tag} is not a local image, not scanned");
+                    continue;
+                }
+                let sbom = Path::new(&self.local_dir).join("sbom").join(format!(
+                    "{}-{}.json",
+                    tag.replace(':', "-"),
+                    state::unix_now()
+                ));
+                let report = scan.run(&tag, &sbom)?;
+                let result = scan.check(&tag, &report);
+                scans.insert(tag.clone(), report);
+                result?;
+            }
         }
+        Ok(())
+    }
 
-        deployer.deploy(
-            host,
-            &self.ssh_user,
-            &self.apps,
-            &self.caddy,
-            &self.remote_dir,
-            only,
+    /// Transfer the images of the `built` apps the server doesn't
+    /// run yet, all at once, keeping the ones they replace for
+    /// rollback.
+    fn transfer_images(
+        &self,
+        deployer: &dyn Deployer,
+        ssh: &SshSession,
+        built: &[&App],
+    ) -> DeployResult<()> {
+        let mut changed = Vec::new();
+        for app in built.iter().copied() {
+            if deployer.image_unchanged(app, ssh) {
+                eprintln!(
+                    "Image of {} unchanged on {}, skipping transfer",
+                    app.name,
+                    ssh.host()
+                );
+                continue;
+            }
+            if self.keep_images > 0 {
+                ssh.exec(&rollback::keep_command(
+                    &app.name,
+                    self.keep_images,
+                    deployer.runtime(),
+                ))?;
+            }
+            changed.push(app);
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        events::phase_started("transfer image", Some(ssh.host()));
+        timing::measure("transfer", || deployer.transfer_images(&changed, ssh))?;
+        if self.keep_images > 0 {
+            let revision = state::git_revision();
+            for app in changed {
+                let tag = ssh.exec(&rollback::version_command(
+                    &app.name,
+                    revision.as_deref(),
+                    self.keep_images,
+                    deployer.runtime(),
+                ))?;
+                if !tag.is_empty() {
+                    eprintln!("Tagged {}:{tag}", app.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Log in to the configured registries: locally when
+    /// `building`, and on the server for those serving a
+    /// prebuilt image of the active `selected` apps.
+    fn registry_login(
+        &self,
+        ssh: &SshSession,
+        selected: &[&App],
+        profiles: &[String],
+        building: bool,
+    ) -> DeployResult<()> {
+        for auth in &self.registries {
+            if building {
+                auth.login_local()?;
+            }
+            let pulled = selected
+                .iter()
+                .filter(|a| compose::is_active(a, profiles))
+                .filter_map(|a| a.image.as_deref())
+                .any(|image| auth.serves(image));
+            if pulled {
+                auth.login_remote(ssh, &self.remote_dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the selected app containers so Caddy serves the
+    /// maintenance page during the deploy.
+    ///
+    /// Without a maintenance page nothing is stopped: the
+    /// deployer's `docker compose up -d` recreates only the
+    /// services that changed, so the others and Caddy keep
+    /// serving traffic.
+    fn stop_containers(&self, ssh: &SshSession, host: &str, selected: &[&App]) -> DeployResult<()> {
+        // First, deploy updated Caddyfile with handle_errors
+        // so Caddy can serve the maintenance page.
+        self.reload_caddy(ssh, host, &self.apps)?;
+        // Only stop selected app containers, keep Caddy
+        let stop_names: Vec<&str> = selected.iter().map(|a| a.name.as_str()).collect();
+        let names = stop_names.join(" ");
+        ssh.exec(&format!(
+            "cd {} && {} rm -sf {} \
+             2>/dev/null || true",
+            self.remote_dir,
+            self.runtime().compose(),
+            names,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Write the Caddy config for `apps` and reload Caddy if it's
+    /// running.
+    fn reload_caddy(&self, ssh: &SshSession, host: &str, apps: &[App]) -> DeployResult<()> {
+        if self.shares_caddy(apps) {
+            let site = shared_caddy::render_site(&self.caddy, host, apps)?;
+            return shared_caddy::publish(ssh, &compose::stack_name(apps), &site);
+        }
+        if !compose::needs_caddy(apps, &self.caddy) {
+            return Ok(());
+        }
+        let caddy_config = caddyfile::render_config(&self.caddy, host, apps)?;
+        self.write_stack_file(
+            ssh,
+            &caddy_config,
+            &format!("{}/{}", self.remote_dir, self.caddy.config_file()),
+            FileAttrs::new().in_place(),
         )?;
+        ssh.exec(&format!(
+            "cd {} && {}",
+            self.remote_dir,
+            deploy::caddy_reload_command(self.runtime().compose(), &self.caddy.reload_command()),
+        ))?;
+        Ok(())
+    }
+
+    /// Write a stack file through the deployer, signed like a
+    /// deploy writes it, or as is without a deployer.
+    fn write_stack_file(
+        &self,
+        ssh: &SshSession,
+        content: &str,
+        path: &str,
+        attrs: FileAttrs,
+    ) -> DeployResult<()> {
+        match &self.deployer {
+            Some(deployer) => deployer.write_file(ssh, content, path, attrs),
+            None => ssh.write_remote_file_with(content, path, &attrs),
+        }
+    }
+
+    /// Replica counts recorded on the server by `scale`.
+    fn recorded_replicas(&self, ssh: &SshSession) -> DeployResult<Replicas> {
+        let content = ssh.exec(&format!(
+            "cat {}/{} 2>/dev/null || true",
+            self.remote_dir,
+            scale::STATE_FILE
+        ))?;
+        scale::parse_state(&content)
+    }
+
+    /// Purge `paths` from the cache of every DNS provider's
+    /// domain.
+    fn purge_cache_now(&self, paths: &[String]) -> DeployResult<()> {
+        let app_dns = self.app_dns();
+        let providers: Vec<&dyn DnsProvider> =
+            self.dns.iter().chain(&app_dns).map(AsRef::as_ref).collect();
+        if providers.is_empty() {
+            eprintln!("Warning: no DNS provider configured, the CDN cache is not purged");
+        }
+        for dns in providers {
+            eprintln!("  Purging the CDN cache of {}", dns.domain());
+            dns.purge_cache(paths)?;
+        }
+        Ok(())
+    }
 
+    fn run_post_deploy(&self, ssh: &SshSession) -> DeployResult<()> {
         if !self.post_deploy.is_empty() {
             eprintln!("Running post-deploy hooks...");
-            let ssh = SshSession::new(host, &self.ssh_user);
             for hook in &self.post_deploy {
                 match hook {
                     PostDeployHook::Upload { local, remote } => {
@@ -387,14 +1798,16 @@ impl Pipeline {
                         );
                         ssh.scp_to(local, &tmp)?;
                         ssh.exec_interactive(&format!(
-                            "docker cp {tmp} {container}:{path} \
-                             && rm -f {tmp}"
+                            "{} cp {tmp} {container}:{path} \
+                             && rm -f {tmp}",
+                            self.runtime().cli()
                         ))?;
                     }
                     PostDeployHook::Exec(cmd) => {
                         eprintln!("  Running: {cmd}");
                         ssh.exec_interactive(cmd)?;
                     }
+                    PostDeployHook::PurgeCache(paths) => self.purge_cache_now(paths)?,
                 }
             }
         }
@@ -413,35 +1826,37 @@ impl Pipeline {
             return self.cmd_deploy_local_dry_run(domain, only);
         }
 
+        let (result, timings) = timing::collect(|| self.deploy_local(domain, skip_build, only));
+        self.report_timings(&timings, "deploy-local", domain, result.is_ok());
+        result
+    }
+
+    fn deploy_local(&self, domain: &str, skip_build: bool, only: &[String]) -> DeployResult<()> {
         // Validate --only names against configured apps
         self.validate_only(only)?;
 
         let selected = self.selected_apps(only);
         let deployer = LocalDeploy::new();
+        Self::check_generated(&deployer.caddy(&self.caddy), domain, &self.apps)?;
 
         if !skip_build {
-            for app in &selected {
-                deployer.build_image(app)?;
+            for app in built_apps(&selected) {
+                events::phase_started("build", None);
+                timing::measure("build", || deployer.build_image(app)).context("build", None)?;
             }
         }
 
-        // Stop existing local stack
-        let compose_path = format!("{}/docker-compose.yml", self.local_dir);
-        if std::path::Path::new(&compose_path).exists() {
-            if only.is_empty() {
-                eprintln!("Stopping existing local stack...");
-                let _ = run_local_compose(&self.local_dir, &["down"]);
-            } else {
-                let names: Vec<&str> = selected.iter().map(|a| a.name.as_str()).collect();
-                let name_strs = names.join(" ");
-                eprintln!("Stopping selected services: {name_strs}...");
-                let mut args = vec!["rm", "-sf"];
-                args.extend(names);
-                let _ = run_local_compose(&self.local_dir, &args);
-            }
-        }
-
-        deployer.deploy(domain, "", &self.apps, &self.caddy, &self.local_dir, only)?;
+        events::phase_started("local deploy", None);
+        timing::measure("configure", || {
+            deployer.deploy(
+                &SshSession::new(domain, ""),
+                &self.apps,
+                &self.caddy,
+                &self.local_dir,
+                only,
+            )
+        })
+        .context("local deploy", None)?;
 
         // Print dnsmasq setup hint if not detected
         print_dnsmasq_hint();
@@ -449,6 +1864,44 @@ impl Pipeline {
         Ok(())
     }
 
+    /// Run the stack of [`local::dev_apps`] in the local stack's
+    /// directory, replacing a stack from `deploy-local`.
+    fn cmd_dev(&self, skip_build: bool) -> DeployResult<()> {
+        let apps = local::dev_apps(&self.apps);
+        let deployer = LocalDeploy::new().plain_http();
+        let host = "localhost";
+        Self::check_generated(&deployer.caddy(&self.caddy), &deployer.site(host), &apps)?;
+
+        if !skip_build {
+            let selected: Vec<&App> = apps.iter().collect();
+            for app in built_apps(&selected) {
+                events::phase_started("build", None);
+                timing::measure("build", || deployer.build_image(app)).context("build", None)?;
+            }
+        }
+
+        events::phase_started("local deploy", None);
+        deployer
+            .deploy(
+                &SshSession::new(host, ""),
+                &apps,
+                &self.caddy,
+                &self.local_dir,
+                &[],
+            )
+            .context("local deploy", None)?;
+
+        for app in &apps {
+            for (published, port) in &app.ports {
+                if app.expose.contains(port) {
+                    eprintln!("{}: http://{host}:{published}", app.name);
+                }
+            }
+        }
+        eprintln!("Stop with: cargo xtask local-down");
+        Ok(())
+    }
+
     fn cmd_local_down(&self) -> DeployResult<()> {
         let compose_path = format!("{}/docker-compose.yml", self.local_dir);
         if !std::path::Path::new(&compose_path).exists() {
@@ -471,32 +1924,69 @@ impl Pipeline {
     }
 
     #[allow(clippy::unnecessary_wraps)]
-    fn cmd_deploy_dry_run(&self, host: &str, only: &[String]) -> DeployResult<()> {
+    fn cmd_deploy_dry_run(
+        &self,
+        host: &str,
+        only: &[String],
+        profiles: &[String],
+    ) -> DeployResult<()> {
         self.validate_only(only)?;
         let selected = self.selected_apps(only);
 
-        let compose_content = compose::render(&self.apps, &self.caddy);
-        let caddyfile_content = caddyfile::render(&self.caddy, host);
+        let apps = compose::activate_profiles(&self.apps, profiles);
+        let compose_content = compose::render(&apps, &self.caddy);
+        let caddy_config = compose::needs_caddy(&apps, &self.caddy)
+            .then(|| caddyfile::render_config(&self.caddy, host, &apps))
+            .transpose()?;
+        let shared_site = self
+            .shares_caddy(&apps)
+            .then(|| shared_caddy::render_site(&self.caddy, host, &apps))
+            .transpose()?;
 
         eprintln!("=== Dry run: no changes will be made ===");
         if !only.is_empty() {
             eprintln!("  (--only: {})", only.join(", "));
         }
+        if !profiles.is_empty() {
+            eprintln!("  (--profile: {})", profiles.join(", "));
+        }
         eprintln!();
 
         eprintln!("--- docker-compose.yml ---");
-        println!("{compose_content}");
+        println!("{}", audit::mask(&compose_content));
 
-        eprintln!("--- Caddyfile ---");
-        println!("{caddyfile_content}");
+        if let Some(caddy_config) = caddy_config {
+            eprintln!("--- {} ---", self.caddy.config_file());
+            println!("{caddy_config}");
+        }
+        if let Some(site) = shared_site {
+            eprintln!(
+                "--- {} (shared Caddy) ---",
+                shared_caddy::site_file(&compose::stack_name(&apps))
+            );
+            println!("{site}");
+        }
+        Self::check_generated(&self.caddy, host, &apps)?;
 
         eprintln!("--- Actions that would be performed ---");
-        for (i, app) in selected.iter().enumerate() {
+        if let Some(jump) = &self.ssh.jump {
+            eprintln!("   (via jump host {}@{})", jump.user, jump.host);
+        }
+        let built = active_apps(built_apps(&selected), profiles);
+        for (i, app) in built.iter().enumerate() {
             let n = i + 1;
-            eprintln!("{n}. Build Docker image: {}:latest", app.name);
+            if app.platforms.is_empty() {
+                eprintln!("{n}. Build Docker image: {}:latest", app.name);
+            } else {
+                eprintln!(
+                    "{n}. Build Docker images: {}:latest ({})",
+                    app.name,
+                    app.platforms.join(", ")
+                );
+            }
         }
-        let base = selected.len();
-        for (i, app) in selected.iter().enumerate() {
+        let base = built.len();
+        for (i, app) in built.iter().enumerate() {
             let n = base + i + 1;
             eprintln!("{n}. Transfer {} to {}@{}", app.name, self.ssh_user, host);
         }
@@ -514,6 +2004,48 @@ impl Pipeline {
             eprintln!("{step}. Restart services: {}", only.join(", "));
         }
 
+        self.print_post_deploy_hooks();
+
+        Ok(())
+    }
+
+    /// Lint the files a deploy of `apps` with `caddy` to `host`
+    /// writes; see [`lint::check`].
+    fn check_generated(caddy: &Caddy, host: &str, apps: &[App]) -> DeployResult<()> {
+        let compose_content = compose::render(apps, caddy);
+        let mut files = vec![(lint::COMPOSE_FILE.to_string(), compose_content)];
+        if compose::needs_caddy(apps, caddy) {
+            files.push((
+                caddy.config_file().to_string(),
+                caddyfile::render_config(caddy, host, apps)?,
+            ));
+        }
+        if caddy.shared && compose::proxies(apps, caddy) {
+            files.push((
+                shared_caddy::site_file(&compose::stack_name(apps)),
+                shared_caddy::render_site(caddy, host, apps)?,
+            ));
+        }
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(name, content)| (name.as_str(), content.as_str()))
+            .collect();
+        lint::check(&files)
+    }
+
+    /// The commands run after a deploy, for the report.
+    fn smoke_tests(&self) -> Vec<String> {
+        self.post_deploy
+            .iter()
+            .filter_map(|hook| match hook {
+                PostDeployHook::Exec(cmd) => Some(cmd.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// List the post-deploy hooks of a dry run.
+    fn print_post_deploy_hooks(&self) {
         if !self.post_deploy.is_empty() {
             eprintln!();
             eprintln!("--- Post-deploy hooks ---");
@@ -530,17 +2062,23 @@ impl Pipeline {
                     } => {
                         eprintln!(
                             "{n}. docker cp {local} -> \
-                             {container}:{path}"
+                         {container}:{path}"
                         );
                     }
                     PostDeployHook::Exec(cmd) => {
                         eprintln!("{n}. Run: {cmd}");
                     }
+                    PostDeployHook::PurgeCache(paths) => {
+                        let what = if paths.is_empty() {
+                            "everything".to_string()
+                        } else {
+                            paths.join(", ")
+                        };
+                        eprintln!("{n}. Purge the CDN cache: {what}");
+                    }
                 }
             }
         }
-
-        Ok(())
     }
 
     #[allow(clippy::unnecessary_wraps)]
@@ -548,11 +2086,11 @@ impl Pipeline {
         self.validate_only(only)?;
         let selected = self.selected_apps(only);
 
-        let compose_content = compose::render(&self.apps, &self.caddy);
-
-        let mut local_caddy = self.caddy.clone();
-        local_caddy.tls_internal = true;
-        let caddyfile_content = caddyfile::render(&local_caddy, domain);
+        let local_caddy = LocalDeploy::new().caddy(&self.caddy);
+        let compose_content = compose::render(&self.apps, &local_caddy);
+        let caddy_config = compose::needs_caddy(&self.apps, &local_caddy)
+            .then(|| caddyfile::render_config(&local_caddy, domain, &self.apps))
+            .transpose()?;
 
         eprintln!(
             "=== Dry run (local): \
@@ -564,13 +2102,17 @@ impl Pipeline {
         eprintln!();
 
         eprintln!("--- docker-compose.yml ---");
-        println!("{compose_content}");
+        println!("{}", audit::mask(&compose_content));
 
-        eprintln!("--- Caddyfile (tls internal) ---");
-        println!("{caddyfile_content}");
+        if let Some(caddy_config) = caddy_config {
+            eprintln!("--- {} (tls internal) ---", local_caddy.config_file());
+            println!("{caddy_config}");
+        }
+        Self::check_generated(&local_caddy, domain, &self.apps)?;
 
         eprintln!("--- Actions that would be performed ---");
-        for (i, app) in selected.iter().enumerate() {
+        let built = built_apps(&selected);
+        for (i, app) in built.iter().enumerate() {
             let n = i + 1;
             eprintln!(
                 "{n}. Build Docker image (native): \
@@ -578,7 +2120,7 @@ impl Pipeline {
                 app.name
             );
         }
-        let mut step = selected.len() + 1;
+        let mut step = built.len() + 1;
         eprintln!("{step}. Write config files to {}/", self.local_dir);
         step += 1;
         let has_env = selected.iter().any(|a| a.env_file.is_some());
@@ -595,9 +2137,502 @@ impl Pipeline {
         Ok(())
     }
 
-    fn cmd_status(&self, host: &str) -> DeployResult<()> {
-        let ssh = SshSession::new(host, &self.ssh_user);
-        ssh.exec_interactive(&format!("cd {} && docker compose ps", self.remote_dir))
+    fn cmd_status(&self, host: &str, trust_new_hostkey: bool) -> DeployResult<()> {
+        let ssh = self.session(host, trust_new_hostkey);
+        events::phase_started("status", Some(host));
+        ssh.exec_interactive(&format!(
+            "cd {} && {} ps",
+            self.remote_dir,
+            self.runtime().compose()
+        ))
+        .context("status", Some(host))?;
+
+        match status::gather(&ssh) {
+            Ok(metrics) => {
+                println!();
+                println!("Server:");
+                for line in metrics.render() {
+                    println!("  {line}");
+                }
+                for warning in metrics.warnings() {
+                    eprintln!("Warning: {warning}");
+                }
+            }
+            Err(e) => eprintln!("Warning: cannot read server metrics: {e}"),
+        }
+        Ok(())
+    }
+
+    fn cmd_top(&self, host: &str) -> DeployResult<()> {
+        let ssh = self.session(host, false);
+        events::phase_started("top", Some(host));
+        let stats = stats::gather(&ssh, self.runtime()).context("top", Some(host))?;
+        if stats.is_empty() {
+            eprintln!("No containers running on {host}");
+            return Ok(());
+        }
+        for line in stats::render(&stats) {
+            println!("{line}");
+        }
+        Ok(())
+    }
+
+    /// Run `docker compose` (`podman-compose`) with `args`, each
+    /// quoted so it reaches compose unchanged.
+    fn cmd_compose(&self, host: &str, args: &[String]) -> DeployResult<()> {
+        let ssh = self.session(host, false);
+        let args: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        events::phase_started("compose", Some(host));
+        ssh.exec_interactive(&format!(
+            "cd {} && {} {}",
+            self.remote_dir,
+            self.runtime().compose(),
+            args.join(" ")
+        ))
+        .context("compose", Some(host))
+    }
+
+    fn cmd_scale(&self, host: &str, services: &[String]) -> DeployResult<()> {
+        let specs = services
+            .iter()
+            .map(|s| scale::parse_spec(s))
+            .collect::<DeployResult<Vec<_>>>()?;
+        let names: Vec<String> = specs.iter().map(|(name, _)| name.clone()).collect();
+        self.validate_only(&names)?;
+
+        let ssh = self.session(host, false);
+        events::phase_started("scale", Some(host));
+        self.scale(&ssh, host, &specs).context("scale", Some(host))
+    }
+
+    /// Apply `specs` on top of the recorded replica counts, then
+    /// update the compose file, the running services, the
+    /// recorded counts, and Caddy's upstreams.
+    fn scale(&self, ssh: &SshSession, host: &str, specs: &[(String, u32)]) -> DeployResult<()> {
+        let mut replicas = self.recorded_replicas(ssh)?;
+        for (name, count) in specs {
+            replicas.insert(name.clone(), *count);
+        }
+        let apps = scale::apply(&self.apps, &replicas);
+
+        // A service with a fixed container name can't be scaled,
+        // so the compose file must match before `up`
+        self.write_stack_file(
+            ssh,
+            &compose::render(&apps, &self.caddy),
+            &format!("{}/docker-compose.yml", self.remote_dir),
+            compose::file_attrs(&apps, &self.caddy),
+        )?;
+
+        let mut args = Vec::new();
+        for (name, count) in specs {
+            eprintln!("Scaling {name} to {count}...");
+            args.push(format!("--scale {name}={count}"));
+        }
+        let names: Vec<&str> = specs.iter().map(|(name, _)| name.as_str()).collect();
+        ssh.exec_interactive(&format!(
+            "cd {} && {} up -d --no-deps {} {}",
+            self.remote_dir,
+            self.runtime().compose(),
+            args.join(" "),
+            names.join(" ")
+        ))?;
+        ssh.write_remote_file(
+            &scale::render_state(&replicas),
+            &format!("{}/{}", self.remote_dir, scale::STATE_FILE),
+        )?;
+
+        if names.iter().any(|name| self.is_routed(name)) {
+            eprintln!("Updating Caddy upstreams...");
+            self.reload_caddy(ssh, host, &apps)?;
+        }
+
+        let running: Vec<App> = apps
+            .into_iter()
+            .filter(|a| a.replicas > 0 && names.contains(&a.name.as_str()))
+            .collect();
+        let rd = &self.remote_dir;
+        let runtime = self.runtime();
+        wait_healthy_or_report(
+            &running,
+            &HealthWait::default(),
+            |name| {
+                let command = runtime.health_status_command(name);
+                ssh.exec(&format!("cd {rd} && {command}"))
+            },
+            |name| {
+                let command = runtime.unhealthy_report_command(name);
+                ssh.exec(&format!("cd {rd} && {command}"))
+            },
+        )?;
+
+        ssh.exec_interactive(&format!(
+            "cd {} && {} ps",
+            self.remote_dir,
+            self.runtime().compose()
+        ))
+    }
+
+    fn cmd_rollback(
+        &self,
+        host: &str,
+        only: &[String],
+        to: Option<&str>,
+        list: bool,
+    ) -> DeployResult<()> {
+        self.validate_only(only)?;
+        let selected = self.selected_apps(only);
+        let built = built_apps(&selected);
+        if built.is_empty() {
+            return Err(DeployError::Other(
+                "no built images to roll back (prebuilt images are pulled)".into(),
+            ));
+        }
+        if self.keep_images == 0 {
+            return Err(DeployError::Other(
+                "no previous images are kept (keep_images is 0)".into(),
+            ));
+        }
+        if let Some(version) = to.filter(|v| !rollback::is_version(v)) {
+            return Err(DeployError::Other(format!(
+                "invalid version '{version}': expected its number or its tag"
+            )));
+        }
+
+        let ssh = self.session(host, false);
+        if list {
+            for app in built {
+                let versions = ssh.exec(&rollback::list_command(&app.name, self.runtime()))?;
+                println!("{}:", app.name);
+                for version in versions.lines() {
+                    println!("  {version}");
+                }
+            }
+            return Ok(());
+        }
+        events::phase_started("rollback", Some(host));
+        self.rollback(&ssh, &built, to)
+            .context("rollback", Some(host))
+    }
+
+    /// Make the previous image of each of `apps`, or their
+    /// version `to`, the current one and restart their services.
+    fn rollback(&self, ssh: &SshSession, apps: &[&App], to: Option<&str>) -> DeployResult<()> {
+        let runtime = self.runtime();
+        for app in apps {
+            if let Some(version) = to {
+                eprintln!("Rolling back {} to version {version}...", app.name);
+                ssh.exec(&rollback::rollback_to_command(
+                    &app.name,
+                    version,
+                    self.keep_images,
+                    runtime,
+                ))?;
+            } else {
+                eprintln!("Rolling back {} to its previous image...", app.name);
+                ssh.exec(&rollback::rollback_command(
+                    &app.name,
+                    self.keep_images,
+                    runtime,
+                ))?;
+            }
+        }
+
+        let names: Vec<&str> = apps.iter().map(|a| a.name.as_str()).collect();
+        ssh.exec_interactive(&format!(
+            "cd {} && {} up -d --no-deps {}",
+            self.remote_dir,
+            runtime.compose(),
+            names.join(" ")
+        ))?;
+
+        let apps: Vec<App> = apps.iter().map(|a| (*a).clone()).collect();
+        let rd = &self.remote_dir;
+        wait_healthy_or_report(
+            &apps,
+            &HealthWait::default(),
+            |name| {
+                let command = runtime.health_status_command(name);
+                ssh.exec(&format!("cd {rd} && {command}"))
+            },
+            |name| {
+                let command = runtime.unhealthy_report_command(name);
+                ssh.exec(&format!("cd {rd} && {command}"))
+            },
+        )?;
+
+        ssh.exec_interactive(&format!(
+            "cd {} && {} ps",
+            self.remote_dir,
+            self.runtime().compose()
+        ))
+    }
+
+    /// Load the maintenance Caddyfile, or the regular one again,
+    /// through Caddy's admin API (`caddy reload`), without
+    /// restarting anything.
+    fn cmd_maintenance(&self, host: &str, on: bool, retry_after: u32) -> DeployResult<()> {
+        let ssh = self.session(host, false);
+        events::phase_started("maintenance", Some(host));
+        self.maintenance(&ssh, host, on, retry_after)
+            .context("maintenance", Some(host))
+    }
+
+    fn maintenance(
+        &self,
+        ssh: &SshSession,
+        host: &str,
+        on: bool,
+        retry_after: u32,
+    ) -> DeployResult<()> {
+        let rd = &self.remote_dir;
+        if self.shares_caddy(&self.apps) {
+            return self.shared_maintenance(ssh, host, on, retry_after);
+        }
+        let runtime = self.runtime();
+        let compose = runtime.compose();
+        if !on {
+            ssh.exec_interactive(&format!(
+                "cd {rd} && {compose} exec -T caddy {}",
+                self.caddy.reload_command()
+            ))?;
+            eprintln!("Maintenance mode off for {host}");
+            return Ok(());
+        }
+
+        let html = maintenance_html(&self.caddy)?;
+        let content =
+            caddyfile::render_maintenance(&self.caddy, host, &self.apps, &html, retry_after);
+        ssh.write_remote_file(&content, &format!("{rd}/Caddyfile.maintenance"))?;
+        // podman-compose has no `cp`
+        let copy = match runtime {
+            Runtime::Docker => "docker compose cp Caddyfile.maintenance caddy".to_string(),
+            Runtime::Podman => format!(
+                "podman cp Caddyfile.maintenance $({})",
+                runtime.containers("caddy", false)
+            ),
+        };
+        ssh.exec_interactive(&format!(
+            "cd {rd} && \
+             {copy}:/etc/caddy/Caddyfile.maintenance && \
+             {compose} exec -T caddy \
+             caddy reload --config /etc/caddy/Caddyfile.maintenance --adapter caddyfile"
+        ))?;
+        eprintln!("Maintenance mode on for {host}; turn it off with:");
+        eprintln!("  cargo xtask maintenance {host} --off");
+        Ok(())
+    }
+
+    /// Maintenance mode through the shared Caddy: the stack's
+    /// site file is swapped for the maintenance page and back.
+    fn shared_maintenance(
+        &self,
+        ssh: &SshSession,
+        host: &str,
+        on: bool,
+        retry_after: u32,
+    ) -> DeployResult<()> {
+        let stack = compose::stack_name(&self.apps);
+        if !on {
+            let site = shared_caddy::render_site(&self.caddy, host, &self.apps)?;
+            shared_caddy::publish(ssh, &stack, &site)?;
+            eprintln!("Maintenance mode off for {host}");
+            return Ok(());
+        }
+        let html = maintenance_html(&self.caddy)?;
+        let content =
+            caddyfile::render_maintenance(&self.caddy, host, &self.apps, &html, retry_after);
+        shared_caddy::publish(ssh, &stack, &content)?;
+        eprintln!("Maintenance mode on for {host}; turn it off with:");
+        eprintln!("  cargo xtask maintenance {host} --off");
+        Ok(())
+    }
+
+    /// Whether the stack's sites are served by the shared Caddy.
+    fn shares_caddy(&self, apps: &[App]) -> bool {
+        self.caddy.shared && compose::proxies(apps, &self.caddy)
+    }
+
+    /// Whether Caddy proxies requests to the app `name`.
+    fn is_routed(&self, name: &str) -> bool {
+        self.caddy.reverse_proxy.iter().any(|up| up.name == name)
+            || self.caddy.routes.iter().any(|(_, up)| up.name == name)
+            || self
+                .apps
+                .iter()
+                .any(|a| a.name == name && a.domain.is_some())
+    }
+
+    fn cmd_tunnel(&self, host: &str, forward: &str) -> DeployResult<()> {
+        let mut fwd = PortForward::parse(forward)?;
+        let ssh = self.session(host, false);
+
+        // App containers are not published on the host; resolve
+        // their address on the Docker network instead.
+        if self.apps.iter().any(|a| a.name == fwd.remote_host) {
+            // First replica when the app is scaled
+            let runtime = self.runtime();
+            let networks = ssh.exec(&format!(
+                "cd {} && {} inspect -f \
+                 '{{{{range $name, $net := .NetworkSettings.Networks}}}}\
+                 {{{{$name}}}} {{{{$net.IPAddress}}}}{{{{println}}}}{{{{end}}}}' \
+                 $({} | head -n 1)",
+                self.remote_dir,
+                runtime.cli(),
+                runtime.containers(&fwd.remote_host, false)
+            ))?;
+            let network = format!("{}-network", compose::stack_name(&self.apps));
+            let ip = stack_network_ip(&networks, &network);
+            if ip.is_empty() {
+                return Err(DeployError::Other(format!(
+                    "container '{}' has no IP address; is it running?",
+                    fwd.remote_host
+                )));
+            }
+            eprintln!("Container {} is at {ip}", fwd.remote_host);
+            fwd.remote_host = ip;
+        }
+
+        eprintln!(
+            "Forwarding 127.0.0.1:{} -> {}:{} via {} \
+             (Ctrl-C to stop)",
+            fwd.local_port,
+            fwd.remote_host,
+            fwd.remote_port,
+            ssh.destination()
+        );
+        events::phase_started("tunnel", Some(host));
+        ssh.forward(&fwd).context("tunnel", Some(host))
+    }
+
+    fn cmd_console(&self, name: &str) -> DeployResult<()> {
+        let provisioner = self
+            .provisioner
+            .as_ref()
+            .ok_or_else(|| DeployError::Other("no provisioner configured".into()))?;
+        events::phase_started("console", None);
+        provisioner.console(name).context("console", None)
+    }
+
+    fn cmd_import(&self, name: &str, ip: &str, domain: Option<&str>) -> DeployResult<()> {
+        if ip.parse::<std::net::IpAddr>().is_err() {
+            return Err(DeployError::InvalidConfig(format!(
+                "{ip} is not an IP address"
+            )));
+        }
+
+        // Same checks as before a deploy, except that an
+        // unreachable server is an error rather than skipped
+        events::phase_started("import", Some(ip));
+        // Pin the server's key unless one is pinned already
+        self.clear_replaced_host_keys(name, ip, domain);
+        eprintln!("Checking {ip}...");
+        let ssh = SshSession::new(ip, &self.ssh_user)
+            .with_options(&self.ssh.clone().trust_new_host_key());
+        let output = ssh
+            .exec(&preflight::host_setup_command(
+                &self.remote_dir,
+                self.runtime(),
+            ))
+            .context("import", Some(ip))?;
+        let setup = preflight::parse_host_setup(&output);
+        if let Some(problem) = setup.problem(&self.remote_dir) {
+            return Err(DeployError::HostNotPrepared(problem)).context("import", Some(ip));
+        }
+
+        let record = self
+            .save_server(name, ip, domain)
+            .context("import", Some(ip))?;
+        self.pin_host_key(&record).context("import", Some(ip))?;
+        if let Some(domain) = domain {
+            self.update_state(|state| {
+                state.dns_records.insert(
+                    domain.to_string(),
+                    DnsRecord {
+                        server: name.to_string(),
+                        ip: ip.to_string(),
+                        ipv6: None,
+                    },
+                );
+            })
+            .context("import", Some(ip))?;
+        }
+        eprintln!("Server '{name}' imported at {}", domain.unwrap_or(ip));
+        Ok(())
+    }
+
+    /// Print the audit trail, or its `last` entries.
+    fn cmd_audit(&self, last: Option<usize>) -> DeployResult<()> {
+        let (location, trail) =
+            self.with_state_store(|store| (store.location(), store.load_trail()));
+        let trail = trail?;
+        if trail.is_empty() {
+            eprintln!("No operations recorded beside {location}");
+            return Ok(());
+        }
+        let skip = last.map_or(0, |n| trail.len().saturating_sub(n));
+        for entry in &trail[skip..] {
+            println!("{entry}");
+        }
+        Ok(())
+    }
+
+    /// The CI workflow deploying on pushes to `branch`, with the
+    /// env files, registry tokens and confirmation the deploys
+    /// need.
+    fn ci_workflow(&self, branch: &str) -> Workflow {
+        let mut workflow = Workflow::new(branch);
+        for app in &self.apps {
+            if let Some(path) = &app.env_file {
+                workflow = workflow.env_file(path, &ci::env_file_secret(&app.name));
+            }
+        }
+        for registry in &self.registries {
+            workflow = workflow.secret(&registry.token_env);
+        }
+        let purges = self
+            .post_deploy
+            .iter()
+            .any(|hook| matches!(hook, PostDeployHook::PurgeCache(_)));
+        if purges {
+            workflow = workflow.secret(dns::cloudflare::TOKEN_ENV);
+        }
+        if let Some(environment) = &self.protected {
+            workflow = workflow.environment(environment);
+        }
+        workflow
+    }
+
+    fn cmd_cache_clean(&self) -> DeployResult<()> {
+        let provisioner = self
+            .provisioner
+            .as_ref()
+            .ok_or_else(|| DeployError::Other("no provisioner configured".into()))?;
+        events::phase_started("cache clean", None);
+        provisioner.clean_image_cache().context("cache clean", None)
+    }
+
+    fn cmd_destroy_dry_run(&self, name: &str) -> DeployResult<()> {
+        let provisioner = self
+            .provisioner
+            .as_ref()
+            .ok_or_else(|| DeployError::Other("no provisioner configured".into()))?;
+
+        eprintln!("=== Dry run: no changes will be made ===");
+        eprintln!();
+        eprintln!("--- Would be removed ---");
+        for item in provisioner.destroy_plan(name)? {
+            println!("  - {item}");
+        }
+        let app_dns = self.app_dns();
+        for dns in self.dns.iter().chain(&app_dns) {
+            println!("  - DNS A record for {}", dns.domain());
+        }
+        for dns in &self.caa_dns {
+            println!("  - DNS CAA records for {}", dns.domain());
+        }
+
+        Ok(())
     }
 
     fn cmd_destroy(&self, name: &str, force: bool) -> DeployResult<()> {
@@ -611,10 +2646,12 @@ impl Pipeline {
             "WARNING: This will permanently delete \
              droplet '{name}'"
         );
-        if !self.dns.is_empty() {
-            for dns in &self.dns {
-                eprintln!("and DNS record for {}", dns.domain());
-            }
+        let app_dns = self.app_dns();
+        for dns in self.dns.iter().chain(&app_dns) {
+            eprintln!("and DNS record for {}", dns.domain());
+        }
+        for dns in &self.caa_dns {
+            eprintln!("and CAA records for {}", dns.domain());
         }
         eprintln!();
 
@@ -629,13 +2666,61 @@ impl Pipeline {
             }
         }
 
-        provisioner.destroy_server(name)?;
+        self.audited("destroy", name, None, || {
+            self.destroy(provisioner.as_ref(), name, &app_dns)
+        })
+    }
+
+    /// Delete the server `name` and the DNS records of the
+    /// pipeline, and forget them.
+    fn destroy(
+        &self,
+        provisioner: &dyn Provisioner,
+        name: &str,
+        app_dns: &[Box<dyn DnsProvider>],
+    ) -> DeployResult<()> {
+        events::phase_started("destroy", None);
+        provisioner.destroy_server(name).context("destroy", None)?;
+        let record = self.forget_server(name);
+        let domains = self
+            .dns
+            .iter()
+            .chain(app_dns)
+            .map(|d| d.domain().to_string());
+        self.clear_known_hosts(
+            std::iter::once(name.to_string())
+                .chain(
+                    record
+                        .into_iter()
+                        .flat_map(|r| std::iter::once(r.ip).chain(r.domain)),
+                )
+                .chain(domains),
+        );
 
         // Remove DNS records
-        for dns in &self.dns {
+        for dns in self.dns.iter().chain(app_dns) {
             let d = dns.domain();
+            events::phase_started("DNS cleanup", Some(d));
             eprintln!("Removing DNS record for {d}...");
-            dns.delete_a_record()?;
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                dns.delete_a_record()
+            })
+            .context("DNS cleanup", Some(d))?;
+            if dns.manages_aaaa_records() {
+                cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                    dns.delete_aaaa_record()
+                })
+                .context("DNS cleanup", Some(d))?;
+            }
+        }
+        for dns in &self.caa_dns {
+            let d = dns.domain();
+            events::phase_started("DNS cleanup", Some(d));
+            eprintln!("Removing CAA records for {d}...");
+            cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+                dns.delete_caa_records()
+            })
+            .context("DNS cleanup", Some(d))?;
         }
 
         eprintln!();
@@ -645,6 +2730,63 @@ impl Pipeline {
     }
 }
 
+/// Flags of `deploy` applying to each of its hosts.
+struct DeployFlags<'a> {
+    skip_build: bool,
+    only: &'a [String],
+    profiles: &'a [String],
+    trust_new_hostkey: bool,
+    rollback_on_failure: bool,
+}
+
+/// Whether a deploy failing with `e` got as far as loading the
+/// new images and restarting services, so rolling back undoes
+/// something.
+fn restarted_services(e: &DeployError) -> bool {
+    matches!(e, DeployError::Context { phase, .. } if phase == "deploy" || phase == "post-deploy hooks")
+}
+
+/// The IP address on `network` in the `name ip` lines listing a
+/// container's networks, or on the first one listed. Compose
+/// prefixes network names with the project name.
+fn stack_network_ip(networks: &str, network: &str) -> String {
+    let entries: Vec<(&str, &str)> = networks
+        .lines()
+        .filter_map(|l| l.trim().split_once(' '))
+        .filter(|(_, ip)| !ip.is_empty())
+        .collect();
+    entries
+        .iter()
+        .find(|(name, _)| *name == network || name.ends_with(&format!("_{network}")))
+        .or_else(|| entries.first())
+        .map(|(_, ip)| (*ip).to_string())
+        .unwrap_or_default()
+}
+
+/// Apps that are built locally, skipping those running a
+/// prebuilt [`App::image`].
+fn built_apps<'a>(apps: &[&'a App]) -> Vec<&'a App> {
+    apps.iter().copied().filter(|a| a.image.is_none()).collect()
+}
+
+/// `apps` that start when deploying with `profiles`.
+fn active_apps<'a>(apps: Vec<&'a App>, profiles: &[String]) -> Vec<&'a App> {
+    apps.into_iter()
+        .filter(|a| compose::is_active(a, profiles))
+        .collect()
+}
+
+/// The maintenance page set on `caddy`, or the default one.
+fn maintenance_html(caddy: &Caddy) -> DeployResult<String> {
+    caddy.maintenance_page.as_ref().map_or_else(
+        || Ok(caddyfile::DEFAULT_MAINTENANCE_PAGE.to_string()),
+        |path| {
+            std::fs::read_to_string(path)
+                .map_err(|_| DeployError::FileNotFound(format!("maintenance page: {path}")))
+        },
+    )
+}
+
 /// Run `docker compose` with an explicit project directory
 /// so relative paths and project naming stay consistent.
 fn run_local_compose(local_dir: &str, args: &[&str]) -> DeployResult<()> {
@@ -693,6 +2835,15 @@ fn print_dnsmasq_hint() {
 struct Cli {
     #[command(subcommand)]
     command: Command,
+
+    /// Echo every external and remote command as it runs
+    #[arg(long, global = true)]
+    trace_commands: bool,
+
+    /// Name of the protected environment, confirming a deploy or
+    /// destroy of it
+    #[arg(long, global = true, value_name = "ENVIRONMENT")]
+    confirm: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -709,12 +2860,27 @@ enum Command {
         /// Cloud region
         #[arg(long)]
         region: Option<String>,
+
+        /// Server size, overriding the configured one
+        #[arg(long)]
+        size: Option<String>,
+
+        /// OS image, overriding the configured one
+        #[arg(long)]
+        image: Option<String>,
+
+        /// Provider SSH key ID to install instead of the detected
+        /// keys (repeatable)
+        #[arg(long = "ssh-key")]
+        ssh_key: Vec<String>,
     },
 
     /// Deploy to a server
     Deploy {
-        /// Hostname or IP address
-        host: String,
+        /// Hostnames, IP addresses, or names of provisioned
+        /// servers, deployed one at a time
+        #[arg(required = true, value_name = "HOST")]
+        hosts: Vec<String>,
 
         /// Skip Docker image build
         #[arg(long)]
@@ -727,6 +2893,24 @@ enum Command {
         /// Deploy only the listed services (repeatable)
         #[arg(long)]
         only: Vec<String>,
+
+        /// Also start the services of this compose profile
+        /// (repeatable)
+        #[arg(long)]
+        profile: Vec<String>,
+
+        /// Replace the pinned host key (after a server rebuild)
+        #[arg(long)]
+        trust_new_hostkey: bool,
+
+        /// Don't wait for containers to become healthy
+        #[arg(long)]
+        no_wait: bool,
+
+        /// Put the previous images back on a host whose new
+        /// containers fail to start or become healthy
+        #[arg(long)]
+        rollback_on_failure: bool,
     },
 
     /// Deploy locally for testing
@@ -747,16 +2931,143 @@ enum Command {
         only: Vec<String>,
     },
 
+    /// Run the stack locally for development: plain HTTP on
+    /// localhost, exposed ports published, dev volumes mounted
+    Dev {
+        /// Skip Docker image build
+        #[arg(long)]
+        skip_build: bool,
+    },
+
     /// Stop the local stack
     LocalDown,
 
     /// Show local container status
     LocalStatus,
 
-    /// Show container status on a remote server
+    /// Show container status and server metrics on a remote server
     Status {
-        /// Hostname or IP address
+        /// Hostname, IP address, or name of a provisioned server
+        host: String,
+
+        /// Replace the pinned host key (after a server rebuild)
+        #[arg(long)]
+        trust_new_hostkey: bool,
+    },
+
+    /// Show CPU and memory use of each container on a remote server
+    Top {
+        /// Hostname, IP address, or name of a provisioned server
+        host: String,
+    },
+
+    /// Run `docker compose` with any arguments in the remote directory
+    Compose {
+        /// Hostname, IP address, or name of a provisioned server
+        host: String,
+
+        /// Arguments passed on to `docker compose`, after `--`
+        #[arg(last = true, required = true)]
+        args: Vec<String>,
+    },
+
+    /// Change the number of replicas of running services
+    Scale {
+        /// Hostname, IP address, or name of a provisioned server
+        host: String,
+
+        /// `SERVICE=COUNT` pairs, e.g. `api=3 worker=2`
+        #[arg(required = true)]
+        services: Vec<String>,
+    },
+
+    /// Go back to the image deployed before the current one
+    Rollback {
+        /// Hostname, IP address, or name of a provisioned server
+        host: String,
+
+        /// Roll back only the listed services (repeatable)
+        #[arg(long)]
+        only: Vec<String>,
+
+        /// Go back to this version, by number (e.g. 3) or tag
+        /// (e.g. deploy-3-1a2b3c4), instead of the previous image
+        #[arg(long)]
+        to: Option<String>,
+
+        /// List the versions kept on the server instead
+        #[arg(long, conflicts_with = "to")]
+        list: bool,
+    },
+
+    /// Switch Caddy to a maintenance page, or back
+    Maintenance {
+        /// Hostname, IP address, or name of a provisioned server
         host: String,
+
+        /// Answer every request with the maintenance page (503)
+        #[arg(long, conflicts_with = "off", required_unless_present = "off")]
+        on: bool,
+
+        /// Restore normal proxying
+        #[arg(long)]
+        off: bool,
+
+        /// Seconds advertised in the `Retry-After` header
+        #[arg(long, default_value_t = 300)]
+        retry_after: u32,
+    },
+
+    /// Forward a local port through SSH to the server
+    Tunnel {
+        /// Hostname, IP address, or name of a provisioned server
+        host: String,
+
+        /// `LOCAL:HOST:REMOTE` (as `ssh -L`); HOST may be an app
+        /// name to reach its container
+        forward: String,
+    },
+
+    /// Attach to the serial console of a server, e.g. a libvirt VM
+    /// that never got an IP
+    Console {
+        /// Server name
+        name: String,
+    },
+
+    /// Adopt a server that wasn't provisioned by catapulta, after
+    /// checking it is reachable over SSH and runs Docker
+    Import {
+        /// Name to give the server
+        name: String,
+
+        /// IP address of the server
+        #[arg(long)]
+        ip: String,
+
+        /// Domain already pointing at the server
+        #[arg(long)]
+        domain: Option<String>,
+    },
+
+    /// Show the audit trail of provisions, imports, deploys,
+    /// rollbacks and destroys
+    Audit {
+        /// Show only the last N entries
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+    },
+
+    /// Set up deploys from CI
+    Ci {
+        #[command(subcommand)]
+        action: CiCommand,
+    },
+
+    /// Manage the OS images cached by the provisioner
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
     },
 
     /// Destroy a server
@@ -767,5 +3078,36 @@ enum Command {
         /// Skip interactive confirmation prompt
         #[arg(long)]
         force: bool,
+
+        /// List what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CiCommand {
+    /// Print a workflow deploying on every push, to save in the
+    /// repository
+    Generate {
+        #[command(subcommand)]
+        platform: CiPlatform,
+    },
+}
+
+#[derive(Subcommand)]
+enum CiPlatform {
+    /// GitHub Actions, e.g. .github/workflows/deploy.yml
+    Github {
+        /// Branch whose pushes deploy
+        #[arg(long, default_value = "main")]
+        branch: String,
     },
 }
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove the cached images, e.g. cloud images on a libvirt
+    /// hypervisor
+    Clean,
+}
@@ -1,15 +1,25 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::cmd;
 use crate::error::{DeployError, DeployResult};
-use crate::provision::{Provisioner, ServerInfo};
-use crate::ssh::SshSession;
+use crate::provision::setup::{self, SetupStep};
+use crate::provision::{ProvisionOverrides, Provisioner, ServerInfo};
+use crate::ssh::{SshOptions, SshSession};
+
+/// How long `doctl compute droplet create --wait` may take.
+const CREATE_TIMEOUT: Duration = Duration::from_secs(600);
 
 /// `DigitalOcean` provisioner using `doctl` CLI.
 pub struct DigitalOcean {
     pub size: String,
     pub region: String,
     pub image: String,
+    /// Steps run by [`Provisioner::setup_server`].
+    pub setup_steps: Vec<SetupStep>,
+    /// Regions tried in order when the requested one has no
+    /// capacity for the droplet.
+    pub fallback_regions: Vec<String>,
 }
 
 impl DigitalOcean {
@@ -19,6 +29,8 @@ impl DigitalOcean {
             size: "s-1vcpu-1gb".to_string(),
             region: "fra1".to_string(),
             image: "ubuntu-24-04-x64".to_string(),
+            setup_steps: SetupStep::defaults(),
+            fallback_regions: Vec::new(),
         }
     }
 
@@ -40,12 +52,55 @@ impl DigitalOcean {
         self
     }
 
+    /// Add a step run after the others during
+    /// [`Provisioner::setup_server`].
+    #[must_use]
+    pub fn setup_step(mut self, step: SetupStep) -> Self {
+        self.setup_steps.push(step);
+        self
+    }
+
+    /// Replace the setup steps, e.g. to run a custom step before
+    /// the default ones.
+    #[must_use]
+    pub fn setup_steps(mut self, steps: Vec<SetupStep>) -> Self {
+        self.setup_steps = steps;
+        self
+    }
+
+    /// Create the droplet in `region` when the requested region
+    /// is out of capacity or doesn't offer the size. Repeat to
+    /// try several, in order.
+    ///
+    /// ```
+    /// use catapulta::DigitalOcean;
+    ///
+    /// let do_ = DigitalOcean::new()
+    ///     .region("fra1")
+    ///     .fallback_region("ams3")
+    ///     .fallback_region("lon1");
+    ///
+    /// assert_eq!(do_.fallback_regions, ["ams3", "lon1"]);
+    /// ```
+    #[must_use]
+    pub fn fallback_region(mut self, region: &str) -> Self {
+        self.fallback_regions.push(region.to_string());
+        self
+    }
+
+    /// Leave out the setup step named `name` (e.g. `firewall`).
+    #[must_use]
+    pub fn skip_setup_step(mut self, name: &str) -> Self {
+        self.setup_steps.retain(|step| step.name() != name);
+        self
+    }
+
     /// Detect all SSH keys registered with `DigitalOcean` that
     /// have a matching local private key.
     ///
     /// Returns a list of `(key_id, private_key_path)` pairs.
     fn detect_do_ssh_keys() -> DeployResult<Vec<(String, String)>> {
-        let output = cmd::run(
+        let output = cmd::run_with_timeout(
             "doctl",
             &[
                 "compute",
@@ -55,6 +110,7 @@ impl DigitalOcean {
                 "ID,FingerPrint",
                 "--no-header",
             ],
+            cmd::API_TIMEOUT,
         )?;
 
         if output.trim().is_empty() {
@@ -130,34 +186,21 @@ impl DigitalOcean {
         Ok(matched)
     }
 
-    fn get_droplet_ip(name: &str) -> DeployResult<String> {
-        let output = cmd::run(
+    /// The output of `doctl compute droplet list` read by
+    /// [`parse_droplet`].
+    fn list_droplets() -> DeployResult<String> {
+        cmd::run_with_timeout(
             "doctl",
             &[
                 "compute",
                 "droplet",
                 "list",
                 "--format",
-                "Name,PublicIPv4",
+                "Name,PublicIPv4,Region,PublicIPv6",
                 "--no-header",
             ],
-        )?;
-
-        for line in output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 && parts[0] == name {
-                return Ok(parts[1].to_string());
-            }
-        }
-
-        Err(DeployError::ServerNotFound(name.into()))
-    }
-
-    /// Run the remote setup script over SSH.
-    fn run_setup_script(ssh: &SshSession, domain: &str, remote_dir: &str) -> DeployResult<()> {
-        let script = include_str!("../../scripts/setup-server.sh");
-        let escaped = script.replace('\'', "'\\''");
-        ssh.exec_interactive(&format!("bash -c '{escaped}' _ '{domain}' '{remote_dir}'"))
+            cmd::API_TIMEOUT,
+        )
     }
 }
 
@@ -179,7 +222,7 @@ impl Provisioner for DigitalOcean {
             ));
         }
 
-        cmd::run("doctl", &["account", "get"]).map_err(|_| {
+        cmd::run_with_timeout("doctl", &["account", "get"], cmd::API_TIMEOUT).map_err(|_| {
             DeployError::PrerequisiteMissing(
                 "doctl is not authenticated. \
                  Run: doctl auth init"
@@ -195,37 +238,80 @@ impl Provisioner for DigitalOcean {
         Self::detect_do_ssh_keys()
     }
 
+    fn default_region(&self) -> &str {
+        &self.region
+    }
+
     fn create_server(
         &self,
         name: &str,
         region: &str,
         ssh_key_ids: &[String],
     ) -> DeployResult<ServerInfo> {
-        eprintln!("Creating droplet '{name}' in {region}...");
+        self.create_server_with(name, region, ssh_key_ids, &ProvisionOverrides::default())
+    }
 
-        let ids_csv = ssh_key_ids.join(",");
+    fn create_server_with(
+        &self,
+        name: &str,
+        region: &str,
+        ssh_key_ids: &[String],
+        overrides: &ProvisionOverrides,
+    ) -> DeployResult<ServerInfo> {
+        let size = overrides.size.as_deref().unwrap_or(&self.size);
+        let image = overrides.image.as_deref().unwrap_or(&self.image);
+        let ssh_key_ids = if overrides.ssh_keys.is_empty() {
+            ssh_key_ids
+        } else {
+            &overrides.ssh_keys
+        };
 
-        cmd::run_interactive(
-            "doctl",
-            &[
-                "compute",
-                "droplet",
-                "create",
-                name,
-                "--image",
-                &self.image,
-                "--size",
-                &self.size,
-                "--region",
-                region,
-                "--ssh-keys",
-                &ids_csv,
-                "--enable-monitoring",
-                "--wait",
-            ],
-        )?;
+        let ids_csv = ssh_key_ids.join(",");
+        let mut regions = vec![region];
+        for fallback in &self.fallback_regions {
+            if !regions.contains(&fallback.as_str()) {
+                regions.push(fallback);
+            }
+        }
+        let mut created = region;
+        for (i, &candidate) in regions.iter().enumerate() {
+            eprintln!("Creating droplet '{name}' ({size}, {image}) in {candidate}...");
+            match cmd::run_classified(
+                "doctl",
+                &[
+                    "compute",
+                    "droplet",
+                    "create",
+                    name,
+                    "--image",
+                    image,
+                    "--size",
+                    size,
+                    "--region",
+                    candidate,
+                    "--ssh-keys",
+                    &ids_csv,
+                    "--enable-monitoring",
+                    "--enable-ipv6",
+                    "--wait",
+                ],
+                CREATE_TIMEOUT,
+                |stderr| create_error(stderr, candidate, size),
+            ) {
+                Ok(_) => {
+                    created = candidate;
+                    break;
+                }
+                Err(e @ DeployError::RegionUnavailable { .. }) if i + 1 < regions.len() => {
+                    eprintln!("Warning: {e}, trying {}", regions[i + 1]);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let region = created;
 
-        let ip = Self::get_droplet_ip(name)?;
+        let (ip, _, ipv6) = parse_droplet(&Self::list_droplets()?, name)
+            .ok_or_else(|| DeployError::ServerNotFound(name.into()))?;
         eprintln!("Droplet created! IP: {ip}");
 
         let keys = Self::detect_do_ssh_keys()?;
@@ -234,27 +320,35 @@ impl Provisioner for DigitalOcean {
         Ok(ServerInfo {
             name: name.to_string(),
             ip,
+            ipv6,
             region: region.to_string(),
             ssh_key_ids: ids,
             ssh_key_files: files,
         })
     }
 
-    fn setup_server(&self, server: &ServerInfo, domain: Option<&str>) -> DeployResult<()> {
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        ssh_options: &SshOptions,
+    ) -> DeployResult<()> {
         SshSession::clear_known_host(&server.ip);
-        let ssh = SshSession::new(&server.ip, "root").with_keys(&server.ssh_key_files);
+        let ssh = SshSession::new(&server.ip, "root")
+            .with_keys(&server.ssh_key_files)
+            .with_options(ssh_options);
 
-        ssh.wait_for_ready(30, std::time::Duration::from_secs(10))?;
+        ssh.wait_until_ready()?;
 
         let domain_str = domain.unwrap_or(&server.ip);
         let remote_dir = "/opt/app";
 
-        Self::run_setup_script(&ssh, domain_str, remote_dir)?;
+        setup::run(&ssh, &self.setup_steps, domain_str, remote_dir)?;
 
         // Setup SSH config (use first key for the config entry)
         let host_alias = domain.unwrap_or(&server.name);
         let first_key = server.ssh_key_files.first().map_or("", String::as_str);
-        super::setup_ssh_config(&server.ip, host_alias, first_key)?;
+        super::setup_ssh_config(&server.ip, host_alias, first_key, ssh_options)?;
 
         eprintln!();
         eprintln!("========================================");
@@ -278,65 +372,29 @@ impl Provisioner for DigitalOcean {
     }
 
     fn get_server(&self, name: &str) -> DeployResult<Option<ServerInfo>> {
-        let output = cmd::run(
-            "doctl",
-            &[
-                "compute",
-                "droplet",
-                "list",
-                "--format",
-                "Name,PublicIPv4,Region",
-                "--no-header",
-            ],
-        )?;
-
-        for line in output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 3 && parts[0] == name {
-                let keys = Self::detect_do_ssh_keys()?;
-                let (ids, files): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
-                return Ok(Some(ServerInfo {
-                    name: name.to_string(),
-                    ip: parts[1].to_string(),
-                    region: parts[2].to_string(),
-                    ssh_key_ids: ids,
-                    ssh_key_files: files,
-                }));
-            }
-        }
-
-        Ok(None)
+        let Some((ip, region, ipv6)) = parse_droplet(&Self::list_droplets()?, name) else {
+            return Ok(None);
+        };
+        let keys = Self::detect_do_ssh_keys()?;
+        let (ids, files): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
+        Ok(Some(ServerInfo {
+            name: name.to_string(),
+            ip,
+            ipv6,
+            region,
+            ssh_key_ids: ids,
+            ssh_key_files: files,
+        }))
     }
 
     fn destroy_server(&self, name: &str) -> DeployResult<()> {
-        let output = cmd::run(
-            "doctl",
-            &[
-                "compute",
-                "droplet",
-                "list",
-                "--format",
-                "Name,ID",
-                "--no-header",
-            ],
-        )?;
-
-        let droplet_id = output
-            .lines()
-            .find_map(|line| {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 && parts[0] == name {
-                    Some(parts[1].to_string())
-                } else {
-                    None
-                }
-            })
-            .ok_or_else(|| DeployError::ServerNotFound(name.into()))?;
+        let droplet_id = droplet_id(name)?;
 
         eprintln!("Deleting droplet '{name}'...");
-        cmd::run(
+        cmd::run_with_timeout(
             "doctl",
             &["compute", "droplet", "delete", &droplet_id, "--force"],
+            cmd::API_TIMEOUT,
         )?;
         eprintln!("Droplet '{name}' deleted");
 
@@ -345,4 +403,90 @@ impl Provisioner for DigitalOcean {
 
         Ok(())
     }
+
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        let droplet_id = droplet_id(name)?;
+        let mut plan = vec![format!("droplet '{name}' (ID {droplet_id})")];
+        plan.extend(super::ssh_config_entry_plan(name));
+        Ok(plan)
+    }
+}
+
+/// Look up the ID of the droplet named `name`.
+fn droplet_id(name: &str) -> DeployResult<String> {
+    let output = cmd::run_with_timeout(
+        "doctl",
+        &[
+            "compute",
+            "droplet",
+            "list",
+            "--format",
+            "Name,ID",
+            "--no-header",
+        ],
+        cmd::API_TIMEOUT,
+    )?;
+
+    output
+        .lines()
+        .find_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 && parts[0] == name {
+                Some(parts[1].to_string())
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| DeployError::ServerNotFound(name.into()))
+}
+
+/// The public IPv4, region and public IPv6 of droplet `name`.
+///
+/// Reads the output of `doctl compute droplet list --format
+/// Name,PublicIPv4,Region,PublicIPv6 --no-header`, whose IPv6
+/// column is empty for droplets created without it.
+#[must_use]
+pub fn parse_droplet(output: &str, name: &str) -> Option<(String, String, Option<String>)> {
+    output.lines().find_map(|line| {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        (parts.len() >= 3 && parts[0] == name).then(|| {
+            (
+                parts[1].to_string(),
+                parts[2].to_string(),
+                parts.get(3).map(|ip| (*ip).to_string()),
+            )
+        })
+    })
+}
+
+/// The error behind a failed `doctl compute droplet create` in
+/// `region`, from the API message in its `stderr`, when it is
+/// one a user can act on.
+#[must_use]
+pub fn create_error(stderr: &str, region: &str, size: &str) -> Option<DeployError> {
+    let message = stderr.to_lowercase();
+    if message.contains("droplet limit") || message.contains("exceed your") {
+        let detail = stderr
+            .lines()
+            .rev()
+            .find(|l| !l.trim().is_empty())
+            .map_or(stderr, str::trim);
+        Some(DeployError::QuotaExceeded(detail.to_string()))
+    } else if message.contains("not available in this region")
+        || message.contains("region is not available")
+        || message.contains("currently unavailable")
+        || message.contains("capacity")
+    {
+        Some(DeployError::RegionUnavailable {
+            region: region.to_string(),
+            size: size.to_string(),
+        })
+    } else if message.contains("invalid size") {
+        Some(DeployError::InvalidSize {
+            size: size.to_string(),
+            region: region.to_string(),
+        })
+    } else {
+        None
+    }
 }
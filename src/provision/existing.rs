@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use crate::error::{DeployError, DeployResult};
+use crate::provision::setup::{self, SetupStep};
+use crate::provision::{Provisioner, ServerInfo};
+use crate::ssh::{SshOptions, SshSession};
+
+/// Provisioner for a server you already have, reachable as
+/// `root` with an SSH key.
+///
+/// Nothing is created or destroyed: `provision` runs the setup
+/// steps on the server (installing Docker, enabling the
+/// firewall, ...) and adds the SSH config entry, and `destroy`
+/// only removes that entry and the pinned host key, leaving the
+/// server and its containers alone. The setup steps are safe to
+/// run again, so `provision` can be repeated, e.g. after adding
+/// a step.
+pub struct ExistingServer {
+    /// IP or hostname of the server.
+    pub host: String,
+    /// Local SSH private key authorized for `root`.
+    pub ssh_key: String,
+    /// Steps run on the server by [`Provisioner::setup_server`].
+    pub setup_steps: Vec<SetupStep>,
+}
+
+impl ExistingServer {
+    #[must_use]
+    pub fn new(host: &str, ssh_key: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            ssh_key: ssh_key.to_string(),
+            setup_steps: SetupStep::defaults(),
+        }
+    }
+
+    /// Add a step run after the others during
+    /// [`Provisioner::setup_server`].
+    #[must_use]
+    pub fn setup_step(mut self, step: SetupStep) -> Self {
+        self.setup_steps.push(step);
+        self
+    }
+
+    /// Replace the setup steps, e.g. to run a custom step before
+    /// the default ones.
+    #[must_use]
+    pub fn setup_steps(mut self, steps: Vec<SetupStep>) -> Self {
+        self.setup_steps = steps;
+        self
+    }
+
+    /// Leave out the setup step named `name` (e.g. `firewall`).
+    #[must_use]
+    pub fn skip_setup_step(mut self, name: &str) -> Self {
+        self.setup_steps.retain(|step| step.name() != name);
+        self
+    }
+}
+
+impl Provisioner for ExistingServer {
+    fn check_prerequisites(&self) -> DeployResult<()> {
+        eprintln!("Checking prerequisites...");
+        if !Path::new(&self.ssh_key).exists() {
+            return Err(DeployError::FileNotFound(format!(
+                "SSH key not found: {}",
+                self.ssh_key
+            )));
+        }
+        eprintln!("Prerequisites OK");
+        Ok(())
+    }
+
+    fn detect_ssh_keys(&self) -> DeployResult<Vec<(String, String)>> {
+        Ok(vec![(String::new(), self.ssh_key.clone())])
+    }
+
+    fn create_server(
+        &self,
+        name: &str,
+        _region: &str,
+        _ssh_key_ids: &[String],
+    ) -> DeployResult<ServerInfo> {
+        eprintln!("Using existing server '{name}' at {}", self.host);
+        Ok(ServerInfo {
+            name: name.to_string(),
+            ip: self.host.clone(),
+            ipv6: None,
+            region: String::new(),
+            ssh_key_ids: Vec::new(),
+            ssh_key_files: vec![self.ssh_key.clone()],
+        })
+    }
+
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        ssh_options: &SshOptions,
+    ) -> DeployResult<()> {
+        // The server keeps its host key, so the pinned one stays
+        let ssh = SshSession::new(&server.ip, "root")
+            .with_keys(&server.ssh_key_files)
+            .with_options(ssh_options);
+
+        ssh.wait_until_ready()?;
+
+        let domain_str = domain.unwrap_or(&server.ip);
+        setup::run(&ssh, &self.setup_steps, domain_str, "/opt/app")?;
+
+        let host_alias = domain.unwrap_or(&server.name);
+        super::setup_ssh_config(&server.ip, host_alias, &self.ssh_key, ssh_options)?;
+
+        eprintln!();
+        eprintln!("========================================");
+        eprintln!("Server set up successfully!");
+        eprintln!("========================================");
+        eprintln!();
+        eprintln!("Server: {}", server.name);
+        eprintln!("Host: {}", server.ip);
+        if let Some(d) = domain {
+            eprintln!("Domain: {d}");
+        }
+        let deploy_host = domain.unwrap_or(&server.ip);
+        eprintln!("SSH: ssh {deploy_host}");
+        eprintln!();
+        eprintln!("Deploy with:");
+        eprintln!("  cargo xtask deploy {deploy_host}");
+        eprintln!();
+
+        Ok(())
+    }
+
+    /// Always `None`, so `provision` runs the setup steps again
+    /// instead of stopping at an existing server.
+    fn get_server(&self, _name: &str) -> DeployResult<Option<ServerInfo>> {
+        Ok(None)
+    }
+
+    fn destroy_server(&self, name: &str) -> DeployResult<()> {
+        eprintln!("Leaving server {} running; forgetting '{name}'", self.host);
+        SshSession::clear_known_host(&self.host);
+        super::remove_ssh_config_entry(name)?;
+        Ok(())
+    }
+
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        let mut plan = vec![format!(
+            "pinned host key of {} (the server itself is left running)",
+            self.host
+        )];
+        plan.extend(super::ssh_config_entry_plan(name));
+        Ok(plan)
+    }
+}
@@ -1,8 +1,10 @@
+use std::fmt::Write;
 use std::path::PathBuf;
 
 use crate::error::{DeployError, DeployResult};
+use crate::provision::setup::{self, SetupStep};
 use crate::provision::{Provisioner, ServerInfo};
-use crate::ssh::SshSession;
+use crate::ssh::{JumpHost, SshOptions, SshSession, shell_quote};
 
 /// Networking mode for the VM.
 #[derive(Debug, Clone)]
@@ -16,11 +18,25 @@ pub enum NetworkMode {
     Nat,
 }
 
+/// An extra disk attached to a VM, see [`Libvirt::data_disk`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDisk {
+    /// Size in GiB.
+    pub size_gib: u32,
+    /// Where the disk is mounted in the VM, e.g. `/srv/data`.
+    pub mount_path: String,
+}
+
 /// Libvirt/KVM provisioner for local or remote hypervisors.
 ///
 /// Manages virtual machines via `virsh` and `virt-install` over
 /// SSH. Cloud images are provisioned with cloud-init (`NoCloud`
 /// datasource).
+///
+/// Downloaded images are cached on the hypervisor under
+/// `<storage_dir>/cloud-images`, one file per URL, and checked
+/// against their SHA256 before each use: an image replaced
+/// upstream is downloaded again.
 pub struct Libvirt {
     /// SSH hostname or IP of the hypervisor.
     pub hypervisor_host: String,
@@ -28,6 +44,10 @@ pub struct Libvirt {
     pub hypervisor_user: String,
     /// Optional SSH private key for the hypervisor connection.
     pub hypervisor_key: Option<String>,
+    /// Optional SSH port of the hypervisor.
+    pub hypervisor_port: Option<u16>,
+    /// Optional bastion in front of the hypervisor.
+    pub hypervisor_jump: Option<JumpHost>,
     /// Number of vCPUs (default: 2).
     pub vcpus: u32,
     /// RAM in MiB (default: 2048).
@@ -36,6 +56,9 @@ pub struct Libvirt {
     pub disk_gib: u32,
     /// Cloud image URL to download on the hypervisor.
     pub image_url: String,
+    /// Expected SHA256 of the image. When unset, it is read from
+    /// the `SHA256SUMS` file published next to `image_url`.
+    pub image_sha256: Option<String>,
     /// Network mode (default: NAT).
     pub network: NetworkMode,
     /// Directory on the hypervisor for VM disk images.
@@ -45,6 +68,10 @@ pub struct Libvirt {
     pub vm_ssh_key: String,
     /// `os-variant` passed to `virt-install`.
     pub os_variant: String,
+    /// Extra disks attached after the OS disk.
+    pub data_disks: Vec<DataDisk>,
+    /// Steps run on the VM by [`Provisioner::setup_server`].
+    pub setup_steps: Vec<SetupStep>,
 }
 
 impl Libvirt {
@@ -63,6 +90,8 @@ impl Libvirt {
             hypervisor_host: hypervisor_host.to_string(),
             hypervisor_user: "root".to_string(),
             hypervisor_key: None,
+            hypervisor_port: None,
+            hypervisor_jump: None,
             vcpus: 2,
             memory_mib: 2048,
             disk_gib: 20,
@@ -70,10 +99,13 @@ impl Libvirt {
                 releases/24.04/release/\
                 ubuntu-24.04-server-cloudimg-amd64.img"
                 .to_string(),
+            image_sha256: None,
             network: NetworkMode::Nat,
             storage_dir: "/var/lib/libvirt/images".to_string(),
             vm_ssh_key: vm_ssh_key.to_string(),
             os_variant: "ubuntu24.04".to_string(),
+            data_disks: Vec::new(),
+            setup_steps: SetupStep::defaults(),
         }
     }
 
@@ -89,6 +121,26 @@ impl Libvirt {
         self
     }
 
+    #[must_use]
+    pub const fn hypervisor_port(mut self, port: u16) -> Self {
+        self.hypervisor_port = Some(port);
+        self
+    }
+
+    /// Reach the hypervisor through a bastion host.
+    ///
+    /// To reach NAT-networked VMs from outside the hypervisor,
+    /// pass the hypervisor itself to [`crate::Pipeline::jump_host`].
+    #[must_use]
+    pub fn hypervisor_jump(mut self, host: &str, user: &str, key: Option<&str>) -> Self {
+        let mut jump = JumpHost::new(host, user);
+        if let Some(key) = key {
+            jump = jump.key(key);
+        }
+        self.hypervisor_jump = Some(jump);
+        self
+    }
+
     #[must_use]
     pub const fn vcpus(mut self, n: u32) -> Self {
         self.vcpus = n;
@@ -113,6 +165,14 @@ impl Libvirt {
         self
     }
 
+    /// Pin the SHA256 of the image, for URLs with no
+    /// `SHA256SUMS` next to them.
+    #[must_use]
+    pub fn image_sha256(mut self, sha256: &str) -> Self {
+        self.image_sha256 = Some(sha256.to_lowercase());
+        self
+    }
+
     #[must_use]
     pub fn network(mut self, mode: NetworkMode) -> Self {
         self.network = mode;
@@ -131,11 +191,55 @@ impl Libvirt {
         self
     }
 
+    /// Attach a `size_gib` disk, formatted as ext4 and mounted at
+    /// `mount_path` by cloud-init, e.g. for app data in volumes
+    /// bound there.
+    ///
+    /// The disk is kept by `destroy`, and reattached as is, with
+    /// its data, when a VM of the same name is provisioned again.
+    #[must_use]
+    pub fn data_disk(mut self, size_gib: u32, mount_path: &str) -> Self {
+        self.data_disks.push(DataDisk {
+            size_gib,
+            mount_path: mount_path.to_string(),
+        });
+        self
+    }
+
+    /// Add a step run after the others during
+    /// [`Provisioner::setup_server`].
+    #[must_use]
+    pub fn setup_step(mut self, step: SetupStep) -> Self {
+        self.setup_steps.push(step);
+        self
+    }
+
+    /// Replace the setup steps, e.g. to run a custom step before
+    /// the default ones.
+    #[must_use]
+    pub fn setup_steps(mut self, steps: Vec<SetupStep>) -> Self {
+        self.setup_steps = steps;
+        self
+    }
+
+    /// Leave out the setup step named `name` (e.g. `firewall`).
+    #[must_use]
+    pub fn skip_setup_step(mut self, name: &str) -> Self {
+        self.setup_steps.retain(|step| step.name() != name);
+        self
+    }
+
     // -- private helpers --
 
     /// Open an SSH session to the hypervisor.
     fn hypervisor_ssh(&self) -> SshSession {
-        let ssh = SshSession::new(&self.hypervisor_host, &self.hypervisor_user);
+        let mut ssh = SshSession::new(&self.hypervisor_host, &self.hypervisor_user);
+        if let Some(port) = self.hypervisor_port {
+            ssh = ssh.port(port);
+        }
+        if let Some(jump) = &self.hypervisor_jump {
+            ssh = ssh.via_jump(&jump.host, &jump.user, jump.key.as_deref());
+        }
         if let Some(key) = &self.hypervisor_key {
             ssh.with_key(key)
         } else {
@@ -143,6 +247,81 @@ impl Libvirt {
         }
     }
 
+    /// Directory on the hypervisor caching the cloud images.
+    fn image_cache_dir(&self) -> String {
+        format!("{}/cloud-images", self.storage_dir)
+    }
+
+    /// The SHA256 the image must have: the pinned one, or the
+    /// one published in `SHA256SUMS` next to the image.
+    fn expected_sha256(&self, ssh: &SshSession) -> DeployResult<String> {
+        if let Some(sha256) = &self.image_sha256 {
+            return Ok(sha256.clone());
+        }
+        let (base, file) = self.image_url.rsplit_once('/').unwrap_or_default();
+        let sums_url = format!("{base}/SHA256SUMS");
+        let missing = || {
+            DeployError::PrerequisiteMissing(format!(
+                "no SHA256 for {} in {sums_url}; pin it with `image_sha256`",
+                self.image_url
+            ))
+        };
+        let sums = ssh
+            .exec(&format!("wget -q -O - {}", shell_quote(&sums_url)))
+            .map_err(|_| missing())?;
+        published_sha256(&sums, file).ok_or_else(missing)
+    }
+
+    /// The path of the cloud image in the cache on the
+    /// hypervisor, downloaded first when missing or when its
+    /// checksum doesn't match.
+    fn cached_image(&self, ssh: &SshSession) -> DeployResult<String> {
+        let expected = self.expected_sha256(ssh)?;
+        let dir = self.image_cache_dir();
+        let cached = format!("{dir}/{}", cache_file_name(&self.image_url));
+        let sha256sum = |path: &str| {
+            ssh.exec(&format!(
+                "sha256sum {} 2>/dev/null || true",
+                shell_quote(path)
+            ))
+            .map(|output| {
+                output
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+        };
+
+        if sha256sum(&cached)? == expected {
+            return Ok(cached);
+        }
+
+        eprintln!("Downloading cloud image...");
+        let part = format!("{cached}.part");
+        ssh.exec(&format!(
+            "mkdir -p {} && wget -q -O {} {}",
+            shell_quote(&dir),
+            shell_quote(&part),
+            shell_quote(&self.image_url)
+        ))?;
+        let actual = sha256sum(&part)?;
+        if actual != expected {
+            let _ = ssh.exec(&format!("rm -f {}", shell_quote(&part)));
+            return Err(DeployError::ImageChecksumMismatch {
+                url: self.image_url.clone(),
+                expected,
+                actual,
+            });
+        }
+        ssh.exec(&format!(
+            "mv {} {}",
+            shell_quote(&part),
+            shell_quote(&cached)
+        ))?;
+        Ok(cached)
+    }
+
     /// Read the public key content from `vm_ssh_key.pub`.
     fn read_pub_key(&self) -> DeployResult<String> {
         let pub_path = format!("{}.pub", self.vm_ssh_key);
@@ -150,6 +329,27 @@ impl Libvirt {
             .map_err(|_| DeployError::FileNotFound(format!("public key not found: {pub_path}")))
     }
 
+    /// Path on the hypervisor of the `index`th data disk of VM
+    /// `name`.
+    fn data_disk_path(&self, name: &str, index: usize) -> String {
+        format!("{}/{name}-data{index}.qcow2", self.storage_dir)
+    }
+
+    /// Create the data disks of VM `name` that don't exist yet,
+    /// returning the `virt-install` arguments attaching them all.
+    fn data_disk_args(&self, ssh: &SshSession, name: &str) -> DeployResult<String> {
+        let mut args = Vec::new();
+        for (i, disk) in self.data_disks.iter().enumerate() {
+            let path = shell_quote(&self.data_disk_path(name, i));
+            ssh.exec(&format!(
+                "test -f {path} || qemu-img create -f qcow2 {path} {}G",
+                disk.size_gib
+            ))?;
+            args.push(format!(" --disk path={path},format=qcow2,bus=virtio"));
+        }
+        Ok(args.concat())
+    }
+
     /// Create a `NoCloud` seed ISO on the hypervisor.
     ///
     /// Writes `user-data` and `meta-data` to a temp directory,
@@ -168,7 +368,8 @@ impl Libvirt {
                  ssh_authorized_keys:\n      \
                    - {pub_key}\n\
              ssh_pwauth: false\n\
-             package_update: false\n"
+             package_update: false\n{}",
+            data_disk_config(&self.data_disks)
         );
 
         let meta_data = format!("instance-id: {name}\nlocal-hostname: {name}\n");
@@ -235,14 +436,6 @@ impl Libvirt {
         )))
     }
 
-    /// Run the remote setup script on the VM (not the
-    /// hypervisor).
-    fn run_setup_script(ssh: &SshSession, domain: &str, remote_dir: &str) -> DeployResult<()> {
-        let script = include_str!("../../scripts/setup-server.sh");
-        let escaped = script.replace('\'', "'\\''");
-        ssh.exec_interactive(&format!("bash -c '{escaped}' _ '{domain}' '{remote_dir}'"))
-    }
-
     /// Network arguments for virt-install.
     fn network_args(&self) -> String {
         match &self.network {
@@ -312,6 +505,10 @@ impl Provisioner for Libvirt {
         Ok(vec![(String::new(), self.vm_ssh_key.clone())])
     }
 
+    fn default_region(&self) -> &'static str {
+        "local"
+    }
+
     fn create_server(
         &self,
         name: &str,
@@ -323,15 +520,8 @@ impl Provisioner for Libvirt {
 
         eprintln!("Creating VM '{name}'...");
 
-        // Download cloud image if not cached
-        let cached = format!("{}/cloud-base.img", self.storage_dir);
-        let has_cache = ssh
-            .exec(&format!("test -f {cached} && echo yes"))
-            .unwrap_or_default();
-        if has_cache.trim() != "yes" {
-            eprintln!("Downloading cloud image...");
-            ssh.exec(&format!("wget -q -O {cached} '{}'", self.image_url))?;
-        }
+        // Download the cloud image unless cached and intact
+        let cached = self.cached_image(&ssh)?;
 
         // Create disk from base image and resize
         ssh.exec(&format!("cp {cached} {disk_path}"))?;
@@ -339,6 +529,7 @@ impl Provisioner for Libvirt {
 
         // Create cloud-init seed ISO
         let seed_iso = self.create_seed_iso(&ssh, name)?;
+        let data_disks = self.data_disk_args(&ssh, name)?;
 
         // Run virt-install
         let net_arg = self.network_args();
@@ -347,7 +538,7 @@ impl Provisioner for Libvirt {
              --name {name} \
              --vcpus {} \
              --memory {} \
-             --disk path={disk_path},format=qcow2 \
+             --disk path={disk_path},format=qcow2{data_disks} \
              --disk path={seed_iso},device=cdrom \
              --os-variant {} \
              --network {net_arg} \
@@ -365,28 +556,36 @@ impl Provisioner for Libvirt {
         Ok(ServerInfo {
             name: name.to_string(),
             ip,
+            ipv6: None,
             region: "local".to_string(),
             ssh_key_ids: Vec::new(),
             ssh_key_files: vec![self.vm_ssh_key.clone()],
         })
     }
 
-    fn setup_server(&self, server: &ServerInfo, domain: Option<&str>) -> DeployResult<()> {
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        ssh_options: &SshOptions,
+    ) -> DeployResult<()> {
         // SSH to the VM itself, not the hypervisor
         SshSession::clear_known_host(&server.ip);
-        let ssh = SshSession::new(&server.ip, "root").with_keys(&server.ssh_key_files);
+        let ssh = SshSession::new(&server.ip, "root")
+            .with_keys(&server.ssh_key_files)
+            .with_options(ssh_options);
 
-        ssh.wait_for_ready(30, std::time::Duration::from_secs(10))?;
+        ssh.wait_until_ready()?;
 
         let domain_str = domain.unwrap_or(&server.ip);
         let remote_dir = "/opt/app";
 
-        Self::run_setup_script(&ssh, domain_str, remote_dir)?;
+        setup::run(&ssh, &self.setup_steps, domain_str, remote_dir)?;
 
         // Setup SSH config (use first key for the config entry)
         let host_alias = domain.unwrap_or(&server.name);
         let first_key = server.ssh_key_files.first().map_or("", String::as_str);
-        super::setup_ssh_config(&server.ip, host_alias, first_key)?;
+        super::setup_ssh_config(&server.ip, host_alias, first_key, ssh_options)?;
 
         eprintln!();
         eprintln!("========================================");
@@ -427,6 +626,7 @@ impl Provisioner for Libvirt {
                     return Ok(Some(ServerInfo {
                         name: name.to_string(),
                         ip,
+                        ipv6: None,
                         region: "local".to_string(),
                         ssh_key_ids: Vec::new(),
                         ssh_key_files: vec![self.vm_ssh_key.clone()],
@@ -441,6 +641,7 @@ impl Provisioner for Libvirt {
                     return Ok(Some(ServerInfo {
                         name: name.to_string(),
                         ip,
+                        ipv6: None,
                         region: "local".to_string(),
                         ssh_key_ids: Vec::new(),
                         ssh_key_files: vec![self.vm_ssh_key.clone()],
@@ -454,6 +655,7 @@ impl Provisioner for Libvirt {
         Ok(Some(ServerInfo {
             name: name.to_string(),
             ip: String::new(),
+            ipv6: None,
             region: "local".to_string(),
             ssh_key_ids: Vec::new(),
             ssh_key_files: vec![self.vm_ssh_key.clone()],
@@ -468,10 +670,17 @@ impl Provisioner for Libvirt {
         // Force stop if running
         let _ = ssh.exec(&format!("virsh destroy {name} 2>/dev/null"));
 
-        // Undefine and remove storage
+        // Undefine and remove storage, but the data disks
+        let storage = if self.data_disks.is_empty() {
+            "--remove-all-storage".to_string()
+        } else {
+            format!(
+                "--storage {}",
+                shell_quote(&format!("{}/{name}.qcow2", self.storage_dir))
+            )
+        };
         ssh.exec(&format!(
-            "virsh undefine {name} \
-             --remove-all-storage 2>/dev/null || true"
+            "virsh undefine {name} {storage} 2>/dev/null || true"
         ))?;
 
         // Remove seed ISO if it exists
@@ -485,6 +694,52 @@ impl Provisioner for Libvirt {
 
         Ok(())
     }
+
+    fn console(&self, name: &str) -> DeployResult<()> {
+        eprintln!(
+            "Attaching to the console of {name} on {} (Ctrl-] to detach)...",
+            self.hypervisor_host
+        );
+        self.hypervisor_ssh()
+            .terminal(&format!("virsh console {}", shell_quote(name)))
+    }
+
+    fn clean_image_cache(&self) -> DeployResult<()> {
+        let dir = self.image_cache_dir();
+        eprintln!(
+            "Removing the cloud images cached in {dir} on {}...",
+            self.hypervisor_host
+        );
+        // cloud-base.img is where images were cached before
+        self.hypervisor_ssh().exec(&format!(
+            "rm -rf {} {}",
+            shell_quote(&dir),
+            shell_quote(&format!("{}/cloud-base.img", self.storage_dir))
+        ))?;
+        eprintln!("Image cache cleaned");
+        Ok(())
+    }
+
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        let host = &self.hypervisor_host;
+        let mut plan = if self.data_disks.is_empty() {
+            vec![format!(
+                "VM '{name}' on {host}, with all its storage volumes"
+            )]
+        } else {
+            vec![format!(
+                "VM '{name}' on {host}, with its OS disk {}/{name}.qcow2 \
+                 (data disks are kept)",
+                self.storage_dir
+            )]
+        };
+        plan.push(format!(
+            "seed ISO {}/{name}-seed.iso on {host}",
+            self.storage_dir
+        ));
+        plan.extend(super::ssh_config_entry_plan(name));
+        Ok(plan)
+    }
 }
 
 /// Parse an IP address from `virsh domifaddr` output.
@@ -529,3 +784,60 @@ pub fn parse_domifaddr(output: &str) -> Option<String> {
     }
     None
 }
+
+/// The cloud-config formatting and mounting `disks`, attached
+/// as `/dev/vdb`, `/dev/vdc` and so on after the OS disk.
+///
+/// Each disk gets an ext4 file system labelled `data<index>`,
+/// unless it already has one, so a disk reattached to a rebuilt
+/// VM keeps its data.
+#[must_use]
+pub fn data_disk_config(disks: &[DataDisk]) -> String {
+    if disks.is_empty() {
+        return String::new();
+    }
+    let mut fs_setup = String::from("fs_setup:\n");
+    let mut mounts = String::from("mounts:\n");
+    for ((i, disk), device) in disks.iter().enumerate().zip('b'..='z') {
+        let _ = write!(
+            fs_setup,
+            "  - label: data{i}\n    filesystem: ext4\n    \
+             device: /dev/vd{device}\n    partition: none\n    overwrite: false\n"
+        );
+        let _ = writeln!(
+            mounts,
+            "  - [LABEL=data{i}, {}, ext4, \"defaults,nofail\", \"0\", \"2\"]",
+            disk.mount_path
+        );
+    }
+    fs_setup + &mounts
+}
+
+/// The file name caching the image at `url`: the URL without
+/// its scheme, with anything but letters, digits, `.` and `_`
+/// turned into `-`, so each URL gets its own file.
+#[must_use]
+pub fn cache_file_name(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    url.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// The SHA256 of `file` in the content of a `SHA256SUMS` file,
+/// whose lines are `<sha256> *<file>` (or with a space instead of
+/// `*` for text mode).
+#[must_use]
+pub fn published_sha256(sums: &str, file: &str) -> Option<String> {
+    sums.lines().find_map(|line| {
+        let (sha256, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start().trim_start_matches('*');
+        (name == file && sha256.len() == 64).then(|| sha256.to_lowercase())
+    })
+}
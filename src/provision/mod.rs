@@ -1,20 +1,42 @@
 pub mod digitalocean;
+pub mod existing;
 pub mod libvirt;
+pub mod proxmox;
+pub mod scaleway;
+pub mod setup;
 
-use std::path::PathBuf;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
 
 use crate::error::{DeployError, DeployResult};
+use crate::ssh::{SshOptions, pinned_hosts_file};
 
 /// Information about a provisioned server.
 #[derive(Debug, Clone)]
 pub struct ServerInfo {
     pub name: String,
     pub ip: String,
+    /// Public IPv6 address, when the provider reports one;
+    /// published as an AAAA record by `provision`.
+    pub ipv6: Option<String>,
     pub region: String,
     pub ssh_key_ids: Vec<String>,
     pub ssh_key_files: Vec<String>,
 }
 
+/// Per-run replacements for values baked into a provisioner,
+/// set from `provision --size/--image/--ssh-key`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProvisionOverrides {
+    /// Provider-specific server size (e.g. `s-2vcpu-4gb`).
+    pub size: Option<String>,
+    /// Provider-specific OS image (e.g. `ubuntu-22-04-x64`).
+    pub image: Option<String>,
+    /// Provider SSH key IDs to install instead of the detected
+    /// ones.
+    pub ssh_keys: Vec<String>,
+}
+
 /// A provisioner creates, configures, and destroys cloud servers.
 pub trait Provisioner {
     /// Check that all prerequisites are installed and
@@ -30,6 +52,14 @@ pub trait Provisioner {
         Ok(Vec::new())
     }
 
+    /// Region used when `provision` is run without `--region`.
+    ///
+    /// Empty for providers without regions.
+    #[allow(clippy::unnecessary_literal_bound)]
+    fn default_region(&self) -> &str {
+        ""
+    }
+
     /// Create a new server and return its info.
     fn create_server(
         &self,
@@ -38,15 +68,74 @@ pub trait Provisioner {
         ssh_key_ids: &[String],
     ) -> DeployResult<ServerInfo>;
 
-    /// Install Docker, configure firewall, start Caddy
+    /// Create a new server, replacing configured values with
+    /// `overrides`.
+    ///
+    /// The default accepts no overrides and delegates to
+    /// [`Self::create_server`]; provisioners that support them
+    /// override this method.
+    fn create_server_with(
+        &self,
+        name: &str,
+        region: &str,
+        ssh_key_ids: &[String],
+        overrides: &ProvisionOverrides,
+    ) -> DeployResult<ServerInfo> {
+        let unsupported = [
+            ("--size", overrides.size.is_some()),
+            ("--image", overrides.image.is_some()),
+            ("--ssh-key", !overrides.ssh_keys.is_empty()),
+        ];
+        if let Some((flag, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(DeployError::Other(format!(
+                "{flag} is not supported by this provisioner"
+            )));
+        }
+        self.create_server(name, region, ssh_key_ids)
+    }
+
+    /// Run the provisioner's [`setup::SetupStep`]s: by default
+    /// install Docker, configure firewall, start Caddy
     /// placeholder.
-    fn setup_server(&self, server: &ServerInfo, domain: Option<&str>) -> DeployResult<()>;
+    ///
+    /// `ssh` carries the pipeline's connection settings (e.g. a
+    /// jump host) for reaching the new server.
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        ssh: &SshOptions,
+    ) -> DeployResult<()>;
 
     /// Get an existing server by name.
     fn get_server(&self, name: &str) -> DeployResult<Option<ServerInfo>>;
 
     /// Destroy a server by name.
     fn destroy_server(&self, name: &str) -> DeployResult<()>;
+
+    /// Describe what [`Self::destroy_server`] would remove, one
+    /// item per line, without changing anything. Backs
+    /// `destroy --dry-run`.
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        Ok(vec![format!("server '{name}'")])
+    }
+
+    /// Attach the local terminal to the serial console of the
+    /// server `name`, for one that never gets an IP or fails to
+    /// boot. Backs `console`.
+    fn console(&self, name: &str) -> DeployResult<()> {
+        Err(DeployError::Other(format!(
+            "this provisioner has no console access to '{name}'"
+        )))
+    }
+
+    /// Remove the OS images the provisioner downloaded and kept
+    /// for new servers. Backs `cache clean`.
+    fn clean_image_cache(&self) -> DeployResult<()> {
+        Err(DeployError::Other(
+            "this provisioner keeps no image cache".to_string(),
+        ))
+    }
 }
 
 /// Remove a Host block from SSH config content.
@@ -81,48 +170,198 @@ pub fn remove_ssh_host_entry(content: &str, host: &str) -> String {
     out
 }
 
-/// Add an entry to `~/.ssh/config` for a server.
-pub fn setup_ssh_config(ip: &str, host_alias: &str, key_file: &str) -> DeployResult<()> {
-    let home = std::env::var("HOME").map_err(|_| DeployError::EnvMissing("HOME".into()))?;
-    let config_path = PathBuf::from(&home).join(".ssh").join("config");
+/// SSH config file holding the Host entries catapulta manages,
+/// relative to `~/.ssh`.
+pub const MANAGED_SSH_CONFIG: &str = "config.d/catapulta";
 
-    let mut content = if config_path.exists() {
-        std::fs::read_to_string(&config_path)?
-    } else {
-        String::new()
-    };
+/// Add an entry for a server to the managed SSH config
+/// (`~/.ssh/config.d/catapulta`), included from `~/.ssh/config`.
+///
+/// A configured port and jump host are written as `Port` and
+/// `ProxyJump` lines so `ssh <alias>` connects the same way the
+/// pipeline does. Host keys are checked strictly against the
+/// keys pinned during setup. `~/.ssh/config` is only changed to
+/// add the `Include` line once, or to drop an entry for the
+/// alias written there by older versions; files created are
+/// readable by their owner only.
+pub fn setup_ssh_config(
+    ip: &str,
+    host_alias: &str,
+    key_file: &str,
+    ssh: &SshOptions,
+) -> DeployResult<()> {
+    let ssh_dir = ssh_dir()?;
+    let managed_path = ssh_dir.join(MANAGED_SSH_CONFIG);
+    if let Some(dir) = managed_path.parent() {
+        create_private_dir(dir)?;
+    }
 
-    // Remove existing entry for this host alias
-    content = remove_ssh_host_entry(&content, host_alias);
+    let content = read_if_exists(&managed_path)?;
+    let mut content = remove_ssh_host_entry(&content, host_alias)
+        .trim_end()
+        .to_string();
+    if !content.is_empty() {
+        content.push_str("\n\n");
+    }
 
-    // Append new entry
-    let entry = format!(
-        "\nHost {host_alias}\n    \
+    let _ = write!(
+        content,
+        "Host {host_alias}\n    \
          HostName {ip}\n    \
          User root\n    \
          IdentityFile {key_file}\n    \
-         StrictHostKeyChecking no\n"
+         StrictHostKeyChecking yes\n"
     );
-    content.push_str(&entry);
+    if let Some(pins) = pinned_hosts_file() {
+        let _ = writeln!(content, "    UserKnownHostsFile {}", pins.display());
+    }
+    if let Some(port) = ssh.port {
+        let _ = writeln!(content, "    Port {port}");
+    }
+    if let Some(jump) = &ssh.jump {
+        let _ = writeln!(content, "    ProxyJump {}@{}", jump.user, jump.host);
+    }
+    write_private(&managed_path, &content)?;
+
+    let config_path = ssh_dir.join("config");
+    let existing = config_path.exists();
+    let main = read_if_exists(&config_path)?;
+    let mut updated = main.clone();
+    if has_ssh_host_entry(&updated, host_alias) {
+        updated = remove_ssh_host_entry(&updated, host_alias);
+    }
+    updated = add_ssh_include(&updated);
+    if !existing {
+        write_private(&config_path, &updated)?;
+    } else if updated != main {
+        // Rewriting in place keeps the file's permissions
+        std::fs::write(&config_path, &updated)?;
+    }
 
-    std::fs::write(&config_path, &content)?;
     eprintln!("SSH config: ssh {host_alias}");
     Ok(())
 }
 
-/// Remove an SSH host entry from `~/.ssh/config`.
-pub fn remove_ssh_config_entry(host_alias: &str) -> DeployResult<()> {
+/// Whether SSH config `content` includes the managed SSH config,
+/// by name or through a `config.d/*` glob.
+#[must_use]
+pub fn has_ssh_include(content: &str) -> bool {
+    content.lines().any(|line| {
+        let mut words = line.split_whitespace();
+        words
+            .next()
+            .is_some_and(|w| w.eq_ignore_ascii_case("include"))
+            && words.any(|path| {
+                let path = path.trim_start_matches("~/.ssh/");
+                path == MANAGED_SSH_CONFIG || path == "config.d/*"
+            })
+    })
+}
+
+/// SSH config `content` with an `Include` of the managed SSH
+/// config, unchanged when it already has one.
+///
+/// The line goes first: an `Include` after a `Host` line would
+/// only apply within that Host block.
+#[must_use]
+pub fn add_ssh_include(content: &str) -> String {
+    if has_ssh_include(content) {
+        return content.to_string();
+    }
+    let include = format!("Include {MANAGED_SSH_CONFIG}\n");
+    if content.trim().is_empty() {
+        include
+    } else {
+        format!("{include}\n{content}")
+    }
+}
+
+fn ssh_dir() -> DeployResult<PathBuf> {
     let home = std::env::var("HOME").map_err(|_| DeployError::EnvMissing("HOME".into()))?;
-    let config_path = PathBuf::from(&home).join(".ssh").join("config");
+    Ok(PathBuf::from(home).join(".ssh"))
+}
+
+fn read_if_exists(path: &Path) -> DeployResult<String> {
+    if path.exists() {
+        Ok(std::fs::read_to_string(path)?)
+    } else {
+        Ok(String::new())
+    }
+}
 
-    if !config_path.exists() {
-        return Ok(());
+/// Create `dir` and its missing parents, accessible by their
+/// owner only.
+fn create_private_dir(dir: &Path) -> DeployResult<()> {
+    for ancestor in dir.ancestors().collect::<Vec<_>>().into_iter().rev() {
+        if ancestor.as_os_str().is_empty() || ancestor.exists() {
+            continue;
+        }
+        std::fs::create_dir(ancestor)?;
+        set_mode(ancestor, 0o700)?;
     }
+    Ok(())
+}
 
-    let content = std::fs::read_to_string(&config_path)?;
-    let updated = remove_ssh_host_entry(&content, host_alias);
-    std::fs::write(&config_path, updated)?;
+/// Write `path`, readable by its owner only as ssh expects of
+/// its config.
+fn write_private(path: &Path, content: &str) -> DeployResult<()> {
+    std::fs::write(path, content)?;
+    set_mode(path, 0o600)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> DeployResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
 
-    eprintln!("SSH config entry removed: {host_alias}");
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn set_mode(_path: &Path, _mode: u32) -> DeployResult<()> {
     Ok(())
 }
+
+/// Whether SSH config `content` has a Host block for `host`.
+#[must_use]
+pub fn has_ssh_host_entry(content: &str, host: &str) -> bool {
+    let header = format!("Host {host}");
+    content.lines().any(|line| line.trim() == header)
+}
+
+/// Describe the SSH config entry that
+/// [`remove_ssh_config_entry`] would remove, if there is one.
+#[must_use]
+pub fn ssh_config_entry_plan(host_alias: &str) -> Option<String> {
+    ssh_config_files(host_alias)
+        .ok()?
+        .first()
+        .map(|(path, _)| format!("SSH config entry 'Host {host_alias}' in {}", path.display()))
+}
+
+/// Remove an SSH host entry from the managed SSH config, and
+/// from `~/.ssh/config` where older versions wrote it.
+pub fn remove_ssh_config_entry(host_alias: &str) -> DeployResult<()> {
+    let files = ssh_config_files(host_alias)?;
+    for (path, content) in &files {
+        std::fs::write(path, remove_ssh_host_entry(content, host_alias))?;
+    }
+    if !files.is_empty() {
+        eprintln!("SSH config entry removed: {host_alias}");
+    }
+    Ok(())
+}
+
+/// The SSH config files with a Host block for `host_alias`, and
+/// their content.
+fn ssh_config_files(host_alias: &str) -> DeployResult<Vec<(PathBuf, String)>> {
+    let ssh_dir = ssh_dir()?;
+    let mut files = Vec::new();
+    for path in [ssh_dir.join(MANAGED_SSH_CONFIG), ssh_dir.join("config")] {
+        let content = read_if_exists(&path)?;
+        if has_ssh_host_entry(&content, host_alias) {
+            files.push((path, content));
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,504 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+use crate::audit;
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::provision::setup::{self, SetupStep};
+use crate::provision::{Provisioner, ServerInfo};
+use crate::ssh::{SshOptions, SshSession};
+
+/// How often Proxmox tasks and the guest agent are polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a Proxmox task (e.g. a full clone) may take.
+const TASK_TIMEOUT: Duration = Duration::from_secs(900);
+
+/// Proxmox VE provisioner, cloning VMs from a cloud-init template
+/// through the Proxmox API.
+///
+/// Requires `PROXMOX_API_TOKEN` set with an API token as
+/// `user@realm!token-id=secret`, with the `PVEVMAdmin` role (and
+/// `Datastore.AllocateSpace` on the target storage). The template
+/// needs a cloud-init drive and the QEMU guest agent, which
+/// reports the IP of new VMs.
+pub struct Proxmox {
+    /// Base URL of the API, e.g. `https://pve.lan:8006`.
+    pub api_url: String,
+    /// Cluster node holding the template.
+    pub node: String,
+    /// VM id of the cloud-init template.
+    pub template: u32,
+    /// Local SSH private key whose `.pub` sibling is injected
+    /// via cloud-init. Used to SSH into the VM after creation.
+    pub vm_ssh_key: String,
+    /// Number of cores (default: 2).
+    pub cores: u32,
+    /// RAM in MiB (default: 2048).
+    pub memory_mib: u32,
+    /// Size the boot disk is grown to, in GiB (default: the
+    /// template's).
+    pub disk_gib: Option<u32>,
+    /// Boot disk of the template (default: `scsi0`).
+    pub disk: String,
+    /// Storage for the cloned disks (default: the template's).
+    pub storage: Option<String>,
+    /// CA certificate of the API, e.g. a copy of
+    /// `/etc/pve/pve-root-ca.pem` for the self-signed default.
+    pub ca_cert: Option<String>,
+    /// Steps run on the VM by [`Provisioner::setup_server`].
+    pub setup_steps: Vec<SetupStep>,
+}
+
+/// A Proxmox VM, as listed by `GET /cluster/resources`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vm {
+    pub vmid: u32,
+    pub name: String,
+    pub node: String,
+}
+
+impl Proxmox {
+    /// Create a new Proxmox provisioner.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_url` - base URL of the API, e.g.
+    ///   `https://pve.lan:8006`
+    /// * `node` - cluster node holding the template
+    /// * `template` - VM id of the cloud-init template
+    /// * `vm_ssh_key` - path to the local SSH private key; the
+    ///   matching `.pub` file is injected into the VM via
+    ///   cloud-init
+    #[must_use]
+    pub fn new(api_url: &str, node: &str, template: u32, vm_ssh_key: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            node: node.to_string(),
+            template,
+            vm_ssh_key: vm_ssh_key.to_string(),
+            cores: 2,
+            memory_mib: 2048,
+            disk_gib: None,
+            disk: "scsi0".to_string(),
+            storage: None,
+            ca_cert: None,
+            setup_steps: SetupStep::defaults(),
+        }
+    }
+
+    #[must_use]
+    pub const fn cores(mut self, n: u32) -> Self {
+        self.cores = n;
+        self
+    }
+
+    #[must_use]
+    pub const fn memory_mib(mut self, mib: u32) -> Self {
+        self.memory_mib = mib;
+        self
+    }
+
+    #[must_use]
+    pub const fn disk_gib(mut self, gib: u32) -> Self {
+        self.disk_gib = Some(gib);
+        self
+    }
+
+    #[must_use]
+    pub fn disk(mut self, disk: &str) -> Self {
+        self.disk = disk.to_string();
+        self
+    }
+
+    #[must_use]
+    pub fn storage(mut self, storage: &str) -> Self {
+        self.storage = Some(storage.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn ca_cert(mut self, path: &str) -> Self {
+        self.ca_cert = Some(path.to_string());
+        self
+    }
+
+    /// Add a step run after the others during
+    /// [`Provisioner::setup_server`].
+    #[must_use]
+    pub fn setup_step(mut self, step: SetupStep) -> Self {
+        self.setup_steps.push(step);
+        self
+    }
+
+    /// Leave out the setup step named `name` (e.g. `firewall`).
+    #[must_use]
+    pub fn skip_setup_step(mut self, name: &str) -> Self {
+        self.setup_steps.retain(|step| step.name() != name);
+        self
+    }
+
+    // -- private helpers --
+
+    fn auth_header() -> DeployResult<String> {
+        let token = std::env::var("PROXMOX_API_TOKEN").map_err(|_| {
+            DeployError::EnvMissing(
+                "PROXMOX_API_TOKEN not set. Create a token under \
+                 Datacenter > Permissions > API Tokens and set it as \
+                 user@realm!token-id=secret"
+                    .into(),
+            )
+        })?;
+        audit::register_secret(&token);
+        Ok(format!("Authorization: PVEAPIToken={token}"))
+    }
+
+    /// Call the API, returning the `data` of the response.
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> DeployResult<Value> {
+        let url = format!("{}/api2/json/{path}", self.api_url);
+        let mut options = Vec::new();
+        if let Some(ca_cert) = &self.ca_cert {
+            options.push(format!("cacert = {}", cmd::curl_quote(ca_cert)));
+        }
+        let body = body.map(Value::to_string);
+        let (status, response) = cmd::http_request(
+            method,
+            &url,
+            &[Self::auth_header()?],
+            body.as_deref(),
+            &options,
+        )?;
+        if status >= 400 {
+            return Err(DeployError::Other(format!(
+                "Proxmox {method} {path} returned HTTP {status}: {response}"
+            )));
+        }
+        let response: Value = serde_json::from_str(&response)?;
+        Ok(response["data"].clone())
+    }
+
+    /// Wait for the task `upid` returned by an API call to end.
+    fn wait_for_task(&self, upid: &Value) -> DeployResult<()> {
+        let upid = upid
+            .as_str()
+            .ok_or_else(|| DeployError::Other(format!("expected a Proxmox task, got {upid}")))?;
+        // Tasks run on the node named in their id
+        let node = upid.split(':').nth(1).unwrap_or(&self.node);
+        let path = format!("nodes/{node}/tasks/{}/status", encode(upid));
+        let deadline = Instant::now() + TASK_TIMEOUT;
+        while Instant::now() < deadline {
+            let status = self.request("GET", &path, None)?;
+            if status["status"] == "stopped" {
+                return match status["exitstatus"].as_str() {
+                    Some("OK") => Ok(()),
+                    exit => Err(DeployError::Other(format!(
+                        "Proxmox task {upid} failed: {}",
+                        exit.unwrap_or("unknown error")
+                    ))),
+                };
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Err(DeployError::Timeout {
+            command: format!("Proxmox task {upid}"),
+            timeout: TASK_TIMEOUT,
+        })
+    }
+
+    /// The VM named `name`, if any.
+    fn find_vm(&self, name: &str) -> DeployResult<Option<Vm>> {
+        let resources = self.request("GET", "cluster/resources?type=vm", None)?;
+        Ok(parse_vms(&resources).into_iter().find(|vm| vm.name == name))
+    }
+
+    /// The IPv4 reported by the guest agent of `vm`, if it is
+    /// running and has one.
+    fn agent_ip(&self, vm: &Vm) -> Option<String> {
+        let path = format!(
+            "nodes/{}/qemu/{}/agent/network-get-interfaces",
+            vm.node, vm.vmid
+        );
+        // The agent answers with an error until it has started
+        let interfaces = self.request("GET", &path, None).ok()?;
+        parse_agent_ipv4(&interfaces)
+    }
+
+    /// Poll the guest agent until it reports an IP.
+    fn wait_for_ip(&self, vm: &Vm) -> DeployResult<String> {
+        let max_attempts = 60;
+        for attempt in 1..=max_attempts {
+            eprint!("Waiting for IP ({attempt}/{max_attempts})... ");
+            if let Some(ip) = self.agent_ip(vm) {
+                eprintln!("got {ip}");
+                return Ok(ip);
+            }
+            eprintln!("not yet");
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Err(DeployError::Other(format!(
+            "VM '{}' reported no IP after {max_attempts} attempts; \
+             is qemu-guest-agent installed in the template?",
+            vm.name
+        )))
+    }
+
+    fn read_pub_key(&self) -> DeployResult<String> {
+        let pub_path = format!("{}.pub", self.vm_ssh_key);
+        std::fs::read_to_string(&pub_path)
+            .map_err(|_| DeployError::FileNotFound(format!("public key not found: {pub_path}")))
+    }
+
+    fn server_info(&self, vm: &Vm, ip: String) -> ServerInfo {
+        ServerInfo {
+            name: vm.name.clone(),
+            ip,
+            ipv6: None,
+            region: vm.node.clone(),
+            ssh_key_ids: Vec::new(),
+            ssh_key_files: vec![self.vm_ssh_key.clone()],
+        }
+    }
+}
+
+impl Provisioner for Proxmox {
+    fn check_prerequisites(&self) -> DeployResult<()> {
+        eprintln!("Checking prerequisites...");
+
+        if !cmd::command_exists("curl") {
+            return Err(DeployError::PrerequisiteMissing(
+                "curl is not installed".into(),
+            ));
+        }
+        let key_path = PathBuf::from(&self.vm_ssh_key);
+        if !key_path.exists() {
+            return Err(DeployError::FileNotFound(format!(
+                "VM SSH key not found: {}",
+                self.vm_ssh_key
+            )));
+        }
+        self.read_pub_key()?;
+
+        let template = format!("nodes/{}/qemu/{}/config", self.node, self.template);
+        let config = self.request("GET", &template, None).map_err(|e| {
+            DeployError::PrerequisiteMissing(format!(
+                "cannot read template {} on {}: {e}",
+                self.template, self.node
+            ))
+        })?;
+        if config["template"] != 1 {
+            return Err(DeployError::PrerequisiteMissing(format!(
+                "VM {} on {} is not a template",
+                self.template, self.node
+            )));
+        }
+
+        eprintln!("Prerequisites OK");
+        Ok(())
+    }
+
+    fn detect_ssh_keys(&self) -> DeployResult<Vec<(String, String)>> {
+        Ok(vec![(String::new(), self.vm_ssh_key.clone())])
+    }
+
+    fn default_region(&self) -> &str {
+        &self.node
+    }
+
+    fn create_server(
+        &self,
+        name: &str,
+        region: &str,
+        _ssh_key_ids: &[String],
+    ) -> DeployResult<ServerInfo> {
+        let vmid: u32 = self
+            .request("GET", "cluster/nextid", None)?
+            .as_str()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| DeployError::Other("Proxmox returned no free VM id".into()))?;
+        let vm = Vm {
+            vmid,
+            name: name.to_string(),
+            node: region.to_string(),
+        };
+
+        eprintln!(
+            "Cloning template {} into VM {vmid} '{name}' on {region}...",
+            self.template
+        );
+        let mut clone = json!({ "newid": vmid, "name": name, "full": 1 });
+        if region != self.node {
+            clone["target"] = json!(region);
+        }
+        if let Some(storage) = &self.storage {
+            clone["storage"] = json!(storage);
+        }
+        let task = self.request(
+            "POST",
+            &format!("nodes/{}/qemu/{}/clone", self.node, self.template),
+            Some(&clone),
+        )?;
+        self.wait_for_task(&task)?;
+
+        let vm_path = format!("nodes/{region}/qemu/{vmid}");
+        let config = json!({
+            "cores": self.cores,
+            "memory": self.memory_mib,
+            "agent": 1,
+            "ciuser": "root",
+            "ipconfig0": "ip=dhcp",
+            "sshkeys": encode(self.read_pub_key()?.trim()),
+        });
+        self.request("POST", &format!("{vm_path}/config"), Some(&config))?;
+
+        if let Some(gib) = self.disk_gib {
+            let resize = json!({ "disk": self.disk, "size": format!("{gib}G") });
+            self.request("PUT", &format!("{vm_path}/resize"), Some(&resize))?;
+        }
+
+        let task = self.request("POST", &format!("{vm_path}/status/start"), None)?;
+        self.wait_for_task(&task)?;
+
+        let ip = self.wait_for_ip(&vm)?;
+        eprintln!("VM created! IP: {ip}");
+        Ok(self.server_info(&vm, ip))
+    }
+
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        ssh_options: &SshOptions,
+    ) -> DeployResult<()> {
+        SshSession::clear_known_host(&server.ip);
+        let ssh = SshSession::new(&server.ip, "root")
+            .with_keys(&server.ssh_key_files)
+            .with_options(ssh_options);
+
+        ssh.wait_until_ready()?;
+
+        let domain_str = domain.unwrap_or(&server.ip);
+        setup::run(&ssh, &self.setup_steps, domain_str, "/opt/app")?;
+
+        let host_alias = domain.unwrap_or(&server.name);
+        let first_key = server.ssh_key_files.first().map_or("", String::as_str);
+        super::setup_ssh_config(&server.ip, host_alias, first_key, ssh_options)?;
+
+        eprintln!();
+        eprintln!("========================================");
+        eprintln!("VM provisioned successfully!");
+        eprintln!("========================================");
+        eprintln!();
+        eprintln!("VM: {}", server.name);
+        eprintln!("IP: {}", server.ip);
+        eprintln!("Node: {}", server.region);
+        if let Some(d) = domain {
+            eprintln!("Domain: {d}");
+        }
+        let deploy_host = domain.unwrap_or(&server.ip);
+        eprintln!("SSH: ssh {deploy_host}");
+        eprintln!();
+        eprintln!("Deploy with:");
+        eprintln!("  cargo xtask deploy {deploy_host}");
+        eprintln!();
+
+        Ok(())
+    }
+
+    fn get_server(&self, name: &str) -> DeployResult<Option<ServerInfo>> {
+        let Some(vm) = self.find_vm(name)? else {
+            return Ok(None);
+        };
+        // A VM whose agent is not up yet has no IP
+        let ip = self.agent_ip(&vm).unwrap_or_default();
+        Ok(Some(self.server_info(&vm, ip)))
+    }
+
+    fn destroy_server(&self, name: &str) -> DeployResult<()> {
+        let vm = self
+            .find_vm(name)?
+            .ok_or_else(|| DeployError::ServerNotFound(name.into()))?;
+        let vm_path = format!("nodes/{}/qemu/{}", vm.node, vm.vmid);
+
+        eprintln!("Destroying VM {} '{name}'...", vm.vmid);
+        let status = self.request("GET", &format!("{vm_path}/status/current"), None)?;
+        if status["status"] == "running" {
+            let task = self.request("POST", &format!("{vm_path}/status/stop"), None)?;
+            self.wait_for_task(&task)?;
+        }
+        let task = self.request(
+            "DELETE",
+            &format!("{vm_path}?purge=1&destroy-unreferenced-disks=1"),
+            None,
+        )?;
+        self.wait_for_task(&task)?;
+        eprintln!("VM '{name}' destroyed");
+
+        super::remove_ssh_config_entry(name)?;
+
+        Ok(())
+    }
+
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        let vm = self
+            .find_vm(name)?
+            .ok_or_else(|| DeployError::ServerNotFound(name.into()))?;
+        let mut plan = vec![format!(
+            "VM {} '{name}' on {}, with its disks",
+            vm.vmid, vm.node
+        )];
+        plan.extend(super::ssh_config_entry_plan(name));
+        Ok(plan)
+    }
+}
+
+/// The VMs, not templates, in the `data` of
+/// `GET /cluster/resources?type=vm`.
+#[must_use]
+pub fn parse_vms(resources: &Value) -> Vec<Vm> {
+    resources
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|r| r["type"] == "qemu" && r["template"] != 1)
+        .filter_map(|r| {
+            Some(Vm {
+                vmid: u32::try_from(r["vmid"].as_u64()?).ok()?,
+                name: r["name"].as_str()?.to_string(),
+                node: r["node"].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The first non-loopback IPv4 in the `data` of
+/// `GET /nodes/{node}/qemu/{vmid}/agent/network-get-interfaces`.
+#[must_use]
+pub fn parse_agent_ipv4(interfaces: &Value) -> Option<String> {
+    interfaces["result"]
+        .as_array()?
+        .iter()
+        .filter(|i| i["name"] != "lo")
+        .flat_map(|i| i["ip-addresses"].as_array().into_iter().flatten())
+        .filter(|a| a["ip-address-type"] == "ipv4")
+        .filter_map(|a| a["ip-address"].as_str())
+        .find(|ip| !ip.starts_with("127."))
+        .map(str::to_string)
+}
+
+/// Percent-encode `value`, as the API expects for task ids in
+/// paths and for the `sshkeys` option.
+#[must_use]
+pub fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
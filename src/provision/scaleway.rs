@@ -0,0 +1,442 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::provision::setup::{self, SetupStep};
+use crate::provision::{ProvisionOverrides, Provisioner, ServerInfo};
+use crate::ssh::{SshOptions, SshSession};
+
+/// How long `scw instance server create --wait` may take.
+const CREATE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Scaleway provisioner for Instances, using the `scw` CLI.
+///
+/// Scaleway installs the SSH keys of the project on every new
+/// instance, so the keys are matched against local ones rather
+/// than passed at creation.
+pub struct Scaleway {
+    /// Commercial type of the instance (e.g. `DEV1-S`).
+    pub commercial_type: String,
+    /// Availability zone (e.g. `fr-par-1`).
+    pub zone: String,
+    pub image: String,
+    /// Steps run by [`Provisioner::setup_server`].
+    pub setup_steps: Vec<SetupStep>,
+}
+
+/// A Scaleway instance, as listed by `scw instance server list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    pub id: String,
+    pub name: String,
+    /// Public IPv4, if the instance has one.
+    pub ip: Option<String>,
+    /// Public IPv6, if the instance has one.
+    pub ipv6: Option<String>,
+    pub zone: String,
+}
+
+impl Scaleway {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            commercial_type: "DEV1-S".to_string(),
+            zone: "fr-par-1".to_string(),
+            image: "ubuntu_noble".to_string(),
+            setup_steps: SetupStep::defaults(),
+        }
+    }
+
+    #[must_use]
+    pub fn commercial_type(mut self, commercial_type: &str) -> Self {
+        self.commercial_type = commercial_type.to_string();
+        self
+    }
+
+    #[must_use]
+    pub fn zone(mut self, zone: &str) -> Self {
+        self.zone = zone.to_string();
+        self
+    }
+
+    #[must_use]
+    pub fn image(mut self, image: &str) -> Self {
+        self.image = image.to_string();
+        self
+    }
+
+    /// Add a step run after the others during
+    /// [`Provisioner::setup_server`].
+    #[must_use]
+    pub fn setup_step(mut self, step: SetupStep) -> Self {
+        self.setup_steps.push(step);
+        self
+    }
+
+    /// Leave out the setup step named `name` (e.g. `firewall`).
+    #[must_use]
+    pub fn skip_setup_step(mut self, name: &str) -> Self {
+        self.setup_steps.retain(|step| step.name() != name);
+        self
+    }
+
+    /// Match the SSH keys of the project against the local public
+    /// keys in `~/.ssh`.
+    ///
+    /// Returns a list of `(key_id, private_key_path)` pairs.
+    fn detect_scw_ssh_keys() -> DeployResult<Vec<(String, String)>> {
+        let output = cmd::run_with_timeout(
+            "scw",
+            &["iam", "ssh-key", "list", "-o", "json"],
+            cmd::API_TIMEOUT,
+        )?;
+        let registered: Vec<Value> = serde_json::from_str(&output)?;
+        if registered.is_empty() {
+            return Err(DeployError::PrerequisiteMissing(
+                "no SSH keys found in the Scaleway project".into(),
+            ));
+        }
+
+        let home = std::env::var("HOME").map_err(|_| DeployError::EnvMissing("HOME".into()))?;
+        let ssh_dir = PathBuf::from(&home).join(".ssh");
+        let pub_keys: Vec<PathBuf> = std::fs::read_dir(&ssh_dir)
+            .map_err(|_| DeployError::FileNotFound("~/.ssh directory not found".into()))?
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "pub"))
+            .collect();
+
+        let mut matched = Vec::new();
+        for key in &registered {
+            let (Some(id), Some(public_key)) = (key["id"].as_str(), key["public_key"].as_str())
+            else {
+                continue;
+            };
+            for pub_key in &pub_keys {
+                let Ok(local) = std::fs::read_to_string(pub_key) else {
+                    continue;
+                };
+                if same_public_key(&local, public_key) {
+                    let pub_key_str = pub_key.to_string_lossy();
+                    let private_key = pub_key_str
+                        .strip_suffix(".pub")
+                        .unwrap_or(&pub_key_str)
+                        .to_string();
+                    eprintln!("SSH key: {private_key} (ID: {id})");
+                    matched.push((id.to_string(), private_key));
+                    break;
+                }
+            }
+        }
+
+        if matched.is_empty() {
+            return Err(DeployError::PrerequisiteMissing(
+                "no local key matches an SSH key of the Scaleway project; \
+                 add one with: scw iam ssh-key create public-key=\"$(cat ~/.ssh/id_ed25519.pub)\""
+                    .into(),
+            ));
+        }
+        Ok(matched)
+    }
+
+    /// The instance named `name` in `zone`, if any.
+    fn find_instance(name: &str, zone: &str) -> DeployResult<Option<Instance>> {
+        let output = cmd::run_with_timeout(
+            "scw",
+            &[
+                "instance",
+                "server",
+                "list",
+                &format!("name={name}"),
+                &format!("zone={zone}"),
+                "-o",
+                "json",
+            ],
+            cmd::API_TIMEOUT,
+        )?;
+        Ok(parse_instances(&output)?
+            .into_iter()
+            .find(|i| i.name == name))
+    }
+
+    fn server_info(instance: Instance) -> DeployResult<ServerInfo> {
+        let ip = instance.ip.ok_or_else(|| {
+            DeployError::Other(format!("instance '{}' has no public IPv4", instance.name))
+        })?;
+        let keys = Self::detect_scw_ssh_keys()?;
+        let (ids, files): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
+        Ok(ServerInfo {
+            name: instance.name,
+            ip,
+            ipv6: instance.ipv6,
+            region: instance.zone,
+            ssh_key_ids: ids,
+            ssh_key_files: files,
+        })
+    }
+}
+
+impl Default for Scaleway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Provisioner for Scaleway {
+    fn check_prerequisites(&self) -> DeployResult<()> {
+        eprintln!("Checking prerequisites...");
+
+        if !cmd::command_exists("scw") {
+            return Err(DeployError::PrerequisiteMissing(
+                "scw is not installed. \
+                 Install with: brew install scw"
+                    .into(),
+            ));
+        }
+
+        cmd::run_with_timeout(
+            "scw",
+            &["instance", "server", "list", &format!("zone={}", self.zone)],
+            cmd::API_TIMEOUT,
+        )
+        .map_err(|_| {
+            DeployError::PrerequisiteMissing(
+                "scw is not authenticated. \
+                 Run: scw init"
+                    .into(),
+            )
+        })?;
+
+        eprintln!("Prerequisites OK");
+        Ok(())
+    }
+
+    fn detect_ssh_keys(&self) -> DeployResult<Vec<(String, String)>> {
+        Self::detect_scw_ssh_keys()
+    }
+
+    fn default_region(&self) -> &str {
+        &self.zone
+    }
+
+    fn create_server(
+        &self,
+        name: &str,
+        region: &str,
+        ssh_key_ids: &[String],
+    ) -> DeployResult<ServerInfo> {
+        self.create_server_with(name, region, ssh_key_ids, &ProvisionOverrides::default())
+    }
+
+    fn create_server_with(
+        &self,
+        name: &str,
+        region: &str,
+        _ssh_key_ids: &[String],
+        overrides: &ProvisionOverrides,
+    ) -> DeployResult<ServerInfo> {
+        if !overrides.ssh_keys.is_empty() {
+            return Err(DeployError::Other(
+                "--ssh-key is not supported by Scaleway, \
+                 which installs the SSH keys of the project"
+                    .into(),
+            ));
+        }
+        let commercial_type = overrides.size.as_deref().unwrap_or(&self.commercial_type);
+        let image = overrides.image.as_deref().unwrap_or(&self.image);
+
+        eprintln!("Creating instance '{name}' ({commercial_type}, {image}) in {region}...");
+        let output = cmd::run_classified(
+            "scw",
+            &[
+                "instance",
+                "server",
+                "create",
+                &format!("name={name}"),
+                &format!("type={commercial_type}"),
+                &format!("image={image}"),
+                &format!("zone={region}"),
+                "ip=new",
+                "--wait",
+                "-o",
+                "json",
+            ],
+            CREATE_TIMEOUT,
+            |stderr| create_error(stderr, region, commercial_type),
+        )?;
+        let server: Value = serde_json::from_str(&output)?;
+        let instance = instance(&server).ok_or_else(|| {
+            DeployError::Other(format!(
+                "unexpected output of scw instance server create: {output}"
+            ))
+        })?;
+
+        let info = Self::server_info(instance)?;
+        eprintln!("Instance created! IP: {}", info.ip);
+        Ok(info)
+    }
+
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        ssh_options: &SshOptions,
+    ) -> DeployResult<()> {
+        SshSession::clear_known_host(&server.ip);
+        let ssh = SshSession::new(&server.ip, "root")
+            .with_keys(&server.ssh_key_files)
+            .with_options(ssh_options);
+
+        ssh.wait_until_ready()?;
+
+        let domain_str = domain.unwrap_or(&server.ip);
+        setup::run(&ssh, &self.setup_steps, domain_str, "/opt/app")?;
+
+        let host_alias = domain.unwrap_or(&server.name);
+        let first_key = server.ssh_key_files.first().map_or("", String::as_str);
+        super::setup_ssh_config(&server.ip, host_alias, first_key, ssh_options)?;
+
+        eprintln!();
+        eprintln!("========================================");
+        eprintln!("Instance provisioned successfully!");
+        eprintln!("========================================");
+        eprintln!();
+        eprintln!("Instance: {}", server.name);
+        eprintln!("IP: {}", server.ip);
+        eprintln!("Zone: {}", server.region);
+        if let Some(d) = domain {
+            eprintln!("Domain: {d}");
+        }
+        let deploy_host = domain.unwrap_or(&server.ip);
+        eprintln!("SSH: ssh {deploy_host}");
+        eprintln!();
+        eprintln!("Deploy with:");
+        eprintln!("  cargo xtask deploy {deploy_host}");
+        eprintln!();
+
+        Ok(())
+    }
+
+    fn get_server(&self, name: &str) -> DeployResult<Option<ServerInfo>> {
+        Self::find_instance(name, &self.zone)?
+            .map(Self::server_info)
+            .transpose()
+    }
+
+    fn destroy_server(&self, name: &str) -> DeployResult<()> {
+        let instance = Self::find_instance(name, &self.zone)?
+            .ok_or_else(|| DeployError::ServerNotFound(name.into()))?;
+
+        eprintln!("Terminating instance '{name}'...");
+        cmd::run_with_timeout(
+            "scw",
+            &[
+                "instance",
+                "server",
+                "terminate",
+                &instance.id,
+                &format!("zone={}", instance.zone),
+                "with-ip=true",
+                "with-block=true",
+                "--wait",
+            ],
+            CREATE_TIMEOUT,
+        )?;
+        eprintln!("Instance '{name}' terminated");
+
+        super::remove_ssh_config_entry(name)?;
+
+        Ok(())
+    }
+
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        let instance = Self::find_instance(name, &self.zone)?
+            .ok_or_else(|| DeployError::ServerNotFound(name.into()))?;
+        let mut plan = vec![format!(
+            "instance '{name}' (ID {}, {}) with its IP and volumes",
+            instance.id, instance.zone
+        )];
+        plan.extend(super::ssh_config_entry_plan(name));
+        Ok(plan)
+    }
+}
+
+/// The instance described by a server object of the API.
+fn instance(server: &Value) -> Option<Instance> {
+    // Newer instances list their IPs in `public_ips`
+    let ip = server["public_ips"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|ip| ip["family"].as_str().is_none_or(|f| f == "inet"))
+        .chain(std::iter::once(&server["public_ip"]))
+        .find_map(|ip| ip["address"].as_str())
+        .map(str::to_string);
+    let ipv6 = server["public_ips"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|ip| ip["family"] == "inet6")
+        .chain(std::iter::once(&server["ipv6"]))
+        .find_map(|ip| ip["address"].as_str())
+        .map(str::to_string);
+    Some(Instance {
+        id: server["id"].as_str()?.to_string(),
+        name: server["name"].as_str()?.to_string(),
+        ip,
+        ipv6,
+        zone: server["zone"].as_str()?.to_string(),
+    })
+}
+
+/// The instances in the JSON output of
+/// `scw instance server list -o json`.
+pub fn parse_instances(output: &str) -> DeployResult<Vec<Instance>> {
+    let servers: Vec<Value> = serde_json::from_str(output)?;
+    Ok(servers.iter().filter_map(instance).collect())
+}
+
+/// Whether two OpenSSH public key lines hold the same key,
+/// ignoring their comments.
+fn same_public_key(a: &str, b: &str) -> bool {
+    let key = |line: &str| {
+        let mut parts = line.split_whitespace();
+        (
+            parts.next().map(str::to_string),
+            parts.next().map(str::to_string),
+        )
+    };
+    let a = key(a);
+    a.1.is_some() && a == key(b)
+}
+
+/// The error behind a failed `scw instance server create` in
+/// `zone`, from the API message in its `stderr`, when it is one
+/// a user can act on.
+#[must_use]
+pub fn create_error(stderr: &str, zone: &str, commercial_type: &str) -> Option<DeployError> {
+    let message = stderr.to_lowercase();
+    if message.contains("quota") {
+        let detail = stderr
+            .lines()
+            .rev()
+            .find(|l| !l.trim().is_empty())
+            .map_or(stderr, str::trim);
+        Some(DeployError::QuotaExceeded(detail.to_string()))
+    } else if message.contains("out of stock") || message.contains("not available in this zone") {
+        Some(DeployError::RegionUnavailable {
+            region: zone.to_string(),
+            size: commercial_type.to_string(),
+        })
+    } else if message.contains("commercial_type") || message.contains("unknown commercial type") {
+        Some(DeployError::InvalidSize {
+            size: commercial_type.to_string(),
+            region: zone.to_string(),
+        })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,144 @@
+//! Steps of the setup run on freshly provisioned servers.
+//!
+//! [`Provisioner::setup_server`](super::Provisioner::setup_server)
+//! runs a list of [`SetupStep`]s as one bash script over SSH. The
+//! defaults wait for apt, install Docker, open the firewall,
+//! create the remote directory and start a placeholder Caddy.
+//! Provisioners take extra steps (installing ffmpeg, mounting an
+//! NFS share, ...) and can skip default ones. Servers meant for
+//! Podman rather than Docker run [`SetupStep::podman_defaults`]:
+//!
+//! ```rust,no_run
+//! use catapulta::DigitalOcean;
+//! use catapulta::provision::setup::SetupStep;
+//!
+//! let provisioner = DigitalOcean::new()
+//!     .setup_step(SetupStep::custom("ffmpeg", "apt_get install -y ffmpeg"))
+//!     .skip_setup_step("firewall");
+//! let fedora = DigitalOcean::new().setup_steps(SetupStep::podman_defaults());
+//! ```
+//!
+//! Every step runs after a shared prelude (`set -euo pipefail`)
+//! and can use `$DOMAIN`, `$REMOTE_DIR` and `apt_get`, an
+//! `apt-get` retried while the dpkg lock is held.
+
+use crate::error::DeployResult;
+use crate::ssh::{SshSession, bash_script_command, shell_quote};
+
+const PRELUDE: &str = include_str!("../../scripts/setup/prelude.sh");
+
+/// One step of the server setup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupStep {
+    /// Wait for cloud-init and apt locks, and stop
+    /// unattended-upgrades.
+    WaitForApt,
+    /// Install Docker and the compose plugin.
+    InstallDocker,
+    /// Install Podman and `podman-compose`, with `dnf` or `apt`,
+    /// for [`crate::deploy::Runtime::Podman`].
+    InstallPodman,
+    /// Enable ufw (firewalld when only it is installed) with
+    /// SSH, HTTP and HTTPS open.
+    Firewall,
+    /// Create the remote app directory.
+    CreateDirs,
+    /// Start a Caddy answering 503 until the first deploy.
+    StartCaddy,
+    /// A user-supplied bash snippet.
+    Custom {
+        /// Name shown in the output and used by
+        /// `skip_setup_step`.
+        name: String,
+        /// Bash run on the server.
+        script: String,
+    },
+}
+
+impl SetupStep {
+    /// A step running `script` with bash on the server.
+    #[must_use]
+    pub fn custom(name: &str, script: &str) -> Self {
+        Self::Custom {
+            name: name.to_string(),
+            script: script.to_string(),
+        }
+    }
+
+    /// The steps run when none are configured, in order.
+    #[must_use]
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::WaitForApt,
+            Self::InstallDocker,
+            Self::Firewall,
+            Self::CreateDirs,
+            Self::StartCaddy,
+        ]
+    }
+
+    /// [`SetupStep::defaults`] installing Podman instead of
+    /// Docker, e.g. on RHEL or Fedora.
+    #[must_use]
+    pub fn podman_defaults() -> Vec<Self> {
+        Self::defaults()
+            .into_iter()
+            .map(|step| match step {
+                Self::InstallDocker => Self::InstallPodman,
+                other => other,
+            })
+            .collect()
+    }
+
+    /// Name of the step (e.g. `install_docker`).
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            Self::WaitForApt => "wait_for_apt",
+            Self::InstallDocker => "install_docker",
+            Self::InstallPodman => "install_podman",
+            Self::Firewall => "firewall",
+            Self::CreateDirs => "create_dirs",
+            Self::StartCaddy => "start_caddy",
+            Self::Custom { name, .. } => name,
+        }
+    }
+
+    /// Bash run for the step.
+    #[must_use]
+    pub fn script(&self) -> &str {
+        match self {
+            Self::WaitForApt => include_str!("../../scripts/setup/wait-for-apt.sh"),
+            Self::InstallDocker => include_str!("../../scripts/setup/install-docker.sh"),
+            Self::InstallPodman => include_str!("../../scripts/setup/install-podman.sh"),
+            Self::Firewall => include_str!("../../scripts/setup/firewall.sh"),
+            Self::CreateDirs => include_str!("../../scripts/setup/create-dirs.sh"),
+            Self::StartCaddy => include_str!("../../scripts/setup/start-caddy.sh"),
+            Self::Custom { script, .. } => script,
+        }
+    }
+}
+
+/// The setup script running `steps` in order, taking the domain
+/// and remote directory as `$1` and `$2`. Each step runs in a
+/// subshell, so a `cd` doesn't leak into the next one.
+#[must_use]
+pub fn script(steps: &[SetupStep]) -> String {
+    let mut parts = vec![PRELUDE.to_string()];
+    for step in steps {
+        let banner = shell_quote(&format!("==> {}", step.name()));
+        parts.push(format!("echo {banner}\n(\n{}\n)", step.script().trim_end()));
+    }
+    parts.push("echo \"Setup complete!\"\n".to_string());
+    parts.join("\n")
+}
+
+/// Run `steps` on the server behind `ssh`.
+pub fn run(
+    ssh: &SshSession,
+    steps: &[SetupStep],
+    domain: &str,
+    remote_dir: &str,
+) -> DeployResult<()> {
+    ssh.exec_interactive(&bash_script_command(&script(steps), &[domain, remote_dir]))
+}
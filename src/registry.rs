@@ -0,0 +1,109 @@
+//! Authentication to private image registries.
+//!
+//! [`crate::Pipeline::registry_auth`] logs Docker in to a
+//! registry before a deploy: locally when apps are built (their
+//! `FROM` images may be private), and on the server when a
+//! prebuilt [`App::image`](crate::App::image) comes from that
+//! registry. The token is read from an environment variable and
+//! always passed on stdin, so it never shows in a command line,
+//! `--trace-commands` output or the audit log.
+
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::{FileAttrs, SshSession, shell_quote};
+
+/// Registry of images named without one (`nginx:1.27`).
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// The registry `image` is pulled from: its first path component
+/// when it looks like a host (`ghcr.io/org/app`,
+/// `localhost:5000/app`), [`DEFAULT_REGISTRY`] otherwise.
+#[must_use]
+pub fn image_registry(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            first
+        }
+        _ => DEFAULT_REGISTRY,
+    }
+}
+
+/// Credentials for one registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryAuth {
+    /// Registry host, e.g. `ghcr.io`.
+    pub registry: String,
+    pub user: String,
+    /// Environment variable holding the token or password.
+    pub token_env: String,
+}
+
+impl RegistryAuth {
+    #[must_use]
+    pub fn new(registry: &str, user: &str, token_env: &str) -> Self {
+        Self {
+            registry: registry.to_string(),
+            user: user.to_string(),
+            token_env: token_env.to_string(),
+        }
+    }
+
+    /// Whether `image` is pulled from this registry.
+    #[must_use]
+    pub fn serves(&self, image: &str) -> bool {
+        image_registry(image) == normalize(&self.registry)
+    }
+
+    /// The token, from [`Self::token_env`].
+    pub fn token(&self) -> DeployResult<String> {
+        std::env::var(&self.token_env).map_err(|_| DeployError::EnvMissing(self.token_env.clone()))
+    }
+
+    /// Arguments of the `docker login` reading the token from
+    /// stdin.
+    #[must_use]
+    pub fn login_args(&self) -> Vec<&str> {
+        vec![
+            "login",
+            &self.registry,
+            "-u",
+            &self.user,
+            "--password-stdin",
+        ]
+    }
+
+    /// Log the local Docker in to the registry.
+    pub fn login_local(&self) -> DeployResult<()> {
+        let token = self.token()?;
+        eprintln!("Logging in to {} locally...", self.registry);
+        cmd::run_with_stdin("docker", &self.login_args(), token.as_bytes())?;
+        Ok(())
+    }
+
+    /// Log the server's Docker in to the registry. The token is
+    /// written to a file readable by the SSH user only, fed to
+    /// `docker login` and removed.
+    pub fn login_remote(&self, ssh: &SshSession, remote_dir: &str) -> DeployResult<()> {
+        let token = self.token()?;
+        eprintln!("Logging in to {} on the server...", self.registry);
+        let token_file = format!("{remote_dir}/.registry-token");
+        ssh.write_remote_file_with(&token, &token_file, &FileAttrs::new().mode(0o600))?;
+        let token_file = shell_quote(&token_file);
+        ssh.exec(&format!(
+            "docker login {} -u {} --password-stdin < {token_file}; \
+             status=$?; rm -f {token_file}; exit $status",
+            shell_quote(&self.registry),
+            shell_quote(&self.user)
+        ))?;
+        Ok(())
+    }
+}
+
+/// `registry` as [`image_registry`] returns it: Docker Hub's
+/// aliases are [`DEFAULT_REGISTRY`].
+fn normalize(registry: &str) -> &str {
+    match registry {
+        "index.docker.io" | "registry-1.docker.io" => DEFAULT_REGISTRY,
+        other => other,
+    }
+}
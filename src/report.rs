@@ -0,0 +1,394 @@
+//! Deploy reports, written with [`crate::Pipeline::report_file`].
+//!
+//! A report sums up each host of a deploy: the apps and the
+//! images they run (with the digests of the images built), the
+//! Git commit, the changes since the previous deploy to the host,
+//! the healthchecks and the post-deploy commands (smoke tests),
+//! the vulnerability scans, and the time spent in each phase. It is built from the same [`DeployEvent`]s as
+//! [`crate::Pipeline::on_event`] listeners receive, and written
+//! as Markdown, or HTML when the path ends in `.html`, to attach
+//! to a pull request or a release page.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::events::DeployEvent;
+use crate::state::{DeploymentRecord, format_utc};
+use crate::timing::Timings;
+
+/// The format of a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    /// The format of a report written to `path`: HTML for
+    /// `.html` and `.htm` files, Markdown otherwise.
+    #[must_use]
+    pub fn for_path(path: &str) -> Self {
+        let extension = Path::new(path).extension().unwrap_or_default();
+        if extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm") {
+            Self::Html
+        } else {
+            Self::Markdown
+        }
+    }
+}
+
+/// An image built during the deploy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltImage {
+    pub tag: String,
+    /// Size in bytes (0 when unknown).
+    pub size: u64,
+    /// Image id, when the image is in the local engine.
+    pub digest: Option<String>,
+}
+
+/// The last healthcheck of an app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Healthcheck {
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// `None` while the container was not up.
+    pub status: Option<String>,
+}
+
+/// Collects the images built and the healthchecks of a deploy
+/// from its events.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    /// Images built, by app. Images are built once for all the
+    /// hosts of a deploy.
+    pub built: BTreeMap<String, BuiltImage>,
+    /// Healthchecks of the current host, by app.
+    pub healthchecks: BTreeMap<String, Healthcheck>,
+    /// Phases started on the current host, in order.
+    pub phases: Vec<String>,
+}
+
+impl Recorder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `event`.
+    pub fn record(&mut self, event: &DeployEvent) {
+        match event {
+            DeployEvent::ImageBuilt { app, tag, size } => {
+                self.built.insert(
+                    app.clone(),
+                    BuiltImage {
+                        tag: tag.clone(),
+                        size: *size,
+                        digest: None,
+                    },
+                );
+            }
+            DeployEvent::HealthcheckAttempt {
+                app,
+                attempt,
+                max_attempts,
+                status,
+            } => {
+                self.healthchecks.insert(
+                    app.clone(),
+                    Healthcheck {
+                        attempts: *attempt,
+                        max_attempts: *max_attempts,
+                        status: status.clone(),
+                    },
+                );
+            }
+            DeployEvent::PhaseStarted { phase, .. } => self.phases.push(phase.clone()),
+            DeployEvent::TransferProgress { .. } | DeployEvent::Done { .. } => {}
+        }
+    }
+
+    /// The report of the deploy `deployment` that just finished,
+    /// taking the healthchecks and phases recorded for its
+    /// host.
+    pub fn finish_host(
+        &mut self,
+        deployment: DeploymentRecord,
+        previous: Option<DeploymentRecord>,
+        timings: Timings,
+        error: Option<String>,
+    ) -> HostReport {
+        HostReport {
+            deployment,
+            previous,
+            timings,
+            built: self.built.clone(),
+            healthchecks: std::mem::take(&mut self.healthchecks),
+            phases: std::mem::take(&mut self.phases),
+            hooks: Vec::new(),
+            error,
+        }
+    }
+}
+
+/// The deploy to one host.
+#[derive(Debug, Clone)]
+pub struct HostReport {
+    pub deployment: DeploymentRecord,
+    /// The last successful deploy to the host before this one.
+    pub previous: Option<DeploymentRecord>,
+    pub timings: Timings,
+    /// Images built for the deploy, by app.
+    pub built: BTreeMap<String, BuiltImage>,
+    /// Last healthcheck of each app.
+    pub healthchecks: BTreeMap<String, Healthcheck>,
+    /// Phases started, in order.
+    pub phases: Vec<String>,
+    /// Commands run after the deploy, such as smoke tests.
+    pub hooks: Vec<String>,
+    /// Why the deploy failed.
+    pub error: Option<String>,
+}
+
+impl HostReport {
+    /// The changes since the previous deploy to the host.
+    #[must_use]
+    pub fn changes(&self) -> Vec<String> {
+        let Some(previous) = &self.previous else {
+            return vec!["First deploy to this host".to_string()];
+        };
+        let mut changes = Vec::new();
+        match (&previous.revision, &self.deployment.revision) {
+            (Some(from), Some(to)) if from != to => {
+                changes.push(format!("Revision {from} → {to}"));
+            }
+            _ => {}
+        }
+        for (app, image) in &self.deployment.images {
+            match previous.images.get(app) {
+                None => changes.push(format!("{app} added ({image})")),
+                Some(old) if old != image => changes.push(format!("{app}: {old} → {image}")),
+                Some(_) => {}
+            }
+        }
+        for app in previous.images.keys() {
+            if !self.deployment.images.contains_key(app) {
+                changes.push(format!("{app} removed"));
+            }
+        }
+        if changes.is_empty() {
+            changes.push("No changes to the revision or the images".to_string());
+        }
+        changes
+    }
+
+    /// Whether the post-deploy commands passed, failed, or did
+    /// not run.
+    #[must_use]
+    pub fn hooks_outcome(&self) -> &'static str {
+        let ran = self.phases.iter().any(|p| p == "post-deploy hooks");
+        match (ran, self.deployment.success) {
+            (true, true) => "passed",
+            (true, false) => "failed",
+            (false, _) => "not run",
+        }
+    }
+
+    fn blocks(&self) -> Vec<Block> {
+        let d = &self.deployment;
+        let outcome = if d.success { "succeeded" } else { "failed" };
+        let mut details = vec![format!("Finished: {}", format_utc(d.at))];
+        if let Some(revision) = &d.revision {
+            details.push(format!("Revision: {revision}"));
+        }
+        if let Some(user) = &d.user {
+            details.push(format!("User: {user}"));
+        }
+        let mut blocks = vec![
+            Block::Heading(2, format!("{} ({outcome})", d.host)),
+            Block::Items(details),
+        ];
+        if let Some(error) = &self.error {
+            blocks.push(Block::Heading(3, "Error".into()));
+            blocks.push(Block::Code(error.clone()));
+        }
+
+        let apps = d
+            .images
+            .iter()
+            .map(|(app, image)| {
+                let digest = self
+                    .built
+                    .get(app)
+                    .and_then(|b| b.digest.clone())
+                    .unwrap_or_else(|| "-".into());
+                vec![app.clone(), image.clone(), digest]
+            })
+            .collect();
+        blocks.push(Block::Heading(3, "Apps".into()));
+        blocks.push(Block::Table(vec!["App", "Image", "Digest"], apps));
+
+        blocks.push(Block::Heading(3, "Changes".into()));
+        blocks.push(Block::Items(self.changes()));
+
+        if !self.healthchecks.is_empty() {
+            let rows = self
+                .healthchecks
+                .iter()
+                .map(|(app, h)| {
+                    vec![
+                        app.clone(),
+                        h.status.clone().unwrap_or_else(|| "not running".into()),
+                        format!("{}/{}", h.attempts, h.max_attempts),
+                    ]
+                })
+                .collect();
+            blocks.push(Block::Heading(3, "Healthchecks".into()));
+            blocks.push(Block::Table(vec!["App", "Status", "Attempts"], rows));
+        }
+
+        if !self.hooks.is_empty() {
+            // Hooks stop at the first failure, so a failure is
+            // reported for all of them
+            blocks.push(Block::Heading(
+                3,
+                format!("Smoke tests ({})", self.hooks_outcome()),
+            ));
+            blocks.push(Block::Items(self.hooks.clone()));
+        }
+
+        if !d.scans.is_empty() {
+            let rows = d
+                .scans
+                .iter()
+                .map(|(image, scan)| {
+                    vec![
+                        image.clone(),
+                        scan.critical.to_string(),
+                        scan.high.to_string(),
+                        scan.medium.to_string(),
+                        scan.low.to_string(),
+                    ]
+                })
+                .collect();
+            blocks.push(Block::Heading(3, "Vulnerabilities".into()));
+            blocks.push(Block::Table(
+                vec!["Image", "Critical", "High", "Medium", "Low"],
+                rows,
+            ));
+        }
+
+        let rows = self
+            .timings
+            .phases
+            .iter()
+            .map(|(phase, duration)| (phase.as_str(), duration))
+            .chain([("total", &self.timings.total)])
+            .map(|(phase, duration)| {
+                vec![phase.to_string(), format!("{:.1}s", duration.as_secs_f64())]
+            })
+            .collect();
+        blocks.push(Block::Heading(3, "Timing".into()));
+        blocks.push(Block::Table(vec!["Phase", "Duration"], rows));
+        blocks
+    }
+}
+
+/// A piece of a report, rendered in either format.
+enum Block {
+    Heading(u8, String),
+    Items(Vec<String>),
+    Table(Vec<&'static str>, Vec<Vec<String>>),
+    Code(String),
+}
+
+/// The report of the deploys to `hosts`, as `format`.
+#[must_use]
+pub fn render(hosts: &[HostReport], format: ReportFormat) -> String {
+    let mut blocks = vec![Block::Heading(1, "Deploy report".into())];
+    blocks.extend(hosts.iter().flat_map(HostReport::blocks));
+    match format {
+        ReportFormat::Markdown => markdown(&blocks),
+        ReportFormat::Html => html(&blocks),
+    }
+}
+
+fn markdown(blocks: &[Block]) -> String {
+    let cell = |s: &str| s.replace('|', "\\|").replace('\n', " ");
+    let mut out = String::new();
+    for block in blocks {
+        match block {
+            Block::Heading(level, text) => {
+                let _ = writeln!(out, "{} {text}\n", "#".repeat(usize::from(*level)));
+            }
+            Block::Items(items) => {
+                for item in items {
+                    let _ = writeln!(out, "- {item}");
+                }
+                out.push('\n');
+            }
+            Block::Table(headers, rows) => {
+                let _ = writeln!(out, "| {} |", headers.join(" | "));
+                let _ = writeln!(out, "|{}", "---|".repeat(headers.len()));
+                for row in rows {
+                    let row: Vec<String> = row.iter().map(|c| cell(c)).collect();
+                    let _ = writeln!(out, "| {} |", row.join(" | "));
+                }
+                out.push('\n');
+            }
+            Block::Code(text) => {
+                let _ = writeln!(out, "```\n{text}\n```\n");
+            }
+        }
+    }
+    out
+}
+
+fn html(blocks: &[Block]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Deploy report</title>\n</head>\n<body>\n",
+    );
+    for block in blocks {
+        match block {
+            Block::Heading(level, text) => {
+                let _ = writeln!(out, "<h{level}>{}</h{level}>", escape(text));
+            }
+            Block::Items(items) => {
+                out.push_str("<ul>\n");
+                for item in items {
+                    let _ = writeln!(out, "<li>{}</li>", escape(item));
+                }
+                out.push_str("</ul>\n");
+            }
+            Block::Table(headers, rows) => {
+                out.push_str("<table>\n<tr>");
+                for header in headers {
+                    let _ = write!(out, "<th>{header}</th>");
+                }
+                out.push_str("</tr>\n");
+                for row in rows {
+                    out.push_str("<tr>");
+                    for cell in row {
+                        let _ = write!(out, "<td>{}</td>", escape(cell));
+                    }
+                    out.push_str("</tr>\n");
+                }
+                out.push_str("</table>\n");
+            }
+            Block::Code(text) => {
+                let _ = writeln!(out, "<pre>{}</pre>", escape(text));
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
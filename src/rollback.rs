@@ -0,0 +1,176 @@
+//! Previous images kept on the server for rollback.
+//!
+//! Before a deploy loads a new `name:latest`, the image it
+//! replaces is tagged `name:previous`, and older ones
+//! `name:previous-2`, `name:previous-3`, ... up to
+//! [`crate::Pipeline::keep_images`]. `cargo xtask rollback <host>`
+//! moves that history back by one, so rolling back needs no
+//! build or transfer.
+//!
+//! Each image a deploy transfers is also tagged with a version,
+//! `name:deploy-<n>-<commit>` (`<n>` counting the deploys of the
+//! image on the server, `<commit>` the local Git commit, when
+//! there is one), and the last [`crate::Pipeline::keep_images`]
+//! versions before the current one are kept.
+//! `cargo xtask rollback <host> --list` shows them, and
+//! `--to <n>` goes back to any of them.
+
+use crate::deploy::Runtime;
+
+/// Number of previous images kept by default.
+pub const DEFAULT_KEEP: u32 = 1;
+
+/// Tag of the `n`th previous image (`previous`, `previous-2`, ...).
+#[must_use]
+pub fn previous_tag(n: u32) -> String {
+    if n == 1 {
+        "previous".to_string()
+    } else {
+        format!("previous-{n}")
+    }
+}
+
+/// Tags from newest to oldest: `latest`, then `keep` previous
+/// ones.
+fn history(keep: u32) -> Vec<String> {
+    std::iter::once("latest".to_string())
+        .chain((1..=keep).map(previous_tag))
+        .collect()
+}
+
+/// Remote command moving `image:latest` into the history of the
+/// `runtime` engine before a new image is loaded, dropping the
+/// oldest beyond `keep`.
+///
+/// Does nothing when there is no `image:latest` yet, or when it
+/// is already `image:previous` (a deploy retried without a new
+/// image), so the history never holds the same image twice.
+#[must_use]
+pub fn keep_command(image: &str, keep: u32, runtime: Runtime) -> String {
+    if keep == 0 {
+        return "true".to_string();
+    }
+    let cli = runtime.cli();
+    let tags = history(keep);
+    let shift: Vec<String> = tags
+        .windows(2)
+        .rev()
+        .map(|pair| {
+            format!(
+                "{cli} tag {image}:{} {image}:{} 2>/dev/null",
+                pair[0], pair[1]
+            )
+        })
+        .collect();
+    format!(
+        "latest=$({cli} image inspect -f '{{{{.Id}}}}' {image}:latest 2>/dev/null); \
+         previous=$({cli} image inspect -f '{{{{.Id}}}}' {image}:previous 2>/dev/null); \
+         if [ -n \"$latest\" ] && [ \"$latest\" != \"$previous\" ]; then {}; fi; true",
+        shift.join("; ")
+    )
+}
+
+/// Remote command making `image:previous` the new `image:latest`
+/// and moving the older history up by one, with the `runtime`
+/// engine. Fails when there is no previous image.
+#[must_use]
+pub fn rollback_command(image: &str, keep: u32, runtime: Runtime) -> String {
+    let cli = runtime.cli();
+    let tags = history(keep.max(1));
+    let shift: Vec<String> = tags
+        .windows(2)
+        .map(|pair| {
+            format!(
+                "{{ {cli} tag {image}:{} {image}:{} 2>/dev/null \
+                 || {cli} rmi {image}:{} >/dev/null 2>&1; }}",
+                pair[1], pair[0], pair[0]
+            )
+        })
+        .collect();
+    format!(
+        "{cli} image inspect {image}:previous >/dev/null 2>&1 \
+         || {{ echo 'no previous image of {image}' >&2; exit 1; }}; \
+         {}; {cli} rmi {image}:{} >/dev/null 2>&1; true",
+        shift.join("; "),
+        tags[tags.len() - 1]
+    )
+}
+
+/// Prefix of the version tags.
+pub const VERSION_PREFIX: &str = "deploy-";
+
+/// Remote command listing the version tags of `image`, newest
+/// first.
+fn versions(image: &str, cli: &str) -> String {
+    format!(
+        "{cli} image ls {image} --format '{{{{.Tag}}}}' \
+         | grep '^{VERSION_PREFIX}[0-9]' | sort -t- -k2 -rn"
+    )
+}
+
+/// Remote command tagging the freshly loaded `image:latest` with
+/// the next version, printing its tag.
+///
+/// The tag carries `revision` when there is one. Versions beyond
+/// the current one and `keep` older ones are dropped.
+#[must_use]
+pub fn version_command(image: &str, revision: Option<&str>, keep: u32, runtime: Runtime) -> String {
+    let cli = runtime.cli();
+    let list = versions(image, cli);
+    let suffix = revision.map(|r| format!("-{r}")).unwrap_or_default();
+    format!(
+        "n=$({list} | head -n1 | cut -d- -f2); \
+         tag={VERSION_PREFIX}$((${{n:-0}} + 1)){suffix}; \
+         {cli} tag {image}:latest {image}:$tag && echo $tag; \
+         {list} | tail -n +{} | while read -r old; do {cli} rmi {image}:$old >/dev/null 2>&1; done; \
+         true",
+        keep + 2
+    )
+}
+
+/// Remote command listing the versions of `image` kept on the
+/// server, newest first, marking the current one.
+#[must_use]
+pub fn list_command(image: &str, runtime: Runtime) -> String {
+    let cli = runtime.cli();
+    format!(
+        "latest=$({cli} image inspect -f '{{{{.Id}}}}' {image}:latest 2>/dev/null); \
+         {} | while read -r tag; do \
+         if [ \"$({cli} image inspect -f '{{{{.Id}}}}' {image}:$tag)\" = \"$latest\" ]; \
+         then echo \"$tag (current)\"; else echo \"$tag\"; fi; done",
+        versions(image, cli)
+    )
+}
+
+/// Whether `version` names a version: its number (`3`) or its
+/// tag (`deploy-3-1a2b3c4`).
+#[must_use]
+pub fn is_version(version: &str) -> bool {
+    !version.is_empty()
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Remote command making the version `version` of `image` the
+/// new `image:latest`, with the `runtime` engine.
+///
+/// The image it replaces goes into the history like on a deploy,
+/// so a plain rollback returns to it. Fails when the version
+/// isn't kept.
+#[must_use]
+pub fn rollback_to_command(image: &str, version: &str, keep: u32, runtime: Runtime) -> String {
+    let cli = runtime.cli();
+    let pattern = if version.chars().all(|c| c.is_ascii_digit()) {
+        format!("^{VERSION_PREFIX}{version}(-|$)")
+    } else {
+        format!("^{version}$")
+    };
+    format!(
+        "tag=$({} | grep -E '{pattern}' | head -n1); \
+         [ -n \"$tag\" ] || {{ echo 'no version {version} of {image}' >&2; exit 1; }}; \
+         {}; {cli} tag {image}:$tag {image}:latest",
+        versions(image, cli),
+        keep_command(image, keep, runtime)
+    )
+}
@@ -0,0 +1,73 @@
+//! Replica counts set on a running server.
+//!
+//! `cargo xtask scale <host> api=3` records the new counts in
+//! [`STATE_FILE`] next to `docker-compose.yml`. Later deploys
+//! read it back, so a scaled service keeps its replica count
+//! instead of returning to [`App::replicas`].
+//!
+//! [`App::replicas`]: crate::App::replicas
+
+use indexmap::IndexMap;
+
+use crate::app::App;
+use crate::error::{DeployError, DeployResult};
+
+/// File in the remote directory holding the recorded counts, as
+/// a JSON object of service name to replicas.
+pub const STATE_FILE: &str = "replicas.json";
+
+/// Recorded replica count per service.
+pub type Replicas = IndexMap<String, u32>;
+
+/// Parse a `service=count` argument.
+///
+/// # Example
+///
+/// ```
+/// use catapulta::scale::parse_spec;
+///
+/// assert_eq!(parse_spec("api=3").unwrap(), ("api".to_string(), 3));
+/// assert!(parse_spec("api").is_err());
+/// ```
+pub fn parse_spec(spec: &str) -> DeployResult<(String, u32)> {
+    let invalid = || DeployError::Other(format!("invalid scale '{spec}', expected SERVICE=COUNT"));
+    let (name, count) = spec.split_once('=').ok_or_else(invalid)?;
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    let count = count.parse().map_err(|_| invalid())?;
+    Ok((name.to_string(), count))
+}
+
+/// Parse the content of [`STATE_FILE`]. Empty content (no file
+/// yet) means nothing was scaled.
+pub fn parse_state(content: &str) -> DeployResult<Replicas> {
+    if content.trim().is_empty() {
+        return Ok(Replicas::new());
+    }
+    serde_json::from_str(content)
+        .map_err(|e| DeployError::InvalidConfig(format!("{STATE_FILE}: {e}")))
+}
+
+/// Content of [`STATE_FILE`] for `replicas`.
+#[must_use]
+pub fn render_state(replicas: &Replicas) -> String {
+    let mut json = serde_json::to_string_pretty(replicas).unwrap_or_default();
+    json.push('\n');
+    json
+}
+
+/// `apps` with their recorded replica counts applied. Entries
+/// for services that no longer exist are ignored.
+#[must_use]
+pub fn apply(apps: &[App], replicas: &Replicas) -> Vec<App> {
+    apps.iter()
+        .map(|app| {
+            let mut app = app.clone();
+            if let Some(&n) = replicas.get(&app.name) {
+                app.replicas = n;
+            }
+            app
+        })
+        .collect()
+}
@@ -0,0 +1,315 @@
+//! Vulnerability scans of the images built for a deploy, enabled
+//! with [`crate::Pipeline::scan_images`].
+//!
+//! Right after the build, each image is scanned with Trivy or
+//! Docker Scout, on the local Docker daemon, before anything
+//! reaches the server. The deploy fails when an image has
+//! vulnerabilities at or above the threshold
+//! ([`Severity::Critical`] by default), and the number found at
+//! each severity is kept with the deployment in the state (see
+//! [`crate::state::DeploymentRecord::scans`]). With
+//! [`ImageScan::sbom`], the scanner also writes an SBOM of each
+//! image to the local directory, recorded next to the counts.
+//!
+//! Images built elsewhere (e.g. on the server by
+//! [`crate::DockerContext`]) and prebuilt ones are not scanned.
+//!
+//! ```rust,no_run
+//! use catapulta::scan::{ImageScan, Scanner, Severity};
+//! use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+//!
+//! let app = App::new("api").expose(8000);
+//! let caddy = Caddy::new().reverse_proxy(app.upstream());
+//! Pipeline::new(app, caddy)
+//!     .deploy(DockerSaveLoad::new())
+//!     .scan_images(
+//!         ImageScan::new()
+//!             .scanner(Scanner::Trivy)
+//!             .fail_on(Severity::High)
+//!             .sbom(),
+//!     );
+//! ```
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+
+/// Severity of a vulnerability, from the least to the most
+/// severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// The severity named `name` in a scanner's report (`HIGH`,
+    /// `High`, ...), or `None` for unranked ones (`UNKNOWN`,
+    /// `negligible`, ...).
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        })
+    }
+}
+
+/// The tool scanning the images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scanner {
+    /// Trivy when it is installed, else Docker Scout.
+    #[default]
+    Auto,
+    /// `trivy image`.
+    Trivy,
+    /// `docker scout cves`, from the Docker Scout CLI plugin.
+    Scout,
+}
+
+impl Scanner {
+    /// Resolve [`Scanner::Auto`] into the installed scanner.
+    fn resolve(self) -> DeployResult<Self> {
+        match self {
+            Self::Auto if cmd::command_exists("trivy") => Ok(Self::Trivy),
+            Self::Auto if cmd::run("docker", &["scout", "version"]).is_ok() => Ok(Self::Scout),
+            Self::Auto => Err(DeployError::PrerequisiteMissing(
+                "trivy or docker scout (required to scan images)".into(),
+            )),
+            other => Ok(other),
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Auto | Self::Trivy => "trivy",
+            Self::Scout => "docker scout",
+        }
+    }
+}
+
+/// Settings of the scan of the built images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageScan {
+    pub scanner: Scanner,
+    /// Lowest severity failing the deploy, or `None` to only
+    /// record the findings.
+    pub fail_on: Option<Severity>,
+    /// Write an SBOM of each image.
+    pub sbom: bool,
+}
+
+impl Default for ImageScan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImageScan {
+    /// Scan with the installed scanner, failing on critical
+    /// vulnerabilities.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            scanner: Scanner::Auto,
+            fail_on: Some(Severity::Critical),
+            sbom: false,
+        }
+    }
+
+    /// Scan with `scanner`.
+    #[must_use]
+    pub const fn scanner(mut self, scanner: Scanner) -> Self {
+        self.scanner = scanner;
+        self
+    }
+
+    /// Fail the deploy on vulnerabilities of `severity` or
+    /// above.
+    #[must_use]
+    pub const fn fail_on(mut self, severity: Severity) -> Self {
+        self.fail_on = Some(severity);
+        self
+    }
+
+    /// Record the findings without ever failing the deploy.
+    #[must_use]
+    pub const fn report_only(mut self) -> Self {
+        self.fail_on = None;
+        self
+    }
+
+    /// Also write an SBOM of each image: `CycloneDX` with Trivy,
+    /// SPDX with Docker Scout.
+    #[must_use]
+    pub const fn sbom(mut self) -> Self {
+        self.sbom = true;
+        self
+    }
+
+    /// Scan the local image `image`, writing its SBOM to
+    /// `sbom_path` when enabled.
+    pub fn run(&self, image: &str, sbom_path: &Path) -> DeployResult<ScanReport> {
+        let scanner = self.scanner.resolve()?;
+        eprintln!("Scanning {image} with {}...", scanner.name());
+        let mut report = match scanner {
+            Scanner::Scout => parse_scout(&cmd::run(
+                "docker",
+                &["scout", "cves", "--format", "gitlab", image],
+            )?)?,
+            _ => parse_trivy(&cmd::run(
+                "trivy",
+                &["image", "--quiet", "--format", "json", image],
+            )?)?,
+        };
+        if self.sbom {
+            if let Some(dir) = sbom_path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let path = sbom_path.to_string_lossy();
+            match scanner {
+                Scanner::Scout => cmd::run(
+                    "docker",
+                    &[
+                        "scout", "sbom", "--format", "spdx", "--output", &path, image,
+                    ],
+                )?,
+                _ => cmd::run(
+                    "trivy",
+                    &[
+                        "image",
+                        "--quiet",
+                        "--format",
+                        "cyclonedx",
+                        "--output",
+                        &path,
+                        image,
+                    ],
+                )?,
+            };
+            report.sbom = Some(path.into_owned());
+        }
+        eprintln!("  {image}: {report}");
+        Ok(report)
+    }
+
+    /// Fail when `report` of `image` has vulnerabilities at or
+    /// above the threshold.
+    pub fn check(&self, image: &str, report: &ScanReport) -> DeployResult<()> {
+        match self.fail_on {
+            Some(threshold) if report.at_or_above(threshold) > 0 => Err(DeployError::Vulnerable {
+                image: image.to_string(),
+                threshold,
+                found: report.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The findings of the scan of an image.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub scanner: String,
+    pub critical: u32,
+    pub high: u32,
+    pub medium: u32,
+    pub low: u32,
+    /// Path of the SBOM written for the image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sbom: Option<String>,
+}
+
+impl ScanReport {
+    fn new(scanner: Scanner) -> Self {
+        Self {
+            scanner: scanner.name().to_string(),
+            ..Self::default()
+        }
+    }
+
+    const fn add(&mut self, severity: Severity) {
+        match severity {
+            Severity::Low => self.low += 1,
+            Severity::Medium => self.medium += 1,
+            Severity::High => self.high += 1,
+            Severity::Critical => self.critical += 1,
+        }
+    }
+
+    /// Number of vulnerabilities of `severity` or above.
+    #[must_use]
+    pub const fn at_or_above(&self, severity: Severity) -> u32 {
+        match severity {
+            Severity::Critical => self.critical,
+            Severity::High => self.critical + self.high,
+            Severity::Medium => self.critical + self.high + self.medium,
+            Severity::Low => self.critical + self.high + self.medium + self.low,
+        }
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} critical, {} high, {} medium, {} low",
+            self.critical, self.high, self.medium, self.low
+        )
+    }
+}
+
+/// Count the vulnerabilities of `trivy image --format json`.
+pub fn parse_trivy(json: &str) -> DeployResult<ScanReport> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    let mut report = ScanReport::new(Scanner::Trivy);
+    let results = value["Results"].as_array().map_or(&[][..], Vec::as_slice);
+    for result in results {
+        let vulnerabilities = result["Vulnerabilities"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice);
+        for vulnerability in vulnerabilities {
+            if let Some(severity) = vulnerability["Severity"].as_str().and_then(Severity::parse) {
+                report.add(severity);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Count the vulnerabilities of `docker scout cves --format
+/// gitlab`.
+pub fn parse_scout(json: &str) -> DeployResult<ScanReport> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    let mut report = ScanReport::new(Scanner::Scout);
+    let vulnerabilities = value["vulnerabilities"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice);
+    for vulnerability in vulnerabilities {
+        if let Some(severity) = vulnerability["severity"].as_str().and_then(Severity::parse) {
+            report.add(severity);
+        }
+    }
+    Ok(report)
+}
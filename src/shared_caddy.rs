@@ -0,0 +1,135 @@
+//! One Caddy shared by the stacks on a server.
+//!
+//! With [`Caddy::shared`], a stack doesn't run its own Caddy.
+//! A single one runs from [`SHARED_DIR`], owns ports 80 and 443,
+//! and imports one Caddyfile per stack from its `sites`
+//! directory, so pipelines deploying to the same server each
+//! manage their own sites. Proxied apps join [`SHARED_NETWORK`]
+//! under an alias prefixed with their stack
+//! ([`upstream_alias`]), which keeps `api` of one stack apart
+//! from `api` of another.
+
+use crate::app::App;
+use crate::caddy::{Caddy, DEFAULT_IMAGE};
+use crate::caddyfile;
+use crate::compose;
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::{FileAttrs, SshSession};
+
+/// Directory of the shared Caddy's compose stack on the server.
+pub const SHARED_DIR: &str = "/opt/catapulta-caddy";
+
+/// Docker network joining the shared Caddy to proxied apps.
+pub const SHARED_NETWORK: &str = "catapulta-caddy";
+
+/// Main Caddyfile of the shared Caddy: every stack's site file.
+const MAIN_CADDYFILE: &str = "import /etc/caddy/sites/*.caddy\n";
+
+/// Name of `app` on [`SHARED_NETWORK`], for the stack `stack`.
+#[must_use]
+pub fn upstream_alias(stack: &str, app: &str) -> String {
+    format!("{stack}-{app}")
+}
+
+/// Path of the site file of `stack` on the server.
+#[must_use]
+pub fn site_file(stack: &str) -> String {
+    format!("{SHARED_DIR}/sites/{stack}.caddy")
+}
+
+/// `docker-compose.yml` of the shared Caddy.
+#[must_use]
+pub fn compose_file() -> String {
+    format!(
+        "name: {SHARED_NETWORK}
+services:
+  caddy:
+    image: {DEFAULT_IMAGE}
+    container_name: {SHARED_NETWORK}
+    restart: unless-stopped
+    ports:
+      - 80:80
+      - 443:443
+    volumes:
+      - ./Caddyfile:/etc/caddy/Caddyfile:ro
+      - ./sites:/etc/caddy/sites:ro
+      - caddy-data:/data
+      - caddy-config:/config
+    networks:
+      - {SHARED_NETWORK}
+volumes:
+  caddy-data: {{}}
+  caddy-config: {{}}
+networks:
+  {SHARED_NETWORK}:
+    external: true
+"
+    )
+}
+
+/// The site file of the stack: its Caddyfile, proxying to the
+/// apps' aliases on [`SHARED_NETWORK`].
+pub fn render_site(caddy: &Caddy, domain: &str, apps: &[App]) -> DeployResult<String> {
+    if caddy.json {
+        return Err(DeployError::InvalidConfig(
+            "the shared Caddy is configured with Caddyfiles; \
+             Caddy::json can't be combined with Caddy::shared"
+                .into(),
+        ));
+    }
+    if caddy.dns_challenge.is_some() {
+        return Err(DeployError::InvalidConfig(
+            "the shared Caddy holds no DNS provider token; \
+             Caddy::dns_challenge can't be combined with Caddy::shared"
+                .into(),
+        ));
+    }
+    let stack = compose::stack_name(apps);
+    let mut caddy = caddy.clone();
+    for upstream in caddy
+        .reverse_proxy
+        .iter_mut()
+        .chain(caddy.routes.iter_mut().map(|(_, up)| up))
+    {
+        upstream.name = upstream_alias(&stack, &upstream.name);
+    }
+    let apps: Vec<App> = apps
+        .iter()
+        .map(|app| {
+            let mut app = app.clone();
+            app.name = upstream_alias(&stack, &app.name);
+            app
+        })
+        .collect();
+    Ok(caddyfile::render_with_apps(&caddy, domain, &apps))
+}
+
+/// Start the shared Caddy unless it runs, creating its network
+/// and directory on first use.
+pub fn start(ssh: &SshSession) -> DeployResult<()> {
+    ssh.exec(&format!(
+        "mkdir -p {SHARED_DIR}/sites && \
+         (docker network inspect {SHARED_NETWORK} >/dev/null 2>&1 || \
+         docker network create {SHARED_NETWORK} >/dev/null)"
+    ))?;
+    ssh.write_remote_file_with(
+        MAIN_CADDYFILE,
+        &format!("{SHARED_DIR}/Caddyfile"),
+        &FileAttrs::new().in_place(),
+    )?;
+    ssh.write_remote_file(&compose_file(), &format!("{SHARED_DIR}/docker-compose.yml"))?;
+    ssh.exec(&format!("cd {SHARED_DIR} && docker compose up -d"))?;
+    Ok(())
+}
+
+/// Install `site` as the site file of `stack` and reload the
+/// shared Caddy.
+pub fn publish(ssh: &SshSession, stack: &str, site: &str) -> DeployResult<()> {
+    eprintln!("Updating the shared Caddy...");
+    ssh.write_remote_file(site, &site_file(stack))?;
+    ssh.exec(&format!(
+        "cd {SHARED_DIR} && docker compose exec -T caddy \
+         caddy reload --config /etc/caddy/Caddyfile --adapter caddyfile"
+    ))?;
+    Ok(())
+}
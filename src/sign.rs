@@ -0,0 +1,134 @@
+//! Signatures of the files a deploy copies to the server, made
+//! with [minisign](https://jedisct1.github.io/minisign/).
+//!
+//! With [`crate::DockerSaveLoad::sign`], the image tarball and
+//! the files of the stack (compose file, Caddyfile, config and
+//! env files) are signed locally with the secret key, and
+//! verified on the server against the public key pinned at
+//! [`REMOTE_PUBLIC_KEY`] before the image is loaded or the file
+//! replaces the previous version. An artifact swapped on the way
+//! or in `/tmp` on the server is rejected instead of deployed.
+//!
+//! A streamed image goes from the SSH channel straight into
+//! `docker load` and has nothing to sign, so signing makes
+//! [`crate::Transfer::Auto`] copy tarballs instead.
+//!
+//! The server needs `minisign` installed. A secret key without a
+//! password (`minisign -G -W`) signs unattended; otherwise the
+//! password is read from [`PASSWORD_ENV`], or prompted for.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::audit;
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::{FileAttrs, SshSession, shell_quote};
+
+/// Where the public key verifying the signatures is pinned on
+/// the server.
+pub const REMOTE_PUBLIC_KEY: &str = "/etc/catapulta/minisign.pub";
+
+/// Environment variable holding the password of the secret key.
+pub const PASSWORD_ENV: &str = "MINISIGN_PASSWORD";
+
+/// Suffix of the signature of a file.
+pub const SIGNATURE_SUFFIX: &str = ".minisig";
+
+/// Number of the next temporary file signed by [`Signer::sign`].
+static NEXT_FILE: AtomicU32 = AtomicU32::new(0);
+
+/// A minisign key pair signing the artifacts of a deploy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signer {
+    /// Path of the secret key.
+    pub secret_key: String,
+    /// Path of the public key, pinned on the servers.
+    pub public_key: String,
+}
+
+impl Signer {
+    /// Sign with the minisign key pair at `secret_key` and
+    /// `public_key` (e.g. `~/.minisign/minisign.key` and
+    /// `minisign.pub`).
+    #[must_use]
+    pub fn minisign(secret_key: &str, public_key: &str) -> Self {
+        Self {
+            secret_key: secret_key.to_string(),
+            public_key: public_key.to_string(),
+        }
+    }
+
+    /// Sign the local file `path`, returning the path of its
+    /// signature, next to it.
+    pub fn sign_file(&self, path: &str) -> DeployResult<String> {
+        if !cmd::command_exists("minisign") {
+            return Err(DeployError::PrerequisiteMissing(
+                "minisign (required to sign artifacts)".into(),
+            ));
+        }
+        let signature = format!("{path}{SIGNATURE_SUFFIX}");
+        let args = ["-S", "-s", &self.secret_key, "-m", path, "-x", &signature];
+        match std::env::var(PASSWORD_ENV) {
+            Ok(password) => {
+                audit::register_secret(&password);
+                cmd::run_with_stdin("minisign", &args, format!("{password}\n").as_bytes())
+                    .map(drop)?;
+            }
+            Err(_) => cmd::run_interactive("minisign", &args)?,
+        }
+        Ok(signature)
+    }
+
+    /// The signature of `content`.
+    pub fn sign(&self, content: &str) -> DeployResult<String> {
+        let path = std::env::temp_dir().join(format!(
+            "catapulta-sign-{}-{}",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = path.to_string_lossy().into_owned();
+        std::fs::write(&path, content)?;
+        let signature = self
+            .sign_file(&path)
+            .and_then(|signature| Ok(std::fs::read_to_string(signature)?));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(format!("{path}{SIGNATURE_SUFFIX}"));
+        signature
+    }
+
+    /// The attributes writing `content` with its signature, on
+    /// top of `attrs`.
+    pub fn signed(&self, content: &str, attrs: FileAttrs) -> DeployResult<FileAttrs> {
+        Ok(attrs.signature(&self.sign(content)?))
+    }
+
+    /// Pin the public key on the server at
+    /// [`REMOTE_PUBLIC_KEY`], checking it can verify signatures.
+    pub fn install(&self, ssh: &SshSession) -> DeployResult<()> {
+        let has_minisign = ssh.exec("command -v minisign >/dev/null 2>&1 && echo yes || true")?;
+        if has_minisign != "yes" {
+            return Err(DeployError::PrerequisiteMissing(format!(
+                "minisign on {} (required to verify signatures)",
+                ssh.host()
+            )));
+        }
+        let key = std::fs::read_to_string(&self.public_key)
+            .map_err(|e| DeployError::FileNotFound(format!("{}: {e}", self.public_key)))?;
+        let dir = REMOTE_PUBLIC_KEY
+            .rsplit_once('/')
+            .map_or("/", |(dir, _)| dir);
+        ssh.exec(&format!("mkdir -p {dir}"))?;
+        ssh.write_remote_file_with(&key, REMOTE_PUBLIC_KEY, &FileAttrs::new().mode(0o644))
+    }
+}
+
+/// Remote command verifying the file at `path` against its
+/// `signature` and the pinned public key.
+#[must_use]
+pub fn verify_command(path: &str, signature: &str) -> String {
+    format!(
+        "minisign -Vq -p {REMOTE_PUBLIC_KEY} -m {} -x {}",
+        shell_quote(path),
+        shell_quote(signature)
+    )
+}
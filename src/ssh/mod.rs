@@ -0,0 +1,917 @@
+#[cfg(feature = "native-ssh")]
+mod native;
+#[cfg(not(feature = "native-ssh"))]
+mod openssh;
+pub mod pool;
+pub mod tunnel;
+
+#[cfg(feature = "native-ssh")]
+use native::Native as DefaultTransport;
+#[cfg(not(feature = "native-ssh"))]
+use openssh::OpenSsh as DefaultTransport;
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::sign;
+
+/// A bastion host that SSH connections are tunnelled through.
+///
+/// Rendered as a `ProxyCommand` for the OpenSSH binaries, and as
+/// a `direct-tcpip` channel by the native client. Its host key is
+/// checked like the destination's.
+#[derive(Debug, Clone)]
+pub struct JumpHost {
+    pub host: String,
+    pub user: String,
+    /// Private key for the bastion. Falls back to the agent and
+    /// `~/.ssh/config` when unset.
+    pub key: Option<String>,
+}
+
+impl JumpHost {
+    #[must_use]
+    pub fn new(host: &str, user: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            user: user.to_string(),
+            key: None,
+        }
+    }
+
+    #[must_use]
+    pub fn key(mut self, key_path: &str) -> Self {
+        self.key = Some(key_path.to_string());
+        self
+    }
+}
+
+/// Connection settings shared by every SSH session a pipeline
+/// opens, whether from the pipeline itself, the deployer, or the
+/// provisioner.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use catapulta::ssh::SshOptions;
+///
+/// let opts = SshOptions::new()
+///     .jump_host("bastion.example.com", "ops", None)
+///     .port(2222)
+///     .retries(60, Duration::from_secs(5));
+///
+/// assert_eq!(opts.port, Some(2222));
+/// assert_eq!(opts.jump.unwrap().host, "bastion.example.com");
+/// ```
+#[derive(Debug, Clone)]
+pub struct SshOptions {
+    pub jump: Option<JumpHost>,
+    /// Port override. Falls back to `~/.ssh/config`, then 22.
+    pub port: Option<u16>,
+    /// TCP connect and handshake timeout (default: 10s).
+    pub connect_timeout: Duration,
+    /// Attempts made by [`SshSession::wait_until_ready`]
+    /// (default: 30).
+    pub retries: u32,
+    /// Delay between readiness attempts (default: 10s).
+    pub retry_interval: Duration,
+    /// Keepalive interval; `None` disables keepalives
+    /// (default: 15s).
+    pub keepalive: Option<Duration>,
+    /// Limit for captured (non-interactive) remote commands run
+    /// with [`SshSession::exec`]; `None` waits forever
+    /// (default: [`cmd::REMOTE_TIMEOUT`]).
+    pub command_timeout: Option<Duration>,
+    /// Pin the key of a host that has none pinned, instead of
+    /// rejecting it (default: false).
+    pub trust_new_host_key: bool,
+    transport: Arc<dyn SshTransport>,
+}
+
+impl Default for SshOptions {
+    fn default() -> Self {
+        Self {
+            jump: None,
+            port: None,
+            connect_timeout: Duration::from_secs(10),
+            retries: 30,
+            retry_interval: Duration::from_secs(10),
+            keepalive: Some(Duration::from_secs(15)),
+            command_timeout: Some(cmd::REMOTE_TIMEOUT),
+            trust_new_host_key: false,
+            transport: Arc::new(DefaultTransport),
+        }
+    }
+}
+
+impl SshOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tunnel connections through a bastion host.
+    #[must_use]
+    pub fn jump_host(mut self, host: &str, user: &str, key: Option<&str>) -> Self {
+        let mut jump = JumpHost::new(host, user);
+        if let Some(key) = key {
+            jump = jump.key(key);
+        }
+        self.jump = Some(jump);
+        self
+    }
+
+    #[must_use]
+    pub const fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    #[must_use]
+    pub const fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Number of readiness attempts and the delay between them.
+    #[must_use]
+    pub const fn retries(mut self, count: u32, interval: Duration) -> Self {
+        self.retries = count;
+        self.retry_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.keepalive = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn command_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Pin the key a host presents when none is pinned for it
+    /// yet, as `provision` and `import` do for the servers they
+    /// add. Otherwise connections to a host whose key is neither
+    /// pinned nor in `~/.ssh/known_hosts` fail with
+    /// [`DeployError::HostKeyUnknown`].
+    #[must_use]
+    pub const fn trust_new_host_key(mut self) -> Self {
+        self.trust_new_host_key = true;
+        self
+    }
+
+    /// Carry sessions over `transport` instead of the default
+    /// client, e.g. [`crate::testing::FakeSsh`] to test pipeline
+    /// wiring without a network.
+    #[must_use]
+    pub fn transport(mut self, transport: impl SshTransport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// [`SshSession::clear_known_host`] through the configured
+    /// transport.
+    pub fn clear_known_host(&self, host: &str) {
+        self.transport.clear_known_host(host);
+    }
+}
+
+/// How an [`SshSession`] reaches its host.
+///
+/// Sessions use the native client with the `native-ssh` feature
+/// and the OpenSSH binaries without it, unless another transport
+/// is set with [`SshOptions::transport`].
+pub trait SshTransport: std::fmt::Debug + Send + Sync {
+    /// Run `command` and capture its trimmed stdout.
+    fn exec(&self, session: &SshSession, command: &str) -> DeployResult<String>;
+
+    /// Run `command`, streaming its output to the local terminal.
+    fn exec_interactive(&self, session: &SshSession, command: &str) -> DeployResult<()>;
+
+    /// Run `command` on a terminal of the host attached to the
+    /// local one.
+    ///
+    /// Goes through the system `ssh -t` by default, since it
+    /// hands the local terminal over.
+    fn terminal(&self, session: &SshSession, command: &str) -> DeployResult<()> {
+        let mut args = session.openssh_args();
+        args.push("-t".to_string());
+        args.push(session.destination());
+        args.push(command.to_string());
+        let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+        cmd::run_interactive("ssh", &refs)
+    }
+
+    /// Copy a local file to the host, calling `progress` with the
+    /// bytes sent so far and the file size.
+    fn scp_to(
+        &self,
+        session: &SshSession,
+        local_path: &str,
+        remote_path: &str,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> DeployResult<()>;
+
+    /// Write `content` to `remote_path` as is.
+    fn write_file(
+        &self,
+        session: &SshSession,
+        content: &str,
+        remote_path: &str,
+    ) -> DeployResult<()>;
+
+    /// Write `content` to `remote_path` atomically, applying
+    /// `attrs` (see [`SshSession::write_remote_file_with`]).
+    fn write_remote_file(
+        &self,
+        session: &SshSession,
+        content: &str,
+        remote_path: &str,
+        attrs: &FileAttrs,
+    ) -> DeployResult<()> {
+        let tmp = format!("{remote_path}.catapulta-tmp.{}", std::process::id());
+        let signature = format!("{tmp}{}", sign::SIGNATURE_SUFFIX);
+        cmd::retry(cmd::RETRY_ATTEMPTS, cmd::RETRY_BACKOFF, || {
+            self.write_file(session, content, &tmp)?;
+            if let Some(content) = &attrs.signature {
+                self.write_file(session, content, &signature)?;
+            }
+            Ok(())
+        })?;
+
+        if let Err(e) = self.exec(session, &replace_command(&tmp, remote_path, attrs)) {
+            let _ = self.exec(
+                session,
+                &format!("rm -f {} {}", shell_quote(&tmp), shell_quote(&signature)),
+            );
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Feed the output of the `local` pipe into `remote_command`.
+    fn pipe_from_local(
+        &self,
+        session: &SshSession,
+        local: &cmd::Pipe,
+        remote_command: &str,
+    ) -> DeployResult<()>;
+
+    /// Forward a local port through the host until interrupted.
+    fn forward(&self, session: &SshSession, fwd: &tunnel::PortForward) -> DeployResult<()>;
+
+    /// See [`SshSession::clear_known_host`].
+    fn clear_known_host(&self, host: &str) {
+        SshSession::clear_known_host(host);
+    }
+
+    /// Pin the key pinned for the session's host under `aliases`
+    /// too (see [`SshSession::pin_host_key`]).
+    fn alias_host_key(&self, session: &SshSession, aliases: &[&str]) -> DeployResult<()> {
+        let Some(pins) = pinned_hosts_file() else {
+            return Ok(());
+        };
+        let entry = |host: &str| {
+            session
+                .options
+                .port
+                .filter(|&p| p != 22)
+                .map_or_else(|| host.to_string(), |port| format!("[{host}]:{port}"))
+        };
+        let path = pins.to_string_lossy().to_string();
+        let Ok(found) = cmd::run("ssh-keygen", &["-F", &entry(&session.host), "-f", &path]) else {
+            return Ok(());
+        };
+        // `host keytype key`, the host possibly hashed
+        let keys: Vec<&str> = found
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(' ').map(|(_, key)| key.trim()))
+            .collect();
+        for alias in aliases {
+            remove_host_entry(&pins, &entry(alias));
+            let mut file = std::fs::OpenOptions::new().append(true).open(&pins)?;
+            for key in &keys {
+                writeln!(file, "{} {key}", entry(alias))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// See [`SshSession::forget_host_key`].
+    fn forget_host_key(&self, session: &SshSession) {
+        let Some(pins) = pinned_hosts_file() else {
+            return;
+        };
+        remove_host_entry(&pins, &session.host);
+        if let Some(port) = session.options.port.filter(|&p| p != 22) {
+            remove_host_entry(&pins, &format!("[{}]:{port}", session.host));
+        }
+    }
+}
+
+/// Permissions applied to a file written with
+/// [`SshSession::write_remote_file_with`], and the signature it
+/// must match.
+///
+/// Unset fields leave the remote defaults (umask, connecting
+/// user) in place.
+///
+/// # Example
+///
+/// ```
+/// use catapulta::ssh::FileAttrs;
+///
+/// let attrs = FileAttrs::new().mode(0o600).owner("app:app");
+///
+/// assert_eq!(attrs.mode, Some(0o600));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FileAttrs {
+    /// Octal permission bits, e.g. `0o600`.
+    pub mode: Option<u32>,
+    /// `user` or `user:group`, passed to `chown`.
+    pub owner: Option<String>,
+    /// minisign signature of the content, verified before the
+    /// file replaces the previous version (see [`crate::sign`]).
+    pub signature: Option<String>,
+    /// Overwrite the file instead of renaming a new one over it,
+    /// for files bind-mounted one at a time into a container.
+    pub in_place: bool,
+}
+
+impl FileAttrs {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub const fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    #[must_use]
+    pub fn owner(mut self, owner: &str) -> Self {
+        self.owner = Some(owner.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn signature(mut self, signature: &str) -> Self {
+        self.signature = Some(signature.to_string());
+        self
+    }
+
+    /// Keep the file's inode: a container bind-mounting the file
+    /// itself (e.g. the Caddyfile) keeps seeing the old inode
+    /// after a rename, so such files are overwritten instead.
+    #[must_use]
+    pub const fn in_place(mut self) -> Self {
+        self.in_place = true;
+        self
+    }
+}
+
+/// Remote command putting the temporary file `tmp` in place of
+/// `remote_path`, once it matches its signature, with `attrs`.
+///
+/// The temporary file is renamed over the target, or with
+/// [`FileAttrs::in_place`] copied into it and removed, so the
+/// target keeps its inode.
+#[must_use]
+pub fn replace_command(tmp: &str, remote_path: &str, attrs: &FileAttrs) -> String {
+    let quoted_tmp = shell_quote(tmp);
+    let quoted_path = shell_quote(remote_path);
+    let mut steps = Vec::new();
+    if attrs.signature.is_some() {
+        let signature = format!("{tmp}{}", sign::SIGNATURE_SUFFIX);
+        steps.push(sign::verify_command(tmp, &signature));
+        steps.push(format!("rm -f {}", shell_quote(&signature)));
+    }
+    // In place, the target gets the attributes after the copy
+    let target = if attrs.in_place {
+        &quoted_path
+    } else {
+        &quoted_tmp
+    };
+    if attrs.in_place {
+        steps.push(format!("cat {quoted_tmp} > {quoted_path}"));
+        steps.push(format!("rm -f {quoted_tmp}"));
+    }
+    if let Some(mode) = attrs.mode {
+        steps.push(format!("chmod {mode:o} {target}"));
+    }
+    if let Some(owner) = &attrs.owner {
+        steps.push(format!("chown {} {target}", shell_quote(owner)));
+    }
+    if !attrs.in_place {
+        steps.push(format!("mv -f {quoted_tmp} {quoted_path}"));
+    }
+    steps.join(" && ")
+}
+
+/// SSH session wrapper for executing commands and transferring
+/// files to a remote host.
+///
+/// With the default `native-ssh` feature, commands and file
+/// transfers run over an in-process SSH client (russh + SFTP).
+/// The connection is opened on first use and reused for every
+/// later call on the same session. Host aliases, ports, and
+/// identity files are resolved through `~/.ssh/config`, and the
+/// SSH agent is used when no explicit key matches.
+///
+/// Without the feature, the session shells out to the OpenSSH
+/// `ssh` and `scp` binaries instead.
+pub struct SshSession {
+    host: String,
+    user: String,
+    keys: Vec<String>,
+    options: SshOptions,
+    #[cfg(feature = "native-ssh")]
+    conn: native::Connection,
+}
+
+impl SshSession {
+    #[must_use]
+    pub fn new(host: &str, user: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            user: user.to_string(),
+            keys: Vec::new(),
+            options: SshOptions::default(),
+            #[cfg(feature = "native-ssh")]
+            conn: native::Connection::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_key(mut self, key_path: &str) -> Self {
+        self.keys.push(key_path.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_keys(mut self, key_paths: &[String]) -> Self {
+        self.keys.extend_from_slice(key_paths);
+        self
+    }
+
+    /// Apply shared connection settings (jump host, ...).
+    #[must_use]
+    pub fn with_options(mut self, options: &SshOptions) -> Self {
+        self.options = options.clone();
+        self
+    }
+
+    /// Reach the host through a bastion.
+    #[must_use]
+    pub fn via_jump(mut self, host: &str, user: &str, key: Option<&str>) -> Self {
+        self.options = self.options.jump_host(host, user, key);
+        self
+    }
+
+    #[must_use]
+    pub fn port(mut self, port: u16) -> Self {
+        self.options = self.options.port(port);
+        self
+    }
+
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options = self.options.connect_timeout(timeout);
+        self
+    }
+
+    /// Number of attempts and delay used by
+    /// [`Self::wait_until_ready`].
+    #[must_use]
+    pub fn retries(mut self, count: u32, interval: Duration) -> Self {
+        self.options = self.options.retries(count, interval);
+        self
+    }
+
+    #[must_use]
+    pub fn keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.options = self.options.keepalive(interval);
+        self
+    }
+
+    #[must_use]
+    pub fn command_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options = self.options.command_timeout(timeout);
+        self
+    }
+
+    /// The remote host this session connects to.
+    #[must_use]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The remote user this session authenticates as.
+    #[must_use]
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// `user@host` as passed to `ssh`, `scp`, and `rsync`.
+    #[must_use]
+    pub fn destination(&self) -> String {
+        format!("{}@{}", self.user, self.host)
+    }
+
+    /// `ssh://user@host[:port]`, the `DOCKER_HOST` reaching the
+    /// host's Docker daemon over SSH.
+    #[must_use]
+    pub fn docker_host(&self) -> String {
+        let port = self
+            .options
+            .port
+            .filter(|&p| p != 22)
+            .map(|p| format!(":{p}"))
+            .unwrap_or_default();
+        format!("ssh://{}{port}", self.destination())
+    }
+
+    /// OpenSSH command line (without destination) for tools that
+    /// take a remote shell, such as `rsync -e`.
+    ///
+    /// Arguments containing spaces are single-quoted.
+    #[must_use]
+    pub fn ssh_command(&self) -> String {
+        let mut parts = vec!["ssh".to_string()];
+        for arg in self.openssh_args() {
+            if arg.contains(' ') {
+                parts.push(format!("'{arg}'"));
+            } else {
+                parts.push(arg);
+            }
+        }
+        parts.join(" ")
+    }
+
+    /// The `UserKnownHostsFile` value: the pinned keys, where new
+    /// keys go, then the user's own `known_hosts`, which is left
+    /// out when trusting a new key so that a stale entry there
+    /// cannot reject it.
+    fn known_hosts_files(&self) -> Option<String> {
+        let pins = pinned_hosts_file()?;
+        match std::env::var("HOME") {
+            Ok(home) if !self.options.trust_new_host_key => {
+                Some(format!("{} {home}/.ssh/known_hosts", pins.display()))
+            }
+            _ => Some(pins.display().to_string()),
+        }
+    }
+
+    /// Options passed to the OpenSSH binaries before the
+    /// destination.
+    fn openssh_args(&self) -> Vec<String> {
+        let checking = if self.options.trust_new_host_key {
+            "accept-new"
+        } else {
+            "yes"
+        };
+        let mut args = vec![
+            "-o".to_string(),
+            format!("StrictHostKeyChecking={checking}"),
+        ];
+        let known_hosts = self.known_hosts_files();
+        if let Some(known_hosts) = &known_hosts {
+            args.push("-o".to_string());
+            args.push(format!("UserKnownHostsFile={known_hosts}"));
+        }
+        args.extend([
+            "-o".to_string(),
+            format!("ConnectTimeout={}", self.options.connect_timeout.as_secs()),
+        ]);
+        // `-o Port=` works for ssh and scp alike, unlike -p/-P.
+        if let Some(port) = self.options.port {
+            args.push("-o".to_string());
+            args.push(format!("Port={port}"));
+        }
+        if let Some(interval) = self.options.keepalive {
+            args.push("-o".to_string());
+            args.push(format!("ServerAliveInterval={}", interval.as_secs()));
+        }
+        for key in &self.keys {
+            args.push("-i".to_string());
+            args.push(key.clone());
+        }
+        if let Some(jump) = &self.options.jump {
+            args.push("-o".to_string());
+            args.push(jump_arg(jump, checking, known_hosts.as_deref()));
+        }
+        args
+    }
+
+    /// Remove stale host key entries from `known_hosts` and the
+    /// pinned host keys.
+    ///
+    /// This prevents "host key mismatch" errors when a server
+    /// is reprovisioned at the same IP address. The next
+    /// connection pins the new server's key.
+    pub fn clear_known_host(host: &str) {
+        let Ok(home) = std::env::var("HOME") else {
+            return;
+        };
+        let known_hosts = PathBuf::from(&home).join(".ssh").join("known_hosts");
+        remove_host_entry(&known_hosts, host);
+        if let Some(pins) = pinned_hosts_file() {
+            remove_host_entry(&pins, host);
+        }
+    }
+
+    /// Drop the pinned key for this session's host and port so
+    /// the next connection pins whatever key the server presents.
+    ///
+    /// Backs `--trust-new-hostkey`; only use it when the server
+    /// is known to have been rebuilt.
+    pub fn forget_host_key(&self) {
+        self.options.transport.forget_host_key(self);
+    }
+
+    /// Connect to the host, pinning its key when none is pinned
+    /// yet and [`SshOptions::trust_new_host_key`] is set, and pin
+    /// the same key under `aliases`, such as the server's domain,
+    /// so that connections made through them are not rejected.
+    pub fn pin_host_key(&self, aliases: &[&str]) -> DeployResult<()> {
+        self.exec("true")?;
+        self.options.transport.alias_host_key(self, aliases)
+    }
+
+    /// Whether a host key is pinned when none is yet (see
+    /// [`SshOptions::trust_new_host_key`]).
+    #[must_use]
+    pub const fn trusts_new_host_key(&self) -> bool {
+        self.options.trust_new_host_key
+    }
+
+    /// Execute a command on the remote host and capture output.
+    ///
+    /// Fails with [`DeployError::Timeout`] when the command runs
+    /// longer than the configured `command_timeout`.
+    pub fn exec(&self, command: &str) -> DeployResult<String> {
+        self.options.transport.exec(self, command)
+    }
+
+    /// Execute a command on the remote host interactively.
+    pub fn exec_interactive(&self, command: &str) -> DeployResult<()> {
+        self.options.transport.exec_interactive(self, command)
+    }
+
+    /// Run `command` on a terminal of the remote host attached to
+    /// the local one, e.g. `virsh console`.
+    ///
+    /// Goes through the system `ssh -t` with either backend, since
+    /// it hands the local terminal over.
+    pub fn terminal(&self, command: &str) -> DeployResult<()> {
+        self.options.transport.terminal(self, command)
+    }
+
+    /// Copy a local file to the remote host.
+    pub fn scp_to(&self, local_path: &str, remote_path: &str) -> DeployResult<()> {
+        self.scp_to_with_progress(local_path, remote_path, &mut |_, _| {})
+    }
+
+    /// Copy a local file to the remote host, calling `progress`
+    /// with the bytes sent so far and the file size.
+    ///
+    /// The native client reports as the copy advances; the
+    /// OpenSSH backend only once `scp` has finished.
+    pub fn scp_to_with_progress(
+        &self,
+        local_path: &str,
+        remote_path: &str,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> DeployResult<()> {
+        self.options
+            .transport
+            .scp_to(self, local_path, remote_path, progress)
+    }
+
+    /// Write content to a remote file atomically.
+    ///
+    /// See [`Self::write_remote_file_with`].
+    pub fn write_remote_file(&self, content: &str, remote_path: &str) -> DeployResult<()> {
+        self.write_remote_file_with(content, remote_path, &FileAttrs::default())
+    }
+
+    /// Write content to a remote file atomically, applying `attrs`.
+    ///
+    /// The content goes to a temporary file next to `remote_path`
+    /// (over SFTP with the native client), which gets its mode and
+    /// owner set and is then renamed over the target. Readers never
+    /// see a partially written file, and a failed write leaves the
+    /// previous version in place. A file with a signature is
+    /// only renamed once it matches it, with its signature written
+    /// next to the temporary file.
+    ///
+    /// With [`FileAttrs::in_place`], the complete temporary file
+    /// is copied into the target instead, see
+    /// [`replace_command`].
+    pub fn write_remote_file_with(
+        &self,
+        content: &str,
+        remote_path: &str,
+        attrs: &FileAttrs,
+    ) -> DeployResult<()> {
+        self.options
+            .transport
+            .write_remote_file(self, content, remote_path, attrs)
+    }
+
+    /// Run the `local` pipe and feed its output into
+    /// `remote_command` on the remote host, like
+    /// `local | ssh host remote`, without staging data on disk.
+    pub fn pipe_from_local(&self, local: &cmd::Pipe, remote_command: &str) -> DeployResult<()> {
+        self.options
+            .transport
+            .pipe_from_local(self, local, remote_command)
+    }
+
+    /// Forward a local port to a host and port reachable from the
+    /// remote server (like `ssh -L`).
+    ///
+    /// Blocks until the process is interrupted. Only binds to
+    /// `127.0.0.1`.
+    pub fn forward(&self, fwd: &tunnel::PortForward) -> DeployResult<()> {
+        self.options.transport.forward(self, fwd)
+    }
+
+    /// Wait for SSH to become available on the remote host.
+    pub fn wait_for_ready(&self, max_attempts: u32, interval: Duration) -> DeployResult<()> {
+        for attempt in 1..=max_attempts {
+            eprint!(
+                "Waiting for SSH \
+                 ({attempt}/{max_attempts})... "
+            );
+            if self.exec("echo ok").is_ok() {
+                eprintln!("connected");
+                return Ok(());
+            }
+            eprintln!("retrying");
+            thread::sleep(interval);
+        }
+
+        Err(DeployError::SshFailed(format!(
+            "SSH not ready after {max_attempts} attempts \
+             on {}",
+            self.host
+        )))
+    }
+
+    /// Wait for SSH using the session's configured retry count
+    /// and interval.
+    pub fn wait_until_ready(&self) -> DeployResult<()> {
+        self.wait_for_ready(self.options.retries, self.options.retry_interval)
+    }
+}
+
+/// File holding the host keys pinned by catapulta
+/// (`~/.ssh/catapulta_known_hosts`).
+///
+/// Kept apart from `~/.ssh/known_hosts` so that keys recorded at
+/// provision time are not silently replaced by other tools.
+/// Returns `None` when `HOME` is unset.
+#[must_use]
+pub fn pinned_hosts_file() -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(
+        PathBuf::from(home)
+            .join(".ssh")
+            .join("catapulta_known_hosts"),
+    )
+}
+
+/// SSH config `content` with the files of its `Include` lines
+/// inlined, for parsers that don't follow them.
+///
+/// Relative paths are resolved against `ssh_dir`, as OpenSSH
+/// does for the user config, and a `*` in the file name matches
+/// like a shell glob, in sorted order. Missing files are
+/// skipped.
+#[must_use]
+pub fn inline_ssh_includes(content: &str, ssh_dir: &Path) -> String {
+    inline_includes(content, ssh_dir, 0)
+}
+
+/// OpenSSH's limit on nested `Include`s.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+fn inline_includes(content: &str, ssh_dir: &Path, depth: usize) -> String {
+    let mut out = String::new();
+    for line in content.lines() {
+        let mut words = line.split_whitespace();
+        let is_include = words
+            .next()
+            .is_some_and(|w| w.eq_ignore_ascii_case("include"));
+        if !is_include || depth >= MAX_INCLUDE_DEPTH {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        for pattern in words {
+            for file in include_files(pattern, ssh_dir) {
+                if let Ok(included) = std::fs::read_to_string(&file) {
+                    out.push_str(&inline_includes(&included, ssh_dir, depth + 1));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn include_files(pattern: &str, ssh_dir: &Path) -> Vec<PathBuf> {
+    let path = match (pattern.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => ssh_dir.join(pattern),
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let Some((prefix, suffix)) = name.split_once('*') else {
+        return vec![path];
+    };
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    files.sort();
+    files
+}
+
+fn remove_host_entry(file: &Path, host: &str) {
+    if !file.exists() {
+        return;
+    }
+    let path = file.to_string_lossy().to_string();
+    let _ = cmd::run("ssh-keygen", &["-R", host, "-f", &path]);
+}
+
+/// Quote `s` for a POSIX shell, so paths with spaces or quotes
+/// survive the remote shell.
+#[must_use]
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Build a remote command that runs `script` with `bash -c`,
+/// passing `args` as `$1`, `$2`, ...
+///
+/// The script and every argument are quoted separately, so values
+/// such as domains never become part of the script text.
+#[must_use]
+pub fn bash_script_command(script: &str, args: &[&str]) -> String {
+    let mut command = format!("bash -c {} _", shell_quote(script));
+    for arg in args {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+    command
+}
+
+/// `ProxyCommand` reaching the destination through `jump`, whose
+/// host key is checked like the destination's: with `checking`
+/// as `StrictHostKeyChecking`, against the `known_hosts` files.
+///
+/// Used rather than `-J`, which takes neither a key nor the
+/// options given on the command line.
+fn jump_arg(jump: &JumpHost, checking: &str, known_hosts: Option<&str>) -> String {
+    let mut command = "ProxyCommand=ssh".to_string();
+    if let Some(key) = &jump.key {
+        let _ = write!(command, " -i {key}");
+    }
+    let _ = write!(command, " -o StrictHostKeyChecking={checking}");
+    if let Some(known_hosts) = known_hosts {
+        let _ = write!(command, " -o \"UserKnownHostsFile={known_hosts}\"");
+    }
+    let _ = write!(command, " -W %h:%p {}@{}", jump.user, jump.host);
+    command
+}
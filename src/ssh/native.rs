@@ -0,0 +1,786 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use russh::client::Msg;
+use russh::client::{self, Handle};
+use russh::keys::agent::client::AgentClient;
+use russh::keys::known_hosts;
+use russh::keys::{HashAlg, PrivateKeyWithHashAlg, PublicKeyOrCertificate, load_secret_key};
+use russh::{Channel, ChannelMsg, ChannelReadHalf, Disconnect};
+use russh_sftp::client::SftpSession;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+use crate::audit;
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::tunnel::PortForward;
+use crate::ssh::{SshOptions, SshSession, SshTransport, inline_ssh_includes, pinned_hosts_file};
+
+/// Identity files tried when neither explicit keys nor
+/// `~/.ssh/config` provide one, in the same order as OpenSSH.
+const DEFAULT_IDENTITIES: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Lines of standard error kept when a remote command fails.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Lazily established in-process SSH connection.
+///
+/// The first operation connects and authenticates; later
+/// operations reuse the same transport and open a new channel
+/// each time.
+pub struct Connection {
+    runtime: Runtime,
+    state: Mutex<Option<Established>>,
+}
+
+/// An authenticated session, plus the bastion session it is
+/// tunnelled through (kept alive for as long as the target is).
+struct Established {
+    target: Arc<Handle<Client>>,
+    jump: Option<Handle<Client>>,
+}
+
+/// Output of a remote command run over an exec channel.
+struct ExecOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_status: u32,
+}
+
+/// Client-side event handler.
+///
+/// Host keys must match the key pinned in [`pinned_hosts_file`]
+/// or recorded in `~/.ssh/known_hosts`. A host with neither is
+/// pinned only with [`SshOptions::trust_new_host_key`], and
+/// rejected otherwise; `~/.ssh/known_hosts` is not consulted
+/// then. Rejections are flagged in `mismatch` and
+/// `unknown` so the caller can report
+/// [`DeployError::HostKeyMismatch`] or
+/// [`DeployError::HostKeyUnknown`].
+struct Client {
+    host: String,
+    port: u16,
+    trust_new: bool,
+    mismatch: Arc<AtomicBool>,
+    unknown: Arc<AtomicBool>,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKeyOrCertificate,
+    ) -> Result<bool, Self::Error> {
+        let PublicKeyOrCertificate::PublicKey { key, .. } = server_public_key else {
+            return Ok(false);
+        };
+        // A stale `~/.ssh/known_hosts` entry must not stand in the
+        // way of trusting a rebuilt server's key
+        let pins = pinned_hosts_file();
+        let checked = match &pins {
+            Some(path) => known_hosts::check_known_hosts_path(&self.host, self.port, key, path)
+                .and_then(|pinned| {
+                    Ok(pinned
+                        || (!self.trust_new
+                            && known_hosts::check_known_hosts(&self.host, self.port, key)?))
+                }),
+            None => known_hosts::check_known_hosts(&self.host, self.port, key),
+        };
+        match checked {
+            Ok(true) => Ok(true),
+            Ok(false) if !self.trust_new => {
+                eprintln!(
+                    "Host key for {} is not pinned (server presented {})",
+                    self.host,
+                    key.fingerprint(HashAlg::Sha256)
+                );
+                self.unknown.store(true, Ordering::Relaxed);
+                Ok(false)
+            }
+            Ok(false) => {
+                let learned = match &pins {
+                    Some(path) => {
+                        known_hosts::learn_known_hosts_path(&self.host, self.port, key, path)
+                    }
+                    None => known_hosts::learn_known_hosts(&self.host, self.port, key),
+                };
+                match learned {
+                    Ok(()) => eprintln!(
+                        "Pinned host key for {}: {}",
+                        self.host,
+                        key.fingerprint(HashAlg::Sha256)
+                    ),
+                    Err(e) => {
+                        eprintln!("Warning: could not record host key for {}: {e}", self.host);
+                    }
+                }
+                Ok(true)
+            }
+            Err(russh::keys::Error::KeyChanged { .. }) => {
+                eprintln!(
+                    "Host key for {} changed (now {})",
+                    self.host,
+                    key.fingerprint(HashAlg::Sha256)
+                );
+                self.mismatch.store(true, Ordering::Relaxed);
+                Ok(false)
+            }
+            Err(e) => {
+                eprintln!("Host key verification failed for {}: {e}", self.host);
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build tokio runtime");
+        Self {
+            runtime,
+            state: Mutex::new(None),
+        }
+    }
+}
+
+impl Connection {
+    /// Return the cached session handle, connecting first when
+    /// there is none (or the previous one was closed).
+    fn handle(&self, session: &SshSession) -> DeployResult<Arc<Handle<Client>>> {
+        let mut guard = self
+            .state
+            .lock()
+            .map_err(|_| DeployError::SshFailed("SSH connection lock poisoned".into()))?;
+        let reconnecting = match guard.as_ref() {
+            Some(established) if !established.target.is_closed() => {
+                return Ok(Arc::clone(&established.target));
+            }
+            Some(_) => true,
+            None => false,
+        };
+        // A dropped connection mid-deploy is retried; a first
+        // connection that fails is left to the caller (e.g.
+        // `wait_for_ready`, which has its own retry loop).
+        let attempts = if reconnecting { cmd::RETRY_ATTEMPTS } else { 1 };
+        let established = cmd::retry(attempts, cmd::RETRY_BACKOFF, || {
+            self.runtime.block_on(connect(session))
+        })?;
+        let handle = Arc::clone(&established.target);
+        *guard = Some(established);
+        drop(guard);
+        Ok(handle)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().ok().and_then(Option::take);
+        if let Some(established) = state {
+            self.runtime.block_on(async {
+                let _ = established
+                    .target
+                    .disconnect(Disconnect::ByApplication, "", "en")
+                    .await;
+                if let Some(jump) = established.jump {
+                    let _ = jump.disconnect(Disconnect::ByApplication, "", "en").await;
+                }
+            });
+        }
+    }
+}
+
+/// Transport over the native client.
+#[derive(Debug)]
+pub struct Native;
+
+impl SshTransport for Native {
+    fn exec(&self, session: &SshSession, command: &str) -> DeployResult<String> {
+        exec(session, command)
+    }
+
+    fn exec_interactive(&self, session: &SshSession, command: &str) -> DeployResult<()> {
+        exec_interactive(session, command)
+    }
+
+    fn scp_to(
+        &self,
+        session: &SshSession,
+        local_path: &str,
+        remote_path: &str,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> DeployResult<()> {
+        scp_to(session, local_path, remote_path, progress)
+    }
+
+    fn write_file(
+        &self,
+        session: &SshSession,
+        content: &str,
+        remote_path: &str,
+    ) -> DeployResult<()> {
+        write_remote_file(session, content, remote_path)
+    }
+
+    fn pipe_from_local(
+        &self,
+        session: &SshSession,
+        local: &cmd::Pipe,
+        remote_command: &str,
+    ) -> DeployResult<()> {
+        pipe_from_local(session, local, remote_command)
+    }
+
+    fn forward(&self, session: &SshSession, fwd: &PortForward) -> DeployResult<()> {
+        forward(session, fwd)
+    }
+}
+
+/// Execute a command on the remote host and capture its trimmed
+/// stdout. Fails if the command exits non-zero.
+pub fn exec(session: &SshSession, command: &str) -> DeployResult<String> {
+    let destination = session.destination();
+    audit::record("ssh", &[&destination, command], || {
+        let conn = &session.conn;
+        let handle = conn.handle(session)?;
+        let output =
+            conn.runtime.block_on(async {
+                let run = run_channel(&handle, command, false);
+                match session.options.command_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, run).await.map_err(|_| {
+                        DeployError::Timeout {
+                            command: command.to_string(),
+                            timeout,
+                        }
+                    })?,
+                    None => run.await,
+                }
+            })?;
+
+        if output.exit_status == 0 {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            Err(DeployError::RemoteCommandFailed {
+                command: command.to_string(),
+                exit_status: output.exit_status,
+                stderr: stderr_tail(&output.stderr),
+            })
+        }
+    })
+}
+
+/// Execute a command on the remote host, streaming its output to
+/// the local terminal.
+pub fn exec_interactive(session: &SshSession, command: &str) -> DeployResult<()> {
+    let destination = session.destination();
+    audit::record("ssh", &[&destination, command], || {
+        let conn = &session.conn;
+        let handle = conn.handle(session)?;
+        let output = conn.runtime.block_on(run_channel(&handle, command, true))?;
+
+        if output.exit_status == 0 {
+            Ok(())
+        } else {
+            Err(DeployError::RemoteCommandFailed {
+                command: command.to_string(),
+                exit_status: output.exit_status,
+                stderr: String::new(),
+            })
+        }
+    })
+}
+
+/// Upload a local file to the remote host over SFTP.
+pub fn scp_to(
+    session: &SshSession,
+    local_path: &str,
+    remote_path: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> DeployResult<()> {
+    let destination = session.destination();
+    audit::record(
+        "sftp",
+        &["put", local_path, &format!("{destination}:{remote_path}")],
+        || {
+            if !Path::new(local_path).exists() {
+                return Err(DeployError::FileNotFound(local_path.to_string()));
+            }
+            let conn = &session.conn;
+            let handle = conn.handle(session)?;
+            conn.runtime.block_on(async {
+                let sftp = open_sftp(&handle).await?;
+                let mut local = tokio::fs::File::open(local_path).await?;
+                let total = local.metadata().await?.len();
+                let mut remote = sftp.create(remote_path).await.map_err(|e| sftp_error(&e))?;
+                let mut buf = vec![0; 256 * 1024];
+                let mut sent = 0;
+                loop {
+                    let n = local.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    remote.write_all(&buf[..n]).await?;
+                    sent += n as u64;
+                    progress(sent, total);
+                }
+                remote.shutdown().await?;
+                sftp.close().await.map_err(|e| sftp_error(&e))
+            })
+        },
+    )
+}
+
+/// Write content to a remote file over SFTP.
+pub fn write_remote_file(
+    session: &SshSession,
+    content: &str,
+    remote_path: &str,
+) -> DeployResult<()> {
+    let destination = session.destination();
+    audit::record(
+        "sftp",
+        &["write", &format!("{destination}:{remote_path}")],
+        || {
+            let conn = &session.conn;
+            let handle = conn.handle(session)?;
+            conn.runtime.block_on(async {
+                let sftp = open_sftp(&handle).await?;
+                let mut file = sftp.create(remote_path).await.map_err(|e| sftp_error(&e))?;
+                file.write_all(content.as_bytes()).await?;
+                file.shutdown().await?;
+                sftp.close().await.map_err(|e| sftp_error(&e))
+            })
+        },
+    )
+}
+
+/// Run `local_command` through `sh -c` and pipe its stdout into
+/// `remote_command`'s stdin, streaming the remote output.
+pub fn pipe_from_local(
+    session: &SshSession,
+    local: &cmd::Pipe,
+    remote_command: &str,
+) -> DeployResult<()> {
+    let destination = session.destination();
+    let local_command = local.to_string();
+    audit::record(
+        "ssh",
+        &[&destination, remote_command, "<", &local_command],
+        || {
+            let conn = &session.conn;
+            let handle = conn.handle(session)?;
+            conn.runtime.block_on(async {
+                let mut children = local.spawn(Stdio::piped())?;
+                let stdout = children.last_mut().and_then(|c| c.stdout.take());
+                let Some(stdout) = stdout else {
+                    return Err(DeployError::Other("local command has no stdout".into()));
+                };
+                let stdout = tokio::process::ChildStdout::from_std(stdout)?;
+
+                let channel = open_exec(&handle, remote_command).await?;
+                let (mut read, write) = channel.split();
+                let upload = async {
+                    write
+                        .data(stdout)
+                        .await
+                        .map_err(|e| DeployError::SshFailed(e.to_string()))?;
+                    write
+                        .eof()
+                        .await
+                        .map_err(|e| DeployError::SshFailed(e.to_string()))
+                };
+                let (uploaded, output) =
+                    tokio::join!(upload, collect_output(&mut read, remote_command, true));
+                local.wait(children)?;
+                uploaded?;
+                let output = output?;
+                if output.exit_status == 0 {
+                    Ok(())
+                } else {
+                    Err(DeployError::RemoteCommandFailed {
+                        command: remote_command.to_string(),
+                        exit_status: output.exit_status,
+                        stderr: String::new(),
+                    })
+                }
+            })
+        },
+    )
+}
+
+/// Listen on `127.0.0.1:<local_port>` and forward every accepted
+/// connection through a `direct-tcpip` channel. Runs until the
+/// process is interrupted or the listener fails.
+pub fn forward(session: &SshSession, fwd: &PortForward) -> DeployResult<()> {
+    let conn = &session.conn;
+    let handle = conn.handle(session)?;
+    conn.runtime.block_on(async {
+        let listener = TcpListener::bind(("127.0.0.1", fwd.local_port)).await?;
+        loop {
+            let (mut socket, peer) = listener.accept().await?;
+            let channel = match handle
+                .channel_open_direct_tcpip(
+                    fwd.remote_host.as_str(),
+                    u32::from(fwd.remote_port),
+                    peer.ip().to_string(),
+                    u32::from(peer.port()),
+                )
+                .await
+            {
+                Ok(channel) => channel,
+                Err(e) => {
+                    eprintln!(
+                        "Could not open tunnel to {}:{}: {e}",
+                        fwd.remote_host, fwd.remote_port
+                    );
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                let mut stream = channel.into_stream();
+                let _ = tokio::io::copy_bidirectional(&mut socket, &mut stream).await;
+            });
+        }
+    })
+}
+
+/// Connect and authenticate, resolving the host alias, port, and
+/// identity files through `~/.ssh/config` like OpenSSH does.
+///
+/// When a jump host is configured, the bastion is connected
+/// first and the target session runs over a `direct-tcpip`
+/// channel opened from it.
+async fn connect(session: &SshSession) -> DeployResult<Established> {
+    let options = &session.options;
+    let target = resolve(&session.host, options.port);
+    let Some(jump) = &options.jump else {
+        let stream = tcp_connect(&target.hostname, target.port, options).await?;
+        let handle = handshake(stream, &target, &session.user, &session.keys, options).await?;
+        return Ok(Established {
+            target: Arc::new(handle),
+            jump: None,
+        });
+    };
+
+    let bastion = resolve(&jump.host, None);
+    let stream = tcp_connect(&bastion.hostname, bastion.port, options).await?;
+    let jump_keys: Vec<String> = jump.key.iter().cloned().collect();
+    let jump_handle = handshake(stream, &bastion, &jump.user, &jump_keys, options).await?;
+
+    let channel = jump_handle
+        .channel_open_direct_tcpip(
+            target.hostname.as_str(),
+            u32::from(target.port),
+            "127.0.0.1",
+            0,
+        )
+        .await
+        .map_err(|e| {
+            DeployError::SshFailed(format!(
+                "jump host {} could not reach {}:{}: {e}",
+                jump.host, target.hostname, target.port
+            ))
+        })?;
+    let handle = handshake(
+        channel.into_stream(),
+        &target,
+        &session.user,
+        &session.keys,
+        options,
+    )
+    .await?;
+
+    Ok(Established {
+        target: Arc::new(handle),
+        jump: Some(jump_handle),
+    })
+}
+
+/// A host alias resolved through `~/.ssh/config`.
+struct Resolved {
+    hostname: String,
+    port: u16,
+    host_config: russh_config::HostConfig,
+}
+
+/// `port` overrides the port from `~/.ssh/config`.
+fn resolve(host: &str, port: Option<u16>) -> Resolved {
+    let config = read_ssh_config()
+        .and_then(|content| russh_config::parse(&content, host).ok())
+        .unwrap_or_else(|| russh_config::Config::default(host));
+    Resolved {
+        hostname: config.host().to_string(),
+        port: port.unwrap_or_else(|| config.port()),
+        host_config: config.host_config,
+    }
+}
+
+/// `~/.ssh/config` with its `Include` files inlined, which
+/// `russh_config` doesn't follow.
+fn read_ssh_config() -> Option<String> {
+    let ssh_dir = PathBuf::from(std::env::var("HOME").ok()?).join(".ssh");
+    let content = std::fs::read_to_string(ssh_dir.join("config")).ok()?;
+    Some(inline_ssh_includes(&content, &ssh_dir))
+}
+
+async fn tcp_connect(hostname: &str, port: u16, options: &SshOptions) -> DeployResult<TcpStream> {
+    let timeout = options.connect_timeout;
+    tokio::time::timeout(timeout, TcpStream::connect((hostname, port)))
+        .await
+        .map_err(|_| {
+            DeployError::SshFailed(format!(
+                "connection to {hostname}:{port} timed out after {}s",
+                timeout.as_secs()
+            ))
+        })?
+        .map_err(|e| DeployError::SshFailed(format!("{hostname}:{port}: {e}")))
+}
+
+/// Run the SSH handshake over `stream` and authenticate as
+/// `user`.
+async fn handshake<S>(
+    stream: S,
+    target: &Resolved,
+    user: &str,
+    keys: &[String],
+    options: &SshOptions,
+) -> DeployResult<Handle<Client>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let config = Arc::new(client::Config {
+        keepalive_interval: options.keepalive,
+        ..client::Config::default()
+    });
+    let mismatch = Arc::new(AtomicBool::new(false));
+    let unknown = Arc::new(AtomicBool::new(false));
+    let handler = Client {
+        host: target.hostname.clone(),
+        port: target.port,
+        trust_new: options.trust_new_host_key,
+        mismatch: Arc::clone(&mismatch),
+        unknown: Arc::clone(&unknown),
+    };
+
+    let connecting = client::connect_stream(config, stream, handler);
+    let mut handle = tokio::time::timeout(options.connect_timeout, connecting)
+        .await
+        .map_err(|_| {
+            DeployError::SshFailed(format!(
+                "SSH handshake with {} timed out after {}s",
+                target.hostname,
+                options.connect_timeout.as_secs()
+            ))
+        })?
+        .map_err(|e| {
+            if mismatch.load(Ordering::Relaxed) {
+                DeployError::HostKeyMismatch {
+                    host: target.hostname.clone(),
+                }
+            } else if unknown.load(Ordering::Relaxed) {
+                DeployError::HostKeyUnknown {
+                    host: target.hostname.clone(),
+                }
+            } else {
+                DeployError::SshFailed(format!("{}: {e}", target.hostname))
+            }
+        })?;
+
+    if authenticate(&mut handle, user, keys, &target.host_config).await? {
+        Ok(handle)
+    } else {
+        Err(DeployError::SshFailed(format!(
+            "authentication failed for {user}@{}",
+            target.hostname
+        )))
+    }
+}
+
+/// Try explicit keys, then the SSH agent, then identity files
+/// from `~/.ssh/config` and the OpenSSH defaults.
+async fn authenticate(
+    session: &mut Handle<Client>,
+    user: &str,
+    keys: &[String],
+    host_config: &russh_config::HostConfig,
+) -> DeployResult<bool> {
+    let hash_alg = session
+        .best_supported_rsa_hash()
+        .await
+        .map_err(|e| DeployError::SshFailed(e.to_string()))?
+        .flatten();
+
+    for key in keys.iter().map(|k| expand_tilde(k)) {
+        if try_key_file(session, user, &key, hash_alg).await? {
+            return Ok(true);
+        }
+    }
+
+    if let Ok(mut agent) = AgentClient::connect_env().await {
+        let identities = agent.request_identities().await.unwrap_or_default();
+        for identity in identities {
+            let public = identity.public_key().into_owned();
+            let result = session
+                .authenticate_publickey_with(user, public, hash_alg, &mut agent)
+                .await;
+            if result.is_ok_and(|r| r.success()) {
+                return Ok(true);
+            }
+        }
+    }
+
+    let home = std::env::var("HOME").map(PathBuf::from).unwrap_or_default();
+    let fallback: Vec<PathBuf> = host_config.identity_file.clone().unwrap_or_else(|| {
+        DEFAULT_IDENTITIES
+            .iter()
+            .map(|name| home.join(".ssh").join(name))
+            .collect()
+    });
+    for key in fallback {
+        if key.exists() && try_key_file(session, user, &key, hash_alg).await? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+async fn try_key_file(
+    session: &mut Handle<Client>,
+    user: &str,
+    path: &Path,
+    hash_alg: Option<russh::keys::HashAlg>,
+) -> DeployResult<bool> {
+    let key = match load_secret_key(path, None) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Skipping SSH key {}: {e}", path.display());
+            return Ok(false);
+        }
+    };
+    let result = session
+        .authenticate_publickey(user, PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+        .await
+        .map_err(|e| DeployError::SshFailed(e.to_string()))?;
+    Ok(result.success())
+}
+
+/// Open an exec channel, run `command`, and collect its output.
+///
+/// When `stream` is set, output is forwarded to the local
+/// stdout/stderr as it arrives instead of being buffered.
+async fn run_channel(
+    handle: &Handle<Client>,
+    command: &str,
+    stream: bool,
+) -> DeployResult<ExecOutput> {
+    let channel = open_exec(handle, command).await?;
+    let (mut read, _write) = channel.split();
+    collect_output(&mut read, command, stream).await
+}
+
+async fn open_exec(handle: &Handle<Client>, command: &str) -> DeployResult<Channel<Msg>> {
+    let channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| DeployError::SshFailed(e.to_string()))?;
+    channel
+        .exec(true, command)
+        .await
+        .map_err(|e| DeployError::SshFailed(e.to_string()))?;
+    Ok(channel)
+}
+
+/// The last [`STDERR_TAIL_LINES`] lines of a command's captured
+/// standard error, kept in [`DeployError::RemoteCommandFailed`].
+fn stderr_tail(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr.trim().lines().collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+/// Read channel messages until the remote command exits,
+/// buffering or (when `stream` is set) printing its output.
+async fn collect_output(
+    read: &mut ChannelReadHalf,
+    command: &str,
+    stream: bool,
+) -> DeployResult<ExecOutput> {
+    let mut output = ExecOutput {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        exit_status: 0,
+    };
+    let mut exit_status = None;
+
+    while let Some(msg) = read.wait().await {
+        match msg {
+            ChannelMsg::Data { ref data } => {
+                if stream {
+                    let mut out = std::io::stdout();
+                    out.write_all(data)?;
+                    out.flush()?;
+                } else {
+                    output.stdout.extend_from_slice(data);
+                }
+            }
+            ChannelMsg::ExtendedData { ref data, ext: 1 } => {
+                if stream {
+                    std::io::stderr().write_all(data)?;
+                } else {
+                    output.stderr.extend_from_slice(data);
+                }
+            }
+            ChannelMsg::ExitStatus { exit_status: code } => {
+                exit_status = Some(code);
+            }
+            ChannelMsg::ExitSignal { signal_name, .. } => {
+                return Err(DeployError::SshFailed(format!(
+                    "remote command killed by signal {signal_name:?}: {command}"
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    output.exit_status = exit_status.ok_or_else(|| {
+        DeployError::SshFailed(format!(
+            "connection closed before command exited: {command}"
+        ))
+    })?;
+    Ok(output)
+}
+
+async fn open_sftp(handle: &Handle<Client>) -> DeployResult<SftpSession> {
+    let channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| DeployError::SshFailed(e.to_string()))?;
+    channel
+        .request_subsystem(true, "sftp")
+        .await
+        .map_err(|e| DeployError::SshFailed(e.to_string()))?;
+    SftpSession::new(channel.into_stream())
+        .await
+        .map_err(|e| sftp_error(&e))
+}
+
+fn sftp_error(e: &russh_sftp::client::error::Error) -> DeployError {
+    DeployError::SshFailed(format!("SFTP: {e}"))
+}
+
+/// Expand a leading `~/` to `$HOME`, as OpenSSH does for `-i`.
+fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
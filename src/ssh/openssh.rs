@@ -0,0 +1,137 @@
+//! Fallback backend that shells out to the OpenSSH `ssh` and
+//! `scp` binaries. Used when the `native-ssh` feature is
+//! disabled.
+
+use crate::cmd;
+use crate::error::DeployResult;
+use crate::ssh::tunnel::PortForward;
+use crate::ssh::{SshSession, SshTransport, shell_quote};
+
+/// Transport over the OpenSSH `ssh` and `scp` binaries.
+#[derive(Debug)]
+pub struct OpenSsh;
+
+impl SshTransport for OpenSsh {
+    fn exec(&self, session: &SshSession, command: &str) -> DeployResult<String> {
+        exec(session, command)
+    }
+
+    fn exec_interactive(&self, session: &SshSession, command: &str) -> DeployResult<()> {
+        exec_interactive(session, command)
+    }
+
+    fn scp_to(
+        &self,
+        session: &SshSession,
+        local_path: &str,
+        remote_path: &str,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> DeployResult<()> {
+        scp_to(session, local_path, remote_path, progress)
+    }
+
+    fn write_file(
+        &self,
+        session: &SshSession,
+        content: &str,
+        remote_path: &str,
+    ) -> DeployResult<()> {
+        write_remote_file(session, content, remote_path)
+    }
+
+    fn pipe_from_local(
+        &self,
+        session: &SshSession,
+        local: &cmd::Pipe,
+        remote_command: &str,
+    ) -> DeployResult<()> {
+        pipe_from_local(session, local, remote_command)
+    }
+
+    fn forward(&self, session: &SshSession, fwd: &PortForward) -> DeployResult<()> {
+        forward(session, fwd)
+    }
+}
+
+/// Execute a command on the remote host and capture output.
+pub fn exec(session: &SshSession, command: &str) -> DeployResult<String> {
+    let args = build_ssh_args(session, command);
+    let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    session.options.command_timeout.map_or_else(
+        || cmd::run("ssh", &refs),
+        |timeout| cmd::run_with_timeout("ssh", &refs, timeout),
+    )
+}
+
+/// Execute a command on the remote host interactively.
+pub fn exec_interactive(session: &SshSession, command: &str) -> DeployResult<()> {
+    let args = build_ssh_args(session, command);
+    let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    cmd::run_interactive("ssh", &refs)
+}
+
+/// Copy a local file to the remote host.
+pub fn scp_to(
+    session: &SshSession,
+    local_path: &str,
+    remote_path: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> DeployResult<()> {
+    let mut args = session.openssh_args();
+    let dest = format!("{}:{remote_path}", session.destination());
+    args.push(local_path.to_string());
+    args.push(dest);
+
+    let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    cmd::run_interactive("scp", &refs)?;
+    let size = std::fs::metadata(local_path).map_or(0, |m| m.len());
+    progress(size, size);
+    Ok(())
+}
+
+/// Write content to a remote file via stdin pipe.
+pub fn write_remote_file(
+    session: &SshSession,
+    content: &str,
+    remote_path: &str,
+) -> DeployResult<()> {
+    let command = format!("cat > {}", shell_quote(remote_path));
+    let args = build_ssh_args(session, &command);
+    let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    cmd::run_with_stdin("ssh", &refs, content.as_bytes())?;
+    Ok(())
+}
+
+/// Pipe a local command's stdout into a remote command through
+/// the system `ssh`.
+pub fn pipe_from_local(
+    session: &SshSession,
+    local: &cmd::Pipe,
+    remote_command: &str,
+) -> DeployResult<()> {
+    let args = build_ssh_args(session, remote_command);
+    let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    local.clone().then("ssh", &refs).run()
+}
+
+/// Forward a local port with `ssh -N -L` until interrupted.
+pub fn forward(session: &SshSession, fwd: &PortForward) -> DeployResult<()> {
+    let mut args = session.openssh_args();
+    args.extend([
+        "-o".to_string(),
+        "ExitOnForwardFailure=yes".to_string(),
+        "-N".to_string(),
+        "-L".to_string(),
+        format!("127.0.0.1:{}", fwd.to_spec()),
+        session.destination(),
+    ]);
+    let refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    cmd::run_interactive("ssh", &refs)
+}
+
+fn build_ssh_args(session: &SshSession, command: &str) -> Vec<String> {
+    let mut args = session.openssh_args();
+    args.push(session.destination());
+    args.push(command.to_string());
+    args
+}
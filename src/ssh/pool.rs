@@ -0,0 +1,144 @@
+//! Run the same command on several hosts at once.
+
+use std::io::Write;
+use std::thread;
+
+use crate::error::{DeployError, DeployResult};
+use crate::ssh::{SshOptions, SshSession};
+
+/// Output of a command on one host of an [`SshPool`].
+#[derive(Debug)]
+pub struct HostOutput {
+    pub host: String,
+    pub result: DeployResult<String>,
+}
+
+/// A set of SSH sessions that commands are fanned out to
+/// concurrently, one thread per host.
+///
+/// Sessions are kept for the lifetime of the pool, so with the
+/// native client each host's connection is reused across calls.
+///
+/// # Example
+///
+/// ```no_run
+/// use catapulta::ssh::SshOptions;
+/// use catapulta::ssh::pool::SshPool;
+///
+/// let pool = SshPool::from_hosts(&["web1", "web2"], "root", &SshOptions::default());
+/// pool.exec_prefixed("docker compose ps")?;
+/// # Ok::<(), catapulta::error::DeployError>(())
+/// ```
+pub struct SshPool {
+    sessions: Vec<SshSession>,
+}
+
+impl SshPool {
+    #[must_use]
+    pub const fn new(sessions: Vec<SshSession>) -> Self {
+        Self { sessions }
+    }
+
+    /// Open one session per host with the same user and options.
+    #[must_use]
+    pub fn from_hosts(hosts: &[&str], user: &str, options: &SshOptions) -> Self {
+        let sessions = hosts
+            .iter()
+            .map(|host| SshSession::new(host, user).with_options(options))
+            .collect();
+        Self::new(sessions)
+    }
+
+    /// Hosts in the pool, in insertion order.
+    #[must_use]
+    pub fn hosts(&self) -> Vec<&str> {
+        self.sessions.iter().map(SshSession::host).collect()
+    }
+
+    #[must_use]
+    pub fn sessions(&self) -> &[SshSession] {
+        &self.sessions
+    }
+
+    /// Run `command` on every host concurrently and collect the
+    /// captured output, in the same order as [`Self::hosts`].
+    #[must_use]
+    pub fn exec(&self, command: &str) -> Vec<HostOutput> {
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .sessions
+                .iter()
+                .map(|ssh| scope.spawn(move || ssh.exec(command)))
+                .collect();
+            self.sessions
+                .iter()
+                .zip(handles)
+                .map(|(ssh, handle)| HostOutput {
+                    host: ssh.host().to_string(),
+                    result: handle.join().unwrap_or_else(|_| {
+                        Err(DeployError::Other(format!(
+                            "command thread for {} panicked",
+                            ssh.host()
+                        )))
+                    }),
+                })
+                .collect()
+        })
+    }
+
+    /// Run `command` on every host concurrently and print each
+    /// host's output as it finishes, every line prefixed with
+    /// `[host]`.
+    ///
+    /// Fails if the command failed on any host, after all hosts
+    /// have finished.
+    pub fn exec_prefixed(&self, command: &str) -> DeployResult<()> {
+        let width = self.hosts().iter().map(|h| h.len()).max().unwrap_or(0);
+        let failed: Vec<String> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .sessions
+                .iter()
+                .map(|ssh| {
+                    scope.spawn(move || {
+                        let result = ssh.exec(command);
+                        print_prefixed(ssh.host(), width, &result);
+                        result.is_ok()
+                    })
+                })
+                .collect();
+            self.sessions
+                .iter()
+                .zip(handles)
+                .filter_map(|(ssh, handle)| {
+                    (!matches!(handle.join(), Ok(true))).then(|| ssh.host().to_string())
+                })
+                .collect()
+        });
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(DeployError::Other(format!(
+                "command failed on {} of {} hosts ({}): {command}",
+                failed.len(),
+                self.sessions.len(),
+                failed.join(", ")
+            )))
+        }
+    }
+}
+
+/// Print a host's output in one block so lines from different
+/// hosts never interleave.
+fn print_prefixed(host: &str, width: usize, result: &DeployResult<String>) {
+    let prefix = format!("[{host:<width$}]");
+    match result {
+        Ok(output) => {
+            let mut out = std::io::stdout().lock();
+            for line in output.lines() {
+                let _ = writeln!(out, "{prefix} {line}");
+            }
+        }
+        Err(e) => eprintln!("{prefix} error: {e}"),
+    }
+}
@@ -0,0 +1,76 @@
+//! Local port forwarding (`ssh -L`) specifications.
+
+use crate::error::{DeployError, DeployResult};
+
+/// A local port forwarded to a host and port reachable from the
+/// remote server.
+///
+/// # Example
+///
+/// ```
+/// use catapulta::ssh::tunnel::PortForward;
+///
+/// let fwd = PortForward::parse("15432:localhost:5432").unwrap();
+///
+/// assert_eq!(fwd.local_port, 15432);
+/// assert_eq!(fwd.remote_host, "localhost");
+/// assert_eq!(fwd.remote_port, 5432);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortForward {
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+impl PortForward {
+    #[must_use]
+    pub fn new(local_port: u16, remote_host: &str, remote_port: u16) -> Self {
+        Self {
+            local_port,
+            remote_host: remote_host.to_string(),
+            remote_port,
+        }
+    }
+
+    /// Parse a forward in `ssh -L` notation.
+    ///
+    /// Accepts `LOCAL:HOST:REMOTE`, `LOCAL:REMOTE` (host
+    /// `localhost`), and a bare `PORT` (same port on both ends).
+    pub fn parse(spec: &str) -> DeployResult<Self> {
+        let invalid = || {
+            DeployError::Other(format!(
+                "invalid tunnel '{spec}': expected \
+                 LOCAL_PORT:HOST:REMOTE_PORT"
+            ))
+        };
+        let port = |s: &str| {
+            s.parse::<u16>()
+                .ok()
+                .filter(|&p| p != 0)
+                .ok_or_else(invalid)
+        };
+
+        let parts: Vec<&str> = spec.split(':').collect();
+        match parts.as_slice() {
+            [p] => {
+                let p = port(p)?;
+                Ok(Self::new(p, "localhost", p))
+            }
+            [local, remote] => Ok(Self::new(port(local)?, "localhost", port(remote)?)),
+            [local, host, remote] if !host.is_empty() => {
+                Ok(Self::new(port(local)?, host, port(remote)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The forward in `ssh -L` notation.
+    #[must_use]
+    pub fn to_spec(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.local_port, self.remote_host, self.remote_port
+        )
+    }
+}
@@ -0,0 +1,503 @@
+//! Servers provisioned, DNS records set, and deployments made.
+//!
+//! `provision` records each server's IP and domain, and the DNS
+//! records it points at the server; `destroy` removes them again.
+//! `import` records a server set up without catapulta the same
+//! way, after checking it is reachable and runs Docker.
+//! `deploy` records each deployment with the images it shipped.
+//! Commands taking a host (`deploy`, `status`, `rollback`, ...)
+//! accept the server name used at provision time and connect to
+//! the recorded domain, or IP when the server has no domain.
+//!
+//! The state lives in a [`StateStore`]: by default
+//! [`LocalState`], `servers.json` under
+//! [`crate::Pipeline::local_dir`]. [`S3State`] keeps it in an S3
+//! bucket instead, so a team shares one view of its servers
+//! (see [`crate::Pipeline::state_store`]).
+//!
+//! Beside the state, the store keeps an append-only audit trail
+//! of the operations changing servers: each `provision`,
+//! `import`, `deploy` (which ships the env files holding the
+//! secrets), `rollback` and `destroy`, with when, by whom, on
+//! what, and whether it succeeded. `cargo xtask audit` prints
+//! it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cmd;
+use crate::error::{DeployError, DeployResult};
+use crate::scan::ScanReport;
+
+/// Name of the state file in the pipeline's local directory.
+pub const STATE_FILE: &str = "servers.json";
+
+/// Number of deployments kept in the state, oldest dropped
+/// first.
+pub const MAX_DEPLOYMENTS: usize = 100;
+
+/// Name of the audit trail file, one JSON entry per line, beside
+/// [`STATE_FILE`].
+pub const TRAIL_FILE: &str = "audit-trail.jsonl";
+
+/// Environment variable naming the user in the audit trail,
+/// e.g. the account of a CI job.
+pub const USER_ENV: &str = "CATAPULTA_USER";
+
+/// A provisioned server, as recorded in the state file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerRecord {
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+}
+
+impl ServerRecord {
+    /// The host to connect to: the domain, or the IP without
+    /// one.
+    #[must_use]
+    pub fn host(&self) -> &str {
+        self.domain.as_deref().unwrap_or(&self.ip)
+    }
+}
+
+/// A DNS record set by `provision`, or adopted by `import`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsRecord {
+    /// Name of the server the record points at.
+    pub server: String,
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<String>,
+}
+
+/// A deploy to one host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentRecord {
+    pub host: String,
+    /// Unix time the deploy finished at, in seconds.
+    pub at: u64,
+    pub success: bool,
+    /// Image of each deployed app: the prebuilt [`crate::App::image`],
+    /// or `name:latest` for an image built locally.
+    pub images: BTreeMap<String, String>,
+    /// Git commit of the local checkout, when deploying from
+    /// one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// User who ran the deploy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Findings of the scan of each image built for the deploy,
+    /// by tag (see [`crate::scan`]).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scans: BTreeMap<String, ScanReport>,
+}
+
+impl DeploymentRecord {
+    /// A deploy to `host` finishing now, by the current user
+    /// from the current Git commit.
+    #[must_use]
+    pub fn now(host: &str, success: bool, images: BTreeMap<String, String>) -> Self {
+        Self {
+            host: host.to_string(),
+            at: unix_now(),
+            success,
+            images,
+            revision: git_revision(),
+            user: std::env::var("USER").ok(),
+            scans: BTreeMap::new(),
+        }
+    }
+}
+
+/// An operation in the audit trail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrailEntry {
+    /// Unix time the operation finished at, in seconds.
+    pub at: u64,
+    /// Who ran it (see [`current_user`]).
+    pub user: String,
+    /// The command, e.g. `destroy`.
+    pub action: String,
+    /// The server name or hosts it was run on.
+    pub target: String,
+    pub success: bool,
+    /// What else it changed, e.g. the env files a deploy sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl TrailEntry {
+    /// `action` on `target` finishing now, by the current user.
+    #[must_use]
+    pub fn now(action: &str, target: &str, success: bool) -> Self {
+        Self {
+            at: unix_now(),
+            user: current_user(),
+            action: action.to_string(),
+            target: target.to_string(),
+            success,
+            detail: None,
+        }
+    }
+
+    #[must_use]
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+impl fmt::Display for TrailEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = if self.success { "ok" } else { "FAILED" };
+        write!(
+            f,
+            "{}  {}  {} {}  {outcome}",
+            format_utc(self.at),
+            self.user,
+            self.action,
+            self.target
+        )?;
+        if let Some(detail) = &self.detail {
+            write!(f, "  ({detail})")?;
+        }
+        Ok(())
+    }
+}
+
+/// The user running catapulta: [`USER_ENV`], else the Git
+/// `user.email`, else the login name.
+#[must_use]
+pub fn current_user() -> String {
+    std::env::var(USER_ENV)
+        .ok()
+        .filter(|user| !user.is_empty())
+        .or_else(git_user)
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn git_user() -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "user.email"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let user = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !user.is_empty()).then_some(user)
+}
+
+/// `secs` since the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`.
+#[must_use]
+pub fn format_utc(secs: u64) -> String {
+    let (days, time) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01, shifted to start
+    // the year in March so leap days come last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Parse the JSON lines of an audit trail.
+fn parse_trail(content: &str) -> DeployResult<Vec<TrailEntry>> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// The commit checked out in the working directory, if it is in
+/// a Git repository.
+pub(crate) fn git_revision() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let revision = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !revision.is_empty()).then_some(revision)
+}
+
+/// Servers by name, DNS records by domain, and deployments,
+/// oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub servers: BTreeMap<String, ServerRecord>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dns_records: BTreeMap<String, DnsRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deployments: Vec<DeploymentRecord>,
+}
+
+impl State {
+    /// Read the state file in `dir`. A missing file is an empty
+    /// state.
+    pub fn load(dir: &Path) -> DeployResult<Self> {
+        let path = dir.join(STATE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Write the state file in `dir`, creating `dir` if needed.
+    pub fn save(&self, dir: &Path) -> DeployResult<()> {
+        std::fs::create_dir_all(dir)?;
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(dir.join(STATE_FILE), content + "\n")?;
+        Ok(())
+    }
+
+    /// The host recorded for the server `name`, if any.
+    #[must_use]
+    pub fn host(&self, name: &str) -> Option<&str> {
+        self.servers.get(name).map(ServerRecord::host)
+    }
+
+    /// Record `deployment`, dropping the oldest beyond
+    /// [`MAX_DEPLOYMENTS`].
+    pub fn record_deployment(&mut self, deployment: DeploymentRecord) {
+        self.deployments.push(deployment);
+        let excess = self.deployments.len().saturating_sub(MAX_DEPLOYMENTS);
+        self.deployments.drain(..excess);
+    }
+
+    /// The images `app` was successfully deployed with, newest
+    /// first, each once.
+    #[must_use]
+    pub fn image_history(&self, app: &str) -> Vec<&str> {
+        let mut images: Vec<&str> = Vec::new();
+        for deployment in self.deployments.iter().rev().filter(|d| d.success) {
+            if let Some(image) = deployment.images.get(app) {
+                if !images.contains(&image.as_str()) {
+                    images.push(image);
+                }
+            }
+        }
+        images
+    }
+}
+
+/// Where the [`State`] is kept.
+///
+/// Commands load the state, change it, and save it back, so two
+/// runs changing it at the same time may lose one of the
+/// changes.
+pub trait StateStore {
+    /// The location of the state, for messages.
+    fn location(&self) -> String;
+
+    /// Read the state. A store without one yet has an empty
+    /// state.
+    fn load(&self) -> DeployResult<State>;
+
+    /// Replace the stored state with `state`.
+    fn save(&self, state: &State) -> DeployResult<()>;
+
+    /// Add `entry` at the end of the audit trail.
+    fn append_trail(&self, entry: &TrailEntry) -> DeployResult<()>;
+
+    /// Read the audit trail, oldest first. A store without one
+    /// yet has an empty trail.
+    fn load_trail(&self) -> DeployResult<Vec<TrailEntry>>;
+}
+
+/// State in [`STATE_FILE`], and the audit trail in
+/// [`TRAIL_FILE`], in a local directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalState {
+    pub dir: PathBuf,
+}
+
+impl LocalState {
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl StateStore for LocalState {
+    fn location(&self) -> String {
+        self.dir.join(STATE_FILE).display().to_string()
+    }
+
+    fn load(&self) -> DeployResult<State> {
+        State::load(&self.dir)
+    }
+
+    fn save(&self, state: &State) -> DeployResult<()> {
+        state.save(&self.dir)
+    }
+
+    fn append_trail(&self, entry: &TrailEntry) -> DeployResult<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(TRAIL_FILE))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    fn load_trail(&self) -> DeployResult<Vec<TrailEntry>> {
+        let path = self.dir.join(TRAIL_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        parse_trail(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// How long a transfer of the state to or from S3 may take.
+const S3_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// State in an object of an S3 bucket, through the `aws` CLI.
+///
+/// Credentials and region come from the usual AWS configuration
+/// (`AWS_PROFILE`, `AWS_ACCESS_KEY_ID`, `~/.aws/config`, ...).
+/// Other S3-compatible stores (Scaleway, `MinIO`, ...) work with
+/// [`S3State::endpoint`].
+///
+/// The audit trail is kept in [`TRAIL_FILE`] next to the state
+/// object. S3 objects can't be appended to, so each entry
+/// rewrites the whole trail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3State {
+    pub bucket: String,
+    /// Key of the state object, e.g. `catapulta/servers.json`.
+    pub key: String,
+    /// Endpoint URL of an S3-compatible store.
+    pub endpoint: Option<String>,
+}
+
+impl S3State {
+    #[must_use]
+    pub fn new(bucket: &str, key: &str) -> Self {
+        Self {
+            bucket: bucket.to_string(),
+            key: key.trim_start_matches('/').to_string(),
+            endpoint: None,
+        }
+    }
+
+    /// Use an S3-compatible store at `url` instead of AWS.
+    #[must_use]
+    pub fn endpoint(mut self, url: &str) -> Self {
+        self.endpoint = Some(url.to_string());
+        self
+    }
+
+    /// The `s3://bucket/key` URL of the state object.
+    #[must_use]
+    pub fn url(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.key)
+    }
+
+    /// The URL of the audit trail object, [`TRAIL_FILE`] next to
+    /// the state object.
+    #[must_use]
+    pub fn trail_url(&self) -> String {
+        match self.key.rsplit_once('/') {
+            Some((dir, _)) => format!("s3://{}/{dir}/{TRAIL_FILE}", self.bucket),
+            None => format!("s3://{}/{TRAIL_FILE}", self.bucket),
+        }
+    }
+
+    /// The content of the object at `url`, empty when it doesn't
+    /// exist.
+    fn read(&self, url: &str) -> DeployResult<String> {
+        let result = cmd::run_classified("aws", &self.copy_args(url, "-"), S3_TIMEOUT, |stderr| {
+            is_missing_object(stderr).then(|| DeployError::FileNotFound(url.to_string()))
+        });
+        match result {
+            Err(DeployError::FileNotFound(_)) => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    /// Arguments of `aws s3 cp` from `from` to `to`.
+    #[must_use]
+    pub fn copy_args<'a>(&'a self, from: &'a str, to: &'a str) -> Vec<&'a str> {
+        let mut args = vec!["s3", "cp", "--only-show-errors", from, to];
+        if let Some(endpoint) = &self.endpoint {
+            args.extend(["--endpoint-url", endpoint]);
+        }
+        args
+    }
+}
+
+impl StateStore for S3State {
+    fn location(&self) -> String {
+        self.url()
+    }
+
+    fn load(&self) -> DeployResult<State> {
+        let content = self.read(&self.url())?;
+        if content.trim().is_empty() {
+            return Ok(State::default());
+        }
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save(&self, state: &State) -> DeployResult<()> {
+        let url = self.url();
+        let content = serde_json::to_string_pretty(state)? + "\n";
+        cmd::run_with_stdin("aws", &self.copy_args("-", &url), content.as_bytes())?;
+        Ok(())
+    }
+
+    fn append_trail(&self, entry: &TrailEntry) -> DeployResult<()> {
+        let url = self.trail_url();
+        let mut content = self.read(&url)?;
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content += &(serde_json::to_string(entry)? + "\n");
+        cmd::run_with_stdin("aws", &self.copy_args("-", &url), content.as_bytes())?;
+        Ok(())
+    }
+
+    fn load_trail(&self) -> DeployResult<Vec<TrailEntry>> {
+        parse_trail(&self.read(&self.trail_url())?)
+    }
+}
+
+/// Whether `aws s3 cp` failed with `stderr` because the object
+/// doesn't exist.
+#[must_use]
+pub fn is_missing_object(stderr: &str) -> bool {
+    stderr.contains("(404)") || stderr.contains("NoSuchKey") || stderr.contains("Not Found")
+}
@@ -0,0 +1,127 @@
+//! Container resource usage shown by `cargo xtask top`.
+//!
+//! On a small server one container growing its memory or
+//! spinning a CPU starves the others. [`gather`] reads a
+//! snapshot of `docker stats` (or `podman stats`) for every
+//! running container on the host, the other stacks and the
+//! shared Caddy included, since they all compete for the same
+//! capacity.
+
+use crate::deploy::Runtime;
+use crate::error::DeployResult;
+use crate::ssh::SshSession;
+
+/// Resource usage of one container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerStats {
+    /// Container name.
+    pub name: String,
+    /// CPU use in percent of one CPU, so above 100 on several.
+    pub cpu_percent: f64,
+    /// Memory in use in bytes.
+    pub memory: u64,
+    /// Memory limit in bytes, the host's memory when unlimited.
+    pub memory_limit: u64,
+}
+
+/// Remote command printing the stats parsed by [`parse`], with
+/// the `runtime` engine.
+#[must_use]
+pub fn command(runtime: Runtime) -> String {
+    format!(
+        "{} stats --no-stream --format '{{{{.Name}}}}\\t{{{{.CPUPerc}}}}\\t{{{{.MemUsage}}}}'",
+        runtime.cli()
+    )
+}
+
+/// Parse the output of [`command`], busiest container first.
+/// Lines that can't be read are left out.
+#[must_use]
+pub fn parse(output: &str) -> Vec<ContainerStats> {
+    let mut stats: Vec<ContainerStats> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.trim();
+            let cpu = fields.next()?.trim().trim_end_matches('%');
+            let (memory, limit) = fields.next()?.split_once('/')?;
+            Some(ContainerStats {
+                name: name.to_string(),
+                cpu_percent: cpu.parse().ok()?,
+                memory: parse_size(memory)?,
+                memory_limit: parse_size(limit)?,
+            })
+        })
+        .collect();
+    stats.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent));
+    stats
+}
+
+/// Bytes in a size as printed by Docker, e.g. `12.5MiB` or
+/// `1.2GB`.
+#[must_use]
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let unit_start = size.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = size.split_at(unit_start);
+    let factor: f64 = match unit {
+        "B" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    let bytes = number.trim().parse::<f64>().ok()? * factor;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Some(bytes.round() as u64)
+}
+
+/// Gather the stats of the containers on the host behind `ssh`,
+/// run by the `runtime` engine.
+pub fn gather(ssh: &SshSession, runtime: Runtime) -> DeployResult<Vec<ContainerStats>> {
+    ssh.exec(&command(runtime)).map(|output| parse(&output))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn human(bytes: u64) -> String {
+    let mib = bytes as f64 / (1024.0 * 1024.0);
+    if mib < 1024.0 {
+        format!("{mib:.1} MiB")
+    } else {
+        format!("{:.1} GiB", mib / 1024.0)
+    }
+}
+
+/// A table of `stats`, one line per container, then the total.
+#[must_use]
+pub fn render(stats: &[ContainerStats]) -> Vec<String> {
+    let width = stats
+        .iter()
+        .map(|s| s.name.len())
+        .chain(["CONTAINER".len()])
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![format!("{:width$}  {:>7}  MEMORY", "CONTAINER", "CPU")];
+    for s in stats {
+        lines.push(format!(
+            "{:width$}  {:>6.1}%  {} / {}",
+            s.name,
+            s.cpu_percent,
+            human(s.memory),
+            human(s.memory_limit)
+        ));
+    }
+    let cpu: f64 = stats.iter().map(|s| s.cpu_percent).sum();
+    let memory: u64 = stats.iter().map(|s| s.memory).sum();
+    lines.push(format!(
+        "{:width$}  {cpu:>6.1}%  {}",
+        "total",
+        human(memory)
+    ));
+    lines
+}
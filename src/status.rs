@@ -0,0 +1,214 @@
+//! Host metrics shown by `cargo xtask status`.
+//!
+//! Container status alone hides the most common failure mode, a
+//! full disk. [`gather`] reads disk usage of `/` and the Docker
+//! data root, memory, load and Docker's own disk usage in a
+//! single SSH round trip. Any section that can't be read is left
+//! out.
+
+use crate::deploy::preflight::{self, DiskFree};
+use crate::error::DeployResult;
+use crate::ssh::SshSession;
+
+/// Disk usage at or above which `status` prints a warning.
+pub const DISK_WARN_PERCENT: u64 = 90;
+
+/// Memory of the host, from `/proc/meminfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memory {
+    /// Total memory in bytes.
+    pub total: u64,
+    /// Memory available to new processes in bytes.
+    pub available: u64,
+}
+
+/// One line of `docker system df`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DockerUsage {
+    /// Kind of object (`Images`, `Containers`, ...).
+    pub kind: String,
+    /// Size as printed by Docker (e.g. `4.1GB`).
+    pub size: String,
+    /// Reclaimable size as printed by Docker (e.g. `2.3GB (56%)`).
+    pub reclaimable: String,
+}
+
+/// Metrics of one host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerMetrics {
+    /// 1, 5 and 15 minute load averages.
+    pub load: Option<[f64; 3]>,
+    /// Number of CPUs.
+    pub cpus: Option<u32>,
+    /// Host memory.
+    pub memory: Option<Memory>,
+    /// Filesystems holding `/` and the Docker data root, without
+    /// duplicates.
+    pub disks: Vec<DiskFree>,
+    /// Docker disk usage per kind of object.
+    pub docker: Vec<DockerUsage>,
+}
+
+/// Remote command printing every section parsed by [`parse`].
+#[must_use]
+pub fn command() -> String {
+    [
+        "echo '### df'",
+        "df -Pk / \"$(docker info -f '{{.DockerRootDir}}' 2>/dev/null \
+         || echo /var/lib/docker)\" 2>/dev/null",
+        "echo '### meminfo'",
+        "cat /proc/meminfo 2>/dev/null",
+        "echo '### loadavg'",
+        "cat /proc/loadavg 2>/dev/null",
+        "echo '### nproc'",
+        "nproc 2>/dev/null",
+        "echo '### docker'",
+        "docker system df --format '{{.Type}}\\t{{.Size}}\\t{{.Reclaimable}}' 2>/dev/null",
+        "true",
+    ]
+    .join("; ")
+}
+
+/// Parse the output of [`command`].
+#[must_use]
+pub fn parse(output: &str) -> ServerMetrics {
+    let mut metrics = ServerMetrics::default();
+    for section in output.split("### ").skip(1) {
+        let (name, body) = section.split_once('\n').unwrap_or((section, ""));
+        match name.trim() {
+            "df" => {
+                for disk in preflight::parse_df(body) {
+                    if !metrics.disks.iter().any(|d| d.mount == disk.mount) {
+                        metrics.disks.push(disk);
+                    }
+                }
+            }
+            "meminfo" => metrics.memory = parse_meminfo(body),
+            "loadavg" => metrics.load = parse_loadavg(body),
+            "nproc" => metrics.cpus = body.trim().parse().ok(),
+            "docker" => metrics.docker = parse_docker_df(body),
+            _ => {}
+        }
+    }
+    metrics
+}
+
+fn parse_meminfo(body: &str) -> Option<Memory> {
+    let field = |key: &str| {
+        body.lines().find_map(|line| {
+            let kb = line.strip_prefix(key)?.trim().trim_end_matches("kB").trim();
+            kb.parse::<u64>().ok().map(|kb| kb * 1024)
+        })
+    };
+    Some(Memory {
+        total: field("MemTotal:")?,
+        available: field("MemAvailable:")?,
+    })
+}
+
+fn parse_loadavg(body: &str) -> Option<[f64; 3]> {
+    let mut fields = body.split_whitespace().map(str::parse::<f64>);
+    Some([
+        fields.next()?.ok()?,
+        fields.next()?.ok()?,
+        fields.next()?.ok()?,
+    ])
+}
+
+fn parse_docker_df(body: &str) -> Vec<DockerUsage> {
+    body.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(DockerUsage {
+                kind: fields.next()?.trim().to_string(),
+                size: fields.next()?.trim().to_string(),
+                reclaimable: fields.next()?.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Gather the metrics of the host behind `ssh`.
+pub fn gather(ssh: &SshSession) -> DeployResult<ServerMetrics> {
+    ssh.exec(&command()).map(|output| parse(&output))
+}
+
+/// Percentage of `used` in `total`, rounded up.
+const fn percent(used: u64, total: u64) -> u64 {
+    if total == 0 {
+        0
+    } else {
+        (used * 100).div_ceil(total)
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+}
+
+impl DiskFree {
+    /// Percentage of the filesystem in use.
+    #[must_use]
+    pub const fn used_percent(&self) -> u64 {
+        percent(self.size.saturating_sub(self.available), self.size)
+    }
+}
+
+impl ServerMetrics {
+    /// Lines describing the host, one per metric.
+    #[must_use]
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some([one, five, fifteen]) = self.load {
+            let cpus = self
+                .cpus
+                .map(|n| format!(" ({n} CPUs)"))
+                .unwrap_or_default();
+            lines.push(format!("load     {one:.2} {five:.2} {fifteen:.2}{cpus}"));
+        }
+        if let Some(memory) = self.memory {
+            let used = memory.total.saturating_sub(memory.available);
+            lines.push(format!(
+                "memory   {} / {} used ({}%)",
+                gib(used),
+                gib(memory.total),
+                percent(used, memory.total)
+            ));
+        }
+        for disk in &self.disks {
+            lines.push(format!(
+                "disk     {}: {} / {} used ({}%)",
+                disk.mount,
+                gib(disk.size.saturating_sub(disk.available)),
+                gib(disk.size),
+                disk.used_percent()
+            ));
+        }
+        for usage in &self.docker {
+            lines.push(format!(
+                "docker   {}: {}, {} reclaimable",
+                usage.kind.to_lowercase(),
+                usage.size,
+                usage.reclaimable
+            ));
+        }
+        lines
+    }
+
+    /// Warnings for filesystems at or above [`DISK_WARN_PERCENT`].
+    #[must_use]
+    pub fn warnings(&self) -> Vec<String> {
+        self.disks
+            .iter()
+            .filter(|disk| disk.used_percent() >= DISK_WARN_PERCENT)
+            .map(|disk| {
+                format!(
+                    "{} is {}% full, free up space (e.g. `docker image prune -a`)",
+                    disk.mount,
+                    disk.used_percent()
+                )
+            })
+            .collect()
+    }
+}
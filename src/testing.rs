@@ -0,0 +1,804 @@
+//! Test doubles for unit-testing pipeline wiring.
+//!
+//! The mocks implement [`Provisioner`], [`DnsProvider`], and
+//! [`Deployer`] without touching a cloud API, [`MemoryState`]
+//! keeps the [`State`] without a file, and [`FakeSsh`]
+//! stands in for every SSH session once attached with
+//! [`SshOptions::transport`]. Each double records its calls behind a
+//! shared handle, so a clone kept by the test still sees the
+//! calls made through the copy moved into the [`crate::Pipeline`].
+//!
+//! # Example
+//!
+//! ```
+//! use catapulta::testing::{FakeSsh, MockDeployer, MockProvisioner};
+//! use catapulta::ssh::SshOptions;
+//! use catapulta::{App, Caddy, Pipeline};
+//!
+//! let deployer = MockDeployer::new();
+//! let ssh = FakeSsh::new();
+//! let dir = std::env::temp_dir().join("catapulta-doc-testing");
+//!
+//! let app = App::new("my-service").expose(3000);
+//! let caddy = Caddy::new().reverse_proxy(app.upstream());
+//! Pipeline::new(app, caddy)
+//!     .provision(MockProvisioner::new())
+//!     .deploy(deployer.clone())
+//!     .ssh_options(SshOptions::new().transport(ssh.clone()))
+//!     .local_dir(dir.to_str().unwrap())
+//!     .run_from(["xtask", "deploy", "203.0.113.10"])?;
+//!
+//! assert!(deployer.calls().contains(&"build_image my-service".to_string()));
+//! assert!(ssh.commands().iter().any(|c| c.contains("docker compose version")));
+//! # Ok::<(), catapulta::error::DeployError>(())
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::app::App;
+use crate::caddy::Caddy;
+use crate::cmd;
+use crate::deploy::Deployer;
+use crate::dns::DnsProvider;
+use crate::error::{DeployError, DeployResult};
+use crate::provision::{ProvisionOverrides, Provisioner, ServerInfo};
+use crate::sign;
+use crate::ssh::tunnel::PortForward;
+use crate::ssh::{FileAttrs, SshOptions, SshSession, SshTransport};
+use crate::state::{State, StateStore, TrailEntry};
+
+/// IP given to servers created by [`MockProvisioner`] unless set
+/// with [`MockProvisioner::ip`] (from TEST-NET-3).
+const MOCK_IP: &str = "203.0.113.10";
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Call log shared between a mock and its clones, with the
+/// methods set to fail.
+#[derive(Debug, Clone, Default)]
+struct Recorder {
+    calls: Arc<Mutex<Vec<String>>>,
+    failing: Vec<String>,
+}
+
+impl Recorder {
+    /// Record `method` called with `args`, failing if the method
+    /// was set to.
+    fn call(&self, method: &str, args: &[&str]) -> DeployResult<()> {
+        let mut line = vec![method];
+        line.extend_from_slice(args);
+        lock(&self.calls).push(line.join(" "));
+        if self.failing.iter().any(|m| m == method) {
+            return Err(DeployError::Other(format!("mock {method} failed")));
+        }
+        Ok(())
+    }
+
+    fn calls(&self) -> Vec<String> {
+        lock(&self.calls).clone()
+    }
+}
+
+/// A [`Provisioner`] keeping servers in memory.
+///
+/// Calls are recorded as `method arg...`, e.g.
+/// `create_server web fra1`.
+#[derive(Debug, Clone, Default)]
+pub struct MockProvisioner {
+    recorder: Recorder,
+    servers: Arc<Mutex<HashMap<String, ServerInfo>>>,
+    ip: Option<String>,
+    ipv6: Option<String>,
+}
+
+impl MockProvisioner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// IP assigned to created servers (default: `203.0.113.10`).
+    #[must_use]
+    pub fn ip(mut self, ip: &str) -> Self {
+        self.ip = Some(ip.to_string());
+        self
+    }
+
+    /// IPv6 address assigned to created servers (default: none).
+    #[must_use]
+    pub fn ipv6(mut self, ipv6: &str) -> Self {
+        self.ipv6 = Some(ipv6.to_string());
+        self
+    }
+
+    /// Start with a server `name` already running at `ip`.
+    #[must_use]
+    pub fn existing(self, name: &str, ip: &str) -> Self {
+        lock(&self.servers).insert(name.to_string(), server(name, ip, "fra1"));
+        self
+    }
+
+    /// Make `method` (e.g. `"setup_server"`) fail after being
+    /// recorded.
+    #[must_use]
+    pub fn fail(mut self, method: &str) -> Self {
+        self.recorder.failing.push(method.to_string());
+        self
+    }
+
+    /// Calls made so far, oldest first.
+    #[must_use]
+    pub fn calls(&self) -> Vec<String> {
+        self.recorder.calls()
+    }
+
+    /// The server named `name`, if created and not destroyed.
+    #[must_use]
+    pub fn server(&self, name: &str) -> Option<ServerInfo> {
+        lock(&self.servers).get(name).cloned()
+    }
+}
+
+impl Provisioner for MockProvisioner {
+    fn check_prerequisites(&self) -> DeployResult<()> {
+        self.recorder.call("check_prerequisites", &[])
+    }
+
+    fn detect_ssh_keys(&self) -> DeployResult<Vec<(String, String)>> {
+        self.recorder.call("detect_ssh_keys", &[])?;
+        Ok(Vec::new())
+    }
+
+    fn default_region(&self) -> &'static str {
+        "fra1"
+    }
+
+    fn create_server(
+        &self,
+        name: &str,
+        region: &str,
+        ssh_key_ids: &[String],
+    ) -> DeployResult<ServerInfo> {
+        self.create_server_with(name, region, ssh_key_ids, &ProvisionOverrides::default())
+    }
+
+    fn create_server_with(
+        &self,
+        name: &str,
+        region: &str,
+        _ssh_key_ids: &[String],
+        overrides: &ProvisionOverrides,
+    ) -> DeployResult<ServerInfo> {
+        let mut args = vec![name.to_string(), region.to_string()];
+        args.extend(overrides.size.iter().map(|s| format!("size={s}")));
+        args.extend(overrides.image.iter().map(|i| format!("image={i}")));
+        args.extend(overrides.ssh_keys.iter().map(|k| format!("ssh_key={k}")));
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.recorder.call("create_server", &args)?;
+        let info = ServerInfo {
+            ipv6: self.ipv6.clone(),
+            ..server(name, self.ip.as_deref().unwrap_or(MOCK_IP), region)
+        };
+        lock(&self.servers).insert(name.to_string(), info.clone());
+        Ok(info)
+    }
+
+    fn setup_server(
+        &self,
+        server: &ServerInfo,
+        domain: Option<&str>,
+        _ssh: &SshOptions,
+    ) -> DeployResult<()> {
+        let mut args = vec![server.name.as_str(), server.ip.as_str()];
+        args.extend(domain);
+        self.recorder.call("setup_server", &args)
+    }
+
+    fn get_server(&self, name: &str) -> DeployResult<Option<ServerInfo>> {
+        self.recorder.call("get_server", &[name])?;
+        Ok(self.server(name))
+    }
+
+    fn destroy_server(&self, name: &str) -> DeployResult<()> {
+        self.recorder.call("destroy_server", &[name])?;
+        lock(&self.servers).remove(name);
+        Ok(())
+    }
+
+    fn destroy_plan(&self, name: &str) -> DeployResult<Vec<String>> {
+        self.recorder.call("destroy_plan", &[name])?;
+        let server = self
+            .server(name)
+            .ok_or_else(|| DeployError::ServerNotFound(name.to_string()))?;
+        Ok(vec![format!("server '{name}' ({})", server.ip)])
+    }
+
+    fn console(&self, name: &str) -> DeployResult<()> {
+        self.recorder.call("console", &[name])
+    }
+
+    fn clean_image_cache(&self) -> DeployResult<()> {
+        self.recorder.call("clean_image_cache", &[])
+    }
+}
+
+fn server(name: &str, ip: &str, region: &str) -> ServerInfo {
+    ServerInfo {
+        name: name.to_string(),
+        ip: ip.to_string(),
+        ipv6: None,
+        region: region.to_string(),
+        ssh_key_ids: Vec::new(),
+        ssh_key_files: Vec::new(),
+    }
+}
+
+/// A [`DnsProvider`] keeping A and AAAA records in memory.
+///
+/// Calls are recorded with the domain they apply to, e.g.
+/// `upsert_a_record example.com 203.0.113.10`. Providers derived
+/// with [`DnsProvider::for_domain`] share the records and call
+/// log of the original.
+#[derive(Debug, Clone)]
+pub struct MockDnsProvider {
+    domain: String,
+    recorder: Recorder,
+    records: Arc<Mutex<HashMap<String, String>>>,
+    aaaa_records: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MockDnsProvider {
+    #[must_use]
+    pub fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            recorder: Recorder::default(),
+            records: Arc::default(),
+            aaaa_records: Arc::default(),
+        }
+    }
+
+    /// Make `method` (e.g. `"upsert_a_record"`) fail after being
+    /// recorded.
+    #[must_use]
+    pub fn fail(mut self, method: &str) -> Self {
+        self.recorder.failing.push(method.to_string());
+        self
+    }
+
+    /// Calls made so far, oldest first.
+    #[must_use]
+    pub fn calls(&self) -> Vec<String> {
+        self.recorder.calls()
+    }
+
+    /// The IP the A record for `domain` points to, if set.
+    #[must_use]
+    pub fn record(&self, domain: &str) -> Option<String> {
+        lock(&self.records).get(domain).cloned()
+    }
+
+    /// The IPv6 address the AAAA record for `domain` points to,
+    /// if set.
+    #[must_use]
+    pub fn aaaa_record(&self, domain: &str) -> Option<String> {
+        lock(&self.aaaa_records).get(domain).cloned()
+    }
+}
+
+impl DnsProvider for MockDnsProvider {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn upsert_a_record(&self, ip: &str) -> DeployResult<()> {
+        self.recorder.call("upsert_a_record", &[&self.domain, ip])?;
+        lock(&self.records).insert(self.domain.clone(), ip.to_string());
+        Ok(())
+    }
+
+    fn delete_a_record(&self) -> DeployResult<()> {
+        self.recorder.call("delete_a_record", &[&self.domain])?;
+        lock(&self.records).remove(&self.domain);
+        Ok(())
+    }
+
+    fn upsert_aaaa_record(&self, ip: &str) -> DeployResult<()> {
+        self.recorder
+            .call("upsert_aaaa_record", &[&self.domain, ip])?;
+        lock(&self.aaaa_records).insert(self.domain.clone(), ip.to_string());
+        Ok(())
+    }
+
+    fn delete_aaaa_record(&self) -> DeployResult<()> {
+        self.recorder.call("delete_aaaa_record", &[&self.domain])?;
+        lock(&self.aaaa_records).remove(&self.domain);
+        Ok(())
+    }
+
+    fn manages_aaaa_records(&self) -> bool {
+        true
+    }
+
+    fn check_access(&self) -> DeployResult<()> {
+        self.recorder.call("check_access", &[&self.domain])
+    }
+
+    fn purge_cache(&self, paths: &[String]) -> DeployResult<()> {
+        let mut args = vec![self.domain.as_str()];
+        args.extend(paths.iter().map(String::as_str));
+        self.recorder.call("purge_cache", &args)
+    }
+
+    fn upsert_caa_records(&self, issuers: &[&str]) -> DeployResult<()> {
+        let mut args = vec![self.domain.as_str()];
+        args.extend(issuers);
+        self.recorder.call("upsert_caa_records", &args)
+    }
+
+    fn delete_caa_records(&self) -> DeployResult<()> {
+        self.recorder.call("delete_caa_records", &[&self.domain])
+    }
+
+    fn for_domain(&self, domain: &str) -> Option<Box<dyn DnsProvider>> {
+        Some(Box::new(Self {
+            domain: domain.to_string(),
+            ..self.clone()
+        }))
+    }
+}
+
+/// A [`Deployer`] that builds, transfers, and deploys nothing.
+///
+/// Calls are recorded as `build_image <app>`,
+/// `transfer_image <app> <host>`, and
+/// `deploy <host> <remote_dir> [<only>...]`.
+#[derive(Debug, Clone, Default)]
+pub struct MockDeployer {
+    recorder: Recorder,
+    unchanged: Vec<String>,
+}
+
+impl MockDeployer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `method` (e.g. `"deploy"`) fail after being recorded.
+    #[must_use]
+    pub fn fail(mut self, method: &str) -> Self {
+        self.recorder.failing.push(method.to_string());
+        self
+    }
+
+    /// Report the image of `app` as already on the remote host,
+    /// so the pipeline skips its transfer.
+    #[must_use]
+    pub fn unchanged(mut self, app: &str) -> Self {
+        self.unchanged.push(app.to_string());
+        self
+    }
+
+    /// Calls made so far, oldest first.
+    #[must_use]
+    pub fn calls(&self) -> Vec<String> {
+        self.recorder.calls()
+    }
+}
+
+impl Deployer for MockDeployer {
+    fn build_image(&self, app: &App) -> DeployResult<()> {
+        self.recorder.call("build_image", &[&app.name])
+    }
+
+    fn transfer_image(&self, app: &App, ssh: &SshSession) -> DeployResult<()> {
+        self.recorder
+            .call("transfer_image", &[&app.name, ssh.host()])
+    }
+
+    fn image_unchanged(&self, app: &App, _ssh: &SshSession) -> bool {
+        self.unchanged.contains(&app.name)
+    }
+
+    fn deploy(
+        &self,
+        ssh: &SshSession,
+        _apps: &[App],
+        _caddy: &Caddy,
+        remote_dir: &str,
+        only: &[String],
+    ) -> DeployResult<()> {
+        let mut args = vec![ssh.host(), remote_dir];
+        args.extend(only.iter().map(String::as_str));
+        self.recorder.call("deploy", &args)
+    }
+
+    fn write_file(
+        &self,
+        ssh: &SshSession,
+        content: &str,
+        path: &str,
+        attrs: FileAttrs,
+    ) -> DeployResult<()> {
+        self.recorder.call("write_file", &[ssh.host(), path])?;
+        ssh.write_remote_file_with(content, path, &attrs)
+    }
+}
+
+/// A [`StateStore`] keeping the state and audit trail in memory,
+/// shared with its clones.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryState {
+    state: Arc<Mutex<State>>,
+    saves: Arc<Mutex<u32>>,
+    trail: Arc<Mutex<Vec<TrailEntry>>>,
+}
+
+impl MemoryState {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The state saved last.
+    #[must_use]
+    pub fn state(&self) -> State {
+        lock(&self.state).clone()
+    }
+
+    /// Number of times the state was saved.
+    #[must_use]
+    pub fn saves(&self) -> u32 {
+        *lock(&self.saves)
+    }
+
+    /// The audit trail, oldest entry first.
+    #[must_use]
+    pub fn trail(&self) -> Vec<TrailEntry> {
+        lock(&self.trail).clone()
+    }
+}
+
+impl StateStore for MemoryState {
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn load(&self) -> DeployResult<State> {
+        Ok(self.state())
+    }
+
+    fn save(&self, state: &State) -> DeployResult<()> {
+        *lock(&self.state) = state.clone();
+        *lock(&self.saves) += 1;
+        Ok(())
+    }
+
+    fn append_trail(&self, entry: &TrailEntry) -> DeployResult<()> {
+        lock(&self.trail).push(entry.clone());
+        Ok(())
+    }
+
+    fn load_trail(&self) -> DeployResult<Vec<TrailEntry>> {
+        Ok(self.trail())
+    }
+}
+
+/// An SSH operation recorded by [`FakeSsh`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SshCall {
+    /// [`SshSession::exec`] or [`SshSession::exec_interactive`].
+    Exec { host: String, command: String },
+    /// [`SshSession::scp_to`].
+    Upload {
+        host: String,
+        local: String,
+        remote: String,
+    },
+    /// [`SshSession::write_remote_file`] and its variants.
+    Write {
+        host: String,
+        path: String,
+        content: String,
+        /// Permission bits set with [`crate::ssh::FileAttrs::mode`].
+        mode: Option<u32>,
+        /// Written with [`crate::ssh::FileAttrs::in_place`].
+        in_place: bool,
+    },
+    /// [`SshSession::pipe_from_local`].
+    Pipe {
+        host: String,
+        local: String,
+        remote: String,
+    },
+    /// [`SshSession::forward`], in `ssh -L` notation.
+    Forward { host: String, spec: String },
+    /// [`SshOptions::clear_known_host`](crate::ssh::SshOptions::clear_known_host).
+    ClearKnownHost { host: String },
+}
+
+/// Canned result for commands containing a pattern.
+#[derive(Debug, Clone)]
+enum Reply {
+    Output(String),
+    Exit(u32),
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    calls: Vec<SshCall>,
+    replies: Vec<(String, Reply)>,
+    /// Hosts with a pinned key, when host keys are checked.
+    pins: Option<HashSet<String>>,
+}
+
+/// In-memory SSH server for every host.
+///
+/// Commands succeed with empty output unless a reply was set up
+/// with [`FakeSsh::respond`] or [`FakeSsh::fail`]; the most
+/// recently added matching reply wins. Uploads and pipes succeed
+/// without reading local files or running local commands.
+///
+/// # Example
+///
+/// ```
+/// use catapulta::ssh::{SshOptions, SshSession};
+/// use catapulta::testing::FakeSsh;
+///
+/// let fake = FakeSsh::new().respond("uname -m", "aarch64");
+/// let ssh = SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+///
+/// assert_eq!(ssh.exec("uname -m")?, "aarch64");
+/// assert_eq!(fake.commands(), ["uname -m"]);
+/// # Ok::<(), catapulta::error::DeployError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FakeSsh {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeSsh {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer commands containing `pattern` with `output`.
+    #[must_use]
+    pub fn respond(self, pattern: &str, output: &str) -> Self {
+        self.reply(pattern, Reply::Output(output.to_string()))
+    }
+
+    /// Fail commands containing `pattern` with `exit_status`.
+    #[must_use]
+    pub fn fail(self, pattern: &str, exit_status: u32) -> Self {
+        self.reply(pattern, Reply::Exit(exit_status))
+    }
+
+    fn reply(self, pattern: &str, reply: Reply) -> Self {
+        lock(&self.state).replies.push((pattern.to_string(), reply));
+        self
+    }
+
+    /// Check host keys: operations on a host without a pinned key
+    /// fail with [`DeployError::HostKeyUnknown`], unless the
+    /// session trusts new host keys, which pins it.
+    #[must_use]
+    pub fn pin_host_keys(self) -> Self {
+        lock(&self.state).pins = Some(HashSet::new());
+        self
+    }
+
+    /// Whether a key is pinned for `host`.
+    #[must_use]
+    pub fn is_pinned(&self, host: &str) -> bool {
+        lock(&self.state)
+            .pins
+            .as_ref()
+            .is_some_and(|pins| pins.contains(host))
+    }
+
+    /// Every operation so far, oldest first.
+    #[must_use]
+    pub fn calls(&self) -> Vec<SshCall> {
+        lock(&self.state).calls.clone()
+    }
+
+    /// Commands run so far, on any host.
+    #[must_use]
+    pub fn commands(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                SshCall::Exec { command, .. } => Some(command),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Content last written to `path`, on any host.
+    #[must_use]
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.calls().into_iter().rev().find_map(|call| match call {
+            SshCall::Write {
+                path: p, content, ..
+            } if p == path => Some(content),
+            _ => None,
+        })
+    }
+
+    /// Permission bits `path` was last written with, on any host.
+    #[must_use]
+    pub fn file_mode(&self, path: &str) -> Option<u32> {
+        self.calls().into_iter().rev().find_map(|call| match call {
+            SshCall::Write { path: p, mode, .. } if p == path => mode,
+            _ => None,
+        })
+    }
+
+    /// Whether `path` was last written in place, on any host.
+    #[must_use]
+    pub fn file_in_place(&self, path: &str) -> Option<bool> {
+        self.calls().into_iter().rev().find_map(|call| match call {
+            SshCall::Write {
+                path: p, in_place, ..
+            } if p == path => Some(in_place),
+            _ => None,
+        })
+    }
+
+    fn push(&self, call: SshCall) {
+        lock(&self.state).calls.push(call);
+    }
+
+    /// Check the host key of the session's host, as a connection
+    /// does.
+    fn connect(&self, session: &SshSession) -> DeployResult<()> {
+        let host = session.host();
+        let pinned = lock(&self.state).pins.as_mut().map(|pins| {
+            pins.contains(host) || (session.trusts_new_host_key() && pins.insert(host.to_string()))
+        });
+        if pinned == Some(false) {
+            return Err(DeployError::HostKeyUnknown {
+                host: host.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn unpin(&self, host: &str) {
+        if let Some(pins) = &mut lock(&self.state).pins {
+            pins.remove(host);
+        }
+    }
+}
+
+impl SshTransport for FakeSsh {
+    fn exec(&self, session: &SshSession, command: &str) -> DeployResult<String> {
+        self.connect(session)?;
+        self.push(SshCall::Exec {
+            host: session.host().to_string(),
+            command: command.to_string(),
+        });
+        let reply = lock(&self.state)
+            .replies
+            .iter()
+            .rev()
+            .find(|(pattern, _)| command.contains(pattern.as_str()))
+            .map(|(_, reply)| reply.clone());
+        match reply {
+            None => Ok(String::new()),
+            Some(Reply::Output(output)) => Ok(output),
+            Some(Reply::Exit(exit_status)) => Err(DeployError::RemoteCommandFailed {
+                command: command.to_string(),
+                exit_status,
+                stderr: String::new(),
+            }),
+        }
+    }
+
+    fn exec_interactive(&self, session: &SshSession, command: &str) -> DeployResult<()> {
+        self.exec(session, command).map(drop)
+    }
+
+    fn terminal(&self, session: &SshSession, command: &str) -> DeployResult<()> {
+        self.exec_interactive(session, command)
+    }
+
+    fn scp_to(
+        &self,
+        session: &SshSession,
+        local_path: &str,
+        remote_path: &str,
+        _progress: &mut dyn FnMut(u64, u64),
+    ) -> DeployResult<()> {
+        self.connect(session)?;
+        self.push(SshCall::Upload {
+            host: session.host().to_string(),
+            local: local_path.to_string(),
+            remote: remote_path.to_string(),
+        });
+        Ok(())
+    }
+
+    fn write_file(
+        &self,
+        session: &SshSession,
+        content: &str,
+        remote_path: &str,
+    ) -> DeployResult<()> {
+        self.write_remote_file(session, content, remote_path, &FileAttrs::new())
+    }
+
+    /// Records the file as written to `remote_path`, without the
+    /// temporary file, and its signature next to it.
+    fn write_remote_file(
+        &self,
+        session: &SshSession,
+        content: &str,
+        remote_path: &str,
+        attrs: &FileAttrs,
+    ) -> DeployResult<()> {
+        self.connect(session)?;
+        self.push(SshCall::Write {
+            host: session.host().to_string(),
+            path: remote_path.to_string(),
+            content: content.to_string(),
+            mode: attrs.mode,
+            in_place: attrs.in_place,
+        });
+        if let Some(signature) = &attrs.signature {
+            let path = format!("{remote_path}{}", sign::SIGNATURE_SUFFIX);
+            self.write_file(session, signature, &path)?;
+        }
+        Ok(())
+    }
+
+    fn pipe_from_local(
+        &self,
+        session: &SshSession,
+        local: &cmd::Pipe,
+        remote_command: &str,
+    ) -> DeployResult<()> {
+        self.connect(session)?;
+        self.push(SshCall::Pipe {
+            host: session.host().to_string(),
+            local: local.to_string(),
+            remote: remote_command.to_string(),
+        });
+        Ok(())
+    }
+
+    fn forward(&self, session: &SshSession, fwd: &PortForward) -> DeployResult<()> {
+        self.connect(session)?;
+        self.push(SshCall::Forward {
+            host: session.host().to_string(),
+            spec: fwd.to_spec(),
+        });
+        Ok(())
+    }
+
+    fn clear_known_host(&self, host: &str) {
+        self.unpin(host);
+        self.push(SshCall::ClearKnownHost {
+            host: host.to_string(),
+        });
+    }
+
+    fn alias_host_key(&self, session: &SshSession, aliases: &[&str]) -> DeployResult<()> {
+        if let Some(pins) = &mut lock(&self.state).pins
+            && pins.contains(session.host())
+        {
+            pins.extend(aliases.iter().map(ToString::to_string));
+        }
+        Ok(())
+    }
+
+    fn forget_host_key(&self, session: &SshSession) {
+        self.unpin(session.host());
+    }
+}
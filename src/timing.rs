@@ -0,0 +1,142 @@
+//! Per-phase deploy durations.
+//!
+//! The pipeline runs each deploy inside [`collect`], and the
+//! pipeline and deployers wrap their phases (build, save,
+//! transfer, load, restart, healthcheck, ...) in [`measure`].
+//! A phase's time excludes the phases measured inside it, so a
+//! deployer timing `save` and `load` within the pipeline's
+//! `transfer` leaves `transfer` with the copy alone. Outside of
+//! [`collect`], [`measure`] just runs its closure.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+#[derive(Default)]
+struct Collector {
+    phases: Vec<(String, Duration)>,
+    /// Time spent in nested phases, one entry per open phase.
+    nested: Vec<Duration>,
+}
+
+thread_local! {
+    static COLLECTOR: RefCell<Option<Collector>> = const { RefCell::new(None) };
+}
+
+/// Durations of the phases of one deploy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Time per phase, summed over repeats (e.g. one build per
+    /// app), in the order phases first ran.
+    pub phases: Vec<(String, Duration)>,
+    /// Wall-clock time of the whole deploy, including time spent
+    /// outside measured phases.
+    pub total: Duration,
+}
+
+impl Timings {
+    /// Time spent in `phase`, if it ran.
+    #[must_use]
+    pub fn get(&self, phase: &str) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|(name, _)| name == phase)
+            .map(|(_, d)| *d)
+    }
+
+    /// Table of phase durations followed by the total.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(["total".len()])
+            .max()
+            .unwrap_or(0);
+        let mut out = String::from("Timing:\n");
+        for (name, duration) in &self.phases {
+            let _ = writeln!(out, "  {name:<width$}  {:>8.1}s", duration.as_secs_f64());
+        }
+        let _ = writeln!(
+            out,
+            "  {:<width$}  {:>8.1}s",
+            "total",
+            self.total.as_secs_f64()
+        );
+        out
+    }
+
+    /// One JSON line for a metrics file, with durations in
+    /// seconds.
+    #[must_use]
+    pub fn to_json_line(&self, command: &str, host: &str, success: bool) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let phases: serde_json::Map<String, serde_json::Value> = self
+            .phases
+            .iter()
+            .map(|(name, d)| (name.clone(), json!(round_secs(*d))))
+            .collect();
+        json!({
+            "timestamp": timestamp,
+            "command": command,
+            "host": host,
+            "success": success,
+            "total": round_secs(self.total),
+            "phases": phases,
+        })
+        .to_string()
+    }
+}
+
+/// Seconds with millisecond precision.
+fn round_secs(d: Duration) -> f64 {
+    (d.as_secs_f64() * 1000.0).round() / 1000.0
+}
+
+/// Run `f`, collecting the phases it measures.
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Timings) {
+    let start = Instant::now();
+    let previous = COLLECTOR.replace(Some(Collector::default()));
+    let result = f();
+    let collector = COLLECTOR.replace(previous).unwrap_or_default();
+    let timings = Timings {
+        phases: collector.phases,
+        total: start.elapsed(),
+    };
+    (result, timings)
+}
+
+/// Run `f` as `phase`, adding its duration to the current
+/// collection.
+pub fn measure<T>(phase: &str, f: impl FnOnce() -> T) -> T {
+    let active =
+        COLLECTOR.with_borrow_mut(|c| c.as_mut().map(|c| c.nested.push(Duration::ZERO)).is_some());
+    if !active {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+
+    COLLECTOR.with_borrow_mut(|c| {
+        let Some(c) = c.as_mut() else {
+            return;
+        };
+        let nested = c.nested.pop().unwrap_or_default();
+        if let Some(parent) = c.nested.last_mut() {
+            *parent += elapsed;
+        }
+        let own = elapsed.saturating_sub(nested);
+        match c.phases.iter_mut().find(|(name, _)| name == phase) {
+            Some((_, total)) => *total += own,
+            None => c.phases.push((phase.to_string(), own)),
+        }
+    });
+    result
+}
@@ -1,4 +1,4 @@
-use catapulta::App;
+use catapulta::{App, EnvVar};
 
 #[test]
 fn defaults() {
@@ -69,6 +69,23 @@ fn builder_chain() {
     assert!(!app.cache_source);
 }
 
+#[test]
+fn env_secret_marks_the_variable() {
+    let app = App::new("test")
+        .env("HOST", "0.0.0.0")
+        .env_secret("API_KEY", "app-test-secret");
+
+    assert_eq!(
+        app.env,
+        vec![
+            EnvVar::new("HOST", "0.0.0.0"),
+            EnvVar::secret("API_KEY", "app-test-secret"),
+        ]
+    );
+    assert!(app.env[1].secret);
+    assert!(app.has_secrets());
+}
+
 #[test]
 fn env_file_overrides() {
     let app = App::new("x").env_file("first.env").env_file("second.env");
@@ -152,3 +169,110 @@ fn source_with_dockerfile() {
     assert_eq!(app.dockerfile, "deploy/Dockerfile");
     assert_eq!(app.context.as_deref(), Some("deploy"));
 }
+
+#[test]
+fn domain_builder() {
+    let app = App::new("api").expose(8000).domain("api.example.com");
+
+    assert_eq!(app.domain.as_deref(), Some("api.example.com"));
+    assert_eq!(App::new("web").domain, None);
+}
+
+#[test]
+fn platforms_builder() {
+    let app = App::new("api").platforms(&["linux/amd64", "linux/arm64"]);
+
+    assert_eq!(app.platforms, vec!["linux/amd64", "linux/arm64"]);
+    assert!(App::new("api").platforms.is_empty());
+}
+
+#[test]
+fn platform_tag_per_arch() {
+    let app = App::new("api");
+
+    assert_eq!(app.platform_tag("linux/amd64"), "api:latest-amd64");
+    assert_eq!(app.platform_tag("linux/arm/v7"), "api:latest-arm-v7");
+}
+
+#[test]
+fn platform_for_machine_matches_uname() {
+    let app = App::new("api").platforms(&["linux/amd64", "linux/arm64"]);
+
+    assert_eq!(app.platform_for_machine("x86_64"), Some("linux/amd64"));
+    assert_eq!(app.platform_for_machine("aarch64\n"), Some("linux/arm64"));
+    assert_eq!(app.platform_for_machine("armv7l"), None);
+    assert_eq!(app.platform_for_machine("sparc64"), None);
+}
+
+#[test]
+fn runs_on_follows_platforms() {
+    let amd64 = App::new("api");
+    let multi = App::new("api").platforms(&["linux/amd64", "linux/arm64"]);
+
+    assert!(amd64.runs_on("x86_64\n"));
+    assert!(!amd64.runs_on("aarch64"));
+    assert!(App::new("api").platform("linux/arm64").runs_on("aarch64"));
+    assert!(multi.runs_on("aarch64"));
+    assert!(!multi.runs_on("armv7l"));
+    // Unknown architectures aren't second-guessed
+    assert!(amd64.runs_on("sparc64"));
+}
+
+#[test]
+fn runtime_options_builders() {
+    let app = App::new("browser")
+        .hostname("chrome")
+        .working_dir("/app")
+        .shm_size("2g")
+        .alias("headless");
+
+    assert_eq!(app.hostname.as_deref(), Some("chrome"));
+    assert_eq!(app.working_dir.as_deref(), Some("/app"));
+    assert_eq!(app.shm_size.as_deref(), Some("2g"));
+    assert_eq!(app.aliases, vec!["headless"]);
+}
+
+#[test]
+fn image_builder() {
+    let app = App::new("db").image("postgres:16").auto_update();
+
+    assert_eq!(app.image.as_deref(), Some("postgres:16"));
+    assert!(app.auto_update);
+    assert_eq!(App::new("api").image, None);
+}
+
+#[test]
+fn config_file_builder() {
+    let app = App::new("proxy").config_file("nginx.conf", "events {}", "/etc/nginx/nginx.conf");
+
+    assert_eq!(app.config_files.len(), 1);
+    assert_eq!(app.config_files[0].name, "nginx.conf");
+    assert_eq!(app.config_files[0].content, "events {}");
+    assert_eq!(app.config_files[0].mount, "/etc/nginx/nginx.conf");
+}
+
+#[test]
+fn pre_build_commands_run_in_order() {
+    let dir = std::env::temp_dir().join("catapulta-pre-build");
+    std::fs::create_dir_all(&dir).unwrap();
+    let app = App::new("web")
+        .pre_build("echo assets > out.txt")
+        .pre_build("echo done >> out.txt");
+
+    assert_eq!(app.pre_build.len(), 2);
+    catapulta::deploy::run_pre_build(&app, Some(&dir)).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out.txt")).unwrap(),
+        "assets\ndone\n"
+    );
+}
+
+#[test]
+fn failing_pre_build_aborts() {
+    let app = App::new("web")
+        .pre_build("exit 3")
+        .pre_build("echo unreachable");
+
+    let err = catapulta::deploy::run_pre_build(&app, None).unwrap_err();
+    assert!(err.to_string().contains("exit 3"));
+}
@@ -0,0 +1,74 @@
+use catapulta::audit::{command_line, mask, redact, register_secret};
+
+#[test]
+fn redact_masks_credential_headers() {
+    assert_eq!(
+        redact("Authorization: Bearer abc123"),
+        "Authorization: [REDACTED]"
+    );
+    assert_eq!(
+        redact("X-Ovh-Application: appkey"),
+        "X-Ovh-Application: [REDACTED]"
+    );
+    assert_eq!(redact("X-Auth-Key: k"), "X-Auth-Key: [REDACTED]");
+}
+
+#[test]
+fn redact_masks_secret_assignments() {
+    assert_eq!(redact("CF_API_TOKEN=abc"), "CF_API_TOKEN=[REDACTED]");
+    assert_eq!(redact("db_password=hunter2"), "db_password=[REDACTED]");
+    assert_eq!(redact("API_KEY=xyz"), "API_KEY=[REDACTED]");
+}
+
+#[test]
+fn redact_keeps_ordinary_arguments() {
+    assert_eq!(
+        redact("Content-Type: application/json"),
+        "Content-Type: application/json"
+    );
+    assert_eq!(
+        redact("DATABASE_URL=sqlite:/app/db"),
+        "DATABASE_URL=sqlite:/app/db"
+    );
+    assert_eq!(redact("--format={{.Names}}"), "--format={{.Names}}");
+    assert_eq!(redact("root@203.0.113.10"), "root@203.0.113.10");
+}
+
+#[test]
+fn command_line_redacts_each_argument() {
+    let line = command_line(
+        "curl",
+        &[
+            "-s",
+            "-H",
+            "X-Ovh-Consumer: ck",
+            "https://api.ovh.com/1.0/auth/time",
+        ],
+    );
+    assert_eq!(
+        line,
+        "curl -s -H X-Ovh-Consumer: [REDACTED] https://api.ovh.com/1.0/auth/time"
+    );
+}
+
+#[test]
+fn command_line_masks_registered_secrets() {
+    register_secret("s3cr3t-value-for-audit-test");
+    let line = command_line(
+        "sh",
+        &[
+            "-c",
+            "printf '%s' 's3cr3t-value-for-audit-test+GET' | shasum",
+        ],
+    );
+    assert_eq!(line, "sh -c printf '%s' '[REDACTED]+GET' | shasum");
+}
+
+#[test]
+fn mask_hides_registered_secrets() {
+    register_secret("s3cr3t-value-for-mask-test");
+    assert_eq!(
+        mask("API_KEY: s3cr3t-value-for-mask-test\nPORT: 80\n"),
+        "API_KEY: [REDACTED]\nPORT: 80\n"
+    );
+}
@@ -0,0 +1,83 @@
+//! Integration test: a redeploy with a changed Caddyfile is
+//! served without recreating Caddy.
+//!
+//! Requires Docker with the compose plugin and port 80 free.
+//! Skipped in normal `cargo test` runs unless the `integration`
+//! feature is enabled.
+
+#![cfg(feature = "integration")]
+
+use catapulta::cmd;
+use catapulta::deploy::Deployer;
+use catapulta::deploy::local::LocalDeploy;
+use catapulta::ssh::SshSession;
+use catapulta::{App, Caddy};
+
+fn deploy(dir: &str, header: &str) {
+    let web = App::new("catapulta-reload-test")
+        .image("traefik/whoami:v1.10")
+        .expose(80);
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .directive(&format!("header X-Catapulta-Config {header}"));
+    LocalDeploy::new()
+        .plain_http()
+        .deploy(
+            &SshSession::new("localhost", "root"),
+            &[web],
+            &caddy,
+            dir,
+            &[],
+        )
+        .expect("local deploy failed");
+}
+
+fn served_header() -> String {
+    let headers = cmd::run(
+        "curl",
+        &["-sS", "-D", "-", "-o", "/dev/null", "http://localhost/"],
+    )
+    .expect("curl failed");
+    headers
+        .lines()
+        .find_map(|l| {
+            l.strip_prefix("X-Catapulta-Config: ")
+                .or_else(|| l.strip_prefix("x-catapulta-config: "))
+        })
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn changed_caddyfile_is_served_after_redeploy() {
+    let dir = std::env::temp_dir().join("catapulta-caddy-reload");
+    let _ = std::fs::remove_dir_all(&dir);
+    let dir = dir.to_str().unwrap();
+
+    deploy(dir, "first");
+    let caddy_id = cmd::run(
+        "docker",
+        &["compose", "--project-directory", dir, "ps", "-q", "caddy"],
+    )
+    .unwrap();
+    assert_eq!(served_header(), "first");
+
+    deploy(dir, "second");
+    let result = served_header();
+    let caddy_after = cmd::run(
+        "docker",
+        &["compose", "--project-directory", dir, "ps", "-q", "caddy"],
+    )
+    .unwrap();
+    let _ = cmd::run(
+        "docker",
+        &["compose", "--project-directory", dir, "down", "-v"],
+    );
+
+    assert_eq!(result, "second");
+    assert_eq!(
+        caddy_after, caddy_id,
+        "Caddy was recreated instead of reloaded"
+    );
+}
@@ -195,3 +195,161 @@ fn single_reverse_proxy_with_upstream() {
     assert!(result.contains("reverse_proxy app:3000"));
     assert!(!result.contains("handle"));
 }
+
+#[test]
+fn app_domains_get_own_site_blocks() {
+    let api = App::new("api").expose(8000).domain("api.example.com");
+    let web = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(web.upstream()).gzip();
+
+    let result = caddyfile::render_with_apps(&caddy, "example.com", &[api, web]);
+
+    assert!(result.contains("example.com {"));
+    assert!(result.contains("reverse_proxy web:3000"));
+    assert!(result.contains("api.example.com {"));
+    assert!(result.contains("reverse_proxy api:8000"));
+    assert_eq!(result.matches("encode gzip").count(), 2);
+}
+
+#[test]
+fn empty_main_site_omitted_when_apps_have_domains() {
+    let api = App::new("api").expose(8000).domain("api.example.com");
+    let docs = App::new("docs").expose(80).domain("docs.example.com");
+
+    let result = caddyfile::render_with_apps(&Caddy::new(), "example.com", &[api, docs]);
+    let tokens = tokenize(&result).expect("tokenize failed");
+    let cf = parse(&tokens).expect("parse failed");
+
+    assert_eq!(cf.sites.len(), 2);
+    assert!(result.starts_with("api.example.com {"));
+    assert!(!result.lines().any(|l| l.starts_with("example.com")));
+}
+
+#[test]
+fn scaled_upstream_uses_dynamic_a() {
+    let api = App::new("api").expose(8000).replicas(2);
+    let web = App::new("web").expose(3000);
+    let caddy = Caddy::new()
+        .route("/api/*", api.upstream())
+        .route("", web.upstream());
+
+    let result = caddyfile::render_with_apps(&caddy, "example.com", &[api, web]);
+    let tokens = tokenize(&result).expect("tokenize failed");
+    parse(&tokens).expect("parse failed");
+
+    assert!(result.contains("dynamic a api 8000"));
+    assert!(result.contains("refresh 5s"));
+    assert!(result.contains("lb_policy round_robin"));
+    assert!(result.contains("reverse_proxy web:3000"));
+}
+
+#[test]
+fn routes_to_several_ports_of_one_app() {
+    let api = App::new("api").expose(8000).expose(9000).expose(9001);
+    let caddy = Caddy::new()
+        .route("/grpc.*", api.upstream_port(9000).h2c())
+        .route("/ws/*", api.upstream_port(9001))
+        .route("", api.upstream());
+
+    let result = caddyfile::render_with_apps(&caddy, "example.com", &[api]);
+    let tokens = tokenize(&result).expect("tokenize failed");
+    parse(&tokens).expect("parse failed");
+
+    assert!(result.contains("reverse_proxy api:9000 {"));
+    assert!(result.contains("transport http {"));
+    assert!(result.contains("versions h2c 2"));
+    assert!(result.contains("reverse_proxy api:9001\n"));
+    assert!(result.contains("reverse_proxy api:8000\n"));
+    assert_eq!(result.matches("transport http").count(), 1);
+}
+
+#[test]
+fn grpc_reverse_proxy() {
+    let api = App::new("api").expose(50051);
+    let caddy = Caddy::new().reverse_proxy_grpc(api.upstream());
+
+    let result = caddyfile::render_with_apps(&caddy, "example.com", &[api]);
+    let tokens = tokenize(&result).expect("tokenize failed");
+    parse(&tokens).expect("parse failed");
+
+    assert!(result.contains(
+        "\treverse_proxy api:50051 {\n\
+         \t\ttransport http {\n\
+         \t\t\tversions h2c 2\n\
+         \t\t}\n\
+         \t}"
+    ));
+}
+
+#[test]
+fn grpc_route_on_scaled_app() {
+    let api = App::new("api").expose(50051).replicas(2);
+    let caddy = Caddy::new().route_grpc("/helloworld.*", api.upstream());
+
+    let result = caddyfile::render_with_apps(&caddy, "example.com", &[api]);
+
+    assert!(result.contains("dynamic a api 50051"));
+    assert!(result.contains("versions h2c 2"));
+}
+
+#[test]
+fn maintenance_answers_every_site_with_503() {
+    let api = App::new("api").expose(8000).domain("api.example.com");
+    let web = App::new("web").expose(3000);
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .basic_auth("admin", "$2a$14$hash")
+        .tls_internal();
+
+    let result =
+        caddyfile::render_maintenance(&caddy, "example.com", &[api, web], "<p>brb</p>", 120);
+
+    // caddyfile-rs can't lex a heredoc closed with `HTML 503`,
+    // which Caddy accepts
+    assert!(result.starts_with("example.com {"));
+    assert!(result.contains("\napi.example.com {"));
+    assert_eq!(
+        result
+            .matches("respond <<HTML\n<p>brb</p>\nHTML 503")
+            .count(),
+        2
+    );
+    assert_eq!(result.matches("header Retry-After 120").count(), 2);
+    assert_eq!(result.matches("tls internal").count(), 2);
+    assert!(!result.contains("reverse_proxy"));
+    assert!(!result.contains("basic_auth"));
+}
+
+#[test]
+fn wildcard_site_gets_dns_challenge_tls() {
+    let saas = App::new("saas").expose(3000).domain("*.example.com");
+    let caddy = Caddy::new().dns_challenge("cloudflare", "CF_API_TOKEN", "cf-token");
+
+    let result = caddyfile::render_config(&caddy, "example.com", &[saas]).unwrap();
+
+    assert!(result.contains("*.example.com {"));
+    assert!(result.contains("reverse_proxy saas:3000"));
+    assert!(result.contains("dns cloudflare {env.CF_API_TOKEN}"));
+    assert!(!result.contains("cf-token"));
+}
+
+#[test]
+fn wildcard_needs_dns_challenge() {
+    let saas = App::new("saas").expose(3000).domain("*.example.com");
+
+    let err = caddyfile::render_config(&Caddy::new(), "example.com", std::slice::from_ref(&saas))
+        .unwrap_err();
+    assert!(err.to_string().contains("*.example.com"));
+
+    let internal = Caddy::new().tls_internal();
+    assert!(caddyfile::render_config(&internal, "example.com", &[saas]).is_ok());
+}
+
+#[test]
+fn dns_challenge_is_not_rendered_to_json() {
+    let caddy = Caddy::new()
+        .json()
+        .dns_challenge("cloudflare", "CF_API_TOKEN", "cf-token");
+
+    assert!(caddyfile::render_config(&caddy, "example.com", &[]).is_err());
+}
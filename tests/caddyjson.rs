@@ -0,0 +1,152 @@
+use catapulta::compose;
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline, caddyjson};
+use serde_json::json;
+
+#[test]
+fn site_with_auth_compression_and_headers() {
+    let app = App::new("app").expose(3000);
+    let caddy = Caddy::new()
+        .json()
+        .basic_auth("admin", "$2a$14$hash")
+        .reverse_proxy(app.upstream())
+        .gzip()
+        .security_headers();
+
+    let config = caddyjson::config(&caddy, "example.com", &[app]).unwrap();
+
+    let server = &config["apps"]["http"]["servers"]["srv0"];
+    assert_eq!(server["listen"], json!([":443"]));
+    let site = &server["routes"][0];
+    assert_eq!(site["match"], json!([{ "host": ["example.com"] }]));
+    let routes = site["handle"][0]["routes"].as_array().unwrap();
+    assert_eq!(routes[0]["handle"][0]["handler"], "headers");
+    assert_eq!(routes[1]["handle"][0]["handler"], "encode");
+    let auth = &routes[2]["handle"][0]["providers"]["http_basic"]["accounts"][0];
+    assert_eq!(auth["username"], "admin");
+    assert_eq!(auth["password"], "$2a$14$hash");
+    assert_eq!(
+        routes[3]["handle"][0],
+        json!({ "handler": "reverse_proxy", "upstreams": [{ "dial": "app:3000" }] })
+    );
+}
+
+#[test]
+fn routes_scaled_and_h2c_upstreams() {
+    let api = App::new("api").expose(8000).replicas(3);
+    let grpc = App::new("grpc").expose(50051);
+    let web = App::new("web").expose(80);
+    let caddy = Caddy::new()
+        .json()
+        .route("/api/*", api.upstream())
+        .route_grpc("/helloworld.*", grpc.upstream())
+        .route("", web.upstream());
+
+    let config = caddyjson::config(&caddy, "example.com", &[api, grpc, web]).unwrap();
+
+    let routes = &config["apps"]["http"]["servers"]["srv0"]["routes"][0]["handle"][0]["routes"];
+    assert_eq!(routes[0]["match"], json!([{ "path": ["/api/*"] }]));
+    assert_eq!(
+        routes[0]["handle"][0]["dynamic_upstreams"],
+        json!({ "source": "a", "name": "api", "port": "8000", "refresh": "5s" })
+    );
+    assert_eq!(
+        routes[1]["handle"][0]["transport"]["versions"],
+        json!(["h2c", "2"])
+    );
+    assert!(routes[2].get("match").is_none());
+    assert_eq!(routes[2]["terminal"], true);
+}
+
+#[test]
+fn app_domains_tls_internal_and_extra_apps() {
+    let admin = App::new("admin").expose(9000).domain("admin.example.com");
+    let caddy = Caddy::new()
+        .tls_internal()
+        .json_app("layer4", json!({ "servers": {} }));
+
+    let config = caddyjson::config(&caddy, "example.com", &[admin]).unwrap();
+
+    let sites = config["apps"]["http"]["servers"]["srv0"]["routes"]
+        .as_array()
+        .unwrap();
+    assert_eq!(sites.len(), 1);
+    assert_eq!(sites[0]["match"][0]["host"], json!(["admin.example.com"]));
+    assert_eq!(
+        config["apps"]["tls"]["automation"]["policies"][0],
+        json!({ "subjects": ["admin.example.com"], "issuers": [{ "module": "internal" }] })
+    );
+    assert_eq!(config["apps"]["layer4"], json!({ "servers": {} }));
+}
+
+#[test]
+fn raw_directives_are_rejected() {
+    let caddy = Caddy::new().json().directive("log");
+
+    let err = caddyjson::render_with_apps(&caddy, "example.com", &[]).unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+}
+
+#[test]
+fn compose_runs_caddy_with_the_json_config() {
+    let app = App::new("app").expose(3000);
+    let caddy = Caddy::new().json().reverse_proxy(app.upstream());
+
+    let yaml = compose::render(&[app], &caddy);
+
+    assert!(yaml.contains("./caddy.json:/etc/caddy/caddy.json:ro"));
+    assert!(yaml.contains("/etc/caddy/caddy.json"));
+    assert!(!yaml.contains("Caddyfile"));
+}
+
+#[test]
+fn scaling_pushes_the_json_config() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\nhealthy\n");
+    let web = App::new("web").expose(3000).healthcheck("true");
+    let caddy = Caddy::new().json().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join("catapulta-caddyjson-scale");
+
+    Pipeline::new(web, caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "scale", "web1", "web=2"])
+        .unwrap();
+
+    let config: serde_json::Value =
+        serde_json::from_str(&fake.file("/opt/app/caddy.json").unwrap()).unwrap();
+    assert_eq!(
+        config["apps"]["http"]["servers"]["srv0"]["routes"][0]["handle"][0]["routes"][0]["handle"]
+            [0]["dynamic_upstreams"]["name"],
+        "web"
+    );
+    assert!(fake.file("/opt/app/Caddyfile").is_none());
+    assert!(
+        fake.commands()
+            .iter()
+            .any(|c| c.contains("caddy reload --config /etc/caddy/caddy.json"))
+    );
+}
+
+#[test]
+fn layer4_streams() {
+    let mqtt = App::new("mqtt").expose(1883);
+    let dns = App::new("dns").expose(53);
+    let caddy = Caddy::new()
+        .layer4()
+        .stream(8883, mqtt.upstream())
+        .stream_udp(53, dns.upstream());
+
+    assert!(caddy.uses_json());
+    let config = caddyjson::config(&caddy, "example.com", &[mqtt, dns]).unwrap();
+
+    let servers = &config["apps"]["layer4"]["servers"];
+    assert_eq!(servers["tcp-8883"]["listen"], json!(["tcp/:8883"]));
+    assert_eq!(
+        servers["tcp-8883"]["routes"][0]["handle"][0],
+        json!({ "handler": "proxy", "upstreams": [{ "dial": ["tcp/mqtt:1883"] }] })
+    );
+    assert_eq!(servers["udp-53"]["listen"], json!(["udp/:53"]));
+}
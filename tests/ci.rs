@@ -0,0 +1,88 @@
+use catapulta::ci::{Workflow, env_file_secret};
+use catapulta::{App, Caddy, Pipeline};
+
+#[test]
+fn github_workflow() {
+    let workflow = Workflow::new("release")
+        .env_file("deploy/.env.api", &env_file_secret("api"))
+        .secret("GHCR_TOKEN")
+        .secret("GHCR_TOKEN")
+        .environment("production");
+
+    assert_eq!(
+        workflow.required_secrets(),
+        ["SSH_PRIVATE_KEY", "ENV_FILE_API", "GHCR_TOKEN"]
+    );
+    assert_eq!(
+        workflow.github(),
+        "\
+# Generated by `cargo xtask ci generate github`.
+#
+# Repository secrets: SSH_PRIVATE_KEY, ENV_FILE_API, GHCR_TOKEN
+# Repository variable: DEPLOY_HOSTS, the hosts to deploy to
+
+name: Deploy
+
+on:
+  push:
+    branches: [release]
+  workflow_dispatch:
+
+concurrency:
+  group: deploy
+  cancel-in-progress: false
+
+jobs:
+  deploy:
+    runs-on: ubuntu-latest
+    environment: production
+    steps:
+      - uses: actions/checkout@v4
+      - uses: dtolnay/rust-toolchain@stable
+      - uses: Swatinem/rust-cache@v2
+      - uses: webfactory/ssh-agent@v0.9.0
+        with:
+          ssh-private-key: ${{ secrets.SSH_PRIVATE_KEY }}
+      - name: Write env files
+        env:
+          ENV_FILE_API: ${{ secrets.ENV_FILE_API }}
+        run: |
+          mkdir -p 'deploy'
+          printf '%s\\n' \"$ENV_FILE_API\" > 'deploy/.env.api'
+      - name: Deploy
+        env:
+          CATAPULTA_USER: ${{ github.actor }}
+          CATAPULTA_CONFIRM: production
+          GHCR_TOKEN: ${{ secrets.GHCR_TOKEN }}
+        run: cargo xtask deploy ${{ vars.DEPLOY_HOSTS }}
+"
+    );
+}
+
+#[test]
+fn workflow_is_valid_yaml() {
+    let yaml: serde_yaml::Value = serde_yaml::from_str(&Workflow::new("main").github()).unwrap();
+    let steps = &yaml["jobs"]["deploy"]["steps"];
+    assert_eq!(steps.as_sequence().unwrap().len(), 5);
+    assert_eq!(
+        steps[4]["run"].as_str(),
+        Some("cargo xtask deploy ${{ vars.DEPLOY_HOSTS }}")
+    );
+    assert!(yaml["jobs"]["deploy"].get("environment").is_none());
+}
+
+#[test]
+fn env_file_secret_names() {
+    assert_eq!(env_file_secret("api"), "ENV_FILE_API");
+    assert_eq!(env_file_secret("my-worker.v2"), "ENV_FILE_MY_WORKER_V2");
+}
+
+#[test]
+fn generate_command() {
+    let app = App::new("api").env_file("deploy/.env.api");
+    let pipeline = Pipeline::new(app, Caddy::new()).protect("production");
+
+    pipeline
+        .run_from(["xtask", "ci", "generate", "github", "--branch", "release"])
+        .unwrap();
+}
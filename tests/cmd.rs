@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use catapulta::cmd;
+use catapulta::error::DeployError;
+
+#[test]
+fn run_with_timeout_captures_output() {
+    let out = cmd::run_with_timeout("echo", &["hello"], Duration::from_secs(5)).unwrap();
+    assert_eq!(out, "hello");
+}
+
+#[test]
+fn run_with_timeout_kills_slow_command() {
+    let err = cmd::run_with_timeout("sleep", &["5"], Duration::from_millis(100)).unwrap_err();
+    assert!(matches!(
+        err,
+        DeployError::Timeout { ref command, timeout }
+            if command == "sleep 5" && timeout == Duration::from_millis(100)
+    ));
+}
+
+#[test]
+fn run_with_timeout_reports_failure() {
+    let err = cmd::run_with_timeout("false", &[], Duration::from_secs(5)).unwrap_err();
+    assert!(matches!(err, DeployError::CommandFailed { .. }));
+}
+
+#[test]
+fn run_with_timeout_missing_program() {
+    let err = cmd::run_with_timeout("catapulta-no-such-program", &[], Duration::from_secs(5))
+        .unwrap_err();
+    assert!(matches!(err, DeployError::CommandNotFound(_)));
+}
+
+#[test]
+fn retry_retries_transient_errors() {
+    let mut calls = 0;
+    let result = cmd::retry(3, Duration::ZERO, || {
+        calls += 1;
+        if calls < 3 {
+            Err(DeployError::SshFailed("connection reset".into()))
+        } else {
+            Ok(calls)
+        }
+    });
+    assert_eq!(result.unwrap(), 3);
+}
+
+#[test]
+fn retry_gives_up_after_attempts() {
+    let mut calls = 0;
+    let result: Result<(), _> = cmd::retry(2, Duration::ZERO, || {
+        calls += 1;
+        Err(DeployError::DnsError("HTTP 503 Service Unavailable".into()))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 2);
+}
+
+#[test]
+fn retry_does_not_retry_permanent_errors() {
+    let mut calls = 0;
+    let result: Result<(), _> = cmd::retry(3, Duration::ZERO, || {
+        calls += 1;
+        Err(DeployError::FileNotFound(".env".into()))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn pipe_connects_commands() {
+    let out = std::env::temp_dir().join("catapulta-pipe-out");
+    let _ = std::fs::remove_file(&out);
+    let pipe = cmd::Pipe::new("printf", &["hello world"])
+        .then("tr", &["a-z", "A-Z"])
+        .then("dd", &[&format!("of={}", out.display()), "status=none"]);
+
+    pipe.run().unwrap();
+
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "HELLO WORLD");
+    assert_eq!(
+        pipe.to_string(),
+        format!(
+            "printf hello world | tr a-z A-Z | dd of={} status=none",
+            out.display()
+        )
+    );
+}
+
+#[test]
+fn pipe_fails_with_the_failed_command() {
+    let err = cmd::Pipe::new("echo", &["data"])
+        .then("false", &[])
+        .then("cat", &[])
+        .run()
+        .unwrap_err();
+    assert!(matches!(err, DeployError::CommandFailed { ref command, .. } if command == "false"));
+
+    let err = cmd::Pipe::new("echo", &["data"])
+        .then("catapulta-no-such-program", &[])
+        .run()
+        .unwrap_err();
+    assert!(matches!(err, DeployError::CommandNotFound(_)));
+}
@@ -1,6 +1,6 @@
 use catapulta::compose;
-use catapulta::{App, Caddy};
-use docker_compose_types::Compose;
+use catapulta::{App, Caddy, EnvVar, Job};
+use docker_compose_types::{Compose, Labels, MapOrEmpty, Networks};
 
 #[test]
 fn generates_valid_compose() {
@@ -432,3 +432,346 @@ fn caddy_bind_mount_not_in_top_level_volumes() {
     assert!(yaml.contains("./web-static:/www:ro"));
     assert!(yaml.contains("/host/path:/container:ro"));
 }
+
+#[test]
+fn app_domain_adds_caddy_without_routes() {
+    let api = App::new("api")
+        .expose(8000)
+        .healthcheck("curl -f http://localhost:8000/")
+        .domain("api.example.com");
+
+    let yaml = compose::render(&[api], &Caddy::new());
+    let parsed: Compose = serde_yaml::from_str(&yaml).expect("parse");
+
+    assert!(parsed.services.0.contains_key("caddy"));
+    assert!(parsed.volumes.0.contains_key("caddy-data"));
+    assert!(yaml.contains("condition: service_healthy"));
+}
+
+#[test]
+fn init_job_runs_before_app() {
+    let api = App::new("api")
+        .env("DATABASE_URL", "postgres://db/api")
+        .env_file("deploy/.env.api")
+        .init(
+            Job::from_image("api:latest")
+                .command("./api migrate && ./api seed")
+                .env("RUST_LOG", "info")
+                .volume("api-data", "/data"),
+        );
+
+    let yaml = compose::render(&[api], &Caddy::new());
+    let parsed: Compose = serde_yaml::from_str(&yaml).expect("parse");
+
+    let job = parsed.services.0["api-init"]
+        .as_ref()
+        .expect("init service");
+    assert_eq!(job.image.as_deref(), Some("api:latest"));
+    assert_eq!(job.restart.as_deref(), Some("no"));
+    assert!(yaml.contains("./api migrate && ./api seed"));
+    assert!(yaml.contains("DATABASE_URL=postgres://db/api"));
+    assert!(yaml.contains("RUST_LOG=info"));
+    assert!(yaml.contains(".env.api"));
+    assert!(parsed.volumes.0.contains_key("api-data"));
+
+    assert!(yaml.contains("api-init:\n        condition: service_completed_successfully"));
+}
+
+#[test]
+fn multiple_init_jobs_are_numbered() {
+    let api = App::new("api")
+        .init(Job::from_image("api:latest").command("migrate"))
+        .init(Job::from_image("seeder:latest"));
+
+    assert_eq!(api.init_names(), ["api-init-1", "api-init-2"]);
+
+    let yaml = compose::render(&[api], &Caddy::new());
+    let parsed: Compose = serde_yaml::from_str(&yaml).expect("parse");
+
+    assert!(parsed.services.0.contains_key("api-init-1"));
+    assert!(parsed.services.0.contains_key("api-init-2"));
+}
+
+#[test]
+fn app_without_init_has_no_depends_on() {
+    let yaml = compose::render(&[App::new("api")], &Caddy::new());
+
+    assert!(!yaml.contains("depends_on"));
+}
+
+#[test]
+fn runtime_options_rendered() {
+    let db = App::new("db")
+        .hostname("db01")
+        .working_dir("/var/lib/postgresql")
+        .shm_size("1g")
+        .alias("postgres")
+        .alias("database");
+
+    let yaml = compose::render(&[db], &Caddy::new());
+    let parsed: Compose = serde_yaml::from_str(&yaml).expect("parse");
+    let service = parsed.services.0["db"].as_ref().expect("db service");
+
+    assert_eq!(service.hostname.as_deref(), Some("db01"));
+    assert_eq!(service.working_dir.as_deref(), Some("/var/lib/postgresql"));
+    assert_eq!(service.shm_size.as_deref(), Some("1g"));
+    let Networks::Advanced(nets) = &service.networks else {
+        panic!("expected advanced networks, got {:?}", service.networks);
+    };
+    let Some(MapOrEmpty::Map(settings)) = nets.0.get("db-network") else {
+        panic!("db-network missing");
+    };
+    assert_eq!(settings.aliases, ["postgres", "database"]);
+}
+
+#[test]
+fn runtime_options_omitted_by_default() {
+    let yaml = compose::render(&[App::new("db")], &Caddy::new());
+
+    assert!(!yaml.contains("hostname"));
+    assert!(!yaml.contains("working_dir"));
+    assert!(!yaml.contains("shm_size"));
+    assert!(!yaml.contains("aliases"));
+}
+
+#[test]
+fn prebuilt_image_used_as_is() {
+    let db = App::new("db").image("postgres:16");
+
+    let yaml = compose::render(&[db], &Caddy::new());
+
+    assert!(yaml.contains("image: postgres:16"));
+    assert!(!yaml.contains("db:latest"));
+}
+
+#[test]
+fn auto_update_adds_scoped_watchtower() {
+    let api = App::new("api").expose(8000);
+    let db = App::new("db").image("postgres:16").auto_update();
+    let caddy = Caddy::new().reverse_proxy(api.upstream()).auto_update();
+
+    let yaml = compose::render(&[api, db], &caddy);
+    let parsed: Compose = serde_yaml::from_str(&yaml).expect("parse");
+    let services = &parsed.services.0;
+
+    let watchtower = services["watchtower"].as_ref().expect("watchtower");
+    assert_eq!(
+        watchtower.image.as_deref(),
+        Some("containrrr/watchtower:latest")
+    );
+    assert!(yaml.contains("--label-enable"));
+    assert!(yaml.contains("/var/run/docker.sock:/var/run/docker.sock"));
+
+    let enabled = |name: &str| {
+        services[name]
+            .as_ref()
+            .is_some_and(|s| s.labels != Labels::default())
+    };
+    assert!(enabled("db"));
+    assert!(enabled("caddy"));
+    assert!(!enabled("api"));
+}
+
+#[test]
+fn auto_update_ignored_for_built_apps() {
+    let api = App::new("api").auto_update();
+
+    let yaml = compose::render(&[api], &Caddy::new());
+
+    assert!(!yaml.contains("watchtower"));
+}
+
+#[test]
+fn replicas_drop_container_name() {
+    let api = App::new("api").expose(8000).replicas(3);
+    let web = App::new("web").expose(3000);
+
+    let yaml = compose::render(&[api, web], &Caddy::new());
+    let parsed: Compose = serde_yaml::from_str(&yaml).unwrap();
+
+    let api = parsed.services.0["api"].as_ref().unwrap();
+    assert_eq!(api.container_name, None);
+    assert_eq!(api.deploy.as_ref().unwrap().replicas, Some(3));
+    let web = parsed.services.0["web"].as_ref().unwrap();
+    assert_eq!(web.container_name.as_deref(), Some("web"));
+    assert!(web.deploy.is_none());
+}
+
+#[test]
+fn app_routed_on_several_ports_is_one_dependency() {
+    let api = App::new("api")
+        .expose(8000)
+        .expose(9000)
+        .healthcheck("true");
+    let caddy = Caddy::new()
+        .route("/grpc.*", api.upstream_port(9000).h2c())
+        .route("", api.upstream());
+
+    let yaml = compose::render(&[api], &caddy);
+    let parsed: Compose = serde_yaml::from_str(&yaml).unwrap();
+
+    let caddy = parsed.services.0["caddy"].as_ref().unwrap();
+    let docker_compose_types::DependsOnOptions::Conditional(depends) = &caddy.depends_on else {
+        panic!("expected conditional depends_on");
+    };
+    assert_eq!(depends.len(), 1);
+}
+
+#[test]
+fn profiled_app_only_starts_on_request() {
+    let web = App::new("web").expose(3000).healthcheck("true");
+    let admin = App::new("admin")
+        .expose(8080)
+        .healthcheck("true")
+        .profile("debug")
+        .init(Job::from_image("admin:latest"));
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .route("/admin/*", admin.upstream());
+    let apps = [web, admin];
+
+    let parsed: Compose = serde_yaml::from_str(&compose::render(&apps, &caddy)).unwrap();
+    let services = &parsed.services.0;
+    assert_eq!(services["admin"].as_ref().unwrap().profiles, vec!["debug"]);
+    assert_eq!(
+        services["admin-init"].as_ref().unwrap().profiles,
+        vec!["debug"]
+    );
+    let docker_compose_types::DependsOnOptions::Conditional(depends) =
+        &services["caddy"].as_ref().unwrap().depends_on
+    else {
+        panic!("expected conditional depends_on");
+    };
+    assert!(depends.contains_key("web"));
+    assert!(!depends.contains_key("admin"));
+
+    let active = compose::activate_profiles(&apps, &["debug".to_string()]);
+    assert!(active[1].profiles.is_empty());
+    assert!(compose::is_active(&apps[0], &[]));
+    assert!(!compose::is_active(&apps[1], &[]));
+    let yaml = compose::render(&active, &caddy);
+    assert!(!yaml.contains("profiles:"));
+}
+
+#[test]
+fn streams_are_published_by_their_app() {
+    let web = App::new("web").expose(80);
+    let game = App::new("game").image("game:1").expose(27015);
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .stream(27015, game.upstream())
+        .stream_udp(27016, game.upstream());
+
+    let compose: Compose = serde_yaml::from_str(&compose::render(&[web, game], &caddy)).unwrap();
+
+    let service = |name: &str| compose.services.0[name].clone().unwrap();
+    assert_eq!(
+        service("game").ports,
+        docker_compose_types::Ports::Short(vec![
+            "27015:27015".to_string(),
+            "27016:27015/udp".to_string()
+        ])
+    );
+    assert_eq!(
+        service("caddy").ports,
+        docker_compose_types::Ports::Short(vec!["80:80".to_string(), "443:443".to_string()])
+    );
+    assert_eq!(service("caddy").image.as_deref(), Some("caddy:2-alpine"));
+}
+
+#[test]
+fn layer4_streams_go_through_caddy() {
+    let mqtt = App::new("mqtt").image("eclipse-mosquitto:2").expose(1883);
+    let caddy = Caddy::new()
+        .image("registry.example.com/caddy-l4:2")
+        .layer4()
+        .stream(8883, mqtt.upstream());
+
+    let compose: Compose = serde_yaml::from_str(&compose::render(&[mqtt], &caddy)).unwrap();
+
+    let caddy_service = compose.services.0["caddy"].clone().unwrap();
+    assert_eq!(
+        caddy_service.image.as_deref(),
+        Some("registry.example.com/caddy-l4:2")
+    );
+    assert_eq!(
+        caddy_service.ports,
+        docker_compose_types::Ports::Short(vec![
+            "80:80".to_string(),
+            "443:443".to_string(),
+            "8883:8883".to_string()
+        ])
+    );
+    assert!(caddy_service.volumes.iter().any(|v| matches!(
+        v,
+        docker_compose_types::Volumes::Simple(s) if s == "./caddy.json:/etc/caddy/caddy.json:ro"
+    )));
+    let mqtt_service = compose.services.0["mqtt"].clone().unwrap();
+    assert_eq!(mqtt_service.ports, docker_compose_types::Ports::default());
+}
+
+#[test]
+fn project_names_the_stack() {
+    let app = App::new("api").expose(8000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+
+    let yaml = compose::with_project(Some("billing"), || compose::render(&[app], &caddy));
+    let compose: Compose = serde_yaml::from_str(&yaml).unwrap();
+
+    assert_eq!(compose.name.as_deref(), Some("billing"));
+    let service = |name: &str| compose.services.0[name].clone().unwrap();
+    assert_eq!(
+        service("api").container_name.as_deref(),
+        Some("billing-api")
+    );
+    assert_eq!(
+        service("caddy").container_name.as_deref(),
+        Some("billing-caddy")
+    );
+    assert!(compose.networks.0.contains_key("billing-network"));
+    assert_eq!(compose::project(), None);
+}
+
+#[test]
+fn secret_env_restricts_the_compose_file() {
+    let plain = App::new("web").env("PORT", "80");
+    let api = App::new("api").env_secret("API_KEY", "compose-secret-api-key");
+
+    let apps = [plain, api];
+
+    let yaml = compose::render(&apps, &Caddy::new());
+    assert!(yaml.contains("compose-secret-api-key"));
+    assert!(!catapulta::audit::mask(&yaml).contains("compose-secret-api-key"));
+    assert_eq!(compose::file_attrs(&apps[..1], &Caddy::new()).mode, None);
+    assert_eq!(compose::file_attrs(&apps, &Caddy::new()).mode, Some(0o600));
+}
+
+#[test]
+fn secret_env_built_as_a_literal_is_masked() {
+    let mut api = App::new("api");
+    api.env.push(EnvVar {
+        key: "API_KEY".into(),
+        value: "compose-literal-secret".into(),
+        secret: true,
+    });
+
+    let yaml = compose::render(&[api], &Caddy::new());
+    assert!(yaml.contains("API_KEY=compose-literal-secret"));
+    assert!(!catapulta::audit::mask(&yaml).contains("compose-literal-secret"));
+}
+
+#[test]
+fn dns_challenge_token_goes_to_caddy() {
+    let web = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(web.upstream()).dns_challenge(
+        "cloudflare",
+        "CF_API_TOKEN",
+        "compose-cf-token",
+    );
+    let apps = [web];
+
+    let yaml = compose::render(&apps, &caddy);
+    assert!(yaml.contains("CF_API_TOKEN=compose-cf-token"));
+    assert!(!catapulta::audit::mask(&yaml).contains("compose-cf-token"));
+    assert_eq!(compose::file_attrs(&apps, &caddy).mode, Some(0o600));
+}
@@ -0,0 +1,464 @@
+use catapulta::EnvVar;
+use catapulta::config::{Config, DnsConfig, ProvisionerConfig, StateConfig, TransferConfig};
+use catapulta::{Compression, Runtime};
+
+const TOML: &str = r#"
+remote_dir = "/srv/stack"
+
+[[app]]
+name = "api"
+expose = [8000, 9000]
+ports = ["4222:4222"]
+env = { RUST_LOG = "info", ZONE = "eu" }
+volumes = { api-data = "/data" }
+dev_volumes = { "./api/src" = "/app/src" }
+healthcheck = "curl -f http://localhost:8000/health"
+source = { url = "git@github.com:org/api.git", ref = "main" }
+
+[[app]]
+name = "web"
+image = "nginx:alpine"
+expose = [80]
+auto_update = true
+
+[caddy]
+reverse_proxy = "web"
+gzip = true
+
+[[caddy.route]]
+path = "/api/*"
+upstream = "api:9000"
+
+[provisioner]
+type = "digitalocean"
+size = "s-2vcpu-4gb"
+fallback_regions = ["ams3"]
+
+[[dns]]
+type = "cloudflare"
+domain = "example.com"
+
+[deploy]
+transfer = "stream-zstd"
+bwlimit = 2048
+compression_level = 9
+compression = "gzip"
+healthy_timeout = 600
+
+[[registry]]
+registry = "ghcr.io"
+user = "ci-bot"
+token_env = "GHCR_TOKEN"
+"#;
+
+#[test]
+fn toml_apps() {
+    let config = Config::from_toml(TOML).unwrap();
+    let apps = config.apps().unwrap();
+
+    assert_eq!(apps.len(), 2);
+    let api = &apps[0];
+    assert_eq!(api.expose, vec![8000, 9000]);
+    assert_eq!(api.ports, vec![(4222, 4222)]);
+    assert_eq!(
+        api.env,
+        vec![EnvVar::new("RUST_LOG", "info"), EnvVar::new("ZONE", "eu")]
+    );
+    assert_eq!(
+        api.volumes[0],
+        ("api-data".to_string(), "/data".to_string())
+    );
+    assert_eq!(
+        api.dev_volumes,
+        [("./api/src".to_string(), "/app/src".to_string())]
+    );
+    assert_eq!(
+        api.source,
+        Some(("git@github.com:org/api.git".to_string(), "main".to_string()))
+    );
+    assert_eq!(apps[1].image.as_deref(), Some("nginx:alpine"));
+    assert!(apps[1].auto_update);
+}
+
+#[test]
+fn toml_caddy_and_settings() {
+    let config = Config::from_toml(TOML).unwrap();
+    let caddy = config.caddy(&config.apps().unwrap()).unwrap();
+
+    assert_eq!(caddy.reverse_proxy.unwrap().to_string(), "web:80");
+    assert_eq!(caddy.routes[0].0, "/api/*");
+    assert_eq!(caddy.routes[0].1.to_string(), "api:9000");
+    assert!(caddy.gzip);
+    assert!(matches!(
+        config.provisioner,
+        Some(ProvisionerConfig::DigitalOcean { ref size, ref fallback_regions, .. })
+            if size.as_deref() == Some("s-2vcpu-4gb") && fallback_regions == &["ams3"]
+    ));
+    assert_eq!(config.deploy.transfer, TransferConfig::StreamZstd);
+    let deployer = config.deploy.deployer();
+    assert_eq!(deployer.bwlimit, Some(2048));
+    assert_eq!(deployer.compression_level, Some(9));
+    assert_eq!(deployer.compression, Compression::Gzip);
+    assert_eq!(deployer.cipher, None);
+    assert_eq!(deployer.health_wait.attempts, 120);
+    assert_eq!(deployer.runtime, Runtime::Docker);
+    assert_eq!(config.remote_dir.as_deref(), Some("/srv/stack"));
+    assert_eq!(config.registries[0].registry, "ghcr.io");
+    assert_eq!(config.registries[0].token_env, "GHCR_TOKEN");
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn yaml_config() {
+    let config = Config::from_yaml(
+        "
+apps:
+  - name: api
+    expose: [8000]
+caddy:
+  reverse_proxy: api
+dns:
+  - type: ovh
+    domain: api.example.com
+",
+    )
+    .unwrap();
+
+    let apps = config.apps().unwrap();
+    let caddy = config.caddy(&apps).unwrap();
+    assert_eq!(caddy.reverse_proxy.unwrap().to_string(), "api:8000");
+    assert_eq!(config.dns.len(), 1);
+}
+
+#[test]
+fn scaleway_provisioner() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [provisioner]\ntype = \"scaleway\"\ncommercial_type = \"PLAY2-NANO\"\nzone = \"fr-par-2\"\n",
+    )
+    .unwrap();
+
+    assert!(matches!(
+        &config.provisioner,
+        Some(ProvisionerConfig::Scaleway { zone: Some(zone), image: None, .. }) if zone == "fr-par-2"
+    ));
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn libvirt_data_disks() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [provisioner]\ntype = \"libvirt\"\nhost = \"kvm.lan\"\nvm_ssh_key = \"~/.ssh/id_ed25519\"\n\n\
+         [[provisioner.data_disks]]\nsize_gib = 100\nmount_path = \"/srv/data\"\n",
+    )
+    .unwrap();
+
+    let Some(ProvisionerConfig::Libvirt { data_disks, .. }) = &config.provisioner else {
+        panic!("expected a libvirt provisioner");
+    };
+    assert_eq!(data_disks.len(), 1);
+    assert_eq!(data_disks[0].size_gib, 100);
+    assert_eq!(data_disks[0].mount_path, "/srv/data");
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn dns_ttl_and_proxied() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [[dns]]\ntype = \"ovh\"\ndomain = \"api.example.com\"\nttl = 60\n\n\
+         [[dns]]\ntype = \"cloudflare\"\ndomain = \"example.com\"\nproxied = true\n",
+    )
+    .unwrap();
+
+    assert!(matches!(
+        &config.dns[0],
+        DnsConfig::Ovh { ttl: Some(60), .. }
+    ));
+    assert!(matches!(
+        &config.dns[1],
+        DnsConfig::Cloudflare {
+            ttl: None,
+            proxied: true,
+            ..
+        }
+    ));
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn local_dns_providers() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [[dns]]\ntype = \"pihole\"\nurl = \"http://pi.hole\"\ndomain = \"api.home.arpa\"\n\n\
+         [[dns]]\ntype = \"adguard\"\nurl = \"http://192.168.1.2:3000\"\ndomain = \"api.lan\"\n\n\
+         [[dns]]\ntype = \"duckdns\"\ndomain = \"home.duckdns.org\"\n",
+    )
+    .unwrap();
+
+    assert!(matches!(
+        &config.dns[0],
+        DnsConfig::Pihole { url, domain } if url == "http://pi.hole" && domain == "api.home.arpa"
+    ));
+    assert!(matches!(&config.dns[1], DnsConfig::Adguard { .. }));
+    assert!(
+        matches!(&config.dns[2], DnsConfig::Duckdns { domain } if domain == "home.duckdns.org")
+    );
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn caddy_json_apps() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\nexpose = [8000]\n\n\
+         [caddy]\nreverse_proxy = \"api\"\n\n\
+         [caddy.json_apps.layer4.servers.ssh]\nlisten = [\":2222\"]\n",
+    )
+    .unwrap();
+
+    let caddy = config.caddy(&config.apps().unwrap()).unwrap();
+    assert!(caddy.json);
+    assert_eq!(caddy.json_apps[0].0, "layer4");
+    assert_eq!(caddy.json_apps[0].1["servers"]["ssh"]["listen"][0], ":2222");
+}
+
+#[test]
+fn caddy_streams() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"mqtt\"\nexpose = [1883]\n\n\
+         [caddy]\nlayer4 = true\nimage = \"caddy-l4:2\"\n\n\
+         [[caddy.stream]]\nport = 8883\nupstream = \"mqtt\"\n",
+    )
+    .unwrap();
+
+    let caddy = config.caddy(&config.apps().unwrap()).unwrap();
+    assert!(caddy.proxies_streams());
+    assert_eq!(caddy.image.as_deref(), Some("caddy-l4:2"));
+    assert_eq!(caddy.streams[0].upstream.to_string(), "mqtt:1883");
+    assert!(!caddy.streams[0].udp);
+}
+
+#[test]
+fn state_store() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [state]\ntype = \"s3\"\nbucket = \"acme-ops\"\nkey = \"catapulta/servers.json\"\n\
+         endpoint = \"https://s3.fr-par.scw.cloud\"\n",
+    )
+    .unwrap();
+
+    assert!(matches!(
+        &config.state,
+        Some(StateConfig::S3 { bucket, endpoint: Some(_), .. }) if bucket == "acme-ops"
+    ));
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn podman_runtime() {
+    let config =
+        Config::from_toml("[[app]]\nname = \"api\"\n\n[deploy]\nruntime = \"podman\"\n").unwrap();
+
+    assert_eq!(config.deploy.deployer().runtime, Runtime::Podman);
+}
+
+#[test]
+fn shared_caddy() {
+    let config = Config::from_toml(
+        "project = \"blog\"\n\n[[app]]\nname = \"ghost\"\nexpose = [2368]\n\n\
+         [caddy]\nreverse_proxy = \"ghost\"\nshared = true\n",
+    )
+    .unwrap();
+
+    let caddy = config.caddy(&config.apps().unwrap()).unwrap();
+    assert!(caddy.shared);
+}
+
+#[test]
+fn caddy_dns_challenge() {
+    // SAFETY: the variable is only read by this test.
+    unsafe { std::env::set_var("CATAPULTA_TEST_CF_TOKEN", "cf-config-token") };
+    let config = Config::from_toml(
+        "[[app]]\nname = \"saas\"\nexpose = [3000]\ndomain = \"*.example.com\"\n\n\
+         [caddy.dns_challenge]\nprovider = \"cloudflare\"\n\
+         token_env = \"CATAPULTA_TEST_CF_TOKEN\"\n",
+    )
+    .unwrap();
+
+    let caddy = config.caddy(&config.apps().unwrap()).unwrap();
+    let challenge = caddy.dns_challenge.unwrap();
+    assert_eq!(challenge.provider, "cloudflare");
+    assert_eq!(challenge.token.key, "CATAPULTA_TEST_CF_TOKEN");
+    assert_eq!(challenge.token.value, "cf-config-token");
+    assert!(challenge.token.secret);
+}
+
+#[test]
+fn app_dirs() {
+    let config = Config::from_toml(
+        "app_dirs = true\n\n[[app]]\nname = \"api\"\n\n\
+         [[app]]\nname = \"worker\"\nremote_dir = \"jobs\"\n",
+    )
+    .unwrap();
+
+    let apps = config.apps().unwrap();
+    assert!(config.app_dirs);
+    assert_eq!(apps[0].remote_dir, None);
+    assert_eq!(apps[1].remote_path(".env"), "jobs/.env");
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn no_proxy() {
+    let config =
+        Config::from_toml("no_proxy = true\n\n[[app]]\nname = \"nats\"\nexpose = [4222]\n")
+            .unwrap();
+
+    assert!(config.no_proxy);
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn unknown_key_is_rejected() {
+    let err = Config::from_toml("[[app]]\nname = \"api\"\nexpsoe = [80]\n").unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+    assert!(err.to_string().contains("expsoe"));
+}
+
+#[test]
+fn upstream_must_match_an_app() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\nexpose = [8000]\n\n[caddy]\nreverse_proxy = \"apl\"\n",
+    )
+    .unwrap();
+
+    let err = config.caddy(&config.apps().unwrap()).unwrap_err();
+    assert!(err.to_string().contains("no app with this name"));
+}
+
+#[test]
+fn upstream_port_must_be_exposed() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\nexpose = [8000]\n\n[caddy]\nreverse_proxy = \"api:9000\"\n",
+    )
+    .unwrap();
+
+    let err = config.caddy(&config.apps().unwrap()).unwrap_err();
+    assert!(err.to_string().contains("not exposed"));
+}
+
+#[test]
+fn invalid_port_mapping() {
+    let config = Config::from_toml("[[app]]\nname = \"api\"\nports = [\"8080\"]\n").unwrap();
+
+    let err = config.apps().unwrap_err();
+    assert!(err.to_string().contains("HOST:CONTAINER"));
+}
+
+#[test]
+fn apps_are_required() {
+    let err = Config::from_toml("remote_dir = \"/opt/app\"\n")
+        .unwrap()
+        .apps()
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+}
+
+#[test]
+fn load_picks_format_from_extension() {
+    let dir = std::env::temp_dir().join("catapulta-config-load");
+    std::fs::create_dir_all(&dir).unwrap();
+    let yaml = dir.join("catapulta.yml");
+    std::fs::write(&yaml, "apps:\n  - name: api\n").unwrap();
+
+    let config = Config::load(yaml.to_str().unwrap()).unwrap();
+    assert_eq!(config.apps[0].name, "api");
+
+    let missing = Config::load(dir.join("missing.toml").to_str().unwrap()).unwrap_err();
+    assert_eq!(missing.code(), "E502");
+}
+
+#[test]
+fn grpc_route() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\nexpose = [8000, 9000]\n\n\
+         [[caddy.route]]\npath = \"/grpc.*\"\nupstream = \"api:9000\"\ngrpc = true\n\n\
+         [[caddy.route]]\npath = \"\"\nupstream = \"api\"\n",
+    )
+    .unwrap();
+
+    let caddy = config.caddy(&config.apps().unwrap()).unwrap();
+    assert!(caddy.routes[0].1.h2c);
+    assert!(!caddy.routes[1].1.h2c);
+}
+
+#[test]
+fn secret_env() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\nenv = { RUST_LOG = \"info\" }\n\
+         secret_env = { API_KEY = \"config-secret-api-key\" }\n",
+    )
+    .unwrap();
+
+    let api = &config.apps().unwrap()[0];
+    assert_eq!(
+        api.env,
+        vec![
+            EnvVar::new("RUST_LOG", "info"),
+            EnvVar::secret("API_KEY", "config-secret-api-key")
+        ]
+    );
+    assert!(api.has_secrets());
+}
+
+#[test]
+fn proxmox_provisioner() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [provisioner]\ntype = \"proxmox\"\napi_url = \"https://pve.lan:8006\"\n\
+         node = \"pve1\"\ntemplate = 9000\nvm_ssh_key = \"~/.ssh/id_homelab\"\ndisk_gib = 32\n",
+    )
+    .unwrap();
+
+    let Some(ProvisionerConfig::Proxmox(proxmox)) = config.provisioner.clone() else {
+        panic!("expected a proxmox provisioner");
+    };
+    let provisioner = proxmox.provisioner();
+    assert_eq!(provisioner.template, 9000);
+    assert_eq!(provisioner.disk_gib, Some(32));
+    assert_eq!(provisioner.cores, 2);
+    assert!(config.into_pipeline().is_ok());
+
+    let unknown = Config::from_toml(
+        "[provisioner]\ntype = \"proxmox\"\napi_url = \"https://pve.lan:8006\"\n\
+         node = \"pve1\"\ntemplate = 9000\nvm_ssh_key = \"k\"\nvcpus = 4\n",
+    );
+    assert!(unknown.is_err());
+}
+
+#[test]
+fn existing_server_provisioner() {
+    let config = Config::from_toml(
+        "[[app]]\nname = \"api\"\n\n\
+         [provisioner]\ntype = \"existing\"\nhost = \"203.0.113.5\"\nssh_key = \"~/.ssh/id_ed25519\"\n",
+    )
+    .unwrap();
+
+    assert!(matches!(
+        &config.provisioner,
+        Some(ProvisionerConfig::Existing { host, .. }) if host == "203.0.113.5"
+    ));
+    assert!(config.into_pipeline().is_ok());
+}
+
+#[test]
+fn protected_environment() {
+    let config =
+        Config::from_toml("protected = \"production\"\n\n[[app]]\nname = \"api\"\n").unwrap();
+
+    assert_eq!(config.protected.as_deref(), Some("production"));
+    assert!(config.into_pipeline().is_ok());
+}
@@ -0,0 +1,54 @@
+use catapulta::{App, DoRegistry};
+
+#[test]
+fn image_reference_in_the_registry() {
+    let docr = DoRegistry::new("my-team");
+
+    assert_eq!(
+        docr.image_ref("api:latest"),
+        "registry.digitalocean.com/my-team/api:latest"
+    );
+    assert_eq!(docr.tier, "starter");
+    assert_eq!(docr.subscription_tier("basic").tier, "basic");
+}
+
+#[test]
+fn pull_removes_the_credentials() {
+    let command = DoRegistry::new("my-team").pull_command("api:latest", "/tmp/catapulta-docr-api");
+
+    assert!(command.starts_with(
+        "DOCKER_CONFIG='/tmp/catapulta-docr-api' \
+         docker pull registry.digitalocean.com/my-team/api:latest;"
+    ));
+    assert!(command.contains("rm -rf '/tmp/catapulta-docr-api'"));
+    assert!(
+        command.ends_with("docker tag registry.digitalocean.com/my-team/api:latest api:latest")
+    );
+}
+
+#[test]
+fn multi_platform_images_are_published_as_a_manifest_list() {
+    let docr = DoRegistry::new("my-team");
+    let app = App::new("api").platforms(&["linux/amd64", "linux/arm64"]);
+
+    assert_eq!(
+        docr.manifest_commands(&app),
+        [
+            vec![
+                "manifest",
+                "create",
+                "--amend",
+                "registry.digitalocean.com/my-team/api:latest",
+                "registry.digitalocean.com/my-team/api:latest-amd64",
+                "registry.digitalocean.com/my-team/api:latest-arm64",
+            ],
+            vec![
+                "manifest",
+                "push",
+                "--purge",
+                "registry.digitalocean.com/my-team/api:latest",
+            ],
+        ]
+    );
+    assert!(docr.manifest_commands(&App::new("api")).is_empty());
+}
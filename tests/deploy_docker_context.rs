@@ -0,0 +1,41 @@
+use catapulta::deploy::Deployer;
+use catapulta::ssh::{SshOptions, SshSession};
+use catapulta::testing::FakeSsh;
+use catapulta::{App, DockerContext};
+
+#[test]
+fn builds_against_the_server_daemon() {
+    let ssh = SshSession::new("web1.example.com", "root");
+    assert_eq!(
+        DockerContext::new().docker_args(&ssh),
+        ["--host", "ssh://root@web1.example.com"]
+    );
+
+    let ssh = ssh.port(2222);
+    assert_eq!(
+        DockerContext::new().docker_args(&ssh),
+        ["--host", "ssh://root@web1.example.com:2222"]
+    );
+    assert_eq!(
+        DockerContext::new().context("prod").docker_args(&ssh),
+        ["--context", "prod"]
+    );
+}
+
+#[test]
+fn nothing_is_built_locally() {
+    let app = App::new("api").expose(8000);
+
+    assert!(DockerContext::new().build_image(&app).is_ok());
+}
+
+#[test]
+fn server_platform_must_be_listed() {
+    let app = App::new("api").platforms(&["linux/amd64", "linux/arm64"]);
+    let fake = FakeSsh::new().respond("uname -m", "riscv64");
+    let ssh = SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake));
+
+    let err = DockerContext::new().transfer_image(&app, &ssh).unwrap_err();
+
+    assert!(err.to_string().contains("linux/amd64, linux/arm64"));
+}
@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use catapulta::deploy::{
+    HealthWait, Runtime, caddy_reload_command, health_status_command, same_image,
+    unhealthy_report_command, wait_healthy_or_report,
+};
+use catapulta::sign::Signer;
+use catapulta::ssh::SshOptions;
+use catapulta::testing::FakeSsh;
+use catapulta::{App, Caddy, Compression, DockerSaveLoad, Pipeline, Transfer};
+
+fn deploy(name: &str, caddy: Caddy, fake: &FakeSsh) {
+    deploy_with(name, caddy, DockerSaveLoad::new(), fake);
+}
+
+fn deploy_with(name: &str, caddy: Caddy, deployer: DockerSaveLoad, fake: &FakeSsh) {
+    let web = App::new("web")
+        .image("nginx:1.27")
+        .expose(80)
+        .healthcheck("true");
+    let dir = std::env::temp_dir().join(format!("catapulta-docker-save-{name}"));
+    Pipeline::new(web.clone(), caddy.reverse_proxy(web.upstream()))
+        .deploy(deployer)
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+}
+
+#[test]
+fn bind_mounted_files_are_written_in_place() {
+    let fake = FakeSsh::new();
+    let web = App::new("web").image("nginx:1.27").expose(80).config_file(
+        "nginx.conf",
+        "events {}",
+        "/etc/nginx/nginx.conf",
+    );
+    let dir = std::env::temp_dir().join("catapulta-docker-save-in-place");
+    Pipeline::new(web.clone(), Caddy::new().reverse_proxy(web.upstream()))
+        .deploy(DockerSaveLoad::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    assert_eq!(fake.file_in_place("/opt/app/Caddyfile"), Some(true));
+    assert_eq!(fake.file_in_place("/opt/app/nginx.conf"), Some(true));
+    assert_eq!(
+        fake.file_in_place("/opt/app/docker-compose.yml"),
+        Some(false)
+    );
+}
+
+#[test]
+fn transfer_defaults_to_auto() {
+    assert_eq!(DockerSaveLoad::new().transfer, Transfer::Auto);
+    assert_eq!(DockerSaveLoad::default().transfer, Transfer::Auto);
+}
+
+#[test]
+fn transfer_builder() {
+    let deployer = DockerSaveLoad::new().transfer(Transfer::Scp);
+    assert_eq!(deployer.transfer, Transfer::Scp);
+}
+
+#[test]
+fn transfer_stream_with_zstd() {
+    let deployer = DockerSaveLoad::new().transfer(Transfer::Stream { zstd: true });
+    assert_eq!(deployer.transfer, Transfer::Stream { zstd: true });
+}
+
+#[test]
+fn tarball_compression() {
+    assert_eq!(DockerSaveLoad::new().compression, Compression::Auto);
+    let deployer = DockerSaveLoad::new()
+        .transfer(Transfer::Rsync)
+        .compression(Compression::Gzip);
+    assert_eq!(deployer.compression, Compression::Gzip);
+
+    assert_eq!(
+        Compression::Zstd.load_command("/tmp/web.tar.zst", Runtime::Docker),
+        "zstd -dc /tmp/web.tar.zst | docker load && rm -f /tmp/web.tar.zst"
+    );
+    assert_eq!(
+        Compression::Gzip.load_command("/tmp/web.tar.gz", Runtime::Podman),
+        "gzip -dc /tmp/web.tar.gz | podman load && rm -f /tmp/web.tar.gz"
+    );
+    assert_eq!(
+        Compression::None.load_command("/tmp/web.tar", Runtime::Docker),
+        "docker load < /tmp/web.tar && rm -f /tmp/web.tar"
+    );
+    assert_eq!(Compression::Zstd.extension(), ".zst");
+    assert_eq!(Compression::None.program(), None);
+}
+
+#[test]
+fn transfer_tuning_builders() {
+    let deployer = DockerSaveLoad::new()
+        .bwlimit(1024)
+        .cipher("aes128-gcm@openssh.com")
+        .compression_level(0);
+
+    assert_eq!(deployer.bwlimit, Some(1024));
+    assert_eq!(deployer.cipher.as_deref(), Some("aes128-gcm@openssh.com"));
+    assert_eq!(deployer.compression_level, Some(0));
+    assert_eq!(DockerSaveLoad::new().bwlimit, None);
+}
+
+#[test]
+fn unhealthy_report_shows_logs_and_healthchecks() {
+    let command = unhealthy_report_command("web");
+
+    assert!(command.contains("docker compose logs --no-color --tail 50 web"));
+    assert!(command.contains(
+        "--format='{{range .State.Health.Log}}exit {{.ExitCode}}: {{.Output}}{{end}}' \
+         $(docker compose ps -q web)"
+    ));
+    assert!(command.ends_with("; true"));
+}
+
+#[test]
+fn healthy_wait_builders() {
+    let deployer = DockerSaveLoad::new().healthy_timeout(Duration::from_secs(300));
+    assert_eq!(deployer.health_wait.attempts, 60);
+
+    let deployer = DockerSaveLoad::new()
+        .healthy_interval(Duration::from_secs(2))
+        .healthy_timeout(Duration::from_secs(61));
+    assert_eq!(deployer.health_wait.attempts, 31);
+    assert_eq!(deployer.health_wait.interval, Duration::from_secs(2));
+
+    assert_eq!(
+        DockerSaveLoad::new()
+            .healthy_attempts(3)
+            .health_wait
+            .attempts,
+        3
+    );
+    assert_eq!(DockerSaveLoad::new().health_wait, HealthWait::default());
+}
+
+#[test]
+fn deploy_recreates_services_without_stopping_the_stack() {
+    let fake = FakeSsh::new()
+        .respond("docker inspect", "healthy\n")
+        .respond("cat '/opt/app/Caddyfile'", "previous.example.com {\n}");
+
+    deploy("up", Caddy::new(), &fake);
+
+    let commands = fake.commands();
+    let up = commands
+        .iter()
+        .position(|c| c == "cd /opt/app && docker compose up -d --remove-orphans")
+        .unwrap();
+    assert_eq!(
+        commands[up + 1],
+        format!(
+            "cd /opt/app && {}",
+            caddy_reload_command(
+                "docker compose",
+                "caddy reload --config /etc/caddy/Caddyfile --adapter caddyfile"
+            )
+        )
+    );
+    assert!(
+        !commands
+            .iter()
+            .any(|c| c.contains("docker compose down") || c.contains("docker compose rm"))
+    );
+}
+
+#[test]
+fn unchanged_caddyfile_is_not_reloaded() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+
+    deploy("no-reload", Caddy::new(), &fake);
+
+    assert!(!fake.commands().iter().any(|c| c.contains("caddy reload")));
+}
+
+#[test]
+fn failed_caddy_reload_fails_the_deploy() {
+    let fake = FakeSsh::new()
+        .respond("docker inspect", "healthy\n")
+        .respond("cat '/opt/app/Caddyfile'", "previous.example.com {\n}")
+        .fail("caddy reload", 1);
+    let web = App::new("web").image("nginx:1.27").expose(80);
+    let dir = std::env::temp_dir().join("catapulta-docker-save-reload-fails");
+
+    let err = Pipeline::new(web.clone(), Caddy::new().reverse_proxy(web.upstream()))
+        .deploy(DockerSaveLoad::new())
+        .ssh_options(SshOptions::new().transport(fake))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap_err();
+
+    assert!(err.to_string().contains("caddy reload"), "{err}");
+}
+
+#[test]
+fn changed_config_file_recreates_its_app() {
+    let fake = FakeSsh::new()
+        .respond("docker inspect", "healthy\n")
+        .respond(
+            "cat '/opt/app/nginx.conf'",
+            "events { worker_connections 512; }",
+        );
+    let web = App::new("web").image("nginx:1.27").expose(80).config_file(
+        "nginx.conf",
+        "events {}",
+        "/etc/nginx/nginx.conf",
+    );
+    let dir = std::env::temp_dir().join("catapulta-docker-save-config-changed");
+
+    Pipeline::new(web.clone(), Caddy::new().reverse_proxy(web.upstream()))
+        .deploy(DockerSaveLoad::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    let recreate = commands
+        .iter()
+        .position(|c| c == "cd /opt/app && docker compose up -d --no-deps --force-recreate web")
+        .unwrap();
+    let up = commands
+        .iter()
+        .position(|c| c == "cd /opt/app && docker compose up -d --remove-orphans")
+        .unwrap();
+    assert!(recreate < up);
+}
+
+#[test]
+fn maintenance_page_stops_the_apps_before_the_transfer() {
+    let page = std::env::temp_dir().join("catapulta-docker-save-maintenance.html");
+    std::fs::write(&page, "<h1>Back soon</h1>").unwrap();
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+
+    deploy(
+        "maintenance",
+        Caddy::new().maintenance_page(page.to_str().unwrap()),
+        &fake,
+    );
+
+    let commands = fake.commands();
+    let stop = commands
+        .iter()
+        .position(|c| c.contains("docker compose rm -sf web"))
+        .unwrap();
+    let up = commands
+        .iter()
+        .position(|c| c.contains("docker compose up -d"))
+        .unwrap();
+    assert!(stop < up);
+}
+
+#[test]
+fn status_includes_stopped_containers() {
+    let command = health_status_command("web");
+
+    assert!(command.contains("{{else if gt .RestartCount 0}}restarting"));
+    assert!(command.ends_with("$(docker compose ps -aq web)"));
+}
+
+#[test]
+fn exited_container_fails_with_its_logs() {
+    let apps = [App::new("web"), App::new("worker")];
+    let reported = std::cell::RefCell::new(Vec::new());
+
+    let err = wait_healthy_or_report(
+        &apps,
+        &HealthWait::new(),
+        |name| {
+            Ok(if name == "web" {
+                "running\n"
+            } else {
+                "exited\n"
+            }
+            .to_string())
+        },
+        |name| {
+            reported.borrow_mut().push(name.to_string());
+            Ok("panic: no database\n".to_string())
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err.code(), "E606");
+    assert_eq!(*reported.borrow(), ["worker"]);
+}
+
+#[test]
+fn completed_container_is_not_a_crash() {
+    let apps = [App::new("migrate")];
+
+    wait_healthy_or_report(
+        &apps,
+        &HealthWait::new(),
+        |_| Ok("completed\n".to_string()),
+        |_| panic!("no report for a completed container"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn podman_runtime_runs_podman_compose() {
+    let fake = FakeSsh::new()
+        .respond("podman inspect", "healthy\n")
+        .respond("cat '/opt/app/Caddyfile'", "previous.example.com {\n}\n");
+    let deployer = DockerSaveLoad::new().runtime(Runtime::Podman);
+
+    deploy_with("podman", Caddy::new(), deployer, &fake);
+
+    let commands = fake.commands();
+    assert!(commands[0].contains("command -v podman"));
+    assert!(commands.contains(&"cd /opt/app && podman-compose up -d --remove-orphans".to_string()));
+    assert!(commands.contains(&"cd /opt/app && podman-compose ps".to_string()));
+    assert!(
+        commands
+            .iter()
+            .any(|c| c.contains("podman-compose exec -T caddy caddy reload"))
+    );
+    assert!(!commands.iter().any(|c| c.contains("docker compose")));
+}
+
+#[test]
+fn podman_health_commands_find_containers_by_label() {
+    let command = Runtime::Podman.health_status_command("web");
+
+    assert!(command.starts_with("podman inspect --format="));
+    assert!(command.contains("podman ps -aq"));
+    assert!(command.contains("label=com.docker.compose.project.working_dir=$(pwd -P)"));
+    assert!(command.contains("--filter label=com.docker.compose.service=web)"));
+
+    let report = Runtime::Podman.unhealthy_report_command("web");
+    assert!(report.contains("podman logs --tail 50 $(podman ps -q"));
+    assert!(!report.contains("docker compose"));
+    assert_eq!(
+        Runtime::Docker.health_status_command("web"),
+        health_status_command("web")
+    );
+}
+
+#[test]
+fn image_ids_compare_across_runtimes() {
+    assert!(same_image("sha256:4f1c2d", "sha256:4f1c2d\n"));
+    // Podman prints ids without the prefix
+    assert!(same_image("sha256:4f1c2d", "4f1c2d"));
+    assert!(!same_image("sha256:4f1c2d", "sha256:9a0b1e"));
+    assert!(!same_image("", ""));
+}
+
+#[test]
+fn signing_builder() {
+    let deployer = DockerSaveLoad::new().sign(Signer::minisign("minisign.key", "minisign.pub"));
+    assert_eq!(
+        deployer.signer.map(|s| s.public_key).as_deref(),
+        Some("minisign.pub")
+    );
+    assert_eq!(DockerSaveLoad::new().signer, None);
+}
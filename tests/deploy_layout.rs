@@ -0,0 +1,175 @@
+use catapulta::deploy::Deployer;
+use catapulta::deploy::layout::{self, MANIFEST};
+use catapulta::ssh::{SshOptions, SshSession};
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+
+fn env_file(name: &str) -> String {
+    let dir = std::env::temp_dir().join("catapulta-layout");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!(".env.{name}"));
+    std::fs::write(&path, "A=1\n").unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn env_file_follows_the_app_dir() {
+    let api = App::new("api").remote_dir("services/api/");
+    let worker = App::new("worker");
+    let apps = [api, worker];
+
+    assert_eq!(layout::env_file(&apps[0], &apps), "services/api/.env");
+    assert_eq!(layout::env_file(&apps[1], &apps), ".env.worker");
+    assert_eq!(layout::env_file(&apps[1], &apps[1..]), ".env");
+    assert_eq!(layout::app_dirs(&apps), ["services/api"]);
+}
+
+#[test]
+fn stale_files_stay_inside_the_stack() {
+    let previous = ".env.api\napi/.env\n/etc/passwd\n../other/.env\ndocker-compose.yml\n";
+    let current = ["docker-compose.yml".to_string(), "api/.env".to_string()];
+
+    assert_eq!(layout::stale_files(previous, &current), [".env.api"]);
+}
+
+#[test]
+fn app_dirs_hold_env_and_config_files() {
+    let api = App::new("api")
+        .expose(8000)
+        .healthcheck("true")
+        .env_file(&env_file("api"))
+        .config_file("app.toml", "debug = false\n", "/etc/api/app.toml");
+    let worker = App::new("worker")
+        .healthcheck("true")
+        .env_file(&env_file("worker"));
+    let caddy = Caddy::new().reverse_proxy(api.upstream());
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+    let apps: Vec<App> = [api, worker]
+        .into_iter()
+        .map(|a| {
+            let name = a.name.clone();
+            a.remote_dir(&name)
+        })
+        .collect();
+
+    DockerSaveLoad::new()
+        .deploy(&ssh, &apps, &caddy, "/opt/shop", &[])
+        .unwrap();
+
+    assert!(
+        fake.commands()
+            .contains(&"mkdir -p '/opt/shop/api' '/opt/shop/worker'".to_string())
+    );
+    assert_eq!(fake.file("/opt/shop/api/.env").as_deref(), Some("A=1\n"));
+    assert!(fake.file("/opt/shop/worker/.env").is_some());
+    assert!(fake.file("/opt/shop/api/app.toml").is_some());
+    let compose = fake.file("/opt/shop/docker-compose.yml").unwrap();
+    assert!(compose.contains("env_file: api/.env"));
+    assert!(compose.contains("./api/app.toml:/etc/api/app.toml:ro"));
+    assert_eq!(
+        fake.file(&format!("/opt/shop/{MANIFEST}")).as_deref(),
+        Some("docker-compose.yml\nCaddyfile\napi/app.toml\napi/.env\nworker/.env\n")
+    );
+}
+
+#[test]
+fn deploy_removes_files_of_the_previous_layout() {
+    let api = App::new("api")
+        .expose(8000)
+        .healthcheck("true")
+        .env_file(&env_file("api"))
+        .remote_dir("api");
+    let fake = FakeSsh::new()
+        .respond(
+            &format!("cat '/opt/app/{MANIFEST}'"),
+            "docker-compose.yml\n.env\nconfig/old.conf\n",
+        )
+        .respond("docker inspect", "healthy\n");
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+
+    DockerSaveLoad::new()
+        .deploy(&ssh, &[api], &Caddy::new(), "/opt/app", &[])
+        .unwrap();
+
+    assert!(
+        fake.commands().contains(
+            &"rm -f '/opt/app/.env' && rm -f '/opt/app/config/old.conf' && \
+          (rmdir '/opt/app/config' 2>/dev/null || true)"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn project_names_the_remote_dir() {
+    let fake = FakeSsh::new();
+    let deployer = MockDeployer::new();
+    let web = App::new("web").image("nginx:1.27").expose(80);
+    let dir = std::env::temp_dir().join("catapulta-layout-project");
+    let pipeline = Pipeline::new(web, Caddy::new())
+        .project("blog")
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake))
+        .local_dir(dir.to_str().unwrap());
+
+    pipeline.run_from(["xtask", "deploy", "web1"]).unwrap();
+
+    assert!(
+        deployer
+            .calls()
+            .contains(&"deploy web1 /opt/blog".to_string())
+    );
+}
+
+#[test]
+fn app_dirs_keep_explicit_dirs() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+    let api = App::new("api")
+        .image("api:1")
+        .healthcheck("true")
+        .env_file(".env.api")
+        .remote_dir("backend");
+    let web = App::new("web")
+        .image("web:1")
+        .healthcheck("true")
+        .env_file(".env.web");
+    let dir = std::env::temp_dir().join("catapulta-layout-app-dirs");
+    let pipeline = Pipeline::multi(vec![api, web], Caddy::new())
+        .app_dirs()
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap());
+
+    pipeline
+        .run_from(["xtask", "scale", "web1", "web=1"])
+        .unwrap();
+
+    let yaml = fake.file("/opt/app/docker-compose.yml").unwrap();
+    assert!(yaml.contains("env_file: backend/.env"));
+    assert!(yaml.contains("env_file: web/.env"));
+}
+
+#[test]
+fn secret_env_writes_the_compose_file_owner_only() {
+    let api = App::new("api")
+        .expose(8000)
+        .healthcheck("true")
+        .env_secret("API_KEY", "layout-secret-api-key");
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+
+    DockerSaveLoad::new()
+        .deploy(&ssh, &[api], &Caddy::new(), "/opt/app", &[])
+        .unwrap();
+
+    assert_eq!(fake.file_mode("/opt/app/docker-compose.yml"), Some(0o600));
+    assert!(
+        !fake
+            .commands()
+            .iter()
+            .any(|c| c.contains("layout-secret-api-key"))
+    );
+}
@@ -0,0 +1,134 @@
+use catapulta::deploy::lint::{self, COMPOSE_FILE};
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline, caddyfile, compose};
+
+#[test]
+fn rendered_files_are_clean() {
+    let api = App::new("api").expose(8000).volume("data", "/data");
+    let docs = App::new("docs").expose(80).domain("docs.example.com");
+    let caddy = Caddy::new()
+        .route("/api/*", api.upstream())
+        .gzip()
+        .security_headers();
+    let apps = [api, docs];
+
+    assert!(lint::compose(&compose::render(&apps, &caddy)).is_empty());
+    assert!(lint::caddyfile(&caddyfile::render_with_apps(&caddy, "example.com", &apps)).is_empty());
+}
+
+#[test]
+fn caddyfile_problems() {
+    assert_eq!(
+        lint::caddyfile("example.com {\n\trespond \"hi\n}\n"),
+        ["unterminated quoted string at line 2, column 10"]
+    );
+    assert_eq!(
+        lint::caddyfile("example.com {\n\treverse_proxy web:80\n\na.example.com {\n}\n").len(),
+        1
+    );
+    assert_eq!(
+        lint::caddyfile(
+            "example.com {\n\thandle @api {\n\t\treverse_proxy api:8000\n\t}\n}\n\
+             example.com {\n}\n"
+        ),
+        [
+            "matcher @api of site example.com is not defined",
+            "site example.com is defined twice"
+        ]
+    );
+}
+
+#[test]
+fn placeholders_and_heredoc_status_are_valid() {
+    assert!(
+        lint::caddyfile(
+            "example.com {\n\t@down expression {err.status_code} in [502]\n\
+             \tredir https://www.{host}{uri}\n}\n"
+        )
+        .is_empty()
+    );
+    assert!(
+        lint::caddyfile("example.com {\n\trespond <<HTML\n<p>brb</p>\nHTML 503\n}\n").is_empty()
+    );
+    assert_eq!(
+        lint::caddyfile("example.com {\n\trespond <<HTML\n<p>brb</p>\n}\n"),
+        ["unterminated heredoc, expected closing marker: HTML at line 2, column 10"]
+    );
+}
+
+#[test]
+fn compose_problems() {
+    let yaml = "\
+services:
+  web:
+    ports: ['8080:80', '53:53/udp']
+    depends_on: [db]
+    networks: [front]
+    volumes: ['data:/data', './conf:/etc/conf', '/srv:/srv']
+  Bad Name:
+    ports: ['127.0.0.1:8080:8080', '53:53']
+networks:
+  back: {}
+";
+
+    assert_eq!(
+        lint::compose(yaml),
+        [
+            "service web depends on undefined service db",
+            "service web uses undefined network front",
+            "service web uses undefined volume data",
+            "invalid service name 'Bad Name'",
+            "host port 8080/tcp is published by both web and Bad Name",
+        ]
+    );
+    assert_eq!(lint::lint(COMPOSE_FILE, "services: [").len(), 1);
+    assert_eq!(lint::lint("caddy.json", "[]"), ["not a JSON object"]);
+}
+
+#[test]
+fn broken_directive_fails_deploy_before_the_server() {
+    let fake = FakeSsh::new();
+    let deployer = MockDeployer::new();
+    let web = App::new("web").image("nginx:1.27").expose(80);
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .directive("header {");
+    let dir = std::env::temp_dir().join("catapulta-lint-deploy");
+    let pipeline = Pipeline::new(web, caddy)
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap());
+
+    let err = pipeline.run_from(["xtask", "deploy", "web1"]).unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+    assert!(err.to_string().contains("Caddyfile: "));
+    assert!(fake.calls().is_empty());
+    assert!(deployer.calls().is_empty());
+
+    let dry_run = pipeline.run_from(["xtask", "deploy", "web1", "--dry-run"]);
+    assert_eq!(dry_run.unwrap_err().code(), "E503");
+}
+
+#[test]
+fn broken_directive_fails_deploy_local_before_writing_files() {
+    let web = App::new("web").image("nginx:1.27").expose(80);
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .directive("header {");
+    let dir = std::env::temp_dir().join("catapulta-lint-deploy-local");
+    let _ = std::fs::remove_dir_all(&dir);
+    let pipeline = Pipeline::new(web, caddy).local_dir(dir.to_str().unwrap());
+
+    let err = pipeline
+        .run_from(["xtask", "deploy-local", "app.test", "--skip-build"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+    assert!(err.to_string().contains("Caddyfile: "));
+    assert!(!dir.join(COMPOSE_FILE).exists());
+
+    let dry_run = pipeline.run_from(["xtask", "deploy-local", "app.test", "--dry-run"]);
+    assert_eq!(dry_run.unwrap_err().code(), "E503");
+}
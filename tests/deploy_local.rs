@@ -0,0 +1,68 @@
+use catapulta::deploy::local::{LocalDeploy, dev_apps};
+use catapulta::{App, Caddy, Pipeline, compose};
+
+#[test]
+fn dev_publishes_exposed_ports() {
+    let api = App::new("api").expose(3000).expose(9090).port(9090, 9090);
+    let web = App::new("web").expose(3000).domain("www.example.com");
+
+    let apps = dev_apps(&[api, web]);
+
+    assert_eq!(apps[0].ports, [(9090, 9090), (3000, 3000)]);
+    // 3000 is taken by api
+    assert_eq!(apps[1].ports, [(3001, 3000)]);
+    assert_eq!(apps[1].domain, None);
+}
+
+#[test]
+fn dev_mounts_dev_volumes_absolute() {
+    let api = App::new("api")
+        .volume("api-data", "/data")
+        .dev_volume("./src", "/app/src");
+
+    let apps = dev_apps(std::slice::from_ref(&api));
+
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(apps[0].volumes[0], api.volumes[0]);
+    assert_eq!(
+        apps[0].volumes[1],
+        (
+            cwd.join("src").to_string_lossy().into_owned(),
+            "/app/src".to_string()
+        )
+    );
+    assert!(apps[0].dev_volumes.is_empty());
+    // Deploys leave them out
+    assert!(!compose::render(&[api], &Caddy::new()).contains("/app/src"));
+}
+
+#[test]
+fn plain_http_drops_tls() {
+    let caddy = Caddy::new();
+
+    let local = LocalDeploy::new();
+    assert!(local.caddy(&caddy).tls_internal);
+    assert_eq!(local.site("app.test"), "app.test");
+
+    let dev = LocalDeploy::new().plain_http();
+    assert!(!dev.caddy(&caddy).tls_internal);
+    assert_eq!(dev.site("localhost"), "http://localhost");
+}
+
+#[test]
+fn broken_directive_fails_dev_before_docker() {
+    let web = App::new("web").image("nginx:1.27").expose(80);
+    let caddy = Caddy::new()
+        .reverse_proxy(web.upstream())
+        .directive("header {");
+    let dir = std::env::temp_dir().join("catapulta-dev-lint");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let err = Pipeline::new(web, caddy)
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "dev", "--skip-build"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+    assert!(!dir.join("docker-compose.yml").exists());
+}
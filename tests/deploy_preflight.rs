@@ -0,0 +1,328 @@
+use catapulta::deploy::Runtime;
+use catapulta::deploy::preflight::{self, DiskFree};
+use catapulta::error::DeployError;
+use catapulta::ssh::{SshOptions, SshSession};
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+const DF: &str = "\
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+tmpfs              1048576      512   1048064       1% /tmp
+/dev/vda1         25215872 20123456   3145728      87% /
+";
+
+fn disk(mount: &str, gib: u64) -> DiskFree {
+    DiskFree {
+        mount: mount.to_string(),
+        size: 24 * GIB,
+        available: gib * GIB,
+    }
+}
+
+#[test]
+fn parse_df_output() {
+    let free = preflight::parse_df(DF);
+
+    assert_eq!(free.len(), 2);
+    assert_eq!(free[0].mount, "/tmp");
+    assert_eq!(free[0].available, 1_048_064 * 1024);
+    assert_eq!(free[1].mount, "/");
+    assert_eq!(free[1].size, 25_215_872 * 1024);
+    assert_eq!(free[1].available, 3 * GIB);
+}
+
+#[test]
+fn needs_on_one_filesystem_add_up() {
+    let free = [disk("/", 3), disk("/", 3)];
+
+    assert!(preflight::check(&[("a", GIB), ("b", 2 * GIB)], &free).is_ok());
+
+    let err = preflight::check(&[("a", 2 * GIB), ("b", 2 * GIB)], &free).unwrap_err();
+    assert!(matches!(
+        err,
+        DeployError::InsufficientDiskSpace { ref location, needed, .. }
+            if location == "a and b" && needed == 4 * GIB
+    ));
+    assert_eq!(err.code(), "E602");
+}
+
+#[test]
+fn remote_tmp_too_small() {
+    let fake = FakeSsh::new().respond("df -Pk", DF);
+    let ssh = SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake));
+
+    let err = preflight::check_remote(&ssh, 2 * GIB, false).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "not enough disk space in remote /tmp: 2048 MB needed, 1023 MB available"
+    );
+    assert!(err.hint().unwrap().contains("Transfer::Stream"));
+
+    // Streaming skips /tmp
+    let root = DF.replace(
+        "tmpfs              1048576      512   1048064       1% /tmp\n",
+        "",
+    );
+    let fake = FakeSsh::new().respond("df -Pk", &root);
+    let ssh = SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake));
+    assert!(preflight::check_remote(&ssh, 2 * GIB, true).is_ok());
+}
+
+#[test]
+fn unreadable_df_is_skipped() {
+    let fake = FakeSsh::new().fail("df -Pk", 1);
+    let ssh = SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake));
+
+    assert!(preflight::check_remote(&ssh, 100 * GIB, false).is_ok());
+}
+
+#[test]
+fn host_setup_problems() {
+    let ready = "docker=yes\ndaemon=yes\ncompose=2.24.5\ndir=yes\n";
+    assert_eq!(preflight::parse_host_setup(ready).problem("/opt/app"), None);
+
+    let fresh = "docker=no\ndaemon=no\ncompose=\ndir=no\n";
+    assert_eq!(
+        preflight::parse_host_setup(fresh)
+            .problem("/opt/app")
+            .unwrap(),
+        "Docker is not installed"
+    );
+
+    let no_plugin = "docker=yes\ndaemon=yes\ncompose=\ndir=yes\n";
+    assert!(
+        preflight::parse_host_setup(no_plugin)
+            .problem("/opt/app")
+            .unwrap()
+            .contains("compose plugin")
+    );
+
+    let old = "docker=yes\ndaemon=yes\ncompose=v1.29.2\ndir=yes\n";
+    assert!(
+        preflight::parse_host_setup(old)
+            .problem("/opt/app")
+            .unwrap()
+            .contains("too old")
+    );
+
+    // Unreadable output checks nothing
+    assert_eq!(preflight::parse_host_setup("").problem("/opt/app"), None);
+}
+
+#[test]
+fn podman_host_setup_problems() {
+    let command = preflight::host_setup_command("/opt/app", Runtime::Podman);
+    assert!(command.starts_with("echo runtime=podman; "));
+    assert!(command.contains("command -v podman"));
+    assert!(command.contains("podman-compose version --short"));
+    assert!(!preflight::host_setup_command("/opt/app", Runtime::Docker).contains("podman"));
+
+    // podman-compose 1.x is current
+    let ready = "runtime=podman\ndocker=yes\ndaemon=yes\ncompose=1.0.6\ndir=yes\n";
+    let setup = preflight::parse_host_setup(ready);
+    assert_eq!(setup.runtime, Runtime::Podman);
+    assert_eq!(setup.problem("/opt/app"), None);
+
+    let fresh = "runtime=podman\ndocker=no\ndaemon=no\ncompose=\ndir=yes\n";
+    assert_eq!(
+        preflight::parse_host_setup(fresh)
+            .problem("/opt/app")
+            .unwrap(),
+        "Podman is not installed"
+    );
+
+    let no_compose = "runtime=podman\ndocker=yes\ndaemon=yes\ncompose=\ndir=yes\n";
+    assert_eq!(
+        preflight::parse_host_setup(no_compose)
+            .problem("/opt/app")
+            .unwrap(),
+        "podman-compose is not installed"
+    );
+}
+
+#[test]
+fn deploy_stops_on_unprepared_host() {
+    let fake = FakeSsh::new().respond(
+        "docker compose version",
+        "docker=yes\ndaemon=yes\ncompose=2.24.5\ndir=no\n",
+    );
+    let deployer = MockDeployer::new();
+    let web = App::new("web").expose(80);
+    let dir = std::env::temp_dir().join("catapulta-preflight-host");
+
+    let err = Pipeline::new(web, Caddy::new())
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E603");
+    assert!(err.to_string().contains("/opt/app does not exist"));
+    assert!(err.hint().unwrap().contains("cargo xtask provision"));
+    assert!(deployer.calls().is_empty());
+    assert_eq!(fake.commands().len(), 1);
+}
+
+#[test]
+fn deploy_stops_on_other_architecture() {
+    let fake = FakeSsh::new().respond(
+        "docker compose version",
+        "docker=yes\ndaemon=yes\ncompose=2.24.5\ndir=yes\nmachine=aarch64\n",
+    );
+    let deployer = MockDeployer::new();
+    let web = App::new("web").expose(80);
+    let db = App::new("db").image("postgres:16");
+    let dir = std::env::temp_dir().join("catapulta-preflight-arch");
+
+    let err = Pipeline::multi(vec![web, db], Caddy::new())
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E604");
+    assert_eq!(
+        err.root().to_string(),
+        "app 'web' is built for linux/amd64, but the server is aarch64 (linux/arm64)"
+    );
+    assert!(err.hint().unwrap().contains("App::platform"));
+    assert!(deployer.calls().is_empty());
+}
+
+#[test]
+fn matching_architecture_passes() {
+    let setup = preflight::parse_host_setup("docker=yes\nmachine=aarch64\n");
+    let arm = App::new("web").platform("linux/arm64");
+
+    assert_eq!(setup.machine.as_deref(), Some("aarch64"));
+    assert!(preflight::check_platforms(&[&arm], "aarch64").is_ok());
+    assert_eq!(preflight::parse_host_setup("machine=\n").machine, None);
+}
+
+const CONTAINERS: &str = "\
+web|web:latest|app|/opt/app|0.0.0.0:8080->8080/tcp, :::8080->8080/tcp
+billing-caddy|caddy:2-alpine|billing|/opt/billing|0.0.0.0:80->80/tcp, 0.0.0.0:443->443/tcp
+postgres|postgres:17|||5432/tcp
+";
+
+#[test]
+fn parse_container_list() {
+    let containers = preflight::parse_containers(CONTAINERS);
+
+    assert_eq!(containers.len(), 3);
+    assert_eq!(containers[0].ports, vec![8080]);
+    assert_eq!(containers[1].working_dir, "/opt/billing");
+    assert_eq!(containers[1].ports, vec![80, 443]);
+    assert_eq!(containers[2].project, "");
+    assert!(containers[2].ports.is_empty());
+}
+
+#[test]
+fn stacks_in_other_directories_collide() {
+    let containers = preflight::parse_containers(CONTAINERS);
+    let web = App::new("web").expose(8080);
+    let db = App::new("postgres").image("postgres:17");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let apps = [web, db];
+
+    let footprint = preflight::Footprint::new(&apps, &caddy, "/srv/shop");
+    assert_eq!(footprint.project, "shop");
+    assert_eq!(footprint.images, vec!["web:latest"]);
+    assert_eq!(
+        footprint.collisions(&containers, "/srv/shop"),
+        vec![
+            "container name web is taken by the stack in /opt/app",
+            "image web:latest is used by the stack in /opt/app",
+            "port 80 is published by the stack in /opt/billing",
+            "port 443 is published by the stack in /opt/billing",
+            "container name postgres is taken by container postgres",
+        ]
+    );
+    // Redeploying the same stack collides with nothing
+    let own = preflight::Footprint::new(&apps[..1], &Caddy::new(), "/opt/app");
+    assert!(own.collisions(&containers, "/opt/app/").is_empty());
+}
+
+#[test]
+fn project_prefixes_container_names() {
+    let db = App::new("postgres").image("postgres:17");
+
+    let footprint = catapulta::compose::with_project(Some("billing"), || {
+        preflight::Footprint::new(&[db], &Caddy::new(), "/opt/app")
+    });
+
+    assert_eq!(footprint.project, "billing");
+    assert_eq!(footprint.containers, vec!["billing-postgres"]);
+    assert_eq!(preflight::default_project("/opt/My.App/"), "myapp");
+}
+
+#[test]
+fn deploy_stops_on_collision() {
+    let fake = FakeSsh::new().respond("docker ps -a", CONTAINERS);
+    let deployer = MockDeployer::new();
+    let dir = std::env::temp_dir().join("catapulta-preflight-collision");
+
+    let err = Pipeline::new(App::new("postgres").image("postgres:17"), Caddy::new())
+        .remote_dir("/opt/db")
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E605");
+    assert!(err.to_string().contains("postgres"));
+    assert!(err.hint().unwrap().contains("Pipeline::project"));
+    assert!(deployer.calls().is_empty());
+}
+
+#[test]
+fn compose_check_output() {
+    assert_eq!(preflight::parse_compose_check("\nstatus=0\n"), None);
+    assert_eq!(
+        preflight::parse_compose_check(
+            "service \"web\" refers to undefined volume data: invalid compose project\nstatus=15\n"
+        )
+        .as_deref(),
+        Some("service \"web\" refers to undefined volume data: invalid compose project")
+    );
+    assert_eq!(preflight::parse_compose_check(""), None);
+}
+
+#[test]
+fn rejected_compose_file_keeps_the_stack_running() {
+    let fake = FakeSsh::new().respond(
+        "config -q",
+        "yaml: line 3: mapping values are not allowed in this context\nstatus=15\n",
+    );
+    let deployer = MockDeployer::new();
+    let web = App::new("web")
+        .image("nginx:1.27")
+        .expose(80)
+        .env_file(".env.web");
+    let dir = std::env::temp_dir().join("catapulta-preflight-compose");
+    let pipeline = Pipeline::new(web, Caddy::new())
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap());
+
+    let err = pipeline.run_from(["xtask", "deploy", "web1"]).unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+    assert!(err.to_string().contains("mapping values are not allowed"));
+    let checked = fake
+        .file(&format!("/opt/app/{}", preflight::COMPOSE_CHECK_FILE))
+        .unwrap();
+    assert!(!checked.contains("env_file"));
+    assert!(
+        !fake
+            .commands()
+            .iter()
+            .any(|c| c.contains("docker compose down"))
+    );
+    assert!(deployer.calls().is_empty());
+}
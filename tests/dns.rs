@@ -1,4 +1,5 @@
-use catapulta::dns::split_domain;
+use catapulta::Cloudflare;
+use catapulta::dns::{is_wildcard, split_domain};
 
 #[test]
 fn split_fqdn() {
@@ -20,3 +21,22 @@ fn split_deep_subdomain() {
     assert_eq!(zone, "example.com");
     assert_eq!(sub, "a.b");
 }
+
+#[test]
+fn wildcard_domains() {
+    assert!(is_wildcard("*.example.com"));
+    assert!(is_wildcard("*.app.example.com"));
+    assert!(!is_wildcard("app.example.com"));
+    assert!(!is_wildcard("example.com"));
+}
+
+#[test]
+fn cloudflare_ttl_and_proxied() {
+    let cloudflare = Cloudflare::new("example.com");
+    assert_eq!(cloudflare.ttl, 300);
+    assert!(!cloudflare.proxied);
+
+    let cloudflare = Cloudflare::new("example.com").ttl(1).proxied(true);
+    assert_eq!(cloudflare.ttl, 1);
+    assert!(cloudflare.proxied);
+}
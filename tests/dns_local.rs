@@ -0,0 +1,69 @@
+use catapulta::dns::DnsProvider;
+use catapulta::dns::adguard::rewrite_answers;
+use catapulta::dns::duckdns::{update_name, update_url};
+use catapulta::dns::pihole::{Pihole, host_entries};
+
+#[test]
+fn pihole_entries_of_the_domain() {
+    let response = r#"{"config":{"dns":{"hosts":[
+        "192.168.1.10 api.home.arpa",
+        "192.168.1.11 web.home.arpa api.home.arpa",
+        "192.168.1.12 apis.home.arpa"
+    ]}},"took":0.001}"#;
+
+    assert_eq!(
+        host_entries(response, "api.home.arpa").unwrap(),
+        [
+            "192.168.1.10 api.home.arpa",
+            "192.168.1.11 web.home.arpa api.home.arpa"
+        ]
+    );
+}
+
+#[test]
+fn pihole_unexpected_response() {
+    let err = host_entries(r#"{"error":{"key":"unauthorized"}}"#, "api.home.arpa").unwrap_err();
+
+    assert_eq!(err.code(), "E401");
+}
+
+#[test]
+fn pihole_rejects_wildcards() {
+    let pihole = Pihole::new("http://pi.hole", "*.home.arpa");
+
+    let err = pihole.upsert_a_record("192.168.1.10").unwrap_err();
+
+    assert_eq!(err.code(), "E503");
+}
+
+#[test]
+fn adguard_answers_of_the_domain() {
+    let response = r#"[
+        {"domain":"api.lan","answer":"192.168.1.10","enabled":true},
+        {"domain":"web.lan","answer":"192.168.1.11","enabled":true},
+        {"domain":"api.lan","answer":"192.168.1.12","enabled":true}
+    ]"#;
+
+    assert_eq!(
+        rewrite_answers(response, "api.lan").unwrap(),
+        ["192.168.1.10", "192.168.1.12"]
+    );
+    assert!(rewrite_answers("[]", "api.lan").unwrap().is_empty());
+}
+
+#[test]
+fn duckdns_name_of_the_domain() {
+    assert_eq!(update_name("home.duckdns.org"), Some("home"));
+    assert_eq!(update_name("api.home.duckdns.org"), Some("home"));
+    assert_eq!(update_name("home.duckdns.org."), Some("home"));
+    assert_eq!(update_name("duckdns.org"), None);
+    assert_eq!(update_name("home.example.com"), None);
+}
+
+#[test]
+fn duckdns_update_url() {
+    assert_eq!(
+        update_url("home", "t0ken", "203.0.113.7"),
+        "https://www.duckdns.org/update?domains=home&token=t0ken&ip=203.0.113.7"
+    );
+}
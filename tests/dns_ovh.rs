@@ -1,4 +1,6 @@
-use catapulta::dns::ovh::{Ovh, OvhCredentials, parse_ini_value};
+use catapulta::dns::DnsProvider;
+use catapulta::dns::ovh::{Ovh, OvhCredentials, caa_target, cname_target, parse_ini_value};
+use catapulta::error::DeployError;
 
 #[test]
 fn parse_ovh_conf() {
@@ -135,3 +137,72 @@ fn ovh_domain() {
     let ovh = Ovh::new("app.example.com");
     assert_eq!(ovh.domain, "app.example.com");
 }
+
+fn test_creds() -> OvhCredentials {
+    OvhCredentials {
+        endpoint: "ovh-eu".into(),
+        application_key: "abc123".into(),
+        application_secret: "secret456".into(),
+        consumer_key: "ck789".into(),
+    }
+}
+
+#[test]
+fn sign_matches_ovh_scheme() {
+    let creds = OvhCredentials {
+        application_secret: "AS".into(),
+        consumer_key: "CK".into(),
+        ..test_creds()
+    };
+    let sig = Ovh::sign(
+        &creds,
+        "GET",
+        "https://eu.api.ovh.com/1.0/auth/time",
+        "",
+        "1",
+    )
+    .unwrap();
+    assert_eq!(sig, "$1$6cd733ff30b01603f6cda13b446fd2fbd4b7cb83");
+}
+
+#[test]
+fn sign_hostile_body_is_hashed_verbatim() {
+    let body = r#"{"subDomain":"it's $(touch /tmp/pwned) `id`"}"#;
+    let sig = Ovh::sign(
+        &test_creds(),
+        "POST",
+        "https://eu.api.ovh.com/1.0/domain/zone/example.com/record",
+        body,
+        "1700000000",
+    )
+    .unwrap();
+    assert_eq!(sig, "$1$7daa592b2f886b632f6a79002be21456287aad3b");
+}
+
+#[test]
+fn cname_targets_are_absolute() {
+    assert_eq!(cname_target("blog.pages.dev"), "blog.pages.dev.");
+    assert_eq!(cname_target("blog.pages.dev."), "blog.pages.dev.");
+}
+
+#[test]
+fn cname_on_zone_apex_is_rejected() {
+    let err = Ovh::new("example.com")
+        .upsert_cname_record("blog.pages.dev")
+        .unwrap_err();
+
+    assert!(matches!(err, DeployError::InvalidConfig(_)));
+}
+
+#[test]
+fn ttl_builder() {
+    assert_eq!(Ovh::new("example.com").ttl, 300);
+
+    let ovh = Ovh::new("example.com").ttl(60);
+    assert_eq!(ovh.ttl, 60);
+}
+
+#[test]
+fn caa_target_allows_the_issuer() {
+    assert_eq!(caa_target("letsencrypt.org"), "0 issue \"letsencrypt.org\"");
+}
@@ -0,0 +1,93 @@
+use catapulta::deploy::Deployer;
+use catapulta::deploy::envfile::{self, EnvDiff, parse_keys};
+use catapulta::ssh::{SshOptions, SshSession};
+use catapulta::testing::FakeSsh;
+use catapulta::{App, Caddy, DockerSaveLoad};
+
+#[test]
+fn keys_skip_comments_and_exports() {
+    let content = "\
+# database
+DATABASE_URL=postgres://db/app
+export RUST_LOG=info
+
+API_KEY = secret
+DATABASE_URL=postgres://other
+not a variable
+";
+
+    assert_eq!(parse_keys(content), ["DATABASE_URL", "RUST_LOG", "API_KEY"]);
+}
+
+#[test]
+fn diff_reports_dropped_added_and_missing_keys() {
+    let local = "A=1\nB=2\nD=4\n";
+    let remote = "A=1\nB=2\nC=3\n";
+    let example = "A=\nE=\n";
+
+    let diff = EnvDiff::new(local, Some(remote), Some(example));
+
+    assert_eq!(diff.removed, ["C"]);
+    assert_eq!(diff.added, ["D"]);
+    assert_eq!(diff.missing, ["E"]);
+    assert_eq!(
+        diff.warnings(".env"),
+        [
+            ".env drops C set on the server",
+            ".env lacks E from its example"
+        ]
+    );
+    // Values are never part of a warning
+    assert!(!diff.warnings(".env").concat().contains('='));
+}
+
+#[test]
+fn first_deploy_has_nothing_to_drop() {
+    let diff = EnvDiff::new("A=1\n", None, None);
+
+    assert_eq!(diff, EnvDiff::default());
+    assert!(diff.warnings(".env").is_empty());
+}
+
+#[test]
+fn example_next_to_env_file() {
+    let dir = std::env::temp_dir().join("catapulta-envfile-example");
+    std::fs::create_dir_all(&dir).unwrap();
+    let env = dir.join(".env.api");
+    let env = env.to_str().unwrap();
+    let _ = std::fs::remove_file(format!("{env}.example"));
+    std::fs::write(dir.join(".env.example"), "A=\n").unwrap();
+
+    assert_eq!(
+        envfile::example_path(env).as_deref(),
+        dir.join(".env.example").to_str()
+    );
+
+    std::fs::write(format!("{env}.example"), "A=\n").unwrap();
+    assert_eq!(envfile::example_path(env), Some(format!("{env}.example")));
+}
+
+#[test]
+fn deploy_reads_remote_env_before_replacing_it() {
+    let dir = std::env::temp_dir().join("catapulta-envfile-deploy");
+    std::fs::create_dir_all(&dir).unwrap();
+    let env = dir.join(".env");
+    std::fs::write(&env, "A=1\n").unwrap();
+    let app = App::new("web")
+        .expose(3000)
+        .healthcheck("true")
+        .env_file(env.to_str().unwrap());
+    let fake = FakeSsh::new()
+        .respond("cat '/opt/app/.env'", "A=1\nB=2\n")
+        .respond("docker inspect", "healthy\n");
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+
+    DockerSaveLoad::new()
+        .deploy(&ssh, &[app], &Caddy::new(), "/opt/app", &[])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert!(commands.contains(&"cat '/opt/app/.env' 2>/dev/null || true".to_string()));
+    assert_eq!(fake.file("/opt/app/.env").as_deref(), Some("A=1\n"));
+}
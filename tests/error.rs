@@ -1,4 +1,4 @@
-use catapulta::error::DeployError;
+use catapulta::error::{DeployError, DeployResult, ResultExt};
 
 #[test]
 fn display_command_not_found() {
@@ -12,6 +12,19 @@ fn display_ssh_failed() {
     assert_eq!(err.to_string(), "SSH connection failed: timeout");
 }
 
+#[test]
+fn display_remote_command_failed() {
+    let err = DeployError::RemoteCommandFailed {
+        command: "docker compose up -d".into(),
+        exit_status: 1,
+        stderr: String::new(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "remote command exited with status 1: docker compose up -d"
+    );
+}
+
 #[test]
 fn display_prerequisite_missing() {
     let err = DeployError::PrerequisiteMissing("doctl".into());
@@ -51,6 +64,35 @@ fn display_healthcheck_timeout() {
     );
 }
 
+#[test]
+fn display_container_exited() {
+    let err = DeployError::ContainerExited {
+        name: "worker".into(),
+        state: "restarting".into(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "container 'worker' stopped right after starting (restarting)"
+    );
+    assert_eq!(err.code(), "E606");
+    assert!(err.hint().unwrap().contains("docker compose up worker"));
+}
+
+#[test]
+fn display_image_checksum_mismatch() {
+    let err = DeployError::ImageChecksumMismatch {
+        url: "https://example.com/disk.img".into(),
+        expected: "aa".into(),
+        actual: "bb".into(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "checksum of https://example.com/disk.img is bb, expected aa"
+    );
+    assert_eq!(err.code(), "E306");
+    assert!(err.hint().unwrap().contains("image_sha256"));
+}
+
 #[test]
 fn display_other() {
     let err = DeployError::Other("custom error".into());
@@ -70,3 +112,187 @@ fn from_json_error() {
     let err: DeployError = json_err.into();
     assert!(matches!(err, DeployError::Json(_)));
 }
+
+#[test]
+fn display_host_key_mismatch() {
+    let err = DeployError::HostKeyMismatch {
+        host: "203.0.113.7".into(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "host key for 203.0.113.7 does not match the pinned key; \
+         rerun with --trust-new-hostkey if the server was rebuilt"
+    );
+}
+
+#[test]
+fn display_timeout() {
+    let err = DeployError::Timeout {
+        command: "curl -s https://eu.api.ovh.com/1.0/auth/time".into(),
+        timeout: std::time::Duration::from_secs(60),
+    };
+    assert_eq!(
+        err.to_string(),
+        "command timed out after 60s: curl -s https://eu.api.ovh.com/1.0/auth/time"
+    );
+}
+
+#[test]
+fn transient_errors() {
+    assert!(DeployError::SshFailed("10.0.0.5:22: Connection reset by peer".into()).is_transient());
+    assert!(DeployError::DnsError("HTTP 429 Too Many Requests".into()).is_transient());
+    assert!(DeployError::DnsError("OVH API GET /domain returned HTTP 502: ".into()).is_transient());
+    assert!(
+        DeployError::SshFailed("SSH handshake with host timed out after 10s".into()).is_transient()
+    );
+}
+
+#[test]
+fn permanent_errors() {
+    assert!(!DeployError::SshFailed("authentication failed for root@host".into()).is_transient());
+    assert!(!DeployError::DnsError("HTTP 403 Forbidden".into()).is_transient());
+    assert!(
+        !DeployError::HostKeyMismatch {
+            host: "host".into()
+        }
+        .is_transient()
+    );
+    assert!(!DeployError::FileNotFound(".env".into()).is_transient());
+    assert!(
+        !DeployError::Timeout {
+            command: "ssh root@host docker ps".into(),
+            timeout: std::time::Duration::from_secs(300),
+        }
+        .is_transient()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn transient_command_exit_codes() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    let failed = |command: &str, code: i32| DeployError::CommandFailed {
+        command: command.into(),
+        status: ExitStatus::from_raw(code << 8),
+    };
+    assert!(failed("rsync -vz --partial a b", 12).is_transient());
+    assert!(failed("ssh -o X root@host true", 255).is_transient());
+    assert!(!failed("rsync -vz a b", 1).is_transient());
+    assert!(!failed("docker build .", 255).is_transient());
+}
+
+fn remote_failure() -> DeployError {
+    DeployError::RemoteCommandFailed {
+        command: "docker compose up -d".into(),
+        exit_status: 1,
+        stderr: String::new(),
+    }
+}
+
+#[test]
+fn display_context() {
+    let err = remote_failure().context("deploy", Some("example.com"));
+    assert_eq!(
+        err.to_string(),
+        "deploy on example.com: remote command exited with status 1: docker compose up -d"
+    );
+
+    let err = DeployError::CommandNotFound("docker".into()).context("build", None);
+    assert_eq!(err.to_string(), "build: command not found: docker");
+}
+
+#[test]
+fn result_context() {
+    let result: DeployResult<()> = Err(remote_failure());
+    let err = result.context("deploy", Some("example.com")).unwrap_err();
+
+    assert!(matches!(err, DeployError::Context { .. }));
+    assert!(matches!(
+        err.root(),
+        DeployError::RemoteCommandFailed { .. }
+    ));
+}
+
+#[test]
+fn codes_are_stable() {
+    assert_eq!(remote_failure().code(), "E104");
+    assert_eq!(DeployError::SshFailed("refused".into()).code(), "E201");
+    assert_eq!(
+        DeployError::HostKeyMismatch { host: "h".into() }.code(),
+        "E202"
+    );
+    assert_eq!(
+        DeployError::HostKeyUnknown { host: "h".into() }.code(),
+        "E203"
+    );
+    assert_eq!(DeployError::DnsError("x".into()).code(), "E401");
+    assert_eq!(
+        DeployError::HealthcheckTimeout("api".into(), 30).code(),
+        "E601"
+    );
+    assert_eq!(DeployError::Other("x".into()).code(), "E901");
+    assert_eq!(remote_failure().context("deploy", None).code(), "E104");
+}
+
+#[test]
+fn hints() {
+    let hint = |err: DeployError| err.hint().unwrap_or_default();
+
+    assert!(hint(DeployError::CommandNotFound("doctl".into())).contains("install `doctl`"));
+    assert!(
+        hint(DeployError::SshFailed(
+            "authentication failed for root@h".into()
+        ))
+        .contains("ssh-add")
+    );
+    assert!(hint(DeployError::SshFailed("connection refused".into())).contains("retries"));
+    assert!(hint(DeployError::DnsError("HTTP 403 Forbidden".into())).contains("credentials"));
+    assert!(
+        hint(DeployError::HealthcheckTimeout("api".into(), 30)).contains("docker compose logs api")
+    );
+    assert!(DeployError::Other("x".into()).hint().is_none());
+}
+
+#[test]
+fn render_report() {
+    let err = remote_failure().context("deploy", Some("example.com"));
+
+    let report = err.render();
+    let lines: Vec<&str> = report.lines().collect();
+
+    assert_eq!(
+        lines[0],
+        "error[E104]: remote command exited with status 1: docker compose up -d"
+    );
+    assert_eq!(lines[1], "  phase:   deploy");
+    assert_eq!(lines[2], "  host:    example.com");
+    assert_eq!(lines[3], "  command: docker compose up -d");
+    assert!(lines[4].starts_with("  hint:    "));
+}
+
+#[test]
+fn render_without_context() {
+    let report = DeployError::Other("boom".into()).render();
+    assert_eq!(report, "error[E901]: boom");
+}
+
+#[test]
+fn context_keeps_transient() {
+    let err = DeployError::SshFailed("reset".into()).context("deploy", Some("h"));
+    assert!(err.is_transient());
+}
+
+#[test]
+fn render_includes_remote_stderr() {
+    let err = DeployError::RemoteCommandFailed {
+        command: "docker compose up -d".into(),
+        exit_status: 1,
+        stderr: "no such service: web\nexit".into(),
+    };
+    assert!(
+        err.render()
+            .contains("\n  stderr:  no such service: web\n           exit")
+    );
+}
@@ -0,0 +1,170 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use catapulta::deploy::{HealthWait, wait_healthy, wait_healthy_or_report, without_health_wait};
+use catapulta::error::DeployError;
+use catapulta::events::{self, DeployEvent, Listener};
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+fn recorder() -> (
+    Rc<RefCell<Vec<DeployEvent>>>,
+    impl Fn(DeployEvent) + 'static,
+) {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&seen);
+    (seen, move |e| sink.borrow_mut().push(e))
+}
+
+fn pipeline(name: &str, deployer: MockDeployer) -> Pipeline {
+    let app = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-events-{name}"));
+    Pipeline::new(app, caddy)
+        .deploy(deployer)
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .local_dir(dir.to_str().unwrap())
+}
+
+fn phases(events: &[DeployEvent]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            DeployEvent::PhaseStarted { phase, .. } => Some(phase.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn deploy_emits_phases_then_done() {
+    let (seen, listener) = recorder();
+
+    pipeline("deploy", MockDeployer::new())
+        .on_event(listener)
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let seen = seen.borrow();
+    assert_eq!(
+        phases(&seen),
+        ["build", "transfer image", "deploy", "post-deploy hooks"]
+    );
+    assert_eq!(
+        seen[1],
+        DeployEvent::PhaseStarted {
+            phase: "transfer image".to_string(),
+            host: Some("web1".to_string()),
+        }
+    );
+    assert_eq!(seen.last(), Some(&DeployEvent::Done { success: true }));
+}
+
+#[test]
+fn failure_reports_done() {
+    let (seen, listener) = recorder();
+
+    let result = pipeline("failure", MockDeployer::new().fail("deploy"))
+        .on_event(listener)
+        .run_from(["xtask", "deploy", "web1", "--skip-build"]);
+
+    assert!(result.is_err());
+    let seen = seen.borrow();
+    assert_eq!(phases(&seen).last().unwrap(), "deploy");
+    assert_eq!(seen.last(), Some(&DeployEvent::Done { success: false }));
+}
+
+#[test]
+fn every_listener_receives_events() {
+    let (first, a) = recorder();
+    let (second, b) = recorder();
+
+    pipeline("listeners", MockDeployer::new())
+        .on_event(a)
+        .on_event(b)
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap();
+
+    assert!(!first.borrow().is_empty());
+    assert_eq!(*first.borrow(), *second.borrow());
+}
+
+#[test]
+fn emit_outside_pipeline_is_dropped() {
+    let (seen, listener) = recorder();
+    let listeners: Vec<Listener> = vec![Rc::new(listener)];
+
+    events::with_listeners(&listeners, || events::phase_started("build", None));
+    events::phase_started("deploy", None);
+
+    assert_eq!(phases(&seen.borrow()), ["build"]);
+}
+
+#[test]
+fn healthcheck_attempts_are_reported() {
+    let (seen, listener) = recorder();
+    let listeners: Vec<Listener> = vec![Rc::new(listener)];
+    let apps = [App::new("web").healthcheck("true")];
+
+    events::with_listeners(&listeners, || {
+        wait_healthy(&apps, |_| Ok("healthy\n".to_string()))
+    })
+    .unwrap();
+
+    assert_eq!(
+        *seen.borrow(),
+        [DeployEvent::HealthcheckAttempt {
+            app: "web".to_string(),
+            attempt: 1,
+            max_attempts: 30,
+            status: Some("healthy".to_string()),
+        }]
+    );
+}
+
+#[test]
+fn health_wait_bounds_attempts_and_reports() {
+    let (seen, listener) = recorder();
+    let listeners: Vec<Listener> = vec![Rc::new(listener)];
+    let apps = [App::new("web").healthcheck("false")];
+    let wait = HealthWait {
+        attempts: 2,
+        interval: Duration::from_millis(1),
+    };
+    let reported = Cell::new(0);
+
+    let err = events::with_listeners(&listeners, || {
+        wait_healthy_or_report(
+            &apps,
+            &wait,
+            |_| Ok("unhealthy\n".to_string()),
+            |name| {
+                reported.set(reported.get() + 1);
+                Ok(format!("logs of {name}"))
+            },
+        )
+    })
+    .unwrap_err();
+
+    assert!(matches!(err, DeployError::HealthcheckTimeout(ref name, 2) if name == "web"));
+    assert_eq!(reported.get(), 1);
+    assert_eq!(seen.borrow().len(), 2);
+}
+
+#[test]
+fn no_wait_skips_healthchecks() {
+    let apps = [App::new("web").healthcheck("true")];
+    let inspected = Cell::new(false);
+
+    without_health_wait(|| {
+        wait_healthy(&apps, |_| {
+            inspected.set(true);
+            Ok(String::new())
+        })
+    })
+    .unwrap();
+
+    assert!(!inspected.get());
+}
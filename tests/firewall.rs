@@ -0,0 +1,135 @@
+use catapulta::firewall;
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+const UFW_STATUS: &str = "\
+Status: active
+
+To                         Action      From
+--                         ------      ----
+OpenSSH                    ALLOW       Anywhere
+80/tcp                     ALLOW       Anywhere
+443/tcp                    ALLOW       Anywhere
+8443/tcp                   ALLOW       Anywhere
+5432                       DENY        Anywhere
+OpenSSH (v6)               ALLOW       Anywhere (v6)
+";
+
+fn pipeline(name: &str, fake: &FakeSsh) -> Pipeline {
+    let web = App::new("web").expose(3000);
+    let nats = App::new("nats")
+        .image("nats:2")
+        .port(4222, 4222)
+        .port(8443, 8443);
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-firewall-{name}"));
+    Pipeline::multi(vec![web, nats], caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+}
+
+#[test]
+fn parse_ufw_status() {
+    let status = firewall::parse_status(UFW_STATUS);
+
+    assert!(status.active);
+    assert!(status.allows(443));
+    assert!(!status.allows(5432));
+    assert!(!status.allows(4222));
+    assert_eq!(
+        firewall::parse_status("Status: inactive\n"),
+        firewall::UfwStatus::default()
+    );
+    assert!(!firewall::parse_status("").active);
+}
+
+#[test]
+fn published_ports_are_deduplicated() {
+    let apps = [
+        App::new("a").port(9000, 9000).port(4222, 4222),
+        App::new("b").port(4222, 4223),
+    ];
+
+    assert_eq!(firewall::published_ports(&apps), vec![4222, 9000]);
+}
+
+#[test]
+fn deploy_opens_missing_ports() {
+    let fake = FakeSsh::new().respond("ufw status", UFW_STATUS);
+
+    pipeline("open", &fake)
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert!(commands.contains(&"ufw allow 4222/tcp".to_string()));
+    assert!(!commands.contains(&"ufw allow 8443/tcp".to_string()));
+}
+
+#[test]
+fn inactive_firewall_is_left_alone() {
+    let fake = FakeSsh::new().respond("ufw status", "Status: inactive\n");
+
+    pipeline("inactive", &fake)
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    assert!(!fake.commands().iter().any(|c| c.starts_with("ufw allow")));
+}
+
+#[test]
+fn deploy_opens_stream_ports() {
+    let fake = FakeSsh::new().respond("ufw status", UFW_STATUS);
+    let game = App::new("game").image("game:1").expose(27015);
+    let caddy = Caddy::new()
+        .stream(443, game.upstream())
+        .stream_udp(27015, game.upstream());
+    let dir = std::env::temp_dir().join("catapulta-firewall-streams");
+
+    Pipeline::new(game, caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert!(commands.contains(&"ufw allow 27015/udp".to_string()));
+    assert!(!commands.contains(&"ufw allow 443/tcp".to_string()));
+    assert_eq!(
+        firewall::stream_ports(&Caddy::new().stream_udp(53, App::new("dns").expose(53).upstream())),
+        vec![(53, "udp")]
+    );
+}
+
+#[test]
+fn no_proxy_publishes_exposed_ports() {
+    let fake = FakeSsh::new()
+        .respond("ufw status", UFW_STATUS)
+        .respond("docker inspect", "healthy\n");
+    let nats = App::new("nats")
+        .image("nats:2")
+        .expose(4222)
+        .healthcheck("true");
+    let caddy = Caddy::new().reverse_proxy(nats.upstream());
+    let dir = std::env::temp_dir().join("catapulta-firewall-no-proxy");
+    let pipeline = Pipeline::new(nats, caddy)
+        .no_proxy()
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap());
+
+    pipeline.run_from(["xtask", "deploy", "web1"]).unwrap();
+    pipeline
+        .run_from(["xtask", "scale", "web1", "nats=1"])
+        .unwrap();
+
+    assert!(fake.commands().contains(&"ufw allow 4222/tcp".to_string()));
+    let compose = fake.file("/opt/app/docker-compose.yml").unwrap();
+    assert!(compose.contains("4222:4222"));
+    assert!(!compose.contains("caddy"));
+    assert!(fake.file("/opt/app/Caddyfile").is_none());
+    assert!(!fake.commands().iter().any(|c| c.contains("caddy reload")));
+}
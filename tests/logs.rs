@@ -0,0 +1,77 @@
+use catapulta::compose;
+use catapulta::logs::{LogShipping, LogSink};
+use catapulta::{App, Caddy};
+use docker_compose_types::Compose;
+
+#[test]
+fn loki_config_ships_listed_containers() {
+    let config = LogShipping::loki("https://logs.example.com")
+        .vector_config("shop", &["shop", "shop-caddy"]);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&config).expect("parse");
+
+    let source = &parsed["sources"]["containers"];
+    assert_eq!(source["type"], "docker_logs");
+    assert_eq!(source["include_containers"][1], "shop-caddy");
+
+    let sink = &parsed["sinks"]["logs"];
+    assert_eq!(sink["type"], "loki");
+    assert_eq!(sink["endpoint"], "https://logs.example.com");
+    assert_eq!(sink["labels"]["stack"], "shop");
+    assert_eq!(sink["labels"]["container"], "{{ container_name }}");
+    assert!(sink.get("auth").is_none());
+}
+
+#[test]
+fn basic_auth_reads_password_from_env() {
+    let config = LogShipping::loki("https://logs.example.com")
+        .basic_auth("shop", "LOKI_PASSWORD")
+        .vector_config("shop", &["shop"]);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&config).expect("parse");
+
+    let auth = &parsed["sinks"]["logs"]["auth"];
+    assert_eq!(auth["user"], "shop");
+    assert_eq!(auth["password"], "${LOKI_PASSWORD}");
+}
+
+#[test]
+fn elasticsearch_config() {
+    let config =
+        LogShipping::elasticsearch("https://es.example.com:9200").vector_config("shop", &["shop"]);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&config).expect("parse");
+
+    let sink = &parsed["sinks"]["logs"];
+    assert_eq!(sink["type"], "elasticsearch");
+    assert_eq!(sink["endpoints"][0], "https://es.example.com:9200");
+    assert_eq!(sink["bulk"]["index"], "shop-%Y.%m.%d");
+}
+
+#[test]
+fn bundled_loki_adds_loki_app() {
+    let shipping = LogShipping::bundled_loki();
+    let apps = shipping.apps("shop", &["shop"]);
+
+    assert_eq!(shipping.sink, LogSink::BundledLoki);
+    let names: Vec<&str> = apps.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names, ["vector", "loki"]);
+    assert!(apps[0].config_files[0].content.contains("http://loki:3100"));
+    assert!(apps[1].image.is_some());
+}
+
+#[test]
+fn vector_service_mounts_socket_and_config() {
+    let mut apps = vec![App::new("shop").expose(3000)];
+    apps.extend(
+        LogShipping::loki("https://logs.example.com")
+            .env_file("deploy/.env.vector")
+            .apps("shop", &["shop"]),
+    );
+
+    let yaml = compose::render(&apps, &Caddy::new());
+    let parsed: Compose = serde_yaml::from_str(&yaml).expect("parse");
+
+    assert!(yaml.contains("/var/run/docker.sock:/var/run/docker.sock:ro"));
+    assert!(yaml.contains("./vector.yaml:/etc/vector/vector.yaml:ro"));
+    assert!(yaml.contains(".env.vector"));
+    assert!(parsed.volumes.0.contains_key("vector-data"));
+    assert!(!parsed.volumes.0.contains_key("/var/run/docker.sock"));
+}
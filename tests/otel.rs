@@ -0,0 +1,110 @@
+use catapulta::events::DeployEvent;
+use catapulta::otel::{Trace, parse_headers};
+
+fn phase(name: &str) -> DeployEvent {
+    DeployEvent::PhaseStarted {
+        phase: name.to_string(),
+        host: Some("web1".to_string()),
+    }
+}
+
+fn attempt(attempt: u32) -> DeployEvent {
+    DeployEvent::HealthcheckAttempt {
+        app: "api".to_string(),
+        attempt,
+        max_attempts: 10,
+        status: Some("starting".to_string()),
+    }
+}
+
+#[test]
+fn phases_are_children_of_the_command() {
+    let mut trace = Trace::starting_at("deploy", 100);
+    trace.record_at(&phase("build"), 110);
+    trace.record_at(&phase("deploy"), 150);
+    trace.record_at(&DeployEvent::Done { success: true }, 200);
+
+    let names: Vec<&str> = trace.spans.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["deploy", "build", "deploy"]);
+    let root = &trace.spans[0];
+    assert_eq!((root.start, root.end, root.failed), (100, Some(200), false));
+    assert_eq!((trace.spans[1].start, trace.spans[1].end), (110, Some(150)));
+    assert_eq!((trace.spans[2].start, trace.spans[2].end), (150, Some(200)));
+    for span in &trace.spans[1..] {
+        assert_eq!(span.parent_span_id.as_ref(), Some(&root.span_id));
+        assert_eq!(span.host.as_deref(), Some("web1"));
+    }
+}
+
+#[test]
+fn healthcheck_spans_the_attempts_within_the_phase() {
+    let mut trace = Trace::starting_at("deploy", 0);
+    trace.record_at(&phase("deploy"), 10);
+    trace.record_at(&attempt(1), 20);
+    trace.record_at(&attempt(2), 30);
+    trace.record_at(&attempt(3), 45);
+    trace.record_at(&phase("DNS cleanup"), 60);
+    trace.record_at(&DeployEvent::Done { success: true }, 70);
+
+    let healthcheck = &trace.spans[2];
+    assert_eq!(healthcheck.name, "healthcheck");
+    assert_eq!((healthcheck.start, healthcheck.end), (20, Some(45)));
+    assert_eq!(
+        healthcheck.parent_span_id.as_ref(),
+        Some(&trace.spans[1].span_id)
+    );
+    assert_eq!(trace.spans.len(), 4);
+}
+
+#[test]
+fn failed_phase_and_command_are_errors() {
+    let mut trace = Trace::starting_at("deploy", 0);
+    trace.record_at(&phase("build"), 1);
+    trace.record_at(&phase("transfer image"), 2);
+    trace.record_at(&DeployEvent::Done { success: false }, 3);
+
+    let failed: Vec<(&str, bool)> = trace
+        .spans
+        .iter()
+        .map(|s| (s.name.as_str(), s.failed))
+        .collect();
+    assert_eq!(
+        failed,
+        [("deploy", true), ("build", false), ("transfer image", true)]
+    );
+}
+
+#[test]
+fn otlp_json_export() {
+    let mut trace = Trace::starting_at("deploy", 5);
+    trace.record_at(&phase("build"), 6);
+    trace.record_at(&DeployEvent::Done { success: false }, 9);
+
+    let json = trace.to_otlp("shop");
+    let resource = &json["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0],
+        serde_json::json!({"key": "service.name", "value": {"stringValue": "shop"}})
+    );
+    let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+    assert_eq!(spans.len(), 2);
+    assert_eq!(spans[0]["name"], "deploy");
+    assert_eq!(spans[0]["traceId"], trace.trace_id.as_str());
+    assert_eq!(spans[0]["startTimeUnixNano"], "5");
+    assert_eq!(spans[0]["endTimeUnixNano"], "9");
+    assert_eq!(spans[0]["status"]["code"], 2);
+    assert!(spans[0].get("parentSpanId").is_none());
+    assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+    assert_eq!(spans[1]["attributes"][0]["value"]["stringValue"], "web1");
+    assert_eq!(trace.trace_id.len(), 32);
+    assert_eq!(trace.spans[0].span_id.len(), 16);
+}
+
+#[test]
+fn headers_from_the_environment_format() {
+    assert_eq!(
+        parse_headers("authorization=Bearer abc, x-scope = prod,invalid"),
+        ["authorization: Bearer abc", "x-scope: prod"]
+    );
+    assert!(parse_headers("").is_empty());
+}
@@ -0,0 +1,76 @@
+use catapulta::pipeline::CONFIRM_ENV;
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer, MockDnsProvider, MockProvisioner};
+use catapulta::{App, Caddy, Pipeline};
+
+fn pipeline(name: &str, provisioner: &MockProvisioner, deployer: &MockDeployer) -> Pipeline {
+    let app = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-protected-{name}"));
+    Pipeline::new(app, caddy)
+        .local_dir(dir.to_str().unwrap())
+        .provision(provisioner.clone())
+        .dns(MockDnsProvider::new("example.com"))
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .protect("production")
+}
+
+// The only test setting CONFIRM_ENV, so the refusals before it
+// is set can't be confirmed by another test
+#[test]
+fn deploy_and_destroy_need_the_environment_name() {
+    let provisioner = MockProvisioner::new().existing("web", "203.0.113.10");
+    let deployer = MockDeployer::new();
+    let pipeline = pipeline("confirm", &provisioner, &deployer);
+
+    let err = pipeline
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap_err();
+    assert_eq!(err.code(), "E504");
+    assert!(err.to_string().contains("--confirm production"), "{err}");
+    let err = pipeline
+        .run_from(["xtask", "deploy", "web1", "--confirm", "staging"])
+        .unwrap_err();
+    assert_eq!(err.code(), "E504");
+    let err = pipeline
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap_err();
+    assert_eq!(err.code(), "E504");
+    assert!(deployer.calls().is_empty());
+    assert!(!provisioner.calls().iter().any(|c| c.starts_with("destroy")));
+
+    pipeline
+        .run_from([
+            "xtask",
+            "deploy",
+            "web1",
+            "--skip-build",
+            "--confirm",
+            "production",
+        ])
+        .unwrap();
+    assert!(!deployer.calls().is_empty());
+
+    unsafe { std::env::set_var(CONFIRM_ENV, "production") };
+    let result = pipeline.run_from(["xtask", "destroy", "web", "--force"]);
+    unsafe { std::env::remove_var(CONFIRM_ENV) };
+    result.unwrap();
+    assert!(
+        provisioner
+            .calls()
+            .contains(&"destroy_server web".to_string())
+    );
+}
+
+#[test]
+fn dry_runs_and_other_commands_need_no_confirmation() {
+    let provisioner = MockProvisioner::new().existing("web", "203.0.113.10");
+    let deployer = MockDeployer::new();
+    let pipeline = pipeline("dry-run", &provisioner, &deployer);
+
+    pipeline
+        .run_from(["xtask", "destroy", "web", "--dry-run"])
+        .unwrap();
+    pipeline.run_from(["xtask", "status", "web1"]).unwrap();
+}
@@ -1,5 +1,9 @@
 use catapulta::DigitalOcean;
-use catapulta::provision::remove_ssh_host_entry;
+use catapulta::error::DeployError;
+use catapulta::provision::digitalocean::{create_error, parse_droplet};
+use catapulta::provision::{
+    Provisioner, add_ssh_include, has_ssh_host_entry, has_ssh_include, remove_ssh_host_entry,
+};
 
 #[test]
 fn defaults() {
@@ -10,6 +14,12 @@ fn defaults() {
     assert_eq!(do_.image, "ubuntu-24-04-x64");
 }
 
+#[test]
+fn default_region_follows_builder() {
+    assert_eq!(DigitalOcean::new().default_region(), "fra1");
+    assert_eq!(DigitalOcean::new().region("nyc1").default_region(), "nyc1");
+}
+
 #[test]
 fn builder_chain() {
     let do_ = DigitalOcean::new()
@@ -115,3 +125,97 @@ Host b
     assert!(result.contains("Host a"));
     assert!(result.contains("Host b"));
 }
+
+#[test]
+fn has_host_entry_matches_exact_alias() {
+    let config = "\
+Host myserver
+    HostName 1.2.3.4
+
+Host myserver-staging
+    HostName 5.6.7.8";
+
+    assert!(has_ssh_host_entry(config, "myserver"));
+    assert!(has_ssh_host_entry(config, "myserver-staging"));
+    assert!(!has_ssh_host_entry(config, "other"));
+}
+
+#[test]
+fn include_goes_before_host_blocks() {
+    let config = "Host github.com\n    User git\n";
+
+    let result = add_ssh_include(config);
+
+    assert_eq!(
+        result,
+        "Include config.d/catapulta\n\nHost github.com\n    User git\n"
+    );
+    assert_eq!(add_ssh_include(&result), result);
+    assert_eq!(add_ssh_include(""), "Include config.d/catapulta\n");
+}
+
+#[test]
+fn include_by_path_or_glob() {
+    assert!(has_ssh_include("include ~/.ssh/config.d/catapulta\n"));
+    assert!(has_ssh_include("Include config.d/*\n"));
+    assert!(has_ssh_include("Include other.conf config.d/catapulta\n"));
+    assert!(!has_ssh_include("Include config.d/work\n"));
+    assert!(!has_ssh_include("# Include config.d/catapulta\n"));
+}
+
+#[test]
+fn create_errors_are_typed() {
+    let quota = "Error: POST https://api.digitalocean.com/v2/droplets: 422 \
+                 (request \"3f1c\") creating this/these droplet(s) will exceed your \
+                 droplet limit";
+    let err = create_error(quota, "fra1", "s-1vcpu-1gb").unwrap();
+    assert_eq!(err.code(), "E303");
+    assert!(err.to_string().contains("droplet limit"));
+
+    let capacity = "Error: POST https://api.digitalocean.com/v2/droplets: 422 \
+                    (request \"9a2e\") Size is not available in this region.";
+    let err = create_error(capacity, "sfo2", "c-32").unwrap();
+    assert!(matches!(
+        err,
+        DeployError::RegionUnavailable { ref region, ref size } if region == "sfo2" && size == "c-32"
+    ));
+    assert!(err.hint().unwrap().contains("--region"));
+
+    let size = "Error: POST https://api.digitalocean.com/v2/droplets: 422 \
+                (request \"77b0\") You specified an invalid size for Droplet creation.";
+    let err = create_error(size, "fra1", "s-9vcpu-1mb").unwrap();
+    assert_eq!(err.code(), "E305");
+    assert!(err.hint().unwrap().contains("fra1"));
+
+    assert!(create_error("Error: unable to authenticate you", "fra1", "s-1vcpu-1gb").is_none());
+}
+
+#[test]
+fn fallback_regions_builder() {
+    let do_ = DigitalOcean::new()
+        .fallback_region("ams3")
+        .fallback_region("lon1");
+
+    assert_eq!(do_.fallback_regions, ["ams3", "lon1"]);
+    assert!(DigitalOcean::new().fallback_regions.is_empty());
+}
+
+#[test]
+fn droplet_addresses_from_list_output() {
+    let output = "web    203.0.113.10    fra1    2a03:b0c0:3:d0::1a2b:1\n\
+                  db     203.0.113.11    fra1\n";
+
+    assert_eq!(
+        parse_droplet(output, "web"),
+        Some((
+            "203.0.113.10".to_string(),
+            "fra1".to_string(),
+            Some("2a03:b0c0:3:d0::1a2b:1".to_string())
+        ))
+    );
+    assert_eq!(
+        parse_droplet(output, "db"),
+        Some(("203.0.113.11".to_string(), "fra1".to_string(), None))
+    );
+    assert_eq!(parse_droplet(output, "cache"), None);
+}
@@ -0,0 +1,69 @@
+use catapulta::ExistingServer;
+use catapulta::provision::{ProvisionOverrides, Provisioner};
+
+fn key() -> String {
+    let dir = std::env::temp_dir().join("catapulta-existing");
+    std::fs::create_dir_all(&dir).unwrap();
+    let key = dir.join("id_ed25519");
+    std::fs::write(&key, "").unwrap();
+    key.to_str().unwrap().to_string()
+}
+
+#[test]
+fn provisioning_uses_the_given_host() {
+    let key = key();
+    let server = ExistingServer::new("203.0.113.5", &key);
+
+    server.check_prerequisites().unwrap();
+    assert!(server.get_server("web").unwrap().is_none());
+    let info = server.create_server("web", "", &[]).unwrap();
+    assert_eq!(info.name, "web");
+    assert_eq!(info.ip, "203.0.113.5");
+    assert_eq!(info.ssh_key_files, vec![key.clone()]);
+    assert_eq!(server.detect_ssh_keys().unwrap(), [(String::new(), key)]);
+}
+
+#[test]
+fn missing_key_and_overrides_are_rejected() {
+    let server = ExistingServer::new("203.0.113.5", "/nonexistent/id_ed25519");
+    assert_eq!(server.check_prerequisites().unwrap_err().code(), "E502");
+
+    let overrides = ProvisionOverrides {
+        size: Some("s-2vcpu-4gb".to_string()),
+        ..ProvisionOverrides::default()
+    };
+    let err = server
+        .create_server_with("web", "", &[], &overrides)
+        .unwrap_err();
+    assert!(err.to_string().contains("--size"));
+}
+
+#[test]
+fn destroy_leaves_the_server_running() {
+    let server = ExistingServer::new("203.0.113.5", &key()).skip_setup_step("firewall");
+
+    let plan = server.destroy_plan("web").unwrap();
+    assert_eq!(
+        plan[0],
+        "pinned host key of 203.0.113.5 (the server itself is left running)"
+    );
+    assert!(!server.setup_steps.iter().any(|s| s.name() == "firewall"));
+}
+
+#[test]
+fn no_console() {
+    let err = ExistingServer::new("203.0.113.5", "/tmp/id_ed25519")
+        .console("web")
+        .unwrap_err();
+
+    assert!(err.to_string().contains("no console access to 'web'"));
+}
+
+#[test]
+fn no_image_cache() {
+    let err = ExistingServer::new("203.0.113.5", "/tmp/id_ed25519")
+        .clean_image_cache()
+        .unwrap_err();
+
+    assert!(err.to_string().contains("no image cache"));
+}
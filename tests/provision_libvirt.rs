@@ -1,4 +1,8 @@
-use catapulta::provision::libvirt::{Libvirt, NetworkMode, parse_domifaddr};
+use catapulta::provision::Provisioner;
+use catapulta::provision::libvirt::{
+    DataDisk, Libvirt, NetworkMode, cache_file_name, data_disk_config, parse_domifaddr,
+    published_sha256,
+};
 
 #[test]
 fn parse_domifaddr_nat_output() {
@@ -51,6 +55,8 @@ fn builder_defaults() {
     assert_eq!(lv.hypervisor_host, "myhost");
     assert_eq!(lv.hypervisor_user, "root");
     assert!(lv.hypervisor_key.is_none());
+    assert!(lv.hypervisor_port.is_none());
+    assert!(lv.hypervisor_jump.is_none());
     assert_eq!(lv.vcpus, 2);
     assert_eq!(lv.memory_mib, 2048);
     assert_eq!(lv.disk_gib, 20);
@@ -58,6 +64,7 @@ fn builder_defaults() {
     assert_eq!(lv.os_variant, "ubuntu24.04");
     assert_eq!(lv.storage_dir, "/var/lib/libvirt/images");
     assert!(matches!(lv.network, NetworkMode::Nat));
+    assert!(lv.image_sha256.is_none());
 }
 
 #[test]
@@ -65,22 +72,28 @@ fn builder_chain() {
     let lv = Libvirt::new("myhost", "/tmp/key")
         .hypervisor_user("admin")
         .hypervisor_key("/tmp/hv_key")
+        .hypervisor_port(2222)
+        .hypervisor_jump("bastion", "ops", None)
         .vcpus(4)
         .memory_mib(4096)
         .disk_gib(50)
         .network(NetworkMode::Bridged("br0".into()))
         .storage_dir("/data/vms")
         .os_variant("debian12")
-        .image_url("https://example.com/image.img");
+        .image_url("https://example.com/image.img")
+        .image_sha256("ABC123");
 
     assert_eq!(lv.hypervisor_user, "admin");
     assert_eq!(lv.hypervisor_key, Some("/tmp/hv_key".to_string()));
+    assert_eq!(lv.hypervisor_port, Some(2222));
+    assert_eq!(lv.hypervisor_jump.unwrap().host, "bastion");
     assert_eq!(lv.vcpus, 4);
     assert_eq!(lv.memory_mib, 4096);
     assert_eq!(lv.disk_gib, 50);
     assert_eq!(lv.storage_dir, "/data/vms");
     assert_eq!(lv.os_variant, "debian12");
     assert_eq!(lv.image_url, "https://example.com/image.img");
+    assert_eq!(lv.image_sha256.as_deref(), Some("abc123"));
     assert!(matches!(
         lv.network,
         NetworkMode::Bridged(ref b) if b == "br0"
@@ -98,3 +111,84 @@ fn network_mode_bridged() {
     let mode = NetworkMode::Bridged("virbr1".into());
     assert!(matches!(mode, NetworkMode::Bridged(ref b) if b == "virbr1"));
 }
+
+#[test]
+fn default_region_is_local() {
+    let lv = Libvirt::new("kvm.example.com", "/home/user/.ssh/id_ed25519");
+
+    assert_eq!(lv.default_region(), "local");
+}
+
+#[test]
+fn cache_file_per_url() {
+    assert_eq!(
+        cache_file_name(
+            "https://cloud-images.ubuntu.com/releases/24.04/release/ubuntu-24.04-server-cloudimg-amd64.img"
+        ),
+        "cloud-images.ubuntu.com-releases-24.04-release-ubuntu-24.04-server-cloudimg-amd64.img"
+    );
+    assert_ne!(
+        cache_file_name("https://example.com/a/disk.img"),
+        cache_file_name("https://example.com/b/disk.img")
+    );
+}
+
+#[test]
+fn sha256_from_published_sums() {
+    let a = "a".repeat(64);
+    let b = "B".repeat(64);
+    let sums = format!(
+        "{a} *ubuntu-24.04-server-cloudimg-amd64.img\n\
+         {b}  ubuntu-24.04-server-cloudimg-arm64.img\n"
+    );
+
+    assert_eq!(
+        published_sha256(&sums, "ubuntu-24.04-server-cloudimg-amd64.img"),
+        Some(a)
+    );
+    assert_eq!(
+        published_sha256(&sums, "ubuntu-24.04-server-cloudimg-arm64.img"),
+        Some("b".repeat(64))
+    );
+    assert_eq!(published_sha256(&sums, "other.img"), None);
+    assert_eq!(
+        published_sha256("<html>Not Found</html>", "other.img"),
+        None
+    );
+}
+
+#[test]
+fn data_disks_are_formatted_and_mounted() {
+    let lv = Libvirt::new("myhost", "/tmp/key")
+        .data_disk(100, "/srv/data")
+        .data_disk(20, "/var/backups");
+
+    assert_eq!(
+        lv.data_disks[0],
+        DataDisk {
+            size_gib: 100,
+            mount_path: "/srv/data".to_string(),
+        }
+    );
+    assert_eq!(
+        data_disk_config(&lv.data_disks),
+        "fs_setup:\n\
+         \x20 - label: data0\n    filesystem: ext4\n    device: /dev/vdb\n    partition: none\n    overwrite: false\n\
+         \x20 - label: data1\n    filesystem: ext4\n    device: /dev/vdc\n    partition: none\n    overwrite: false\n\
+         mounts:\n\
+         \x20 - [LABEL=data0, /srv/data, ext4, \"defaults,nofail\", \"0\", \"2\"]\n\
+         \x20 - [LABEL=data1, /var/backups, ext4, \"defaults,nofail\", \"0\", \"2\"]\n"
+    );
+    assert_eq!(data_disk_config(&[]), "");
+}
+
+#[test]
+fn destroy_plan_keeps_data_disks() {
+    let plan = Libvirt::new("myhost", "/tmp/key")
+        .data_disk(100, "/srv/data")
+        .destroy_plan("web")
+        .unwrap();
+
+    assert!(plan[0].contains("/var/lib/libvirt/images/web.qcow2"));
+    assert!(plan[0].contains("data disks are kept"));
+}
@@ -0,0 +1,94 @@
+use catapulta::Proxmox;
+use catapulta::provision::Provisioner;
+use catapulta::provision::proxmox::{Vm, encode, parse_agent_ipv4, parse_vms};
+use serde_json::json;
+
+#[test]
+fn defaults() {
+    let pve = Proxmox::new(
+        "https://pve.lan:8006/",
+        "pve1",
+        9000,
+        "/home/u/.ssh/id_homelab",
+    );
+
+    assert_eq!(pve.api_url, "https://pve.lan:8006");
+    assert_eq!(pve.cores, 2);
+    assert_eq!(pve.memory_mib, 2048);
+    assert_eq!(pve.disk_gib, None);
+    assert_eq!(pve.disk, "scsi0");
+    assert_eq!(pve.default_region(), "pve1");
+    assert_eq!(
+        pve.detect_ssh_keys().unwrap(),
+        [(String::new(), "/home/u/.ssh/id_homelab".to_string())]
+    );
+}
+
+#[test]
+fn builder_chain() {
+    let pve = Proxmox::new("https://pve.lan:8006", "pve1", 9000, "k")
+        .cores(4)
+        .memory_mib(8192)
+        .disk_gib(64)
+        .disk("virtio0")
+        .storage("local-lvm")
+        .ca_cert("pve-root-ca.pem")
+        .skip_setup_step("firewall");
+
+    assert_eq!(pve.cores, 4);
+    assert_eq!(pve.memory_mib, 8192);
+    assert_eq!(pve.disk_gib, Some(64));
+    assert_eq!(pve.disk, "virtio0");
+    assert_eq!(pve.storage.as_deref(), Some("local-lvm"));
+    assert_eq!(pve.ca_cert.as_deref(), Some("pve-root-ca.pem"));
+    assert!(!pve.setup_steps.iter().any(|s| s.name() == "firewall"));
+}
+
+#[test]
+fn vms_from_cluster_resources() {
+    let resources = json!([
+        {"type": "qemu", "vmid": 9000, "name": "ubuntu-template", "node": "pve1", "template": 1},
+        {"type": "qemu", "vmid": 105, "name": "web", "node": "pve2", "template": 0},
+        {"type": "lxc", "vmid": 200, "name": "dns", "node": "pve1"}
+    ]);
+
+    assert_eq!(
+        parse_vms(&resources),
+        [Vm {
+            vmid: 105,
+            name: "web".to_string(),
+            node: "pve2".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn agent_ip_skips_loopback_and_ipv6() {
+    let interfaces = json!({"result": [
+        {"name": "lo", "ip-addresses": [
+            {"ip-address-type": "ipv4", "ip-address": "127.0.0.1"}
+        ]},
+        {"name": "eth0", "ip-addresses": [
+            {"ip-address-type": "ipv6", "ip-address": "fe80::1"},
+            {"ip-address-type": "ipv4", "ip-address": "192.168.1.42"}
+        ]}
+    ]});
+
+    assert_eq!(
+        parse_agent_ipv4(&interfaces).as_deref(),
+        Some("192.168.1.42")
+    );
+    assert_eq!(parse_agent_ipv4(&json!({"result": []})), None);
+}
+
+#[test]
+fn encode_escapes_reserved_characters() {
+    assert_eq!(
+        encode("ssh-ed25519 AAAA+/= me@host"),
+        "ssh-ed25519%20AAAA%2B%2F%3D%20me%40host"
+    );
+    assert_eq!(
+        encode("UPID:pve1:0001:qmclone:105:root@pam!ci:"),
+        "UPID%3Apve1%3A0001%3Aqmclone%3A105%3Aroot%40pam%21ci%3A"
+    );
+}
@@ -0,0 +1,95 @@
+use catapulta::Scaleway;
+use catapulta::error::DeployError;
+use catapulta::provision::scaleway::{Instance, create_error, parse_instances};
+use catapulta::provision::{ProvisionOverrides, Provisioner};
+
+#[test]
+fn defaults() {
+    let scw = Scaleway::new();
+
+    assert_eq!(scw.commercial_type, "DEV1-S");
+    assert_eq!(scw.zone, "fr-par-1");
+    assert_eq!(scw.image, "ubuntu_noble");
+    assert_eq!(scw.default_region(), "fr-par-1");
+}
+
+#[test]
+fn builder_chain() {
+    let scw = Scaleway::new()
+        .commercial_type("PLAY2-NANO")
+        .zone("nl-ams-1")
+        .image("debian_bookworm")
+        .skip_setup_step("firewall");
+
+    assert_eq!(scw.commercial_type, "PLAY2-NANO");
+    assert_eq!(scw.default_region(), "nl-ams-1");
+    assert_eq!(scw.image, "debian_bookworm");
+    assert!(!scw.setup_steps.iter().any(|s| s.name() == "firewall"));
+}
+
+#[test]
+fn instances_from_list_output() {
+    let output = r#"[
+        {"id": "11111111-aaaa", "name": "web", "zone": "fr-par-1",
+         "public_ip": null,
+         "public_ips": [
+            {"address": "2001:db8::1", "family": "inet6"},
+            {"address": "51.15.0.10", "family": "inet"}
+         ]},
+        {"id": "22222222-bbbb", "name": "db", "zone": "fr-par-1",
+         "public_ip": {"address": "51.15.0.11"}},
+        {"id": "33333333-cccc", "name": "private", "zone": "fr-par-2",
+         "public_ip": null, "public_ips": []}
+    ]"#;
+
+    let instances = parse_instances(output).unwrap();
+
+    assert_eq!(instances[0].ip.as_deref(), Some("51.15.0.10"));
+    assert_eq!(instances[0].ipv6.as_deref(), Some("2001:db8::1"));
+    assert_eq!(instances[1].ip.as_deref(), Some("51.15.0.11"));
+    assert_eq!(instances[1].ipv6, None);
+    assert_eq!(
+        instances[2],
+        Instance {
+            id: "33333333-cccc".to_string(),
+            name: "private".to_string(),
+            ip: None,
+            ipv6: None,
+            zone: "fr-par-2".to_string(),
+        }
+    );
+}
+
+#[test]
+fn create_errors_are_typed() {
+    let quota = create_error(
+        "Error: quotas exceeded: instances_dev1_s_servers (limit 2)",
+        "fr-par-1",
+        "DEV1-S",
+    );
+    assert!(matches!(quota, Some(DeployError::QuotaExceeded(_))));
+
+    let stock = create_error(
+        "Error: PLAY2-NANO is out of stock in fr-par-1",
+        "fr-par-1",
+        "PLAY2-NANO",
+    )
+    .unwrap();
+    assert_eq!(stock.code(), "E304");
+
+    assert!(create_error("Error: network unreachable", "fr-par-1", "DEV1-S").is_none());
+}
+
+#[test]
+fn ssh_key_override_is_rejected() {
+    let overrides = ProvisionOverrides {
+        ssh_keys: vec!["123".to_string()],
+        ..ProvisionOverrides::default()
+    };
+
+    let err = Scaleway::new()
+        .create_server_with("web", "fr-par-1", &[], &overrides)
+        .unwrap_err();
+
+    assert!(err.to_string().contains("--ssh-key"));
+}
@@ -0,0 +1,83 @@
+use catapulta::provision::setup::{self, SetupStep};
+use catapulta::{DigitalOcean, Libvirt};
+
+fn names(steps: &[SetupStep]) -> Vec<&str> {
+    steps.iter().map(SetupStep::name).collect()
+}
+
+#[test]
+fn provisioners_run_default_steps() {
+    let expected = [
+        "wait_for_apt",
+        "install_docker",
+        "firewall",
+        "create_dirs",
+        "start_caddy",
+    ];
+
+    assert_eq!(names(&DigitalOcean::new().setup_steps), expected);
+    assert_eq!(
+        names(&Libvirt::new("kvm", "/tmp/key").setup_steps),
+        expected
+    );
+}
+
+#[test]
+fn podman_defaults_install_podman() {
+    assert_eq!(
+        names(&SetupStep::podman_defaults()),
+        [
+            "wait_for_apt",
+            "install_podman",
+            "firewall",
+            "create_dirs",
+            "start_caddy",
+        ]
+    );
+    assert!(
+        SetupStep::InstallPodman
+            .script()
+            .contains("unqualified-search-registries")
+    );
+}
+
+#[test]
+fn steps_can_be_added_and_skipped() {
+    let do_ = DigitalOcean::new()
+        .setup_step(SetupStep::custom("ffmpeg", "apt_get install -y ffmpeg"))
+        .skip_setup_step("firewall")
+        .skip_setup_step("start_caddy");
+
+    assert_eq!(
+        names(&do_.setup_steps),
+        ["wait_for_apt", "install_docker", "create_dirs", "ffmpeg"]
+    );
+}
+
+#[test]
+fn steps_can_be_replaced() {
+    let mut steps = vec![SetupStep::custom("nfs", "mount -a")];
+    steps.extend(SetupStep::defaults());
+    let lv = Libvirt::new("kvm", "/tmp/key").setup_steps(steps);
+
+    assert_eq!(lv.setup_steps[0].name(), "nfs");
+    assert_eq!(lv.setup_steps.len(), 6);
+}
+
+#[test]
+fn script_runs_steps_in_order_after_prelude() {
+    let script = setup::script(&[
+        SetupStep::CreateDirs,
+        SetupStep::custom("it's custom", "touch \"$REMOTE_DIR/marker\""),
+    ]);
+
+    assert!(script.starts_with("#!/usr/bin/env bash"));
+    assert!(script.contains("set -euo pipefail"));
+    assert!(script.contains("apt_get() {"));
+    let dirs = script.find("echo '==> create_dirs'").unwrap();
+    let custom = script.find("echo '==> it'\\''s custom'").unwrap();
+    assert!(dirs < custom);
+    assert!(script.contains("(\ntouch \"$REMOTE_DIR/marker\"\n)"));
+    assert!(script.trim_end().ends_with("echo \"Setup complete!\""));
+    assert!(!script.contains("docker compose up"));
+}
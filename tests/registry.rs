@@ -0,0 +1,81 @@
+use catapulta::registry::{RegistryAuth, image_registry};
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+fn pipeline(name: &str, fake: &FakeSsh, token_env: &str) -> Pipeline {
+    let app = App::new("api").image("ghcr.io/org/api:1.4").expose(8000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-registry-{name}"));
+    Pipeline::new(app, caddy)
+        .deploy(MockDeployer::new())
+        .registry_auth("ghcr.io", "ci-bot", token_env)
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+}
+
+#[test]
+fn registry_of_an_image() {
+    assert_eq!(image_registry("ghcr.io/org/api:1.4"), "ghcr.io");
+    assert_eq!(image_registry("localhost:5000/api"), "localhost:5000");
+    assert_eq!(image_registry("org/api"), "docker.io");
+    assert_eq!(image_registry("nginx:alpine"), "docker.io");
+
+    let hub = RegistryAuth::new("index.docker.io", "me", "HUB_TOKEN");
+    assert!(hub.serves("org/api"));
+    assert!(!hub.serves("ghcr.io/org/api"));
+}
+
+#[test]
+fn deploy_logs_in_on_the_server_without_exposing_the_token() {
+    // SAFETY: the variable is only read by this test.
+    unsafe { std::env::set_var("CATAPULTA_TEST_GHCR_TOKEN", "s3cret") };
+    let fake = FakeSsh::new();
+
+    pipeline("login", &fake, "CATAPULTA_TEST_GHCR_TOKEN")
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    assert_eq!(
+        fake.file("/opt/app/.registry-token").as_deref(),
+        Some("s3cret")
+    );
+    let commands = fake.commands();
+    let login = commands
+        .iter()
+        .position(|c| c.starts_with("docker login 'ghcr.io' -u 'ci-bot' --password-stdin"))
+        .expect("docker login on the server");
+    assert!(commands[login].contains("rm -f '/opt/app/.registry-token'"));
+    assert!(commands.iter().all(|c| !c.contains("s3cret")));
+}
+
+#[test]
+fn unrelated_registry_is_not_logged_in() {
+    let fake = FakeSsh::new();
+    let app = App::new("web").image("nginx:alpine").expose(80);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    let dir = std::env::temp_dir().join("catapulta-registry-unrelated");
+
+    Pipeline::new(app, caddy)
+        .deploy(MockDeployer::new())
+        .registry_auth("ghcr.io", "ci-bot", "CATAPULTA_TEST_UNSET_TOKEN")
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    assert!(!fake.commands().iter().any(|c| c.contains("login")));
+}
+
+#[test]
+fn missing_token_fails_before_deploying() {
+    let fake = FakeSsh::new();
+
+    let err = pipeline("missing", &fake, "CATAPULTA_TEST_MISSING_TOKEN")
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E501");
+    assert!(err.to_string().contains("CATAPULTA_TEST_MISSING_TOKEN"));
+    assert_eq!(fake.file("/opt/app/.registry-token"), None);
+}
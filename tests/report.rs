@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use catapulta::events::DeployEvent;
+use catapulta::report::{self, HostReport, Recorder, ReportFormat};
+use catapulta::scan::ScanReport;
+use catapulta::ssh::SshOptions;
+use catapulta::state::DeploymentRecord;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::timing::Timings;
+use catapulta::{App, Caddy, Pipeline};
+
+fn deployment(revision: &str, images: &[(&str, &str)], success: bool) -> DeploymentRecord {
+    DeploymentRecord {
+        host: "web1".to_string(),
+        at: 0,
+        success,
+        images: images
+            .iter()
+            .map(|(app, image)| ((*app).to_string(), (*image).to_string()))
+            .collect(),
+        revision: Some(revision.to_string()),
+        user: Some("alice".to_string()),
+        scans: BTreeMap::new(),
+    }
+}
+
+fn host_report(success: bool) -> HostReport {
+    let mut recorder = Recorder::new();
+    for event in [
+        DeployEvent::ImageBuilt {
+            app: "api".into(),
+            tag: "api:latest".into(),
+            size: 1024,
+        },
+        DeployEvent::PhaseStarted {
+            phase: "deploy".into(),
+            host: Some("web1".into()),
+        },
+        DeployEvent::HealthcheckAttempt {
+            app: "api".into(),
+            attempt: 1,
+            max_attempts: 30,
+            status: None,
+        },
+        DeployEvent::HealthcheckAttempt {
+            app: "api".into(),
+            attempt: 3,
+            max_attempts: 30,
+            status: Some("healthy".into()),
+        },
+        DeployEvent::PhaseStarted {
+            phase: "post-deploy hooks".into(),
+            host: Some("web1".into()),
+        },
+    ] {
+        recorder.record(&event);
+    }
+    recorder.built.get_mut("api").unwrap().digest = Some("sha256:abc".into());
+
+    let mut current = deployment(
+        "2b2b2b2",
+        &[("api", "api:latest"), ("db", "postgres:17")],
+        success,
+    );
+    current.scans.insert(
+        "api:latest".into(),
+        ScanReport {
+            scanner: "trivy".into(),
+            high: 2,
+            ..ScanReport::default()
+        },
+    );
+    let previous = deployment(
+        "1a1a1a1",
+        &[
+            ("api", "api:latest"),
+            ("db", "postgres:16"),
+            ("cache", "redis:7"),
+        ],
+        true,
+    );
+    let timings = Timings {
+        phases: vec![("build".into(), Duration::from_millis(12_340))],
+        total: Duration::from_secs(40),
+    };
+    let error = (!success).then(|| "smoke-test exited with 1".to_string());
+    let mut report = recorder.finish_host(current, Some(previous), timings, error);
+    report.hooks = vec!["curl -f https://example.com/health".into()];
+    report
+}
+
+#[test]
+fn changes_since_previous_deploy() {
+    let report = host_report(true);
+
+    assert_eq!(
+        report.changes(),
+        [
+            "Revision 1a1a1a1 → 2b2b2b2",
+            "db: postgres:16 → postgres:17",
+            "cache removed"
+        ]
+    );
+}
+
+#[test]
+fn first_deploy_has_no_previous() {
+    let mut report = host_report(true);
+    report.previous = None;
+
+    assert_eq!(report.changes(), ["First deploy to this host"]);
+}
+
+#[test]
+fn recorder_takes_healthchecks_per_host() {
+    let mut recorder = Recorder::new();
+    recorder.record(&DeployEvent::HealthcheckAttempt {
+        app: "api".into(),
+        attempt: 1,
+        max_attempts: 5,
+        status: Some("healthy".into()),
+    });
+    let first = recorder.finish_host(
+        deployment("1a1a1a1", &[], true),
+        None,
+        Timings::default(),
+        None,
+    );
+    let second = recorder.finish_host(
+        deployment("1a1a1a1", &[], true),
+        None,
+        Timings::default(),
+        None,
+    );
+
+    assert_eq!(first.healthchecks["api"].attempts, 1);
+    assert!(second.healthchecks.is_empty());
+    assert_eq!(first.hooks_outcome(), "not run");
+}
+
+#[test]
+fn markdown_report() {
+    let markdown = report::render(&[host_report(true)], ReportFormat::Markdown);
+
+    assert!(markdown.starts_with("# Deploy report\n\n## web1 (succeeded)\n"));
+    assert!(markdown.contains("- Revision: 2b2b2b2\n- User: alice\n"));
+    assert!(markdown.contains(
+        "| App | Image | Digest |\n|---|---|---|\n\
+         | api | api:latest | sha256:abc |\n| db | postgres:17 | - |\n"
+    ));
+    assert!(markdown.contains("| api | healthy | 3/30 |"));
+    assert!(
+        markdown.contains("### Smoke tests (passed)\n\n- curl -f https://example.com/health\n")
+    );
+    assert!(markdown.contains("| api:latest | 0 | 2 | 0 | 0 |"));
+    assert!(markdown.contains("| build | 12.3s |\n| total | 40.0s |"));
+    assert!(!markdown.contains("### Error"));
+}
+
+#[test]
+fn failed_deploy_reports_the_error() {
+    let markdown = report::render(&[host_report(false)], ReportFormat::Markdown);
+
+    assert!(markdown.contains("## web1 (failed)"));
+    assert!(markdown.contains("### Error\n\n```\nsmoke-test exited with 1\n```"));
+    assert!(markdown.contains("### Smoke tests (failed)"));
+}
+
+#[test]
+fn html_report_is_escaped() {
+    let mut report = host_report(false);
+    report.error = Some("<script>".into());
+
+    let html = report::render(&[report], ReportFormat::Html);
+
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h2>web1 (failed)</h2>"));
+    assert!(html.contains("<pre>&lt;script&gt;</pre>"));
+    assert!(html.contains("<tr><td>api</td><td>api:latest</td><td>sha256:abc</td></tr>"));
+    assert!(html.ends_with("</body>\n</html>\n"));
+}
+
+#[test]
+fn format_follows_the_extension() {
+    assert_eq!(ReportFormat::for_path("report.HTML"), ReportFormat::Html);
+    assert_eq!(ReportFormat::for_path("out/report.htm"), ReportFormat::Html);
+    assert_eq!(
+        ReportFormat::for_path("deploy-report.md"),
+        ReportFormat::Markdown
+    );
+}
+
+#[test]
+fn deploy_writes_a_report_per_host() {
+    let dir = std::env::temp_dir().join("catapulta-report-deploy");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("report.md");
+    let web = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+
+    Pipeline::new(web, caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .local_dir(dir.to_str().unwrap())
+        .after_deploy("smoke-test")
+        .report_file(path.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1", "web2"])
+        .unwrap();
+
+    let markdown = std::fs::read_to_string(&path).unwrap();
+    assert!(markdown.contains("## web1 (succeeded)"));
+    assert!(markdown.contains("## web2 (succeeded)"));
+    assert!(markdown.contains("| web | web:latest |"));
+    assert!(markdown.contains("### Smoke tests (passed)\n\n- smoke-test\n"));
+    assert!(markdown.contains("- First deploy to this host"));
+}
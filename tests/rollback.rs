@@ -0,0 +1,195 @@
+use catapulta::deploy::Runtime;
+use catapulta::rollback;
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+fn pipeline(name: &str, fake: &FakeSsh) -> Pipeline {
+    let web = App::new("web").expose(3000).healthcheck("true");
+    let db = App::new("db").image("postgres:16");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-rollback-{name}"));
+    Pipeline::multi(vec![web, db], caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+}
+
+#[test]
+fn keep_shifts_history_oldest_first() {
+    let command = rollback::keep_command("api", 2, Runtime::Docker);
+
+    assert!(command.contains(
+        "docker tag api:previous api:previous-2 2>/dev/null; \
+         docker tag api:latest api:previous 2>/dev/null"
+    ));
+    assert!(command.contains("\"$latest\" != \"$previous\""));
+    assert_eq!(rollback::keep_command("api", 0, Runtime::Docker), "true");
+    assert_eq!(rollback::previous_tag(3), "previous-3");
+}
+
+#[test]
+fn rollback_moves_history_up() {
+    let command = rollback::rollback_command("api", 2, Runtime::Docker);
+
+    assert!(command.starts_with("docker image inspect api:previous"));
+    assert!(command.contains("docker tag api:previous api:latest"));
+    assert!(command.contains("docker tag api:previous-2 api:previous"));
+    assert!(command.contains("docker rmi api:previous-2"));
+}
+
+#[test]
+fn deploy_keeps_previous_image_of_built_apps() {
+    let fake = FakeSsh::new();
+
+    pipeline("deploy", &fake)
+        .keep_images(3)
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert!(commands.contains(&rollback::keep_command("web", 3, Runtime::Docker)));
+    assert!(!commands.iter().any(|c| c.contains("db:previous")));
+}
+
+#[test]
+fn deploy_without_history() {
+    let fake = FakeSsh::new();
+
+    pipeline("no-history", &fake)
+        .keep_images(0)
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    assert!(!fake.commands().iter().any(|c| c.contains(":previous")));
+}
+
+#[test]
+fn rollback_restarts_services() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+
+    pipeline("rollback", &fake)
+        .run_from(["xtask", "rollback", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert_eq!(
+        commands[0],
+        rollback::rollback_command("web", 1, Runtime::Docker)
+    );
+    assert_eq!(
+        commands[1],
+        "cd /opt/app && docker compose up -d --no-deps web"
+    );
+}
+
+#[test]
+fn rollback_of_prebuilt_image_is_rejected() {
+    let fake = FakeSsh::new();
+
+    let err = pipeline("prebuilt", &fake)
+        .run_from(["xtask", "rollback", "web1", "--only", "db"])
+        .unwrap_err();
+
+    assert!(err.to_string().contains("no built images"));
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn version_tags_count_deploys_and_drop_old_ones() {
+    let command = rollback::version_command("api", Some("1a2b3c4"), 2, Runtime::Docker);
+
+    assert!(command.starts_with(
+        "n=$(docker image ls api --format '{{.Tag}}' | grep '^deploy-[0-9]' | sort -t- -k2 -rn \
+         | head -n1 | cut -d- -f2)"
+    ));
+    assert!(command.contains(" + 1))-1a2b3c4;"));
+    assert!(command.contains("docker tag api:latest api:$tag && echo $tag"));
+    assert!(command.contains("tail -n +4 | while read -r old; do docker rmi api:$old"));
+    assert!(
+        rollback::version_command("api", None, 1, Runtime::Podman).contains(" + 1)); podman tag")
+    );
+}
+
+#[test]
+fn rollback_to_a_version() {
+    let by_number = rollback::rollback_to_command("api", "3", 1, Runtime::Docker);
+    assert!(by_number.contains("grep -E '^deploy-3(-|$)'"));
+    assert!(by_number.contains(&rollback::keep_command("api", 1, Runtime::Docker)));
+    assert!(by_number.ends_with("docker tag api:$tag api:latest"));
+
+    let by_tag = rollback::rollback_to_command("api", "deploy-3-1a2b3c4", 1, Runtime::Docker);
+    assert!(by_tag.contains("grep -E '^deploy-3-1a2b3c4$'"));
+
+    assert!(rollback::is_version("deploy-3-1a2b3c4"));
+    assert!(!rollback::is_version("3; rm -rf /"));
+    assert!(!rollback::is_version(""));
+}
+
+#[test]
+fn deploy_tags_the_transferred_images_with_a_version() {
+    let fake = FakeSsh::new().respond("echo $tag", "deploy-1\n");
+
+    pipeline("version", &fake)
+        .keep_images(2)
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    let keep = commands
+        .iter()
+        .position(|c| *c == rollback::keep_command("web", 2, Runtime::Docker))
+        .unwrap();
+    let version = commands
+        .iter()
+        .position(|c| c.contains("docker tag web:latest web:$tag"))
+        .unwrap();
+    assert!(keep < version);
+    assert!(commands[version].contains("tail -n +4"));
+    assert!(!commands.iter().any(|c| c.contains("db:$tag")));
+}
+
+#[test]
+fn rollback_to_restarts_services() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+
+    pipeline("rollback-to", &fake)
+        .run_from(["xtask", "rollback", "web1", "--to", "2"])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert_eq!(
+        commands[0],
+        rollback::rollback_to_command("web", "2", 1, Runtime::Docker)
+    );
+    assert_eq!(
+        commands[1],
+        "cd /opt/app && docker compose up -d --no-deps web"
+    );
+}
+
+#[test]
+fn rollback_lists_versions_without_restarting() {
+    let fake = FakeSsh::new().respond("while read -r tag", "deploy-2 (current)\ndeploy-1\n");
+
+    pipeline("list", &fake)
+        .run_from(["xtask", "rollback", "web1", "--list"])
+        .unwrap();
+
+    assert_eq!(
+        fake.commands(),
+        [rollback::list_command("web", Runtime::Docker)]
+    );
+}
+
+#[test]
+fn rollback_to_invalid_version_is_rejected() {
+    let fake = FakeSsh::new();
+
+    let err = pipeline("invalid", &fake)
+        .run_from(["xtask", "rollback", "web1", "--to", "2;reboot"])
+        .unwrap_err();
+
+    assert!(err.to_string().contains("invalid version"));
+    assert!(fake.calls().is_empty());
+}
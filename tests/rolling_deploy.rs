@@ -0,0 +1,105 @@
+use catapulta::deploy::Runtime;
+use catapulta::rollback;
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer, SshCall};
+use catapulta::{App, Caddy, Pipeline};
+
+fn pipeline(name: &str, deployer: &MockDeployer, fake: &FakeSsh) -> Pipeline {
+    let web = App::new("web").expose(3000).healthcheck("true");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-rolling-{name}"));
+    Pipeline::new(web, caddy)
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .after_deploy("smoke-test")
+}
+
+fn hosts(fake: &FakeSsh) -> Vec<String> {
+    let mut hosts: Vec<String> = fake
+        .calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            SshCall::Exec { host, .. } => Some(host),
+            _ => None,
+        })
+        .collect();
+    hosts.dedup();
+    hosts
+}
+
+#[test]
+fn hosts_are_deployed_one_at_a_time() {
+    let deployer = MockDeployer::new();
+    let fake = FakeSsh::new();
+
+    pipeline("order", &deployer, &fake)
+        .run_from(["xtask", "deploy", "web1", "web2"])
+        .unwrap();
+
+    assert_eq!(
+        deployer.calls(),
+        [
+            "build_image web",
+            "transfer_image web web1",
+            "deploy web1 /opt/app",
+            "transfer_image web web2",
+            "deploy web2 /opt/app",
+        ]
+    );
+    assert_eq!(hosts(&fake), ["web1", "web2"]);
+}
+
+#[test]
+fn failing_host_stops_the_rollout() {
+    let deployer = MockDeployer::new();
+    let fake = FakeSsh::new().fail("smoke-test", 1);
+
+    let err = pipeline("stop", &deployer, &fake)
+        .run_from(["xtask", "deploy", "web1", "web2"])
+        .unwrap_err();
+
+    assert!(err.to_string().starts_with("post-deploy hooks on web1"));
+    assert!(!deployer.calls().iter().any(|c| c.contains("web2")));
+    assert_eq!(hosts(&fake), ["web1"]);
+    assert!(
+        !fake
+            .commands()
+            .contains(&rollback::rollback_command("web", 1, Runtime::Docker))
+    );
+}
+
+#[test]
+fn failing_host_is_rolled_back_on_request() {
+    let deployer = MockDeployer::new();
+    let fake = FakeSsh::new()
+        .fail("smoke-test", 1)
+        .respond("docker inspect", "healthy\n");
+
+    pipeline("rollback", &deployer, &fake)
+        .run_from(["xtask", "deploy", "web1", "web2", "--rollback-on-failure"])
+        .unwrap_err();
+
+    let commands = fake.commands();
+    let rollback = commands
+        .iter()
+        .position(|c| *c == rollback::rollback_command("web", 1, Runtime::Docker))
+        .unwrap();
+    assert_eq!(
+        commands[rollback + 1],
+        "cd /opt/app && docker compose up -d --no-deps web"
+    );
+    assert_eq!(hosts(&fake), ["web1"]);
+}
+
+#[test]
+fn build_failure_is_not_rolled_back() {
+    let deployer = MockDeployer::new().fail("build_image");
+    let fake = FakeSsh::new();
+
+    pipeline("build", &deployer, &fake)
+        .run_from(["xtask", "deploy", "web1", "--rollback-on-failure"])
+        .unwrap_err();
+
+    assert!(!fake.commands().iter().any(|c| c.contains(":previous")));
+}
@@ -0,0 +1,152 @@
+use catapulta::scale::{self, Replicas};
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, DockerSaveLoad, Pipeline};
+
+fn pipeline(name: &str, fake: &FakeSsh) -> Pipeline {
+    let web = App::new("web").expose(3000).healthcheck("true");
+    let worker = App::new("worker").healthcheck("true");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-scale-{name}"));
+    Pipeline::multi(vec![web, worker], caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+}
+
+#[test]
+fn parse_specs() {
+    assert_eq!(scale::parse_spec("worker=0").unwrap(), ("worker".into(), 0));
+    assert!(scale::parse_spec("=2").is_err());
+    assert!(scale::parse_spec("api=many").is_err());
+}
+
+#[test]
+fn state_round_trip() {
+    let mut replicas = Replicas::new();
+    replicas.insert("api".to_string(), 3);
+
+    let parsed = scale::parse_state(&scale::render_state(&replicas)).unwrap();
+    assert_eq!(parsed, replicas);
+    assert!(scale::parse_state("").unwrap().is_empty());
+    assert_eq!(scale::parse_state("[1]").unwrap_err().code(), "E503");
+}
+
+#[test]
+fn apply_ignores_unknown_services() {
+    let mut replicas = Replicas::new();
+    replicas.insert("api".to_string(), 3);
+    replicas.insert("gone".to_string(), 2);
+
+    let apps = scale::apply(&[App::new("api"), App::new("web")], &replicas);
+
+    assert_eq!(apps[0].replicas, 3);
+    assert_eq!(apps[1].replicas, 1);
+}
+
+#[test]
+fn scale_routed_service() {
+    let fake = FakeSsh::new()
+        .respond("cat /opt/app/replicas.json", "{\"worker\": 2}")
+        .respond("docker inspect", "healthy\nhealthy\nhealthy\n");
+
+    pipeline("routed", &fake)
+        .run_from(["xtask", "scale", "web1", "web=3"])
+        .unwrap();
+
+    assert!(
+        fake.commands().contains(
+            &"cd /opt/app && docker compose up -d --no-deps --scale web=3 web".to_string()
+        )
+    );
+    let state = scale::parse_state(&fake.file("/opt/app/replicas.json").unwrap()).unwrap();
+    assert_eq!(state["worker"], 2);
+    assert_eq!(state["web"], 3);
+    assert!(
+        fake.file("/opt/app/Caddyfile")
+            .unwrap()
+            .contains("dynamic a web 3000")
+    );
+    assert!(fake.commands().iter().any(|c| c.contains("caddy reload")));
+    let compose = fake.file("/opt/app/docker-compose.yml").unwrap();
+    assert!(!compose.contains("container_name: web\n"));
+}
+
+#[test]
+fn scale_unrouted_service_keeps_caddy() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\nhealthy\n");
+
+    pipeline("unrouted", &fake)
+        .run_from(["xtask", "scale", "web1", "worker=2"])
+        .unwrap();
+
+    assert!(fake.file("/opt/app/Caddyfile").is_none());
+    assert_eq!(
+        scale::parse_state(&fake.file("/opt/app/replicas.json").unwrap()).unwrap()["worker"],
+        2
+    );
+}
+
+#[test]
+fn scale_rejects_unknown_service() {
+    let fake = FakeSsh::new();
+
+    let err = pipeline("unknown", &fake)
+        .run_from(["xtask", "scale", "web1", "api=2"])
+        .unwrap_err();
+
+    assert!(err.to_string().contains("unknown service 'api'"));
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn deploy_keeps_recorded_replicas() {
+    let fake = FakeSsh::new()
+        .respond("cat /opt/app/replicas.json", "{\"web\": 3}")
+        .respond("docker inspect", "healthy\nhealthy\nhealthy\n");
+    let web = App::new("web")
+        .image("nginx:alpine")
+        .expose(80)
+        .healthcheck("true");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join("catapulta-scale-deploy");
+
+    Pipeline::new(web, caddy)
+        .deploy(DockerSaveLoad::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "deploy", "web1"])
+        .unwrap();
+
+    let compose = fake.file("/opt/app/docker-compose.yml").unwrap();
+    assert!(compose.contains("replicas: 3"));
+    assert!(
+        fake.file("/opt/app/Caddyfile")
+            .unwrap()
+            .contains("dynamic a web 80")
+    );
+}
+
+#[test]
+fn scale_writes_stack_files_through_the_deployer() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\nhealthy\nhealthy\n");
+    let deployer = MockDeployer::new();
+    let web = App::new("web").expose(3000).healthcheck("true");
+    let dir = std::env::temp_dir().join("catapulta-scale-deployer");
+
+    Pipeline::new(web.clone(), Caddy::new().reverse_proxy(web.upstream()))
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "scale", "web1", "web=3"])
+        .unwrap();
+
+    assert_eq!(
+        deployer.calls(),
+        vec![
+            "write_file web1 /opt/app/docker-compose.yml",
+            "write_file web1 /opt/app/Caddyfile",
+        ]
+    );
+    assert!(fake.file("/opt/app/docker-compose.yml").is_some());
+}
@@ -0,0 +1,89 @@
+use catapulta::error::DeployError;
+use catapulta::scan::{ImageScan, ScanReport, Severity, parse_scout, parse_trivy};
+
+const TRIVY: &str = r#"{
+  "ArtifactName": "api:latest",
+  "Results": [
+    {
+      "Target": "api:latest (debian 12.5)",
+      "Vulnerabilities": [
+        {"VulnerabilityID": "CVE-2024-0001", "Severity": "CRITICAL"},
+        {"VulnerabilityID": "CVE-2024-0002", "Severity": "HIGH"},
+        {"VulnerabilityID": "CVE-2024-0003", "Severity": "LOW"},
+        {"VulnerabilityID": "CVE-2024-0004", "Severity": "UNKNOWN"}
+      ]
+    },
+    {"Target": "app/Cargo.lock"}
+  ]
+}"#;
+
+#[test]
+fn trivy_report_counts_by_severity() {
+    let report = parse_trivy(TRIVY).unwrap();
+
+    assert_eq!(report.scanner, "trivy");
+    assert_eq!(
+        (report.critical, report.high, report.medium, report.low),
+        (1, 1, 0, 1)
+    );
+    assert_eq!(report.at_or_above(Severity::High), 2);
+    assert_eq!(report.to_string(), "1 critical, 1 high, 0 medium, 1 low");
+}
+
+#[test]
+fn scout_report_counts_by_severity() {
+    let report = parse_scout(
+        r#"{"version": "15.0.0", "vulnerabilities": [
+            {"id": "CVE-2024-0005", "severity": "Medium"},
+            {"id": "CVE-2024-0006", "severity": "Medium"},
+            {"id": "CVE-2024-0007", "severity": "Unspecified"}
+        ]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(report.scanner, "docker scout");
+    assert_eq!(report.medium, 2);
+    assert_eq!(report.at_or_above(Severity::Low), 2);
+    assert!(parse_scout("not json").is_err());
+}
+
+#[test]
+fn scan_fails_at_the_threshold() {
+    let report = ScanReport {
+        high: 3,
+        ..ScanReport::default()
+    };
+
+    assert!(ImageScan::new().check("api:latest", &report).is_ok());
+    let err = ImageScan::new()
+        .fail_on(Severity::High)
+        .check("api:latest", &report)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        DeployError::Vulnerable {
+            threshold: Severity::High,
+            ..
+        }
+    ));
+    assert_eq!(err.code(), "E607");
+    assert!(err.to_string().contains("api:latest"));
+    assert!(
+        ImageScan::new()
+            .fail_on(Severity::Low)
+            .report_only()
+            .check("api:latest", &report)
+            .is_ok()
+    );
+}
+
+#[test]
+fn severities_are_ordered() {
+    assert!(Severity::Critical > Severity::High);
+    assert_eq!(Severity::parse("HIGH"), Some(Severity::High));
+    assert_eq!(Severity::parse("negligible"), None);
+    assert_eq!(
+        serde_json::to_string(&Severity::Medium).unwrap(),
+        "\"medium\""
+    );
+}
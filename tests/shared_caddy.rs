@@ -0,0 +1,137 @@
+use catapulta::compose;
+use catapulta::error::DeployError;
+use catapulta::shared_caddy::{self, SHARED_DIR, SHARED_NETWORK};
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+#[test]
+fn stack_joins_the_shared_network_without_its_own_caddy() {
+    let ghost = App::new("ghost").image("ghost:5").expose(2368);
+    let mysql = App::new("mysql").image("mysql:8").expose(3306);
+    let caddy = Caddy::new().reverse_proxy(ghost.upstream()).shared();
+
+    let yaml = compose::with_project(Some("blog"), || compose::render(&[ghost, mysql], &caddy));
+
+    assert!(!yaml.contains("\n  caddy:\n"));
+    assert!(!yaml.contains("443:443"));
+    assert!(yaml.contains("blog-ghost"));
+    assert!(yaml.contains(&format!("{SHARED_NETWORK}:\n    external: true")));
+    // Only proxied apps are reachable from the shared Caddy
+    let mysql_service = &yaml[yaml.find("  mysql:").unwrap()..];
+    let mysql_service = &mysql_service[..mysql_service.find("\nnetworks:").unwrap()];
+    assert!(!mysql_service.contains(SHARED_NETWORK));
+}
+
+#[test]
+fn site_proxies_to_stack_aliases() {
+    let api = App::new("api").expose(8000).replicas(2);
+    let web = App::new("web").expose(3000).domain("shop.example.com");
+    let caddy = Caddy::new()
+        .route("/api/*", api.upstream())
+        .route("", web.upstream())
+        .shared();
+
+    let site = compose::with_project(Some("shop"), || {
+        shared_caddy::render_site(&caddy, "example.com", &[api, web])
+    })
+    .unwrap();
+
+    assert!(site.contains("shop-web:3000"));
+    assert!(site.contains("dynamic a shop-api 8000"));
+    assert!(site.contains("shop.example.com {"));
+    assert_eq!(
+        shared_caddy::site_file("shop"),
+        format!("{SHARED_DIR}/sites/shop.caddy")
+    );
+}
+
+#[test]
+fn json_config_is_rejected() {
+    let app = App::new("api").expose(8000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream()).json().shared();
+
+    let err = shared_caddy::render_site(&caddy, "example.com", &[app]).unwrap_err();
+
+    assert!(matches!(err, DeployError::InvalidConfig(_)));
+}
+
+#[test]
+fn dns_challenge_is_rejected() {
+    let app = App::new("api").expose(8000);
+    let caddy = Caddy::new()
+        .reverse_proxy(app.upstream())
+        .dns_challenge("cloudflare", "CF_API_TOKEN", "token")
+        .shared();
+
+    let err = shared_caddy::render_site(&caddy, "example.com", &[app]).unwrap_err();
+
+    assert!(matches!(err, DeployError::InvalidConfig(_)));
+}
+
+#[test]
+fn deploy_starts_the_shared_caddy_and_installs_the_site() {
+    let fake = FakeSsh::new().respond("docker inspect", "healthy\n");
+    let ghost = App::new("ghost")
+        .image("ghost:5")
+        .expose(2368)
+        .domain("blog.example.com")
+        .healthcheck("true");
+    let caddy = Caddy::new().shared();
+    let dir = std::env::temp_dir().join("catapulta-shared-caddy");
+    let pipeline = Pipeline::new(ghost, caddy)
+        .project("blog")
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap());
+
+    pipeline.run_from(["xtask", "deploy", "web1"]).unwrap();
+
+    let commands = fake.commands();
+    let start = commands
+        .iter()
+        .position(|c| c.contains(&format!("docker network create {SHARED_NETWORK}")))
+        .unwrap();
+    let reload = commands
+        .iter()
+        .position(|c| c.starts_with(&format!("cd {SHARED_DIR}")) && c.contains("caddy reload"))
+        .unwrap();
+    assert!(start < reload);
+    assert!(
+        fake.file(&format!("{SHARED_DIR}/docker-compose.yml"))
+            .is_some()
+    );
+    let site = fake
+        .file(&format!("{SHARED_DIR}/sites/blog.caddy"))
+        .unwrap();
+    assert!(site.contains("reverse_proxy blog-ghost:2368"));
+    assert!(fake.file("/opt/app/Caddyfile").is_none());
+}
+
+#[test]
+fn maintenance_swaps_the_site_file() {
+    let fake = FakeSsh::new();
+    let ghost = App::new("ghost").expose(2368).domain("blog.example.com");
+    let caddy = Caddy::new().shared();
+    let dir = std::env::temp_dir().join("catapulta-shared-caddy-maintenance");
+    let pipeline = Pipeline::new(ghost, caddy)
+        .project("blog")
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap());
+
+    pipeline
+        .run_from(["xtask", "maintenance", "--on", "web1"])
+        .unwrap();
+    let site = fake
+        .file(&format!("{SHARED_DIR}/sites/blog.caddy"))
+        .unwrap();
+    assert!(site.contains("respond <<HTML"));
+
+    pipeline
+        .run_from(["xtask", "maintenance", "--off", "web1"])
+        .unwrap();
+    let site = fake
+        .file(&format!("{SHARED_DIR}/sites/blog.caddy"))
+        .unwrap();
+    assert!(site.contains("reverse_proxy blog-ghost:2368"));
+}
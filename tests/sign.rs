@@ -0,0 +1,51 @@
+use catapulta::error::DeployError;
+use catapulta::sign::{REMOTE_PUBLIC_KEY, Signer, verify_command};
+use catapulta::ssh::{FileAttrs, SshOptions, SshSession};
+use catapulta::testing::FakeSsh;
+
+fn session(fake: &FakeSsh) -> SshSession {
+    SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()))
+}
+
+#[test]
+fn verify_against_the_pinned_key() {
+    assert_eq!(
+        verify_command("/tmp/catapulta-api.tar", "/tmp/catapulta-api.tar.minisig"),
+        "minisign -Vq -p /etc/catapulta/minisign.pub -m '/tmp/catapulta-api.tar' \
+         -x '/tmp/catapulta-api.tar.minisig'"
+    );
+}
+
+#[test]
+fn signed_files_carry_their_signature() {
+    let fake = FakeSsh::new();
+    let attrs = FileAttrs::new()
+        .mode(0o600)
+        .signature("untrusted comment: ...\nRWQ...");
+
+    session(&fake)
+        .write_remote_file_with("KEY=value\n", "/opt/app/.env", &attrs)
+        .unwrap();
+
+    assert_eq!(fake.file("/opt/app/.env").as_deref(), Some("KEY=value\n"));
+    assert_eq!(fake.file_mode("/opt/app/.env"), Some(0o600));
+    assert!(
+        fake.file("/opt/app/.env.minisig")
+            .unwrap()
+            .starts_with("untrusted comment")
+    );
+}
+
+#[test]
+fn install_pins_the_public_key() {
+    let key = std::env::temp_dir().join("catapulta-sign-install.pub");
+    std::fs::write(&key, "untrusted comment: minisign public key\nRWQ...\n").unwrap();
+    let signer = Signer::minisign("minisign.key", key.to_str().unwrap());
+
+    let fake = FakeSsh::new().respond("command -v minisign", "yes");
+    signer.install(&session(&fake)).unwrap();
+    assert!(fake.file(REMOTE_PUBLIC_KEY).unwrap().ends_with("RWQ...\n"));
+
+    let err = signer.install(&session(&FakeSsh::new())).unwrap_err();
+    assert!(matches!(err, DeployError::PrerequisiteMissing(ref m) if m.contains("web1")));
+}
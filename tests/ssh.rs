@@ -0,0 +1,355 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use catapulta::cmd;
+use catapulta::error::DeployResult;
+use catapulta::ssh::tunnel::PortForward;
+use catapulta::ssh::{
+    FileAttrs, SshOptions, SshSession, SshTransport, bash_script_command, inline_ssh_includes,
+    pinned_hosts_file, replace_command, shell_quote,
+};
+
+/// Transport logging the commands run and the files written.
+#[derive(Debug, Clone, Default)]
+struct Log(Arc<Mutex<Vec<String>>>);
+
+impl Log {
+    fn push(&self, line: String) {
+        self.0.lock().unwrap().push(line);
+    }
+}
+
+impl SshTransport for Log {
+    fn exec(&self, session: &SshSession, command: &str) -> DeployResult<String> {
+        self.push(format!("{} exec {command}", session.host()));
+        Ok(String::new())
+    }
+
+    fn exec_interactive(&self, session: &SshSession, command: &str) -> DeployResult<()> {
+        self.exec(session, command).map(drop)
+    }
+
+    fn scp_to(
+        &self,
+        _session: &SshSession,
+        local_path: &str,
+        remote_path: &str,
+        _progress: &mut dyn FnMut(u64, u64),
+    ) -> DeployResult<()> {
+        self.push(format!("scp {local_path} {remote_path}"));
+        Ok(())
+    }
+
+    fn write_file(
+        &self,
+        _session: &SshSession,
+        content: &str,
+        remote_path: &str,
+    ) -> DeployResult<()> {
+        self.push(format!("write {remote_path} {content}"));
+        Ok(())
+    }
+
+    fn pipe_from_local(
+        &self,
+        _session: &SshSession,
+        local: &cmd::Pipe,
+        remote_command: &str,
+    ) -> DeployResult<()> {
+        self.push(format!("pipe {local} {remote_command}"));
+        Ok(())
+    }
+
+    fn forward(&self, _session: &SshSession, fwd: &PortForward) -> DeployResult<()> {
+        self.push(format!("forward {}", fwd.to_spec()));
+        Ok(())
+    }
+}
+
+#[test]
+fn session_accessors() {
+    let ssh = SshSession::new("example.com", "deploy");
+
+    assert_eq!(ssh.host(), "example.com");
+    assert_eq!(ssh.user(), "deploy");
+    assert_eq!(ssh.destination(), "deploy@example.com");
+}
+
+#[test]
+fn ssh_command_defaults() {
+    let cmd = SshSession::new("example.com", "root").ssh_command();
+
+    assert!(cmd.starts_with("ssh -o StrictHostKeyChecking=yes"));
+    assert!(cmd.contains(" -o ConnectTimeout=10"));
+    assert!(cmd.contains(" -o ServerAliveInterval=15"));
+    assert!(!cmd.contains("Port="));
+}
+
+#[test]
+fn ssh_command_port_timeout_keepalive() {
+    let cmd = SshSession::new("example.com", "root")
+        .port(2222)
+        .connect_timeout(Duration::from_secs(30))
+        .keepalive(None)
+        .ssh_command();
+
+    assert!(cmd.contains(" -o ConnectTimeout=30"));
+    assert!(cmd.contains(" -o Port=2222"));
+    assert!(!cmd.contains("ServerAliveInterval"));
+}
+
+#[test]
+fn ssh_command_uses_pinned_hosts_file() {
+    let cmd = SshSession::new("example.com", "root").ssh_command();
+    let pins = pinned_hosts_file().unwrap();
+
+    assert!(pins.ends_with(".ssh/catapulta_known_hosts"));
+    assert!(cmd.contains(&format!(
+        " -o 'UserKnownHostsFile={} {}/.ssh/known_hosts'",
+        pins.display(),
+        std::env::var("HOME").unwrap()
+    )));
+}
+
+#[test]
+fn ssh_command_pins_new_host_keys_only_when_trusted() {
+    let options = SshOptions::new().trust_new_host_key();
+    let cmd = SshSession::new("example.com", "root")
+        .with_options(&options)
+        .ssh_command();
+
+    assert!(cmd.starts_with("ssh -o StrictHostKeyChecking=accept-new"));
+    // A stale entry in the user's known_hosts must not reject the
+    // new key
+    let pins = pinned_hosts_file().unwrap();
+    assert!(cmd.contains(&format!(" -o UserKnownHostsFile={} ", pins.display())));
+}
+
+#[test]
+fn ssh_command_with_key() {
+    let ssh = SshSession::new("example.com", "root").with_key("/tmp/id");
+
+    assert!(ssh.ssh_command().ends_with(" -i /tmp/id"));
+}
+
+#[test]
+fn ssh_command_via_jump() {
+    let ssh = SshSession::new("10.0.0.5", "root").via_jump("bastion", "ops", None);
+
+    let cmd = ssh.ssh_command();
+    assert!(cmd.contains(" -o 'ProxyCommand=ssh -o StrictHostKeyChecking=yes"));
+    assert!(cmd.ends_with(" -W %h:%p ops@bastion'"));
+}
+
+#[test]
+fn ssh_command_via_jump_with_key() {
+    let ssh = SshSession::new("10.0.0.5", "root").via_jump("bastion", "ops", Some("/tmp/jump"));
+
+    let pins = pinned_hosts_file().unwrap();
+    let home = std::env::var("HOME").unwrap();
+
+    assert!(ssh.ssh_command().ends_with(&format!(
+        " -o 'ProxyCommand=ssh -i /tmp/jump -o StrictHostKeyChecking=yes \
+         -o \"UserKnownHostsFile={} {home}/.ssh/known_hosts\" -W %h:%p ops@bastion'",
+        pins.display()
+    )));
+}
+
+#[test]
+fn ssh_command_via_jump_pins_like_the_destination() {
+    let options = SshOptions::new()
+        .jump_host("bastion", "ops", None)
+        .trust_new_host_key();
+    let cmd = SshSession::new("10.0.0.5", "root")
+        .with_options(&options)
+        .ssh_command();
+    let pins = pinned_hosts_file().unwrap();
+
+    assert!(cmd.ends_with(&format!(
+        " -o 'ProxyCommand=ssh -o StrictHostKeyChecking=accept-new \
+         -o \"UserKnownHostsFile={}\" -W %h:%p ops@bastion'",
+        pins.display()
+    )));
+}
+
+#[test]
+fn options_jump_host() {
+    let opts = SshOptions::new().jump_host("bastion", "ops", Some("/tmp/jump"));
+    let jump = opts.jump.unwrap();
+
+    assert_eq!(jump.host, "bastion");
+    assert_eq!(jump.user, "ops");
+    assert_eq!(jump.key.as_deref(), Some("/tmp/jump"));
+}
+
+#[test]
+fn session_with_options() {
+    let opts = SshOptions::new().jump_host("bastion", "ops", None);
+    let ssh = SshSession::new("10.0.0.5", "root").with_options(&opts);
+
+    assert!(ssh.ssh_command().contains("-W %h:%p ops@bastion"));
+}
+
+#[test]
+fn options_defaults() {
+    let opts = SshOptions::default();
+
+    assert!(opts.jump.is_none());
+    assert!(opts.port.is_none());
+    assert_eq!(opts.connect_timeout, Duration::from_secs(10));
+    assert_eq!(opts.retries, 30);
+    assert_eq!(opts.retry_interval, Duration::from_secs(10));
+    assert_eq!(opts.keepalive, Some(Duration::from_secs(15)));
+    assert_eq!(opts.command_timeout, Some(Duration::from_secs(300)));
+}
+
+#[test]
+fn options_retries() {
+    let opts = SshOptions::new().retries(60, Duration::from_secs(5));
+
+    assert_eq!(opts.retries, 60);
+    assert_eq!(opts.retry_interval, Duration::from_secs(5));
+}
+
+#[test]
+fn shell_quote_plain() {
+    assert_eq!(shell_quote("/opt/app/Caddyfile"), "'/opt/app/Caddyfile'");
+}
+
+#[test]
+fn shell_quote_spaces_and_quotes() {
+    assert_eq!(shell_quote("/opt/my app"), "'/opt/my app'");
+    assert_eq!(shell_quote("it's"), "'it'\\''s'");
+}
+
+#[test]
+fn bash_script_command_quotes_script_and_args() {
+    assert_eq!(
+        bash_script_command("echo \"$1\"", &["example.com"]),
+        "bash -c 'echo \"$1\"' _ 'example.com'"
+    );
+}
+
+#[test]
+fn bash_script_command_passes_hostile_args_verbatim() {
+    let args = [
+        "it's.example.com",
+        "$(touch /tmp/catapulta-pwned)",
+        "`id`; rm -rf /",
+        "/opt/my app",
+    ];
+    let command = bash_script_command("printf '%s\\n' \"$@\"", &args);
+
+    let output = cmd::run("sh", &["-c", &command]).unwrap();
+
+    assert_eq!(output.lines().collect::<Vec<_>>(), args);
+}
+
+#[test]
+fn file_attrs_builder() {
+    let attrs = FileAttrs::new().mode(0o640).owner("app:app");
+
+    assert_eq!(attrs.mode, Some(0o640));
+    assert_eq!(attrs.owner.as_deref(), Some("app:app"));
+}
+
+#[test]
+fn file_attrs_default() {
+    let attrs = FileAttrs::default();
+
+    assert!(attrs.mode.is_none());
+    assert!(attrs.owner.is_none());
+}
+
+#[test]
+fn replace_renames_the_temporary_file() {
+    let attrs = FileAttrs::new().mode(0o600);
+
+    assert_eq!(
+        replace_command("/opt/app/.env.tmp", "/opt/app/.env", &attrs),
+        "chmod 600 '/opt/app/.env.tmp' && mv -f '/opt/app/.env.tmp' '/opt/app/.env'"
+    );
+}
+
+#[test]
+fn replace_in_place_copies_into_the_target() {
+    let attrs = FileAttrs::new().mode(0o644).in_place();
+
+    assert_eq!(
+        replace_command("/opt/app/Caddyfile.tmp", "/opt/app/Caddyfile", &attrs),
+        "cat '/opt/app/Caddyfile.tmp' > '/opt/app/Caddyfile' && rm -f '/opt/app/Caddyfile.tmp' && \
+         chmod 644 '/opt/app/Caddyfile'"
+    );
+}
+
+#[test]
+fn replace_in_place_keeps_the_inode() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = std::env::temp_dir().join("catapulta-replace-in-place");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let target = dir.join("Caddyfile");
+    let tmp = dir.join("Caddyfile.tmp");
+    std::fs::write(&target, "old\n").unwrap();
+    std::fs::write(&tmp, "new\n").unwrap();
+    let inode = std::fs::metadata(&target).unwrap().ino();
+
+    let command = replace_command(
+        tmp.to_str().unwrap(),
+        target.to_str().unwrap(),
+        &FileAttrs::new().in_place(),
+    );
+    cmd::run("sh", &["-c", &command]).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "new\n");
+    assert_eq!(std::fs::metadata(&target).unwrap().ino(), inode);
+    assert!(!tmp.exists());
+}
+
+#[test]
+fn ssh_config_includes_are_inlined() {
+    let dir = std::env::temp_dir().join("catapulta-ssh-include");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("config.d")).unwrap();
+    std::fs::write(dir.join("config.d/b"), "Host b\n    HostName 10.0.0.2\n").unwrap();
+    std::fs::write(
+        dir.join("config.d/a"),
+        "Host a\n    HostName 10.0.0.1\nInclude nested\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("nested"), "Host n\n    HostName 10.0.0.3\n").unwrap();
+
+    let config = "Include config.d/* missing\n\nHost *\n    User root\n";
+    let result = inline_ssh_includes(config, &dir);
+
+    assert_eq!(
+        result,
+        "Host a\n    HostName 10.0.0.1\nHost n\n    HostName 10.0.0.3\n\
+         Host b\n    HostName 10.0.0.2\n\nHost *\n    User root\n"
+    );
+}
+
+#[test]
+fn sessions_run_over_the_configured_transport() {
+    let log = Log::default();
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(log.clone()));
+
+    ssh.exec("uptime").unwrap();
+    ssh.write_remote_file("hello", "/etc/motd").unwrap();
+
+    let tmp = format!("/etc/motd.catapulta-tmp.{}", std::process::id());
+    let lines = log.0.lock().unwrap().clone();
+    assert_eq!(
+        lines,
+        [
+            "web1 exec uptime".to_string(),
+            format!("write {tmp} hello"),
+            format!(
+                "web1 exec {}",
+                replace_command(&tmp, "/etc/motd", &FileAttrs::new())
+            ),
+        ]
+    );
+}
@@ -0,0 +1,29 @@
+use catapulta::ssh::pool::SshPool;
+use catapulta::ssh::{SshOptions, SshSession};
+
+#[test]
+fn from_hosts_keeps_order() {
+    let pool = SshPool::from_hosts(&["web1", "web2", "db"], "deploy", &SshOptions::default());
+
+    assert_eq!(pool.hosts(), vec!["web1", "web2", "db"]);
+    assert!(pool.sessions().iter().all(|s| s.user() == "deploy"));
+}
+
+#[test]
+fn new_from_sessions() {
+    let pool = SshPool::new(vec![
+        SshSession::new("a.example.com", "root"),
+        SshSession::new("b.example.com", "admin"),
+    ]);
+
+    assert_eq!(pool.hosts(), vec!["a.example.com", "b.example.com"]);
+    assert_eq!(pool.sessions()[1].user(), "admin");
+}
+
+#[test]
+fn empty_pool_succeeds() {
+    let pool = SshPool::new(Vec::new());
+
+    assert!(pool.exec("true").is_empty());
+    assert!(pool.exec_prefixed("true").is_ok());
+}
@@ -0,0 +1,64 @@
+use catapulta::ssh::SshOptions;
+use catapulta::ssh::tunnel::PortForward;
+use catapulta::testing::{FakeSsh, MockDeployer, SshCall};
+use catapulta::{App, Caddy, Pipeline};
+
+#[test]
+fn parse_full_spec() {
+    let fwd = PortForward::parse("15432:db:5432").unwrap();
+    assert_eq!(fwd, PortForward::new(15432, "db", 5432));
+}
+
+#[test]
+fn parse_two_ports_defaults_to_localhost() {
+    let fwd = PortForward::parse("8081:8080").unwrap();
+    assert_eq!(fwd, PortForward::new(8081, "localhost", 8080));
+}
+
+#[test]
+fn parse_single_port() {
+    let fwd = PortForward::parse("5432").unwrap();
+    assert_eq!(fwd, PortForward::new(5432, "localhost", 5432));
+}
+
+#[test]
+fn parse_rejects_invalid() {
+    for spec in [
+        "",
+        "abc",
+        "0:localhost:5432",
+        "5432::5432",
+        "1:2:3:4",
+        "70000",
+    ] {
+        assert!(PortForward::parse(spec).is_err(), "{spec}");
+    }
+}
+
+#[test]
+fn to_spec_round_trips() {
+    let fwd = PortForward::parse("15432:db:5432").unwrap();
+    assert_eq!(fwd.to_spec(), "15432:db:5432");
+}
+
+#[test]
+fn tunnel_resolves_the_container_on_the_stack_network() {
+    let web = App::new("web").expose(3000);
+    let fake = FakeSsh::new().respond(
+        "inspect",
+        "catapulta-shared 172.19.0.2\napp_web-network 172.18.0.3\n",
+    );
+    let dir = std::env::temp_dir().join("catapulta-tunnel-network");
+
+    Pipeline::new(web, Caddy::new())
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "tunnel", "web1", "13000:web:3000"])
+        .unwrap();
+
+    assert!(fake.calls().contains(&SshCall::Forward {
+        host: "web1".into(),
+        spec: "13000:172.18.0.3:3000".into(),
+    }));
+}
@@ -0,0 +1,630 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use catapulta::scan::ScanReport;
+use catapulta::ssh::SshOptions;
+use catapulta::state::{
+    DeploymentRecord, DnsRecord, LocalState, MAX_DEPLOYMENTS, S3State, STATE_FILE, ServerRecord,
+    State, StateStore, TRAIL_FILE, TrailEntry, USER_ENV, format_utc, is_missing_object,
+};
+use catapulta::testing::{
+    FakeSsh, MemoryState, MockDeployer, MockDnsProvider, MockProvisioner, SshCall,
+};
+use catapulta::{App, Caddy, Pipeline};
+
+/// A fresh local directory, without state from earlier runs.
+fn local_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("catapulta-state-{name}"));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn pipeline(dir: &Path, provisioner: &MockProvisioner, deployer: &MockDeployer) -> Pipeline {
+    let app = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    Pipeline::new(app, caddy)
+        .local_dir(dir.to_str().unwrap())
+        .provision(provisioner.clone())
+        .dns(MockDnsProvider::new("example.com"))
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+}
+
+#[test]
+fn state_round_trip() {
+    let dir = local_dir("round-trip");
+    assert_eq!(State::load(&dir).unwrap(), State::default());
+
+    let mut state = State::default();
+    state.servers.insert(
+        "web".to_string(),
+        ServerRecord {
+            ip: "203.0.113.10".to_string(),
+            domain: Some("example.com".to_string()),
+        },
+    );
+    state.servers.insert(
+        "db".to_string(),
+        ServerRecord {
+            ip: "203.0.113.11".to_string(),
+            domain: None,
+        },
+    );
+    state.save(&dir).unwrap();
+
+    let loaded = State::load(&dir).unwrap();
+    assert_eq!(loaded, state);
+    assert_eq!(loaded.host("web"), Some("example.com"));
+    assert_eq!(loaded.host("db"), Some("203.0.113.11"));
+    assert_eq!(loaded.host("cache"), None);
+    let content = std::fs::read_to_string(dir.join(STATE_FILE)).unwrap();
+    assert!(!content.contains("\"domain\": null"));
+}
+
+#[test]
+fn deploy_by_provisioned_name() {
+    let dir = local_dir("deploy");
+    let provisioner = MockProvisioner::new();
+    let deployer = MockDeployer::new();
+    let pipeline = pipeline(&dir, &provisioner, &deployer);
+
+    pipeline
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "web", "--skip-build"])
+        .unwrap();
+
+    assert_eq!(
+        deployer.calls(),
+        [
+            "transfer_image web example.com",
+            "deploy example.com /opt/app"
+        ]
+    );
+    // Resolved from the state file, not the provider
+    assert_eq!(
+        provisioner
+            .calls()
+            .iter()
+            .filter(|c| *c == "get_server web")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn destroy_forgets_server() {
+    let dir = local_dir("destroy");
+    let provisioner = MockProvisioner::new();
+    let pipeline = pipeline(&dir, &provisioner, &MockDeployer::new());
+
+    pipeline.run_from(["xtask", "provision", "web"]).unwrap();
+    assert_eq!(State::load(&dir).unwrap().host("web"), Some("203.0.113.10"));
+
+    pipeline
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap();
+    assert_eq!(State::load(&dir).unwrap(), State::default());
+}
+
+/// The hosts whose known host keys were cleared through `fake`.
+fn cleared_hosts(fake: &FakeSsh) -> Vec<String> {
+    fake.calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            SshCall::ClearKnownHost { host } => Some(host),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn destroy_clears_known_hosts() {
+    let dir = local_dir("destroy-known-hosts");
+    let fake = FakeSsh::new();
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()));
+
+    pipeline
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+    assert!(cleared_hosts(&fake).is_empty());
+
+    pipeline
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap();
+    assert_eq!(cleared_hosts(&fake), ["web", "203.0.113.10", "example.com"]);
+}
+
+#[test]
+fn new_ip_clears_known_hosts() {
+    let dir = local_dir("new-ip");
+    let fake = FakeSsh::new();
+    let provision = |provisioner: &MockProvisioner| {
+        pipeline(&dir, provisioner, &MockDeployer::new())
+            .ssh_options(SshOptions::new().transport(fake.clone()))
+            .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+            .unwrap();
+    };
+
+    provision(&MockProvisioner::new());
+    provision(&MockProvisioner::new().existing("web", "203.0.113.10"));
+    assert!(cleared_hosts(&fake).is_empty());
+
+    provision(&MockProvisioner::new().existing("web", "198.51.100.20"));
+    assert_eq!(
+        cleared_hosts(&fake),
+        ["203.0.113.10", "198.51.100.20", "example.com"]
+    );
+    assert_eq!(
+        State::load(&dir).unwrap().servers["web"].ip,
+        "198.51.100.20"
+    );
+}
+
+#[test]
+fn unknown_names_are_looked_up_with_the_provisioner() {
+    let dir = local_dir("lookup");
+    let provisioner = MockProvisioner::new().existing("db", "198.51.100.9");
+    let deployer = MockDeployer::new();
+    let pipeline = pipeline(&dir, &provisioner, &deployer);
+
+    pipeline
+        .run_from(["xtask", "deploy", "db", "--skip-build"])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "web1.example.com", "--skip-build"])
+        .unwrap();
+
+    assert_eq!(provisioner.calls(), ["get_server db"]);
+    assert_eq!(deployer.calls()[1], "deploy 198.51.100.9 /opt/app");
+    assert_eq!(deployer.calls()[3], "deploy web1.example.com /opt/app");
+}
+
+#[test]
+fn state_store_replaces_the_state_file() {
+    let dir = local_dir("store");
+    let store = MemoryState::new();
+    let pipeline =
+        pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new()).state_store(store.clone());
+
+    pipeline
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "web", "--skip-build"])
+        .unwrap();
+
+    let state = store.state();
+    assert_eq!(state.host("web"), Some("example.com"));
+    assert_eq!(
+        state.dns_records["example.com"],
+        DnsRecord {
+            server: "web".to_string(),
+            ip: "203.0.113.10".to_string(),
+            ipv6: None,
+        }
+    );
+    let deployment = &state.deployments[0];
+    assert_eq!(deployment.host, "example.com");
+    assert!(deployment.success);
+    assert_eq!(deployment.images["web"], "web:latest");
+    assert!(!dir.join(STATE_FILE).exists());
+
+    pipeline
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap();
+    let state = store.state();
+    assert!(state.servers.is_empty());
+    assert!(state.dns_records.is_empty());
+    assert_eq!(state.deployments.len(), 1);
+}
+
+#[test]
+fn failed_deploys_are_recorded() {
+    let dir = local_dir("failed-deploy");
+    let store = MemoryState::new();
+    let deployer = MockDeployer::new().fail("deploy");
+
+    let result = pipeline(&dir, &MockProvisioner::new(), &deployer)
+        .state_store(store.clone())
+        .run_from(["xtask", "deploy", "198.51.100.9", "--skip-build"]);
+
+    assert!(result.is_err());
+    let state = store.state();
+    assert_eq!(state.deployments.len(), 1);
+    assert!(!state.deployments[0].success);
+    assert!(state.image_history("web").is_empty());
+}
+
+#[test]
+fn unchanged_state_is_not_saved() {
+    let dir = local_dir("unchanged");
+    let store = MemoryState::new();
+    let pipeline =
+        pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new()).state_store(store.clone());
+
+    pipeline.run_from(["xtask", "provision", "web"]).unwrap();
+    assert_eq!(store.saves(), 1);
+
+    pipeline
+        .run_from(["xtask", "destroy", "db", "--force"])
+        .unwrap();
+    assert_eq!(store.saves(), 1);
+}
+
+fn deployment(image: &str, success: bool) -> DeploymentRecord {
+    DeploymentRecord {
+        host: "web1".to_string(),
+        at: 0,
+        success,
+        images: BTreeMap::from([("api".to_string(), image.to_string())]),
+        revision: None,
+        user: None,
+        scans: BTreeMap::new(),
+    }
+}
+
+#[test]
+fn image_history_newest_first() {
+    let mut state = State::default();
+    state.record_deployment(deployment("ghcr.io/org/api:1", true));
+    state.record_deployment(deployment("ghcr.io/org/api:2", true));
+    state.record_deployment(deployment("ghcr.io/org/api:3", false));
+    state.record_deployment(deployment("ghcr.io/org/api:1", true));
+
+    assert_eq!(
+        state.image_history("api"),
+        ["ghcr.io/org/api:1", "ghcr.io/org/api:2"]
+    );
+    assert!(state.image_history("web").is_empty());
+}
+
+#[test]
+fn deployments_are_capped() {
+    let mut state = State::default();
+    for i in 0..=MAX_DEPLOYMENTS {
+        state.record_deployment(deployment(&format!("api:{i}"), true));
+    }
+
+    assert_eq!(state.deployments.len(), MAX_DEPLOYMENTS);
+    assert_eq!(state.deployments[0].images["api"], "api:1");
+}
+
+#[test]
+fn s3_copy_args() {
+    let s3 = S3State::new("acme-ops", "/catapulta/servers.json");
+    assert_eq!(s3.url(), "s3://acme-ops/catapulta/servers.json");
+    assert_eq!(
+        s3.copy_args(&s3.url(), "-"),
+        [
+            "s3",
+            "cp",
+            "--only-show-errors",
+            "s3://acme-ops/catapulta/servers.json",
+            "-"
+        ]
+    );
+
+    let scaleway = s3.endpoint("https://s3.fr-par.scw.cloud");
+    assert_eq!(
+        &scaleway.copy_args("-", "s3://acme-ops/x")[5..],
+        ["--endpoint-url", "https://s3.fr-par.scw.cloud"]
+    );
+}
+
+#[test]
+fn missing_s3_object() {
+    assert!(is_missing_object(
+        "fatal error: An error occurred (404) when calling the HeadObject operation: Key \"servers.json\" does not exist"
+    ));
+    assert!(!is_missing_object(
+        "fatal error: An error occurred (403) when calling the HeadObject operation: Forbidden"
+    ));
+}
+
+#[test]
+fn import_records_server_and_dns() {
+    let dir = local_dir("import");
+    let store = MemoryState::new();
+    let fake = FakeSsh::new();
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new())
+        .state_store(store.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()));
+
+    pipeline
+        .run_from([
+            "xtask",
+            "import",
+            "legacy",
+            "--ip",
+            "203.0.113.10",
+            "--domain",
+            "legacy.example.com",
+        ])
+        .unwrap();
+
+    let state = store.state();
+    assert_eq!(
+        state.servers["legacy"],
+        ServerRecord {
+            ip: "203.0.113.10".to_string(),
+            domain: Some("legacy.example.com".to_string()),
+        }
+    );
+    assert_eq!(
+        state.dns_records["legacy.example.com"],
+        DnsRecord {
+            server: "legacy".to_string(),
+            ip: "203.0.113.10".to_string(),
+            ipv6: None,
+        }
+    );
+    assert!(fake.commands().iter().any(|c| c.contains("docker info")));
+}
+
+#[test]
+fn provisioned_server_is_deployed_by_domain() {
+    let dir = local_dir("pin-provision");
+    let fake = FakeSsh::new().pin_host_keys();
+    let deployer = MockDeployer::new();
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &deployer)
+        .ssh_options(SshOptions::new().transport(fake.clone()));
+
+    pipeline
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+    assert!(fake.is_pinned("203.0.113.10"));
+    assert!(fake.is_pinned("example.com"));
+
+    pipeline
+        .run_from(["xtask", "deploy", "web", "--skip-build"])
+        .unwrap();
+    assert_eq!(
+        deployer.calls(),
+        [
+            "transfer_image web example.com",
+            "deploy example.com /opt/app"
+        ]
+    );
+}
+
+#[test]
+fn reprovisioned_server_keeps_its_new_host_key() {
+    let dir = local_dir("pin-reprovision");
+    let fake = FakeSsh::new().pin_host_keys();
+    let deployer = MockDeployer::new();
+    let provision = |provisioner: &MockProvisioner| {
+        pipeline(&dir, provisioner, &deployer)
+            .ssh_options(SshOptions::new().transport(fake.clone()))
+            .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+            .unwrap();
+    };
+
+    provision(&MockProvisioner::new());
+    provision(&MockProvisioner::new().ip("198.51.100.20"));
+
+    // The keys of the replaced server go before the new one is
+    // pinned
+    let calls = fake.calls();
+    let cleared = calls
+        .iter()
+        .rposition(|c| matches!(c, SshCall::ClearKnownHost { .. }))
+        .unwrap();
+    let pinned = calls
+        .iter()
+        .position(|c| matches!(c, SshCall::Exec { host, .. } if host == "198.51.100.20"))
+        .unwrap();
+    assert!(cleared < pinned);
+    assert!(!fake.is_pinned("203.0.113.10"));
+    assert!(fake.is_pinned("198.51.100.20"));
+
+    pipeline(&dir, &MockProvisioner::new(), &deployer)
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .run_from(["xtask", "deploy", "web", "--skip-build"])
+        .unwrap();
+}
+
+#[test]
+fn imported_server_is_deployed_by_domain() {
+    let dir = local_dir("pin-import");
+    let fake = FakeSsh::new().pin_host_keys();
+    let deployer = MockDeployer::new();
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &deployer)
+        .ssh_options(SshOptions::new().transport(fake.clone()));
+
+    pipeline
+        .run_from([
+            "xtask",
+            "import",
+            "legacy",
+            "--ip",
+            "203.0.113.10",
+            "--domain",
+            "legacy.example.com",
+        ])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "legacy", "--skip-build"])
+        .unwrap();
+
+    assert!(fake.is_pinned("legacy.example.com"));
+    assert_eq!(deployer.calls().len(), 2);
+}
+
+#[test]
+fn unpinned_host_is_rejected_unless_trusted() {
+    let dir = local_dir("pin-unknown");
+    let fake = FakeSsh::new().pin_host_keys();
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()));
+
+    let err = pipeline
+        .run_from(["xtask", "deploy", "203.0.113.99", "--skip-build"])
+        .unwrap_err();
+    assert_eq!(err.code(), "E203", "{err}");
+
+    pipeline
+        .run_from([
+            "xtask",
+            "deploy",
+            "203.0.113.99",
+            "--skip-build",
+            "--trust-new-hostkey",
+        ])
+        .unwrap();
+    assert!(fake.is_pinned("203.0.113.99"));
+}
+
+#[test]
+fn import_without_docker_fails() {
+    let dir = local_dir("import-no-docker");
+    let store = MemoryState::new();
+    let fake = FakeSsh::new().respond("command -v docker", "docker=no\n");
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new())
+        .state_store(store.clone())
+        .ssh_options(SshOptions::new().transport(fake));
+
+    let err = pipeline
+        .run_from(["xtask", "import", "legacy", "--ip", "203.0.113.10"])
+        .unwrap_err();
+
+    assert!(err.to_string().contains("Docker is not installed"), "{err}");
+    assert!(store.state().servers.is_empty());
+}
+
+#[test]
+fn import_rejects_invalid_ip() {
+    let dir = local_dir("import-invalid-ip");
+    let pipeline = pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new());
+
+    assert!(
+        pipeline
+            .run_from(["xtask", "import", "legacy", "--ip", "legacy.example.com"])
+            .is_err()
+    );
+    assert!(State::load(&dir).unwrap().servers.is_empty());
+}
+
+#[test]
+fn audit_trail_records_changes() {
+    let dir = local_dir("trail");
+    let store = MemoryState::new();
+    let pipeline =
+        pipeline(&dir, &MockProvisioner::new(), &MockDeployer::new()).state_store(store.clone());
+    unsafe { std::env::set_var(USER_ENV, "alice@example.com") };
+
+    pipeline
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "web", "--dry-run"])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "web", "--skip-build"])
+        .unwrap();
+    pipeline.run_from(["xtask", "status", "web"]).unwrap();
+    pipeline
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap();
+
+    let trail = store.trail();
+    let actions: Vec<(&str, &str)> = trail
+        .iter()
+        .map(|e| (e.action.as_str(), e.target.as_str()))
+        .collect();
+    assert_eq!(
+        actions,
+        [("provision", "web"), ("deploy", "web"), ("destroy", "web")]
+    );
+    assert!(
+        trail
+            .iter()
+            .all(|e| e.success && e.user == "alice@example.com")
+    );
+    assert_eq!(trail[0].detail.as_deref(), Some("domain example.com"));
+}
+
+#[test]
+fn audit_trail_is_appended_to_a_local_file() {
+    let dir = local_dir("trail-local");
+    let deployer = MockDeployer::new().fail("deploy");
+    let app = App::new("api").env_file("deploy/.env.api");
+    let pipeline = Pipeline::new(app, Caddy::new())
+        .local_dir(dir.to_str().unwrap())
+        .deploy(deployer)
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()));
+
+    assert!(
+        pipeline
+            .run_from(["xtask", "deploy", "198.51.100.9", "--skip-build"])
+            .is_err()
+    );
+    let store = LocalState::new(&dir);
+    store
+        .append_trail(&TrailEntry::now("rollback", "198.51.100.9", true))
+        .unwrap();
+
+    let trail = store.load_trail().unwrap();
+    assert_eq!(trail.len(), 2);
+    assert_eq!(trail[0].action, "deploy");
+    assert!(!trail[0].success);
+    assert_eq!(
+        trail[0].detail.as_deref(),
+        Some("env files deploy/.env.api")
+    );
+    assert_eq!(trail[1].action, "rollback");
+    let content = std::fs::read_to_string(dir.join(TRAIL_FILE)).unwrap();
+    assert_eq!(content.lines().count(), 2);
+}
+
+#[test]
+fn trail_entries_render_in_utc() {
+    assert_eq!(format_utc(0), "1970-01-01 00:00:00 UTC");
+    assert_eq!(format_utc(951_868_799), "2000-02-29 23:59:59 UTC");
+    assert_eq!(format_utc(4_107_542_400), "2100-03-01 00:00:00 UTC");
+
+    let entry = TrailEntry {
+        at: 1_792_071_930,
+        user: "alice@example.com".to_string(),
+        action: "destroy".to_string(),
+        target: "web".to_string(),
+        success: false,
+        detail: None,
+    };
+    assert_eq!(
+        entry.to_string(),
+        "2026-10-15 13:45:30 UTC  alice@example.com  destroy web  FAILED"
+    );
+}
+
+#[test]
+fn s3_trail_next_to_the_state() {
+    assert_eq!(
+        S3State::new("acme-ops", "catapulta/servers.json").trail_url(),
+        "s3://acme-ops/catapulta/audit-trail.jsonl"
+    );
+    assert_eq!(
+        S3State::new("acme-ops", "servers.json").trail_url(),
+        "s3://acme-ops/audit-trail.jsonl"
+    );
+}
+
+#[test]
+fn deployment_records_keep_the_scans() {
+    let mut record = deployment("api:latest", false);
+    assert!(!serde_json::to_string(&record).unwrap().contains("scans"));
+
+    record.scans.insert(
+        "api:latest".to_string(),
+        ScanReport {
+            scanner: "trivy".to_string(),
+            critical: 2,
+            ..ScanReport::default()
+        },
+    );
+    let json = serde_json::to_string(&record).unwrap();
+    let read: DeploymentRecord = serde_json::from_str(&json).unwrap();
+    assert_eq!(read.scans["api:latest"].critical, 2);
+}
@@ -0,0 +1,68 @@
+use catapulta::deploy::Runtime;
+use catapulta::ssh::SshOptions;
+use catapulta::stats::{self, ContainerStats};
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+const OUTPUT: &str = "\
+app-web\t1.25%\t120MiB / 1.944GiB
+app-worker\t87.50%\t1.5GiB / 1.944GiB
+app-caddy\t--\t-- / --
+shared-caddy\t0.00%\t24.5MB / 2GB
+";
+
+#[test]
+fn parse_stats() {
+    let stats = stats::parse(OUTPUT);
+
+    assert_eq!(stats.len(), 3);
+    assert_eq!(
+        stats[0],
+        ContainerStats {
+            name: "app-worker".to_string(),
+            cpu_percent: 87.5,
+            memory: 1_610_612_736,
+            memory_limit: 2_087_354_106,
+        }
+    );
+    assert_eq!(stats[1].name, "app-web");
+    assert_eq!(stats[2].memory, 24_500_000);
+    assert_eq!(stats[2].memory_limit, 2_000_000_000);
+}
+
+#[test]
+fn parse_docker_sizes() {
+    assert_eq!(stats::parse_size("0B"), Some(0));
+    assert_eq!(stats::parse_size(" 1.5KiB "), Some(1536));
+    assert_eq!(stats::parse_size("3kB"), Some(3000));
+    assert_eq!(stats::parse_size("--"), None);
+    assert_eq!(stats::parse_size("12"), None);
+}
+
+#[test]
+fn render_a_table_with_the_total() {
+    let lines = stats::render(&stats::parse(OUTPUT));
+
+    assert_eq!(lines[0], "CONTAINER         CPU  MEMORY");
+    assert_eq!(lines[1], "app-worker      87.5%  1.5 GiB / 1.9 GiB");
+    assert_eq!(lines[2], "app-web          1.2%  120.0 MiB / 1.9 GiB");
+    assert_eq!(lines[3], "shared-caddy     0.0%  23.4 MiB / 1.9 GiB");
+    assert_eq!(lines[4], "total           88.8%  1.6 GiB");
+}
+
+#[test]
+fn top_runs_docker_stats_once() {
+    let fake = FakeSsh::new().respond("docker stats", OUTPUT);
+    let web = App::new("web").expose(80);
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join("catapulta-top");
+
+    Pipeline::new(web, caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "top", "web1"])
+        .unwrap();
+
+    assert_eq!(fake.commands(), [stats::command(Runtime::Docker)]);
+}
@@ -0,0 +1,94 @@
+use catapulta::ssh::SshOptions;
+use catapulta::status::{self, Memory};
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::{App, Caddy, Pipeline};
+
+const OUTPUT: &str = "\
+### df
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+/dev/vda1         25215872 22123456   1048576      96% /
+/dev/vda1         25215872 22123456   1048576      96% /
+### meminfo
+MemTotal:        4028440 kB
+MemFree:          210112 kB
+MemAvailable:    2014220 kB
+### loadavg
+0.52 0.40 0.31 1/123 4567
+### nproc
+2
+### docker
+Images\t4.1GB\t2.3GB (56%)
+Build Cache\t0B\t0B
+";
+
+#[test]
+fn parse_metrics() {
+    let metrics = status::parse(OUTPUT);
+
+    assert_eq!(metrics.load, Some([0.52, 0.40, 0.31]));
+    assert_eq!(metrics.cpus, Some(2));
+    assert_eq!(
+        metrics.memory,
+        Some(Memory {
+            total: 4_028_440 * 1024,
+            available: 2_014_220 * 1024,
+        })
+    );
+    // The Docker data root on the root filesystem is listed once
+    assert_eq!(metrics.disks.len(), 1);
+    assert_eq!(metrics.disks[0].used_percent(), 96);
+    assert_eq!(metrics.docker[0].kind, "Images");
+    assert_eq!(metrics.docker[0].reclaimable, "2.3GB (56%)");
+    assert_eq!(metrics.docker.len(), 2);
+
+    let lines = metrics.render();
+    assert_eq!(lines[0], "load     0.52 0.40 0.31 (2 CPUs)");
+    assert_eq!(lines[1], "memory   1.9 GiB / 3.8 GiB used (50%)");
+    assert_eq!(lines[2], "disk     /: 23.0 GiB / 24.0 GiB used (96%)");
+    assert_eq!(lines[3], "docker   images: 4.1GB, 2.3GB (56%) reclaimable");
+
+    assert_eq!(metrics.warnings().len(), 1);
+    assert!(metrics.warnings()[0].starts_with("/ is 96% full"));
+}
+
+#[test]
+fn missing_sections_are_left_out() {
+    let metrics = status::parse("### df\n### meminfo\n### loadavg\n### nproc\n### docker\n");
+
+    assert_eq!(metrics, status::ServerMetrics::default());
+    assert!(metrics.render().is_empty());
+    assert!(metrics.warnings().is_empty());
+}
+
+#[test]
+fn status_reports_server_metrics() {
+    let fake = FakeSsh::new().respond("### df", OUTPUT);
+    let web = App::new("web").expose(80);
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join("catapulta-status");
+
+    Pipeline::new(web, caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "status", "web1"])
+        .unwrap();
+
+    let commands = fake.commands();
+    assert_eq!(commands[0], "cd /opt/app && docker compose ps");
+    assert!(commands[1].contains("cat /proc/meminfo"));
+}
+
+#[test]
+fn status_survives_unreadable_metrics() {
+    let fake = FakeSsh::new().fail("### df", 255);
+    let web = App::new("web").expose(80);
+    let dir = std::env::temp_dir().join("catapulta-status-fail");
+
+    Pipeline::new(web, Caddy::new())
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(fake))
+        .local_dir(dir.to_str().unwrap())
+        .run_from(["xtask", "status", "web1"])
+        .unwrap();
+}
@@ -0,0 +1,651 @@
+use catapulta::dns::{DnsProvider, DnsPurpose};
+use catapulta::error::{DeployError, DeployResult};
+use catapulta::provision::{ProvisionOverrides, Provisioner, ServerInfo};
+use catapulta::ssh::{SshOptions, SshSession};
+use catapulta::testing::{FakeSsh, MockDeployer, MockDnsProvider, MockProvisioner, SshCall};
+use catapulta::{App, Caddy, Cloudflare, DuckDns, Pipeline};
+
+fn pipeline(name: &str) -> Pipeline {
+    let app = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    let dir = std::env::temp_dir().join(format!("catapulta-testing-{name}"));
+    Pipeline::new(app, caddy)
+        .local_dir(dir.to_str().unwrap())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+}
+
+#[test]
+fn provisioner_keeps_servers() {
+    let provisioner = MockProvisioner::new().ip("198.51.100.7");
+
+    let server = provisioner.create_server("web", "ams3", &[]).unwrap();
+    assert_eq!(server.ip, "198.51.100.7");
+    assert_eq!(
+        provisioner.get_server("web").unwrap().unwrap().region,
+        "ams3"
+    );
+
+    provisioner.destroy_server("web").unwrap();
+    assert!(provisioner.server("web").is_none());
+    assert_eq!(
+        provisioner.calls(),
+        [
+            "create_server web ams3",
+            "get_server web",
+            "destroy_server web"
+        ]
+    );
+}
+
+#[test]
+fn mock_fails_on_request() {
+    let deployer = MockDeployer::new().fail("build_image");
+
+    let err = catapulta::deploy::Deployer::build_image(&deployer, &App::new("web")).unwrap_err();
+
+    assert_eq!(err.to_string(), "mock build_image failed");
+    assert_eq!(deployer.calls(), ["build_image web"]);
+}
+
+#[test]
+fn dns_for_domain_shares_records() {
+    let dns = MockDnsProvider::new("example.com");
+    let api = dns.for_domain("api.example.com").unwrap();
+
+    api.upsert_a_record("203.0.113.10").unwrap();
+    dns.delete_a_record().unwrap();
+
+    assert_eq!(api.domain(), "api.example.com");
+    assert_eq!(
+        dns.record("api.example.com").as_deref(),
+        Some("203.0.113.10")
+    );
+    assert_eq!(
+        dns.calls(),
+        [
+            "upsert_a_record api.example.com 203.0.113.10",
+            "delete_a_record example.com"
+        ]
+    );
+}
+
+#[test]
+fn fake_ssh_replies() {
+    let fake = FakeSsh::new()
+        .respond("uname", "x86_64")
+        .fail("docker", 125);
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+
+    assert_eq!(ssh.exec("uname -m").unwrap(), "x86_64");
+    assert_eq!(ssh.exec("true").unwrap(), "");
+    assert_eq!(ssh.exec("docker ps").unwrap_err().code(), "E104");
+    assert_eq!(fake.commands(), ["uname -m", "true", "docker ps"]);
+}
+
+#[test]
+fn fake_ssh_records_files() {
+    let fake = FakeSsh::new();
+    let ssh =
+        SshSession::new("web1", "root").with_options(&SshOptions::new().transport(fake.clone()));
+
+    ssh.write_remote_file("a", "/opt/app/.env").unwrap();
+    ssh.write_remote_file("b", "/opt/app/.env").unwrap();
+    ssh.scp_to("dump.sql", "/tmp/dump.sql").unwrap();
+
+    assert_eq!(fake.file("/opt/app/.env").as_deref(), Some("b"));
+    assert_eq!(
+        fake.calls().last(),
+        Some(&SshCall::Upload {
+            host: "web1".to_string(),
+            local: "dump.sql".to_string(),
+            remote: "/tmp/dump.sql".to_string(),
+        })
+    );
+}
+
+#[test]
+fn pipeline_provision_sets_dns() {
+    let provisioner = MockProvisioner::new();
+    let dns = MockDnsProvider::new("example.com");
+
+    pipeline("provision")
+        .provision(provisioner.clone())
+        .dns(dns.clone())
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+
+    assert_eq!(dns.record("example.com").as_deref(), Some("203.0.113.10"));
+    assert_eq!(
+        provisioner.calls(),
+        [
+            "check_prerequisites",
+            "get_server web",
+            "detect_ssh_keys",
+            "create_server web fra1",
+            "setup_server web 203.0.113.10 example.com"
+        ]
+    );
+}
+
+#[test]
+fn pipeline_dns_per_purpose() {
+    let provisioner = MockProvisioner::new();
+    let registrar = MockDnsProvider::new("example.com");
+    let caa = MockDnsProvider::new("example.com");
+    let pipeline = pipeline("dns-purpose")
+        .provision(provisioner)
+        .dns(registrar.clone())
+        .dns_for(DnsPurpose::Caa, caa.clone());
+
+    pipeline
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap();
+
+    assert_eq!(
+        registrar.calls(),
+        [
+            "check_access example.com",
+            "upsert_a_record example.com 203.0.113.10",
+            "delete_a_record example.com",
+            "delete_aaaa_record example.com",
+        ]
+    );
+    assert_eq!(
+        caa.calls(),
+        [
+            "check_access example.com",
+            "upsert_caa_records example.com letsencrypt.org sectigo.com",
+            "delete_caa_records example.com"
+        ]
+    );
+}
+
+#[test]
+fn caddy_modules_of_dns_providers() {
+    assert_eq!(
+        Cloudflare::new("example.com").caddy_module(),
+        Some(("cloudflare", "CF_API_TOKEN"))
+    );
+    assert_eq!(
+        DuckDns::new("home.duckdns.org").caddy_module(),
+        Some(("duckdns", "DUCKDNS_TOKEN"))
+    );
+    assert_eq!(MockDnsProvider::new("example.com").caddy_module(), None);
+}
+
+#[test]
+fn pipeline_provision_publishes_the_ipv6_address() {
+    let provisioner = MockProvisioner::new().ipv6("2001:db8::10");
+    let dns = MockDnsProvider::new("example.com");
+
+    pipeline("provision-ipv6")
+        .provision(provisioner)
+        .dns(dns.clone())
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+
+    assert_eq!(
+        dns.calls(),
+        [
+            "check_access example.com",
+            "upsert_a_record example.com 203.0.113.10",
+            "upsert_aaaa_record example.com 2001:db8::10"
+        ]
+    );
+    assert_eq!(
+        dns.aaaa_record("example.com").as_deref(),
+        Some("2001:db8::10")
+    );
+}
+
+#[test]
+fn pipeline_provision_without_ipv6_sets_no_aaaa_record() {
+    let dns = MockDnsProvider::new("example.com");
+
+    pipeline("provision-ipv4")
+        .provision(MockProvisioner::new())
+        .dns(dns.clone())
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap();
+
+    assert!(!dns.calls().iter().any(|c| c.contains("aaaa")));
+}
+
+#[test]
+fn pipeline_provision_checks_dns_access_first() {
+    let provisioner = MockProvisioner::new();
+    let dns = MockDnsProvider::new("example.com").fail("check_access");
+
+    let err = pipeline("provision-dns-access")
+        .provision(provisioner.clone())
+        .dns(dns.clone())
+        .run_from(["xtask", "provision", "web", "--domain", "example.com"])
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "check prerequisites on example.com: mock check_access failed"
+    );
+    assert_eq!(provisioner.calls(), ["check_prerequisites"]);
+    assert_eq!(dns.calls(), ["check_access example.com"]);
+}
+
+#[test]
+fn pipeline_provision_overrides() {
+    let provisioner = MockProvisioner::new();
+
+    pipeline("provision-overrides")
+        .provision(provisioner.clone())
+        .run_from([
+            "xtask",
+            "provision",
+            "web",
+            "--size",
+            "s-2vcpu-4gb",
+            "--image",
+            "ubuntu-22-04-x64",
+            "--ssh-key",
+            "123",
+            "--ssh-key",
+            "456",
+        ])
+        .unwrap();
+
+    assert!(provisioner.calls().contains(
+        &"create_server web fra1 size=s-2vcpu-4gb image=ubuntu-22-04-x64 ssh_key=123 ssh_key=456"
+            .to_string()
+    ));
+}
+
+/// Provisioner relying on the default `create_server_with`.
+struct Fixed;
+
+impl Provisioner for Fixed {
+    fn check_prerequisites(&self) -> DeployResult<()> {
+        Ok(())
+    }
+
+    fn create_server(
+        &self,
+        name: &str,
+        region: &str,
+        _ssh_key_ids: &[String],
+    ) -> DeployResult<ServerInfo> {
+        Ok(ServerInfo {
+            name: name.to_string(),
+            ip: "192.0.2.1".to_string(),
+            ipv6: None,
+            region: region.to_string(),
+            ssh_key_ids: Vec::new(),
+            ssh_key_files: Vec::new(),
+        })
+    }
+
+    fn setup_server(
+        &self,
+        _server: &ServerInfo,
+        _domain: Option<&str>,
+        _ssh: &SshOptions,
+    ) -> DeployResult<()> {
+        Ok(())
+    }
+
+    fn get_server(&self, _name: &str) -> DeployResult<Option<ServerInfo>> {
+        Ok(None)
+    }
+
+    fn destroy_server(&self, _name: &str) -> DeployResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn overrides_unsupported_by_default() {
+    let none = ProvisionOverrides::default();
+    assert!(Fixed.create_server_with("web", "fra1", &[], &none).is_ok());
+
+    let size = ProvisionOverrides {
+        size: Some("large".to_string()),
+        ..ProvisionOverrides::default()
+    };
+    let err = Fixed
+        .create_server_with("web", "fra1", &[], &size)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--size is not supported by this provisioner"
+    );
+}
+
+#[test]
+fn pipeline_maintenance_on_off() {
+    let fake = FakeSsh::new();
+    let pipeline = pipeline("maintenance").ssh_options(SshOptions::new().transport(fake.clone()));
+
+    pipeline
+        .run_from([
+            "xtask",
+            "maintenance",
+            "example.com",
+            "--on",
+            "--retry-after",
+            "60",
+        ])
+        .unwrap();
+    pipeline
+        .run_from(["xtask", "maintenance", "example.com", "--off"])
+        .unwrap();
+
+    let page = fake.file("/opt/app/Caddyfile.maintenance").unwrap();
+    assert!(page.starts_with("example.com {"));
+    assert!(page.contains("header Retry-After 60"));
+    let commands = fake.commands();
+    assert!(
+        commands[0]
+            .contains("caddy reload --config /etc/caddy/Caddyfile.maintenance --adapter caddyfile")
+    );
+    assert!(commands[1].contains("caddy reload --config /etc/caddy/Caddyfile --adapter caddyfile"));
+}
+
+#[test]
+fn pipeline_maintenance_needs_a_mode() {
+    let err = pipeline("maintenance-mode")
+        .run_from(["xtask", "maintenance", "example.com"])
+        .unwrap_err();
+    assert!(err.to_string().contains("--on"));
+
+    assert!(
+        pipeline("maintenance-mode")
+            .run_from(["xtask", "maintenance", "example.com", "--on", "--off"])
+            .is_err()
+    );
+}
+
+#[test]
+fn pipeline_deploy_only() {
+    let deployer = MockDeployer::new();
+    let fake = FakeSsh::new();
+
+    pipeline("deploy")
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .after_deploy("docker compose ps")
+        .run_from(["xtask", "deploy", "web1", "--only", "web"])
+        .unwrap();
+
+    assert_eq!(
+        deployer.calls(),
+        [
+            "build_image web",
+            "transfer_image web web1",
+            "deploy web1 /opt/app web"
+        ]
+    );
+    let commands = fake.commands();
+    assert!(commands[0].contains("docker compose version"));
+    assert!(commands[1].starts_with("docker ps -a"));
+    assert!(commands[2].contains("config -q"));
+    assert!(!commands.iter().any(|c| c.contains("docker compose rm")));
+    assert_eq!(commands.last().unwrap(), "docker compose ps");
+}
+
+#[test]
+fn pipeline_deploy_skips_unchanged_images() {
+    let deployer = MockDeployer::new().unchanged("web");
+    let fake = FakeSsh::new();
+
+    pipeline("deploy-unchanged")
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap();
+
+    assert_eq!(deployer.calls(), ["deploy web1 /opt/app"]);
+    // The rollback history keeps the image it had
+    assert!(!fake.commands().iter().any(|c| c.contains(":previous")));
+}
+
+#[test]
+fn pipeline_transfers_the_changed_images_together() {
+    let web = App::new("web").expose(3000);
+    let api = App::new("api").expose(8000);
+    let worker = App::new("worker");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join("catapulta-testing-transfer-together");
+    let deployer = MockDeployer::new().unchanged("api");
+    let fake = FakeSsh::new();
+
+    Pipeline::multi(vec![web, api, worker], caddy)
+        .local_dir(dir.to_str().unwrap())
+        .deploy(deployer.clone())
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap();
+
+    // The unchanged image stays; the mock sends the others one
+    // after the other through the default `transfer_images`
+    assert_eq!(
+        deployer.calls(),
+        [
+            "transfer_image web web1",
+            "transfer_image worker web1",
+            "deploy web1 /opt/app"
+        ]
+    );
+    let commands = fake.commands();
+    let kept: Vec<&String> = commands
+        .iter()
+        .filter(|c| c.contains(":previous"))
+        .collect();
+    assert_eq!(kept.len(), 2);
+    assert!(kept[0].contains("web:latest") && kept[1].contains("worker:latest"));
+}
+
+#[test]
+fn pipeline_purges_the_cache_after_deploy() {
+    let dns = MockDnsProvider::new("example.com");
+
+    pipeline("purge-cache")
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .dns(dns.clone())
+        .purge_cache(&["/index.html", "/assets/app.js"])
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap();
+
+    assert_eq!(
+        dns.calls(),
+        ["purge_cache example.com /index.html /assets/app.js"]
+    );
+}
+
+#[test]
+fn pipeline_purge_fails_the_deploy() {
+    let dns = MockDnsProvider::new("example.com").fail("purge_cache");
+
+    let err = pipeline("purge-cache-failed")
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .dns(dns)
+        .purge_cache(&[])
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "post-deploy hooks on web1: mock purge_cache failed"
+    );
+}
+
+#[test]
+fn pipeline_reports_failed_phase() {
+    let deployer = MockDeployer::new().fail("deploy");
+
+    let err = pipeline("failed")
+        .deploy(deployer)
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        DeployError::Context { ref phase, ref host, .. }
+            if phase == "deploy" && host.as_deref() == Some("web1")
+    ));
+}
+
+#[test]
+fn pipeline_rejects_bad_arguments() {
+    let err = pipeline("args").run_from(["xtask", "deploy"]).unwrap_err();
+
+    assert!(err.to_string().contains("<HOST>"));
+}
+
+#[test]
+fn pipeline_destroy_dry_run_changes_nothing() {
+    let provisioner = MockProvisioner::new().existing("web", "203.0.113.10");
+    let dns = MockDnsProvider::new("example.com");
+
+    pipeline("destroy-dry-run")
+        .provision(provisioner.clone())
+        .dns(dns.clone())
+        .run_from(["xtask", "destroy", "web", "--dry-run"])
+        .unwrap();
+
+    assert_eq!(provisioner.calls(), ["destroy_plan web"]);
+    assert!(provisioner.server("web").is_some());
+    assert!(dns.calls().is_empty());
+}
+
+#[test]
+fn pipeline_destroy_removes_the_aaaa_record() {
+    let dns = MockDnsProvider::new("example.com");
+
+    pipeline("destroy-ipv6")
+        .provision(MockProvisioner::new().existing("web", "203.0.113.10"))
+        .dns(dns.clone())
+        .run_from(["xtask", "destroy", "web", "--force"])
+        .unwrap();
+
+    assert_eq!(
+        dns.calls(),
+        [
+            "delete_a_record example.com",
+            "delete_aaaa_record example.com"
+        ]
+    );
+}
+
+#[test]
+fn pipeline_destroy_dry_run_unknown_server() {
+    let err = pipeline("destroy-unknown")
+        .provision(MockProvisioner::new())
+        .run_from(["xtask", "destroy", "web", "--dry-run"])
+        .unwrap_err();
+
+    assert_eq!(err.code(), "E302");
+}
+
+#[test]
+fn pipeline_deploy_profile() {
+    let web = App::new("web").expose(3000);
+    let admin = App::new("admin").expose(8080).profile("debug");
+    let caddy = Caddy::new().reverse_proxy(web.upstream());
+    let dir = std::env::temp_dir().join("catapulta-testing-profile");
+    let deploy = |deployer: &MockDeployer, args: &[&str]| {
+        Pipeline::multi(vec![web.clone(), admin.clone()], caddy.clone())
+            .local_dir(dir.to_str().unwrap())
+            .deploy(deployer.clone())
+            .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+            .run_from(["xtask", "deploy", "web1"].iter().chain(args))
+    };
+
+    let deployer = MockDeployer::new();
+    deploy(&deployer, &[]).unwrap();
+    assert!(!deployer.calls().contains(&"build_image admin".to_string()));
+
+    let deployer = MockDeployer::new();
+    deploy(&deployer, &["--profile", "debug"]).unwrap();
+    assert!(deployer.calls().contains(&"build_image admin".to_string()));
+
+    let err = deploy(&MockDeployer::new(), &["--profile", "tools"]).unwrap_err();
+    assert!(err.to_string().contains("Known profiles: debug"));
+}
+
+#[test]
+fn pipeline_compose_passes_the_arguments_through() {
+    let fake = FakeSsh::new();
+
+    pipeline("compose")
+        .ssh_options(SshOptions::new().transport(fake.clone()))
+        .run_from([
+            "xtask", "compose", "web1", "--", "exec", "web", "echo", "it's",
+        ])
+        .unwrap();
+
+    assert_eq!(
+        fake.commands(),
+        ["cd /opt/app && docker compose 'exec' 'web' 'echo' 'it'\\''s'"]
+    );
+}
+
+#[test]
+fn pipeline_compose_needs_arguments() {
+    let fake = FakeSsh::new();
+
+    assert!(
+        pipeline("compose-empty")
+            .ssh_options(SshOptions::new().transport(fake.clone()))
+            .run_from(["xtask", "compose", "web1"])
+            .is_err()
+    );
+    assert!(fake.commands().is_empty());
+}
+
+#[test]
+fn pipeline_console_goes_to_the_provisioner() {
+    let provisioner = MockProvisioner::new().existing("web", "203.0.113.10");
+
+    pipeline("console")
+        .provision(provisioner.clone())
+        .run_from(["xtask", "console", "web"])
+        .unwrap();
+
+    assert_eq!(provisioner.calls(), ["console web"]);
+}
+
+#[test]
+fn pipeline_console_needs_a_provisioner() {
+    let err = pipeline("console-none")
+        .run_from(["xtask", "console", "web"])
+        .unwrap_err();
+
+    assert!(err.to_string().contains("no provisioner configured"));
+}
+
+#[test]
+fn terminal_runs_like_interactive_commands() {
+    let fake = FakeSsh::new();
+    let ssh = SshSession::new("hypervisor", "root")
+        .with_options(&SshOptions::new().transport(fake.clone()));
+
+    ssh.terminal("virsh console 'web'").unwrap();
+
+    assert_eq!(fake.commands(), ["virsh console 'web'"]);
+}
+
+#[test]
+fn pipeline_cache_clean_goes_to_the_provisioner() {
+    let provisioner = MockProvisioner::new();
+
+    pipeline("cache-clean")
+        .provision(provisioner.clone())
+        .run_from(["xtask", "cache", "clean"])
+        .unwrap();
+
+    assert_eq!(provisioner.calls(), ["clean_image_cache"]);
+}
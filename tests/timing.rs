@@ -0,0 +1,104 @@
+use std::thread;
+use std::time::Duration;
+
+use catapulta::ssh::SshOptions;
+use catapulta::testing::{FakeSsh, MockDeployer};
+use catapulta::timing::{self, Timings};
+use catapulta::{App, Caddy, Pipeline};
+
+#[test]
+fn nested_phases_are_excluded() {
+    let ((), timings) = timing::collect(|| {
+        timing::measure("transfer", || {
+            timing::measure("save", || thread::sleep(Duration::from_millis(30)));
+        });
+    });
+
+    let names: Vec<&str> = timings.phases.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, ["save", "transfer"]);
+    assert!(timings.get("save").unwrap() >= Duration::from_millis(30));
+    assert!(timings.get("transfer").unwrap() < Duration::from_millis(30));
+    assert!(timings.total >= Duration::from_millis(30));
+}
+
+#[test]
+fn repeated_phases_are_summed() {
+    let ((), timings) = timing::collect(|| {
+        for _ in 0..2 {
+            timing::measure("build", || thread::sleep(Duration::from_millis(10)));
+        }
+    });
+
+    assert_eq!(timings.phases.len(), 1);
+    assert!(timings.get("build").unwrap() >= Duration::from_millis(20));
+}
+
+#[test]
+fn measure_outside_collect_runs() {
+    assert_eq!(timing::measure("build", || 42), 42);
+}
+
+#[test]
+fn summary_table() {
+    let timings = Timings {
+        phases: vec![
+            ("build".to_string(), Duration::from_millis(12_340)),
+            ("healthcheck".to_string(), Duration::from_secs(5)),
+        ],
+        total: Duration::from_millis(17_500),
+    };
+
+    assert_eq!(
+        timings.summary(),
+        "Timing:\n\
+         \x20 build            12.3s\n\
+         \x20 healthcheck       5.0s\n\
+         \x20 total            17.5s\n"
+    );
+}
+
+#[test]
+fn json_line() {
+    let timings = Timings {
+        phases: vec![("transfer".to_string(), Duration::from_millis(1_500))],
+        total: Duration::from_secs(2),
+    };
+
+    let line: serde_json::Value =
+        serde_json::from_str(&timings.to_json_line("deploy", "web1", true)).unwrap();
+
+    assert_eq!(line["host"], "web1");
+    assert_eq!(line["success"], true);
+    assert_eq!(line["total"], 2.0);
+    assert_eq!(line["phases"]["transfer"], 1.5);
+}
+
+#[test]
+fn pipeline_appends_metrics() {
+    let dir = std::env::temp_dir().join("catapulta-timing-metrics");
+    let metrics = dir.join("metrics.jsonl");
+    let _ = std::fs::remove_file(&metrics);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let app = App::new("web").expose(3000);
+    let caddy = Caddy::new().reverse_proxy(app.upstream());
+    let pipeline = Pipeline::new(app, caddy)
+        .deploy(MockDeployer::new())
+        .ssh_options(SshOptions::new().transport(FakeSsh::new()))
+        .local_dir(dir.to_str().unwrap())
+        .metrics_file(metrics.to_str().unwrap());
+    pipeline.run_from(["xtask", "deploy", "web1"]).unwrap();
+    pipeline
+        .run_from(["xtask", "deploy", "web1", "--skip-build"])
+        .unwrap();
+
+    let content = std::fs::read_to_string(&metrics).unwrap();
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0]["phases"]["build"].is_number());
+    assert!(lines[1]["phases"]["build"].is_null());
+    assert!(lines[1]["phases"]["transfer"].is_number());
+}